@@ -8,11 +8,53 @@ extern crate serde;
 extern crate serde_json;
 extern crate wyrm;
 
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
 use criterion::Criterion;
 
-use recommenders::data::{Interaction, Interactions};
-use recommenders::models::{ewma, lstm};
+use recommenders::data::{CompressedInteractions, Interaction, Interactions};
+use recommenders::ids::{ItemId, Timestamp, UserId};
+use recommenders::models::ann::{AnnIndex, AnnParameters};
+use recommenders::models::serving::ScoringCache;
+use recommenders::models::{ewma, knn, lstm};
+use recommenders::models::{AsVector, ItemEmbeddings};
 use recommenders::models::{Loss, Optimizer};
+use recommenders::OnlineRankingModel;
+
+/// The system allocator, counting the number of allocations made.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Return the average number of allocations made by a call to `func`.
+fn allocations_per_call<F: FnMut()>(mut func: F) -> f32 {
+    let num_calls = 100;
+    let start = ALLOCATIONS.load(Ordering::SeqCst);
+
+    for _ in 0..num_calls {
+        func();
+    }
+
+    (ALLOCATIONS.load(Ordering::SeqCst) - start) as f32 / num_calls as f32
+}
+
+/// The history used to compute user representations.
+const HISTORY: [ItemId; 3] = [ItemId(1), ItemId(2), ItemId(3)];
 
 fn load_movielens(path: &str, sample_size: usize) -> Interactions {
     let mut reader = csv::Reader::from_path(path).unwrap();
@@ -23,6 +65,20 @@ fn load_movielens(path: &str, sample_size: usize) -> Interactions {
     Interactions::from(interactions)
 }
 
+/// A single user with a few interactions, for marking models over
+/// `num_items` items as fitted by fitting them for zero epochs.
+fn single_user(num_items: usize) -> CompressedInteractions {
+    let mut data = Interactions::new(1, num_items);
+    for item_id in 0..3 {
+        data.push(Interaction::new(
+            UserId(0),
+            ItemId(item_id),
+            Timestamp(item_id as i64),
+        ));
+    }
+    data.to_compressed()
+}
+
 fn bench_lstm(c: &mut Criterion) {
     c.bench_function("lstm", |b| {
         let data = load_movielens("data.csv", 10000).to_compressed();
@@ -63,9 +119,238 @@ fn bench_ewma(c: &mut Criterion) {
     });
 }
 
-criterion_group!{
+/// Fits processing one sequence per step against fits batching several
+/// sequences into each step.
+fn bench_ewma_sequence_batch_size(c: &mut Criterion) {
+    let data = load_movielens("data.csv", 10000).to_compressed();
+
+    for &batch_size in &[1, 32] {
+        let mut model = ewma::Hyperparameters::new(data.num_items(), 128)
+            .embedding_dim(32)
+            .learning_rate(0.16)
+            .l2_penalty(0.0004)
+            .loss(Loss::Hinge)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(3)
+            .num_threads(1)
+            .sequence_batch_size(batch_size)
+            .build();
+        let data = data.clone();
+
+        c.bench_function(&format!("ewma_batch_{}", batch_size), move |b| {
+            b.iter(|| {
+                model.fit(&data).unwrap();
+            })
+        });
+    }
+}
+
+/// Short histories over a small set of items, fitted with catalogs of
+/// different sizes: with sparse updates, the fit time should not depend
+/// on the catalog size.
+fn bench_ewma_catalog_size(c: &mut Criterion) {
+    let interactions: Vec<_> = (0..2000)
+        .flat_map(|user_id| {
+            (0..5).map(move |t| {
+                Interaction::new(
+                    UserId(user_id),
+                    ItemId((user_id * 7 + t) % 5000),
+                    Timestamp(t as i64),
+                )
+            })
+        })
+        .collect();
+
+    for &num_items in &[5_000, 500_000] {
+        let mut data = Interactions::new(2000, num_items);
+        for interaction in &interactions {
+            data.push(interaction.clone());
+        }
+        let data = data.to_compressed();
+
+        let mut model = ewma::Hyperparameters::new(num_items, 8)
+            .embedding_dim(32)
+            .learning_rate(0.16)
+            .l2_penalty(0.0004)
+            .loss(Loss::Hinge)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(1)
+            .num_threads(1)
+            .build();
+
+        c.bench_function(&format!("ewma_sparse_{}_items", num_items), move |b| {
+            b.iter(|| {
+                model.fit(&data).unwrap();
+            })
+        });
+    }
+}
+
+fn bench_predict_candidates(c: &mut Criterion) {
+    c.bench_function("predict_candidates", |b| {
+        let num_items = 5_000_000;
+        let mut model = ewma::Hyperparameters::new(num_items, 128)
+            .embedding_dim(32)
+            .num_epochs(0)
+            .num_threads(1)
+            .build();
+        model.fit(&single_user(num_items)).unwrap();
+
+        let user = model.user_representation(&HISTORY).unwrap();
+        let candidates: Vec<_> = (0..1000)
+            .map(|x| ItemId::from_index(x * (num_items / 1000)))
+            .collect();
+
+        b.iter(|| {
+            model.predict_candidates(&user, &candidates, 10).unwrap();
+        })
+    });
+}
+
+/// Scoring into a new vector against scoring into a reused buffer.
+fn bench_predict_into(c: &mut Criterion) {
+    let num_items = 10_000;
+    let mut model = lstm::Hyperparameters::new(num_items, 128)
+        .embedding_dim(32)
+        .num_epochs(0)
+        .num_threads(1)
+        .build();
+    model.fit(&single_user(num_items)).unwrap();
+    let user = model.user_representation(&HISTORY).unwrap();
+    let item_ids: Vec<_> = (0..1000)
+        .map(|x| ItemId::from_index(x * (num_items / 1000)))
+        .collect();
+    let mut out = vec![0.0; item_ids.len()];
+
+    println!(
+        "Allocations per call: predict {}, predict_into {}",
+        allocations_per_call(|| {
+            model.predict(&user, &item_ids).unwrap();
+        }),
+        allocations_per_call(|| model.predict_into(&user, &item_ids, &mut out).unwrap())
+    );
+
+    c.bench_function("predict", |b| {
+        b.iter(|| model.predict(&user, &item_ids).unwrap())
+    });
+
+    c.bench_function("predict_into", move |b| {
+        b.iter(|| model.predict_into(&user, &item_ids, &mut out).unwrap())
+    });
+}
+
+/// Fit and predict times of session kNN against the EWMA model.
+fn bench_session_knn(c: &mut Criterion) {
+    let data = load_movielens("data.csv", 10000).to_compressed();
+    let item_ids: Vec<_> = (0..data.num_items()).map(ItemId::from_index).collect();
+    let history = data
+        .iter_users()
+        .find(|user| user.len() > 10)
+        .unwrap()
+        .item_ids;
+
+    c.bench_function("session_knn_fit", |b| {
+        let mut model = knn::SessionKnn::new(data.num_items(), 100);
+
+        b.iter(|| {
+            model.fit(&data).unwrap();
+        })
+    });
+
+    c.bench_function("session_knn_predict", |b| {
+        let mut model = knn::SessionKnn::new(data.num_items(), 100);
+        model.fit(&data).unwrap();
+
+        b.iter(|| {
+            let user = model.user_representation(history).unwrap();
+            model.predict(&user, &item_ids).unwrap()
+        })
+    });
+
+    c.bench_function("ewma_predict", |b| {
+        let mut model = ewma::Hyperparameters::new(data.num_items(), 128)
+            .embedding_dim(32)
+            .num_epochs(1)
+            .num_threads(1)
+            .build();
+        model.fit(&data).unwrap();
+
+        b.iter(|| {
+            let user = model.user_representation(history).unwrap();
+            model.predict(&user, &item_ids).unwrap()
+        })
+    });
+}
+
+/// Query latency of the approximate index against exact scoring of
+/// the whole catalog.
+fn bench_ann_top_k(c: &mut Criterion) {
+    let num_items = 100_000;
+    let mut model = ewma::Hyperparameters::new(num_items, 128)
+        .embedding_dim(32)
+        .num_epochs(0)
+        .num_threads(1)
+        .build();
+    model.fit(&single_user(num_items)).unwrap();
+    let user = model.user_representation(&HISTORY).unwrap();
+    let index = AnnIndex::build(&model.item_embeddings(), AnnParameters::new());
+
+    c.bench_function("ann_top_k", move |b| {
+        let user_vector = user.as_vector();
+        b.iter(|| index.top_k(&user_vector, 10, &[]))
+    });
+
+    c.bench_function("exact_top_k", move |b| {
+        let user = model.user_representation(&HISTORY).unwrap();
+        b.iter(|| {
+            let mut scores: Vec<_> = model
+                .predict_all_items(&user, num_items)
+                .unwrap()
+                .into_iter()
+                .enumerate()
+                .collect();
+            scores.sort_by(|x, y| y.1.partial_cmp(&x.1).unwrap());
+            scores.truncate(10);
+            scores
+        })
+    });
+}
+
+fn bench_scoring_cache_top_k(c: &mut Criterion) {
+    let num_items = 1_000_000;
+    let mut model = ewma::Hyperparameters::new(num_items, 128)
+        .embedding_dim(32)
+        .num_epochs(0)
+        .num_threads(1)
+        .build();
+    model.fit(&single_user(num_items)).unwrap();
+    let user = model.user_representation(&HISTORY).unwrap();
+    let cache = ScoringCache::new(&model);
+
+    c.bench_function("scoring_cache_top_k", move |b| {
+        let user_vector = user.as_vector();
+        b.iter(|| cache.top_k(&user_vector, 10, None))
+    });
+
+    c.bench_function("predict_sort_top_k", move |b| {
+        let user = model.user_representation(&HISTORY).unwrap();
+        b.iter(|| {
+            let mut scores: Vec<_> = model
+                .predict_all_items(&user, num_items)
+                .unwrap()
+                .into_iter()
+                .enumerate()
+                .collect();
+            scores.sort_by(|x, y| y.1.partial_cmp(&x.1).unwrap());
+            scores.truncate(10);
+            scores
+        })
+    });
+}
+
+criterion_group! {
     name = benches;
     config = Criterion::default().sample_size(10);
-    targets = bench_lstm, bench_ewma
+    targets = bench_lstm, bench_ewma, bench_ewma_sequence_batch_size, bench_ewma_catalog_size, bench_predict_candidates, bench_predict_into, bench_session_knn, bench_ann_top_k, bench_scoring_cache_top_k
 }
 criterion_main!(benches);
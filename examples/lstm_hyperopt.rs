@@ -18,12 +18,13 @@ use std::time::{Duration, Instant};
 
 use recommenders::data::{user_based_split, CompressedInteractions, Interaction, Interactions};
 use recommenders::evaluation::mrr_score;
+use recommenders::ids::{ItemId, Timestamp, UserId};
 use recommenders::models::lstm;
 
 #[derive(Deserialize, Serialize)]
 struct GoodbooksInteraction {
-    user_id: usize,
-    book_id: usize,
+    user_id: UserId,
+    book_id: ItemId,
     rating: usize,
 }
 
@@ -33,7 +34,7 @@ fn load_goodbooks(path: &str) -> Interactions {
         .deserialize::<GoodbooksInteraction>()
         .map(|x| x.unwrap())
         .enumerate()
-        .map(|(i, x)| Interaction::new(x.user_id, x.book_id, i))
+        .map(|(i, x)| Interaction::new(x.user_id, x.book_id, Timestamp(i as i64)))
         .collect();
     interactions.sort_by_key(|x| x.user_id());
 
@@ -48,7 +49,11 @@ fn load_dummy() -> Interactions {
 
     for user in 0..num_users {
         for item in 0..num_items {
-            interactions.push(Interaction::new(user, 1000 + item, item));
+            interactions.push(Interaction::new(
+                UserId(user),
+                ItemId(1000 + item),
+                Timestamp(item as i64),
+            ));
         }
     }
 
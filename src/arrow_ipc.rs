@@ -0,0 +1,366 @@
+//! Import and export of interactions in the Apache Arrow IPC stream format.
+//!
+//! Interactions are stored as record batches with `UInt64` user id and item
+//! id columns, an `Int64` timestamp column and an optional `Float32` weight
+//! column; `UInt64` timestamp columns, as written by earlier versions, are
+//! read too. Batches are read and written one at a time, so only a single
+//! batch of Arrow data is held in memory alongside the interactions
+//! themselves.
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, Float32Array, Int64Array, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::ipc::reader::StreamReader;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+
+use crate::data::{Interaction, Interactions};
+use crate::datasets::DatasetError;
+use crate::ids::{ItemId, Timestamp, UserId};
+
+/// Default number of interactions per written record batch.
+const DEFAULT_BATCH_SIZE: usize = 65_536;
+
+/// Names of the Arrow columns holding each interaction field.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArrowColumns {
+    user_id: String,
+    item_id: String,
+    timestamp: String,
+    weight: Option<String>,
+    batch_size: usize,
+}
+
+impl Default for ArrowColumns {
+    fn default() -> Self {
+        ArrowColumns {
+            user_id: "user_id".to_owned(),
+            item_id: "item_id".to_owned(),
+            timestamp: "timestamp".to_owned(),
+            weight: Some("weight".to_owned()),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+impl ArrowColumns {
+    /// Use the default column names: `user_id`, `item_id`, `timestamp`
+    /// and `weight`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the name of the user id column.
+    pub fn user_id(mut self, name: &str) -> Self {
+        self.user_id = name.to_owned();
+        self
+    }
+
+    /// Set the name of the item id column.
+    pub fn item_id(mut self, name: &str) -> Self {
+        self.item_id = name.to_owned();
+        self
+    }
+
+    /// Set the name of the timestamp column.
+    pub fn timestamp(mut self, name: &str) -> Self {
+        self.timestamp = name.to_owned();
+        self
+    }
+
+    /// Set the name of the weight column, or `None` to neither read nor
+    /// write weights. When reading, a missing weight column gives all
+    /// interactions a weight of 1.
+    pub fn weight(mut self, name: Option<&str>) -> Self {
+        self.weight = name.map(str::to_owned);
+        self
+    }
+
+    /// Set the number of interactions per written record batch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is zero.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "Batch size must be positive.");
+        self.batch_size = batch_size;
+        self
+    }
+
+    fn schema(&self) -> Schema {
+        let mut fields = vec![
+            Field::new(&self.user_id, DataType::UInt64, false),
+            Field::new(&self.item_id, DataType::UInt64, false),
+            Field::new(&self.timestamp, DataType::Int64, false),
+        ];
+
+        if let Some(weight) = &self.weight {
+            fields.push(Field::new(weight, DataType::Float32, false));
+        }
+
+        Schema::new(fields)
+    }
+}
+
+/// Return the column `name` of `batch` as an array of type `T`.
+fn column<'a, T: 'static>(batch: &'a RecordBatch, name: &str) -> Result<&'a T, DatasetError> {
+    let idx = batch.schema().index_of(name)?;
+    let column = batch.column(idx);
+
+    if column.null_count() > 0 {
+        return Err(DatasetError::Malformed(format!(
+            "Column {} has missing values.",
+            name
+        )));
+    }
+
+    column.as_any().downcast_ref::<T>().ok_or_else(|| {
+        DatasetError::Malformed(format!(
+            "Column {} has type {:?}.",
+            name,
+            column.data_type()
+        ))
+    })
+}
+
+/// Return the column `name` of `batch` as timestamps. The column may be
+/// `Int64`, or `UInt64` with values that fit in an `i64`.
+fn timestamp_column(batch: &RecordBatch, name: &str) -> Result<Vec<Timestamp>, DatasetError> {
+    let idx = batch.schema().index_of(name)?;
+
+    if batch.column(idx).data_type() == &DataType::UInt64 {
+        let timestamps: &UInt64Array = column(batch, name)?;
+
+        (0..timestamps.len())
+            .map(|idx| {
+                i64::try_from(timestamps.value(idx))
+                    .map(Timestamp)
+                    .map_err(|_| {
+                        DatasetError::Malformed(format!("Column {} has out-of-range values.", name))
+                    })
+            })
+            .collect()
+    } else {
+        let timestamps: &Int64Array = column(batch, name)?;
+
+        Ok((0..timestamps.len())
+            .map(|idx| Timestamp(timestamps.value(idx)))
+            .collect())
+    }
+}
+
+impl Interactions {
+    /// Read interactions from an Arrow IPC stream, one record batch at a
+    /// time, taking each field from the column named in `columns`.
+    ///
+    /// The number of users and items is one more than the largest
+    /// user and item id.
+    pub fn from_arrow_ipc<R: Read>(
+        reader: R,
+        columns: &ArrowColumns,
+    ) -> Result<Interactions, DatasetError> {
+        let reader = StreamReader::try_new(reader)?;
+        let has_weights = columns
+            .weight
+            .as_ref()
+            .is_some_and(|name| reader.schema().index_of(name).is_ok());
+
+        let mut interactions = Vec::new();
+
+        for batch in reader {
+            let batch = batch?;
+
+            let user_ids: &UInt64Array = column(&batch, &columns.user_id)?;
+            let item_ids: &UInt64Array = column(&batch, &columns.item_id)?;
+            let timestamps = timestamp_column(&batch, &columns.timestamp)?;
+            let weights: Option<&Float32Array> = match &columns.weight {
+                Some(name) if has_weights => Some(column(&batch, name)?),
+                _ => None,
+            };
+
+            for idx in 0..batch.num_rows() {
+                let interaction = Interaction::new(
+                    UserId(user_ids.value(idx)),
+                    ItemId(item_ids.value(idx)),
+                    timestamps[idx],
+                );
+
+                interactions.push(match weights {
+                    Some(weights) => interaction.with_weight(weights.value(idx)),
+                    None => interaction,
+                });
+            }
+        }
+
+        if interactions.is_empty() {
+            Ok(Interactions::new(0, 0))
+        } else {
+            Ok(Interactions::from(interactions))
+        }
+    }
+
+    /// Write the interactions to an Arrow IPC stream, in record batches of
+    /// at most [ArrowColumns::batch_size] interactions, naming each column
+    /// as in `columns`.
+    pub fn to_arrow_ipc<W: Write>(
+        &self,
+        writer: W,
+        columns: &ArrowColumns,
+    ) -> Result<(), DatasetError> {
+        let schema = Arc::new(columns.schema());
+        let mut writer = StreamWriter::try_new(writer, &schema)?;
+
+        for chunk in self.data().chunks(columns.batch_size) {
+            let mut arrays: Vec<ArrayRef> = vec![
+                Arc::new(UInt64Array::from(
+                    chunk.iter().map(|x| x.user_id().get()).collect::<Vec<_>>(),
+                )),
+                Arc::new(UInt64Array::from(
+                    chunk.iter().map(|x| x.item_id().get()).collect::<Vec<_>>(),
+                )),
+                Arc::new(Int64Array::from(
+                    chunk
+                        .iter()
+                        .map(|x| x.timestamp().get())
+                        .collect::<Vec<_>>(),
+                )),
+            ];
+
+            if columns.weight.is_some() {
+                arrays.push(Arc::new(Float32Array::from(
+                    chunk.iter().map(|x| x.weight()).collect::<Vec<_>>(),
+                )));
+            }
+
+            writer.write(&RecordBatch::try_new(schema.clone(), arrays)?)?;
+        }
+
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn interactions() -> Interactions {
+        Interactions::from(
+            (0..100)
+                .map(|idx| {
+                    Interaction::new(
+                        UserId(idx % 7),
+                        ItemId((idx * 3) % 11),
+                        Timestamp(idx as i64 - 50),
+                    )
+                    .with_weight(idx as f32 / 10.0)
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let interactions = interactions();
+        let columns = ArrowColumns::new().batch_size(30);
+
+        let mut buffer = Vec::new();
+        interactions.to_arrow_ipc(&mut buffer, &columns).unwrap();
+        let read = Interactions::from_arrow_ipc(Cursor::new(buffer), &columns).unwrap();
+
+        assert_eq!(read.data(), interactions.data());
+        assert_eq!(read.shape(), interactions.shape());
+    }
+
+    #[test]
+    fn custom_columns() {
+        let interactions = interactions();
+        let columns = ArrowColumns::new()
+            .user_id("customer")
+            .item_id("product")
+            .timestamp("ts")
+            .weight(Some("clicks"));
+
+        let path = std::env::temp_dir().join("sbr_interactions.arrow");
+        interactions
+            .to_arrow_ipc(std::fs::File::create(&path).unwrap(), &columns)
+            .unwrap();
+        let read =
+            Interactions::from_arrow_ipc(std::fs::File::open(&path).unwrap(), &columns).unwrap();
+        assert_eq!(read.data(), interactions.data());
+
+        // The default names are not in the file.
+        match Interactions::from_arrow_ipc(
+            std::fs::File::open(&path).unwrap(),
+            &ArrowColumns::new(),
+        ) {
+            Err(DatasetError::Arrow(_)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // Without the weight column, all weights are 1.
+        let unweighted = Interactions::from_arrow_ipc(
+            std::fs::File::open(&path).unwrap(),
+            &columns.clone().weight(Some("missing")),
+        )
+        .unwrap();
+        assert!(unweighted.data().iter().all(|x| x.weight() == 1.0));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unweighted_and_empty() {
+        let columns = ArrowColumns::new().weight(None);
+
+        let mut buffer = Vec::new();
+        interactions().to_arrow_ipc(&mut buffer, &columns).unwrap();
+        let read = Interactions::from_arrow_ipc(Cursor::new(buffer), &ArrowColumns::new()).unwrap();
+        assert_eq!(read.len(), 100);
+        assert!(read.data().iter().all(|x| x.weight() == 1.0));
+
+        let mut buffer = Vec::new();
+        Interactions::new(0, 0)
+            .to_arrow_ipc(&mut buffer, &columns)
+            .unwrap();
+        let read = Interactions::from_arrow_ipc(Cursor::new(buffer), &columns).unwrap();
+        assert_eq!(read.len(), 0);
+    }
+
+    #[test]
+    fn unsigned_timestamps() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("user_id", DataType::UInt64, false),
+            Field::new("item_id", DataType::UInt64, false),
+            Field::new("timestamp", DataType::UInt64, false),
+        ]));
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(vec![0, 1])),
+            Arc::new(UInt64Array::from(vec![2, 3])),
+            Arc::new(UInt64Array::from(vec![10, 20])),
+        ];
+
+        let mut buffer = Vec::new();
+        {
+            let mut writer = StreamWriter::try_new(&mut buffer, &schema).unwrap();
+            writer
+                .write(&RecordBatch::try_new(schema.clone(), columns).unwrap())
+                .unwrap();
+            writer.finish().unwrap();
+        }
+
+        let read = Interactions::from_arrow_ipc(Cursor::new(buffer), &ArrowColumns::new()).unwrap();
+        assert_eq!(
+            read.data()
+                .iter()
+                .map(|x| x.timestamp())
+                .collect::<Vec<_>>(),
+            vec![Timestamp(10), Timestamp(20)]
+        );
+    }
+}
@@ -0,0 +1,521 @@
+//! Side-by-side comparison of models on the same data.
+//!
+//! [compare] fits every [ModelSpec] on the same training data with the
+//! same seed, evaluates it on the same test data, and collects the
+//! metrics, fitting times and prediction latencies in a
+//! [ComparisonReport], which serializes to JSON and displays as a table.
+//!
+//! A model that fails to fit or to predict, or that panics, gets an error
+//! in its row of the report; the other models are compared as usual.
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::CompressedInteractions;
+use crate::evaluation::{coverage_at_k, mrr_score, ndcg_score};
+use crate::models::boxed::BoxedModel;
+use crate::rng::SeedSequence;
+use crate::{Error, OnlineRankingModel};
+
+type Builder = Box<dyn FnOnce(&CompressedInteractions, &FitContext) -> Result<BoxedModel, Error>>;
+
+/// What a [ModelSpec] may use when fitting its model.
+#[derive(Debug)]
+pub struct FitContext {
+    seeds: SeedSequence,
+    cancellation_flag: Arc<AtomicBool>,
+    num_items: usize,
+}
+
+impl FitContext {
+    /// The seed sequence shared by all models, created from
+    /// [CompareOptions::seed]. Pass it to the `seed_sequence` method of
+    /// the model hyperparameters.
+    pub fn seeds(&self) -> &SeedSequence {
+        &self.seeds
+    }
+
+    /// A flag set when the time budget runs out. Pass it to the
+    /// `cancellation_flag` method of the model hyperparameters, where
+    /// available, to stop fitting in time.
+    pub fn cancellation_flag(&self) -> Arc<AtomicBool> {
+        self.cancellation_flag.clone()
+    }
+
+    /// The number of items in the data.
+    pub fn num_items(&self) -> usize {
+        self.num_items
+    }
+}
+
+/// A named model to compare: a closure building a model and fitting it
+/// on the training data.
+pub struct ModelSpec {
+    name: String,
+    build: Builder,
+}
+
+impl ModelSpec {
+    /// Create a model named `name`, built and fitted by `build`.
+    pub fn new<F>(name: &str, build: F) -> Self
+    where
+        F: FnOnce(&CompressedInteractions, &FitContext) -> Result<BoxedModel, Error> + 'static,
+    {
+        ModelSpec {
+            name: name.to_owned(),
+            build: Box::new(build),
+        }
+    }
+
+    /// The name of the model.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Debug for ModelSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ModelSpec")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// Options for [compare].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompareOptions {
+    seed: u64,
+    time_budget: Option<Duration>,
+    k: usize,
+    num_latency_users: usize,
+}
+
+impl CompareOptions {
+    /// Seed 0, no time budget, metrics at 10, and latency measured over
+    /// 100 test users.
+    pub fn new() -> Self {
+        CompareOptions {
+            seed: 0,
+            time_budget: None,
+            k: 10,
+            num_latency_users: 100,
+        }
+    }
+
+    /// Set the seed of the [SeedSequence] shared by all models.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Give each model at most `time_budget` to fit. Models whose fitting
+    /// takes longer fail with an error.
+    pub fn time_budget(mut self, time_budget: Duration) -> Self {
+        self.time_budget = Some(time_budget);
+        self
+    }
+
+    /// Set the number of recommendations NDCG and coverage are computed
+    /// over, and that are requested when measuring latency.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k` is zero.
+    pub fn k(mut self, k: usize) -> Self {
+        assert!(k > 0, "k must be positive.");
+        self.k = k;
+        self
+    }
+
+    /// Set the number of test users whose recommendations are timed to
+    /// measure the prediction latency.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_latency_users` is zero.
+    pub fn num_latency_users(mut self, num_latency_users: usize) -> Self {
+        assert!(
+            num_latency_users > 0,
+            "Latency must be measured over at least one user."
+        );
+        self.num_latency_users = num_latency_users;
+        self
+    }
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        CompareOptions::new()
+    }
+}
+
+/// Test metrics of a model.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Mean reciprocal rank: see [crate::evaluation::mrr_score].
+    pub mrr: f32,
+    /// NDCG at k: see [crate::evaluation::ndcg_score].
+    pub ndcg: f32,
+    /// Catalog coverage at k: see [crate::evaluation::coverage_at_k].
+    pub coverage: f32,
+}
+
+/// The outcome of comparing one model.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModelResult {
+    /// Name of the model.
+    pub name: String,
+    /// Test metrics, unless the model failed.
+    pub metrics: Option<Metrics>,
+    /// Time taken to build and fit the model, including failed attempts.
+    pub fit_time: Duration,
+    /// Mean time to compute the top k recommendations of a test user,
+    /// unless the model failed.
+    pub predict_latency: Option<Duration>,
+    /// Why the model failed, if it did.
+    pub error: Option<String>,
+}
+
+/// Results of [compare], one per model in the order given. Displays as
+/// an ASCII table.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ComparisonReport {
+    /// The number of recommendations metrics were computed over.
+    pub k: usize,
+    /// Results for every model.
+    pub results: Vec<ModelResult>,
+}
+
+impl ComparisonReport {
+    /// Serialize the report to pretty-printed JSON.
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// The model with the highest MRR among those that did not fail.
+    pub fn best(&self) -> Option<&ModelResult> {
+        self.results
+            .iter()
+            .filter_map(|result| result.metrics.as_ref().map(|metrics| (result, metrics.mrr)))
+            .max_by(|(_, x), (_, y)| x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(result, _)| result)
+    }
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let separator = format!(
+            "+{:-<20}+{:-<10}+{:-<10}+{:-<10}+{:-<12}+{:-<14}+",
+            "", "", "", "", "", ""
+        );
+
+        writeln!(f, "{}", separator)?;
+        writeln!(
+            f,
+            "|{:<20}|{:>10}|{:>10}|{:>10}|{:>12}|{:>14}|",
+            "model",
+            "MRR",
+            format!("NDCG@{}", self.k),
+            format!("Cov@{}", self.k),
+            "fit time (s)",
+            "latency (ms)"
+        )?;
+        writeln!(f, "{}", separator)?;
+
+        for result in &self.results {
+            match (&result.metrics, result.predict_latency) {
+                (Some(metrics), Some(latency)) => writeln!(
+                    f,
+                    "|{:<20}|{:>10.4}|{:>10.4}|{:>10.4}|{:>12.1}|{:>14.3}|",
+                    result.name,
+                    metrics.mrr,
+                    metrics.ndcg,
+                    metrics.coverage,
+                    result.fit_time.as_secs_f32(),
+                    latency.as_secs_f64() * 1000.0
+                )?,
+                _ => writeln!(
+                    f,
+                    "|{:<20}|{:>10}|{:>10}|{:>10}|{:>12.1}|{:>14}|",
+                    result.name,
+                    "-",
+                    "-",
+                    "-",
+                    result.fit_time.as_secs_f32(),
+                    "-"
+                )?,
+            }
+        }
+
+        write!(f, "{}", separator)?;
+
+        for result in &self.results {
+            if let Some(error) = &result.error {
+                write!(f, "\n{} failed: {}", result.name, error)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Run `func`, turning a panic into an error message.
+fn catch_panic<T, F: FnOnce() -> Result<T, Error>>(func: F) -> Result<T, String> {
+    match panic::catch_unwind(AssertUnwindSafe(func)) {
+        Ok(result) => result.map_err(|err| err.to_string()),
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_owned());
+            Err(format!("panicked: {}", message))
+        }
+    }
+}
+
+/// Build and fit a model with `build`, setting the cancellation flag of
+/// `context` if it takes longer than `time_budget`.
+fn fit(
+    build: Builder,
+    train: &CompressedInteractions,
+    context: &FitContext,
+    time_budget: Option<Duration>,
+) -> Result<BoxedModel, String> {
+    let (done, finished) = mpsc::channel::<()>();
+
+    if let Some(time_budget) = time_budget {
+        let cancellation_flag = context.cancellation_flag();
+        thread::spawn(move || {
+            if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(time_budget) {
+                cancellation_flag.store(true, Ordering::SeqCst);
+            }
+        });
+    }
+
+    let start = Instant::now();
+    let model = catch_panic(|| build(train, context));
+    drop(done);
+
+    match time_budget {
+        Some(time_budget) if model.is_ok() && start.elapsed() > time_budget => Err(format!(
+            "time budget of {:.1}s exceeded",
+            time_budget.as_secs_f32()
+        )),
+        _ => model,
+    }
+}
+
+fn evaluate(
+    model: &BoxedModel,
+    test: &CompressedInteractions,
+    options: &CompareOptions,
+) -> Result<(Metrics, Duration), Error> {
+    let metrics = Metrics {
+        mrr: mrr_score(model, test)?,
+        ndcg: ndcg_score(model, test, options.k)?,
+        coverage: coverage_at_k(model, test, options.k)?,
+    };
+
+    let histories: Vec<_> = test
+        .iter_users()
+        .filter(|user| !user.is_empty())
+        .take(options.num_latency_users)
+        .map(|user| user.item_ids)
+        .collect();
+
+    let start = Instant::now();
+    for history in &histories {
+        let user = model.user_representation(history)?;
+        model.predict_top_k(&user, options.k, &[])?;
+    }
+    let latency = start.elapsed() / histories.len().max(1) as u32;
+
+    Ok((metrics, latency))
+}
+
+/// Fit every model in `models` on `train`, evaluate it on `test`, and
+/// report the results in the same order.
+///
+/// Each model is given a [FitContext] with the seed and time budget of
+/// `options`. Metrics are computed as in [crate::evaluation], predicting
+/// the last item of each test user from the preceding ones.
+pub fn compare(
+    models: Vec<ModelSpec>,
+    train: &CompressedInteractions,
+    test: &CompressedInteractions,
+    options: &CompareOptions,
+) -> ComparisonReport {
+    let results = models
+        .into_iter()
+        .map(|spec| {
+            let context = FitContext {
+                seeds: SeedSequence::new(options.seed),
+                cancellation_flag: Arc::new(AtomicBool::new(false)),
+                num_items: train.num_items(),
+            };
+
+            log_info!("Fitting {}", spec.name);
+            let start = Instant::now();
+            let model = fit(spec.build, train, &context, options.time_budget);
+            let fit_time = start.elapsed();
+
+            let evaluation =
+                model.and_then(|model| catch_panic(|| evaluate(&model, test, options)));
+
+            match evaluation {
+                Ok((metrics, latency)) => ModelResult {
+                    name: spec.name,
+                    metrics: Some(metrics),
+                    fit_time,
+                    predict_latency: Some(latency),
+                    error: None,
+                },
+                Err(error) => {
+                    log_info!("{} failed: {}", spec.name, error);
+                    ModelResult {
+                        name: spec.name,
+                        metrics: None,
+                        fit_time,
+                        predict_latency: None,
+                        error: Some(error),
+                    }
+                }
+            }
+        })
+        .collect();
+
+    ComparisonReport {
+        k: options.k,
+        results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::ids::{ItemId, Timestamp, UserId};
+    use crate::models::baselines::{Popularity, Random};
+    use crate::models::boxed::IntoBoxedModel;
+    use crate::FittingError;
+
+    /// Users whose items are skewed towards low item ids.
+    fn skewed_data(seeds: &SeedSequence) -> (CompressedInteractions, CompressedInteractions) {
+        let num_items = 30;
+        let mut rng = seeds.derive("data");
+        let mut train = Interactions::new(100, num_items);
+        let mut test = Interactions::new(100, num_items);
+
+        for user_id in 0..100 {
+            let data = if user_id < 80 { &mut train } else { &mut test };
+
+            for timestamp in 0..10 {
+                let item_id = rng
+                    .gen_range(0, num_items as u64)
+                    .min(rng.gen_range(0, num_items as u64));
+                data.push(Interaction::new(
+                    UserId(user_id),
+                    ItemId(item_id),
+                    Timestamp(timestamp),
+                ));
+            }
+        }
+
+        (train.to_compressed(), test.to_compressed())
+    }
+
+    fn baselines() -> Vec<ModelSpec> {
+        vec![
+            ModelSpec::new("popularity", |train, context| {
+                let mut model = Popularity::new(context.num_items());
+                model.fit(train)?;
+                Ok(model.boxed(context.num_items()))
+            }),
+            ModelSpec::new("random", |_, context| {
+                let seed = context.seeds().derive("random").gen();
+                Ok(Random::new(seed).boxed(context.num_items()))
+            }),
+        ]
+    }
+
+    #[test]
+    fn popularity_beats_random() {
+        let (train, test) = skewed_data(&SeedSequence::new(42));
+        let options = CompareOptions::new().seed(42).k(5);
+
+        let report = compare(baselines(), &train, &test, &options);
+
+        assert_eq!(report.results.len(), 2);
+        assert!(report.results.iter().all(|result| result.error.is_none()));
+        assert_eq!(report.best().unwrap().name, "popularity");
+
+        let table = report.to_string();
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+        assert!(lines[3].starts_with("|popularity"));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["results"][1]["name"], "random");
+        assert_eq!(json["k"], 5);
+    }
+
+    #[test]
+    fn failures_are_recorded() {
+        let (train, test) = skewed_data(&SeedSequence::new(42));
+        let mut models = baselines();
+        models.insert(
+            0,
+            ModelSpec::new("failing", |_, _| {
+                Err(Error::Fitting(FittingError::NoInteractions))
+            }),
+        );
+        models.insert(1, ModelSpec::new("panicking", |_, _| panic!("oops")));
+
+        let report = compare(models, &train, &test, &CompareOptions::new());
+
+        let names: Vec<_> = report.results.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["failing", "panicking", "popularity", "random"]);
+        assert_eq!(
+            report.results[0].error.as_deref(),
+            Some("No interactions were supplied.")
+        );
+        assert_eq!(report.results[1].error.as_deref(), Some("panicked: oops"));
+        assert!(report.results[0].metrics.is_none());
+        assert!(report.results[2].metrics.is_some());
+        assert!(report
+            .to_string()
+            .contains("failing failed: No interactions"));
+    }
+
+    #[test]
+    fn time_budget() {
+        let (train, test) = skewed_data(&SeedSequence::new(42));
+        let models = vec![ModelSpec::new("slow", |train, context| {
+            thread::sleep(Duration::from_millis(200));
+            assert!(context.cancellation_flag().load(Ordering::SeqCst));
+
+            let mut model = Popularity::new(context.num_items());
+            model.fit(train)?;
+            Ok(model.boxed(context.num_items()))
+        })];
+        let options = CompareOptions::new().time_budget(Duration::from_millis(50));
+
+        let report = compare(models, &train, &test, &options);
+
+        assert!(report.results[0]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("time budget"));
+    }
+}
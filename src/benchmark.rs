@@ -0,0 +1,170 @@
+//! A standard benchmark suite for detecting performance regressions.
+//!
+//! Not intended for production use: it downloads every built-in dataset
+//! and fits models with default hyperparameters, which takes a while.
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use async_std::task;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use crate::data::{user_based_split, Interactions};
+use crate::datasets::{download_movielens_100k, download_steam_games, DatasetError};
+use crate::evaluation::{mrr_score, ndcg_score};
+use crate::models::{ewma, lstm};
+use crate::{Error, OnlineRankingModel, PredictionError};
+
+/// Maximum sequence length used for all benchmarked models.
+const MAX_SEQUENCE_LENGTH: usize = 32;
+
+/// Fraction of users held out for evaluation.
+const TEST_FRACTION: f32 = 0.2;
+
+/// The result of benchmarking one model on one dataset.
+#[derive(Clone, Debug)]
+pub struct BenchmarkResult {
+    /// Name of the dataset.
+    pub dataset: String,
+    /// Name of the model.
+    pub model: String,
+    /// Test set MRR.
+    pub mrr: f32,
+    /// Test set NDCG@10.
+    pub ndcg_at_10: f32,
+    /// Time taken to fit the model.
+    pub fit_time: Duration,
+}
+
+/// Results of [benchmark_all], displayed as an ASCII table.
+#[derive(Clone, Debug, Default)]
+pub struct BenchmarkResults {
+    /// Results for every (dataset, model) pair.
+    pub results: Vec<BenchmarkResult>,
+}
+
+impl fmt::Display for BenchmarkResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let separator = format!(
+            "+{:-<20}+{:-<8}+{:-<10}+{:-<10}+{:-<12}+",
+            "", "", "", "", ""
+        );
+
+        writeln!(f, "{}", separator)?;
+        writeln!(
+            f,
+            "|{:<20}|{:<8}|{:>10}|{:>10}|{:>12}|",
+            "dataset", "model", "MRR", "NDCG@10", "fit time (s)"
+        )?;
+        writeln!(f, "{}", separator)?;
+
+        for result in &self.results {
+            writeln!(
+                f,
+                "|{:<20}|{:<8}|{:>10.4}|{:>10.4}|{:>12.1}|",
+                result.dataset,
+                result.model,
+                result.mrr,
+                result.ndcg_at_10,
+                result.fit_time.as_secs_f32()
+            )?;
+        }
+
+        write!(f, "{}", separator)
+    }
+}
+
+fn evaluate<T: OnlineRankingModel + Sync>(
+    dataset: &str,
+    model_name: &str,
+    model: &T,
+    fit_time: Duration,
+    test: &Interactions,
+) -> Result<BenchmarkResult, PredictionError> {
+    let test = test.to_compressed();
+
+    Ok(BenchmarkResult {
+        dataset: dataset.to_owned(),
+        model: model_name.to_owned(),
+        mrr: mrr_score(model, &test)?,
+        ndcg_at_10: ndcg_score(model, &test, 10)?,
+        fit_time,
+    })
+}
+
+fn benchmark_dataset<R: Rng>(
+    name: &str,
+    mut data: Interactions,
+    rng: &mut R,
+) -> Result<Vec<BenchmarkResult>, Error> {
+    let mut split_rng = XorShiftRng::from_seed(rng.gen());
+    let (train, test) = user_based_split(&mut data, &mut split_rng, TEST_FRACTION);
+    let train = train.to_compressed();
+
+    let mut lstm = lstm::Hyperparameters::new(data.num_items(), MAX_SEQUENCE_LENGTH)
+        .from_seed(rng.gen())
+        .build();
+    let start = Instant::now();
+    lstm.fit(&train)?;
+    let lstm_result = evaluate(name, "LSTM", &lstm, start.elapsed(), &test)?;
+
+    let mut ewma = ewma::Hyperparameters::new(data.num_items(), MAX_SEQUENCE_LENGTH)
+        .from_seed(rng.gen())
+        .build();
+    let start = Instant::now();
+    ewma.fit(&train)?;
+    let ewma_result = evaluate(name, "EWMA", &ewma, start.elapsed(), &test)?;
+
+    Ok(vec![lstm_result, ewma_result])
+}
+
+/// Download all built-in datasets, fit the LSTM and EWMA models with
+/// default hyperparameters on each, and report their test MRR, NDCG@10
+/// and fitting time.
+///
+/// Datasets that must be downloaded manually are skipped if they are not
+/// in the cache.
+pub fn benchmark_all<R: Rng>(rng: &mut R) -> Result<BenchmarkResults, Error> {
+    let mut datasets = vec![("movielens-100k", task::block_on(download_movielens_100k())?)];
+
+    match task::block_on(download_steam_games()) {
+        Ok(data) => datasets.push(("steam-200k", data)),
+        Err(DatasetError::ManualDownload { url, path }) => {
+            log_warn!("Skipping steam-200k: download it from {} to {}", url, path);
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    let mut results = BenchmarkResults::default();
+
+    for (name, data) in datasets {
+        results.results.extend(benchmark_dataset(name, data, rng)?);
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_table() {
+        let results = BenchmarkResults {
+            results: vec![BenchmarkResult {
+                dataset: "movielens-100k".to_owned(),
+                model: "EWMA".to_owned(),
+                mrr: 0.12345,
+                ndcg_at_10: 0.2,
+                fit_time: Duration::from_millis(1500),
+            }],
+        };
+
+        let table = results.to_string();
+        let lines: Vec<_> = table.lines().collect();
+
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|line| line.len() == lines[0].len()));
+        assert!(lines[3].contains("movielens-100k") && lines[3].contains("0.1235"));
+        assert!(lines[3].contains("1.5"));
+    }
+}
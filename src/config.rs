@@ -0,0 +1,252 @@
+//! Crate-wide parallelism configuration.
+//!
+//! Fitting the sequence models, computing their perplexity and evaluating
+//! any model all run on one shared thread pool, so that composing them does
+//! not start more threads than the machine has cores. Its size is resolved,
+//! in order, from [set_num_threads], the `SBR_NUM_THREADS` and
+//! `RAYON_NUM_THREADS` environment variables, and the number of available
+//! cores. The pool is started on first use, and its threads are named
+//! `sbr-worker-<index>`.
+//!
+//! To run a single call on a different number of threads, build a
+//! separate pool with [ThreadConfig::build] and call the crate from
+//! [ThreadPool::install]: work started from inside any thread pool stays
+//! on that pool.
+//!
+//! The `num_threads` hyperparameter of the sequence models sets how many
+//! partitions the training data is split into, and so, with a fixed seed,
+//! the fitted model; it defaults to [num_threads].
+use std::env;
+use std::error;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use rayon;
+
+/// Environment variables read, in order, for the default number of
+/// threads.
+const ENV_VARS: [&str; 2] = ["SBR_NUM_THREADS", "RAYON_NUM_THREADS"];
+
+/// Prefix of the names of the shared pool's threads.
+const THREAD_NAME_PREFIX: &str = "sbr-worker";
+
+/// Error starting a thread pool.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The operating system could not start the threads.
+    ThreadPool(rayon::ThreadPoolBuildError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::ThreadPool(err) => write!(f, "Cannot start thread pool: {}", err),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ConfigError::ThreadPool(err) => Some(err),
+        }
+    }
+}
+
+impl From<rayon::ThreadPoolBuildError> for ConfigError {
+    fn from(err: rayon::ThreadPoolBuildError) -> Self {
+        ConfigError::ThreadPool(err)
+    }
+}
+
+/// State of the shared pool: its size, and the pool itself once started.
+struct Global {
+    num_threads: Option<usize>,
+    pool: Option<Arc<rayon::ThreadPool>>,
+}
+
+static GLOBAL: Mutex<Global> = Mutex::new(Global {
+    num_threads: None,
+    pool: None,
+});
+
+fn lock() -> MutexGuard<'static, Global> {
+    // The state is consistent even if a thread panicked holding the lock.
+    GLOBAL.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Resolve the default number of threads from the environment.
+fn default_num_threads() -> usize {
+    ENV_VARS
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .filter_map(|value| value.trim().parse().ok())
+        .find(|&num_threads| num_threads > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |num| num.get()))
+}
+
+/// Configuration of a thread pool.
+#[derive(Clone, Debug)]
+pub struct ThreadConfig {
+    num_threads: usize,
+}
+
+impl ThreadConfig {
+    /// Configure a pool sized from the environment, as described in the
+    /// [module documentation](self).
+    pub fn new() -> Self {
+        ThreadConfig {
+            num_threads: default_num_threads(),
+        }
+    }
+
+    /// Set the number of threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_threads` is zero.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        assert!(num_threads > 0, "The number of threads must be positive.");
+        self.num_threads = num_threads;
+        self
+    }
+
+    fn build_pool(&self, prefix: &'static str) -> Result<rayon::ThreadPool, ConfigError> {
+        Ok(rayon::ThreadPoolBuilder::new()
+            .num_threads(self.num_threads)
+            .thread_name(move |idx| format!("{}-{}", prefix, idx))
+            .build()?)
+    }
+
+    /// Start a separate pool, for calls that should not run on the shared
+    /// one. Its threads are named `sbr-custom-<index>`.
+    pub fn build(self) -> Result<ThreadPool, ConfigError> {
+        Ok(ThreadPool {
+            pool: Arc::new(self.build_pool("sbr-custom")?),
+        })
+    }
+
+    /// Replace the shared pool by one with this configuration. Calls
+    /// already running finish on the previous pool.
+    pub fn set_global(self) -> Result<(), ConfigError> {
+        let pool = self.build_pool(THREAD_NAME_PREFIX)?;
+
+        let mut global = lock();
+        global.num_threads = Some(self.num_threads);
+        global.pool = Some(Arc::new(pool));
+
+        Ok(())
+    }
+}
+
+impl Default for ThreadConfig {
+    fn default() -> Self {
+        ThreadConfig::new()
+    }
+}
+
+/// A thread pool built with [ThreadConfig::build].
+#[derive(Clone, Debug)]
+pub struct ThreadPool {
+    pool: Arc<rayon::ThreadPool>,
+}
+
+impl ThreadPool {
+    /// Run `op` on this pool. The parallel work of the crate started by
+    /// `op` runs on this pool rather than on the shared one.
+    pub fn install<R: Send, F: FnOnce() -> R + Send>(&self, op: F) -> R {
+        self.pool.install(op)
+    }
+
+    /// Number of threads of the pool.
+    pub fn num_threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+}
+
+/// Set the number of threads of the shared pool.
+///
+/// # Panics
+///
+/// Panics if `num_threads` is zero.
+pub fn set_num_threads(num_threads: usize) -> Result<(), ConfigError> {
+    ThreadConfig::new().num_threads(num_threads).set_global()
+}
+
+/// Number of threads of the shared pool, which need not have been started.
+pub fn num_threads() -> usize {
+    let mut global = lock();
+
+    *global.num_threads.get_or_insert_with(default_num_threads)
+}
+
+/// Run `op` on the pool the crate's parallel work should use: the pool of
+/// the calling thread, if any, or else the shared pool.
+///
+/// # Panics
+///
+/// Panics if the shared pool cannot be started.
+pub(crate) fn install<R: Send, F: FnOnce() -> R + Send>(op: F) -> R {
+    if rayon::current_thread_index().is_some() {
+        return op();
+    }
+
+    let pool = {
+        let mut global = lock();
+        let num_threads = *global.num_threads.get_or_insert_with(default_num_threads);
+
+        match global.pool {
+            Some(ref pool) => Arc::clone(pool),
+            None => {
+                let pool = ThreadConfig { num_threads }
+                    .build_pool(THREAD_NAME_PREFIX)
+                    .expect("Failed to start the shared thread pool.");
+                Arc::clone(global.pool.get_or_insert(Arc::new(pool)))
+            }
+        }
+    };
+
+    pool.install(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use rayon::prelude::*;
+
+    use super::*;
+
+    fn thread_names() -> Vec<String> {
+        (0..64)
+            .into_par_iter()
+            .map(|_| thread::current().name().unwrap_or("").to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn shared_pool() {
+        let names = install(thread_names);
+        assert!(names.iter().all(|name| name.starts_with("sbr-worker-")));
+
+        // Nested calls stay on the same pool.
+        let nested = install(|| install(thread_names));
+        assert!(nested.iter().all(|name| name.starts_with("sbr-worker-")));
+    }
+
+    #[test]
+    fn per_call_override() {
+        let pool = ThreadConfig::new().num_threads(1).build().unwrap();
+        assert_eq!(pool.num_threads(), 1);
+
+        let (num_threads, names) =
+            pool.install(|| install(|| (rayon::current_num_threads(), thread_names())));
+        assert_eq!(num_threads, 1);
+        assert!(names.iter().all(|name| name == "sbr-custom-0"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_threads() {
+        ThreadConfig::new().num_threads(0);
+    }
+}
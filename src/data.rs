@@ -2,22 +2,42 @@
 
 use std;
 use std::cmp::Ordering;
-use std::hash::Hasher;
-
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, Utc};
+use ndarray::Array2;
 use rand::distributions::{Distribution, Uniform};
 use rand::Rng;
 
 use serde::{Deserialize, Serialize};
 use siphasher::sip::SipHasher;
 
-use super::{ItemId, Timestamp, UserId};
+use super::datasets::DatasetError;
+use super::ids::{ItemId, Timestamp, UserId};
+use super::models::top_k;
+use super::{OnlineRankingModel, PredictionError};
+
+fn default_weight() -> f32 {
+    1.0
+}
 
 /// Basic interaction type.
-#[derive(Clone, Serialize, Deserialize, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Interaction {
     user_id: UserId,
     item_id: ItemId,
     timestamp: Timestamp,
+    #[serde(default = "default_weight")]
+    weight: f32,
+    #[serde(default)]
+    context: Option<usize>,
 }
 
 impl Interaction {
@@ -27,8 +47,52 @@ impl Interaction {
             user_id,
             item_id,
             timestamp,
+            weight: default_weight(),
+            context: None,
         }
     }
+
+    /// Create a new interaction at `datetime`, with a timestamp in
+    /// seconds since the Unix epoch.
+    #[cfg(feature = "chrono")]
+    pub fn new_with_datetime(user_id: UserId, item_id: ItemId, datetime: DateTime<Utc>) -> Self {
+        Interaction::new(user_id, item_id, Timestamp::from_datetime(datetime))
+    }
+
+    /// Set the weight of the interaction. Defaults to 1.0.
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Set a categorical context feature (for example, the hour of day)
+    /// describing the circumstances of the interaction.
+    pub fn with_context(mut self, context: usize) -> Self {
+        self.context = Some(context);
+        self
+    }
+}
+
+impl PartialEq for Interaction {
+    fn eq(&self, other: &Interaction) -> bool {
+        self.user_id == other.user_id
+            && self.item_id == other.item_id
+            && self.timestamp == other.timestamp
+            && self.weight.to_bits() == other.weight.to_bits()
+            && self.context == other.context
+    }
+}
+
+impl Eq for Interaction {}
+
+impl Hash for Interaction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.user_id.hash(state);
+        self.item_id.hash(state);
+        self.timestamp.hash(state);
+        self.weight.to_bits().hash(state);
+        self.context.hash(state);
+    }
 }
 
 impl Interaction {
@@ -42,12 +106,22 @@ impl Interaction {
     }
     /// Return the interaction weight.
     pub fn weight(&self) -> f32 {
-        1.0
+        self.weight
     }
     /// Return the interaction timestamp.
     pub fn timestamp(&self) -> Timestamp {
         self.timestamp
     }
+    /// Return the interaction time, reading the timestamp as seconds since
+    /// the Unix epoch, or `None` if it is out of the range of [DateTime].
+    #[cfg(feature = "chrono")]
+    pub fn timestamp_datetime(&self) -> Option<DateTime<Utc>> {
+        self.timestamp.to_datetime()
+    }
+    /// Return the interaction context feature, if any.
+    pub fn context(&self) -> Option<usize> {
+        self.context
+    }
 }
 
 /// Randomly split interactions between test and traiing sets.
@@ -80,13 +154,83 @@ pub fn user_based_split<R: Rng>(
     let is_train = |x: &Interaction| {
         let mut hasher = SipHasher::new_with_keys(key_0, key_1);
         let user_id = x.user_id();
-        hasher.write_usize(user_id);
+        hasher.write_usize(user_id.index());
         hasher.finish() % denominator > train_cutoff
     };
 
     interactions.split_by(is_train)
 }
 
+/// Data error types.
+#[derive(Debug)]
+pub enum DataError {
+    /// A user id is missing from the id mapping.
+    MissingUserId(UserId),
+    /// An item id is missing from the id mapping.
+    MissingItemId(ItemId),
+    /// The target density is negative or above the current density.
+    InvalidTargetDensity {
+        /// The requested density.
+        target: f64,
+        /// The density of the data.
+        current: f64,
+    },
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataError::MissingUserId(user_id) => {
+                write!(f, "User id {} is missing from the mapping.", user_id)
+            }
+            DataError::MissingItemId(item_id) => {
+                write!(f, "Item id {} is missing from the mapping.", item_id)
+            }
+            DataError::InvalidTargetDensity { target, current } => write!(
+                f,
+                "Target density {} must be between 0 and the current density {}.",
+                target, current
+            ),
+        }
+    }
+}
+
+impl error::Error for DataError {}
+
+/// Map every id in `ids` to `0..n`, in increasing order of the original ids.
+fn build_id_mapping<T, I>(ids: I, from_index: fn(usize) -> T) -> HashMap<T, T>
+where
+    T: Copy + Eq + Hash + Ord,
+    I: Iterator<Item = T>,
+{
+    let mut ids: Vec<_> = ids.collect::<HashSet<_>>().into_iter().collect();
+    ids.sort_unstable();
+
+    ids.into_iter()
+        .enumerate()
+        .map(|(new_idx, old_id)| (old_id, from_index(new_idx)))
+        .collect()
+}
+
+/// Count the elements common to two sorted, deduplicated slices.
+fn sorted_intersection_size<T: Ord>(x: &[T], y: &[T]) -> usize {
+    let (mut i, mut j, mut count) = (0, 0, 0);
+
+    while i < x.len() && j < y.len() {
+        match x[i].cmp(&y[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                count += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+
+    count
+}
+
 /// A collection of individual interactions.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Interactions {
@@ -104,6 +248,97 @@ impl Interactions {
             interactions: Vec::new(),
         }
     }
+    /// Read interactions in the Movielens `ratings.dat` format, where
+    /// every line is `UserID<delimiter>MovieID<delimiter>Rating<delimiter>Timestamp`.
+    ///
+    /// The delimiter is `"::"` for the 1M and 10M datasets and `"\t"` for
+    /// the 100K dataset. Ratings of 4 and above are treated as implicit
+    /// positive interactions; all other ratings are dropped.
+    pub fn from_movielens_dat<P: AsRef<Path>>(
+        path: P,
+        delimiter: &str,
+    ) -> Result<Interactions, DatasetError> {
+        let contents = fs::read_to_string(path)?;
+        let mut interactions = Vec::new();
+
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<_> = line.trim().split(delimiter).collect();
+
+            if fields.len() != 4 {
+                return Err(DatasetError::Malformed(format!(
+                    "Malformed Movielens line: {}",
+                    line
+                )));
+            }
+
+            let rating: f32 = fields[2].parse()?;
+
+            if rating >= 4.0 {
+                interactions.push(Interaction::new(
+                    fields[0].parse()?,
+                    fields[1].parse()?,
+                    fields[3].parse()?,
+                ));
+            }
+        }
+
+        if interactions.is_empty() {
+            return Err(DatasetError::Malformed(
+                "No positive interactions found.".to_owned(),
+            ));
+        }
+
+        Ok(Interactions::from(interactions))
+    }
+
+    /// Create interactions from sessions given as ordered lists of items,
+    /// without user ids or timestamps.
+    ///
+    /// Session `i` becomes user `i`, and its items are given timestamps
+    /// `0, 1, 2, ...` in order, so that converting to
+    /// [CompressedInteractions] preserves the session order.
+    pub fn from_sessions(sessions: Vec<Vec<ItemId>>, num_items: usize) -> Interactions {
+        let mut interactions = Interactions::new(sessions.len(), num_items);
+
+        for (user_id, session) in sessions.into_iter().enumerate() {
+            for (timestamp, item_id) in session.into_iter().enumerate() {
+                interactions.push(Interaction::new(
+                    UserId::from_index(user_id),
+                    item_id,
+                    Timestamp::new(timestamp as i64),
+                ));
+            }
+        }
+
+        interactions
+    }
+
+    /// Create interactions from a dense user × item rating matrix.
+    ///
+    /// Every non-zero cell with `value >= threshold` becomes an interaction
+    /// between the row's user and the column's item, with timestamp 0.
+    ///
+    /// The matrix shape is authoritative: it gives the number of users and
+    /// items, so there are no separately declared dimensions to check it
+    /// against, and users and items with no positive cells are kept. Reshape
+    /// or pad the matrix beforehand to fit a model of a given size.
+    pub fn from_rating_matrix(matrix: &Array2<f32>, threshold: f32) -> Interactions {
+        let (num_users, num_items) = matrix.dim();
+        let mut interactions = Interactions::new(num_users, num_items);
+
+        for ((user_id, item_id), &value) in matrix.indexed_iter() {
+            if value != 0.0 && value >= threshold {
+                interactions.push(Interaction::new(
+                    UserId::from_index(user_id),
+                    ItemId::from_index(item_id),
+                    Timestamp::new(0),
+                ));
+            }
+        }
+
+        interactions
+    }
+
     /// Add a new interaction.
     pub fn push(&mut self, interaction: Interaction) {
         self.interactions.push(interaction);
@@ -171,11 +406,392 @@ impl Interactions {
         (head, tail)
     }
 
+    /// Return the number of interactions that repeat an earlier
+    /// (user, item) pair. This is the number of interactions removed
+    /// by either of the deduplication methods.
+    pub fn count_duplicates(&self) -> usize {
+        let unique: HashSet<_> = self
+            .interactions
+            .iter()
+            .map(|x| (x.user_id(), x.item_id()))
+            .collect();
+
+        self.len() - unique.len()
+    }
+
+    /// Keep only the earliest interaction for every (user, item) pair,
+    /// as required by protocols that only consider a user's first
+    /// rating of an item. Ties in timestamps are resolved in favour of
+    /// the interaction that appears first.
+    ///
+    /// See [Interactions::deduplicate_keep_last] for the opposite policy.
+    pub fn deduplicate_keep_first(&mut self) {
+        self.deduplicate_with(|kept, candidate| {
+            if candidate.timestamp() < kept.timestamp() {
+                candidate.clone()
+            } else {
+                kept.clone()
+            }
+        });
+    }
+
+    /// Keep only the latest interaction for every (user, item) pair.
+    /// Ties in timestamps are resolved in favour of the interaction
+    /// that appears last.
+    ///
+    /// See [Interactions::deduplicate_keep_first] for the opposite policy.
+    pub fn deduplicate_keep_last(&mut self) {
+        self.deduplicate_with(|kept, candidate| {
+            if candidate.timestamp() >= kept.timestamp() {
+                candidate.clone()
+            } else {
+                kept.clone()
+            }
+        });
+    }
+
+    /// Reduce the interactions of every (user, item) pair to one, using
+    /// `keep_fn` to resolve conflicts.
+    ///
+    /// Within each pair, `keep_fn` is called in order of appearance with
+    /// the interaction kept so far and the next conflicting interaction,
+    /// and returns the interaction to keep; it may merge the two, for
+    /// example by summing their weights. The result takes the place of
+    /// the pair's first interaction, and should have the same user and
+    /// item.
+    pub fn deduplicate_with<F>(&mut self, keep_fn: F)
+    where
+        F: Fn(&Interaction, &Interaction) -> Interaction,
+    {
+        let mut positions = HashMap::with_capacity(self.len());
+        let mut deduplicated: Vec<Interaction> = Vec::with_capacity(self.len());
+
+        for interaction in self.interactions.drain(..) {
+            match positions.entry((interaction.user_id(), interaction.item_id())) {
+                Entry::Occupied(entry) => {
+                    let kept = &mut deduplicated[*entry.get()];
+                    *kept = keep_fn(kept, &interaction);
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(deduplicated.len());
+                    deduplicated.push(interaction);
+                }
+            }
+        }
+
+        self.interactions = deduplicated;
+    }
+
+    /// Return a copy with each interaction removed with probability
+    /// `fraction`, simulating interactions that were never recorded.
+    ///
+    /// The number of users and items is preserved.
+    pub fn simulate_missing_at_random<R: Rng>(&self, fraction: f32, rng: &mut R) -> Interactions {
+        Interactions {
+            num_users: self.num_users,
+            num_items: self.num_items,
+            interactions: self
+                .interactions
+                .iter()
+                .filter(|_| rng.gen::<f32>() >= fraction)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Return a copy with interactions removed preferentially when they
+    /// are with popular items, simulating popular items being more likely
+    /// to fall outside the observation window.
+    ///
+    /// An interaction with an item is removed with probability
+    /// `popularity_bias * count / max_count`, where `count` is the
+    /// number of interactions with the item and `max_count` that of the
+    /// most popular item. The number of users and items is preserved.
+    pub fn simulate_missing_not_at_random<R: Rng>(
+        &self,
+        popularity_bias: f32,
+        rng: &mut R,
+    ) -> Interactions {
+        let mut counts = vec![0; self.num_items];
+        for interaction in &self.interactions {
+            counts[interaction.item_id().index()] += 1;
+        }
+        let max_count = counts.iter().cloned().max().unwrap_or(0).max(1) as f32;
+
+        Interactions {
+            num_users: self.num_users,
+            num_items: self.num_items,
+            interactions: self
+                .interactions
+                .iter()
+                .filter(|interaction| {
+                    let removal_probability =
+                        popularity_bias * counts[interaction.item_id().index()] as f32 / max_count;
+                    rng.gen::<f32>() >= removal_probability
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Return a copy with interactions sampled without replacement so that
+    /// its density (see [Interactions::density]) is approximately
+    /// `target_density`, for studying the effect of sparsity.
+    ///
+    /// The number of users and items is preserved, as is the order of
+    /// the remaining interactions. Since interactions can only be removed,
+    /// a target above the current density is an error.
+    pub fn resample_to_target_density<R: Rng>(
+        &self,
+        target_density: f64,
+        rng: &mut R,
+    ) -> Result<Interactions, DataError> {
+        if !(0.0..=self.density()).contains(&target_density) {
+            return Err(DataError::InvalidTargetDensity {
+                target: target_density,
+                current: self.density(),
+            });
+        }
+
+        let num_cells = self.num_users as f64 * self.num_items as f64;
+        let num_sampled = ((target_density * num_cells).round() as usize).min(self.len());
+
+        let mut indices: Vec<_> = (0..self.len()).collect();
+        rng.shuffle(&mut indices);
+        indices.truncate(num_sampled);
+        indices.sort_unstable();
+
+        Ok(Interactions {
+            num_users: self.num_users,
+            num_items: self.num_items,
+            interactions: indices
+                .into_iter()
+                .map(|idx| self.interactions[idx].clone())
+                .collect(),
+        })
+    }
+
+    /// Return a copy containing a `fraction` of the interactions in each
+    /// of `num_strata` equal-width buckets of the timestamp range, sampled
+    /// independently and without replacement.
+    ///
+    /// The number kept from each bucket is rounded up, so sparsely
+    /// populated time periods remain represented. The number of users
+    /// and items is preserved.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_strata` is zero.
+    pub fn stratified_sample_by_timestamp<R: Rng>(
+        &self,
+        num_strata: usize,
+        fraction: f32,
+        rng: &mut R,
+    ) -> Interactions {
+        assert!(num_strata > 0, "There must be at least one stratum.");
+
+        let timestamps = self.interactions.iter().map(|x| x.timestamp());
+        let min_timestamp = timestamps.clone().min().unwrap_or_default();
+        let max_timestamp = timestamps.max().unwrap_or_default();
+        let width = (max_timestamp - min_timestamp) as f64 / num_strata as f64;
+
+        let mut strata = vec![Vec::new(); num_strata];
+        for (idx, interaction) in self.interactions.iter().enumerate() {
+            let stratum = if width > 0.0 {
+                ((interaction.timestamp() - min_timestamp) as f64 / width) as usize
+            } else {
+                0
+            };
+            strata[stratum.min(num_strata - 1)].push(idx);
+        }
+
+        let mut selected = Vec::new();
+        for mut stratum in strata {
+            let num_selected = (fraction * stratum.len() as f32).ceil() as usize;
+            rng.shuffle(&mut stratum);
+            selected.extend(stratum.into_iter().take(num_selected));
+        }
+        selected.sort_unstable();
+
+        Interactions {
+            num_users: self.num_users,
+            num_items: self.num_items,
+            interactions: selected
+                .into_iter()
+                .map(|idx| self.interactions[idx].clone())
+                .collect(),
+        }
+    }
+
+    /// Return a copy with user ids replaced according to `mapping`.
+    ///
+    /// The number of users becomes one more than the largest id in
+    /// `mapping`. Fails if any user id is missing from `mapping`.
+    pub fn convert_user_ids(
+        &self,
+        mapping: &HashMap<UserId, UserId>,
+    ) -> Result<Interactions, DataError> {
+        let interactions = self
+            .interactions
+            .iter()
+            .map(|interaction| {
+                let user_id = *mapping
+                    .get(&interaction.user_id)
+                    .ok_or(DataError::MissingUserId(interaction.user_id))?;
+
+                Ok(Interaction {
+                    user_id,
+                    ..interaction.clone()
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Interactions {
+            num_users: mapping
+                .values()
+                .max()
+                .map_or(0, |max_id| max_id.index() + 1),
+            num_items: self.num_items,
+            interactions,
+        })
+    }
+
+    /// Return a copy with item ids replaced according to `mapping`.
+    ///
+    /// The number of items becomes one more than the largest id in
+    /// `mapping`. Fails if any item id is missing from `mapping`.
+    pub fn convert_item_ids(
+        &self,
+        mapping: &HashMap<ItemId, ItemId>,
+    ) -> Result<Interactions, DataError> {
+        let interactions = self
+            .interactions
+            .iter()
+            .map(|interaction| {
+                let item_id = *mapping
+                    .get(&interaction.item_id)
+                    .ok_or(DataError::MissingItemId(interaction.item_id))?;
+
+                Ok(Interaction {
+                    item_id,
+                    ..interaction.clone()
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Interactions {
+            num_users: self.num_users,
+            num_items: mapping
+                .values()
+                .max()
+                .map_or(0, |max_id| max_id.index() + 1),
+            interactions,
+        })
+    }
+
+    /// Build a mapping from the user ids present in the interactions
+    /// to consecutive ids `0..n`, preserving their order.
+    pub fn build_user_id_mapping(&self) -> HashMap<UserId, UserId> {
+        build_id_mapping(
+            self.interactions.iter().map(|x| x.user_id()),
+            UserId::from_index,
+        )
+    }
+
+    /// Build a mapping from the item ids present in the interactions
+    /// to consecutive ids `0..n`, preserving their order.
+    pub fn build_item_id_mapping(&self) -> HashMap<ItemId, ItemId> {
+        build_id_mapping(
+            self.interactions.iter().map(|x| x.item_id()),
+            ItemId::from_index,
+        )
+    }
+
+    /// For every item with at least one interaction, return its `k` most
+    /// similar items by Jaccard similarity of the sets of users who
+    /// interacted with them, best first.
+    ///
+    /// Only pairs of items sharing at least one user are compared, so the
+    /// cost depends on the number of co-occurrences rather than growing
+    /// quadratically with the number of items.
+    pub fn compute_item_jaccard_top_k(&self, k: usize) -> HashMap<ItemId, Vec<(ItemId, f32)>> {
+        let mut item_users: HashMap<ItemId, Vec<UserId>> = HashMap::new();
+        let mut user_items: HashMap<UserId, Vec<ItemId>> = HashMap::new();
+
+        for interaction in &self.interactions {
+            item_users
+                .entry(interaction.item_id())
+                .or_insert_with(Vec::new)
+                .push(interaction.user_id());
+            user_items
+                .entry(interaction.user_id())
+                .or_insert_with(Vec::new)
+                .push(interaction.item_id());
+        }
+
+        for user_ids in item_users.values_mut() {
+            user_ids.sort_unstable();
+            user_ids.dedup();
+        }
+        for item_ids in user_items.values_mut() {
+            item_ids.sort_unstable();
+            item_ids.dedup();
+        }
+
+        item_users
+            .iter()
+            .map(|(&item_id, users)| {
+                let candidates: HashSet<ItemId> = users
+                    .iter()
+                    .flat_map(|user_id| user_items[user_id].iter().cloned())
+                    .filter(|&other_id| other_id != item_id)
+                    .collect();
+
+                let similarities = candidates
+                    .into_iter()
+                    .map(|other_id| {
+                        let other_users = &item_users[&other_id];
+                        let intersection = sorted_intersection_size(users, other_users);
+                        let union = users.len() + other_users.len() - intersection;
+
+                        (other_id, intersection as f32 / union as f32)
+                    })
+                    .collect();
+
+                (item_id, top_k(similarities, k))
+            })
+            .collect()
+    }
+
     /// Covert to triplet representation.
     pub fn to_triplet(&self) -> TripletInteractions {
         TripletInteractions::from(self)
     }
 
+    /// Return the items of each user, indexed by user id, in the order
+    /// the interactions were added. Repeated interactions are kept.
+    pub fn to_user_adjacency_list(&self) -> Vec<Vec<ItemId>> {
+        let mut adjacency = vec![Vec::new(); self.num_users];
+
+        for interaction in &self.interactions {
+            adjacency[interaction.user_id().index()].push(interaction.item_id());
+        }
+
+        adjacency
+    }
+
+    /// Return the users of each item, indexed by item id, in the order
+    /// the interactions were added. Repeated interactions are kept.
+    pub fn to_item_adjacency_list(&self) -> Vec<Vec<UserId>> {
+        let mut adjacency = vec![Vec::new(); self.num_items];
+
+        for interaction in &self.interactions {
+            adjacency[interaction.item_id().index()].push(interaction.user_id());
+        }
+
+        adjacency
+    }
+
     /// Convert to compressed representation.
     pub fn to_compressed(&self) -> CompressedInteractions {
         CompressedInteractions::from(self)
@@ -195,12 +811,34 @@ impl Interactions {
     pub fn shape(&self) -> (usize, usize) {
         (self.num_users, self.num_items)
     }
+
+    /// Return the number of interactions divided by the number of
+    /// possible (user, item) pairs, or zero if there are none.
+    pub fn density(&self) -> f64 {
+        let num_cells = self.num_users as f64 * self.num_items as f64;
+
+        if num_cells == 0.0 {
+            0.0
+        } else {
+            self.len() as f64 / num_cells
+        }
+    }
 }
 
 impl From<Vec<Interaction>> for Interactions {
     fn from(interactions: Vec<Interaction>) -> Interactions {
-        let num_users = interactions.iter().map(|x| x.user_id()).max().unwrap() + 1;
-        let num_items = interactions.iter().map(|x| x.item_id()).max().unwrap() + 1;
+        let num_users = interactions
+            .iter()
+            .map(|x| x.user_id().index())
+            .max()
+            .unwrap()
+            + 1;
+        let num_items = interactions
+            .iter()
+            .map(|x| x.item_id().index())
+            .max()
+            .unwrap()
+            + 1;
 
         Interactions {
             num_users,
@@ -210,6 +848,30 @@ impl From<Vec<Interaction>> for Interactions {
     }
 }
 
+/// Interactions of `num_users` users, each walking through `sequence_length`
+/// consecutive items from its own id, modulo `num_items`, to keep tests short.
+#[cfg(test)]
+pub(crate) fn synthetic_interactions(
+    num_users: usize,
+    num_items: usize,
+    sequence_length: usize,
+) -> Interactions {
+    let mut interactions = Vec::new();
+
+    for user_id in 0..num_users {
+        for timestamp in 0..sequence_length {
+            let item_id = (user_id + timestamp) % num_items;
+            interactions.push(Interaction::new(
+                UserId::from_index(user_id),
+                ItemId::from_index(item_id),
+                Timestamp(timestamp as i64),
+            ));
+        }
+    }
+
+    Interactions::from(interactions)
+}
+
 fn cmp_timestamp(x: &Interaction, y: &Interaction) -> Ordering {
     let uid_comparison = x.user_id().cmp(&y.user_id());
 
@@ -231,6 +893,9 @@ pub struct CompressedInteractions {
     user_pointers: Vec<usize>,
     item_ids: Vec<ItemId>,
     timestamps: Vec<Timestamp>,
+    contexts: Option<Vec<usize>>,
+    #[serde(default)]
+    weights: Option<Vec<f32>>,
 }
 
 impl<'a> From<&'a Interactions> for CompressedInteractions {
@@ -243,11 +908,28 @@ impl<'a> From<&'a Interactions> for CompressedInteractions {
         let mut item_ids = Vec::with_capacity(data.len());
         let mut timestamps = Vec::with_capacity(data.len());
 
+        // Interactions without a context get context 0 as long as
+        // at least one interaction has one.
+        let has_contexts = data.iter().any(|x| x.context().is_some());
+        let mut contexts = Vec::with_capacity(if has_contexts { data.len() } else { 0 });
+
+        // Weights are only stored if they are not all 1.
+        let has_weights = data.iter().any(|x| x.weight() != 1.0);
+        let mut weights = Vec::with_capacity(if has_weights { data.len() } else { 0 });
+
         for datum in &data {
             item_ids.push(datum.item_id());
             timestamps.push(datum.timestamp());
 
-            user_pointers[datum.user_id() + 1] += 1;
+            if has_contexts {
+                contexts.push(datum.context().unwrap_or(0));
+            }
+
+            if has_weights {
+                weights.push(datum.weight());
+            }
+
+            user_pointers[datum.user_id().index() + 1] += 1;
         }
 
         for idx in 1..user_pointers.len() {
@@ -260,6 +942,8 @@ impl<'a> From<&'a Interactions> for CompressedInteractions {
             user_pointers,
             item_ids,
             timestamps,
+            contexts: if has_contexts { Some(contexts) } else { None },
+            weights: if has_weights { Some(weights) } else { None },
         }
     }
 }
@@ -275,17 +959,21 @@ impl CompressedInteractions {
 
     /// Get a particular user's interactions.
     pub fn get_user(&self, user_id: UserId) -> Option<CompressedInteractionsUser> {
-        if user_id >= self.num_users {
+        let user_idx = user_id.index();
+
+        if user_idx >= self.num_users {
             return None;
         }
 
-        let start = self.user_pointers[user_id];
-        let stop = self.user_pointers[user_id + 1];
+        let start = self.user_pointers[user_idx];
+        let stop = self.user_pointers[user_idx + 1];
 
         Some(CompressedInteractionsUser {
             user_id,
             item_ids: &self.item_ids[start..stop],
             timestamps: &self.timestamps[start..stop],
+            contexts: self.contexts.as_ref().map(|x| &x[start..stop]),
+            weights: self.weights.as_ref().map(|x| &x[start..stop]),
         })
     }
 
@@ -304,17 +992,219 @@ impl CompressedInteractions {
         (self.num_users, self.num_items)
     }
 
+    /// Return a copy keeping only the `max_len` most recent interactions
+    /// of each user. The number of users and items is preserved.
+    pub fn truncate_user_histories(&self, max_len: usize) -> CompressedInteractions {
+        let mut user_pointers = Vec::with_capacity(self.num_users + 1);
+        let mut item_ids = Vec::new();
+        let mut timestamps = Vec::new();
+        let mut contexts = self.contexts.as_ref().map(|_| Vec::new());
+        let mut weights = self.weights.as_ref().map(|_| Vec::new());
+
+        user_pointers.push(0);
+
+        for user in self.iter_users() {
+            let user = user.most_recent(max_len);
+
+            item_ids.extend_from_slice(user.item_ids);
+            timestamps.extend_from_slice(user.timestamps);
+            if let (Some(contexts), Some(user_contexts)) = (contexts.as_mut(), user.contexts) {
+                contexts.extend_from_slice(user_contexts);
+            }
+            if let (Some(weights), Some(user_weights)) = (weights.as_mut(), user.weights) {
+                weights.extend_from_slice(user_weights);
+            }
+
+            user_pointers.push(item_ids.len());
+        }
+
+        CompressedInteractions {
+            num_users: self.num_users,
+            num_items: self.num_items,
+            user_pointers,
+            item_ids,
+            timestamps,
+            contexts,
+            weights,
+        }
+    }
+
+    /// Sample a user uniformly at random from users with at least one
+    /// interaction. Returns `None` if there are no interactions.
+    pub fn sample_random_user<R: Rng>(&self, rng: &mut R) -> Option<CompressedInteractionsUser> {
+        let user_ids: Vec<UserId> = (0..self.num_users)
+            .filter(|&user_idx| self.user_pointers[user_idx + 1] > self.user_pointers[user_idx])
+            .map(UserId::from_index)
+            .collect();
+
+        if user_ids.is_empty() {
+            return None;
+        }
+
+        self.get_user(user_ids[rng.gen_range(0, user_ids.len())])
+    }
+
+    /// Sample an item uniformly at random from items that appear in at
+    /// least one interaction. Returns `None` if there are no interactions.
+    pub fn sample_random_item<R: Rng>(&self, rng: &mut R) -> Option<ItemId> {
+        let mut present = vec![false; self.num_items];
+        for &item_id in &self.item_ids {
+            present[item_id.index()] = true;
+        }
+
+        let item_ids: Vec<ItemId> = (0..self.num_items)
+            .filter(|&item_idx| present[item_idx])
+            .map(ItemId::from_index)
+            .collect();
+
+        if item_ids.is_empty() {
+            return None;
+        }
+
+        Some(item_ids[rng.gen_range(0, item_ids.len())])
+    }
+
+    /// Return the ids of users with no interactions, in increasing order.
+    pub fn users_without_interactions(&self) -> Vec<UserId> {
+        (0..self.num_users)
+            .filter(|&user_idx| self.user_pointers[user_idx + 1] == self.user_pointers[user_idx])
+            .map(UserId::from_index)
+            .collect()
+    }
+
+    /// Return the ids of items with no interactions, in increasing order.
+    pub fn items_without_interactions(&self) -> Vec<ItemId> {
+        let mut present = vec![false; self.num_items];
+        for &item_id in &self.item_ids {
+            present[item_id.index()] = true;
+        }
+
+        (0..self.num_items)
+            .filter(|&item_idx| !present[item_idx])
+            .map(ItemId::from_index)
+            .collect()
+    }
+
+    /// Count how often item `b` immediately follows item `a` in a user's
+    /// history, for every pair `(a, b)` that occurs.
+    pub fn item_transition_matrix(&self) -> HashMap<(ItemId, ItemId), usize> {
+        let mut transitions = HashMap::new();
+
+        for user in self.iter_users() {
+            for pair in user.item_ids.windows(2) {
+                *transitions.entry((pair[0], pair[1])).or_insert(0) += 1;
+            }
+        }
+
+        transitions
+    }
+
+    /// The empirical probability that item `item_b` immediately follows
+    /// item `item_a`: the number of `(item_a, item_b)` transitions divided
+    /// by the number of occurrences of `item_a` that are not the last
+    /// in their user's history. Zero if there are no such occurrences.
+    ///
+    /// This scans all interactions; use [CompressedInteractions::item_transition_matrix]
+    /// to compute many probabilities.
+    pub fn item_transition_probability(&self, item_a: ItemId, item_b: ItemId) -> f32 {
+        let (count, total) = self
+            .iter_users()
+            .flat_map(|user| user.item_ids.windows(2))
+            .filter(|pair| pair[0] == item_a)
+            .fold((0, 0), |(count, total), pair| {
+                (count + (pair[1] == item_b) as usize, total + 1)
+            });
+
+        if total == 0 {
+            0.0
+        } else {
+            count as f32 / total as f32
+        }
+    }
+
+    /// Perform a random walk on the bipartite user-item graph, starting
+    /// at user `start_user`.
+    ///
+    /// Each element of the returned walk is `(is_item, index)`, where
+    /// `index` is the [ItemId::index] or [UserId::index] of the node. From a user,
+    /// the walker moves to a random item the user interacted with; from
+    /// an item, to a random user who interacted with it. Following the
+    /// node2vec `p` parameter, with probability `return_probability` the
+    /// walker instead steps back to the node it has just come from.
+    ///
+    /// The walk has at most `walk_length` nodes, including the start, and
+    /// ends early at nodes without neighbours. It is empty if `start_user`
+    /// is out of range.
+    pub fn random_walk<R: Rng>(
+        &self,
+        start_user: UserId,
+        walk_length: usize,
+        return_probability: f32,
+        rng: &mut R,
+    ) -> Vec<(bool, usize)> {
+        let mut walk = Vec::with_capacity(walk_length);
+
+        if start_user.index() >= self.num_users || walk_length == 0 {
+            return walk;
+        }
+
+        let mut item_users = vec![Vec::new(); self.num_items];
+        let mut user_items = Vec::with_capacity(self.num_users);
+        for user in self.iter_users() {
+            for &item_id in user.item_ids {
+                item_users[item_id.index()].push(user.user_id.index());
+            }
+            user_items.push(
+                user.item_ids
+                    .iter()
+                    .map(|item_id| item_id.index())
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        let mut previous: Option<(bool, usize)> = None;
+        let mut current = (false, start_user.index());
+        walk.push(current);
+
+        while walk.len() < walk_length {
+            let (is_item, idx) = current;
+            let neighbours: &[usize] = if is_item {
+                &item_users[idx]
+            } else {
+                &user_items[idx]
+            };
+
+            if neighbours.is_empty() {
+                break;
+            }
+
+            let next = match previous {
+                Some(previous) if rng.gen::<f32>() < return_probability => previous,
+                _ => (!is_item, neighbours[rng.gen_range(0, neighbours.len())]),
+            };
+
+            previous = Some(current);
+            current = next;
+            walk.push(current);
+        }
+
+        walk
+    }
+
     /// Convert to `Interactions`.
     pub fn to_interactions(&self) -> Interactions {
         let mut interactions = Vec::new();
 
         for user in self.iter_users() {
-            for (&item_id, &timestamp) in izip!(user.item_ids, user.timestamps) {
-                interactions.push(Interaction {
-                    user_id: user.user_id,
-                    item_id,
-                    timestamp,
-                });
+            for (idx, (&item_id, &timestamp)) in izip!(user.item_ids, user.timestamps).enumerate() {
+                let mut interaction = Interaction::new(user.user_id, item_id, timestamp);
+                if let Some(contexts) = user.contexts {
+                    interaction = interaction.with_context(contexts[idx]);
+                }
+                if let Some(weights) = user.weights {
+                    interaction = interaction.with_weight(weights[idx]);
+                }
+                interactions.push(interaction);
             }
         }
 
@@ -344,6 +1234,10 @@ pub struct CompressedInteractionsUser<'a> {
     pub item_ids: &'a [ItemId],
     /// The timestamps of the user's interactions.
     pub timestamps: &'a [Timestamp],
+    /// The context features of the user's interactions, if present.
+    pub contexts: Option<&'a [usize]>,
+    /// The weights of the user's interactions, if any differ from 1.
+    pub weights: Option<&'a [f32]>,
 }
 
 impl<'a> CompressedInteractionsUser<'a> {
@@ -357,6 +1251,19 @@ impl<'a> CompressedInteractionsUser<'a> {
         self.item_ids.is_empty()
     }
 
+    /// Return this user's `n` most recent interactions.
+    pub fn most_recent(&self, n: usize) -> CompressedInteractionsUser<'a> {
+        let start = self.len().saturating_sub(n);
+
+        CompressedInteractionsUser {
+            user_id: self.user_id,
+            item_ids: &self.item_ids[start..],
+            timestamps: &self.timestamps[start..],
+            contexts: self.contexts.map(|x| &x[start..]),
+            weights: self.weights.map(|x| &x[start..]),
+        }
+    }
+
     /// Return a chunked iterator over interactions for this user.
     /// The chunks are such that the _first_ chunk is smallest,
     /// and the remaining chunks are all of `chunk_size`.
@@ -380,9 +1287,11 @@ impl<'a> Iterator for CompressedInteractionsUserIterator<'a> {
             let stop = self.interactions.user_pointers[self.idx + 1];
 
             Some(CompressedInteractionsUser {
-                user_id: self.idx,
+                user_id: UserId::from_index(self.idx),
                 item_ids: &self.interactions.item_ids[start..stop],
                 timestamps: &self.interactions.timestamps[start..stop],
+                contexts: self.interactions.contexts.as_ref().map(|x| &x[start..stop]),
+                weights: self.interactions.weights.as_ref().map(|x| &x[start..stop]),
             })
         };
 
@@ -574,15 +1483,281 @@ impl<'a> From<&'a Interactions> for TripletInteractions {
     }
 }
 
+/// Triplet interactions paired with a pre-selected negative item each,
+/// as produced by [sample_hard_negatives].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TripletWithNegatives {
+    num_users: usize,
+    num_items: usize,
+    user_ids: Vec<UserId>,
+    item_ids: Vec<ItemId>,
+    negative_item_ids: Vec<ItemId>,
+    timestamps: Vec<Timestamp>,
+}
+
+impl TripletWithNegatives {
+    /// Return length.
+    pub fn len(&self) -> usize {
+        self.user_ids.len()
+    }
+
+    /// Check if there are no interactions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the user ids.
+    pub fn user_ids(&self) -> &[UserId] {
+        &self.user_ids
+    }
+
+    /// Return the positive item ids.
+    pub fn item_ids(&self) -> &[ItemId] {
+        &self.item_ids
+    }
+
+    /// Return the negative item ids, one per positive item.
+    pub fn negative_item_ids(&self) -> &[ItemId] {
+        &self.negative_item_ids
+    }
+
+    /// Return the timestamps of the positive interactions.
+    pub fn timestamps(&self) -> &[Timestamp] {
+        &self.timestamps
+    }
+
+    /// Return number of users in the dataset.
+    pub fn num_users(&self) -> usize {
+        self.num_users
+    }
+
+    /// Return number of items in the dataset.
+    pub fn num_items(&self) -> usize {
+        self.num_items
+    }
+}
+
+/// Mine hard negatives offline: for each interaction, sample `n_candidates`
+/// items the user never interacted with, score them with `model` given the
+/// user's preceding interactions, and keep the highest-scoring one.
+///
+/// The negatives are fixed, so they can be reused across training passes
+/// without rescoring. Users who interacted with every item are skipped.
+///
+/// # Panics
+///
+/// Panics if `n_candidates` is zero.
+pub fn sample_hard_negatives<M: OnlineRankingModel, R: Rng>(
+    interactions: &CompressedInteractions,
+    model: &M,
+    n_candidates: usize,
+    rng: &mut R,
+) -> Result<TripletWithNegatives, PredictionError> {
+    assert!(n_candidates > 0, "Must sample at least one candidate.");
+
+    let num_items = interactions.num_items();
+    let mut triplets = TripletWithNegatives {
+        num_users: interactions.num_users(),
+        num_items,
+        user_ids: Vec::new(),
+        item_ids: Vec::new(),
+        negative_item_ids: Vec::new(),
+        timestamps: Vec::new(),
+    };
+
+    let item_range = Uniform::new(0, num_items.max(1));
+    let mut candidates = Vec::with_capacity(n_candidates);
+
+    for user in interactions.iter_users() {
+        let history: HashSet<_> = user.item_ids.iter().cloned().collect();
+
+        if history.len() >= num_items {
+            continue;
+        }
+
+        for (idx, (&item_id, &timestamp)) in izip!(user.item_ids, user.timestamps).enumerate() {
+            candidates.clear();
+            while candidates.len() < n_candidates {
+                let candidate = ItemId::from_index(item_range.sample(rng));
+                if !history.contains(&candidate) {
+                    candidates.push(candidate);
+                }
+            }
+
+            let representation = model.user_representation(&user.item_ids[..idx])?;
+            let scores = model.predict(&representation, &candidates)?;
+
+            if !scores.iter().all(|score| score.is_finite()) {
+                return Err(PredictionError::InvalidPredictionValue);
+            }
+
+            let (negative, _) = top_k(candidates.iter().cloned().zip(scores).collect(), 1)[0];
+
+            triplets.user_ids.push(user.user_id);
+            triplets.item_ids.push(item_id);
+            triplets.negative_item_ids.push(negative);
+            triplets.timestamps.push(timestamp);
+        }
+    }
+
+    Ok(triplets)
+}
+
+/// Sparse binary item features: the feature ids of each item, as the
+/// rows of an item × feature matrix.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ItemFeatures {
+    num_features: usize,
+    features: Vec<Vec<usize>>,
+}
+
+impl ItemFeatures {
+    /// Create a featureless `num_items` × `num_features` matrix.
+    pub fn new(num_items: usize, num_features: usize) -> Self {
+        ItemFeatures {
+            num_features,
+            features: vec![Vec::new(); num_items],
+        }
+    }
+
+    /// Mark `item_id` as having feature `feature_id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either id is out of range.
+    pub fn push(&mut self, item_id: ItemId, feature_id: usize) {
+        assert!(feature_id < self.num_features, "Feature id out of range.");
+
+        let features = &mut self.features[item_id.index()];
+
+        if let Err(idx) = features.binary_search(&feature_id) {
+            features.insert(idx, feature_id);
+        }
+    }
+
+    /// Return the sorted feature ids of `item_id`.
+    pub fn item_features(&self, item_id: ItemId) -> &[usize] {
+        &self.features[item_id.index()]
+    }
+
+    /// Return number of items.
+    pub fn num_items(&self) -> usize {
+        self.features.len()
+    }
+
+    /// Return number of features.
+    pub fn num_features(&self) -> usize {
+        self.num_features
+    }
+}
+
+/// Assigns consecutive UserIds to external string user ids, such as
+/// UUIDs or usernames, and maps between the two.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UserIdMapper {
+    map: HashMap<String, UserId>,
+    reverse: Vec<String>,
+}
+
+impl UserIdMapper {
+    /// Create an empty mapper.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the id of `user_str`, assigning the next unused id if it
+    /// has not been seen before.
+    pub fn get_or_insert(&mut self, user_str: &str) -> UserId {
+        if let Some(&user_id) = self.map.get(user_str) {
+            return user_id;
+        }
+
+        let user_id = UserId::from_index(self.reverse.len());
+        self.map.insert(user_str.to_owned(), user_id);
+        self.reverse.push(user_str.to_owned());
+
+        user_id
+    }
+
+    /// Return the id of `user_str`, if assigned.
+    pub fn lookup_id(&self, user_str: &str) -> Option<UserId> {
+        self.map.get(user_str).cloned()
+    }
+
+    /// Return the string id `user_id` was assigned to, if any.
+    pub fn lookup_str(&self, user_id: UserId) -> Option<&str> {
+        self.reverse.get(user_id.index()).map(|x| x.as_str())
+    }
+
+    /// Return the number of assigned ids.
+    pub fn num_users(&self) -> usize {
+        self.reverse.len()
+    }
+}
+
+/// Assigns consecutive ItemIds to external string item ids, such as
+/// UUIDs or usernames, and maps between the two.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ItemIdMapper {
+    map: HashMap<String, ItemId>,
+    reverse: Vec<String>,
+}
+
+impl ItemIdMapper {
+    /// Create an empty mapper.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the id of `item_str`, assigning the next unused id if it
+    /// has not been seen before.
+    pub fn get_or_insert(&mut self, item_str: &str) -> ItemId {
+        if let Some(&item_id) = self.map.get(item_str) {
+            return item_id;
+        }
+
+        let item_id = ItemId::from_index(self.reverse.len());
+        self.map.insert(item_str.to_owned(), item_id);
+        self.reverse.push(item_str.to_owned());
+
+        item_id
+    }
+
+    /// Return the id of `item_str`, if assigned.
+    pub fn lookup_id(&self, item_str: &str) -> Option<ItemId> {
+        self.map.get(item_str).cloned()
+    }
+
+    /// Return the string id `item_id` was assigned to, if any.
+    pub fn lookup_str(&self, item_id: ItemId) -> Option<&str> {
+        self.reverse.get(item_id.index()).map(|x| x.as_str())
+    }
+
+    /// Return the number of assigned ids.
+    pub fn num_items(&self) -> usize {
+        self.reverse.len()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::collections::HashSet;
-
     use rand;
     use rand::distributions::{Distribution, Uniform};
     use rand::SeedableRng;
 
     use super::*;
+    use crate::ids::{items, times};
+
+    #[test]
+    fn synthetic_interactions_shape() {
+        let data = synthetic_interactions(3, 4, 5).to_compressed();
+
+        assert_eq!(data.num_users(), 3);
+        assert_eq!(data.num_items(), 4);
+        for user in data.iter_users() {
+            assert_eq!(user.item_ids.len(), 5);
+        }
+    }
 
     #[test]
     fn to_compressed() {
@@ -597,10 +1772,12 @@ mod tests {
         let mut rng = rand::XorShiftRng::from_seed([42; 16]);
 
         let interactions: Vec<_> = (0..num_interactions)
-            .map(|_| Interaction {
-                user_id: user_range.sample(&mut rng),
-                item_id: item_range.sample(&mut rng),
-                timestamp: timestamp_range.sample(&mut rng),
+            .map(|_| {
+                Interaction::new(
+                    UserId::from_index(user_range.sample(&mut rng)),
+                    ItemId::from_index(item_range.sample(&mut rng)),
+                    Timestamp(timestamp_range.sample(&mut rng)),
+                )
             })
             .collect();
 
@@ -626,6 +1803,328 @@ mod tests {
         }
     }
 
+    #[test]
+    fn convert_ids() {
+        let interactions = Interactions::from(vec![
+            Interaction::new(UserId(10), ItemId(7), Timestamp(0)),
+            Interaction::new(UserId(3), ItemId(7), Timestamp(1)),
+            Interaction::new(UserId(10), ItemId(2), Timestamp(2)).with_weight(2.0),
+        ]);
+
+        let user_mapping = interactions.build_user_id_mapping();
+        let item_mapping = interactions.build_item_id_mapping();
+
+        assert_eq!(
+            user_mapping,
+            [(UserId(3), UserId(0)), (UserId(10), UserId(1))]
+                .iter()
+                .cloned()
+                .collect()
+        );
+        assert_eq!(
+            item_mapping,
+            [(ItemId(2), ItemId(0)), (ItemId(7), ItemId(1))]
+                .iter()
+                .cloned()
+                .collect()
+        );
+
+        let converted = interactions
+            .convert_user_ids(&user_mapping)
+            .unwrap()
+            .convert_item_ids(&item_mapping)
+            .unwrap();
+
+        assert_eq!(converted.shape(), (2, 2));
+        assert_eq!(
+            converted.data(),
+            &[
+                Interaction::new(UserId(1), ItemId(1), Timestamp(0)),
+                Interaction::new(UserId(0), ItemId(1), Timestamp(1)),
+                Interaction::new(UserId(1), ItemId(0), Timestamp(2)).with_weight(2.0),
+            ]
+        );
+
+        let mut partial = HashMap::new();
+        partial.insert(UserId(10), UserId(0));
+
+        match interactions.convert_user_ids(&partial) {
+            Err(DataError::MissingUserId(UserId(3))) => {}
+            _ => panic!("Expected a missing user id error."),
+        }
+    }
+
+    #[test]
+    fn simulate_missing() {
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+
+        // Item 0 is popular, the other items appear once each.
+        let interactions: Vec<_> = (0..1000)
+            .flat_map(|user_id| {
+                vec![
+                    Interaction::new(UserId(user_id), ItemId(0), Timestamp(0)),
+                    Interaction::new(UserId(user_id), ItemId(user_id + 1), Timestamp(1)),
+                ]
+            })
+            .collect();
+        let interactions = Interactions::from(interactions);
+
+        let missing = interactions.simulate_missing_at_random(0.3, &mut rng);
+        assert_eq!(missing.shape(), interactions.shape());
+        assert!(missing.len() > 1300 && missing.len() < 1500);
+
+        let missing = interactions.simulate_missing_not_at_random(0.5, &mut rng);
+        assert_eq!(missing.shape(), interactions.shape());
+
+        let popular = missing
+            .data()
+            .iter()
+            .filter(|x| x.item_id() == ItemId(0))
+            .count();
+        let unpopular = missing.len() - popular;
+        assert!(popular > 400 && popular < 600);
+        assert!(unpopular > 990);
+    }
+
+    #[test]
+    fn truncate_user_histories() {
+        let interactions: Vec<_> = (0..10)
+            .flat_map(|user_id| {
+                (0..user_id).map(move |t| {
+                    Interaction::new(UserId(user_id), ItemId(t), Timestamp(100 - t as i64))
+                        .with_context(t as usize)
+                })
+            })
+            .collect();
+        let interactions = Interactions::from(interactions).to_compressed();
+
+        let truncated = interactions.truncate_user_histories(3);
+        assert_eq!(truncated.shape(), interactions.shape());
+
+        for (user, truncated_user) in interactions.iter_users().zip(truncated.iter_users()) {
+            assert_eq!(truncated_user.user_id, user.user_id);
+            assert_eq!(truncated_user.len(), user.len().min(3));
+
+            // The newest interactions are retained.
+            assert_eq!(
+                truncated_user.timestamps,
+                &user.timestamps[user.len() - truncated_user.len()..]
+            );
+            assert_eq!(
+                truncated_user.item_ids,
+                &user.item_ids[user.len() - truncated_user.len()..]
+            );
+            let item_indices: Vec<_> = truncated_user.item_ids.iter().map(|x| x.index()).collect();
+            assert_eq!(truncated_user.contexts, Some(&item_indices[..]));
+        }
+    }
+
+    #[test]
+    fn compressed_weights() {
+        let unweighted: Vec<_> = (0..10)
+            .map(|t| Interaction::new(UserId(t % 2), ItemId(t), Timestamp(t as i64)))
+            .collect();
+        let compressed = Interactions::from(unweighted.clone()).to_compressed();
+        assert!(compressed.iter_users().all(|user| user.weights.is_none()));
+
+        let weighted: Vec<_> = unweighted
+            .into_iter()
+            .map(|x| {
+                let weight = x.item_id().get() as f32;
+                x.with_weight(weight)
+            })
+            .collect();
+        let compressed = Interactions::from(weighted.clone()).to_compressed();
+
+        for user in compressed.iter_users() {
+            let weights: Vec<_> = user.item_ids.iter().map(|x| x.get() as f32).collect();
+            assert_eq!(user.weights, Some(&weights[..]));
+
+            let recent = user.most_recent(2);
+            assert_eq!(recent.weights, Some(&weights[weights.len() - 2..]));
+        }
+
+        let truncated = compressed.truncate_user_histories(3);
+        assert_eq!(
+            truncated.get_user(UserId(1)).unwrap().weights,
+            Some(&[5.0, 7.0, 9.0][..])
+        );
+
+        let mut round_trip = compressed.to_interactions().data().to_owned();
+        round_trip.sort_by_key(|x| x.item_id());
+        assert_eq!(round_trip, weighted);
+    }
+
+    #[test]
+    fn item_jaccard_top_k() {
+        // Item 0: users {0, 1, 2}; item 1: users {1, 2}; item 2: users {2, 3};
+        // item 3: user {4}, sharing no users with the other items.
+        let interactions = Interactions::from(vec![
+            Interaction::new(UserId(0), ItemId(0), Timestamp(0)),
+            Interaction::new(UserId(1), ItemId(0), Timestamp(0)),
+            Interaction::new(UserId(2), ItemId(0), Timestamp(0)),
+            Interaction::new(UserId(1), ItemId(1), Timestamp(1)),
+            Interaction::new(UserId(2), ItemId(1), Timestamp(1)),
+            Interaction::new(UserId(2), ItemId(1), Timestamp(2)),
+            Interaction::new(UserId(2), ItemId(2), Timestamp(2)),
+            Interaction::new(UserId(3), ItemId(2), Timestamp(2)),
+            Interaction::new(UserId(4), ItemId(3), Timestamp(0)),
+        ]);
+
+        let similarities = interactions.compute_item_jaccard_top_k(2);
+
+        assert_eq!(similarities.len(), 4);
+        assert_eq!(
+            similarities[&ItemId(0)],
+            vec![(ItemId(1), 2.0 / 3.0), (ItemId(2), 1.0 / 4.0)]
+        );
+        assert_eq!(
+            similarities[&ItemId(1)],
+            vec![(ItemId(0), 2.0 / 3.0), (ItemId(2), 1.0 / 3.0)]
+        );
+        assert_eq!(
+            similarities[&ItemId(2)],
+            vec![(ItemId(1), 1.0 / 3.0), (ItemId(0), 1.0 / 4.0)]
+        );
+        assert!(similarities[&ItemId(3)].is_empty());
+
+        assert_eq!(
+            interactions.compute_item_jaccard_top_k(1)[&ItemId(2)],
+            vec![(ItemId(1), 1.0 / 3.0)]
+        );
+    }
+
+    #[test]
+    fn sample_random() {
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+
+        // Only users 1 and 3 and items 2 and 4 have interactions.
+        let mut interactions = Interactions::new(5, 6);
+        interactions.push(Interaction::new(UserId(1), ItemId(2), Timestamp(0)));
+        interactions.push(Interaction::new(UserId(3), ItemId(4), Timestamp(0)));
+        interactions.push(Interaction::new(UserId(3), ItemId(2), Timestamp(1)));
+        let interactions = interactions.to_compressed();
+
+        let mut user_ids = HashSet::new();
+        let mut item_ids = HashSet::new();
+
+        for _ in 0..100 {
+            let user = interactions.sample_random_user(&mut rng).unwrap();
+            assert!(!user.is_empty());
+            user_ids.insert(user.user_id);
+            item_ids.insert(interactions.sample_random_item(&mut rng).unwrap());
+        }
+
+        assert_eq!(user_ids, [UserId(1), UserId(3)].iter().cloned().collect());
+        assert_eq!(item_ids, [ItemId(2), ItemId(4)].iter().cloned().collect());
+
+        let empty = Interactions::new(5, 6).to_compressed();
+        assert!(empty.sample_random_user(&mut rng).is_none());
+        assert!(empty.sample_random_item(&mut rng).is_none());
+    }
+
+    #[test]
+    fn stratified_sample() {
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+
+        // Recent data dominates: 1000 interactions in the last tenth of
+        // the time range, and 5 in the first tenth.
+        let interactions: Vec<_> = (0..1000)
+            .map(|idx| {
+                Interaction::new(
+                    UserId(idx % 10),
+                    ItemId(idx % 7),
+                    Timestamp(900 + (idx % 100) as i64),
+                )
+            })
+            .chain((0..5).map(|idx| {
+                Interaction::new(UserId(idx), ItemId(idx), Timestamp((idx * 10) as i64))
+            }))
+            .collect();
+        let interactions = Interactions::from(interactions);
+
+        let sample = interactions.stratified_sample_by_timestamp(10, 0.1, &mut rng);
+        assert_eq!(sample.shape(), interactions.shape());
+
+        let old = sample
+            .data()
+            .iter()
+            .filter(|x| x.timestamp() < Timestamp(100))
+            .count();
+        let recent = sample
+            .data()
+            .iter()
+            .filter(|x| x.timestamp() >= Timestamp(900))
+            .count();
+        assert_eq!(old, 1);
+        assert_eq!(recent, 100);
+
+        assert_eq!(
+            interactions
+                .stratified_sample_by_timestamp(3, 1.0, &mut rng)
+                .len(),
+            interactions.len()
+        );
+    }
+
+    #[test]
+    fn random_walk() {
+        let interactions = Interactions::from(vec![
+            Interaction::new(UserId(0), ItemId(0), Timestamp(0)),
+            Interaction::new(UserId(0), ItemId(1), Timestamp(1)),
+            Interaction::new(UserId(1), ItemId(1), Timestamp(2)),
+            Interaction::new(UserId(1), ItemId(2), Timestamp(3)),
+        ])
+        .to_compressed();
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+
+        let walk = interactions.random_walk(UserId(0), 20, 0.0, &mut rng);
+
+        assert_eq!(walk.len(), 20);
+        assert_eq!(walk[0], (false, 0));
+
+        for (&(from_item, from), &(to_item, to)) in walk.iter().zip(walk.iter().skip(1)) {
+            assert_ne!(from_item, to_item);
+
+            let (user_idx, item_idx) = if from_item { (to, from) } else { (from, to) };
+            assert!(interactions
+                .get_user(UserId::from_index(user_idx))
+                .unwrap()
+                .item_ids
+                .contains(&ItemId::from_index(item_idx)));
+        }
+
+        // Always returning oscillates between the first two nodes.
+        let walk = interactions.random_walk(UserId(0), 6, 1.0, &mut rng);
+        assert!(walk.iter().step_by(2).all(|&node| node == walk[0]));
+        assert!(walk.iter().skip(1).step_by(2).all(|&node| node == walk[1]));
+
+        assert!(interactions
+            .random_walk(UserId(5), 10, 0.5, &mut rng)
+            .is_empty());
+    }
+
+    #[test]
+    fn item_transitions() {
+        let interactions =
+            Interactions::from_sessions(vec![items(&[0, 1, 2]), items(&[0, 1, 0])], 3)
+                .to_compressed();
+        let probability = |a, b| interactions.item_transition_probability(ItemId(a), ItemId(b));
+
+        let transitions = interactions.item_transition_matrix();
+        assert_eq!(transitions.len(), 3);
+        assert_eq!(transitions[&(ItemId(0), ItemId(1))], 2);
+        assert_eq!(transitions[&(ItemId(1), ItemId(2))], 1);
+        assert_eq!(transitions[&(ItemId(1), ItemId(0))], 1);
+
+        assert_eq!(probability(0, 1), 1.0);
+        assert_eq!(probability(1, 2), 0.5);
+        assert_eq!(probability(1, 1), 0.0);
+
+        // Item 2 only ever occurs last.
+        assert_eq!(probability(2, 0), 0.0);
+    }
+
     #[test]
     fn test_chunk_iterator() {
         let num_users = 1;
@@ -635,7 +2134,11 @@ mod tests {
 
         for user in 0..num_users {
             for item in 0..num_items {
-                interactions.push(Interaction::new(user, item, item));
+                interactions.push(Interaction::new(
+                    UserId(user),
+                    ItemId(item),
+                    Timestamp(item as i64),
+                ));
             }
         }
 
@@ -649,18 +2152,379 @@ mod tests {
         assert_eq!(chunks.len(), 2);
 
         let expected = [
-            (vec![0, 1_usize], vec![0, 1_usize]),
-            (vec![2_usize, 3, 4], vec![2_usize, 3, 4]),
+            (items(&[0, 1]), vec![Timestamp(0), Timestamp(1)]),
+            (
+                items(&[2, 3, 4]),
+                vec![Timestamp(2), Timestamp(3), Timestamp(4)],
+            ),
         ];
 
         chunks.iter().zip(expected.iter()).for_each(|(x, y)| {
             assert_eq!(&x.0, &y.0.as_slice());
-            assert_eq!(&x.0, &y.1.as_slice());
+            assert_eq!(&x.1, &y.1.as_slice());
         });
 
         //assert!(chunks == []);
     }
 
+    #[test]
+    fn deduplicate() {
+        let interactions = vec![
+            Interaction::new(UserId(0), ItemId(1), Timestamp(5)),
+            Interaction::new(UserId(0), ItemId(1), Timestamp(2)),
+            Interaction::new(UserId(0), ItemId(2), Timestamp(3)),
+            Interaction::new(UserId(1), ItemId(1), Timestamp(7)),
+            Interaction::new(UserId(0), ItemId(1), Timestamp(9)),
+            Interaction::new(UserId(1), ItemId(1), Timestamp(7)),
+        ];
+
+        let mut first = Interactions::from(interactions.clone());
+        let mut last = Interactions::from(interactions);
+        let num_duplicates = first.count_duplicates();
+
+        assert_eq!(num_duplicates, 3);
+
+        first.deduplicate_keep_first();
+        last.deduplicate_keep_last();
+
+        assert_eq!(first.len(), 6 - num_duplicates);
+        assert_eq!(last.len(), 6 - num_duplicates);
+
+        assert_eq!(
+            first.data(),
+            &[
+                Interaction::new(UserId(0), ItemId(1), Timestamp(2)),
+                Interaction::new(UserId(0), ItemId(2), Timestamp(3)),
+                Interaction::new(UserId(1), ItemId(1), Timestamp(7)),
+            ]
+        );
+        assert_eq!(
+            last.data(),
+            &[
+                Interaction::new(UserId(0), ItemId(1), Timestamp(9)),
+                Interaction::new(UserId(0), ItemId(2), Timestamp(3)),
+                Interaction::new(UserId(1), ItemId(1), Timestamp(7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn deduplicate_with_merge() {
+        let mut interactions = Interactions::from(vec![
+            Interaction::new(UserId(0), ItemId(1), Timestamp(5)),
+            Interaction::new(UserId(0), ItemId(2), Timestamp(3)),
+            Interaction::new(UserId(0), ItemId(1), Timestamp(2)).with_weight(2.0),
+            Interaction::new(UserId(0), ItemId(1), Timestamp(9)),
+        ]);
+
+        // Keep the earliest timestamp and the total weight.
+        interactions.deduplicate_with(|kept, candidate| {
+            Interaction::new(
+                kept.user_id(),
+                kept.item_id(),
+                kept.timestamp().min(candidate.timestamp()),
+            )
+            .with_weight(kept.weight() + candidate.weight())
+        });
+
+        assert_eq!(
+            interactions.data(),
+            &[
+                Interaction::new(UserId(0), ItemId(1), Timestamp(2)).with_weight(4.0),
+                Interaction::new(UserId(0), ItemId(2), Timestamp(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn movielens_dat() {
+        let path = std::env::temp_dir().join("sbr_movielens_ratings.dat");
+
+        for &delimiter in &["::", "\t"] {
+            let contents = ["1", "2", "3", "4"]
+                .iter()
+                .map(|user| {
+                    vec![
+                        *user,
+                        "10",
+                        if *user == "2" { "3" } else { "5" },
+                        "978300760",
+                    ]
+                    .join(delimiter)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            fs::write(&path, contents).unwrap();
+
+            let interactions = Interactions::from_movielens_dat(&path, delimiter).unwrap();
+
+            assert_eq!(interactions.len(), 3);
+            assert_eq!(interactions.shape(), (5, 11));
+            assert!(interactions.data().iter().all(|x| x.user_id() != UserId(2)));
+            assert_eq!(interactions.data()[0].timestamp(), Timestamp(978_300_760));
+        }
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn negative_timestamps() {
+        // Interactions before 1970, alongside unsigned timestamps as
+        // written by earlier versions.
+        let csv = "user_id,item_id,timestamp\n0,1,-5\n0,2,-86400\n0,3,7\n1,2,0\n";
+        let interactions: Vec<Interaction> = csv::Reader::from_reader(csv.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let interactions = Interactions::from(interactions);
+
+        let compressed = interactions.to_compressed();
+        let user = compressed.get_user(UserId(0)).unwrap();
+        assert_eq!(user.item_ids, &items(&[2, 1, 3])[..]);
+        assert_eq!(user.timestamps, &times(&[-86_400, -5, 7])[..]);
+        assert_eq!(
+            compressed.to_interactions().data()[0].timestamp(),
+            Timestamp(-86_400)
+        );
+
+        let (before, after) = interactions.split_by(|x| x.timestamp() < Timestamp(0));
+        assert_eq!((before.len(), after.len()), (2, 2));
+
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+        let sample = interactions.stratified_sample_by_timestamp(2, 1.0, &mut rng);
+        assert_eq!(sample.len(), interactions.len());
+    }
+
+    #[test]
+    fn movielens_dat_errors() {
+        use std::error::Error;
+
+        let path = std::env::temp_dir().join("sbr_movielens_errors.dat");
+
+        fs::write(&path, "1::10::5").unwrap();
+        match Interactions::from_movielens_dat(&path, "::") {
+            Err(err @ DatasetError::Malformed(_)) => {
+                assert_eq!(err.to_string(), "Malformed Movielens line: 1::10::5");
+                assert!(err.source().is_none());
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        fs::write(&path, "x::10::5::0").unwrap();
+        match Interactions::from_movielens_dat(&path, "::") {
+            Err(err @ DatasetError::ParseInt(_)) => assert!(err.source().is_some()),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        fs::remove_file(&path).unwrap();
+        match Interactions::from_movielens_dat(&path, "::") {
+            Err(DatasetError::Io(_)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn from_sessions() {
+        let sessions = vec![items(&[3, 1, 3]), vec![], items(&[0, 2])];
+        let interactions = Interactions::from_sessions(sessions, 5);
+
+        assert_eq!(interactions.shape(), (3, 5));
+        assert_eq!(interactions.len(), 5);
+
+        let compressed = interactions.to_compressed();
+        let user = compressed.get_user(UserId(0)).unwrap();
+        assert_eq!(user.item_ids, &items(&[3, 1, 3])[..]);
+        assert_eq!(user.timestamps, &[Timestamp(0), Timestamp(1), Timestamp(2)]);
+        assert!(compressed.get_user(UserId(1)).unwrap().is_empty());
+        assert_eq!(
+            compressed.get_user(UserId(2)).unwrap().item_ids,
+            &items(&[0, 2])[..]
+        );
+    }
+
+    #[test]
+    fn resample_to_target_density() {
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+        let interactions = Interactions::from(
+            (0..1000)
+                .map(|t| Interaction::new(UserId(t % 50), ItemId(t % 40), Timestamp(t as i64)))
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(interactions.shape(), (50, 40));
+        assert!((interactions.density() - 0.5).abs() < 1e-12);
+
+        let resampled = interactions
+            .resample_to_target_density(0.1, &mut rng)
+            .unwrap();
+        assert_eq!(resampled.shape(), (50, 40));
+        assert_eq!(resampled.len(), 200);
+        assert!((resampled.density() - 0.1).abs() < 1e-12);
+
+        // Sampled without replacement, in the original order.
+        let timestamps: Vec<_> = resampled.data().iter().map(|x| x.timestamp()).collect();
+        assert!(timestamps.windows(2).all(|x| x[0] < x[1]));
+
+        match interactions.resample_to_target_density(0.6, &mut rng) {
+            Err(DataError::InvalidTargetDensity { target, current }) => {
+                assert!((target - 0.6).abs() < 1e-12);
+                assert!((current - 0.5).abs() < 1e-12);
+            }
+            _ => panic!("Expected an invalid target density error."),
+        }
+    }
+
+    #[test]
+    fn entities_without_interactions() {
+        let mut interactions = Interactions::new(5, 6);
+        interactions.push(Interaction::new(UserId(1), ItemId(2), Timestamp(0)));
+        interactions.push(Interaction::new(UserId(3), ItemId(0), Timestamp(1)));
+        interactions.push(Interaction::new(UserId(3), ItemId(2), Timestamp(2)));
+
+        let compressed = interactions.to_compressed();
+        assert_eq!(
+            compressed.users_without_interactions(),
+            vec![UserId(0), UserId(2), UserId(4)]
+        );
+        assert_eq!(
+            compressed.items_without_interactions(),
+            items(&[1, 3, 4, 5])
+        );
+
+        let empty = Interactions::new(2, 2).to_compressed();
+        assert_eq!(
+            empty.users_without_interactions(),
+            vec![UserId(0), UserId(1)]
+        );
+        assert_eq!(empty.items_without_interactions(), items(&[0, 1]));
+    }
+
+    #[test]
+    fn adjacency_lists() {
+        let mut interactions = Interactions::new(3, 4);
+        interactions.push(Interaction::new(UserId(2), ItemId(3), Timestamp(0)));
+        interactions.push(Interaction::new(UserId(0), ItemId(1), Timestamp(1)));
+        interactions.push(Interaction::new(UserId(2), ItemId(1), Timestamp(2)));
+        interactions.push(Interaction::new(UserId(2), ItemId(3), Timestamp(3)));
+
+        assert_eq!(
+            interactions.to_user_adjacency_list(),
+            vec![items(&[1]), vec![], items(&[3, 1, 3])]
+        );
+        assert_eq!(
+            interactions.to_item_adjacency_list(),
+            vec![
+                vec![],
+                vec![UserId(0), UserId(2)],
+                vec![],
+                vec![UserId(2), UserId(2)]
+            ]
+        );
+    }
+
+    #[test]
+    fn id_mappers() {
+        let mut users = UserIdMapper::new();
+        let mut items = ItemIdMapper::new();
+        let interactions = Interactions::from(
+            [("alice", "x"), ("bob", "y"), ("alice", "y")]
+                .iter()
+                .map(|&(user, item)| {
+                    Interaction::new(
+                        users.get_or_insert(user),
+                        items.get_or_insert(item),
+                        Timestamp(0),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        assert_eq!((users.num_users(), items.num_items()), (2, 2));
+        assert_eq!(interactions.shape(), (2, 2));
+        assert_eq!(users.lookup_id("bob"), Some(UserId(1)));
+        assert_eq!(users.lookup_id("carol"), None);
+        assert_eq!(users.lookup_str(UserId(0)), Some("alice"));
+        assert_eq!(items.lookup_str(ItemId(1)), Some("y"));
+        assert_eq!(items.lookup_str(ItemId(2)), None);
+
+        let serialized = serde_json::to_string(&users).unwrap();
+        let deserialized: UserIdMapper = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.lookup_id("alice"), Some(UserId(0)));
+        assert_eq!(deserialized.lookup_str(UserId(1)), Some("bob"));
+    }
+
+    #[test]
+    fn hard_negatives() {
+        use crate::models::baselines::Popularity;
+
+        // Item popularity increases with the item id.
+        let mut interactions = Interactions::new(40, 30);
+        for user_id in 0..40 {
+            for item_id in 0..30 {
+                if user_id % 30 < item_id {
+                    interactions.push(Interaction::new(
+                        UserId(user_id),
+                        ItemId(item_id),
+                        Timestamp(item_id as i64),
+                    ));
+                }
+            }
+        }
+        // User 0 interacted with every item but the first; now with all.
+        interactions.push(Interaction::new(UserId(0), ItemId(0), Timestamp(0)));
+        let data = interactions.to_compressed();
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+
+        let mut model = Popularity::new(data.num_items());
+        model.fit(&data).unwrap();
+
+        let random = sample_hard_negatives(&data, &model, 1, &mut rng).unwrap();
+        let hard = sample_hard_negatives(&data, &model, 5, &mut rng).unwrap();
+
+        // User 0 is skipped.
+        assert_eq!(hard.len(), data.to_interactions().len() - 30);
+        assert!(hard.user_ids().iter().all(|&user_id| user_id != UserId(0)));
+        assert_eq!(hard.negative_item_ids().len(), hard.len());
+        assert_eq!((hard.num_users(), hard.num_items()), (40, 30));
+
+        for (&user_id, &negative) in hard.user_ids().iter().zip(hard.negative_item_ids()) {
+            let history = data.get_user(user_id).unwrap().item_ids;
+            assert!(!history.contains(&negative));
+        }
+
+        let mean = |triplets: &TripletWithNegatives| {
+            let sum: u64 = triplets.negative_item_ids().iter().map(|x| x.get()).sum();
+            sum as f32 / triplets.len() as f32
+        };
+        assert!(mean(&hard) > mean(&random));
+    }
+
+    #[test]
+    fn from_rating_matrix() {
+        let matrix = Array2::from_shape_vec(
+            (3, 4),
+            vec![
+                5.0, 0.0, 3.0, 4.0, //
+                0.0, 0.0, 0.0, 0.0, //
+                1.0, 4.5, 0.0, 2.0,
+            ],
+        )
+        .unwrap();
+
+        let interactions = Interactions::from_rating_matrix(&matrix, 4.0);
+        assert_eq!((interactions.num_users(), interactions.num_items()), (3, 4));
+
+        let pairs: Vec<_> = interactions
+            .data()
+            .iter()
+            .map(|x| (x.user_id().get(), x.item_id().get(), x.timestamp().get()))
+            .collect();
+        assert_eq!(pairs, vec![(0, 0, 0), (0, 3, 0), (2, 1, 0)]);
+
+        // Zero cells are never interactions, even below a negative threshold.
+        let all = Interactions::from_rating_matrix(&matrix, -1.0);
+        assert_eq!(all.len(), 6);
+    }
+
     // #[test]
     // fn foo_bar() {
     //     let mut interactions = Vec::new();
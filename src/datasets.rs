@@ -1,32 +1,162 @@
 //! Built-in datasets for easy testing and experimentation.
+//!
+//! Downloading the datasets requires the `datasets` feature. [DatasetError]
+//! is always available, as it is also returned when reading interactions
+//! from files.
+use std::error;
+use std::fmt;
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+
+#[cfg(feature = "datasets")]
 use csv;
-use failure;
+#[cfg(feature = "datasets")]
 use reqwest;
+use serde_json;
+#[cfg(feature = "datasets")]
+use zip;
+
+#[cfg(feature = "datasets")]
+mod download;
 
-use crate::data::{Interaction, Interactions};
+#[cfg(feature = "datasets")]
+pub use self::download::{
+    book_crossing_item_metadata, download_book_crossing, download_movielens_100k,
+    download_steam_games, download_yelp, yelp_stars_filtered,
+};
 
 /// Dataset error types.
-#[derive(Debug, Fail)]
+#[derive(Debug)]
 pub enum DatasetError {
     /// Can't find the home directory.
-    #[fail(display = "Cannot find home directory.")]
     NoHomeDir,
+    /// The dataset cannot be downloaded automatically.
+    ManualDownload {
+        /// Where to download the dataset from.
+        url: String,
+        /// Where the dataset is expected.
+        path: String,
+    },
+    /// The data does not have the expected format.
+    Malformed(String),
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// Downloading the dataset failed.
+    #[cfg(feature = "datasets")]
+    Http(reqwest::Error),
+    /// Parsing a CSV file failed.
+    #[cfg(feature = "datasets")]
+    Csv(csv::Error),
+    /// Parsing a JSON record failed.
+    Json(serde_json::Error),
+    /// Reading a zip archive failed.
+    #[cfg(feature = "datasets")]
+    Zip(zip::result::ZipError),
+    /// Parsing an integer field failed.
+    ParseInt(ParseIntError),
+    /// Parsing a floating point field failed.
+    ParseFloat(ParseFloatError),
+    /// Reading or writing Arrow data failed.
+    #[cfg(feature = "arrow")]
+    Arrow(arrow::error::ArrowError),
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatasetError::NoHomeDir => write!(f, "Cannot find home directory."),
+            DatasetError::ManualDownload { url, path } => write!(
+                f,
+                "Download the dataset from {} and place it at {}.",
+                url, path
+            ),
+            DatasetError::Malformed(message) => write!(f, "{}", message),
+            DatasetError::Io(err) => err.fmt(f),
+            #[cfg(feature = "datasets")]
+            DatasetError::Http(err) => err.fmt(f),
+            #[cfg(feature = "datasets")]
+            DatasetError::Csv(err) => err.fmt(f),
+            DatasetError::Json(err) => err.fmt(f),
+            #[cfg(feature = "datasets")]
+            DatasetError::Zip(err) => err.fmt(f),
+            DatasetError::ParseInt(err) => err.fmt(f),
+            DatasetError::ParseFloat(err) => err.fmt(f),
+            #[cfg(feature = "arrow")]
+            DatasetError::Arrow(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for DatasetError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            DatasetError::NoHomeDir
+            | DatasetError::ManualDownload { .. }
+            | DatasetError::Malformed(_) => None,
+            DatasetError::Io(err) => Some(err),
+            #[cfg(feature = "datasets")]
+            DatasetError::Http(err) => Some(err),
+            #[cfg(feature = "datasets")]
+            DatasetError::Csv(err) => Some(err),
+            DatasetError::Json(err) => Some(err),
+            #[cfg(feature = "datasets")]
+            DatasetError::Zip(err) => Some(err),
+            DatasetError::ParseInt(err) => Some(err),
+            DatasetError::ParseFloat(err) => Some(err),
+            #[cfg(feature = "arrow")]
+            DatasetError::Arrow(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for DatasetError {
+    fn from(err: io::Error) -> Self {
+        DatasetError::Io(err)
+    }
 }
 
-async fn download(url: &str) -> Result<Interactions, failure::Error> {
-    let str = reqwest::get(url).await?.text().await?;
+#[cfg(feature = "datasets")]
+impl From<reqwest::Error> for DatasetError {
+    fn from(err: reqwest::Error) -> Self {
+        DatasetError::Http(err)
+    }
+}
+
+#[cfg(feature = "datasets")]
+impl From<csv::Error> for DatasetError {
+    fn from(err: csv::Error) -> Self {
+        DatasetError::Csv(err)
+    }
+}
+
+impl From<serde_json::Error> for DatasetError {
+    fn from(err: serde_json::Error) -> Self {
+        DatasetError::Json(err)
+    }
+}
+
+#[cfg(feature = "datasets")]
+impl From<zip::result::ZipError> for DatasetError {
+    fn from(err: zip::result::ZipError) -> Self {
+        DatasetError::Zip(err)
+    }
+}
 
-    let mut reader = csv::Reader::from_reader(str.as_bytes());
-    let interactions: Vec<Interaction> = reader.deserialize().collect::<Result<Vec<_>, _>>()?;
+impl From<ParseIntError> for DatasetError {
+    fn from(err: ParseIntError) -> Self {
+        DatasetError::ParseInt(err)
+    }
+}
 
-    Ok(Interactions::from(interactions))
+impl From<ParseFloatError> for DatasetError {
+    fn from(err: ParseFloatError) -> Self {
+        DatasetError::ParseFloat(err)
+    }
 }
 
-/// Download the Movielens 100K dataset and return it.
-///
-/// The data is stored in `~/.sbr-rs/`.
-pub async fn download_movielens_100k() -> Result<Interactions, failure::Error> {
-    Ok(download(
-        "https://github.com/maciejkula/sbr-rs/raw/master/data.csv"
-    ).await?)
+#[cfg(feature = "arrow")]
+impl From<arrow::error::ArrowError> for DatasetError {
+    fn from(err: arrow::error::ArrowError) -> Self {
+        DatasetError::Arrow(err)
+    }
 }
@@ -0,0 +1,404 @@
+//! Downloading and parsing of the built-in datasets.
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use csv;
+use reqwest;
+use serde::Deserialize;
+use serde_json;
+use zip;
+
+use super::DatasetError;
+use crate::data::{Interaction, Interactions};
+use crate::ids::{ItemId, Timestamp, UserId};
+
+async fn download(url: &str) -> Result<Interactions, DatasetError> {
+    log_info!("Downloading {}", url);
+    let str = reqwest::get(url).await?.text().await?;
+    log_info!("Downloaded {} bytes from {}", str.len(), url);
+
+    let mut reader = csv::Reader::from_reader(str.as_bytes());
+    let interactions: Vec<Interaction> = reader.deserialize().collect::<Result<Vec<_>, _>>()?;
+    log_info!("Parsed {} interactions", interactions.len());
+
+    Ok(Interactions::from(interactions))
+}
+
+fn cache_path(dataset: &str, filename: &str) -> Result<PathBuf, DatasetError> {
+    let home = env::var_os("HOME").ok_or(DatasetError::NoHomeDir)?;
+
+    Ok(PathBuf::from(home)
+        .join(".sbr-rs")
+        .join(dataset)
+        .join(filename))
+}
+
+/// Download `url` into `~/.sbr-rs/{dataset}/{filename}` unless it is
+/// already there, and return the path of the cached file.
+///
+/// The body is written to a temporary file next to the cached one and only
+/// renamed into place once complete, so that failed or interrupted downloads
+/// are never mistaken for cached data.
+async fn download_cached(
+    url: &str,
+    dataset: &str,
+    filename: &str,
+) -> Result<PathBuf, DatasetError> {
+    let path = cache_path(dataset, filename)?;
+
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        log_info!("Downloading {} to {}", url, path.display());
+        let bytes = reqwest::get(url).await?.error_for_status()?.bytes().await?;
+
+        let partial_path = path.with_file_name(format!("{}.part", filename));
+        fs::write(&partial_path, &bytes)?;
+        fs::rename(&partial_path, &path)?;
+        log_info!("Downloaded {} bytes to {}", bytes.len(), path.display());
+    } else {
+        log_info!("Using cached {}", path.display());
+    }
+
+    Ok(path)
+}
+
+fn get_or_insert_id(ids: &mut HashMap<String, usize>, key: &str) -> usize {
+    let next_id = ids.len();
+    *ids.entry(key.to_owned()).or_insert(next_id)
+}
+
+/// Download the Movielens 100K dataset and return it.
+///
+/// The data is stored in `~/.sbr-rs/`.
+pub async fn download_movielens_100k() -> Result<Interactions, DatasetError> {
+    Ok(download(
+        "https://github.com/maciejkula/sbr-rs/raw/master/data.csv"
+    ).await?)
+}
+
+const STEAM_URL: &str = "https://www.kaggle.com/tamber/steam-video-games";
+const STEAM_GAMES: &str = "steam-200k.csv";
+
+/// Load the Steam-200K dataset of game purchases and playtime, and return it.
+///
+/// Only `purchase` events become interactions; each is weighted by
+/// one plus the hours the user spent playing the game. User and game
+/// names are mapped to consecutive integer ids in order of appearance, and
+/// the row index is used as the timestamp.
+///
+/// The dataset is hosted on Kaggle, which only serves it to signed-in
+/// users, so it is not fetched automatically: `steam-200k.csv`, from the
+/// archive at <https://www.kaggle.com/tamber/steam-video-games>, must be
+/// placed in `~/.sbr-rs/steam/`.
+pub async fn download_steam_games() -> Result<Interactions, DatasetError> {
+    let path = cache_path("steam", STEAM_GAMES)?;
+
+    if !path.exists() {
+        return Err(DatasetError::ManualDownload {
+            url: STEAM_URL.to_owned(),
+            path: path.display().to_string(),
+        });
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_path(path)?;
+    let records: Vec<(String, String, String, f32, f32)> =
+        reader.deserialize().collect::<Result<Vec<_>, _>>()?;
+
+    let mut user_ids = HashMap::new();
+    let mut item_ids = HashMap::new();
+    let mut hours_played = HashMap::new();
+
+    for (user, game, behaviour, hours, _) in &records {
+        let user_id = get_or_insert_id(&mut user_ids, user);
+        let item_id = get_or_insert_id(&mut item_ids, game);
+
+        if behaviour == "play" {
+            hours_played.insert((user_id, item_id), *hours);
+        }
+    }
+
+    let interactions: Vec<_> = records
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, _, behaviour, _, _))| behaviour == "purchase")
+        .map(|(timestamp, (user, game, _, _, _))| {
+            let user_id = user_ids[user];
+            let item_id = item_ids[game];
+            let hours = hours_played.get(&(user_id, item_id)).unwrap_or(&0.0);
+
+            Interaction::new(
+                UserId::from_index(user_id),
+                ItemId::from_index(item_id),
+                Timestamp(timestamp as i64),
+            )
+            .with_weight(1.0 + hours)
+        })
+        .collect();
+
+    Ok(Interactions::from(interactions))
+}
+
+const BOOK_CROSSING_URL: &str =
+    "https://files.grouplens.org/datasets/book-crossing/BX-CSV-Dump.zip";
+const BOOK_CROSSING_ARCHIVE: &str = "BX-CSV-Dump.zip";
+/// The ISBNs of the rated books, one per line in item id order.
+const BOOK_CROSSING_ISBNS: &str = "isbns.txt";
+
+/// Read the records of the semicolon-delimited `filename` in the zip
+/// archive at `path`, skipping and counting malformed rows.
+///
+/// The Book-Crossing files are Latin-1 encoded, so records are returned as
+/// bytes rather than strings.
+fn read_zipped_csv(path: &Path, filename: &str) -> Result<Vec<csv::ByteRecord>, DatasetError> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    let mut contents = Vec::new();
+    archive.by_name(filename)?.read_to_end(&mut contents)?;
+
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b';')
+        .flexible(true)
+        .from_reader(contents.as_slice());
+
+    let mut records = Vec::new();
+    let mut num_malformed = 0;
+
+    for record in reader.byte_records() {
+        match record {
+            Ok(record) => records.push(record),
+            Err(_) => num_malformed += 1,
+        }
+    }
+
+    if num_malformed > 0 {
+        log_warn!("Skipped {} malformed rows of {}", num_malformed, filename);
+    }
+
+    Ok(records)
+}
+
+fn field(record: &csv::ByteRecord, idx: usize) -> Option<String> {
+    record
+        .get(idx)
+        .map(|field| String::from_utf8_lossy(field).trim().to_owned())
+}
+
+/// Parse the explicit Book-Crossing ratings, mapping users and ISBNs to
+/// consecutive integer ids in order of appearance. Returns the
+/// interactions and the ISBN of each item id.
+fn book_crossing_ratings(path: &Path) -> Result<(Vec<Interaction>, Vec<String>), DatasetError> {
+    let mut user_ids = HashMap::new();
+    let mut item_ids = HashMap::new();
+    let mut isbns = Vec::new();
+    let mut interactions = Vec::new();
+    let mut num_malformed = 0;
+
+    for record in read_zipped_csv(path, "BX-Book-Ratings.csv")? {
+        let fields = (field(&record, 0), field(&record, 1), field(&record, 2));
+        let (user, isbn, rating) = match fields {
+            (Some(user), Some(isbn), Some(rating)) => (user, isbn, rating),
+            _ => {
+                num_malformed += 1;
+                continue;
+            }
+        };
+
+        // A rating of 0 marks an implicit interaction.
+        match rating.parse::<u8>() {
+            Ok(rating) if rating >= 1 => {}
+            Ok(_) => continue,
+            Err(_) => {
+                num_malformed += 1;
+                continue;
+            }
+        }
+
+        let user_id = get_or_insert_id(&mut user_ids, &user);
+        let item_id = get_or_insert_id(&mut item_ids, &isbn);
+        if item_id == isbns.len() {
+            isbns.push(isbn);
+        }
+        let timestamp = Timestamp(interactions.len() as i64);
+
+        interactions.push(Interaction::new(
+            UserId::from_index(user_id),
+            ItemId::from_index(item_id),
+            timestamp,
+        ));
+    }
+
+    if num_malformed > 0 {
+        log_warn!("Skipped {} incomplete Book-Crossing ratings", num_malformed);
+    }
+
+    Ok((interactions, isbns))
+}
+
+/// Download the Book-Crossing dataset of book ratings, and return it.
+///
+/// Only explicit ratings (1 to 10) are kept, and all of them are treated
+/// as positive interactions. Users and ISBNs are mapped to consecutive
+/// integer ids in order of appearance, and the row index is used as the
+/// timestamp. Malformed rows are skipped, with a warning if the `logging`
+/// feature is enabled.
+///
+/// The data is stored in `~/.sbr-rs/book_crossing/`; placing
+/// `BX-CSV-Dump.zip` there manually skips the download.
+pub async fn download_book_crossing() -> Result<Interactions, DatasetError> {
+    let path = download_cached(BOOK_CROSSING_URL, "book_crossing", BOOK_CROSSING_ARCHIVE).await?;
+    let (interactions, isbns) = book_crossing_ratings(&path)?;
+
+    // Saved for `book_crossing_item_metadata`, so that it does not have
+    // to parse the ratings again.
+    let mut contents = isbns.join("\n");
+    contents.push('\n');
+    fs::write(cache_path("book_crossing", BOOK_CROSSING_ISBNS)?, contents)?;
+
+    Ok(Interactions::from(interactions))
+}
+
+/// Return the titles of the Book-Crossing books, keyed by the item ids
+/// used by [download_book_crossing]. Books without a title in the
+/// dataset are missing from the map.
+///
+/// Reads the cached data, so [download_book_crossing] must have been
+/// called before.
+pub fn book_crossing_item_metadata() -> Result<HashMap<ItemId, String>, DatasetError> {
+    let isbns = fs::read_to_string(cache_path("book_crossing", BOOK_CROSSING_ISBNS)?)?;
+    let item_ids: HashMap<&str, ItemId> = isbns
+        .lines()
+        .enumerate()
+        .map(|(item_id, isbn)| (isbn, ItemId::from_index(item_id)))
+        .collect();
+
+    let path = cache_path("book_crossing", BOOK_CROSSING_ARCHIVE)?;
+
+    Ok(read_zipped_csv(&path, "BX-Books.csv")?
+        .iter()
+        .filter_map(|record| {
+            let item_id = item_ids.get(field(record, 0)?.as_str())?;
+            Some((*item_id, field(record, 1)?))
+        })
+        .collect())
+}
+
+const YELP_URL: &str = "https://www.yelp.com/dataset";
+const YELP_REVIEWS: &str = "yelp_academic_dataset_review.json";
+
+/// A review in the Yelp `review.json` file; other fields are ignored.
+#[derive(Deserialize)]
+struct YelpReview {
+    user_id: String,
+    business_id: String,
+    stars: f32,
+    date: String,
+}
+
+/// Parse a `YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS` date into seconds since
+/// the Unix epoch, negative before 1970.
+fn parse_date(date: &str) -> Option<Timestamp> {
+    let mut parts = date.trim().splitn(2, ' ');
+    let mut ymd = parts.next()?.split('-').map(|x| x.parse::<i64>().ok());
+    let (year, month, day) = (ymd.next()??, ymd.next()??, ymd.next()??);
+
+    let seconds = match parts.next() {
+        Some(time) => {
+            let mut hms = time.split(':').map(|x| x.parse::<i64>().ok());
+            let (hours, minutes, seconds) = (hms.next()??, hms.next()??, hms.next()??);
+            hours * 3600 + minutes * 60 + seconds
+        }
+        None => 0,
+    };
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar, counting
+    // years from March so that leap days come last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Some(Timestamp(days * 86_400 + seconds))
+}
+
+/// Parse the cached Yelp reviews with at least `min_stars` stars, mapping
+/// users and businesses to consecutive integer ids in order of appearance.
+fn yelp_reviews(min_stars: f32) -> Result<Interactions, DatasetError> {
+    let path = cache_path("yelp", YELP_REVIEWS)?;
+
+    if !path.exists() {
+        return Err(DatasetError::ManualDownload {
+            url: YELP_URL.to_owned(),
+            path: path.display().to_string(),
+        });
+    }
+
+    let mut user_ids = HashMap::new();
+    let mut item_ids = HashMap::new();
+    let mut interactions = Vec::new();
+
+    log_info!("Reading Yelp reviews from {}", path.display());
+
+    // The file holds one JSON object per line.
+    for line in BufReader::new(fs::File::open(path)?).lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let review: YelpReview = serde_json::from_str(&line)?;
+
+        if review.stars < min_stars {
+            continue;
+        }
+
+        let timestamp = parse_date(&review.date).ok_or_else(|| {
+            DatasetError::Malformed(format!("Malformed Yelp review date: {}", review.date))
+        })?;
+        let user_id = get_or_insert_id(&mut user_ids, &review.user_id);
+        let item_id = get_or_insert_id(&mut item_ids, &review.business_id);
+
+        interactions.push(Interaction::new(
+            UserId::from_index(user_id),
+            ItemId::from_index(item_id),
+            timestamp,
+        ));
+    }
+
+    log_info!("Read {} Yelp reviews", interactions.len());
+
+    Ok(Interactions::from(interactions))
+}
+
+/// Load the Yelp dataset of business reviews, and return it.
+///
+/// Every review is treated as a positive interaction, timestamped with its
+/// date in seconds since the Unix epoch. Users and businesses are mapped to
+/// consecutive integer ids in order of appearance.
+///
+/// The dataset can only be downloaded after accepting Yelp's terms of use,
+/// so it is not fetched automatically: `yelp_academic_dataset_review.json`,
+/// from the archive at <https://www.yelp.com/dataset>, must be placed in
+/// `~/.sbr-rs/yelp/`.
+pub async fn download_yelp() -> Result<Interactions, DatasetError> {
+    yelp_reviews(0.0)
+}
+
+/// Like [download_yelp], but only keeps reviews with at least `min_stars`
+/// stars.
+pub fn yelp_stars_filtered(min_stars: u8) -> Result<Interactions, DatasetError> {
+    yelp_reviews(f32::from(min_stars))
+}
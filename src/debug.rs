@@ -0,0 +1,147 @@
+//! Utilities for inspecting interaction data when debugging models.
+use std::path::Path;
+
+use csv;
+
+use crate::data::CompressedInteractionsUser;
+use crate::datasets::DatasetError;
+use crate::ids::ItemId;
+
+/// Maps item ids to human-readable names.
+#[derive(Clone, Debug)]
+pub struct ItemNameMap {
+    names: Vec<String>,
+}
+
+impl ItemNameMap {
+    /// Read item names from the CSV file at `path`, taking item ids
+    /// from the `id_col` column and names from the `name_col` column.
+    pub fn from_csv<P: AsRef<Path>>(
+        path: P,
+        id_col: &str,
+        name_col: &str,
+    ) -> Result<Self, DatasetError> {
+        let mut reader = csv::Reader::from_path(path)?;
+
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| DatasetError::Malformed(format!("Column {} not found.", name)))
+        };
+        let id_idx = column(id_col)?;
+        let name_idx = column(name_col)?;
+
+        let mut names = Vec::new();
+
+        for record in reader.records() {
+            let record = record?;
+
+            let item_id: ItemId = record
+                .get(id_idx)
+                .ok_or_else(|| {
+                    DatasetError::Malformed(format!("Missing item id in row {:?}.", record))
+                })?
+                .trim()
+                .parse()?;
+            let name = record.get(name_idx).ok_or_else(|| {
+                DatasetError::Malformed(format!("Missing item name in row {:?}.", record))
+            })?;
+
+            if item_id.index() >= names.len() {
+                names.resize(item_id.index() + 1, String::new());
+            }
+
+            names[item_id.index()] = name.to_owned();
+        }
+
+        Ok(ItemNameMap { names })
+    }
+
+    /// Return the name of `item_id`, if known.
+    pub fn name(&self, item_id: ItemId) -> Option<&str> {
+        self.names
+            .get(item_id.index())
+            .map(|name| name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Render a user's interactions as a string such as
+    /// `"[0s] StarWars -> [3d] TheMatrix -> [10d] Inception"`, where the
+    /// bracketed prefix is the time elapsed since the previous interaction,
+    /// treating timestamps as seconds.
+    ///
+    /// Items without a name are shown by their id.
+    pub fn sequence_to_string(&self, user: &CompressedInteractionsUser) -> String {
+        let mut previous_timestamp = user.timestamps.first().cloned().unwrap_or_default();
+
+        izip!(user.item_ids, user.timestamps)
+            .map(|(&item_id, &timestamp)| {
+                let delta = timestamp.saturating_sub(previous_timestamp);
+                previous_timestamp = timestamp;
+
+                let name = self
+                    .name(item_id)
+                    .map(|name| name.to_owned())
+                    .unwrap_or_else(|| item_id.to_string());
+
+                format!("[{}] {}", format_duration(delta), name)
+            })
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+}
+
+fn format_duration(seconds: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+
+    if seconds < MINUTE {
+        format!("{}s", seconds)
+    } else if seconds < HOUR {
+        format!("{}m", seconds / MINUTE)
+    } else if seconds < DAY {
+        format!("{}h", seconds / HOUR)
+    } else {
+        format!("{}d", seconds / DAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::ids::{Timestamp, UserId};
+
+    #[test]
+    fn sequence_to_string() {
+        let path = env::temp_dir().join("sbr_item_names.csv");
+        fs::write(
+            &path,
+            "movie_id,title\n0,StarWars\n1,TheMatrix\n2,Inception\n",
+        )
+        .unwrap();
+
+        let names = ItemNameMap::from_csv(&path, "movie_id", "title").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let day = 24 * 60 * 60;
+        let interactions = Interactions::from(vec![
+            Interaction::new(UserId(0), ItemId(0), Timestamp(day)),
+            Interaction::new(UserId(0), ItemId(1), Timestamp(4 * day)),
+            Interaction::new(UserId(0), ItemId(2), Timestamp(14 * day)),
+            Interaction::new(UserId(0), ItemId(3), Timestamp(14 * day + 90)),
+        ])
+        .to_compressed();
+
+        assert_eq!(
+            names.sequence_to_string(&interactions.get_user(UserId(0)).unwrap()),
+            "[0s] StarWars -> [3d] TheMatrix -> [10d] Inception -> [1m] 3"
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Post-processing of learned embeddings.
+//!
+//! Embeddings learned by the models tend to be far from isotropic: a few
+//! directions carry most of the variance. This module provides principal
+//! component analysis and ZCA whitening, after which cosine similarity is
+//! a better proxy for model scores in nearest-neighbour retrieval.
+use ndarray::{s, Array1, Array2, Axis};
+
+/// Added to eigenvalues before inverting them, to keep directions
+/// with (near) zero variance from blowing up.
+const EPSILON: f64 = 1e-5;
+
+/// Maximum number of Jacobi sweeps in [principal_components].
+const MAX_SWEEPS: usize = 100;
+
+/// Compute the principal components of the rows of `embeddings`.
+///
+/// Returns the mean of the rows, the variances along the principal
+/// components in decreasing order, and the corresponding unit-length
+/// principal components as the columns of a matrix.
+pub fn principal_components(embeddings: &Array2<f32>) -> (Array1<f32>, Array1<f32>, Array2<f32>) {
+    let (mean, covariance) = covariance(embeddings);
+    let (variances, components) = symmetric_eigen(&covariance);
+
+    (
+        mean.mapv(|x| x as f32),
+        variances.mapv(|x| x as f32),
+        components.mapv(|x| x as f32),
+    )
+}
+
+/// Project the rows of `embeddings` onto their first `num_components`
+/// principal components.
+pub fn pca(embeddings: &Array2<f32>, num_components: usize) -> Array2<f32> {
+    let (mean, _, components) = principal_components(embeddings);
+    let num_components = num_components.min(components.ncols());
+
+    (embeddings - &mean).dot(&components.slice(s![.., ..num_components]))
+}
+
+/// ZCA whitening transform for embeddings.
+///
+/// Whitened embeddings have zero mean and identity covariance, while
+/// staying as close as possible to the original embeddings.
+#[derive(Clone, Debug)]
+pub struct EmbeddingWhitener {
+    mean: Array1<f32>,
+    transform: Array2<f32>,
+}
+
+impl EmbeddingWhitener {
+    /// Fit the whitening transform to the rows of `embeddings`.
+    pub fn fit(embeddings: &Array2<f32>) -> Self {
+        let (mean, covariance) = covariance(embeddings);
+        let (variances, components) = symmetric_eigen(&covariance);
+
+        let scale = Array2::from_diag(&variances.mapv(|x| 1.0 / (x.max(0.0) + EPSILON).sqrt()));
+        let transform = components.dot(&scale).dot(&components.t());
+
+        EmbeddingWhitener {
+            mean: mean.mapv(|x| x as f32),
+            transform: transform.mapv(|x| x as f32),
+        }
+    }
+
+    /// Whiten the rows of `embeddings`.
+    pub fn transform(&self, embeddings: &Array2<f32>) -> Array2<f32> {
+        (embeddings - &self.mean).dot(&self.transform)
+    }
+}
+
+/// Return the row mean and the covariance matrix of `embeddings`.
+fn covariance(embeddings: &Array2<f32>) -> (Array1<f64>, Array2<f64>) {
+    let embeddings = embeddings.mapv(|x| x as f64);
+    let num_rows = embeddings.nrows().max(1) as f64;
+
+    let mean = embeddings
+        .mean_axis(Axis(0))
+        .unwrap_or_else(|| Array1::zeros(embeddings.ncols()));
+    let centered = &embeddings - &mean;
+
+    (mean, centered.t().dot(&centered) / num_rows)
+}
+
+/// Eigendecomposition of a symmetric matrix by cyclic Jacobi rotations.
+///
+/// Returns the eigenvalues in decreasing order and the eigenvectors
+/// as the columns of a matrix.
+fn symmetric_eigen(matrix: &Array2<f64>) -> (Array1<f64>, Array2<f64>) {
+    let dim = matrix.nrows();
+    let mut matrix = matrix.to_owned();
+    let mut vectors = Array2::<f64>::eye(dim);
+
+    let total = matrix.iter().map(|x| x * x).sum::<f64>();
+
+    for _ in 0..MAX_SWEEPS {
+        let off_diagonal = matrix
+            .indexed_iter()
+            .filter(|((row, col), _)| row != col)
+            .map(|(_, x)| x * x)
+            .sum::<f64>();
+
+        if off_diagonal <= 1e-24 * total {
+            break;
+        }
+
+        for p in 0..dim {
+            for q in (p + 1)..dim {
+                if matrix[[p, q]] == 0.0 {
+                    continue;
+                }
+
+                let theta = (matrix[[q, q]] - matrix[[p, p]]) / (2.0 * matrix[[p, q]]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+
+                for k in 0..dim {
+                    let (kp, kq) = (matrix[[k, p]], matrix[[k, q]]);
+                    matrix[[k, p]] = c * kp - s * kq;
+                    matrix[[k, q]] = s * kp + c * kq;
+                }
+
+                for k in 0..dim {
+                    let (pk, qk) = (matrix[[p, k]], matrix[[q, k]]);
+                    matrix[[p, k]] = c * pk - s * qk;
+                    matrix[[q, k]] = s * pk + c * qk;
+                }
+
+                for k in 0..dim {
+                    let (kp, kq) = (vectors[[k, p]], vectors[[k, q]]);
+                    vectors[[k, p]] = c * kp - s * kq;
+                    vectors[[k, q]] = s * kp + c * kq;
+                }
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..dim).collect();
+    order.sort_by(|&x, &y| {
+        matrix[[y, y]]
+            .partial_cmp(&matrix[[x, x]])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let values = Array1::from_shape_fn(dim, |idx| matrix[[order[idx], order[idx]]]);
+    let vectors = Array2::from_shape_fn((dim, dim), |(row, col)| vectors[[row, order[col]]]);
+
+    (values, vectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::distributions::{Distribution, Normal};
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+
+    /// Correlated, anisotropic embeddings with a non-zero mean.
+    fn embeddings() -> Array2<f32> {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let normal = Normal::new(0.0, 1.0);
+
+        let noise = Array2::from_shape_fn((2000, 3), |_| normal.sample(&mut rng) as f32);
+        let mixing = ndarray::arr2(&[[3.0, 1.0, 0.0], [0.0, 1.0, 0.5], [0.0, 0.0, 0.1]]);
+
+        noise.dot(&mixing) + &ndarray::arr1(&[1.0, -2.0, 0.5])
+    }
+
+    #[test]
+    fn whitening() {
+        let embeddings = embeddings();
+        let whitener = EmbeddingWhitener::fit(&embeddings);
+        let whitened = whitener.transform(&embeddings);
+
+        let (mean, covariance) = covariance(&whitened);
+
+        assert!(mean.iter().all(|x| x.abs() < 1e-3));
+        for ((row, col), &value) in covariance.indexed_iter() {
+            let expected = if row == col { 1.0 } else { 0.0 };
+            assert!((value - expected).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn principal_components_decomposition() {
+        let embeddings = embeddings();
+        let (_, variances, components) = principal_components(&embeddings);
+
+        assert!(variances[0] >= variances[1] && variances[1] >= variances[2]);
+
+        // The components are orthonormal...
+        let gram = components.t().dot(&components);
+        for ((row, col), &value) in gram.indexed_iter() {
+            let expected = if row == col { 1.0 } else { 0.0 };
+            assert!((value - expected).abs() < 1e-4);
+        }
+
+        // ...and the projections have the component variances.
+        let projected = pca(&embeddings, 2);
+        assert_eq!(projected.shape(), &[2000, 2]);
+
+        let (_, covariance) = covariance(&projected);
+        assert!((covariance[[0, 0]] as f32 - variances[0]).abs() < 1e-2 * variances[0]);
+        assert!(covariance[[0, 1]].abs() < 1e-2 * variances[0] as f64);
+    }
+}
@@ -3,9 +3,57 @@ use std;
 
 use rayon::prelude::*;
 
-use crate::data::CompressedInteractions;
+use crate::config;
+use crate::data::{CompressedInteractions, CompressedInteractionsUser};
+use crate::ids::{ItemId, Timestamp};
 use crate::{OnlineRankingModel, PredictionError};
 
+/// Apply `func` to each `test` sequence of at least two items, along with
+/// the representation of all but its last item.
+///
+/// Users are split into one chunk per thread of the pool chosen by
+/// [config](crate::config), and each chunk's representations are computed
+/// with a single call to [OnlineRankingModel::user_representations].
+fn map_test_users<T, F, R>(
+    model: &T,
+    test: &CompressedInteractions,
+    func: F,
+) -> Result<Vec<R>, PredictionError>
+where
+    T: OnlineRankingModel + Sync,
+    F: Fn(&CompressedInteractionsUser, &T::UserRepresentation) -> Result<R, PredictionError> + Sync,
+    R: Send,
+{
+    let test_users: Vec<_> = test
+        .iter_users()
+        .filter(|user| user.item_ids.len() >= 2)
+        .collect();
+
+    let chunks = config::install(|| {
+        let num_threads = rayon::current_num_threads();
+        let chunk_size = (test_users.len() + num_threads - 1) / num_threads;
+
+        test_users
+            .par_chunks(chunk_size.max(1))
+            .map(|chunk| {
+                let histories: Vec<_> = chunk
+                    .iter()
+                    .map(|user| &user.item_ids[..user.item_ids.len() - 1])
+                    .collect();
+                let representations = model.user_representations(&histories)?;
+
+                chunk
+                    .iter()
+                    .zip(representations.iter())
+                    .map(|(user, representation)| func(user, representation))
+                    .collect::<Result<Vec<_>, PredictionError>>()
+            })
+            .collect::<Result<Vec<_>, PredictionError>>()
+    })?;
+
+    Ok(chunks.into_iter().flatten().collect())
+}
+
 /// Compute the MRR (mean reciprocal rank) of predictions for the last
 /// item in `test` sequences, treating all but the last one item as inputs
 /// in computing the user representation.
@@ -13,36 +61,340 @@ pub fn mrr_score<T: OnlineRankingModel + Sync>(
     model: &T,
     test: &CompressedInteractions,
 ) -> Result<f32, PredictionError> {
-    let item_ids: Vec<usize> = (0..test.num_items()).collect();
+    let mrrs = reciprocal_ranks(model, test)?;
 
-    let mrrs = test
-        .iter_users()
-        .filter(|user| user.item_ids.len() >= 2)
-        .collect::<Vec<_>>()
-        .par_iter()
-        .map(|test_user| {
-            let train_items = &test_user.item_ids[..test_user.item_ids.len().saturating_sub(1)];
-            let test_item = *test_user.item_ids.last().unwrap();
+    Ok(mrrs.iter().map(|&(mrr, _)| mrr).sum::<f32>() / mrrs.len() as f32)
+}
+
+/// Return the reciprocal rank of the last item of each `test` sequence of
+/// at least two items, along with the timestamp of that item.
+fn reciprocal_ranks<T: OnlineRankingModel + Sync>(
+    model: &T,
+    test: &CompressedInteractions,
+) -> Result<Vec<(f32, Timestamp)>, PredictionError> {
+    map_test_users(model, test, |test_user, user_embedding| {
+        let train_items = &test_user.item_ids[..test_user.item_ids.len().saturating_sub(1)];
+        let test_item = *test_user.item_ids.last().unwrap();
+
+        let mut predictions = model.predict_all_items(user_embedding, test.num_items())?;
+
+        for &train_item_id in train_items {
+            predictions[train_item_id.index()] = std::f32::MIN;
+        }
 
-            let user_embedding = model.user_representation(train_items).unwrap();
-            let mut predictions = model.predict(&user_embedding, &item_ids)?;
+        let test_score = predictions[test_item.index()];
+        let mut rank = 0;
 
-            for &train_item_id in train_items {
-                predictions[train_item_id] = std::f32::MIN;
+        for &prediction in &predictions {
+            if prediction >= test_score {
+                rank += 1;
             }
+        }
+
+        Ok((1.0 / rank as f32, *test_user.timestamps.last().unwrap()))
+    })
+}
 
-            let test_score = predictions[test_item];
-            let mut rank = 0;
+/// Like [mrr_score], but weighting each user's reciprocal rank by
+/// `exp(-decay * (max_timestamp - test_timestamp))`, where `test_timestamp`
+/// is the timestamp of the user's test item and `max_timestamp` the latest
+/// of those timestamps. Models that only do well on stale users score lower.
+///
+/// A `decay` of zero gives the same result as [mrr_score].
+pub fn time_aware_mrr<T: OnlineRankingModel + Sync>(
+    model: &T,
+    test: &CompressedInteractions,
+    decay: f64,
+) -> Result<f32, PredictionError> {
+    let mrrs = reciprocal_ranks(model, test)?;
+    let max_timestamp = mrrs.iter().map(|&(_, timestamp)| timestamp).max();
+
+    let (weighted_sum, total_weight) = mrrs.iter().fold(
+        (0.0, 0.0),
+        |(weighted_sum, total_weight), &(mrr, timestamp)| {
+            let age = (max_timestamp.unwrap() - timestamp) as f64;
+            let weight = (-decay * age).exp();
+
+            (
+                weighted_sum + weight * f64::from(mrr),
+                total_weight + weight,
+            )
+        },
+    );
+
+    Ok((weighted_sum / total_weight) as f32)
+}
+
+/// Compute the NDCG (normalized discounted cumulative gain) at `k` of
+/// predictions for the last item in `test` sequences, treating all but
+/// the last one item as inputs in computing the user representation.
+///
+/// With a single relevant item, this is `1 / log2(rank + 1)` if the
+/// item is ranked in the top `k`, and 0 otherwise.
+pub fn ndcg_score<T: OnlineRankingModel + Sync>(
+    model: &T,
+    test: &CompressedInteractions,
+    k: usize,
+) -> Result<f32, PredictionError> {
+    let ndcgs = map_test_users(model, test, |test_user, user_embedding| {
+        let train_items = &test_user.item_ids[..test_user.item_ids.len().saturating_sub(1)];
+        let test_item = *test_user.item_ids.last().unwrap();
+
+        let mut predictions = model.predict_all_items(user_embedding, test.num_items())?;
+
+        for &train_item_id in train_items {
+            predictions[train_item_id.index()] = std::f32::MIN;
+        }
+
+        let test_score = predictions[test_item.index()];
+        let rank = predictions
+            .iter()
+            .filter(|&&prediction| prediction >= test_score)
+            .count();
+
+        if rank <= k {
+            Ok(1.0 / (rank as f32 + 1.0).log2())
+        } else {
+            Ok(0.0)
+        }
+    })?;
+
+    Ok(ndcgs.iter().sum::<f32>() / ndcgs.len() as f32)
+}
+
+/// For every item, compute the fraction of `test` users for whom the item
+/// appears in the top `k` recommendations. The result is indexed by item id.
+///
+/// As in [mrr_score], all but the last item of each sequence are used to
+/// compute the user representation, and are excluded from the recommendations.
+///
+/// A distribution concentrated on a small fraction of items indicates
+/// popularity bias: see [gini_coefficient].
+pub fn item_recommendation_frequency_at_k<T: OnlineRankingModel + Sync>(
+    model: &T,
+    test: &CompressedInteractions,
+    k: usize,
+) -> Result<Vec<f32>, PredictionError> {
+    let recommendations = map_test_users(model, test, |test_user, user_embedding| {
+        let train_items = &test_user.item_ids[..test_user.item_ids.len().saturating_sub(1)];
 
-            for &prediction in &predictions {
-                if prediction >= test_score {
-                    rank += 1;
-                }
+        let mut is_candidate = vec![true; test.num_items()];
+
+        for &train_item_id in train_items {
+            is_candidate[train_item_id.index()] = false;
+        }
+
+        let candidates: Vec<ItemId> = (0..test.num_items())
+            .filter(|&item_id| is_candidate[item_id])
+            .map(ItemId::from_index)
+            .collect();
+
+        Ok(model
+            .rank_items(user_embedding, &candidates)?
+            .into_iter()
+            .take(k)
+            .map(|idx| candidates[idx])
+            .collect::<Vec<_>>())
+    })?;
+
+    let mut frequencies = vec![0.0; test.num_items()];
+
+    for &item_id in recommendations.iter().flatten() {
+        frequencies[item_id.index()] += 1.0;
+    }
+
+    let num_users = recommendations.len().max(1) as f32;
+
+    Ok(frequencies
+        .into_iter()
+        .map(|count| count / num_users)
+        .collect())
+}
+
+/// Compute the fraction of items that appear in the top `k`
+/// recommendations of at least one `test` user.
+pub fn coverage_at_k<T: OnlineRankingModel + Sync>(
+    model: &T,
+    test: &CompressedInteractions,
+    k: usize,
+) -> Result<f32, PredictionError> {
+    let frequencies = item_recommendation_frequency_at_k(model, test, k)?;
+    let covered = frequencies.iter().filter(|&&x| x > 0.0).count();
+
+    Ok(covered as f32 / frequencies.len().max(1) as f32)
+}
+
+/// Return the 1-indexed rank of `target_item` among `all_items`, sorted
+/// by the model's scores for a user with the given `history`.
+///
+/// Items scoring the same as the target are ranked ahead of it, as in
+/// [mrr_score]. Unlike the aggregate metrics, items in `history` are
+/// ranked too: remove them from `all_items` to exclude them.
+pub fn rank_of_item<M: OnlineRankingModel>(
+    model: &M,
+    history: &[ItemId],
+    target_item: ItemId,
+    all_items: &[ItemId],
+) -> Result<usize, PredictionError> {
+    let target_idx = all_items
+        .iter()
+        .position(|&item_id| item_id == target_item)
+        .ok_or(PredictionError::ItemNotInCandidates {
+            item_id: target_item,
+        })?;
+
+    let user = model.user_representation(history)?;
+    let scores = model.predict(&user, all_items)?;
+    let target_score = scores[target_idx];
+
+    Ok(scores
+        .iter()
+        .filter(|&&score| score >= target_score)
+        .count())
+}
+
+/// Compute the Gini coefficient of non-negative `values`: 0.0 when all
+/// values are equal, approaching 1.0 when all the mass is on a single value.
+pub fn gini_coefficient(values: &[f32]) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|x, y| x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal));
+
+    let total: f32 = sorted.iter().sum();
+
+    if sorted.is_empty() || total == 0.0 {
+        return 0.0;
+    }
+
+    let num_values = sorted.len() as f32;
+    let weighted_sum: f32 = sorted
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| (idx + 1) as f32 * value)
+        .sum();
+
+    2.0 * weighted_sum / (num_values * total) - (num_values + 1.0) / num_values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{synthetic_interactions, Interaction, Interactions};
+    use crate::ids::{items, UserId};
+    use crate::models::baselines::Popularity;
+    use crate::models::ewma::Hyperparameters;
+
+    #[test]
+    fn gini() {
+        assert_eq!(gini_coefficient(&[1.0, 1.0, 1.0, 1.0]), 0.0);
+        assert!((gini_coefficient(&[0.0, 0.0, 0.0, 1.0]) - 0.75).abs() < 1e-6);
+        assert_eq!(gini_coefficient(&[0.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn ndcg_bounds() {
+        let test = synthetic_interactions(10, 20, 5).to_compressed();
+        let mut model = Hyperparameters::new(test.num_items(), 5)
+            .num_epochs(0)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&test).unwrap();
+
+        let ndcg = ndcg_score(&model, &test, 10).unwrap();
+        assert!(ndcg >= 0.0 && ndcg <= 1.0);
+
+        // With k covering all items, NDCG is at least MRR.
+        let mrr = mrr_score(&model, &test).unwrap();
+        assert!(ndcg_score(&model, &test, test.num_items()).unwrap() >= mrr);
+    }
+
+    #[test]
+    fn recommendation_frequency() {
+        let test = synthetic_interactions(10, 20, 5).to_compressed();
+        let mut model = Hyperparameters::new(test.num_items(), 5)
+            .num_epochs(0)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&test).unwrap();
+
+        let frequencies = item_recommendation_frequency_at_k(&model, &test, 3).unwrap();
+
+        assert_eq!(frequencies.len(), test.num_items());
+        assert!(frequencies.iter().all(|&x| x >= 0.0 && x <= 1.0));
+        assert!((frequencies.iter().sum::<f32>() - 3.0).abs() < 1e-4);
+
+        let coverage = coverage_at_k(&model, &test, 3).unwrap();
+        let covered = frequencies.iter().filter(|&&x| x > 0.0).count();
+        assert_eq!(coverage, covered as f32 / test.num_items() as f32);
+    }
+
+    #[test]
+    fn rank_of_target_item() {
+        // Item `i` is interacted with by `10 - i` users.
+        let interactions: Vec<_> = (0..10)
+            .flat_map(|item_id| {
+                (0..10 - item_id).map(move |user_id| {
+                    Interaction::new(UserId(user_id), ItemId(item_id), Timestamp(0))
+                })
+            })
+            .collect();
+        let data = Interactions::from(interactions).to_compressed();
+        let mut model = Popularity::new(data.num_items());
+        model.fit(&data).unwrap();
+
+        let all_items: Vec<_> = (0..10).rev().map(ItemId).collect();
+        assert_eq!(rank_of_item(&model, &[], ItemId(0), &all_items).unwrap(), 1);
+        assert_eq!(
+            rank_of_item(&model, &items(&[1]), ItemId(3), &all_items).unwrap(),
+            4
+        );
+        assert_eq!(
+            rank_of_item(&model, &[], ItemId(9), &items(&[9, 2])).unwrap(),
+            2
+        );
+
+        match rank_of_item(&model, &[], ItemId(5), &items(&[0, 1])) {
+            Err(PredictionError::ItemNotInCandidates { item_id }) => {
+                assert_eq!(item_id, ItemId(5))
             }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn time_aware() {
+        // Item `i` is interacted with by `10 - i` users.
+        let interactions: Vec<_> = (0..10)
+            .flat_map(|item_id| {
+                (0..10 - item_id).map(move |user_id| {
+                    Interaction::new(UserId(user_id), ItemId(item_id), Timestamp(0))
+                })
+            })
+            .collect();
+        let mut model = Popularity::new(10);
+        model
+            .fit(&Interactions::from(interactions).to_compressed())
+            .unwrap();
+
+        // User 0 ranks its test item first, but 10 time units before
+        // user 1, whose test item ranks ninth.
+        let test = Interactions::from(vec![
+            Interaction::new(UserId(0), ItemId(5), Timestamp(0)),
+            Interaction::new(UserId(0), ItemId(0), Timestamp(1)),
+            Interaction::new(UserId(1), ItemId(0), Timestamp(0)),
+            Interaction::new(UserId(1), ItemId(9), Timestamp(11)),
+        ])
+        .to_compressed();
+
+        let mrr = mrr_score(&model, &test).unwrap();
+        assert!((mrr - (1.0 + 1.0 / 9.0) / 2.0).abs() < 1e-6);
+        assert!((time_aware_mrr(&model, &test, 0.0).unwrap() - mrr).abs() < 1e-6);
 
-            Ok(1.0 / rank as f32)
-        })
-        .collect::<Result<Vec<f32>, PredictionError>>()?;
+        // The stale user has half the weight of the recent one.
+        let halved = time_aware_mrr(&model, &test, std::f64::consts::LN_2 / 10.0).unwrap();
+        assert!((halved - (0.5 + 1.0 / 9.0) / 1.5).abs() < 1e-6);
 
-    Ok(mrrs.iter().sum::<f32>() / mrrs.len() as f32)
+        assert!(time_aware_mrr(&model, &test, 10.0).unwrap() < mrr);
+    }
 }
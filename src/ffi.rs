@@ -0,0 +1,319 @@
+//! A C interface for serving models exported with `to_json`.
+//!
+//! Build a shared library exposing these functions with
+//! `cargo rustc --release --features ffi --crate-type cdylib`.
+//!
+//! The doc comments of the functions below are written to be carried into
+//! a generated C header. The ownership rules are:
+//!
+//! - `sbr_model_load` returns a model owned by the caller, to be released
+//!   with exactly one call to `sbr_model_free`.
+//! - `sbr_user_representation` returns a representation owned by the
+//!   caller, to be released with exactly one call to
+//!   `sbr_user_representation_free`. It borrows nothing from the model
+//!   or the item ids, but can only be used with the model that made it.
+//! - All other pointers are borrowed for the duration of the call only.
+//! - Passing a null pointer to a free function is a no-op.
+//!
+//! No function unwinds into C: panics are caught at the boundary and
+//! reported as [SBR_ERROR_PANIC], or as a null pointer by the functions
+//! returning pointers.
+use std::ffi::CStr;
+use std::fs;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::ids::ItemId;
+use crate::models::boxed::{BoxedModel, BoxedUser, IntoBoxedModel};
+use crate::models::ewma::ImplicitEWMAModel;
+use crate::models::json::ExportError;
+use crate::models::lstm::ImplicitLSTMModel;
+use crate::OnlineRankingModel;
+
+/// A model loaded with [sbr_model_load].
+pub type Model = BoxedModel;
+/// A user representation computed with [sbr_user_representation].
+pub type Repr = BoxedUser;
+
+/// A required pointer argument is null.
+pub const SBR_ERROR_NULL_POINTER: c_int = -1;
+/// The model failed to produce predictions, for example because it has
+/// not been fitted.
+pub const SBR_ERROR_PREDICTION: c_int = -2;
+/// The call panicked.
+pub const SBR_ERROR_PANIC: c_int = -3;
+
+fn load(path: &CStr) -> Option<Model> {
+    let json = fs::read_to_string(path.to_str().ok()?).ok()?;
+
+    match ImplicitEWMAModel::from_json(&json) {
+        Ok(model) => {
+            let num_items = model.num_items();
+            Some(model.boxed(num_items))
+        }
+        Err(ExportError::WrongModel { .. }) => {
+            let model = ImplicitLSTMModel::from_json(&json).ok()?;
+            let num_items = model.num_items();
+            Some(model.boxed(num_items))
+        }
+        Err(_) => None,
+    }
+}
+
+/// Load a model from the JSON document at `path`, a null-terminated
+/// UTF-8 string, as written by the `to_json` method of the EWMA or LSTM
+/// models.
+///
+/// Returns null if the file cannot be read or does not hold a model.
+/// The model must be released with [sbr_model_free].
+///
+/// # Safety
+///
+/// `path` must be null or point to a null-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sbr_model_load(path: *const c_char) -> *mut Model {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    let path = CStr::from_ptr(path);
+
+    match panic::catch_unwind(|| load(path)) {
+        Ok(Some(model)) => Box::into_raw(Box::new(model)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Release a model returned by [sbr_model_load].
+///
+/// # Safety
+///
+/// `model` must be null or a pointer returned by [sbr_model_load] that
+/// has not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn sbr_model_free(model: *mut Model) {
+    if !model.is_null() {
+        drop(Box::from_raw(model));
+    }
+}
+
+/// Compute the representation of a user from the `len` item ids at
+/// `item_ids`, oldest first.
+///
+/// Returns null if the model is null, an item id is out of range or the
+/// model cannot represent the user. The representation must be released
+/// with [sbr_user_representation_free].
+///
+/// # Safety
+///
+/// `model` must be null or a live pointer returned by [sbr_model_load],
+/// and `item_ids` must point to `len` readable values, or may be null if
+/// `len` is zero.
+#[no_mangle]
+pub unsafe extern "C" fn sbr_user_representation(
+    model: *const Model,
+    item_ids: *const usize,
+    len: usize,
+) -> *mut Repr {
+    if model.is_null() || (item_ids.is_null() && len > 0) {
+        return ptr::null_mut();
+    }
+    let model = &*model;
+    let item_ids: Vec<ItemId> = if len == 0 {
+        Vec::new()
+    } else {
+        slice::from_raw_parts(item_ids, len)
+            .iter()
+            .map(|&item_id| ItemId::from_index(item_id))
+            .collect()
+    };
+
+    match panic::catch_unwind(AssertUnwindSafe(|| model.user_representation(&item_ids))) {
+        Ok(Ok(user)) => Box::into_raw(Box::new(user)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Release a representation returned by [sbr_user_representation].
+///
+/// # Safety
+///
+/// `repr` must be null or a pointer returned by
+/// [sbr_user_representation] that has not been released yet.
+#[no_mangle]
+pub unsafe extern "C" fn sbr_user_representation_free(repr: *mut Repr) {
+    if !repr.is_null() {
+        drop(Box::from_raw(repr));
+    }
+}
+
+/// Write the ids and scores of the `k` highest-scoring items for `repr`
+/// into `out_items` and `out_scores`, best first.
+///
+/// Returns the number of items written, which is smaller than `k` if the
+/// model has fewer items, or one of the negative `SBR_ERROR_*` codes.
+///
+/// # Safety
+///
+/// `model` and `repr` must be null or live pointers returned by
+/// [sbr_model_load] and by [sbr_user_representation] with the same
+/// model, and `out_items` and `out_scores` must each point to `k`
+/// writable values.
+#[no_mangle]
+pub unsafe extern "C" fn sbr_predict_top_k(
+    model: *const Model,
+    repr: *const Repr,
+    k: usize,
+    out_items: *mut usize,
+    out_scores: *mut f32,
+) -> c_int {
+    if model.is_null() || repr.is_null() || out_items.is_null() || out_scores.is_null() {
+        return SBR_ERROR_NULL_POINTER;
+    }
+    let (model, repr) = (&*model, &*repr);
+
+    let top_k = match panic::catch_unwind(AssertUnwindSafe(|| model.predict_top_k(repr, k, &[]))) {
+        Ok(Ok(top_k)) => top_k,
+        Ok(Err(_)) => return SBR_ERROR_PREDICTION,
+        Err(_) => return SBR_ERROR_PANIC,
+    };
+
+    let out_items = slice::from_raw_parts_mut(out_items, top_k.len());
+    let out_scores = slice::from_raw_parts_mut(out_scores, top_k.len());
+    for (idx, (item_id, score)) in top_k.iter().enumerate() {
+        out_items[idx] = item_id.index();
+        out_scores[idx] = *score;
+    }
+
+    top_k.len() as c_int
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::data::synthetic_interactions;
+    use crate::models::{ewma, lstm};
+
+    fn write_model(json: &str, name: &str) -> CString {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, json).unwrap();
+        CString::new(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn serve_through_ffi() {
+        let data = synthetic_interactions(20, 15, 10).to_compressed();
+
+        let mut ewma = ewma::Hyperparameters::new(15, 6)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        ewma.fit(&data).unwrap();
+        let mut lstm = lstm::Hyperparameters::new(15, 6)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        lstm.fit(&data).unwrap();
+
+        let history = [1, 2, 3];
+        let history_ids: Vec<_> = history.iter().map(|&idx| ItemId::from_index(idx)).collect();
+        let item_ids: Vec<_> = (0..15).map(ItemId).collect();
+        let models = vec![
+            (
+                ewma.to_json().unwrap(),
+                ewma.predict(&ewma.user_representation(&history_ids).unwrap(), &item_ids),
+            ),
+            (
+                lstm.to_json().unwrap(),
+                lstm.predict(&lstm.user_representation(&history_ids).unwrap(), &item_ids),
+            ),
+        ];
+
+        for (idx, (json, expected)) in models.into_iter().enumerate() {
+            let expected = expected.unwrap();
+            let path = write_model(&json, &format!("sbr_ffi_{}.json", idx));
+
+            unsafe {
+                let model = sbr_model_load(path.as_ptr());
+                assert!(!model.is_null());
+
+                let repr = sbr_user_representation(model, history.as_ptr(), history.len());
+                assert!(!repr.is_null());
+
+                let mut out_items = vec![0; 20];
+                let mut out_scores = vec![0.0; 20];
+                let written = sbr_predict_top_k(
+                    model,
+                    repr,
+                    5,
+                    out_items.as_mut_ptr(),
+                    out_scores.as_mut_ptr(),
+                );
+                assert_eq!(written, 5);
+                for (&item_id, &score) in out_items.iter().zip(&out_scores).take(5) {
+                    assert_eq!(score, expected[item_id]);
+                }
+                assert!(out_scores[..5].windows(2).all(|x| x[0] >= x[1]));
+
+                // Asking for more items than the model has.
+                let written = sbr_predict_top_k(
+                    model,
+                    repr,
+                    20,
+                    out_items.as_mut_ptr(),
+                    out_scores.as_mut_ptr(),
+                );
+                assert_eq!(written, 15);
+
+                // Both models reject unknown items by default.
+                let out_of_range = [1, 100];
+                assert!(sbr_user_representation(model, out_of_range.as_ptr(), 2).is_null());
+
+                sbr_user_representation_free(repr);
+                sbr_model_free(model);
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let missing = CString::new("/nonexistent/sbr_model.json").unwrap();
+        let garbage = write_model("not a model", "sbr_ffi_garbage.json");
+
+        unsafe {
+            assert!(sbr_model_load(ptr::null()).is_null());
+            assert!(sbr_model_load(missing.as_ptr()).is_null());
+            assert!(sbr_model_load(garbage.as_ptr()).is_null());
+            assert!(sbr_user_representation(ptr::null(), ptr::null(), 0).is_null());
+
+            let mut out_items = [0; 1];
+            let mut out_scores = [0.0; 1];
+            assert_eq!(
+                sbr_predict_top_k(
+                    ptr::null(),
+                    ptr::null(),
+                    1,
+                    out_items.as_mut_ptr(),
+                    out_scores.as_mut_ptr()
+                ),
+                SBR_ERROR_NULL_POINTER
+            );
+
+            // An unfitted model cannot make predictions.
+            let model = ewma::Hyperparameters::new(10, 5).build();
+            let path = write_model(&model.to_json().unwrap(), "sbr_ffi_unfitted.json");
+            let model = sbr_model_load(path.as_ptr());
+            assert!(!model.is_null());
+            assert!(sbr_user_representation(model, [1].as_ptr(), 1).is_null());
+            sbr_model_free(model);
+
+            sbr_model_free(ptr::null_mut());
+            sbr_user_representation_free(ptr::null_mut());
+        }
+    }
+}
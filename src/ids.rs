@@ -0,0 +1,370 @@
+//! Typed user ids, item ids and timestamps.
+//!
+//! The ids are distinct types, so that passing an item id where a user id
+//! is expected fails to compile. Ids wrap a `u64` and timestamps an
+//! `i64`; all three serialize as the bare number, and order as it does,
+//! so that existing data files parse unchanged.
+//!
+//! Ids are dense indices: models keep one row per user or item, and look
+//! ids up with [ItemId::index]. Ids are built from raw numbers with
+//! `new` or [From], and from indices with `from_index`.
+use std::convert::TryFrom;
+use std::fmt;
+use std::num::ParseIntError;
+use std::ops::{Add, AddAssign, Sub};
+use std::str::FromStr;
+
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, TimeZone, Utc};
+use serde::de::{self, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+
+macro_rules! id_type {
+    ($(#[$attr:meta])* $name:ident, $entity:literal) => {
+        $(#[$attr])*
+        #[derive(
+            Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize,
+            Deserialize,
+        )]
+        #[serde(transparent)]
+        #[repr(transparent)]
+        pub struct $name(pub u64);
+
+        impl $name {
+            #[doc = concat!("Wrap a raw ", $entity, " id.")]
+            pub const fn new(id: u64) -> Self {
+                $name(id)
+            }
+
+            #[doc = concat!("The raw ", $entity, " id.")]
+            pub const fn get(self) -> u64 {
+                self.0
+            }
+
+            #[doc = concat!("The id as an index into per-", $entity, " tables.")]
+            ///
+            /// Ids that do not fit in a `usize`, on 32-bit targets, map to
+            /// `usize::MAX`, so that range checks reject them rather than
+            /// seeing a truncated, valid index.
+            pub fn index(self) -> usize {
+                usize::try_from(self.0).unwrap_or(usize::MAX)
+            }
+
+            #[doc = concat!("The id of the ", $entity, " at `index`.")]
+            pub fn from_index(index: usize) -> Self {
+                $name(index as u64)
+            }
+        }
+
+        impl From<u64> for $name {
+            fn from(id: u64) -> Self {
+                $name(id)
+            }
+        }
+
+        impl From<$name> for u64 {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseIntError;
+
+            fn from_str(id: &str) -> Result<Self, ParseIntError> {
+                id.parse().map($name)
+            }
+        }
+    };
+}
+
+id_type!(
+    /// Id of a user.
+    UserId,
+    "user"
+);
+
+id_type!(
+    /// Id of an item.
+    ItemId,
+    "item"
+);
+
+/// Time of an interaction, in units of the data, such as seconds.
+///
+/// Timestamps are signed, so that times before an epoch such as
+/// 1970-01-01 can be represented. They can be compared, and offset by a
+/// number of units; the difference of two timestamps is a signed number
+/// of units. Offsets and differences saturate at the bounds of `i64`
+/// rather than overflowing.
+///
+/// Timestamps serialize as a signed number. Unsigned numbers, as written
+/// by earlier versions, are read back unchanged.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(transparent)]
+#[repr(transparent)]
+pub struct Timestamp(pub i64);
+
+impl Timestamp {
+    /// Wrap a raw timestamp.
+    pub const fn new(timestamp: i64) -> Self {
+        Timestamp(timestamp)
+    }
+
+    /// The raw timestamp.
+    pub const fn get(self) -> i64 {
+        self.0
+    }
+
+    /// Units elapsed from `earlier` to `self`, or zero if `earlier` is
+    /// later.
+    pub fn saturating_sub(self, earlier: Timestamp) -> u64 {
+        (i128::from(self.0) - i128::from(earlier.0)).max(0) as u64
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl Timestamp {
+    /// The timestamp of `datetime`, in seconds since the Unix epoch.
+    pub fn from_datetime(datetime: DateTime<Utc>) -> Self {
+        Timestamp(datetime.timestamp())
+    }
+
+    /// The datetime of the timestamp, read as seconds since the Unix
+    /// epoch, or `None` if it is out of the range of [DateTime].
+    pub fn to_datetime(self) -> Option<DateTime<Utc>> {
+        Utc.timestamp_opt(self.0, 0).single()
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(datetime: DateTime<Utc>) -> Self {
+        Timestamp::from_datetime(datetime)
+    }
+}
+
+impl From<i64> for Timestamp {
+    fn from(timestamp: i64) -> Self {
+        Timestamp(timestamp)
+    }
+}
+
+impl From<Timestamp> for i64 {
+    fn from(timestamp: Timestamp) -> Self {
+        timestamp.0
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = ParseIntError;
+
+    fn from_str(timestamp: &str) -> Result<Self, ParseIntError> {
+        timestamp.parse().map(Timestamp)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TimestampVisitor;
+
+        impl<'de> Visitor<'de> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an integer timestamp")
+            }
+
+            fn visit_i64<E: de::Error>(self, timestamp: i64) -> Result<Timestamp, E> {
+                Ok(Timestamp(timestamp))
+            }
+
+            fn visit_u64<E: de::Error>(self, timestamp: u64) -> Result<Timestamp, E> {
+                i64::try_from(timestamp)
+                    .map(Timestamp)
+                    .map_err(|_| E::invalid_value(Unexpected::Unsigned(timestamp), &self))
+            }
+        }
+
+        // Signed and unsigned 64-bit integers share their binary
+        // encodings, and self-describing formats call either method.
+        deserializer.deserialize_i64(TimestampVisitor)
+    }
+}
+
+impl Add<u64> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, units: u64) -> Timestamp {
+        Timestamp(self.0.saturating_add_unsigned(units))
+    }
+}
+
+impl AddAssign<u64> for Timestamp {
+    fn add_assign(&mut self, units: u64) {
+        *self = *self + units;
+    }
+}
+
+impl Sub<u64> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, units: u64) -> Timestamp {
+        Timestamp(self.0.saturating_sub_unsigned(units))
+    }
+}
+
+impl Sub for Timestamp {
+    type Output = i64;
+
+    /// Units elapsed from `earlier` to `self`, negative if `earlier` is
+    /// later.
+    fn sub(self, earlier: Timestamp) -> i64 {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+/// Item ids from raw ids, to keep tests short.
+#[cfg(test)]
+pub(crate) fn items(raw: &[u64]) -> Vec<ItemId> {
+    raw.iter().cloned().map(ItemId).collect()
+}
+
+/// Timestamps from raw timestamps, to keep tests short.
+#[cfg(test)]
+pub(crate) fn times(raw: &[i64]) -> Vec<Timestamp> {
+    raw.iter().cloned().map(Timestamp).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Row {
+        user_id: UserId,
+        item_id: ItemId,
+        timestamp: Timestamp,
+    }
+
+    #[test]
+    fn serde_as_numbers() {
+        let row = Row {
+            user_id: UserId(1),
+            item_id: ItemId(2),
+            timestamp: Timestamp(3),
+        };
+
+        let json = serde_json::to_string(&row).unwrap();
+        assert_eq!(json, r#"{"user_id":1,"item_id":2,"timestamp":3}"#);
+        assert_eq!(serde_json::from_str::<Row>(&json).unwrap(), row);
+
+        // Binary encodings are those of the raw numbers.
+        assert_eq!(
+            bincode::serialize(&row).unwrap(),
+            bincode::serialize(&(1u64, 2u64, 3i64)).unwrap()
+        );
+        let ids: Vec<ItemId> = serde_json::from_str("[0, 5, 18446744073709551615]").unwrap();
+        assert_eq!(ids, vec![ItemId(0), ItemId(5), ItemId(u64::MAX)]);
+    }
+
+    #[test]
+    fn ordering() {
+        let raw = vec![5u64, 0, 3, 3, 18, 1];
+        let mut sorted_raw = raw.clone();
+        sorted_raw.sort();
+
+        let mut ids: Vec<ItemId> = raw.iter().cloned().map(ItemId::from).collect();
+        ids.sort();
+        assert_eq!(
+            ids.into_iter().map(u64::from).collect::<Vec<_>>(),
+            sorted_raw
+        );
+
+        let mut timestamps = times(&[5, -20, 3, 0, 18, -1]);
+        timestamps.sort();
+        assert_eq!(timestamps, times(&[-20, -1, 0, 3, 5, 18]));
+        assert_eq!(timestamps.iter().max(), Some(&Timestamp(18)));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(ItemId::from_index(7).index(), 7);
+        assert_eq!(ItemId(u64::MAX).index(), usize::MAX);
+        assert_eq!(u64::from(UserId::new(4)), 4);
+        assert_eq!(UserId::from(4).to_string(), "4");
+        assert_eq!("4".parse::<UserId>().unwrap(), UserId(4));
+        assert_eq!("-4".parse::<Timestamp>().unwrap(), Timestamp(-4));
+
+        assert_eq!(Timestamp(10) - Timestamp(4), 6);
+        assert_eq!(Timestamp(10) + 5, Timestamp(15));
+        let mut timestamp = Timestamp(10);
+        timestamp += 2;
+        assert_eq!(timestamp, Timestamp(12));
+        assert_eq!(Timestamp(4) - Timestamp(10), -6);
+        assert_eq!(Timestamp(-4) + 5, Timestamp(1));
+        assert_eq!(Timestamp(4).saturating_sub(Timestamp(10)), 0);
+        assert_eq!(Timestamp(4).saturating_sub(Timestamp(-10)), 14);
+    }
+
+    #[test]
+    fn saturating_arithmetic() {
+        assert_eq!(Timestamp(5) - u64::MAX, Timestamp(i64::MIN));
+        assert_eq!(Timestamp(-5) + u64::MAX, Timestamp(i64::MAX));
+        assert_eq!(Timestamp(i64::MIN) + u64::MAX, Timestamp(i64::MAX));
+        assert_eq!(Timestamp(i64::MIN) + (1 << 63), Timestamp(0));
+        let mut timestamp = Timestamp(i64::MAX - 1);
+        timestamp += 5;
+        assert_eq!(timestamp, Timestamp(i64::MAX));
+
+        assert_eq!(Timestamp(i64::MAX) - Timestamp(-1), i64::MAX);
+        assert_eq!(Timestamp(i64::MIN) - Timestamp(1), i64::MIN);
+        assert_eq!(
+            Timestamp(i64::MAX).saturating_sub(Timestamp(i64::MIN)),
+            u64::MAX
+        );
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn datetimes() {
+        let datetime = Utc.ymd(1969, 12, 31).and_hms(23, 0, 0);
+        let timestamp = Timestamp::from(datetime);
+
+        assert_eq!(timestamp, Timestamp(-3600));
+        assert_eq!(timestamp.to_datetime(), Some(datetime));
+        assert_eq!(Timestamp(i64::MAX).to_datetime(), None);
+    }
+
+    #[test]
+    fn signed_and_unsigned_timestamps() {
+        let timestamps: Vec<Timestamp> = serde_json::from_str("[-86400, 0, 5]").unwrap();
+        assert_eq!(timestamps, times(&[-86_400, 0, 5]));
+        assert_eq!(serde_json::to_string(&Timestamp(-3)).unwrap(), "-3");
+        assert!(serde_json::from_str::<Timestamp>("18446744073709551615").is_err());
+
+        // Binary files from earlier versions store unsigned timestamps.
+        let unsigned = bincode::serialize(&7u64).unwrap();
+        assert_eq!(
+            bincode::deserialize::<Timestamp>(&unsigned).unwrap(),
+            Timestamp(7)
+        );
+        let signed = bincode::serialize(&Timestamp(-7)).unwrap();
+        assert_eq!(
+            bincode::deserialize::<Timestamp>(&signed).unwrap(),
+            Timestamp(-7)
+        );
+    }
+}
@@ -10,6 +10,8 @@
 //! Implemented models:
 //! - LSTM: a model that uses an LSTM network over the sequence of a user's interaction
 //!         to predict their next action;
+//! - Attention: a model that uses causal self-attention over the sequence of a user's
+//!              interactions to predict their next action;
 //! - EWMA: a model that uses a simpler exponentially-weighted average of past actions
 //!         to predict future interactions.
 //!
@@ -17,76 +19,311 @@
 //! quicker to fit, and will probably be a good starting point.
 //!
 //! ## Example
-//! You can fit a model on the Movielens 100K dataset in about 10 seconds:
+//! You can fit a model on the Movielens 100K dataset and evaluate it in
+//! about 10 seconds with [pipeline::run]:
 //!
 //! ```rust
-//! # extern crate sbr;
-//! # extern crate rand;
-//! # use std::time::Instant;
-//! # use rand::SeedableRng;
-//! let mut data = sbr::datasets::download_movielens_100k().unwrap();
+//! use recommenders::models::{lstm, Loss, Optimizer};
+//! use recommenders::pipeline::{self, ModelConfig, PipelineConfig, Split};
 //!
-//! let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+//! # async_std::task::block_on(async {
+//! let data = recommenders::datasets::download_movielens_100k().await.unwrap();
 //!
-//! let (train, test) = sbr::data::user_based_split(&mut data, &mut rng, 0.2);
-//! let train_mat = train.to_compressed();
-//! let test_mat = test.to_compressed();
-//!
-//! println!("Train: {}, test: {}", train.len(), test.len());
-//!
-//! let mut model = sbr::models::lstm::Hyperparameters::new(data.num_items(), 32)
+//! let model = lstm::Hyperparameters::new(data.num_items(), 32)
 //!     .embedding_dim(32)
 //!     .learning_rate(0.16)
 //!     .l2_penalty(0.0004)
-//!     .lstm_variant(sbr::models::lstm::LSTMVariant::Normal)
-//!     .loss(sbr::models::Loss::WARP)
-//!     .optimizer(sbr::models::Optimizer::Adagrad)
-//!     .num_epochs(10)
-//!     .rng(rng)
-//!     .build();
+//!     .lstm_variant(lstm::LSTMVariant::Normal)
+//!     .loss(Loss::WARP)
+//!     .optimizer(Optimizer::Adagrad)
+//!     .num_epochs(10);
+//! let config = PipelineConfig::new(ModelConfig::Lstm(model))
+//!     .split(Split::UserBased { test_fraction: 0.2 })
+//!     .seed(42);
 //!
-//! let start = Instant::now();
-//! let loss = model.fit(&train_mat).unwrap();
-//! let elapsed = start.elapsed();
-//! let train_mrr = sbr::evaluation::mrr_score(&model, &train_mat).unwrap();
-//! let test_mrr = sbr::evaluation::mrr_score(&model, &test_mat).unwrap();
+//! let result = pipeline::run(data, config).unwrap();
 //!
 //! println!(
-//!     "Train MRR {} at loss {} and test MRR {} (in {:?})",
-//!     train_mrr, loss, test_mrr, elapsed
+//!     "Train: {}, test: {}. Test {} at loss {:?} (in {:?})",
+//!     result.num_train, result.num_test, result.report, result.loss, result.timings.fit
 //! );
+//! # });
 //! ```
 #[macro_use]
 extern crate itertools;
+#[cfg(feature = "datasets")]
 extern crate csv;
 #[macro_use]
-extern crate failure;
+mod logging;
+#[cfg(feature = "arrow")]
+pub mod arrow_ipc;
+#[cfg(feature = "training")]
+pub mod bench;
+#[cfg(feature = "benchmarks")]
+pub mod benchmark;
+pub mod config;
 pub mod data;
 pub mod datasets;
+#[cfg(feature = "datasets")]
+pub mod debug;
+#[cfg(feature = "embeddings")]
+pub mod embeddings;
+#[cfg(feature = "training")]
 pub mod evaluation;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod ids;
 pub mod models;
+#[cfg(feature = "training")]
+pub mod pipeline;
+pub mod rng;
+pub mod session;
+
+use std::error;
+use std::fmt;
 
-/// Alias for user indices.
-pub type UserId = usize;
-/// Alias for item indices.
-pub type ItemId = usize;
-/// Alias for timestamps.
-pub type Timestamp = usize;
+use crate::datasets::DatasetError;
+
+/// Former location of [ids::UserId].
+#[deprecated(note = "Use `ids::UserId`. This alias will be removed.")]
+pub type UserId = ids::UserId;
+/// Former location of [ids::ItemId].
+#[deprecated(note = "Use `ids::ItemId`. This alias will be removed.")]
+pub type ItemId = ids::ItemId;
+/// Former location of [ids::Timestamp].
+#[deprecated(note = "Use `ids::Timestamp`. This alias will be removed.")]
+pub type Timestamp = ids::Timestamp;
 
 /// Prediction error types.
-#[derive(Debug, Fail)]
+#[derive(Debug)]
 pub enum PredictionError {
     /// Failed prediction due to numerical issues.
-    #[fail(display = "Invalid prediction value: non-finite or not a number.")]
     InvalidPredictionValue,
+    /// The item to rank is not among the candidate items.
+    ItemNotInCandidates {
+        /// The missing item.
+        item_id: ids::ItemId,
+    },
+    /// An item id is not smaller than the number of items of the model.
+    ItemIdOutOfRange {
+        /// The offending item id.
+        item_id: ids::ItemId,
+        /// Number of items of the model.
+        num_items: usize,
+    },
+    /// The model cannot represent a user without past interactions.
+    EmptyHistory,
+    /// The model has not been fitted yet.
+    ModelNotFitted,
+    /// The output buffer does not have one entry per item to score.
+    OutputLengthMismatch {
+        /// Number of items to score.
+        num_predictions: usize,
+        /// Length of the output buffer.
+        output_len: usize,
+    },
+    /// The contexts do not have one entry per item of the history, or per
+    /// history when computing several representations at once.
+    ContextLengthMismatch {
+        /// Number of items (or histories).
+        num_items: usize,
+        /// Number of contexts.
+        num_contexts: usize,
+    },
+    /// A context is not smaller than the cardinality of the model's
+    /// context feature.
+    ContextOutOfRange {
+        /// The offending context.
+        context: usize,
+        /// Number of distinct contexts of the model.
+        cardinality: usize,
+    },
+    /// The model cannot update a representation one interaction at a
+    /// time, as it learns several timescales or position weights.
+    IncrementalUpdateUnsupported,
+}
+
+impl fmt::Display for PredictionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PredictionError::InvalidPredictionValue => {
+                write!(f, "Invalid prediction value: non-finite or not a number.")
+            }
+            PredictionError::ItemNotInCandidates { item_id } => {
+                write!(f, "Item {} is not among the candidate items.", item_id)
+            }
+            PredictionError::ItemIdOutOfRange { item_id, num_items } => write!(
+                f,
+                "Item {} is out of range for a model of {} items.",
+                item_id, num_items
+            ),
+            PredictionError::EmptyHistory => {
+                write!(f, "The model cannot represent an empty history.")
+            }
+            PredictionError::ModelNotFitted => write!(f, "The model has not been fitted."),
+            PredictionError::OutputLengthMismatch {
+                num_predictions,
+                output_len,
+            } => write!(
+                f,
+                "Output buffer of length {} cannot hold {} predictions.",
+                output_len, num_predictions
+            ),
+            PredictionError::ContextLengthMismatch {
+                num_items,
+                num_contexts,
+            } => write!(
+                f,
+                "{} contexts cannot be aligned with {} items.",
+                num_contexts, num_items
+            ),
+            PredictionError::ContextOutOfRange {
+                context,
+                cardinality,
+            } => write!(
+                f,
+                "Context {} is out of range for a context feature of cardinality {}.",
+                context, cardinality
+            ),
+            PredictionError::IncrementalUpdateUnsupported => write!(
+                f,
+                "The model does not support incremental updates of user representations."
+            ),
+        }
+    }
 }
 
+impl error::Error for PredictionError {}
+
 /// Fitting error types.
-#[derive(Debug, Fail)]
+#[derive(Debug)]
 pub enum FittingError {
     /// No interactions were given.
-    #[fail(display = "No interactions were supplied.")]
     NoInteractions,
+    /// The number of items in the data does not match the model.
+    ItemCountMismatch {
+        /// Number of items the model was built for.
+        model_items: usize,
+        /// Number of items in the data.
+        data_items: usize,
+    },
+    /// The number of users in the data does not match previous fits.
+    UserCountMismatch {
+        /// Number of users the model was previously fitted on.
+        model_users: usize,
+        /// Number of users in the data.
+        data_users: usize,
+    },
+    /// The data has interactions, but none the model can learn from.
+    DegenerateDataset {
+        /// Why the data cannot be fitted.
+        reason: String,
+    },
+    /// An item id in the data is not smaller than the number of items
+    /// of the model.
+    ItemIdOutOfRange {
+        /// The offending item id.
+        item_id: ids::ItemId,
+        /// Number of items of the model.
+        num_items: usize,
+    },
+    /// A hyperparameter has a value the model cannot be fitted with.
+    InvalidHyperparameter {
+        /// Name of the hyperparameter.
+        name: &'static str,
+        /// The offending value.
+        value: String,
+    },
+    /// Fitting was cancelled.
+    Cancelled,
+}
+
+impl fmt::Display for FittingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FittingError::NoInteractions => write!(f, "No interactions were supplied."),
+            FittingError::ItemCountMismatch {
+                model_items,
+                data_items,
+            } => write!(
+                f,
+                "Model was built for {} items, but the data has {} items.",
+                model_items, data_items
+            ),
+            FittingError::UserCountMismatch {
+                model_users,
+                data_users,
+            } => write!(
+                f,
+                "Model was fitted on {} users, but the data has {} users.",
+                model_users, data_users
+            ),
+            FittingError::DegenerateDataset { reason } => {
+                write!(f, "Degenerate dataset: {}.", reason)
+            }
+            FittingError::ItemIdOutOfRange { item_id, num_items } => write!(
+                f,
+                "Item {} is out of range for a model of {} items.",
+                item_id, num_items
+            ),
+            FittingError::InvalidHyperparameter { name, value } => {
+                write!(f, "Invalid value {} for hyperparameter {}.", value, name)
+            }
+            FittingError::Cancelled => write!(f, "Fitting was cancelled."),
+        }
+    }
+}
+
+impl error::Error for FittingError {}
+
+/// Any of the crate's errors, for functions that load data, fit models
+/// and evaluate them in one go.
+#[derive(Debug)]
+pub enum Error {
+    /// Loading a dataset failed.
+    Dataset(DatasetError),
+    /// Fitting a model failed.
+    Fitting(FittingError),
+    /// Computing predictions failed.
+    Prediction(PredictionError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Dataset(err) => err.fmt(f),
+            Error::Fitting(err) => err.fmt(f),
+            Error::Prediction(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Dataset(err) => Some(err),
+            Error::Fitting(err) => Some(err),
+            Error::Prediction(err) => Some(err),
+        }
+    }
+}
+
+impl From<DatasetError> for Error {
+    fn from(err: DatasetError) -> Self {
+        Error::Dataset(err)
+    }
+}
+
+impl From<FittingError> for Error {
+    fn from(err: FittingError) -> Self {
+        Error::Fitting(err)
+    }
+}
+
+impl From<PredictionError> for Error {
+    fn from(err: PredictionError) -> Self {
+        Error::Prediction(err)
+    }
 }
 
 /// Trait describing models that can compute predictions given
@@ -97,13 +334,163 @@ pub trait OnlineRankingModel {
     /// Compute a user representation from past interactions.
     fn user_representation(
         &self,
-        item_ids: &[ItemId],
+        item_ids: &[ids::ItemId],
     ) -> Result<Self::UserRepresentation, PredictionError>;
+    /// Compute the representations of several users from their past
+    /// interactions, one per history.
+    ///
+    /// The default implementation calls
+    /// [OnlineRankingModel::user_representation] for each history;
+    /// models may provide a more efficient implementation.
+    fn user_representations(
+        &self,
+        histories: &[&[ids::ItemId]],
+    ) -> Result<Vec<Self::UserRepresentation>, PredictionError> {
+        histories
+            .iter()
+            .map(|item_ids| self.user_representation(item_ids))
+            .collect()
+    }
     /// Given a user representation, rank `item_ids` according
     /// to how likely the user is to interact with them in the future.
     fn predict(
         &self,
         user: &Self::UserRepresentation,
-        item_ids: &[ItemId],
+        item_ids: &[ids::ItemId],
     ) -> Result<Vec<f32>, PredictionError>;
+    /// Given a user representation, write the scores of `item_ids` into
+    /// `out`, which must be of the same length, without allocating.
+    ///
+    /// The default implementation copies the output of
+    /// [OnlineRankingModel::predict] and so does allocate; models may
+    /// provide an allocation-free implementation.
+    fn predict_into(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ids::ItemId],
+        out: &mut [f32],
+    ) -> Result<(), PredictionError> {
+        check_output_len(item_ids, out)?;
+        out.copy_from_slice(&self.predict(user, item_ids)?);
+        Ok(())
+    }
+    /// Given a user representation, rank `item_ids` according to how
+    /// likely the user is to interact with them in the future, returning
+    /// indices into `item_ids`, best first.
+    ///
+    /// Items with equal scores keep their order in `item_ids`, and items
+    /// scoring NaN are ranked last, after negative infinity.
+    fn rank_items(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ids::ItemId],
+    ) -> Result<Vec<usize>, PredictionError> {
+        Ok(rank_scores(&self.predict(user, item_ids)?))
+    }
+    /// Given a user representation, score all items `0..num_items`.
+    ///
+    /// The default implementation delegates to [OnlineRankingModel::predict];
+    /// models may provide a more efficient implementation.
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        let item_ids: Vec<ids::ItemId> = (0..num_items).map(ids::ItemId::from_index).collect();
+        self.predict(user, &item_ids)
+    }
+}
+
+/// Check that `out` has one entry per item in `item_ids`.
+pub(crate) fn check_output_len(
+    item_ids: &[ids::ItemId],
+    out: &[f32],
+) -> Result<(), PredictionError> {
+    if item_ids.len() == out.len() {
+        Ok(())
+    } else {
+        Err(PredictionError::OutputLengthMismatch {
+            num_predictions: item_ids.len(),
+            output_len: out.len(),
+        })
+    }
+}
+
+/// Return the indices of `scores` from highest to lowest score. The sort
+/// is stable, and NaN scores are ranked last.
+pub(crate) fn rank_scores(scores: &[f32]) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..scores.len()).collect();
+
+    indices.sort_by(|&x, &y| match (scores[x].is_nan(), scores[y].is_nan()) {
+        (false, false) => scores[y]
+            .partial_cmp(&scores[x])
+            .expect("Scores are not NaN."),
+        (is_nan_x, is_nan_y) => is_nan_x.cmp(&is_nan_y),
+    });
+
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::{items, ItemId};
+
+    #[derive(Debug)]
+    struct FixedScores(Vec<f32>);
+
+    impl OnlineRankingModel for FixedScores {
+        type UserRepresentation = ();
+
+        fn user_representation(&self, _item_ids: &[ItemId]) -> Result<(), PredictionError> {
+            Ok(())
+        }
+
+        fn predict(&self, _user: &(), item_ids: &[ItemId]) -> Result<Vec<f32>, PredictionError> {
+            Ok(item_ids
+                .iter()
+                .map(|&item_id| self.0[item_id.index()])
+                .collect())
+        }
+    }
+
+    #[test]
+    fn rank_items() {
+        let model = FixedScores(vec![0.5, 2.0, -1.0, 0.0]);
+        assert_eq!(
+            model.rank_items(&(), &items(&[0, 1, 2, 3])).unwrap(),
+            vec![1, 0, 3, 2]
+        );
+        assert_eq!(model.rank_items(&(), &items(&[2, 3])).unwrap(), vec![1, 0]);
+        assert!(model.rank_items(&(), &items(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rank_items_ties() {
+        // Ties keep the order of the candidates, whichever it is.
+        let model = FixedScores(vec![1.0, 0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(
+            model.rank_items(&(), &items(&[0, 1, 2, 3, 4])).unwrap(),
+            vec![0, 2, 4, 1, 3]
+        );
+        assert_eq!(
+            model.rank_items(&(), &items(&[4, 3, 2, 1, 0])).unwrap(),
+            vec![0, 2, 4, 1, 3]
+        );
+        assert_eq!(model.rank_items(&(), &items(&[2, 2])).unwrap(), vec![0, 1]);
+    }
+
+    #[test]
+    fn rank_items_nan() {
+        let nan = std::f32::NAN;
+        let neg_inf = std::f32::NEG_INFINITY;
+        let model = FixedScores(vec![nan, neg_inf, 1.0, nan, std::f32::INFINITY]);
+
+        // NaN ranks after negative infinity, in candidate order.
+        assert_eq!(
+            model.rank_items(&(), &items(&[0, 1, 2, 3, 4])).unwrap(),
+            vec![4, 2, 1, 0, 3]
+        );
+        assert_eq!(model.rank_items(&(), &items(&[3, 0])).unwrap(), vec![0, 1]);
+    }
 }
@@ -0,0 +1,117 @@
+//! Logging macros that forward to the `log` crate when the `logging`
+//! feature is enabled, and compile to nothing otherwise.
+//!
+//! Records are emitted under the crate's module paths: `info` once per
+//! epoch of each fit, `warn` on problems that do not stop a fit, and
+//! `debug` per minibatch. Without an installed logger, `log` discards
+//! them.
+
+#[cfg(feature = "logging")]
+macro_rules! log_info {
+    ($($arg:tt)*) => { ::log::info!($($arg)*) };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { ::log::warn!($($arg)*) };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { ::log::debug!($($arg)*) };
+}
+
+// The disabled macros still type-check their arguments, so that values
+// computed only for logging do not trigger unused warnings.
+
+#[cfg(not(feature = "logging"))]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(all(test, feature = "logging", feature = "training"))]
+mod tests {
+    use std::sync::Mutex;
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    use crate::data::synthetic_interactions;
+    use crate::models::ewma;
+
+    struct CapturingLogger {
+        records: Mutex<Vec<(Level, String)>>,
+    }
+
+    impl Log for CapturingLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            if record.target().starts_with("recommenders") {
+                self.records
+                    .lock()
+                    .unwrap()
+                    .push((record.level(), record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CapturingLogger = CapturingLogger {
+        records: Mutex::new(Vec::new()),
+    };
+
+    #[test]
+    fn fit_emits_records() {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(LevelFilter::Debug);
+
+        let data = synthetic_interactions(10, 12, 8).to_compressed();
+        let mut model = ewma::Hyperparameters::new(12, 8)
+            .num_epochs(3)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+
+        let records = LOGGER.records.lock().unwrap();
+        let epochs: Vec<_> = records
+            .iter()
+            .filter(|(level, message)| *level == Level::Info && message.starts_with("Epoch "))
+            .collect();
+
+        // Other tests may log concurrently, but this fit logs at least its
+        // three epochs.
+        assert!(epochs.len() >= 3);
+        assert!(epochs.iter().all(|(_, message)| message.contains("loss")
+            && message.contains("learning rate")
+            && message.contains("examples/s")));
+        assert!(records
+            .iter()
+            .any(|(level, message)| *level == Level::Debug && message.starts_with("Step ")));
+    }
+}
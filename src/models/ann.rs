@@ -0,0 +1,613 @@
+//! Approximate top-k retrieval over item embeddings.
+//!
+//! Scoring the full catalog is linear in the number of items. For large
+//! catalogs, [AnnIndex] instead searches a hierarchical navigable small
+//! world (HNSW) graph over the items' scoring vectors, visiting only a
+//! small fraction of them per query.
+//!
+//! The models score items by inner products, which is not a metric. The
+//! index uses the standard reduction of maximum inner product search to
+//! nearest neighbour search: every item vector `x` is extended with
+//! `sqrt(M^2 - |x|^2)`, where `M` is the largest norm, and every query
+//! `q` with a zero, so that `|q' - x'|^2 = |q|^2 + M^2 - 2 q.x` and the
+//! nearest items are those with the largest inner products.
+//!
+//! [AnnModel] wraps any model implementing [ItemEmbeddings] whose user
+//! representations implement [AsVector], adding approximate
+//! [AnnModel::predict_top_k] to its exact predictions.
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+
+use ndarray::Array2;
+use rand;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use serde::{Deserialize, Serialize};
+
+use super::{top_k, AsVector, ItemEmbeddings};
+use crate::ids::ItemId;
+use crate::rng::SeedSequence;
+use crate::{OnlineRankingModel, PredictionError};
+
+/// Parameters of the HNSW graph.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnnParameters {
+    max_connections: usize,
+    ef_construction: usize,
+    ef_search: usize,
+    rng: XorShiftRng,
+}
+
+impl AnnParameters {
+    /// Build new parameters.
+    pub fn new() -> Self {
+        AnnParameters {
+            max_connections: 16,
+            ef_construction: 100,
+            ef_search: 64,
+            rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
+        }
+    }
+
+    /// Set the number of neighbours of each item in the upper layers of
+    /// the graph. The bottom layer has twice as many. More connections
+    /// improve recall at the cost of memory and query time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_connections` is less than 2.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        assert!(max_connections >= 2, "Need at least 2 connections.");
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Set the number of candidates considered when inserting an item.
+    pub fn ef_construction(mut self, ef_construction: usize) -> Self {
+        self.ef_construction = ef_construction;
+        self
+    }
+
+    /// Set the number of candidates considered in queries. It is
+    /// raised to `k` plus the number of excluded items if lower.
+    pub fn ef_search(mut self, ef_search: usize) -> Self {
+        self.ef_search = ef_search;
+        self
+    }
+
+    /// Set the random number generator.
+    pub fn rng(mut self, rng: XorShiftRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    /// Set the random number generator from seed.
+    pub fn from_seed(mut self, seed: [u8; 16]) -> Self {
+        self.rng = XorShiftRng::from_seed(seed);
+        self
+    }
+
+    /// Set the random number generator to the one named `"ann"` in
+    /// `seeds`, so that a single seed pins the whole experiment.
+    pub fn seed_sequence(mut self, seeds: &SeedSequence) -> Self {
+        self.rng = seeds.derive("ann");
+        self
+    }
+}
+
+impl Default for AnnParameters {
+    fn default() -> Self {
+        AnnParameters::new()
+    }
+}
+
+/// An item at some distance from a query, ordered by distance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Candidate {
+    distance: f32,
+    item_id: ItemId,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .partial_cmp(&other.distance)
+            .unwrap_or(Ordering::Equal)
+            .then(self.item_id.cmp(&other.item_id))
+    }
+}
+
+/// An HNSW index for maximum inner product search over item vectors.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnnIndex {
+    num_items: usize,
+    dim: usize,
+    /// Item vectors extended with one coordinate, row by row.
+    vectors: Vec<f32>,
+    /// For every item, its neighbours in each layer it belongs to.
+    neighbours: Vec<Vec<Vec<ItemId>>>,
+    entry_point: Option<ItemId>,
+    max_connections: usize,
+    ef_search: usize,
+}
+
+impl AnnIndex {
+    /// Build an index over the rows of `embeddings`.
+    pub fn build(embeddings: &Array2<f32>, params: AnnParameters) -> AnnIndex {
+        let (num_items, dim) = embeddings.dim();
+        let AnnParameters {
+            max_connections,
+            ef_construction,
+            ef_search,
+            mut rng,
+        } = params;
+
+        let squared_norms: Vec<f32> = embeddings
+            .outer_iter()
+            .map(|row| row.iter().map(|x| x * x).sum())
+            .collect();
+        let max_squared_norm = squared_norms.iter().cloned().fold(0.0, f32::max);
+
+        let mut vectors = Vec::with_capacity(num_items * (dim + 1));
+        for (row, squared_norm) in embeddings.outer_iter().zip(&squared_norms) {
+            vectors.extend(row.iter());
+            vectors.push((max_squared_norm - squared_norm).max(0.0).sqrt());
+        }
+
+        let mut index = AnnIndex {
+            num_items,
+            dim: dim + 1,
+            vectors,
+            neighbours: Vec::with_capacity(num_items),
+            entry_point: None,
+            max_connections,
+            ef_search,
+        };
+
+        let level_multiplier = 1.0 / (max_connections as f64).ln();
+        for item_id in 0..num_items {
+            let uniform: f64 = rng.gen_range(std::f64::EPSILON, 1.0);
+            let level = (-uniform.ln() * level_multiplier).floor() as usize;
+            index.insert(ItemId::from_index(item_id), level, ef_construction);
+        }
+
+        index
+    }
+
+    /// Number of indexed items.
+    pub fn num_items(&self) -> usize {
+        self.num_items
+    }
+
+    /// Return the (approximately) `k` items with the largest inner product
+    /// with `user_vector`, other than those in `exclude`, as
+    /// `(item_id, score)` pairs, best first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `user_vector` does not have the dimension of the item vectors.
+    pub fn top_k(&self, user_vector: &[f32], k: usize, exclude: &[ItemId]) -> Vec<(ItemId, f32)> {
+        assert_eq!(
+            user_vector.len() + 1,
+            self.dim,
+            "User vector has the wrong dimension."
+        );
+
+        let mut entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => return Vec::new(),
+        };
+
+        let mut query = user_vector.to_vec();
+        query.push(0.0);
+
+        for level in (1..self.neighbours[entry_point.index()].len()).rev() {
+            entry_point = self.search_layer(&query, &[entry_point], 1, level)[0].item_id;
+        }
+
+        let exclude: HashSet<_> = exclude.iter().collect();
+        let ef = self.ef_search.max(k + exclude.len());
+
+        let scored = self
+            .search_layer(&query, &[entry_point], ef, 0)
+            .into_iter()
+            .filter(|candidate| !exclude.contains(&candidate.item_id))
+            .map(|candidate| {
+                let score = self.vector(candidate.item_id)[..user_vector.len()]
+                    .iter()
+                    .zip(user_vector)
+                    .map(|(x, y)| x * y)
+                    .sum();
+                (candidate.item_id, score)
+            })
+            .collect();
+
+        top_k(scored, k)
+    }
+
+    fn vector(&self, item_id: ItemId) -> &[f32] {
+        let row = item_id.index();
+        &self.vectors[row * self.dim..(row + 1) * self.dim]
+    }
+
+    fn distance(&self, query: &[f32], item_id: ItemId) -> f32 {
+        query
+            .iter()
+            .zip(self.vector(item_id))
+            .map(|(x, y)| (x - y) * (x - y))
+            .sum()
+    }
+
+    fn max_neighbours(&self, level: usize) -> usize {
+        if level == 0 {
+            2 * self.max_connections
+        } else {
+            self.max_connections
+        }
+    }
+
+    /// Greedy best-first search of one layer, returning up to `ef`
+    /// nearest items found, nearest first.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[ItemId],
+        ef: usize,
+        level: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<ItemId> = entry_points.iter().cloned().collect();
+        // Min-heap of items to expand, and max-heap of the nearest found.
+        let mut candidates = BinaryHeap::new();
+        let mut nearest = BinaryHeap::new();
+
+        for &item_id in entry_points {
+            let candidate = Candidate {
+                distance: self.distance(query, item_id),
+                item_id,
+            };
+            candidates.push(std::cmp::Reverse(candidate));
+            nearest.push(candidate);
+        }
+
+        while let Some(std::cmp::Reverse(candidate)) = candidates.pop() {
+            let furthest = nearest
+                .peek()
+                .map_or(std::f32::INFINITY, |x: &Candidate| x.distance);
+            if candidate.distance > furthest && nearest.len() >= ef {
+                break;
+            }
+
+            for &neighbour in &self.neighbours[candidate.item_id.index()][level] {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+
+                let distance = self.distance(query, neighbour);
+                let furthest = nearest.peek().map_or(std::f32::INFINITY, |x| x.distance);
+
+                if nearest.len() < ef || distance < furthest {
+                    let neighbour = Candidate {
+                        distance,
+                        item_id: neighbour,
+                    };
+                    candidates.push(std::cmp::Reverse(neighbour));
+                    nearest.push(neighbour);
+
+                    if nearest.len() > ef {
+                        nearest.pop();
+                    }
+                }
+            }
+        }
+
+        nearest.into_sorted_vec()
+    }
+
+    fn insert(&mut self, item_id: ItemId, level: usize, ef_construction: usize) {
+        self.neighbours.push(vec![Vec::new(); level + 1]);
+
+        let entry_point = match self.entry_point {
+            Some(entry_point) => entry_point,
+            None => {
+                self.entry_point = Some(item_id);
+                return;
+            }
+        };
+
+        let query = self.vector(item_id).to_vec();
+        let top_level = self.neighbours[entry_point.index()].len() - 1;
+
+        let mut entry_points = vec![entry_point];
+        for search_level in (level + 1..=top_level).rev() {
+            entry_points =
+                vec![self.search_layer(&query, &entry_points, 1, search_level)[0].item_id];
+        }
+
+        for search_level in (0..=level.min(top_level)).rev() {
+            let nearest = self.search_layer(&query, &entry_points, ef_construction, search_level);
+            let max_neighbours = self.max_neighbours(search_level);
+
+            let neighbours = self.select_neighbours(&nearest, max_neighbours);
+
+            for &neighbour in &neighbours {
+                self.neighbours[neighbour.index()][search_level].push(item_id);
+
+                if self.neighbours[neighbour.index()][search_level].len() > max_neighbours {
+                    self.prune(neighbour, search_level);
+                }
+            }
+
+            self.neighbours[item_id.index()][search_level] = neighbours;
+            entry_points = nearest.iter().map(|candidate| candidate.item_id).collect();
+        }
+
+        if level > top_level {
+            self.entry_point = Some(item_id);
+        }
+    }
+
+    /// Choose up to `max_neighbours` of the `candidates`, nearest first.
+    ///
+    /// A candidate is preferred if it is closer to the item being linked
+    /// than to any neighbour chosen so far, which spreads the links over
+    /// different directions and keeps clustered regions connected to the
+    /// rest of the graph. Remaining slots are filled with the nearest
+    /// of the other candidates.
+    fn select_neighbours(&self, candidates: &[Candidate], max_neighbours: usize) -> Vec<ItemId> {
+        let mut selected: Vec<ItemId> = Vec::with_capacity(max_neighbours);
+        let mut skipped = Vec::new();
+
+        for candidate in candidates {
+            if selected.len() >= max_neighbours {
+                break;
+            }
+
+            let vector = self.vector(candidate.item_id);
+            if selected
+                .iter()
+                .all(|&neighbour| self.distance(vector, neighbour) > candidate.distance)
+            {
+                selected.push(candidate.item_id);
+            } else {
+                skipped.push(candidate.item_id);
+            }
+        }
+
+        let num_missing = max_neighbours.saturating_sub(selected.len());
+        selected.extend(skipped.into_iter().take(num_missing));
+
+        selected
+    }
+
+    /// Reselect the neighbours of `item_id` in `level` once it has too many.
+    fn prune(&mut self, item_id: ItemId, level: usize) {
+        let vector = self.vector(item_id).to_vec();
+
+        let mut candidates: Vec<_> = self.neighbours[item_id.index()][level]
+            .iter()
+            .map(|&neighbour| Candidate {
+                distance: self.distance(&vector, neighbour),
+                item_id: neighbour,
+            })
+            .collect();
+        candidates.sort();
+
+        self.neighbours[item_id.index()][level] =
+            self.select_neighbours(&candidates, self.max_neighbours(level));
+    }
+}
+
+/// Wraps a model with an [AnnIndex] over its item embeddings for fast
+/// approximate top-k predictions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnnModel<T> {
+    model: T,
+    index: AnnIndex,
+}
+
+impl<T> AnnModel<T>
+where
+    T: OnlineRankingModel + ItemEmbeddings,
+    T::UserRepresentation: AsVector,
+{
+    /// Index the item embeddings of `model`. The index is not updated
+    /// if the model changes.
+    pub fn new(model: T, params: AnnParameters) -> Self {
+        let index = AnnIndex::build(&model.item_embeddings(), params);
+
+        AnnModel { model, index }
+    }
+
+    /// Return the (approximately) `k` highest-scoring items for `user`
+    /// as `(item_id, score)` pairs, best first.
+    pub fn predict_top_k(
+        &self,
+        user: &T::UserRepresentation,
+        k: usize,
+        exclude: &[ItemId],
+    ) -> Result<Vec<(ItemId, f32)>, PredictionError> {
+        let top_k = self.index.top_k(&user.as_vector(), k, exclude);
+
+        if top_k.iter().all(|&(_, score)| score.is_finite()) {
+            Ok(top_k)
+        } else {
+            Err(PredictionError::InvalidPredictionValue)
+        }
+    }
+
+    /// Return the wrapped model.
+    pub fn model(&self) -> &T {
+        &self.model
+    }
+
+    /// Return the index.
+    pub fn index(&self) -> &AnnIndex {
+        &self.index
+    }
+}
+
+impl<T: OnlineRankingModel> OnlineRankingModel for AnnModel<T> {
+    type UserRepresentation = T::UserRepresentation;
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        self.model.user_representation(item_ids)
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.model.predict(user, item_ids)
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.model.predict_all_items(user, num_items)
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use rand::distributions::{Distribution, Normal};
+
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::ids::{items, Timestamp, UserId};
+    use crate::models::ewma;
+
+    fn random_embeddings(num_items: usize, dim: usize, rng: &mut XorShiftRng) -> Array2<f32> {
+        let normal = Normal::new(0.0, 1.0);
+        Array2::from_shape_fn((num_items, dim), |_| normal.sample(rng) as f32)
+    }
+
+    fn exact_top_k(embeddings: &Array2<f32>, query: &[f32], k: usize) -> Vec<(ItemId, f32)> {
+        let scored = embeddings
+            .outer_iter()
+            .enumerate()
+            .map(|(item_id, row)| {
+                let score = row.iter().zip(query).map(|(x, y)| x * y).sum();
+                (ItemId::from_index(item_id), score)
+            })
+            .collect();
+
+        top_k(scored, k)
+    }
+
+    #[test]
+    fn recall_on_random_embeddings() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let dim = 16;
+        let embeddings = random_embeddings(5000, dim, &mut rng);
+        let index = AnnIndex::build(
+            &embeddings,
+            AnnParameters::new()
+                .max_connections(16)
+                .ef_construction(100)
+                .ef_search(100)
+                .from_seed([42; 16]),
+        );
+
+        let queries = random_embeddings(100, dim, &mut rng);
+        let mut hits = 0;
+        for query in queries.outer_iter() {
+            let query = query.to_vec();
+            let exact: HashSet<_> = exact_top_k(&embeddings, &query, 10)
+                .into_iter()
+                .map(|(item_id, _)| item_id)
+                .collect();
+
+            let approximate = index.top_k(&query, 10, &[]);
+            assert_eq!(approximate.len(), 10);
+            assert!(approximate.windows(2).all(|x| x[0].1 >= x[1].1));
+
+            hits += approximate
+                .iter()
+                .filter(|(item_id, _)| exact.contains(item_id))
+                .count();
+        }
+
+        let recall = hits as f32 / (100 * 10) as f32;
+        println!("Recall@10: {}", recall);
+        assert!(recall >= 0.9);
+    }
+
+    #[test]
+    fn excluded_items() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let embeddings = random_embeddings(200, 8, &mut rng);
+        let index = AnnIndex::build(&embeddings, AnnParameters::new().from_seed([42; 16]));
+
+        let query = embeddings.row(3).to_vec();
+        let top = index.top_k(&query, 5, &[]);
+        let excluded: Vec<_> = top.iter().take(2).map(|&(item_id, _)| item_id).collect();
+
+        let filtered = index.top_k(&query, 5, &excluded);
+        assert_eq!(filtered.len(), 5);
+        assert!(filtered
+            .iter()
+            .all(|(item_id, _)| !excluded.contains(item_id)));
+        assert_eq!(&filtered[..3], &top[2..]);
+
+        let empty = AnnIndex::build(&Array2::zeros((0, 8)), AnnParameters::new());
+        assert!(empty.top_k(&query, 5, &[]).is_empty());
+    }
+
+    #[test]
+    fn wraps_model() {
+        let data = Interactions::from(
+            (0..500)
+                .map(|item_id| {
+                    Interaction::new(
+                        UserId(item_id / 10),
+                        ItemId(item_id),
+                        Timestamp(item_id as i64),
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+        .to_compressed();
+        let mut model = ewma::Hyperparameters::new(500, 10)
+            .embedding_dim(8)
+            .bias_term(false)
+            .num_epochs(0)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+        let model = AnnModel::new(model, AnnParameters::new().from_seed([42; 16]));
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let approximate = model.predict_top_k(&user, 5, &[]).unwrap();
+
+        // Scores match the model's own, including the item biases.
+        let item_ids: Vec<_> = approximate.iter().map(|&(item_id, _)| item_id).collect();
+        let scores = model.predict(&user, &item_ids).unwrap();
+        assert!(approximate
+            .iter()
+            .zip(&scores)
+            .all(|(&(_, x), y)| (x - y).abs() < 1e-4));
+
+        let predictions = model.predict_all_items(&user, 500).unwrap();
+        let scored = predictions
+            .into_iter()
+            .enumerate()
+            .map(|(item_id, score)| (ItemId::from_index(item_id), score))
+            .collect();
+        let exact = top_k(scored, 5);
+        assert_eq!(exact[0].0, approximate[0].0);
+    }
+}
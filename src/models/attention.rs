@@ -0,0 +1,892 @@
+//! Module for self-attention (SASRec-style) models.
+//!
+//! The representation of a user at step `t` is computed by blocks of causal
+//! multi-head self-attention over the items at steps `0..=t`, each followed
+//! by a position-wise feed-forward layer. Learned position embeddings are
+//! added to the item embeddings, so that attention can depend on how far
+//! back each item is as well as on what it is.
+//!
+//! Both sub-layers of a block are residual, and dropout is applied to the
+//! input embeddings and to the output of every sub-layer. There is no
+//! layer normalization.
+//!
+//! Every step attends to all earlier steps, so the cost of a training step
+//! grows quadratically with the maximum sequence length.
+use std::sync::Arc;
+
+use rand;
+use rand::distributions::{Distribution, Normal};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use serde::{Deserialize, Serialize};
+
+use wyrm;
+use wyrm::optim::Optimizers;
+use wyrm::{Arr, BoxedNode, Variable};
+
+use super::metadata::ModelMetadata;
+use super::sampling::MinibatchSampler;
+#[cfg(feature = "training")]
+use super::sequence_model::fit_sequence_model;
+use super::sequence_model::{
+    predict_candidates, score_all_items, SequenceModel, SequenceModelParameters,
+};
+use super::{ImplicitUser, Loss, Optimizer, Parallelism};
+use crate::config;
+#[cfg(feature = "training")]
+use crate::data::CompressedInteractions;
+use crate::ids::ItemId;
+use crate::rng::SeedSequence;
+#[cfg(feature = "training")]
+use crate::FittingError;
+use crate::{OnlineRankingModel, PredictionError};
+
+fn embedding_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
+    let normal = Normal::new(0.0, 1.0 / cols as f64);
+    Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
+}
+
+fn dense_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
+    let normal = Normal::new(0.0, (2.0 / (rows + cols) as f64).sqrt());
+    Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
+}
+
+fn parameter(value: Arr) -> Arc<wyrm::HogwildParameter> {
+    Arc::new(wyrm::HogwildParameter::new(value))
+}
+
+fn deep_clone(parameter: &Arc<wyrm::HogwildParameter>) -> Arc<wyrm::HogwildParameter> {
+    Arc::new(parameter.as_ref().clone())
+}
+
+fn shared(parameter: &Arc<wyrm::HogwildParameter>) -> Variable<wyrm::ParameterNode> {
+    wyrm::ParameterNode::shared(parameter.clone())
+}
+
+/// Sum a non-empty sequence of nodes.
+fn sum<I: IntoIterator<Item = Variable<BoxedNode>>>(terms: I) -> Variable<BoxedNode> {
+    let mut terms = terms.into_iter();
+    let first = terms.next().expect("Cannot sum zero terms.");
+
+    terms.fold(first, |total, term| (total + term).boxed())
+}
+
+/// Hyperparameters for the [ImplicitAttentionModel].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hyperparameters {
+    num_items: usize,
+    max_sequence_length: usize,
+    item_embedding_dim: usize,
+    num_heads: usize,
+    num_blocks: usize,
+    dropout: f32,
+    learning_rate: f32,
+    l2_penalty: f32,
+    loss: Loss,
+    optimizer: Optimizer,
+    parallelism: Parallelism,
+    rng: XorShiftRng,
+    num_threads: usize,
+    num_epochs: usize,
+    #[serde(default)]
+    accumulate_f64: bool,
+    #[serde(default)]
+    warmup_steps: usize,
+    #[serde(skip)]
+    negative_sampler: Option<Arc<dyn MinibatchSampler>>,
+}
+
+impl Hyperparameters {
+    /// Build new hyperparameters.
+    pub fn new(num_items: usize, max_sequence_length: usize) -> Self {
+        Hyperparameters {
+            num_items,
+            max_sequence_length,
+            item_embedding_dim: 16,
+            num_heads: 1,
+            num_blocks: 1,
+            dropout: 0.0,
+            learning_rate: 0.01,
+            l2_penalty: 0.0,
+            loss: Loss::BPR,
+            optimizer: Optimizer::Adam,
+            parallelism: Parallelism::Synchronous,
+            rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
+            num_threads: config::num_threads(),
+            num_epochs: 10,
+            accumulate_f64: false,
+            warmup_steps: 0,
+            negative_sampler: None,
+        }
+    }
+
+    /// Set the learning rate.
+    pub fn learning_rate(mut self, learning_rate: f32) -> Self {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    /// Set the l2 penalty.
+    pub fn l2_penalty(mut self, l2_penalty: f32) -> Self {
+        self.l2_penalty = l2_penalty;
+        self
+    }
+
+    /// Set the embedding dimensionality. It must be divisible by
+    /// the number of attention heads.
+    pub fn embedding_dim(mut self, embedding_dim: usize) -> Self {
+        self.item_embedding_dim = embedding_dim;
+        self
+    }
+
+    /// Set the number of attention heads in each block. Each head
+    /// attends over `embedding_dim / num_heads` dimensions. Defaults to 1.
+    pub fn num_heads(mut self, num_heads: usize) -> Self {
+        self.num_heads = num_heads;
+        self
+    }
+
+    /// Set the number of stacked attention blocks. Defaults to 1.
+    pub fn num_blocks(mut self, num_blocks: usize) -> Self {
+        self.num_blocks = num_blocks;
+        self
+    }
+
+    /// Set the probability of zeroing each input embedding and sub-layer
+    /// output entry during training. Defaults to 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dropout` is not in `[0, 1)`.
+    pub fn dropout(mut self, dropout: f32) -> Self {
+        assert!((0.0..1.0).contains(&dropout), "Dropout must be in [0, 1).");
+        self.dropout = dropout;
+        self
+    }
+
+    /// Set the number of epochs to run per each `fit` call.
+    pub fn num_epochs(mut self, num_epochs: usize) -> Self {
+        self.num_epochs = num_epochs;
+        self
+    }
+
+    /// Set the number of minibatches over which the learning rate is
+    /// linearly ramped up from zero to its configured value. Warmups longer
+    /// than 100 steps ramp up in 100 equal increments.
+    pub fn warmup_steps(mut self, warmup_steps: usize) -> Self {
+        self.warmup_steps = warmup_steps;
+        self
+    }
+
+    /// Set the loss function.
+    pub fn loss(mut self, loss: Loss) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    /// Set the strategy used to sample negative items during training.
+    /// Defaults to sampling uniformly from all items.
+    ///
+    /// The sampler is not serialized: deserialized models fall back
+    /// to uniform sampling.
+    pub fn negative_sampler(mut self, sampler: Box<dyn MinibatchSampler>) -> Self {
+        self.negative_sampler = Some(Arc::from(sampler));
+        self
+    }
+
+    /// Set number of threads to be used.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// Accumulate training losses in `f64` rather than `f32`. Defaults to
+    /// false.
+    ///
+    /// Summed in `f32`, many small losses lose precision, so that the
+    /// reported loss depends on summation order. Parameters stay `f32`
+    /// either way.
+    pub fn accumulate_f64(mut self, enabled: bool) -> Self {
+        self.accumulate_f64 = enabled;
+        self
+    }
+
+    /// Set the type of paralellism.
+    pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Set the random number generator.
+    pub fn rng(mut self, rng: XorShiftRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    /// Set the random number generator from seed.
+    pub fn from_seed(mut self, seed: [u8; 16]) -> Self {
+        self.rng = XorShiftRng::from_seed(seed);
+        self
+    }
+
+    /// Set the random number generator to the one named `"attention"` in
+    /// `seeds`, so that a single seed pins the whole experiment.
+    pub fn seed_sequence(mut self, seeds: &SeedSequence) -> Self {
+        self.rng = seeds.derive("attention");
+        self
+    }
+
+    /// Set the optimizer type.
+    pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    fn build_params(mut self) -> Parameters {
+        assert!(self.num_heads > 0, "There must be at least one head.");
+        assert!(
+            self.item_embedding_dim % self.num_heads == 0,
+            "Embedding dimension {} is not divisible by {} heads.",
+            self.item_embedding_dim,
+            self.num_heads
+        );
+
+        let dim = self.item_embedding_dim;
+        let head_dim = dim / self.num_heads;
+
+        let item_embedding = parameter(embedding_init(self.num_items, dim, &mut self.rng));
+        let position_embedding =
+            parameter(embedding_init(self.max_sequence_length, dim, &mut self.rng));
+        let item_biases = parameter(Arr::zeros((self.num_items, 1)));
+
+        let blocks = (0..self.num_blocks)
+            .map(|_| Block::new(dim, head_dim, self.num_heads, &mut self.rng))
+            .collect();
+
+        Parameters {
+            hyper: self,
+            num_users: None,
+            metadata: None,
+            item_embedding,
+            position_embedding,
+            item_biases,
+            blocks,
+        }
+    }
+
+    /// Build a model out of the chosen hyperparameters.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the embedding dimension is not divisible by the
+    /// number of heads.
+    pub fn build(self) -> ImplicitAttentionModel {
+        ImplicitAttentionModel {
+            params: self.build_params(),
+        }
+    }
+}
+
+/// Parameters of a single causal self-attention head.
+#[derive(Debug, Serialize, Deserialize)]
+struct AttentionHead {
+    query: Arc<wyrm::HogwildParameter>,
+    key: Arc<wyrm::HogwildParameter>,
+    value: Arc<wyrm::HogwildParameter>,
+    /// Maps the head's output back to the embedding dimension.
+    output: Arc<wyrm::HogwildParameter>,
+}
+
+impl AttentionHead {
+    fn new<T: Rng>(dim: usize, head_dim: usize, rng: &mut T) -> Self {
+        AttentionHead {
+            query: parameter(dense_init(dim, head_dim, rng)),
+            key: parameter(dense_init(dim, head_dim, rng)),
+            value: parameter(dense_init(dim, head_dim, rng)),
+            output: parameter(dense_init(head_dim, dim, rng)),
+        }
+    }
+
+    /// Attend from every step to itself and all earlier steps.
+    fn forward(&self, inputs: &[Variable<BoxedNode>]) -> Vec<Variable<BoxedNode>> {
+        let (query, key, value, output) = (
+            shared(&self.query),
+            shared(&self.key),
+            shared(&self.value),
+            shared(&self.output),
+        );
+
+        let head_dim = self.query.value().shape()[1];
+        let scale = 1.0 / (head_dim as f32).sqrt();
+        // Row of ones broadcasting an attention weight across a value.
+        let broadcast = wyrm::InputNode::new(Arr::ones((1, head_dim)));
+
+        let queries: Vec<_> = inputs.iter().map(|x| x.dot(&query).boxed()).collect();
+        let keys: Vec<_> = inputs.iter().map(|x| x.dot(&key).boxed()).collect();
+        let values: Vec<_> = inputs.iter().map(|x| x.dot(&value).boxed()).collect();
+
+        queries
+            .iter()
+            .enumerate()
+            .map(|(idx, query)| {
+                let scores: Vec<_> = keys[..=idx]
+                    .iter()
+                    .map(|key| (scale * query.vector_dot(key)).boxed())
+                    .collect();
+
+                // Softmax weights are exp(score - log(sum(exp(scores)))). The
+                // scores are shifted by their maximum, using
+                // max(a, b) = a + relu(b - a), so that exp cannot overflow.
+                let max_score = scores[1..].iter().fold(scores[0].clone(), |max, score| {
+                    (max.clone() + (score.clone() - max).relu()).boxed()
+                });
+                let log_normalizer = (max_score.clone()
+                    + sum(scores
+                        .iter()
+                        .map(|score| (score.clone() - max_score.clone()).exp().boxed()))
+                    .ln())
+                .boxed();
+
+                let attended = sum(izip!(scores, &values[..=idx]).map(|(score, value)| {
+                    ((score - log_normalizer.clone()).exp().dot(&broadcast) * value.clone()).boxed()
+                }));
+
+                attended.dot(&output).boxed()
+            })
+            .collect()
+    }
+}
+
+impl Clone for AttentionHead {
+    fn clone(&self) -> Self {
+        AttentionHead {
+            query: deep_clone(&self.query),
+            key: deep_clone(&self.key),
+            value: deep_clone(&self.value),
+            output: deep_clone(&self.output),
+        }
+    }
+}
+
+/// A multi-head self-attention layer followed by a feed-forward layer.
+#[derive(Debug, Serialize, Deserialize)]
+struct Block {
+    heads: Vec<AttentionHead>,
+    hidden_weights: Arc<wyrm::HogwildParameter>,
+    hidden_biases: Arc<wyrm::HogwildParameter>,
+    output_weights: Arc<wyrm::HogwildParameter>,
+    output_biases: Arc<wyrm::HogwildParameter>,
+}
+
+impl Block {
+    fn new<T: Rng>(dim: usize, head_dim: usize, num_heads: usize, rng: &mut T) -> Self {
+        Block {
+            heads: (0..num_heads)
+                .map(|_| AttentionHead::new(dim, head_dim, rng))
+                .collect(),
+            hidden_weights: parameter(dense_init(dim, dim, rng)),
+            hidden_biases: parameter(Arr::zeros((1, dim))),
+            output_weights: parameter(dense_init(dim, dim, rng)),
+            output_biases: parameter(Arr::zeros((1, dim))),
+        }
+    }
+
+    /// Run the block over `inputs`, applying `dropout` to the output
+    /// of each sub-layer before its residual connection.
+    fn forward(
+        &self,
+        inputs: &[Variable<BoxedNode>],
+        dropout: &mut dyn FnMut(Variable<BoxedNode>) -> Variable<BoxedNode>,
+    ) -> Vec<Variable<BoxedNode>> {
+        let hidden_weights = shared(&self.hidden_weights);
+        let hidden_biases = shared(&self.hidden_biases);
+        let output_weights = shared(&self.output_weights);
+        let output_biases = shared(&self.output_biases);
+
+        // Summing the projected head outputs is equivalent to projecting
+        // their concatenation.
+        let head_outputs: Vec<_> = self.heads.iter().map(|head| head.forward(inputs)).collect();
+
+        inputs
+            .iter()
+            .enumerate()
+            .map(|(idx, input)| {
+                let attended = sum(head_outputs.iter().map(|outputs| outputs[idx].clone()));
+                let x = (input.clone() + dropout(attended)).boxed();
+
+                let feed_forward = ((x.dot(&hidden_weights) + hidden_biases.clone())
+                    .relu()
+                    .dot(&output_weights)
+                    + output_biases.clone())
+                .boxed();
+
+                (x + dropout(feed_forward)).boxed()
+            })
+            .collect()
+    }
+}
+
+impl Clone for Block {
+    fn clone(&self) -> Self {
+        Block {
+            heads: self.heads.clone(),
+            hidden_weights: deep_clone(&self.hidden_weights),
+            hidden_biases: deep_clone(&self.hidden_biases),
+            output_weights: deep_clone(&self.output_weights),
+            output_biases: deep_clone(&self.output_biases),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Parameters {
+    hyper: Hyperparameters,
+    num_users: Option<usize>,
+    #[serde(default)]
+    metadata: Option<ModelMetadata>,
+    item_embedding: Arc<wyrm::HogwildParameter>,
+    position_embedding: Arc<wyrm::HogwildParameter>,
+    item_biases: Arc<wyrm::HogwildParameter>,
+    blocks: Vec<Block>,
+}
+
+impl Clone for Parameters {
+    fn clone(&self) -> Self {
+        Parameters {
+            hyper: self.hyper.clone(),
+            num_users: self.num_users,
+            metadata: self.metadata.clone(),
+            item_embedding: deep_clone(&self.item_embedding),
+            position_embedding: deep_clone(&self.position_embedding),
+            item_biases: deep_clone(&self.item_biases),
+            blocks: self.blocks.clone(),
+        }
+    }
+}
+
+impl SequenceModelParameters for Parameters {
+    type Output = Model;
+    fn num_items(&self) -> usize {
+        self.hyper.num_items
+    }
+    fn num_users(&mut self) -> &mut Option<usize> {
+        &mut self.num_users
+    }
+    fn metadata(&mut self) -> &mut Option<ModelMetadata> {
+        &mut self.metadata
+    }
+    fn hyperparameters(&self) -> String {
+        serde_json::to_string(&self.hyper).unwrap_or_default()
+    }
+    fn max_sequence_length(&self) -> usize {
+        self.hyper.max_sequence_length
+    }
+    fn num_threads(&self) -> usize {
+        self.hyper.num_threads
+    }
+    fn accumulate_f64(&self) -> bool {
+        self.hyper.accumulate_f64
+    }
+    fn rng(&mut self) -> &mut XorShiftRng {
+        &mut self.hyper.rng
+    }
+    fn negative_sampler(&self) -> Option<&dyn MinibatchSampler> {
+        self.hyper.negative_sampler.as_ref().map(|x| x.as_ref())
+    }
+    fn learning_rate(&self) -> f32 {
+        self.hyper.learning_rate
+    }
+    fn warmup_steps(&self) -> usize {
+        self.hyper.warmup_steps
+    }
+    fn dropout(&self) -> f32 {
+        self.hyper.dropout
+    }
+    fn optimizer(&self, learning_rate: f32) -> Optimizers {
+        match self.hyper.optimizer {
+            Optimizer::Adagrad => Optimizers::Adagrad(
+                wyrm::optim::Adagrad::new()
+                    .learning_rate(learning_rate)
+                    .l2_penalty(self.hyper.l2_penalty),
+            ),
+
+            Optimizer::Adam => Optimizers::Adam(
+                wyrm::optim::Adam::new()
+                    .learning_rate(learning_rate)
+                    .l2_penalty(self.hyper.l2_penalty),
+            ),
+        }
+    }
+    fn parallelism(&self) -> &Parallelism {
+        &self.hyper.parallelism
+    }
+    fn loss(&self) -> &Loss {
+        &self.hyper.loss
+    }
+    fn num_epochs(&self) -> usize {
+        self.hyper.num_epochs
+    }
+    fn build(&self) -> Self::Output {
+        let dim = self.hyper.item_embedding_dim;
+        let max_sequence_length = self.hyper.max_sequence_length;
+
+        let item_embeddings = shared(&self.item_embedding);
+        let position_embeddings = shared(&self.position_embedding);
+        let item_biases = shared(&self.item_biases);
+
+        let inputs: Vec<_> = (0..max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let outputs: Vec<_> = (0..max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let negatives: Vec<_> = (0..max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let positions: Vec<_> = (0..max_sequence_length)
+            .map(|position| wyrm::IndexInputNode::new(&[position; 1]))
+            .collect();
+
+        let use_dropout = self.hyper.dropout > 0.0;
+        let mut dropout_masks = Vec::new();
+        let mut dropout = |x: Variable<BoxedNode>| {
+            if use_dropout {
+                let mask = wyrm::InputNode::new(Arr::ones((1, dim)));
+                dropout_masks.push(mask.clone());
+                (x * mask).boxed()
+            } else {
+                x
+            }
+        };
+
+        let mut hidden: Vec<_> = izip!(&inputs, &positions)
+            .map(|(input, position)| {
+                dropout(
+                    (item_embeddings.index(input) + position_embeddings.index(position)).boxed(),
+                )
+            })
+            .collect();
+
+        for block in &self.blocks {
+            hidden = block.forward(&hidden, &mut dropout);
+        }
+
+        let positive_predictions: Vec<_> = izip!(&hidden, &outputs)
+            .map(|(hidden_state, output)| {
+                hidden_state.vector_dot(&item_embeddings.index(output)) + item_biases.index(output)
+            })
+            .collect();
+        let negative_predictions: Vec<_> = izip!(&hidden, &negatives)
+            .map(|(hidden_state, negative)| {
+                hidden_state.vector_dot(&item_embeddings.index(negative))
+                    + item_biases.index(negative)
+            })
+            .collect();
+
+        let losses: Vec<_> = positive_predictions
+            .into_iter()
+            .zip(negative_predictions.into_iter())
+            .map(|(pos, neg)| match self.hyper.loss {
+                Loss::BPR => (neg - pos).sigmoid().boxed(),
+                Loss::Hinge | Loss::WARP => (1.0 + neg - pos).relu().boxed(),
+            })
+            .collect();
+
+        let mut summed_losses = Vec::with_capacity(losses.len());
+        summed_losses.push(losses[0].clone());
+
+        for loss in &losses[1..] {
+            let loss = (summed_losses.last().unwrap().clone() + loss.clone()).boxed();
+            summed_losses.push(loss);
+        }
+
+        Model {
+            inputs,
+            outputs,
+            negatives,
+            hidden_states: hidden,
+            summed_losses,
+            dropout_masks,
+        }
+    }
+    fn predict_single(&self, user: &[f32], item_idx: usize) -> f32 {
+        let embeddings = self.item_embedding.value();
+        let biases = self.item_biases.value();
+
+        let embedding = embeddings.row(item_idx);
+        let bias = biases[(item_idx, 0)];
+        let dot = wyrm::simd_dot(user, embedding.as_slice().unwrap());
+
+        bias + dot
+    }
+    fn predict_all(&self, user: &[f32], num_items: usize) -> Vec<f32> {
+        score_all_items(
+            self.item_embedding.value(),
+            self.item_biases.value(),
+            user,
+            num_items,
+        )
+    }
+}
+
+struct Model {
+    inputs: Vec<Variable<wyrm::IndexInputNode>>,
+    outputs: Vec<Variable<wyrm::IndexInputNode>>,
+    negatives: Vec<Variable<wyrm::IndexInputNode>>,
+    hidden_states: Vec<Variable<BoxedNode>>,
+    summed_losses: Vec<Variable<BoxedNode>>,
+    dropout_masks: Vec<Variable<wyrm::InputNode>>,
+}
+
+impl SequenceModel for Model {
+    fn state(
+        &self,
+    ) -> (
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<BoxedNode>],
+    ) {
+        (
+            &self.inputs,
+            &self.outputs,
+            &self.negatives,
+            &self.hidden_states,
+        )
+    }
+    fn losses(&mut self) -> &mut [Variable<BoxedNode>] {
+        &mut self.summed_losses
+    }
+    fn hidden_states(&mut self) -> &mut [Variable<BoxedNode>] {
+        &mut self.hidden_states
+    }
+    fn contexts(&self) -> &[Variable<wyrm::IndexInputNode>] {
+        &[]
+    }
+    fn time_deltas(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn dropout_masks(&self) -> &[Variable<wyrm::InputNode>] {
+        &self.dropout_masks
+    }
+    fn weights(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn set_users(&self, _user_ids: &[usize]) {}
+}
+
+/// A self-attention sequence model for implicit feedback.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImplicitAttentionModel {
+    params: Parameters,
+}
+
+impl ImplicitAttentionModel {
+    /// Fit the model.
+    ///
+    /// Returns the loss value.
+    #[cfg(feature = "training")]
+    pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<f32, FittingError> {
+        fit_sequence_model(interactions, &mut self.params)
+    }
+
+    /// Return the record of the last successful fit, or `None` if the
+    /// model has not been fitted. See [ModelMetadata].
+    pub fn metadata(&self) -> Option<&ModelMetadata> {
+        self.params.metadata.as_ref()
+    }
+
+    /// Return the record of the last successful fit mutably, for example
+    /// to add tags.
+    pub fn metadata_mut(&mut self) -> Option<&mut ModelMetadata> {
+        self.params.metadata.as_mut()
+    }
+
+    /// Score only the `candidates` and return the `k` highest-scoring
+    /// `(item_id, score)` pairs, best first.
+    pub fn predict_candidates(
+        &self,
+        user: &ImplicitUser,
+        candidates: &[ItemId],
+        k: usize,
+    ) -> Result<Vec<(ItemId, f32)>, PredictionError> {
+        predict_candidates(&self.params, user, candidates, k)
+    }
+}
+
+impl OnlineRankingModel for ImplicitAttentionModel {
+    type UserRepresentation = ImplicitUser;
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        self.params.user_representation(item_ids)
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.params.predict(user, item_ids)
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.params.predict_all_items(user, num_items)
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use rand::distributions::Uniform;
+
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, Timestamp, UserId};
+    use crate::models::ewma;
+
+    #[test]
+    fn beats_ewma_on_long_dependencies() {
+        // Every user sees six distinct items from the first half of the
+        // catalogue, then the counterpart in the second half of the third
+        // one. Averaging models weight past items by recency alone, so
+        // they cannot single out the third item.
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+        let item_range = Uniform::new(0, 20);
+
+        let mut interactions = Vec::new();
+        for user_id in 0..300 {
+            let mut items = Vec::new();
+            while items.len() < 6 {
+                let item_id = item_range.sample(&mut rng);
+                if !items.contains(&item_id) {
+                    items.push(item_id);
+                }
+            }
+            items.push(items[2] + 20);
+
+            for (t, &item_id) in items.iter().enumerate() {
+                interactions.push(Interaction::new(
+                    UserId(user_id),
+                    ItemId(item_id),
+                    Timestamp(t as i64),
+                ));
+            }
+        }
+        let data = Interactions::from(interactions).to_compressed();
+
+        let mut model = Hyperparameters::new(40, 12)
+            .embedding_dim(32)
+            .num_heads(2)
+            .learning_rate(0.001)
+            .num_epochs(5)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+
+        let mut baseline = ewma::Hyperparameters::new(40, 12)
+            .embedding_dim(32)
+            .learning_rate(0.05)
+            .num_epochs(20)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        baseline.fit(&data).unwrap();
+
+        let mrr = mrr_score(&model, &data).unwrap();
+        let baseline_mrr = mrr_score(&baseline, &data).unwrap();
+
+        println!("Attention MRR {}, EWMA MRR {}", mrr, baseline_mrr);
+        assert!(mrr > baseline_mrr);
+    }
+
+    #[test]
+    fn dropout_masks() {
+        let params = Hyperparameters::new(10, 5)
+            .num_blocks(2)
+            .dropout(0.5)
+            .from_seed([42; 16])
+            .build_params();
+
+        // The inputs and both sub-layers of every block are masked at
+        // each step, with masks of all ones outside training.
+        let model = params.build();
+        assert_eq!(model.dropout_masks().len(), 5 * (1 + 2 * 2));
+        assert!(model
+            .dropout_masks()
+            .iter()
+            .all(|mask| mask.value().iter().all(|&x| x == 1.0)));
+
+        let params = Hyperparameters::new(10, 5).build_params();
+        assert!(params.build().dropout_masks().is_empty());
+    }
+
+    #[test]
+    fn serialization() {
+        let data = Interactions::from(
+            (0..20)
+                .map(|t| Interaction::new(UserId(t % 2), ItemId(t % 5), Timestamp(t as i64)))
+                .collect::<Vec<_>>(),
+        )
+        .to_compressed();
+
+        let mut model = Hyperparameters::new(5, 10)
+            .num_heads(4)
+            .dropout(0.1)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let predictions = model.predict(&user, &items(&[0, 1, 2, 3, 4])).unwrap();
+        for (x, y) in izip!(model.predict_all_items(&user, 5).unwrap(), &predictions) {
+            assert!((x - y).abs() < 1e-5);
+        }
+
+        let serialized = serde_json::to_string(&model).unwrap();
+        let deserialized: ImplicitAttentionModel = serde_json::from_str(&serialized).unwrap();
+        let user = deserialized
+            .user_representation(&items(&[1, 2, 3]))
+            .unwrap();
+        assert_eq!(
+            deserialized
+                .predict(&user, &items(&[0, 1, 2, 3, 4]))
+                .unwrap(),
+            predictions
+        );
+    }
+
+    #[test]
+    fn large_attention_scores() {
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+        let head = AttentionHead::new(8, 8, &mut rng);
+
+        let inputs: Vec<_> = (0..3)
+            .map(|step| {
+                wyrm::InputNode::new(Arr::from_elem((1, 8), 1000.0 * (step + 1) as f32)).boxed()
+            })
+            .collect();
+
+        for output in head.forward(&inputs) {
+            output.forward();
+            assert!(output.value().iter().all(|x| x.is_finite()));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not divisible")]
+    fn heads_must_divide_embedding_dim() {
+        Hyperparameters::new(10, 5)
+            .embedding_dim(16)
+            .num_heads(3)
+            .build();
+    }
+}
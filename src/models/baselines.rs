@@ -0,0 +1,443 @@
+//! Simple non-personalized baselines.
+//!
+//! These models ignore the user's history and are useful as reference
+//! points in evaluations: a sequence model that does not beat them is
+//! not learning anything beyond item popularity.
+use std::hash::Hasher;
+
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher;
+
+use crate::data::CompressedInteractions;
+use crate::ids::{ItemId, Timestamp};
+use crate::{FittingError, OnlineRankingModel, PredictionError};
+
+/// User representation of the baselines, which ignore the user's history.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BaselineUser;
+
+/// Scores items by the log of their number of training interactions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Popularity {
+    num_items: usize,
+    recency_window: Option<u64>,
+    log_counts: Vec<f32>,
+}
+
+impl Popularity {
+    /// Create an unfitted model over `num_items` items, which scores
+    /// all items equally.
+    pub fn new(num_items: usize) -> Self {
+        Popularity {
+            num_items,
+            recency_window: None,
+            log_counts: vec![0.0; num_items],
+        }
+    }
+
+    /// Only count interactions within `window` time units of the most
+    /// recent interaction in the training data. Defaults to counting
+    /// all interactions, as does a window reaching past the earliest
+    /// representable timestamp.
+    pub fn recency_window(mut self, window: u64) -> Self {
+        self.recency_window = Some(window);
+        self
+    }
+
+    /// Fit the model by counting item interactions.
+    pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<(), FittingError> {
+        if interactions.num_items() != self.num_items {
+            return Err(FittingError::ItemCountMismatch {
+                model_items: self.num_items,
+                data_items: interactions.num_items(),
+            });
+        }
+
+        let max_timestamp = interactions
+            .iter_users()
+            .flat_map(|user| user.timestamps.iter().cloned())
+            .max()
+            .ok_or(FittingError::NoInteractions)?;
+        let min_timestamp = self.recency_window.map(|window| max_timestamp - window);
+
+        let mut counts = vec![0_usize; self.num_items];
+        for user in interactions.iter_users() {
+            for (&item_id, &timestamp) in izip!(user.item_ids, user.timestamps) {
+                if min_timestamp.map_or(true, |min_timestamp| timestamp >= min_timestamp) {
+                    counts[item_id.index()] += 1;
+                }
+            }
+        }
+
+        self.log_counts = counts
+            .into_iter()
+            .map(|count| (1.0 + count as f32).ln())
+            .collect();
+
+        Ok(())
+    }
+}
+
+impl OnlineRankingModel for Popularity {
+    type UserRepresentation = BaselineUser;
+
+    fn user_representation(
+        &self,
+        _item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        Ok(BaselineUser)
+    }
+
+    /// Return the log of one plus the number of training interactions
+    /// of each item. Unknown items score 0.
+    fn predict(
+        &self,
+        _user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        Ok(item_ids
+            .iter()
+            .map(|&item_id| self.log_counts.get(item_id.index()).cloned().unwrap_or(0.0))
+            .collect())
+    }
+
+    fn predict_all_items(
+        &self,
+        _user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        let mut scores = self.log_counts.clone();
+        scores.resize(num_items, 0.0);
+
+        Ok(scores)
+    }
+}
+
+/// Scores items by their number of interactions, each weighted by
+/// `0.5^((now - t) / half_life)` for an interaction at time `t`, so that
+/// recently popular items rank first.
+///
+/// Interactions after `now` are ignored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Trending {
+    num_items: usize,
+    half_life: f32,
+    now: Timestamp,
+    scores: Vec<f32>,
+}
+
+impl Trending {
+    /// Create an unfitted model over `num_items` items, which scores
+    /// all items equally.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `half_life` is not positive.
+    pub fn new(num_items: usize, half_life: f32) -> Self {
+        assert!(half_life > 0.0, "Half-life must be positive.");
+
+        Trending {
+            num_items,
+            half_life,
+            now: Timestamp(0),
+            scores: vec![0.0; num_items],
+        }
+    }
+
+    fn decay(&self, elapsed: f32) -> f32 {
+        0.5_f32.powf(elapsed / self.half_life)
+    }
+
+    /// Fit the model by summing the decayed weights of the interactions
+    /// with each item as of time `now`.
+    pub fn fit(
+        &mut self,
+        interactions: &CompressedInteractions,
+        now: Timestamp,
+    ) -> Result<(), FittingError> {
+        if interactions.num_items() != self.num_items {
+            return Err(FittingError::ItemCountMismatch {
+                model_items: self.num_items,
+                data_items: interactions.num_items(),
+            });
+        }
+
+        let mut scores = vec![0.0; self.num_items];
+        let mut num_interactions = 0;
+
+        for user in interactions.iter_users() {
+            for (&item_id, &timestamp) in izip!(user.item_ids, user.timestamps) {
+                if timestamp <= now {
+                    scores[item_id.index()] += self.decay((now - timestamp) as f32);
+                    num_interactions += 1;
+                }
+            }
+        }
+
+        if num_interactions == 0 {
+            return Err(FittingError::NoInteractions);
+        }
+
+        self.scores = scores;
+        self.now = now;
+
+        Ok(())
+    }
+
+    /// Advance the scores to time `now` without revisiting the data.
+    /// Every score decays by the same factor, so the ranking only
+    /// changes when the model is fit on new interactions.
+    pub fn refresh(&mut self, now: Timestamp) {
+        let decay = self.decay((now - self.now) as f32);
+
+        for score in &mut self.scores {
+            *score *= decay;
+        }
+
+        self.now = now;
+    }
+}
+
+impl OnlineRankingModel for Trending {
+    type UserRepresentation = BaselineUser;
+
+    fn user_representation(
+        &self,
+        _item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        Ok(BaselineUser)
+    }
+
+    /// Return the decayed interaction count of each item. Unknown
+    /// items score 0.
+    fn predict(
+        &self,
+        _user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        Ok(item_ids
+            .iter()
+            .map(|&item_id| self.scores.get(item_id.index()).cloned().unwrap_or(0.0))
+            .collect())
+    }
+
+    fn predict_all_items(
+        &self,
+        _user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        let mut scores = self.scores.clone();
+        scores.resize(num_items, 0.0);
+
+        Ok(scores)
+    }
+}
+
+/// Scores items uniformly at random.
+///
+/// Scores are a hash of the seed and the item id, so they are the same
+/// for every user and every call: repeated evaluations give identical
+/// results. Useful for checking that evaluation code reports chance-level
+/// metrics for a model that has learned nothing.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Random {
+    seed: u64,
+}
+
+impl Random {
+    /// Create a model whose scores are determined by `seed`.
+    pub fn new(seed: u64) -> Self {
+        Random { seed }
+    }
+
+    fn score(&self, item_id: ItemId) -> f32 {
+        let mut hasher = SipHasher::new_with_keys(self.seed, 0);
+        hasher.write_usize(item_id.index());
+
+        // Keep the 24 bits an f32 mantissa can represent exactly.
+        (hasher.finish() >> 40) as f32 / (1 << 24) as f32
+    }
+}
+
+impl OnlineRankingModel for Random {
+    type UserRepresentation = BaselineUser;
+
+    fn user_representation(
+        &self,
+        _item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        Ok(BaselineUser)
+    }
+
+    fn predict(
+        &self,
+        _user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        Ok(item_ids
+            .iter()
+            .map(|&item_id| self.score(item_id))
+            .collect())
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, UserId};
+
+    fn skewed_data() -> CompressedInteractions {
+        // Item `i` is interacted with by `10 - i` users, item 0 most recently.
+        let interactions: Vec<_> = (0..10)
+            .flat_map(|item_id| {
+                (0..10 - item_id).map(move |user_id| {
+                    Interaction::new(
+                        UserId(user_id),
+                        ItemId(item_id),
+                        Timestamp(100 - item_id as i64),
+                    )
+                })
+            })
+            .collect();
+
+        Interactions::from(interactions).to_compressed()
+    }
+
+    #[test]
+    fn ranks_by_frequency() {
+        let data = skewed_data();
+        let mut model = Popularity::new(data.num_items());
+        model.fit(&data).unwrap();
+
+        let item_ids: Vec<_> = (0..data.num_items())
+            .rev()
+            .map(ItemId::from_index)
+            .collect();
+        let scores = model.predict(&BaselineUser, &item_ids).unwrap();
+        assert!(scores.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let user = model.user_representation(&[]).unwrap();
+        assert_eq!(
+            model.predict_all_items(&user, data.num_items()).unwrap(),
+            model
+                .predict(
+                    &user,
+                    &(0..data.num_items())
+                        .map(ItemId::from_index)
+                        .collect::<Vec<_>>()
+                )
+                .unwrap()
+        );
+
+        assert!(mrr_score(&model, &data).unwrap() > 0.0);
+
+        let serialized = bincode::serialize(&model).unwrap();
+        let deserialized: Popularity = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(deserialized.log_counts, model.log_counts);
+    }
+
+    #[test]
+    fn recency_window() {
+        let data = skewed_data();
+        let mut model = Popularity::new(data.num_items()).recency_window(2);
+        model.fit(&data).unwrap();
+
+        // Only items 0, 1 and 2 have interactions within the window.
+        let scores = model.predict(&BaselineUser, &items(&[0, 1, 2, 3])).unwrap();
+        assert!(scores[0] > scores[1] && scores[1] > scores[2] && scores[2] > 0.0);
+        assert_eq!(scores[3], 0.0);
+
+        // A window longer than the range of timestamps counts everything.
+        let mut unlimited = Popularity::new(data.num_items());
+        unlimited.fit(&data).unwrap();
+        let mut model = Popularity::new(data.num_items()).recency_window(u64::MAX);
+        model.fit(&data).unwrap();
+        assert_eq!(model.log_counts, unlimited.log_counts);
+    }
+
+    #[test]
+    fn random_is_chance_level() {
+        let num_items = 100;
+
+        // Every item is the held-out last item of the same number of users.
+        let interactions: Vec<_> = (0..1000)
+            .flat_map(|user_id| {
+                vec![
+                    Interaction::new(
+                        UserId(user_id),
+                        ItemId((user_id * 7 + 3) % num_items),
+                        Timestamp(0),
+                    ),
+                    Interaction::new(UserId(user_id), ItemId(user_id % num_items), Timestamp(1)),
+                ]
+            })
+            .collect();
+        let data = Interactions::from(interactions).to_compressed();
+
+        let model = Random::new(42);
+        let mrr = mrr_score(&model, &data).unwrap();
+        assert_eq!(mrr, mrr_score(&model, &data).unwrap());
+
+        let chance = (1..=num_items).map(|rank| 1.0 / rank as f32).sum::<f32>() / num_items as f32;
+        assert!(mrr > 0.5 * chance && mrr < 2.0 * chance);
+
+        let scores = model.predict(&BaselineUser, &items(&[1, 2, 3])).unwrap();
+        assert_eq!(
+            scores,
+            Random::new(42)
+                .predict(&BaselineUser, &items(&[1, 2, 3]))
+                .unwrap()
+        );
+        assert_ne!(
+            scores,
+            Random::new(43)
+                .predict(&BaselineUser, &items(&[1, 2, 3]))
+                .unwrap()
+        );
+        assert!(scores.iter().all(|score| (0.0..1.0).contains(score)));
+    }
+
+    #[test]
+    fn trending_follows_recent_popularity() {
+        // Item 0 is the most popular overall, but only in the first half
+        // of the time range; item 1 is popular in the second half.
+        let mut interactions = Vec::new();
+        for t in 0..100 {
+            let (item_id, count) = if t < 50 { (0, 4) } else { (1, 2) };
+            for user_id in 0..count {
+                interactions.push(Interaction::new(
+                    UserId(user_id),
+                    ItemId(item_id),
+                    Timestamp(t),
+                ));
+            }
+            if t % 10 == 0 {
+                interactions.push(Interaction::new(UserId(0), ItemId(2), Timestamp(t)));
+            }
+        }
+        let data = Interactions::from(interactions).to_compressed();
+
+        let mut popularity = Popularity::new(data.num_items());
+        popularity.fit(&data).unwrap();
+        let scores = popularity.predict(&BaselineUser, &items(&[0, 1])).unwrap();
+        assert!(scores[0] > scores[1]);
+
+        let mut trending = Trending::new(data.num_items(), 10.0);
+        trending.fit(&data, Timestamp(99)).unwrap();
+        let user = trending.user_representation(&[]).unwrap();
+        let scores = trending.predict_all_items(&user, data.num_items()).unwrap();
+        assert!(scores[1] > scores[0] && scores[0] > scores[2]);
+
+        // Refreshing decays every score by the same factor.
+        let mut refreshed = trending.clone();
+        refreshed.refresh(Timestamp(109));
+        let refreshed_scores = refreshed.predict(&user, &items(&[0, 1, 2])).unwrap();
+        assert!(izip!(&scores, &refreshed_scores).all(|(x, y)| (0.5 * x - y).abs() < 1e-4));
+
+        // Interactions after `now` are ignored.
+        trending.fit(&data, Timestamp(40)).unwrap();
+        assert_eq!(trending.predict(&user, &items(&[1])).unwrap(), vec![0.0]);
+    }
+}
@@ -0,0 +1,263 @@
+//! Type-erased ranking models.
+//!
+//! [OnlineRankingModel] has an associated user representation type, so
+//! different models cannot be stored behind one trait object. [BoxedModel]
+//! erases the representation type, so that, for example, an LSTM and an
+//! EWMA model can be swapped at runtime behind a single field.
+use std::any::Any;
+use std::collections::HashSet;
+use std::fmt;
+
+use super::top_k;
+use crate::ids::ItemId;
+use crate::{OnlineRankingModel, PredictionError};
+
+/// A type-erased user representation, produced by [BoxedModel::user_representation].
+pub type BoxedUser = Box<dyn Any + Send>;
+
+/// Object-safe counterpart of [OnlineRankingModel], implemented for every
+/// model whose user representation can be sent between threads.
+pub trait DynRankingModel: Send + Sync {
+    /// Compute a type-erased user representation from past interactions.
+    fn dyn_user_representation(&self, item_ids: &[ItemId]) -> Result<BoxedUser, PredictionError>;
+    /// Score `item_ids` for a representation computed by this model.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `user` was computed by a model of a different type.
+    fn dyn_predict(&self, user: &dyn Any, item_ids: &[ItemId])
+        -> Result<Vec<f32>, PredictionError>;
+    /// Score all items `0..num_items` for a representation computed by
+    /// this model.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `user` was computed by a model of a different type.
+    fn dyn_predict_all_items(
+        &self,
+        user: &dyn Any,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError>;
+}
+
+fn downcast<T: 'static>(user: &dyn Any) -> &T {
+    user.downcast_ref()
+        .expect("User representation was computed by a different model.")
+}
+
+impl<M> DynRankingModel for M
+where
+    M: OnlineRankingModel + Send + Sync,
+    M::UserRepresentation: Send + 'static,
+{
+    fn dyn_user_representation(&self, item_ids: &[ItemId]) -> Result<BoxedUser, PredictionError> {
+        Ok(Box::new(self.user_representation(item_ids)?))
+    }
+
+    fn dyn_predict(
+        &self,
+        user: &dyn Any,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.predict(downcast(user), item_ids)
+    }
+
+    fn dyn_predict_all_items(
+        &self,
+        user: &dyn Any,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.predict_all_items(downcast(user), num_items)
+    }
+}
+
+/// A ranking model over a fixed number of items, with its type erased.
+pub struct BoxedModel {
+    model: Box<dyn DynRankingModel>,
+    num_items: usize,
+}
+
+impl BoxedModel {
+    /// Erase the type of `model`, which ranks `num_items` items.
+    pub fn new<M: DynRankingModel + 'static>(model: M, num_items: usize) -> Self {
+        BoxedModel {
+            model: Box::new(model),
+            num_items,
+        }
+    }
+
+    /// Return the number of items the model ranks.
+    pub fn num_items(&self) -> usize {
+        self.num_items
+    }
+
+    /// Return the `k` highest-scoring items for `user` as `(item_id, score)`
+    /// pairs, best first. Items in `exclude` are never returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `user` was computed by a different model.
+    pub fn predict_top_k(
+        &self,
+        user: &BoxedUser,
+        k: usize,
+        exclude: &[ItemId],
+    ) -> Result<Vec<(ItemId, f32)>, PredictionError> {
+        let scores = self.predict_all_items(user, self.num_items)?;
+
+        if !scores.iter().all(|score| score.is_finite()) {
+            return Err(PredictionError::InvalidPredictionValue);
+        }
+
+        let exclude: HashSet<_> = exclude.iter().collect();
+
+        Ok(top_k(
+            scores
+                .into_iter()
+                .enumerate()
+                .map(|(idx, score)| (ItemId::from_index(idx), score))
+                .filter(|(item_id, _)| !exclude.contains(item_id))
+                .collect(),
+            k,
+        ))
+    }
+}
+
+impl fmt::Debug for BoxedModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BoxedModel")
+            .field("num_items", &self.num_items)
+            .finish()
+    }
+}
+
+impl OnlineRankingModel for BoxedModel {
+    type UserRepresentation = BoxedUser;
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        self.model.dyn_user_representation(item_ids)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `user` was computed by a different model.
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.model.dyn_predict(user.as_ref(), item_ids)
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `user` was computed by a different model.
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.model.dyn_predict_all_items(user.as_ref(), num_items)
+    }
+}
+
+/// Conversion of any ranking model into a [BoxedModel].
+pub trait IntoBoxedModel {
+    /// Erase the type of the model, which ranks `num_items` items.
+    fn boxed(self, num_items: usize) -> BoxedModel;
+}
+
+impl<M: DynRankingModel + 'static> IntoBoxedModel for M {
+    fn boxed(self, num_items: usize) -> BoxedModel {
+        BoxedModel::new(self, num_items)
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, Timestamp, UserId};
+    use crate::models::baselines::Popularity;
+    use crate::models::{ewma, lstm};
+
+    #[test]
+    fn heterogeneous_models() {
+        let mut interactions = Vec::new();
+        for user_id in 0..30 {
+            for timestamp in 0..10 {
+                interactions.push(Interaction::new(
+                    UserId(user_id),
+                    ItemId((user_id + timestamp) % 20),
+                    Timestamp(timestamp as i64),
+                ));
+            }
+        }
+        let data = Interactions::from(interactions).to_compressed();
+
+        let mut lstm = lstm::Hyperparameters::new(20, 10)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        let mut ewma = ewma::Hyperparameters::new(20, 10)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        lstm.fit(&data).unwrap();
+        ewma.fit(&data).unwrap();
+
+        let expected = vec![
+            mrr_score(&lstm, &data).unwrap(),
+            mrr_score(&ewma, &data).unwrap(),
+        ];
+        let lstm_scores = lstm
+            .predict_all_items(&lstm.user_representation(&items(&[1, 2])).unwrap(), 20)
+            .unwrap();
+
+        let models: Vec<BoxedModel> = vec![lstm.boxed(20), ewma.boxed(20)];
+
+        let scores: Vec<_> = models
+            .iter()
+            .map(|model| mrr_score(model, &data).unwrap())
+            .collect();
+        assert_eq!(scores, expected);
+
+        let user = models[0].user_representation(&items(&[1, 2])).unwrap();
+        let top = models[0].predict_top_k(&user, 3, &items(&[1, 2])).unwrap();
+        assert_eq!(top.len(), 3);
+        assert!(top.iter().all(|&(item_id, score)| item_id != ItemId(1)
+            && item_id != ItemId(2)
+            && score == lstm_scores[item_id.index()]));
+        assert!(top.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert!(models.iter().all(|model| model.num_items() == 20));
+    }
+
+    #[test]
+    #[should_panic(expected = "User representation was computed by a different model.")]
+    fn mismatched_representation() {
+        let data = Interactions::from(
+            (0..20)
+                .map(|item_id| {
+                    Interaction::new(UserId(0), ItemId(item_id), Timestamp(item_id as i64))
+                })
+                .collect::<Vec<_>>(),
+        )
+        .to_compressed();
+        let mut lstm = lstm::Hyperparameters::new(20, 10)
+            .num_epochs(0)
+            .num_threads(1)
+            .build();
+        lstm.fit(&data).unwrap();
+
+        let lstm = lstm.boxed(20);
+        let popularity = Popularity::new(20).boxed(20);
+
+        let user = lstm.user_representation(&items(&[1, 2])).unwrap();
+        let _ = popularity.predict(&user, &items(&[0]));
+    }
+}
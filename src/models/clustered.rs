@@ -0,0 +1,388 @@
+//! Per-cluster popularity model.
+//!
+//! Users are clustered by spherical k-means over their normalized item
+//! frequency vectors, and each cluster recommends the items most popular
+//! among its members. This is a cheap step up in personalization from
+//! [Popularity](super::baselines::Popularity) when the user base consists
+//! of a few populations with distinct tastes.
+use std::collections::HashMap;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::data::CompressedInteractions;
+use crate::ids::ItemId;
+use crate::{FittingError, OnlineRankingModel, PredictionError};
+
+/// Maximum number of k-means iterations.
+const MAX_ITERATIONS: usize = 50;
+
+/// User representation of the clustered popularity model: the cluster
+/// nearest to the user's history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClusterUser {
+    cluster: usize,
+}
+
+impl ClusterUser {
+    /// Return the index of the user's cluster.
+    pub fn cluster(&self) -> usize {
+        self.cluster
+    }
+}
+
+/// Recommends the most popular items of the user's cluster.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClusteredPopularity {
+    num_items: usize,
+    centroids: Vec<Vec<f32>>,
+    counts: Vec<Vec<f32>>,
+}
+
+/// Return the L2-normalized item frequencies of `item_ids` as sparse
+/// `(item_id, value)` pairs, ignoring items outside `0..num_items`.
+fn frequency_vector(item_ids: &[ItemId], num_items: usize) -> Vec<(ItemId, f32)> {
+    let mut counts: HashMap<ItemId, f32> = HashMap::new();
+
+    for &item_id in item_ids
+        .iter()
+        .filter(|item_id| item_id.index() < num_items)
+    {
+        *counts.entry(item_id).or_insert(0.0) += 1.0;
+    }
+
+    let norm = counts.values().map(|x| x * x).sum::<f32>().sqrt();
+    let mut vector: Vec<_> = counts
+        .into_iter()
+        .map(|(item_id, count)| (item_id, count / norm))
+        .collect();
+    vector.sort_by_key(|&(item_id, _)| item_id);
+
+    vector
+}
+
+fn similarity(centroid: &[f32], vector: &[(ItemId, f32)]) -> f32 {
+    vector
+        .iter()
+        .map(|&(item_id, value)| centroid[item_id.index()] * value)
+        .sum()
+}
+
+/// Return the index of the centroid most similar to `vector`, preferring
+/// lower indices on ties.
+fn nearest(centroids: &[Vec<f32>], vector: &[(ItemId, f32)]) -> usize {
+    let mut best = (0, std::f32::NEG_INFINITY);
+
+    for (idx, centroid) in centroids.iter().enumerate() {
+        let value = similarity(centroid, vector);
+
+        if value > best.1 {
+            best = (idx, value);
+        }
+    }
+
+    best.0
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+impl ClusteredPopularity {
+    /// Create an unfitted model over `num_items` items, with a single
+    /// empty cluster scoring all items equally.
+    pub fn new(num_items: usize) -> Self {
+        ClusteredPopularity {
+            num_items,
+            centroids: vec![vec![0.0; num_items]],
+            counts: vec![vec![0.0; num_items]],
+        }
+    }
+
+    /// Return the number of clusters.
+    pub fn num_clusters(&self) -> usize {
+        self.centroids.len()
+    }
+
+    /// Return the cluster with the most interactions, preferring lower
+    /// indices on ties.
+    fn largest_cluster(&self) -> usize {
+        let mut best = (0, std::f32::NEG_INFINITY);
+
+        for (idx, counts) in self.counts.iter().enumerate() {
+            let total: f32 = counts.iter().sum();
+
+            if total > best.1 {
+                best = (idx, total);
+            }
+        }
+
+        best.0
+    }
+
+    fn dense(&self, vector: &[(ItemId, f32)]) -> Vec<f32> {
+        let mut dense = vec![0.0; self.num_items];
+
+        for &(item_id, value) in vector {
+            dense[item_id.index()] = value;
+        }
+
+        dense
+    }
+
+    /// Cluster the users with at least one interaction into `num_clusters`
+    /// clusters, initialized with k-means++ seeding drawn from `rng`, and
+    /// count the interactions with each item within each cluster.
+    ///
+    /// There are never more clusters than users, nor more than the number
+    /// of distinct histories k-means++ can seed from.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_clusters` is zero.
+    pub fn fit<R: Rng>(
+        &mut self,
+        interactions: &CompressedInteractions,
+        num_clusters: usize,
+        rng: &mut R,
+    ) -> Result<(), FittingError> {
+        assert!(num_clusters > 0, "There must be at least one cluster.");
+
+        if interactions.num_items() != self.num_items {
+            return Err(FittingError::ItemCountMismatch {
+                model_items: self.num_items,
+                data_items: interactions.num_items(),
+            });
+        }
+
+        let histories: Vec<_> = interactions
+            .iter_users()
+            .filter(|user| !user.item_ids.is_empty())
+            .map(|user| user.item_ids)
+            .collect();
+
+        if histories.is_empty() {
+            return Err(FittingError::NoInteractions);
+        }
+
+        let vectors: Vec<_> = histories
+            .iter()
+            .map(|item_ids| frequency_vector(item_ids, self.num_items))
+            .collect();
+
+        // k-means++: sample each new centroid with probability proportional
+        // to its squared distance from the nearest existing centroid.
+        let mut centroids = vec![self.dense(&vectors[rng.gen_range(0, vectors.len())])];
+
+        while centroids.len() < num_clusters.min(vectors.len()) {
+            let distances: Vec<f32> = vectors
+                .iter()
+                .map(|vector| {
+                    let closest = similarity(&centroids[nearest(&centroids, vector)], vector);
+                    (2.0 - 2.0 * closest).max(0.0)
+                })
+                .collect();
+            let total: f32 = distances.iter().sum();
+
+            if total <= 0.0 {
+                // All remaining users duplicate existing centroids.
+                break;
+            }
+
+            let mut threshold = rng.gen::<f32>() * total;
+            let idx = distances
+                .iter()
+                .position(|&distance| {
+                    threshold -= distance;
+                    threshold < 0.0
+                })
+                .unwrap_or(vectors.len() - 1);
+
+            centroids.push(self.dense(&vectors[idx]));
+        }
+
+        let mut assignments = vec![None; vectors.len()];
+
+        for _ in 0..MAX_ITERATIONS {
+            let new_assignments: Vec<_> = vectors
+                .iter()
+                .map(|vector| Some(nearest(&centroids, vector)))
+                .collect();
+
+            if new_assignments == assignments {
+                break;
+            }
+
+            assignments = new_assignments;
+
+            let mut sums = vec![vec![0.0; self.num_items]; centroids.len()];
+            for (vector, cluster) in vectors.iter().zip(&assignments) {
+                for &(item_id, value) in vector {
+                    sums[cluster.unwrap()][item_id.index()] += value;
+                }
+            }
+
+            // Empty clusters keep their previous centroid.
+            for (centroid, mut sum) in centroids.iter_mut().zip(sums) {
+                if sum.iter().any(|&x| x != 0.0) {
+                    normalize(&mut sum);
+                    *centroid = sum;
+                }
+            }
+        }
+
+        let mut counts = vec![vec![0.0; self.num_items]; centroids.len()];
+        for (item_ids, cluster) in histories.iter().zip(&assignments) {
+            for &item_id in item_ids.iter() {
+                counts[cluster.unwrap()][item_id.index()] += 1.0;
+            }
+        }
+
+        self.centroids = centroids;
+        self.counts = counts;
+
+        Ok(())
+    }
+}
+
+impl OnlineRankingModel for ClusteredPopularity {
+    type UserRepresentation = ClusterUser;
+
+    /// Assign the history to the nearest cluster. Empty histories are
+    /// assigned to the cluster with the most interactions.
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        let vector = frequency_vector(item_ids, self.num_items);
+
+        let cluster = if vector.is_empty() {
+            self.largest_cluster()
+        } else {
+            nearest(&self.centroids, &vector)
+        };
+
+        Ok(ClusterUser { cluster })
+    }
+
+    /// Return the number of interactions with each item within the
+    /// user's cluster. Unknown items score 0.
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        let counts = &self.counts[user.cluster];
+
+        Ok(item_ids
+            .iter()
+            .map(|&item_id| counts.get(item_id.index()).cloned().unwrap_or(0.0))
+            .collect())
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, Timestamp, UserId};
+    use crate::models::baselines::Popularity;
+
+    /// Users `0..20` interact with items `0..10`, and the more numerous
+    /// users `20..50` with items `10..20`.
+    fn two_populations(rng: &mut XorShiftRng) -> CompressedInteractions {
+        let mut interactions = Interactions::new(50, 20);
+
+        for user_id in 0..50 {
+            let offset = if user_id < 20 { 0 } else { 10 };
+
+            for timestamp in 0..6 {
+                let item_id = offset + rng.gen_range(0, 10);
+                interactions.push(Interaction::new(
+                    UserId(user_id),
+                    ItemId(item_id),
+                    Timestamp(timestamp),
+                ));
+            }
+        }
+
+        interactions.to_compressed()
+    }
+
+    #[test]
+    fn recovers_populations() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let data = two_populations(&mut rng);
+
+        let mut model = ClusteredPopularity::new(data.num_items());
+        model.fit(&data, 2, &mut rng).unwrap();
+        assert_eq!(model.num_clusters(), 2);
+
+        let clusters: Vec<_> = data
+            .iter_users()
+            .map(|user| model.user_representation(user.item_ids).unwrap().cluster())
+            .collect();
+        assert!(clusters[..20].iter().all(|&x| x == clusters[0]));
+        assert!(clusters[20..].iter().all(|&x| x == clusters[20]));
+        assert_ne!(clusters[0], clusters[20]);
+
+        // Each cluster only recommends its population's items.
+        let user = model.user_representation(&items(&[3])).unwrap();
+        let scores = model.predict(&user, &items(&[0, 15])).unwrap();
+        assert!(scores[0] > 0.0 && scores[1] == 0.0);
+
+        // Empty histories go to the larger population.
+        assert_eq!(
+            model.user_representation(&items(&[])).unwrap().cluster(),
+            clusters[20]
+        );
+
+        let mut popularity = Popularity::new(data.num_items());
+        popularity.fit(&data).unwrap();
+        assert!(mrr_score(&model, &data).unwrap() > mrr_score(&popularity, &data).unwrap());
+    }
+
+    #[test]
+    fn serialization() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let data = two_populations(&mut rng);
+
+        let mut model = ClusteredPopularity::new(data.num_items());
+        model.fit(&data, 2, &mut rng).unwrap();
+
+        let serialized = serde_json::to_string(&model).unwrap();
+        let deserialized: ClusteredPopularity = serde_json::from_str(&serialized).unwrap();
+
+        let user = model.user_representation(&items(&[12, 14])).unwrap();
+        assert_eq!(
+            deserialized.user_representation(&items(&[12, 14])).unwrap(),
+            user
+        );
+        assert_eq!(
+            deserialized.predict(&user, &items(&[0, 12])).unwrap(),
+            model.predict(&user, &items(&[0, 12])).unwrap()
+        );
+    }
+
+    #[test]
+    fn more_clusters_than_users() {
+        let data = Interactions::from(vec![
+            Interaction::new(UserId(0), ItemId(1), Timestamp(0)),
+            Interaction::new(UserId(1), ItemId(2), Timestamp(0)),
+        ])
+        .to_compressed();
+        let mut model = ClusteredPopularity::new(data.num_items());
+        model
+            .fit(&data, 5, &mut XorShiftRng::from_seed([42; 16]))
+            .unwrap();
+
+        assert_eq!(model.num_clusters(), 2);
+    }
+}
@@ -0,0 +1,747 @@
+//! Module for convolutional (Caser-style) sequence models.
+//!
+//! At every step, the embeddings of the last `L` items form an `L x d`
+//! "image" which is passed through two kinds of convolution:
+//!
+//! - horizontal filters of every height `h` in `1..=L` span `h` consecutive
+//!   items and the full embedding, capturing union-level sequential
+//!   patterns. Their outputs are pooled over positions.
+//! - vertical filters compute weighted sums of the `L` embeddings,
+//!   capturing point-level patterns.
+//!
+//! Both outputs go through a dense ReLU layer to give the user
+//! representation. Steps with fewer than `L` preceding items are padded
+//! with a reserved, learned padding embedding.
+//!
+//! Pooling uses the log-sum-exp of the filter outputs, a smooth
+//! approximation of the max-pooling of the original model.
+use std::sync::Arc;
+
+use rand;
+use rand::distributions::{Distribution, Normal};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use serde::{Deserialize, Serialize};
+
+use wyrm;
+use wyrm::optim::Optimizers;
+use wyrm::{Arr, BoxedNode, Variable};
+
+use super::metadata::ModelMetadata;
+use super::sampling::MinibatchSampler;
+#[cfg(feature = "training")]
+use super::sequence_model::fit_sequence_model;
+use super::sequence_model::{score_all_items, SequenceModel, SequenceModelParameters};
+use super::{ImplicitUser, Loss, Optimizer, Parallelism};
+use crate::config;
+#[cfg(feature = "training")]
+use crate::data::CompressedInteractions;
+use crate::ids::ItemId;
+use crate::rng::SeedSequence;
+#[cfg(feature = "training")]
+use crate::FittingError;
+use crate::{OnlineRankingModel, PredictionError};
+
+fn embedding_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
+    let normal = Normal::new(0.0, 1.0 / cols as f64);
+    Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
+}
+
+fn dense_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
+    let normal = Normal::new(0.0, (2.0 / (rows + cols) as f64).sqrt());
+    Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
+}
+
+fn parameter(value: Arr) -> Arc<wyrm::HogwildParameter> {
+    Arc::new(wyrm::HogwildParameter::new(value))
+}
+
+fn deep_clone(parameter: &Arc<wyrm::HogwildParameter>) -> Arc<wyrm::HogwildParameter> {
+    Arc::new(parameter.as_ref().clone())
+}
+
+fn shared(parameter: &Arc<wyrm::HogwildParameter>) -> Variable<wyrm::ParameterNode> {
+    wyrm::ParameterNode::shared(parameter.clone())
+}
+
+/// Sum a non-empty sequence of nodes.
+fn sum<I: IntoIterator<Item = Variable<BoxedNode>>>(terms: I) -> Variable<BoxedNode> {
+    let mut terms = terms.into_iter();
+    let first = terms.next().expect("Cannot sum zero terms.");
+
+    terms.fold(first, |total, term| (total + term).boxed())
+}
+
+/// Hyperparameters for the [ImplicitCNNModel].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hyperparameters {
+    num_items: usize,
+    max_sequence_length: usize,
+    item_embedding_dim: usize,
+    history_length: usize,
+    num_horizontal_filters: usize,
+    num_vertical_filters: usize,
+    learning_rate: f32,
+    l2_penalty: f32,
+    loss: Loss,
+    optimizer: Optimizer,
+    parallelism: Parallelism,
+    rng: XorShiftRng,
+    num_threads: usize,
+    num_epochs: usize,
+    #[serde(default)]
+    accumulate_f64: bool,
+}
+
+impl Hyperparameters {
+    /// Build new hyperparameters.
+    pub fn new(num_items: usize, max_sequence_length: usize) -> Self {
+        Hyperparameters {
+            num_items,
+            max_sequence_length,
+            item_embedding_dim: 16,
+            history_length: 5,
+            num_horizontal_filters: 4,
+            num_vertical_filters: 4,
+            learning_rate: 0.01,
+            l2_penalty: 0.0,
+            loss: Loss::BPR,
+            optimizer: Optimizer::Adam,
+            parallelism: Parallelism::Synchronous,
+            rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
+            num_threads: config::num_threads(),
+            num_epochs: 10,
+            accumulate_f64: false,
+        }
+    }
+
+    /// Set the learning rate.
+    pub fn learning_rate(mut self, learning_rate: f32) -> Self {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    /// Set the l2 penalty.
+    pub fn l2_penalty(mut self, l2_penalty: f32) -> Self {
+        self.l2_penalty = l2_penalty;
+        self
+    }
+
+    /// Set the embedding dimensionality.
+    pub fn embedding_dim(mut self, embedding_dim: usize) -> Self {
+        self.item_embedding_dim = embedding_dim;
+        self
+    }
+
+    /// Set the number `L` of most recent items the convolutions
+    /// are computed over. Defaults to 5.
+    ///
+    /// The cost of a step grows with the cube of `L`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `history_length` is zero.
+    pub fn history_length(mut self, history_length: usize) -> Self {
+        assert!(history_length > 0, "History length must be at least 1.");
+        self.history_length = history_length;
+        self
+    }
+
+    /// Set the number of horizontal filters of each height. Defaults to 4.
+    pub fn num_horizontal_filters(mut self, num_horizontal_filters: usize) -> Self {
+        self.num_horizontal_filters = num_horizontal_filters;
+        self
+    }
+
+    /// Set the number of vertical filters. Defaults to 4.
+    pub fn num_vertical_filters(mut self, num_vertical_filters: usize) -> Self {
+        self.num_vertical_filters = num_vertical_filters;
+        self
+    }
+
+    /// Set the number of epochs to run per each `fit` call.
+    pub fn num_epochs(mut self, num_epochs: usize) -> Self {
+        self.num_epochs = num_epochs;
+        self
+    }
+
+    /// Set the loss function.
+    pub fn loss(mut self, loss: Loss) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    /// Set number of threads to be used.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// Accumulate training losses in `f64` rather than `f32`. Defaults to
+    /// false.
+    ///
+    /// Summed in `f32`, many small losses lose precision, so that the
+    /// reported loss depends on summation order. Parameters stay `f32`
+    /// either way.
+    pub fn accumulate_f64(mut self, enabled: bool) -> Self {
+        self.accumulate_f64 = enabled;
+        self
+    }
+
+    /// Set the type of paralellism.
+    pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Set the random number generator.
+    pub fn rng(mut self, rng: XorShiftRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    /// Set the random number generator from seed.
+    pub fn from_seed(mut self, seed: [u8; 16]) -> Self {
+        self.rng = XorShiftRng::from_seed(seed);
+        self
+    }
+
+    /// Set the random number generator to the one named `"cnn"` in
+    /// `seeds`, so that a single seed pins the whole experiment.
+    pub fn seed_sequence(mut self, seeds: &SeedSequence) -> Self {
+        self.rng = seeds.derive("cnn");
+        self
+    }
+
+    /// Set the optimizer type.
+    pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    fn build_params(mut self) -> Parameters {
+        assert!(
+            self.num_horizontal_filters + self.num_vertical_filters > 0,
+            "There must be at least one filter."
+        );
+
+        let dim = self.item_embedding_dim;
+        let length = self.history_length;
+        let rng = &mut self.rng;
+
+        // The last row is the padding embedding.
+        let item_embedding = parameter(embedding_init(self.num_items + 1, dim, rng));
+        let item_biases = parameter(Arr::zeros((self.num_items, 1)));
+
+        let num_horizontal = self.num_horizontal_filters;
+        let horizontal = if num_horizontal > 0 {
+            (1..=length)
+                .map(|height| HorizontalFilters {
+                    weights: (0..height)
+                        .map(|_| parameter(dense_init(dim, num_horizontal, rng)))
+                        .collect(),
+                    biases: parameter(Arr::zeros((1, num_horizontal))),
+                    projection: parameter(dense_init(num_horizontal, dim, rng)),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let vertical_weights = parameter(dense_init(self.num_vertical_filters, length, rng));
+        let vertical_projection = (0..self.num_vertical_filters)
+            .map(|_| parameter(dense_init(dim, dim, rng)))
+            .collect();
+
+        Parameters {
+            hyper: self,
+            num_users: None,
+            metadata: None,
+            item_embedding,
+            item_biases,
+            horizontal,
+            vertical_weights,
+            vertical_projection,
+            dense_biases: parameter(Arr::zeros((1, dim))),
+        }
+    }
+
+    /// Build a model out of the chosen hyperparameters.
+    pub fn build(self) -> ImplicitCNNModel {
+        ImplicitCNNModel {
+            params: self.build_params(),
+        }
+    }
+}
+
+/// The horizontal filters of one height `h`.
+#[derive(Debug, Serialize, Deserialize)]
+struct HorizontalFilters {
+    /// The `h` slices of the filters applied to each row of the window.
+    weights: Vec<Arc<wyrm::HogwildParameter>>,
+    biases: Arc<wyrm::HogwildParameter>,
+    /// Maps the pooled filter outputs to the embedding dimension.
+    projection: Arc<wyrm::HogwildParameter>,
+}
+
+impl Clone for HorizontalFilters {
+    fn clone(&self) -> Self {
+        HorizontalFilters {
+            weights: self.weights.iter().map(deep_clone).collect(),
+            biases: deep_clone(&self.biases),
+            projection: deep_clone(&self.projection),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Parameters {
+    hyper: Hyperparameters,
+    num_users: Option<usize>,
+    #[serde(default)]
+    metadata: Option<ModelMetadata>,
+    item_embedding: Arc<wyrm::HogwildParameter>,
+    item_biases: Arc<wyrm::HogwildParameter>,
+    horizontal: Vec<HorizontalFilters>,
+    /// One row of weights over the `L` positions per vertical filter.
+    vertical_weights: Arc<wyrm::HogwildParameter>,
+    /// Maps the output of each vertical filter to the embedding dimension.
+    vertical_projection: Vec<Arc<wyrm::HogwildParameter>>,
+    dense_biases: Arc<wyrm::HogwildParameter>,
+}
+
+impl Clone for Parameters {
+    fn clone(&self) -> Self {
+        Parameters {
+            hyper: self.hyper.clone(),
+            num_users: self.num_users,
+            metadata: self.metadata.clone(),
+            item_embedding: deep_clone(&self.item_embedding),
+            item_biases: deep_clone(&self.item_biases),
+            horizontal: self.horizontal.clone(),
+            vertical_weights: deep_clone(&self.vertical_weights),
+            vertical_projection: self.vertical_projection.iter().map(deep_clone).collect(),
+            dense_biases: deep_clone(&self.dense_biases),
+        }
+    }
+}
+
+impl Parameters {
+    /// The index of the padding embedding.
+    fn padding_idx(&self) -> usize {
+        self.hyper.num_items
+    }
+
+    /// Compute the representation from the embeddings of a window
+    /// of `L` items, oldest first.
+    fn convolve(&self, window: &[Variable<BoxedNode>]) -> Variable<BoxedNode> {
+        let dim = self.hyper.item_embedding_dim;
+        let length = window.len();
+
+        let mut terms = Vec::with_capacity(self.horizontal.len() + 1);
+
+        for (filters, height) in izip!(&self.horizontal, 1..) {
+            let weights: Vec<_> = filters.weights.iter().map(shared).collect();
+            let biases = shared(&filters.biases);
+
+            // Log-sum-exp pooling of the filter outputs at every position.
+            let pooled = sum((0..=length - height).map(|start| {
+                let convolved = sum(izip!(&window[start..start + height], &weights)
+                    .map(|(row, weight)| row.dot(weight).boxed()));
+
+                (convolved + biases.clone()).relu().exp().boxed()
+            }))
+            .ln();
+
+            terms.push(pooled.dot(&shared(&filters.projection)).boxed());
+        }
+
+        let vertical_weights = shared(&self.vertical_weights);
+        let broadcast = wyrm::InputNode::new(Arr::ones((1, dim)));
+        let selectors: Vec<_> = (0..length)
+            .map(|position| {
+                wyrm::InputNode::new(Arr::from_shape_fn((length, 1), |(row, _)| {
+                    if row == position {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                }))
+            })
+            .collect();
+
+        for (filter, projection) in self.vertical_projection.iter().enumerate() {
+            let filter_weights = vertical_weights.index(&wyrm::IndexInputNode::new(&[filter; 1]));
+
+            let weighted_sum = sum(izip!(window, &selectors).map(|(row, selector)| {
+                (filter_weights.dot(selector).dot(&broadcast) * row.clone()).boxed()
+            }));
+
+            terms.push(weighted_sum.dot(&shared(projection)).boxed());
+        }
+
+        (sum(terms) + shared(&self.dense_biases)).relu().boxed()
+    }
+}
+
+impl SequenceModelParameters for Parameters {
+    type Output = Model;
+    fn num_items(&self) -> usize {
+        self.hyper.num_items
+    }
+    fn num_users(&mut self) -> &mut Option<usize> {
+        &mut self.num_users
+    }
+    fn metadata(&mut self) -> &mut Option<ModelMetadata> {
+        &mut self.metadata
+    }
+    fn hyperparameters(&self) -> String {
+        serde_json::to_string(&self.hyper).unwrap_or_default()
+    }
+    fn max_sequence_length(&self) -> usize {
+        self.hyper.max_sequence_length
+    }
+    fn num_threads(&self) -> usize {
+        self.hyper.num_threads
+    }
+    fn accumulate_f64(&self) -> bool {
+        self.hyper.accumulate_f64
+    }
+    fn rng(&mut self) -> &mut XorShiftRng {
+        &mut self.hyper.rng
+    }
+    fn negative_sampler(&self) -> Option<&dyn MinibatchSampler> {
+        None
+    }
+    fn learning_rate(&self) -> f32 {
+        self.hyper.learning_rate
+    }
+    fn warmup_steps(&self) -> usize {
+        0
+    }
+    fn optimizer(&self, learning_rate: f32) -> Optimizers {
+        match self.hyper.optimizer {
+            Optimizer::Adagrad => Optimizers::Adagrad(
+                wyrm::optim::Adagrad::new()
+                    .learning_rate(learning_rate)
+                    .l2_penalty(self.hyper.l2_penalty),
+            ),
+
+            Optimizer::Adam => Optimizers::Adam(
+                wyrm::optim::Adam::new()
+                    .learning_rate(learning_rate)
+                    .l2_penalty(self.hyper.l2_penalty),
+            ),
+        }
+    }
+    fn parallelism(&self) -> &Parallelism {
+        &self.hyper.parallelism
+    }
+    fn loss(&self) -> &Loss {
+        &self.hyper.loss
+    }
+    fn num_epochs(&self) -> usize {
+        self.hyper.num_epochs
+    }
+    fn build(&self) -> Self::Output {
+        let length = self.hyper.history_length;
+
+        let item_embeddings = shared(&self.item_embedding);
+        let item_biases = shared(&self.item_biases);
+
+        let inputs: Vec<_> = (0..self.hyper.max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let outputs: Vec<_> = (0..self.hyper.max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let negatives: Vec<_> = (0..self.hyper.max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+
+        let padding = item_embeddings
+            .index(&wyrm::IndexInputNode::new(&[self.padding_idx(); 1]))
+            .boxed();
+        let input_embeddings: Vec<_> = inputs
+            .iter()
+            .map(|input| item_embeddings.index(input).boxed())
+            .collect();
+
+        let hidden_states: Vec<_> = (0..input_embeddings.len())
+            .map(|idx| {
+                // The window of the last `L` items up to `idx`, padded on the left.
+                let window: Vec<_> = (0..length)
+                    .map(|offset| match (idx + 1 + offset).checked_sub(length) {
+                        Some(position) => input_embeddings[position].clone(),
+                        None => padding.clone(),
+                    })
+                    .collect();
+
+                self.convolve(&window)
+            })
+            .collect();
+
+        let positive_predictions: Vec<_> = izip!(&hidden_states, &outputs)
+            .map(|(hidden_state, output)| {
+                hidden_state.vector_dot(&item_embeddings.index(output)) + item_biases.index(output)
+            })
+            .collect();
+        let negative_predictions: Vec<_> = izip!(&hidden_states, &negatives)
+            .map(|(hidden_state, negative)| {
+                hidden_state.vector_dot(&item_embeddings.index(negative))
+                    + item_biases.index(negative)
+            })
+            .collect();
+
+        let losses: Vec<_> = positive_predictions
+            .into_iter()
+            .zip(negative_predictions.into_iter())
+            .map(|(pos, neg)| match self.hyper.loss {
+                Loss::BPR => (neg - pos).sigmoid().boxed(),
+                Loss::Hinge | Loss::WARP => (1.0 + neg - pos).relu().boxed(),
+            })
+            .collect();
+
+        let mut summed_losses = Vec::with_capacity(losses.len());
+        summed_losses.push(losses[0].clone());
+
+        for loss in &losses[1..] {
+            let loss = (summed_losses.last().unwrap().clone() + loss.clone()).boxed();
+            summed_losses.push(loss);
+        }
+
+        Model {
+            inputs,
+            outputs,
+            negatives,
+            hidden_states,
+            summed_losses,
+        }
+    }
+    fn predict_single(&self, user: &[f32], item_idx: usize) -> f32 {
+        let embeddings = self.item_embedding.value();
+        let biases = self.item_biases.value();
+
+        let embedding = embeddings.row(item_idx);
+        let bias = biases[(item_idx, 0)];
+        let dot = wyrm::simd_dot(user, embedding.as_slice().unwrap());
+
+        bias + dot
+    }
+    fn predict_all(&self, user: &[f32], num_items: usize) -> Vec<f32> {
+        score_all_items(
+            self.item_embedding.value(),
+            self.item_biases.value(),
+            user,
+            num_items,
+        )
+    }
+}
+
+struct Model {
+    inputs: Vec<Variable<wyrm::IndexInputNode>>,
+    outputs: Vec<Variable<wyrm::IndexInputNode>>,
+    negatives: Vec<Variable<wyrm::IndexInputNode>>,
+    hidden_states: Vec<Variable<BoxedNode>>,
+    summed_losses: Vec<Variable<BoxedNode>>,
+}
+
+impl SequenceModel for Model {
+    fn state(
+        &self,
+    ) -> (
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<BoxedNode>],
+    ) {
+        (
+            &self.inputs,
+            &self.outputs,
+            &self.negatives,
+            &self.hidden_states,
+        )
+    }
+    fn losses(&mut self) -> &mut [Variable<BoxedNode>] {
+        &mut self.summed_losses
+    }
+    fn hidden_states(&mut self) -> &mut [Variable<BoxedNode>] {
+        &mut self.hidden_states
+    }
+    fn contexts(&self) -> &[Variable<wyrm::IndexInputNode>] {
+        &[]
+    }
+    fn time_deltas(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn dropout_masks(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn weights(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn set_users(&self, _user_ids: &[usize]) {}
+}
+
+/// A convolutional sequence model for implicit feedback.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImplicitCNNModel {
+    params: Parameters,
+}
+
+impl ImplicitCNNModel {
+    /// Fit the model.
+    ///
+    /// Returns the loss value.
+    #[cfg(feature = "training")]
+    pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<f32, FittingError> {
+        fit_sequence_model(interactions, &mut self.params)
+    }
+
+    /// Return the record of the last successful fit, or `None` if the
+    /// model has not been fitted. See [ModelMetadata].
+    pub fn metadata(&self) -> Option<&ModelMetadata> {
+        self.params.metadata.as_ref()
+    }
+
+    /// Return the record of the last successful fit mutably, for example
+    /// to add tags.
+    pub fn metadata_mut(&mut self) -> Option<&mut ModelMetadata> {
+        self.params.metadata.as_mut()
+    }
+}
+
+impl OnlineRankingModel for ImplicitCNNModel {
+    type UserRepresentation = ImplicitUser;
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        self.params.user_representation(item_ids)
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.params.predict(user, item_ids)
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.params.predict_all_items(user, num_items)
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use rand::distributions::Uniform;
+
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, Timestamp, UserId};
+
+    /// Every user walks through the items along `period` interleaved
+    /// paths, so the next item follows the one `period` steps back.
+    fn cyclic_data(num_users: usize, num_items: usize, period: usize) -> CompressedInteractions {
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+        let item_range = Uniform::new(0, num_items);
+
+        let mut interactions = Vec::new();
+        for user_id in 0..num_users {
+            let start = item_range.sample(&mut rng);
+            let mut paths: Vec<_> = (0..period)
+                .map(|path| start + path * num_items / period)
+                .collect();
+            rng.shuffle(&mut paths);
+
+            for t in 0..12 {
+                interactions.push(Interaction::new(
+                    UserId::from_index(user_id),
+                    ItemId::from_index((paths[t % period] + t / period) % num_items),
+                    Timestamp(t as i64),
+                ));
+            }
+        }
+
+        Interactions::from(interactions).to_compressed()
+    }
+
+    #[test]
+    fn converges_on_sequential_data() {
+        let data = cyclic_data(300, 40, 3);
+
+        let mut model = Hyperparameters::new(40, 12)
+            .embedding_dim(32)
+            .history_length(4)
+            .learning_rate(0.001)
+            .num_epochs(20)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+
+        let untrained_mrr = mrr_score(&model, &data).unwrap();
+        assert!(model.fit(&data).unwrap().is_finite());
+
+        // The next item follows the third-to-last one.
+        let mrr = mrr_score(&model, &data).unwrap();
+        println!("CNN MRR {} (untrained {})", mrr, untrained_mrr);
+        assert!(mrr > 0.5 && mrr > untrained_mrr);
+    }
+
+    #[test]
+    fn short_histories_are_padded() {
+        let model = Hyperparameters::new(10, 8)
+            .history_length(5)
+            .from_seed([42; 16])
+            .build();
+
+        // A single item is preceded by four padding embeddings.
+        let user = model.user_representation(&items(&[3])).unwrap();
+        assert_eq!(user.user_embedding.len(), 16);
+        assert!(user.user_embedding.iter().all(|x| x.is_finite()));
+
+        // Only the last five items matter.
+        let user = model
+            .user_representation(&items(&[1, 2, 3, 4, 5, 6]))
+            .unwrap();
+        let truncated = model.user_representation(&items(&[2, 3, 4, 5, 6])).unwrap();
+        assert!(izip!(&user.user_embedding, &truncated.user_embedding)
+            .all(|(x, y)| (x - y).abs() < 1e-6));
+
+        // The padding embedding is never scored.
+        assert_eq!(model.predict_all_items(&user, 10).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn serialization() {
+        let data = cyclic_data(10, 10, 3);
+
+        let mut model = Hyperparameters::new(10, 6)
+            .history_length(3)
+            .num_vertical_filters(0)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let predictions = model.predict(&user, &items(&[0, 1, 2, 3])).unwrap();
+
+        let serialized = serde_json::to_string(&model).unwrap();
+        let deserialized: ImplicitCNNModel = serde_json::from_str(&serialized).unwrap();
+        let user = deserialized
+            .user_representation(&items(&[1, 2, 3]))
+            .unwrap();
+        assert_eq!(
+            deserialized.predict(&user, &items(&[0, 1, 2, 3])).unwrap(),
+            predictions
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Content-based model scoring items by the similarity of their features.
+//!
+//! The model needs no interactions, so it can rank items that have never
+//! been interacted with. Each feature is embedded as a fixed random ±1
+//! vector derived by hashing its id; an item's vector is the normalized
+//! sum of its feature embeddings, and a user is the normalized mean of
+//! the vectors of the items in their history. Scores are the cosine
+//! similarity between the two.
+use std::hash::Hasher;
+
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher;
+
+use super::{ImplicitUser, ItemEmbeddings};
+use crate::data::ItemFeatures;
+use crate::ids::ItemId;
+use crate::{OnlineRankingModel, PredictionError};
+
+/// Content-based model over an item × feature matrix.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ContentModel {
+    embedding_dim: usize,
+    item_vectors: Vec<Vec<f32>>,
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+fn dot(x: &[f32], y: &[f32]) -> f32 {
+    x.iter().zip(y).map(|(x, y)| x * y).sum()
+}
+
+impl ContentModel {
+    /// Build the item vectors of `features`, embedding every feature in
+    /// `embedding_dim` dimensions using hashes keyed by `seed`.
+    ///
+    /// Items without features get a zero vector and score 0 for every user.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `embedding_dim` is zero.
+    pub fn new(features: &ItemFeatures, embedding_dim: usize, seed: u64) -> Self {
+        assert!(embedding_dim > 0, "Embedding dimension must be positive.");
+
+        let item_vectors = (0..features.num_items())
+            .map(|item_idx| {
+                let mut vector = vec![0.0; embedding_dim];
+
+                for &feature_id in features.item_features(ItemId::from_index(item_idx)) {
+                    for (dim, value) in vector.iter_mut().enumerate() {
+                        let mut hasher = SipHasher::new_with_keys(seed, feature_id as u64);
+                        hasher.write_usize(dim);
+
+                        *value += if hasher.finish() & 1 == 0 { 1.0 } else { -1.0 };
+                    }
+                }
+
+                normalize(&mut vector);
+                vector
+            })
+            .collect();
+
+        ContentModel {
+            embedding_dim,
+            item_vectors,
+        }
+    }
+
+    /// Return number of items.
+    pub fn num_items(&self) -> usize {
+        self.item_vectors.len()
+    }
+}
+
+impl ItemEmbeddings for ContentModel {
+    /// The normalized item vectors, with zero biases.
+    fn item_embeddings(&self) -> Array2<f32> {
+        Array2::from_shape_fn((self.num_items(), self.embedding_dim + 1), |(row, col)| {
+            self.item_vectors[row].get(col).cloned().unwrap_or(0.0)
+        })
+    }
+}
+
+impl OnlineRankingModel for ContentModel {
+    type UserRepresentation = ImplicitUser;
+
+    /// Unknown items in the history are ignored.
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        let mut user_embedding = vec![0.0; self.embedding_dim];
+
+        for vector in item_ids
+            .iter()
+            .filter_map(|id| self.item_vectors.get(id.index()))
+        {
+            for (user_value, value) in user_embedding.iter_mut().zip(vector) {
+                *user_value += value;
+            }
+        }
+
+        // Normalizing the sum gives the same direction as the mean.
+        normalize(&mut user_embedding);
+
+        Ok(ImplicitUser {
+            user_embedding,
+            num_interactions: item_ids.len(),
+            user_bias: None,
+        })
+    }
+
+    /// Return the cosine similarity of each item to the user. Unknown
+    /// items score 0.
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        item_ids
+            .iter()
+            .map(|&item_id| {
+                let score = self
+                    .item_vectors
+                    .get(item_id.index())
+                    .map(|vector| dot(&user.user_embedding, vector))
+                    .unwrap_or(0.0);
+
+                if score.is_finite() {
+                    Ok(score)
+                } else {
+                    Err(PredictionError::InvalidPredictionValue)
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::items;
+
+    #[test]
+    fn shared_features_outrank_unrelated() {
+        let mut features = ItemFeatures::new(4, 6);
+        // Items 0 and 1 are in the history.
+        features.push(ItemId(0), 0);
+        features.push(ItemId(0), 1);
+        features.push(ItemId(1), 1);
+        features.push(ItemId(1), 2);
+        // Item 2 shares features with the history, item 3 does not.
+        features.push(ItemId(2), 0);
+        features.push(ItemId(2), 2);
+        features.push(ItemId(3), 4);
+        features.push(ItemId(3), 5);
+
+        let model = ContentModel::new(&features, 64, 42);
+        let user = model.user_representation(&items(&[0, 1])).unwrap();
+        let scores = model.predict(&user, &items(&[2, 3])).unwrap();
+
+        assert!(scores[0] > scores[1]);
+        assert!(scores.iter().all(|score| score.abs() <= 1.0 + 1e-5));
+    }
+
+    #[test]
+    fn featureless_items_score_zero() {
+        let mut features = ItemFeatures::new(3, 2);
+        features.push(ItemId(0), 0);
+        features.push(ItemId(1), 1);
+
+        let model = ContentModel::new(&features, 16, 0);
+        let user = model.user_representation(&items(&[0])).unwrap();
+
+        assert_eq!(
+            model.predict(&user, &items(&[2, 10])).unwrap(),
+            vec![0.0, 0.0]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Distillation of fast models from slower, more accurate ones.
+//!
+//! [distill_ewma] trains an EWMA student to mimic a trained LSTM teacher by
+//! score matching: rather than ranking the observed items above sampled
+//! ones, the student is trained to give the observed items and sampled
+//! negatives the same scores as the teacher. The student therefore learns
+//! to predict what the teacher would recommend, including the teacher's
+//! smoothing of noise in the raw data.
+use super::ewma::{Hyperparameters, ImplicitEWMAModel};
+use super::lstm::ImplicitLSTMModel;
+use crate::data::CompressedInteractions;
+use crate::evaluation::mrr_score;
+use crate::Error;
+
+/// The result of [distill_ewma].
+#[derive(Debug)]
+pub struct Distillation {
+    /// The fitted student model.
+    pub student: ImplicitEWMAModel,
+    /// The student's mean squared difference from the teacher's scores
+    /// in training.
+    pub distillation_loss: f32,
+    /// The student's MRR on the validation set.
+    pub validation_mrr: f32,
+}
+
+/// Fit an EWMA model built from `hyperparameters` to match the scores of
+/// `teacher` on the sequences of `train`, and evaluate it on `validation`.
+///
+/// See [ImplicitEWMAModel::fit_to_teacher].
+pub fn distill_ewma(
+    teacher: &ImplicitLSTMModel,
+    train: &CompressedInteractions,
+    validation: &CompressedInteractions,
+    hyperparameters: Hyperparameters,
+) -> Result<Distillation, Error> {
+    let mut student = hyperparameters.build();
+    let distillation_loss = student.fit_to_teacher(train, teacher)?;
+    let validation_mrr = mrr_score(&student, validation)?;
+
+    Ok(Distillation {
+        student,
+        distillation_loss,
+        validation_mrr,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::ids::{ItemId, Timestamp, UserId};
+    use crate::models::{lstm, Loss, Optimizer};
+    use crate::OnlineRankingModel;
+
+    /// Users step through the items in order, but a fraction of
+    /// interactions are replaced by random items.
+    fn noisy_data(
+        num_users: usize,
+        num_items: usize,
+        noise: f32,
+        rng: &mut XorShiftRng,
+    ) -> Interactions {
+        let mut interactions = Vec::new();
+
+        for user_id in 0..num_users {
+            for timestamp in 0..15 {
+                let item_id = if rng.gen::<f32>() < noise {
+                    rng.gen_range(0, num_items)
+                } else {
+                    (user_id + timestamp) % num_items
+                };
+                interactions.push(Interaction::new(
+                    UserId::from_index(user_id),
+                    ItemId::from_index(item_id),
+                    Timestamp(timestamp as i64),
+                ));
+            }
+        }
+
+        Interactions::from(interactions)
+    }
+
+    /// The mean squared difference between the scores `student` and
+    /// `teacher` give every item after each user's history.
+    fn score_gap(
+        student: &ImplicitEWMAModel,
+        teacher: &ImplicitLSTMModel,
+        data: &CompressedInteractions,
+    ) -> f32 {
+        let items: Vec<_> = (0..data.num_items()).map(ItemId::from_index).collect();
+        let mut total = 0.0;
+        let mut count = 0;
+
+        for user in data.iter_users() {
+            let student_user = student.user_representation(user.item_ids).unwrap();
+            let teacher_user = teacher.user_representation(user.item_ids).unwrap();
+            let student_scores = student.predict(&student_user, &items).unwrap();
+            let teacher_scores = teacher.predict(&teacher_user, &items).unwrap();
+
+            for (student_score, teacher_score) in student_scores.iter().zip(&teacher_scores) {
+                total += (student_score - teacher_score).powi(2);
+                count += 1;
+            }
+        }
+
+        total / count as f32
+    }
+
+    #[test]
+    fn distilled_student_beats_labels() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let num_items = 20;
+
+        let train = noisy_data(200, num_items, 0.4, &mut rng).to_compressed();
+        let validation = noisy_data(50, num_items, 0.0, &mut rng).to_compressed();
+
+        let mut teacher = lstm::Hyperparameters::new(num_items, 16)
+            .embedding_dim(16)
+            .learning_rate(0.1)
+            .loss(Loss::Hinge)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(20)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        teacher.fit(&train).unwrap();
+
+        let hyperparameters = Hyperparameters::new(num_items, 16)
+            .embedding_dim(16)
+            .learning_rate(0.1)
+            .loss(Loss::Hinge)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(20)
+            .num_threads(1)
+            .from_seed([42; 16]);
+
+        let mut baseline = hyperparameters.clone().build();
+        baseline.fit(&train).unwrap();
+        let baseline_mrr = mrr_score(&baseline, &validation).unwrap();
+
+        let distillation =
+            distill_ewma(&teacher, &train, &validation, hyperparameters.clone()).unwrap();
+
+        assert!(distillation.distillation_loss.is_finite());
+        assert!(distillation.validation_mrr > baseline_mrr);
+
+        // The student's scores move towards the teacher's as it trains,
+        // ending closer to them than those of the model trained on labels.
+        let short =
+            distill_ewma(&teacher, &train, &validation, hyperparameters.num_epochs(1)).unwrap();
+        let gap = score_gap(&distillation.student, &teacher, &validation);
+        assert!(gap < score_gap(&short.student, &teacher, &validation));
+        assert!(gap < score_gap(&baseline, &teacher, &validation));
+    }
+}
@@ -8,27 +8,101 @@
 //!
 //! The representation of a user at time t is given by an n-dimensional vector u:
 //! ```text
-//! u_t = sigmoid(alpha) * i_{t-1} + (1.0 - sigmoid(alpha)) + i_t
+//! u_t = sigmoid(alpha) * u_{t-1} + (1.0 - sigmoid(alpha)) * i_t
 //! ```
 //! where `i_t` is the embedding of the item the user interacted with at time `t`.
-use std::sync::Arc;
+//!
+//! The effective decay `1.0 - sigmoid(alpha)`, the weight given to the most recent
+//! item, can be restricted to a narrower range with [Hyperparameters::decay_bounds].
+//!
+//! Alternatively, the decay can be fixed with [Hyperparameters::fixed_decay], in which
+//! case the representation is the average of past item embeddings weighted by
+//! `(1 - alpha)^k` for the item `k` steps in the past.
+//!
+//! With [Hyperparameters::num_timescales], several averages with separately learned
+//! decays are maintained and combined by a learned convex combination.
+//!
+//! Finally, [Pooling::LearnedPositions] replaces the exponential decay with
+//! learned weights for each of the last `n` positions.
+//!
+//! With [Hyperparameters::time_aware], the state is instead decayed by
+//! `0.5^(dt / half_life)` between interactions `dt` time units apart.
+//!
+//! ## Sparse updates
+//! Each training step only reads the embeddings and biases of the items in the
+//! current sequence. Their gradients are row-sparse, and the optimizers only
+//! update (and apply the L2 penalty to) the rows that were touched, so the cost
+//! of a step does not grow with the number of items. As a consequence, the L2
+//! penalty is only applied to an item's parameters when the item appears in
+//! a training sequence, rather than to the whole table at every step.
+//!
+//! ## Parallelism
+//! Training runs [Hyperparameters::num_threads] workers over disjoint
+//! partitions of the sequences, each through a computation graph built once
+//! per thread; see [Hyperparameters::parallelism] for how the workers share
+//! parameters. Within a worker, each step processes
+//! [Hyperparameters::sequence_batch_size] sequences at once as the rows of a
+//! single matrix, and the optimizer updates once per step.
+//!
+//! ## User biases
+//! With [Hyperparameters::use_user_biases], each user seen in training also has
+//! a learned bias, added to all of the user's scores. All supported losses depend
+//! only on the difference between a positive and a negative item's score for the
+//! same user, so the bias cancels out of them. Each step therefore also has a
+//! logistic loss on the scores themselves, labelling its target as positive and
+//! its sampled negative as negative. The negatives of each user are weighted so that all users
+//! have the same total weight of negatives, as if they had all been shown the same
+//! number of items: users who interact with many items get higher biases than
+//! users who interact with few, instead of the embeddings absorbing the difference.
+use std::f32::consts::LN_2;
+use std::io::{self, Read, Write};
+use std::iter;
+#[cfg(feature = "onnx")]
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+#[cfg(feature = "training")]
+use std::time::Instant;
 
 use rand;
 use rand::distributions::{Distribution, Normal, Uniform};
 use rand::{Rng, SeedableRng, XorShiftRng};
-use rayon;
 use serde::{Deserialize, Serialize};
 
-use ndarray::Axis;
+use ndarray::Array2;
 
 use wyrm;
 use wyrm::optim::Optimizers;
-use wyrm::{Arr, BoxedNode, Variable};
-
-use super::sequence_model::{fit_sequence_model, SequenceModel, SequenceModelParameters};
-use super::{ImplicitUser, Loss, Optimizer, Parallelism};
+use wyrm::{Arr, BoxedNode, DataInput, Variable};
+
+use super::format::{self, ModelLoadError};
+use super::json::{self, ExportError};
+use super::metadata::ModelMetadata;
+#[cfg(feature = "training")]
+use super::metadata::{DatasetStats, FitStats};
+#[cfg(feature = "onnx")]
+use super::onnx::{OnnxError, PoolingGraph};
+use super::sampling::MinibatchSampler;
+#[cfg(feature = "training")]
+use super::sampling::UniformSampler;
+use super::sequence_model::{
+    check_item_ids, predict_candidates, score_all_items, sequence_representation, SequenceModel,
+    SequenceModelParameters,
+};
+#[cfg(feature = "training")]
+use super::sequence_model::{
+    fit_sequence_model, invalid_hyperparameter, sequence_perplexity, set_time_deltas, set_user,
+    validate_interactions,
+};
+use super::{scoring_vectors, ImplicitUser, ItemEmbeddings, Loss, Optimizer, Parallelism};
+use crate::config;
+#[cfg(feature = "training")]
 use crate::data::CompressedInteractions;
-use crate::{FittingError, ItemId, OnlineRankingModel, PredictionError};
+use crate::ids::{ItemId, Timestamp, UserId};
+use crate::rng::SeedSequence;
+#[cfg(feature = "training")]
+use crate::{Error, FittingError};
+use crate::{OnlineRankingModel, PredictionError};
 
 fn embedding_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
     let normal = Normal::new(0.0, 1.0 / cols as f64);
@@ -40,6 +114,46 @@ fn dense_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
     Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
 }
 
+/// How past item embeddings are pooled into the user representation.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Pooling {
+    /// Exponentially-weighted moving average.
+    #[default]
+    Ewma,
+    /// Weighted sum of the last `n` item embeddings, with one learned
+    /// weight per position. The weights are softmax-normalized over
+    /// the positions available.
+    LearnedPositions {
+        /// Number of positions.
+        n: usize,
+    },
+}
+
+/// How to initialize the parameters of new items added by
+/// [ImplicitEWMAModel::grow_items].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GrowInit {
+    /// Random embeddings, initialized as in a new model, and zero biases.
+    Random,
+    /// Zero embeddings and biases.
+    Zeros,
+    /// The mean embedding and bias of the existing items.
+    MeanOfExisting,
+}
+
+/// How item ids the model does not know, such as items added to the
+/// catalog since it was fitted, are treated when computing representations
+/// and scores.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum UnknownItems {
+    /// Return [PredictionError::ItemIdOutOfRange].
+    #[default]
+    Error,
+    /// Treat them as an item with the mean embedding and bias of the known
+    /// items, both in histories and when scoring.
+    MeanItem,
+}
+
 /// Hyperparameters describing the EWMA model.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Hyperparameters {
@@ -54,6 +168,72 @@ pub struct Hyperparameters {
     rng: XorShiftRng,
     num_threads: usize,
     num_epochs: usize,
+    #[serde(default)]
+    accumulate_f64: bool,
+    #[serde(default)]
+    warmup_steps: usize,
+    #[serde(skip)]
+    negative_sampler: Option<Arc<dyn MinibatchSampler>>,
+    #[serde(default)]
+    fixed_decay: Option<f32>,
+    #[serde(default)]
+    decay_init: Option<f32>,
+    #[serde(default = "default_decay_bounds")]
+    decay_bounds: (f32, f32),
+    #[serde(default)]
+    half_life_init: Option<f32>,
+    #[serde(default = "default_learn_half_life")]
+    learn_half_life: bool,
+    #[serde(default = "default_num_timescales")]
+    num_timescales: usize,
+    #[serde(default)]
+    pooling: Pooling,
+    #[serde(default)]
+    max_history: Option<usize>,
+    #[serde(default = "default_bias_term")]
+    bias_term: bool,
+    #[serde(default)]
+    popularity_bias_init: bool,
+    #[serde(default)]
+    anneal_alpha: Option<f32>,
+    #[serde(default = "default_sequence_batch_size")]
+    sequence_batch_size: usize,
+    #[serde(default)]
+    unknown_items: UnknownItems,
+    #[serde(default)]
+    use_user_biases: bool,
+    #[serde(skip)]
+    cancellation_flag: Option<Arc<AtomicBool>>,
+}
+
+fn default_decay_bounds() -> (f32, f32) {
+    (0.0, 1.0)
+}
+
+fn default_learn_half_life() -> bool {
+    true
+}
+
+fn default_num_timescales() -> usize {
+    1
+}
+
+fn default_bias_term() -> bool {
+    true
+}
+
+fn default_sequence_batch_size() -> usize {
+    1
+}
+
+/// Invert `decay = lo + (hi - lo) * (1 - sigmoid(alpha))`.
+fn alpha_for_decay(decay: f32, lo: f32, hi: f32) -> f32 {
+    let weight = 1.0 - (decay - lo) / (hi - lo);
+    (weight / (1.0 - weight)).ln()
+}
+
+fn effective_decay(alpha: f32, lo: f32, hi: f32) -> f32 {
+    lo + (hi - lo) * (1.0 - 1.0 / (1.0 + (-alpha).exp()))
 }
 
 impl Hyperparameters {
@@ -69,8 +249,26 @@ impl Hyperparameters {
             optimizer: Optimizer::Adam,
             parallelism: Parallelism::Synchronous,
             rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
-            num_threads: rayon::current_num_threads(),
+            num_threads: config::num_threads(),
             num_epochs: 10,
+            accumulate_f64: false,
+            warmup_steps: 0,
+            negative_sampler: None,
+            fixed_decay: None,
+            decay_init: None,
+            decay_bounds: default_decay_bounds(),
+            half_life_init: None,
+            learn_half_life: default_learn_half_life(),
+            num_timescales: default_num_timescales(),
+            pooling: Pooling::Ewma,
+            max_history: None,
+            bias_term: default_bias_term(),
+            popularity_bias_init: false,
+            anneal_alpha: None,
+            sequence_batch_size: default_sequence_batch_size(),
+            unknown_items: UnknownItems::Error,
+            use_user_biases: false,
+            cancellation_flag: None,
         }
     }
 
@@ -98,18 +296,220 @@ impl Hyperparameters {
         self
     }
 
+    /// Set the number of minibatches over which the learning rate is
+    /// linearly ramped up from zero to its configured value. Warmups longer
+    /// than 100 steps ramp up in 100 equal increments.
+    pub fn warmup_steps(mut self, warmup_steps: usize) -> Self {
+        self.warmup_steps = warmup_steps;
+        self
+    }
+
+    /// Use a fixed, non-learned decay rate `alpha`. As `alpha` approaches
+    /// 1.0 the representation approaches the last item's embedding; as it
+    /// approaches 0.0 it approaches the average of all past item embeddings.
+    ///
+    /// By default, the decay rate is learned. Must lie in `(0, 1)`, or
+    /// fitting returns [FittingError::InvalidHyperparameter].
+    pub fn fixed_decay(mut self, alpha: f32) -> Self {
+        self.fixed_decay = Some(alpha);
+        self
+    }
+
+    /// Set the initial effective decay of the learned decay rate: the
+    /// weight given to the most recent item. Must lie strictly within
+    /// the decay bounds, or fitting returns
+    /// [FittingError::InvalidHyperparameter]. Defaults to the midpoint of
+    /// the bounds.
+    pub fn decay_init(mut self, decay_init: f32) -> Self {
+        self.decay_init = Some(decay_init);
+        self
+    }
+
+    /// Restrict the effective learned decay to `[lo, hi]` throughout
+    /// training, so that it cannot saturate at 0 or 1. Defaults to `(0, 1)`.
+    ///
+    /// Fitting returns [FittingError::InvalidHyperparameter] unless
+    /// `0 <= lo < hi <= 1`.
+    pub fn decay_bounds(mut self, lo: f32, hi: f32) -> Self {
+        self.decay_bounds = (lo, hi);
+        self
+    }
+
+    /// Maintain `num_timescales` exponentially-weighted averages with
+    /// separately learned decays, so that both short-term and long-term
+    /// interests can be captured. The user representation is a learned
+    /// convex combination of the averages. Defaults to 1.
+    ///
+    /// The initial decays are spread evenly within the decay bounds
+    /// when there is more than one timescale. Only applies to learned
+    /// decays. Fitting returns [FittingError::InvalidHyperparameter] if
+    /// `num_timescales` is zero.
+    pub fn num_timescales(mut self, num_timescales: usize) -> Self {
+        self.num_timescales = num_timescales;
+        self
+    }
+
+    /// Set how past item embeddings are pooled. Defaults to [Pooling::Ewma];
+    /// [Pooling::LearnedPositions] takes precedence over all decay settings.
+    ///
+    /// Fitting returns [FittingError::InvalidHyperparameter] if
+    /// [Pooling::LearnedPositions] has zero positions.
+    pub fn pooling(mut self, pooling: Pooling) -> Self {
+        self.pooling = pooling;
+        self
+    }
+
+    /// Decay the state according to the time elapsed between interactions
+    /// rather than per interaction: the state is multiplied by
+    /// `0.5^(dt / half_life)` between interactions `dt` time units apart,
+    /// starting from a half-life of `half_life_init`. Takes precedence
+    /// over [Hyperparameters::fixed_decay].
+    ///
+    /// Timestamps are only available to [ImplicitEWMAModel::user_representation_with_timestamps];
+    /// other representations assume interactions one time unit apart.
+    ///
+    /// Fitting returns [FittingError::InvalidHyperparameter] if
+    /// `half_life_init` is not positive and finite.
+    pub fn time_aware(mut self, half_life_init: f32) -> Self {
+        self.half_life_init = Some(half_life_init);
+        self
+    }
+
+    /// Set whether the half-life of [Hyperparameters::time_aware] decay
+    /// is learned. Defaults to true.
+    pub fn learn_half_life(mut self, learn_half_life: bool) -> Self {
+        self.learn_half_life = learn_half_life;
+        self
+    }
+
+    /// Only use each user's `max_history` most recent interactions, both
+    /// when fitting and when computing user representations. Defaults to
+    /// unlimited.
+    ///
+    /// Older interactions are geometrically down-weighted by the decay, so
+    /// a generous cap changes the fitted model very little while making
+    /// fitting on long histories proportionally faster.
+    ///
+    /// Fitting returns [FittingError::InvalidHyperparameter] if
+    /// `max_history` is zero.
+    pub fn max_history(mut self, max_history: usize) -> Self {
+        self.max_history = Some(max_history);
+        self
+    }
+
+    /// Set whether each item has a learned bias, capturing its popularity,
+    /// added to its score. Defaults to true.
+    ///
+    /// When enabled, the biases start at zero and are regularized by the
+    /// l2 penalty like all other parameters. When disabled, the biases stay
+    /// at zero.
+    pub fn bias_term(mut self, enabled: bool) -> Self {
+        self.bias_term = enabled;
+        self
+    }
+
+    /// Initialize the item biases to the log of one plus each item's number
+    /// of training interactions on the first call to `fit`, rather than to
+    /// zero. Has no effect if the bias term is disabled. Defaults to false.
+    pub fn popularity_bias_init(mut self, enabled: bool) -> Self {
+        self.popularity_bias_init = enabled;
+        self
+    }
+
+    /// Anneal the decay rate during training: when `enabled`, the
+    /// effective decay starts at `start_alpha` and increases linearly to
+    /// the fixed or learned decay over the first half of the epochs,
+    /// then stays there. Predictions always use the target decay.
+    ///
+    /// A `start_alpha` of 0.0 starts from the plain average of past item
+    /// embeddings with [Hyperparameters::fixed_decay]. Only applies to
+    /// exponential decays: time-aware decay and [Pooling::LearnedPositions]
+    /// are not annealed. Disabled by default.
+    ///
+    /// Fitting returns [FittingError::InvalidHyperparameter] if annealing
+    /// is enabled and `start_alpha` is not in `[0, 1]`.
+    pub fn anneal_alpha(mut self, enabled: bool, start_alpha: f32) -> Self {
+        self.anneal_alpha = if enabled { Some(start_alpha) } else { None };
+        self
+    }
+
+    /// Set how item ids the model does not know are treated. Defaults to
+    /// [UnknownItems::Error].
+    ///
+    /// After [ImplicitEWMAModel::grow_items], the new ids are known and
+    /// scored with their own parameters.
+    pub fn unknown_items(mut self, unknown_items: UnknownItems) -> Self {
+        self.unknown_items = unknown_items;
+        self
+    }
+
+    /// Set whether each user has a learned bias, added to all of the user's
+    /// scores. Defaults to false.
+    ///
+    /// The biases are created by the first fit, one per user of its data,
+    /// and are trained by an additional logistic loss on the scores; see the
+    /// [module documentation](self#user-biases). Only representations from
+    /// [ImplicitEWMAModel::user_representation_for_user] carry the bias; all
+    /// others, and those of unknown users, score as if the bias were zero.
+    pub fn use_user_biases(mut self, enabled: bool) -> Self {
+        self.use_user_biases = enabled;
+        self
+    }
+
     /// Set the loss function.
     pub fn loss(mut self, loss: Loss) -> Self {
         self.loss = loss;
         self
     }
 
+    /// Set the strategy used to sample negative items during training.
+    /// Defaults to sampling uniformly from all items.
+    ///
+    /// The sampler is not serialized: deserialized models fall back
+    /// to uniform sampling.
+    pub fn negative_sampler(mut self, sampler: Box<dyn MinibatchSampler>) -> Self {
+        self.negative_sampler = Some(Arc::from(sampler));
+        self
+    }
+
+    /// Set a flag that cancels fitting when set: `fit` stops at the start
+    /// of the next epoch and returns [FittingError::Cancelled], keeping the
+    /// updates made so far. The flag is not serialized.
+    pub fn cancellation_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancellation_flag = Some(flag);
+        self
+    }
+
     /// Set number of threads to be used.
     pub fn num_threads(mut self, num_threads: usize) -> Self {
         self.num_threads = num_threads;
         self
     }
 
+    /// Set the number of sequences processed together in each training
+    /// step. Defaults to 1.
+    ///
+    /// The sequences of a step go through the model as the rows of one
+    /// matrix, shorter ones padded with steps that do not count towards the
+    /// loss, and the optimizer updates once with their summed gradients.
+    /// Larger batches use each core better, at the cost of fewer updates
+    /// per epoch.
+    pub fn sequence_batch_size(mut self, batch_size: usize) -> Self {
+        self.sequence_batch_size = batch_size;
+        self
+    }
+
+    /// Accumulate training losses in `f64` rather than `f32`. Defaults to
+    /// false.
+    ///
+    /// Summed in `f32`, many small losses lose precision, so that the
+    /// reported loss depends on summation order. Parameters stay `f32`
+    /// either way.
+    pub fn accumulate_f64(mut self, enabled: bool) -> Self {
+        self.accumulate_f64 = enabled;
+        self
+    }
+
     /// Set the type of paralellism.
     pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
         self.parallelism = parallelism;
@@ -129,6 +529,13 @@ impl Hyperparameters {
         self
     }
 
+    /// Set the random number generator to the one named `"ewma"` in
+    /// `seeds`, so that a single seed pins the whole experiment.
+    pub fn seed_sequence(mut self, seeds: &SeedSequence) -> Self {
+        self.rng = seeds.derive("ewma");
+        self
+    }
+
     /// Set the optimizer type.
     pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
         self.optimizer = optimizer;
@@ -159,11 +566,35 @@ impl Hyperparameters {
                 Parallelism::Synchronous
             },
             rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
-            num_threads: Uniform::new(1, rayon::current_num_threads() + 1).sample(rng),
+            num_threads: Uniform::new(1, config::num_threads() + 1).sample(rng),
             num_epochs: 2_usize.pow(Uniform::new(3, 7).sample(rng)),
+            accumulate_f64: false,
+            warmup_steps: 0,
+            negative_sampler: None,
+            fixed_decay: None,
+            decay_init: None,
+            decay_bounds: default_decay_bounds(),
+            half_life_init: None,
+            learn_half_life: default_learn_half_life(),
+            num_timescales: default_num_timescales(),
+            pooling: Pooling::Ewma,
+            max_history: None,
+            bias_term: default_bias_term(),
+            popularity_bias_init: false,
+            anneal_alpha: None,
+            sequence_batch_size: default_sequence_batch_size(),
+            unknown_items: UnknownItems::Error,
+            use_user_biases: false,
+            cancellation_flag: None,
         }
     }
 
+    fn is_cancelled(&self) -> bool {
+        self.cancellation_flag
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::SeqCst))
+    }
+
     fn build_params(mut self) -> Parameters {
         let item_embeddings = Arc::new(wyrm::HogwildParameter::new(embedding_init(
             self.num_items,
@@ -172,10 +603,53 @@ impl Hyperparameters {
         )));
 
         let item_biases = Arc::new(wyrm::HogwildParameter::new(Arr::zeros((self.num_items, 1))));
-        let alpha = Arc::new(wyrm::HogwildParameter::new(Arr::zeros((
-            1,
-            self.item_embedding_dim,
-        ))));
+        let (lo, hi) = self.decay_bounds;
+        // Invalid decay settings, including zero timescales, are rejected
+        // when fitting.
+        let alpha_inits: Vec<f32> = match self.decay_init {
+            Some(decay_init) if self.num_timescales <= 1 && decay_init > lo && decay_init < hi => {
+                vec![alpha_for_decay(decay_init, lo, hi)]
+            }
+            _ if self.num_timescales <= 1 => vec![0.0],
+            _ => (0..self.num_timescales)
+                .map(|idx| {
+                    let fraction = (idx + 1) as f32 / (self.num_timescales + 1) as f32;
+                    alpha_for_decay(lo + (hi - lo) * fraction, lo, hi)
+                })
+                .collect(),
+        };
+        let mut alphas = alpha_inits.iter().map(|&alpha_init| {
+            Arc::new(wyrm::HogwildParameter::new(
+                Arr::zeros((1, self.item_embedding_dim)).map(|_| alpha_init),
+            ))
+        });
+        let alpha = alphas.next().unwrap();
+        let timescale_alphas: Vec<_> = alphas.collect();
+        let timescale_mixing: Vec<_> = if self.num_timescales > 1 {
+            (0..self.num_timescales)
+                .map(|_| {
+                    Arc::new(wyrm::HogwildParameter::new(Arr::zeros((
+                        1,
+                        self.item_embedding_dim,
+                    ))))
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        // Stored as the log of the inverse half-life, so that the
+        // half-life stays positive during training.
+        let time_decay = self.half_life_init.map(|half_life| {
+            Arc::new(wyrm::HogwildParameter::new(
+                Arr::zeros((1, self.item_embedding_dim)).map(|_| -half_life.ln()),
+            ))
+        });
+        let position_logits = match self.pooling {
+            Pooling::LearnedPositions { n } => {
+                Some(Arc::new(wyrm::HogwildParameter::new(Arr::zeros((1, n)))))
+            }
+            Pooling::Ewma => None,
+        };
         let fc1 = Arc::new(wyrm::HogwildParameter::new(dense_init(
             self.item_embedding_dim,
             self.item_embedding_dim,
@@ -189,11 +663,20 @@ impl Hyperparameters {
 
         Parameters {
             hyper: self,
+            num_users: None,
+            metadata: None,
             item_embedding: item_embeddings,
             item_biases,
             alpha,
+            timescale_alphas,
+            timescale_mixing,
+            time_decay,
+            position_logits,
+            user_biases: None,
+            negative_weights: None,
             fc1,
             fc2,
+            mean_item: OnceLock::new(),
         }
     }
 
@@ -208,48 +691,248 @@ impl Hyperparameters {
 #[derive(Debug, Serialize, Deserialize)]
 struct Parameters {
     hyper: Hyperparameters,
+    #[serde(default)]
+    num_users: Option<usize>,
+    #[serde(default)]
+    metadata: Option<ModelMetadata>,
     item_embedding: Arc<wyrm::HogwildParameter>,
     item_biases: Arc<wyrm::HogwildParameter>,
     alpha: Arc<wyrm::HogwildParameter>,
+    /// Decays of the timescales after the first.
+    #[serde(default)]
+    timescale_alphas: Vec<Arc<wyrm::HogwildParameter>>,
+    /// Unnormalized log-weights of each timescale. Empty if
+    /// there is a single timescale.
+    #[serde(default)]
+    timescale_mixing: Vec<Arc<wyrm::HogwildParameter>>,
+    #[serde(default)]
+    time_decay: Option<Arc<wyrm::HogwildParameter>>,
+    /// Unnormalized log-weights of each position, most recent first.
+    #[serde(default)]
+    position_logits: Option<Arc<wyrm::HogwildParameter>>,
+    /// One bias per user, if the model learns user biases and has been
+    /// fitted.
+    #[serde(default)]
+    user_biases: Option<Arc<wyrm::HogwildParameter>>,
+    /// Weight of each user's negatives in the user bias loss, set when
+    /// fitting.
+    #[serde(skip)]
+    negative_weights: Option<Arc<Vec<f32>>>,
     fc1: Arc<wyrm::HogwildParameter>,
     fc2: Arc<wyrm::HogwildParameter>,
+    /// The mean item, computed on first use and reset whenever the item
+    /// embeddings or biases change.
+    #[serde(skip)]
+    mean_item: OnceLock<MeanItem>,
+}
+
+/// The embedding and bias standing for item ids the model does not know.
+#[derive(Clone, Debug)]
+struct MeanItem {
+    embedding: Vec<f32>,
+    bias: f32,
+}
+
+impl Parameters {
+    /// The mean embedding and bias of the known items.
+    fn mean_item(&self) -> &MeanItem {
+        self.mean_item.get_or_init(|| {
+            let embeddings = self.item_embedding.value();
+            let biases = self.item_biases.value();
+            let count = self.hyper.num_items.max(1) as f32;
+
+            MeanItem {
+                embedding: (0..self.hyper.item_embedding_dim)
+                    .map(|col| embeddings.column(col).scalar_sum() / count)
+                    .collect(),
+                bias: biases.scalar_sum() / count,
+            }
+        })
+    }
+
+    /// If the model learns user biases, create zero biases for the users of
+    /// `interactions` unless the model has been fitted already, and weight
+    /// each user's negatives by the mean number of interactions per user
+    /// over the user's own.
+    #[cfg(feature = "training")]
+    fn init_user_biases(&mut self, interactions: &CompressedInteractions) {
+        if !self.hyper.use_user_biases {
+            return;
+        }
+
+        let num_users = interactions.num_users();
+        if self.num_users.is_none() {
+            self.user_biases = Some(Arc::new(wyrm::HogwildParameter::new(Arr::zeros((
+                num_users, 1,
+            )))));
+        }
+
+        let mut counts = vec![0.0; num_users];
+        for user in interactions.iter_users() {
+            counts[user.user_id.index()] = user.item_ids.len() as f32;
+        }
+        let num_active = counts.iter().filter(|&&count| count > 0.0).count();
+        let mean_count = counts.iter().sum::<f32>() / num_active.max(1) as f32;
+
+        self.negative_weights = Some(Arc::new(
+            counts
+                .iter()
+                .map(|&count| if count > 0.0 { mean_count / count } else { 1.0 })
+                .collect(),
+        ));
+    }
+
+    /// Return parameters whose items are those of `item_ids`, in order,
+    /// with unknown ids standing for the mean item, so that pooling items
+    /// `0..item_ids.len()` pools `item_ids`. All other parameters are
+    /// shared with `self`.
+    fn history_parameters(&self, item_ids: &[ItemId]) -> Parameters {
+        let num_items = self.hyper.num_items;
+        let embeddings = self.item_embedding.value();
+        let biases = self.item_biases.value();
+        let mean_item = self.mean_item();
+
+        let item_embedding = Arr::from_shape_fn(
+            (item_ids.len(), self.hyper.item_embedding_dim),
+            |(row, col)| match item_ids[row].index() {
+                idx if idx < num_items => embeddings[(idx, col)],
+                _ => mean_item.embedding[col],
+            },
+        );
+        let item_biases = Arr::from_shape_fn((item_ids.len(), 1), |(row, _)| {
+            match item_ids[row].index() {
+                idx if idx < num_items => biases[(idx, 0)],
+                _ => mean_item.bias,
+            }
+        });
+
+        Parameters {
+            hyper: Hyperparameters {
+                num_items: item_ids.len(),
+                ..self.hyper.clone()
+            },
+            num_users: self.num_users,
+            metadata: None,
+            item_embedding: Arc::new(wyrm::HogwildParameter::new(item_embedding)),
+            item_biases: Arc::new(wyrm::HogwildParameter::new(item_biases)),
+            alpha: self.alpha.clone(),
+            timescale_alphas: self.timescale_alphas.clone(),
+            timescale_mixing: self.timescale_mixing.clone(),
+            time_decay: self.time_decay.clone(),
+            position_logits: self.position_logits.clone(),
+            user_biases: self.user_biases.clone(),
+            negative_weights: self.negative_weights.clone(),
+            fc1: self.fc1.clone(),
+            fc2: self.fc2.clone(),
+            mean_item: OnceLock::new(),
+        }
+    }
 }
 
 impl Clone for Parameters {
     fn clone(&self) -> Self {
         Parameters {
             hyper: self.hyper.clone(),
+            num_users: self.num_users,
+            metadata: self.metadata.clone(),
             item_embedding: Arc::new(self.item_embedding.as_ref().clone()),
             item_biases: Arc::new(self.item_biases.as_ref().clone()),
             alpha: Arc::new(self.alpha.as_ref().clone()),
+            timescale_alphas: self
+                .timescale_alphas
+                .iter()
+                .map(|alpha| Arc::new(alpha.as_ref().clone()))
+                .collect(),
+            timescale_mixing: self
+                .timescale_mixing
+                .iter()
+                .map(|mixing| Arc::new(mixing.as_ref().clone()))
+                .collect(),
+            position_logits: self
+                .position_logits
+                .as_ref()
+                .map(|position_logits| Arc::new(position_logits.as_ref().clone())),
+            time_decay: self
+                .time_decay
+                .as_ref()
+                .map(|time_decay| Arc::new(time_decay.as_ref().clone())),
+            user_biases: self
+                .user_biases
+                .as_ref()
+                .map(|user_biases| Arc::new(user_biases.as_ref().clone())),
+            negative_weights: self.negative_weights.clone(),
             fc1: Arc::new(self.alpha.as_ref().clone()),
             fc2: Arc::new(self.alpha.as_ref().clone()),
+            mean_item: self.mean_item.clone(),
         }
     }
 }
 
 impl SequenceModelParameters for Parameters {
     type Output = Model;
+    fn num_items(&self) -> usize {
+        self.hyper.num_items
+    }
+    fn num_users(&mut self) -> &mut Option<usize> {
+        &mut self.num_users
+    }
+    fn metadata(&mut self) -> &mut Option<ModelMetadata> {
+        &mut self.metadata
+    }
+    fn hyperparameters(&self) -> String {
+        serde_json::to_string(&self.hyper).unwrap_or_default()
+    }
     fn max_sequence_length(&self) -> usize {
         self.hyper.max_sequence_length
     }
+    fn max_history(&self) -> Option<usize> {
+        self.hyper.max_history
+    }
     fn num_threads(&self) -> usize {
         self.hyper.num_threads
     }
+    fn accumulate_f64(&self) -> bool {
+        self.hyper.accumulate_f64
+    }
     fn rng(&mut self) -> &mut XorShiftRng {
         &mut self.hyper.rng
     }
-    fn optimizer(&self) -> Optimizers {
+    fn negative_sampler(&self) -> Option<&dyn MinibatchSampler> {
+        self.hyper.negative_sampler.as_ref().map(|x| x.as_ref())
+    }
+    fn learning_rate(&self) -> f32 {
+        self.hyper.learning_rate
+    }
+    fn warmup_steps(&self) -> usize {
+        self.hyper.warmup_steps
+    }
+    fn is_cancelled(&self) -> bool {
+        self.hyper.is_cancelled()
+    }
+    fn is_fitted(&self) -> bool {
+        self.num_users.is_some()
+    }
+    fn is_frozen(&self, parameter: &Variable<wyrm::ParameterNode>) -> bool {
+        let frozen_half_life = !self.hyper.learn_half_life
+            && self.time_decay.as_ref().is_some_and(|time_decay| {
+                parameter.value().as_ptr() == time_decay.value().as_ptr()
+            });
+        let frozen_biases = !self.hyper.bias_term
+            && parameter.value().as_ptr() == self.item_biases.value().as_ptr();
+
+        frozen_half_life || frozen_biases
+    }
+    fn optimizer(&self, learning_rate: f32) -> Optimizers {
         match self.hyper.optimizer {
             Optimizer::Adagrad => Optimizers::Adagrad(
                 wyrm::optim::Adagrad::new()
-                    .learning_rate(self.hyper.learning_rate)
+                    .learning_rate(learning_rate)
                     .l2_penalty(self.hyper.l2_penalty),
             ),
 
             Optimizer::Adam => Optimizers::Adam(
                 wyrm::optim::Adam::new()
-                    .learning_rate(self.hyper.learning_rate)
+                    .learning_rate(learning_rate)
                     .l2_penalty(self.hyper.l2_penalty),
             ),
         }
@@ -263,21 +946,51 @@ impl SequenceModelParameters for Parameters {
     fn num_epochs(&self) -> usize {
         self.hyper.num_epochs
     }
+    fn start_epoch(&self, model: &Model, epoch: usize) {
+        if let Some(progress) = &model.anneal_progress {
+            let anneal_epochs = self.hyper.num_epochs / 2;
+            let fraction = if anneal_epochs == 0 {
+                1.0
+            } else {
+                (epoch as f32 / anneal_epochs as f32).min(1.0)
+            };
+
+            let shape = progress.value().dim();
+            progress.set_value(&Arr::from_elem(shape, fraction));
+        }
+    }
+    fn sequence_batch_size(&self) -> usize {
+        self.hyper.sequence_batch_size
+    }
     fn build(&self) -> Model {
+        self.build_batch(1)
+    }
+    fn build_batch(&self, batch_size: usize) -> Model {
         let item_embeddings = wyrm::ParameterNode::shared(self.item_embedding.clone());
         let item_biases = wyrm::ParameterNode::shared(self.item_biases.clone());
-        let alpha = wyrm::ParameterNode::shared(self.alpha.clone());
 
         let inputs: Vec<_> = (0..self.hyper.max_sequence_length)
-            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .map(|_| wyrm::IndexInputNode::new(&vec![0; batch_size]))
             .collect();
         let outputs: Vec<_> = (0..self.hyper.max_sequence_length)
-            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .map(|_| wyrm::IndexInputNode::new(&vec![0; batch_size]))
             .collect();
         let negatives: Vec<_> = (0..self.hyper.max_sequence_length)
-            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .map(|_| wyrm::IndexInputNode::new(&vec![0; batch_size]))
             .collect();
 
+        // Each row of the batch is one sequence. Parameters shared by all
+        // rows are copied to every row through a matrix product, as wyrm
+        // has no broadcasting.
+        let batch_column = wyrm::InputNode::new(Arr::ones((batch_size, 1)));
+        let broadcast = |x: Variable<BoxedNode>| {
+            if batch_size == 1 {
+                x
+            } else {
+                batch_column.dot(&x).boxed()
+            }
+        };
+
         let input_embeddings: Vec<_> = inputs
             .iter()
             .map(|input| item_embeddings.index(input))
@@ -299,41 +1012,239 @@ impl SequenceModelParameters for Parameters {
             .map(|negative| item_biases.index(negative))
             .collect();
 
-        let alpha = alpha.sigmoid();
-        let one_minus_alpha = 1.0 - alpha.clone();
-
         let mut states = Vec::with_capacity(self.hyper.max_sequence_length);
         let initial_state = input_embeddings.first().unwrap().clone().boxed();
         states.push(initial_state);
-        for input in &input_embeddings[1..] {
-            let previous_state = states.last().unwrap().clone();
-            states.push(
-                (alpha.clone() * previous_state + one_minus_alpha.clone() * input.clone()).boxed(),
+
+        // Only time-aware models read the gaps between interactions.
+        let time_deltas: Vec<_> = if self.time_decay.is_some() {
+            (0..self.hyper.max_sequence_length)
+                .map(|_| {
+                    wyrm::InputNode::new(Arr::zeros((batch_size, self.hyper.item_embedding_dim)))
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        // Set per epoch by `start_epoch`; outside training the target decay applies.
+        let anneal_start = self.hyper.anneal_alpha;
+        let anneal_progress = anneal_start
+            .map(|_| wyrm::InputNode::new(Arr::ones((1, self.hyper.item_embedding_dim))));
+        let anneal = |decay: Variable<BoxedNode>| match (&anneal_progress, anneal_start) {
+            (Some(progress), Some(start)) => {
+                (progress.clone() * decay + start * (1.0 - progress.clone())).boxed()
+            }
+            _ => decay,
+        };
+
+        if let Some(position_logits) = &self.position_logits {
+            let num_positions = position_logits.value().shape()[1];
+            let position_weights = wyrm::ParameterNode::shared(position_logits.clone()).exp();
+            // Constant matrices selecting and broadcasting scalars: wyrm
+            // has no broadcasting, so this goes through matrix products.
+            let ones = wyrm::InputNode::new(Arr::ones((1, self.hyper.item_embedding_dim)));
+            let selectors: Vec<_> = (0..num_positions)
+                .map(|position| {
+                    wyrm::InputNode::new(Arr::from_shape_fn((num_positions, 1), |(row, _)| {
+                        if row == position {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    }))
+                })
+                .collect();
+
+            for idx in 1..input_embeddings.len() {
+                let available = (idx + 1).min(num_positions);
+
+                let prefix =
+                    wyrm::InputNode::new(Arr::from_shape_fn((num_positions, 1), |(row, _)| {
+                        if row < available {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    }));
+                let normalizer = position_weights.dot(&prefix);
+
+                let state = (0..available)
+                    .map(|position| {
+                        let weight = broadcast(
+                            (position_weights.dot(&selectors[position]) / normalizer.clone())
+                                .dot(&ones)
+                                .boxed(),
+                        );
+                        (weight * input_embeddings[idx - position].clone()).boxed()
+                    })
+                    .fold(None, |total: Option<Variable<BoxedNode>>, x| match total {
+                        Some(total) => Some((total + x).boxed()),
+                        None => Some(x),
+                    })
+                    .unwrap();
+
+                states.push(state);
+            }
+        } else if let Some(time_decay) = &self.time_decay {
+            let inverse_half_life = broadcast(
+                wyrm::ParameterNode::shared(time_decay.clone())
+                    .exp()
+                    .boxed(),
             );
+
+            for (input, time_delta) in izip!(&input_embeddings[1..], &time_deltas[1..]) {
+                let previous_weight =
+                    ((-LN_2) * (time_delta.clone() * inverse_half_life.clone())).exp();
+
+                let previous_state = states.last().unwrap().clone();
+                states.push(
+                    (previous_weight.clone() * previous_state
+                        + (1.0 - previous_weight) * input.clone())
+                    .boxed(),
+                );
+            }
+        } else if let Some(decay) = self.hyper.fixed_decay {
+            // Running sum of the weights of past items: dividing by it turns
+            // the state into an average weighting the item `k` steps in the
+            // past by `(1 - decay)^k`.
+            let ones = wyrm::InputNode::new(Arr::ones((1, self.hyper.item_embedding_dim)));
+            let batch_ones = broadcast(ones.clone().boxed());
+            let decay = broadcast(anneal((decay * ones).boxed()));
+            let mut normalizer = batch_ones.clone();
+
+            for input in &input_embeddings[1..] {
+                normalizer = (1.0 + (1.0 - decay.clone()) * normalizer).boxed();
+                let weight = (batch_ones.clone() / normalizer.clone()).boxed();
+
+                let previous_state = states.last().unwrap().clone();
+                states.push(
+                    ((1.0 - weight.clone()) * previous_state + weight * input.clone()).boxed(),
+                );
+            }
+        } else {
+            let (lo, hi) = self.hyper.decay_bounds;
+
+            let mut timescale_states: Vec<Vec<_>> = iter::once(&self.alpha)
+                .chain(self.timescale_alphas.iter())
+                .map(|alpha| {
+                    // Rescaling the sigmoid keeps the decay within its bounds.
+                    let alpha = wyrm::ParameterNode::shared(alpha.clone());
+                    let decay =
+                        broadcast(anneal((lo + (hi - lo) * (1.0 - alpha.sigmoid())).boxed()));
+                    let one_minus_decay = 1.0 - decay.clone();
+
+                    let mut timescale_states = vec![states[0].clone()];
+                    for input in &input_embeddings[1..] {
+                        let previous_state = timescale_states.last().unwrap().clone();
+                        timescale_states.push(
+                            (one_minus_decay.clone() * previous_state
+                                + decay.clone() * input.clone())
+                            .boxed(),
+                        );
+                    }
+
+                    timescale_states
+                })
+                .collect();
+
+            if timescale_states.len() == 1 {
+                states = timescale_states.pop().unwrap();
+            } else {
+                // Softmax over the timescales, separately for each dimension.
+                let mixing: Vec<_> = self
+                    .timescale_mixing
+                    .iter()
+                    .map(|mixing| wyrm::ParameterNode::shared(mixing.clone()).exp().boxed())
+                    .collect();
+                let normalizer = mixing[1..]
+                    .iter()
+                    .fold(mixing[0].clone(), |total, x| (total + x.clone()).boxed());
+                let weights: Vec<_> = mixing
+                    .into_iter()
+                    .map(|x| broadcast((x / normalizer.clone()).boxed()))
+                    .collect();
+
+                states = (0..input_embeddings.len())
+                    .map(|idx| {
+                        izip!(&weights, &timescale_states)
+                            .map(|(weight, timescale_states)| {
+                                (weight.clone() * timescale_states[idx].clone()).boxed()
+                            })
+                            .fold(None, |total: Option<Variable<BoxedNode>>, x| match total {
+                                Some(total) => Some((total + x).boxed()),
+                                None => Some(x),
+                            })
+                            .unwrap()
+                    })
+                    .collect();
+            }
         }
 
+        // The bias of each sequence's user is added to the scores of all of
+        // its steps, positives and negatives alike.
+        let users = self
+            .user_biases
+            .as_ref()
+            .map(|_| wyrm::IndexInputNode::new(&vec![0; batch_size]));
+        let user_bias = match (&self.user_biases, &users) {
+            (Some(user_biases), Some(users)) => {
+                Some(wyrm::ParameterNode::shared(user_biases.clone()).index(users))
+            }
+            _ => None,
+        };
+        // The weight of each sequence's negatives in the user bias loss.
+        let negative_weights = self
+            .user_biases
+            .as_ref()
+            .map(|_| wyrm::InputNode::new(Arr::ones((batch_size, 1))));
+        let with_user_bias = |prediction: Variable<BoxedNode>| match &user_bias {
+            Some(user_bias) => (prediction + user_bias.clone()).boxed(),
+            None => prediction,
+        };
+
         let positive_predictions: Vec<_> =
             izip!(states.iter(), output_embeddings.iter(), output_biases)
                 .map(|(state, output_embedding, output_bias)| {
-                    state.vector_dot(output_embedding) + output_bias
+                    with_user_bias((state.vector_dot(output_embedding) + output_bias).boxed())
                 })
                 .collect();
         let negative_predictions: Vec<_> =
             izip!(states.iter(), negative_embeddings.iter(), negative_biases)
                 .map(|(state, negative_embedding, negative_bias)| {
-                    state.vector_dot(negative_embedding) + negative_bias
+                    with_user_bias((state.vector_dot(negative_embedding) + negative_bias).boxed())
                 })
                 .collect();
 
-        let losses: Vec<_> = positive_predictions
-            .into_iter()
-            .zip(negative_predictions.into_iter())
+        let losses: Vec<_> = izip!(&positive_predictions, &negative_predictions)
             .map(|(pos, neg)| match self.hyper.loss {
-                Loss::BPR => (neg - pos).sigmoid().boxed(),
-                Loss::Hinge | Loss::WARP => (1.0 + neg - pos).relu().boxed(),
+                Loss::BPR => (neg.clone() - pos.clone()).sigmoid().boxed(),
+                Loss::Hinge | Loss::WARP => (1.0 + neg.clone() - pos.clone()).relu().boxed(),
             })
             .collect();
 
+        // The ranking losses leave the user biases untouched, so they are
+        // trained by a logistic loss labelling targets as positive and
+        // negatives as negative.
+        let losses: Vec<_> = match &negative_weights {
+            Some(negative_weights) => izip!(losses, &positive_predictions, &negative_predictions)
+                .map(|(loss, pos, neg)| {
+                    let positive_loss = (1.0 + (-1.0 * pos.clone()).exp()).ln();
+                    let negative_loss = (1.0 + neg.clone().exp()).ln();
+
+                    (loss + positive_loss + negative_weights.clone() * negative_loss).boxed()
+                })
+                .collect(),
+            None => losses,
+        };
+
+        let loss_weights: Vec<_> = (0..losses.len())
+            .map(|_| wyrm::InputNode::new(Arr::ones((batch_size, 1))))
+            .collect();
+        let losses: Vec<_> = izip!(losses, &loss_weights)
+            .map(|(loss, weight)| (loss * weight.clone()).boxed())
+            .collect();
+
         let mut summed_losses = Vec::with_capacity(losses.len());
         summed_losses.push(losses[0].clone());
 
@@ -346,31 +1257,82 @@ impl SequenceModelParameters for Parameters {
             inputs,
             outputs,
             negatives,
+            time_deltas,
+            loss_weights,
+            anneal_progress,
+            users,
+            negative_weights,
+            user_negative_weights: self.negative_weights.clone(),
             hidden_states: states,
             summed_losses,
+            #[cfg(feature = "training")]
+            positive_predictions,
+            #[cfg(feature = "training")]
+            negative_predictions,
         }
     }
+    fn scores_unknown_items(&self) -> bool {
+        self.hyper.unknown_items == UnknownItems::MeanItem
+    }
     fn predict_single(&self, user: &[f32], item_idx: usize) -> f32 {
+        if item_idx >= self.hyper.num_items {
+            let mean_item = self.mean_item();
+            return mean_item.bias + wyrm::simd_dot(user, &mean_item.embedding);
+        }
+
         let item_embeddings = &self.item_embedding;
         let item_biases = &self.item_biases;
 
         let embeddings = item_embeddings.value();
         let biases = item_biases.value();
 
-        let embedding = embeddings.subview(Axis(0), item_idx);
+        let embedding = embeddings.row(item_idx);
         let bias = biases[(item_idx, 0)];
         let dot = wyrm::simd_dot(user, embedding.as_slice().unwrap());
 
         bias + dot
     }
+    fn predict_all(&self, user: &[f32], num_items: usize) -> Vec<f32> {
+        let mut scores = score_all_items(
+            self.item_embedding.value(),
+            self.item_biases.value(),
+            user,
+            num_items,
+        );
+
+        if scores.len() < num_items {
+            let mean_score = self.predict_single(user, self.hyper.num_items);
+            scores.resize(num_items, mean_score);
+        }
+
+        scores
+    }
 }
 
 struct Model {
     inputs: Vec<Variable<wyrm::IndexInputNode>>,
     outputs: Vec<Variable<wyrm::IndexInputNode>>,
     negatives: Vec<Variable<wyrm::IndexInputNode>>,
+    time_deltas: Vec<Variable<wyrm::InputNode>>,
+    loss_weights: Vec<Variable<wyrm::InputNode>>,
+    /// Fraction of the way from the starting to the target decay, if
+    /// the decay is annealed.
+    anneal_progress: Option<Variable<wyrm::InputNode>>,
+    /// The user of each sequence, if the model learns user biases.
+    users: Option<Variable<wyrm::IndexInputNode>>,
+    /// The weight of each sequence's negatives in the user bias loss.
+    negative_weights: Option<Variable<wyrm::InputNode>>,
+    /// The weight of each user's negatives, looked up when setting the
+    /// users.
+    user_negative_weights: Option<Arc<Vec<f32>>>,
     hidden_states: Vec<Variable<BoxedNode>>,
     summed_losses: Vec<Variable<BoxedNode>>,
+    /// The scores of each step's target and negative, for
+    /// [ImplicitEWMAModel::fit_to_teacher].
+    #[cfg(feature = "training")]
+    positive_predictions: Vec<Variable<BoxedNode>>,
+    #[cfg(feature = "training")]
+    negative_predictions: Vec<Variable<BoxedNode>>,
 }
 
 impl SequenceModel for Model {
@@ -395,9 +1357,120 @@ impl SequenceModel for Model {
     fn hidden_states(&mut self) -> &mut [Variable<BoxedNode>] {
         &mut self.hidden_states
     }
+    fn contexts(&self) -> &[Variable<wyrm::IndexInputNode>] {
+        &[]
+    }
+    fn time_deltas(&self) -> &[Variable<wyrm::InputNode>] {
+        &self.time_deltas
+    }
+    fn dropout_masks(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn weights(&self) -> &[Variable<wyrm::InputNode>] {
+        &self.loss_weights
+    }
+    fn set_users(&self, user_ids: &[usize]) {
+        if let Some(users) = &self.users {
+            users.set_value(user_ids);
+        }
+        if let (Some(negative_weights), Some(user_negative_weights)) =
+            (&self.negative_weights, &self.user_negative_weights)
+        {
+            negative_weights.set_value(&Arr::from_shape_fn((user_ids.len(), 1), |(row, _)| {
+                user_negative_weights
+                    .get(user_ids[row])
+                    .cloned()
+                    .unwrap_or(1.0)
+            }));
+        }
+    }
+}
+
+/// Check that a model with hyperparameters `hyper` can be fitted on
+/// `interactions`, before any work is done.
+#[cfg(feature = "training")]
+fn validate_inputs(
+    hyper: &Hyperparameters,
+    interactions: &CompressedInteractions,
+) -> Result<(), FittingError> {
+    if hyper.is_cancelled() {
+        return Err(FittingError::Cancelled);
+    }
+    if hyper.item_embedding_dim == 0 {
+        return Err(invalid_hyperparameter(
+            "embedding_dim",
+            hyper.item_embedding_dim,
+        ));
+    }
+    if hyper.max_sequence_length == 0 {
+        return Err(invalid_hyperparameter(
+            "max_sequence_length",
+            hyper.max_sequence_length,
+        ));
+    }
+    if hyper.sequence_batch_size == 0 {
+        return Err(invalid_hyperparameter(
+            "sequence_batch_size",
+            hyper.sequence_batch_size,
+        ));
+    }
+    if !(hyper.learning_rate.is_finite() && hyper.learning_rate > 0.0) {
+        return Err(invalid_hyperparameter("learning_rate", hyper.learning_rate));
+    }
+    if hyper.num_threads == 0 {
+        return Err(invalid_hyperparameter("num_threads", hyper.num_threads));
+    }
+    if let Some(alpha) = hyper.fixed_decay {
+        if !(alpha > 0.0 && alpha < 1.0) {
+            return Err(invalid_hyperparameter("fixed_decay", alpha));
+        }
+    }
+    let (lo, hi) = hyper.decay_bounds;
+    if !(lo >= 0.0 && lo < hi && hi <= 1.0) {
+        return Err(invalid_hyperparameter(
+            "decay_bounds",
+            format!("({}, {})", lo, hi),
+        ));
+    }
+    if let Some(decay_init) = hyper.decay_init {
+        if !(decay_init > lo && decay_init < hi) {
+            return Err(invalid_hyperparameter("decay_init", decay_init));
+        }
+    }
+    if hyper.num_timescales == 0 {
+        return Err(invalid_hyperparameter(
+            "num_timescales",
+            hyper.num_timescales,
+        ));
+    }
+    if let Pooling::LearnedPositions { n: 0 } = hyper.pooling {
+        return Err(invalid_hyperparameter(
+            "pooling",
+            format!("{:?}", hyper.pooling),
+        ));
+    }
+    if let Some(half_life_init) = hyper.half_life_init {
+        if !(half_life_init.is_finite() && half_life_init > 0.0) {
+            return Err(invalid_hyperparameter("half_life_init", half_life_init));
+        }
+    }
+    if hyper.max_history == Some(0) {
+        return Err(invalid_hyperparameter("max_history", 0));
+    }
+    if let Some(start_alpha) = hyper.anneal_alpha {
+        if !(0.0..=1.0).contains(&start_alpha) {
+            return Err(invalid_hyperparameter("anneal_alpha", start_alpha));
+        }
+    }
+
+    validate_interactions(interactions, hyper.num_items)
 }
 
 /// Implicit EWMA model.
+///
+/// Item ids the model does not know, such as items added to the catalog
+/// since it was fitted, are rejected unless the model is built with
+/// [Hyperparameters::unknown_items].
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImplicitEWMAModel {
     params: Parameters,
@@ -405,70 +1478,884 @@ pub struct ImplicitEWMAModel {
 
 impl ImplicitEWMAModel {
     /// Fit the EWMA model.
+    #[cfg(feature = "training")]
     pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<f32, FittingError> {
-        fit_sequence_model(interactions, &mut self.params)
-    }
-}
+        validate_inputs(&self.params.hyper, interactions)?;
+
+        let params = &mut self.params;
+        // Fitting changes the item parameters the mean item is computed from.
+        params.mean_item = OnceLock::new();
+
+        if params.hyper.bias_term && params.hyper.popularity_bias_init && params.num_users.is_none()
+        {
+            let mut counts = Arr::zeros((params.hyper.num_items, 1));
+            for user in interactions.iter_users() {
+                for &item_id in user.item_ids {
+                    counts[(item_id.index(), 0)] += 1.0;
+                }
+            }
+
+            params.item_biases = Arc::new(wyrm::HogwildParameter::new(
+                counts.mapv(|count: f32| count.ln_1p()),
+            ));
+        }
+        params.init_user_biases(interactions);
 
-impl OnlineRankingModel for ImplicitEWMAModel {
-    type UserRepresentation = ImplicitUser;
-    fn user_representation(
-        &self,
-        item_ids: &[ItemId],
-    ) -> Result<Self::UserRepresentation, PredictionError> {
-        self.params.user_representation(item_ids)
+        fit_sequence_model(interactions, params)
     }
 
-    fn predict(
-        &self,
-        user: &Self::UserRepresentation,
-        item_ids: &[ItemId],
-    ) -> Result<Vec<f32>, PredictionError> {
-        self.params.predict(user, item_ids)
-    }
-}
+    /// Fit the EWMA model to reproduce the scores of `teacher` rather than
+    /// to rank observed items above sampled ones.
+    ///
+    /// At every step of every training sequence, the model scores the next
+    /// item and a sampled negative, and is trained to minimize the squared
+    /// differences between its scores and those `teacher` gives the same
+    /// items after the user's history up to that step, whatever the
+    /// configured [Hyperparameters::loss]. Returns the mean loss per step
+    /// over all epochs.
+    ///
+    /// Training runs on the calling thread, one sequence per step, at the
+    /// configured learning rate throughout: [Hyperparameters::num_threads]
+    /// and [Hyperparameters::parallelism] are ignored, and the loss is
+    /// always summed in double precision, as with
+    /// [Hyperparameters::accumulate_f64].
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors of [ImplicitEWMAModel::fit], returns
+    /// [FittingError::InvalidHyperparameter] if there are no epochs, or if
+    /// the model is built with a [Hyperparameters::sequence_batch_size]
+    /// above one or with [Hyperparameters::warmup_steps], neither of which
+    /// distillation supports.
+    #[cfg(feature = "training")]
+    pub fn fit_to_teacher<T: OnlineRankingModel>(
+        &mut self,
+        interactions: &CompressedInteractions,
+        teacher: &T,
+    ) -> Result<f32, Error> {
+        let start = Instant::now();
 
-#[cfg(test)]
-mod tests {
-    use std::time::Instant;
+        validate_inputs(&self.params.hyper, interactions)?;
 
-    use super::*;
-    use crate::data::{user_based_split, Interactions};
-    use crate::datasets::download_movielens_100k;
-    use crate::evaluation::mrr_score;
+        let params = &mut self.params;
+        if params.hyper.num_epochs == 0 {
+            return Err(invalid_hyperparameter("num_epochs", params.hyper.num_epochs).into());
+        }
+        if params.hyper.sequence_batch_size > 1 {
+            return Err(invalid_hyperparameter(
+                "sequence_batch_size",
+                params.hyper.sequence_batch_size,
+            )
+            .into());
+        }
+        if params.hyper.warmup_steps > 0 {
+            return Err(invalid_hyperparameter("warmup_steps", params.hyper.warmup_steps).into());
+        }
+        if let Some(num_users) = params.num_users {
+            if interactions.num_users() != num_users {
+                return Err(FittingError::UserCountMismatch {
+                    model_users: num_users,
+                    data_users: interactions.num_users(),
+                }
+                .into());
+            }
+        }
 
-    fn run_test(mut data: Interactions, hyperparameters: Hyperparameters) -> (f32, f32) {
-        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+        params.mean_item = OnceLock::new();
+        params.init_user_biases(interactions);
+
+        let max_length = params.hyper.max_sequence_length;
+
+        // The teacher's representation of the user before each target,
+        // computed once: only the negatives change between epochs.
+        let mut sequences = Vec::new();
+        for user in interactions.iter_users() {
+            let user = match params.hyper.max_history {
+                Some(max_history) => user.most_recent(max_history),
+                None => user,
+            };
+            let mut offset = 0;
+
+            for (item_ids, timestamps) in user.chunks(max_length) {
+                let start = offset;
+                offset += item_ids.len();
+
+                if item_ids.len() < 2 {
+                    continue;
+                }
+
+                let histories: Vec<_> = (start..offset - 1)
+                    .map(|idx| &user.item_ids[..=idx])
+                    .collect();
+                let teacher_users = teacher.user_representations(&histories)?;
+                sequences.push((user.user_id, item_ids, timestamps, teacher_users));
+            }
+        }
 
-        let (train, test) = user_based_split(&mut data, &mut rng, 0.2);
-        let train_mat = train.to_compressed();
-        let test_mat = test.to_compressed();
+        if sequences.is_empty() {
+            return Err(FittingError::NoInteractions.into());
+        }
 
-        let mut model = hyperparameters.rng(rng).build();
+        let model = params.build();
+        let targets: Vec<_> = (0..max_length)
+            .map(|_| {
+                (
+                    wyrm::InputNode::new(Arr::zeros((1, 1))),
+                    wyrm::InputNode::new(Arr::zeros((1, 1))),
+                )
+            })
+            .collect();
+        let mut losses: Vec<Variable<BoxedNode>> = Vec::with_capacity(max_length);
+        for (positive, negative, (positive_target, negative_target)) in izip!(
+            &model.positive_predictions,
+            &model.negative_predictions,
+            &targets
+        ) {
+            let positive_error = positive.clone() - positive_target.clone();
+            let negative_error = negative.clone() - negative_target.clone();
+            let loss = (positive_error.clone() * positive_error
+                + negative_error.clone() * negative_error)
+                .boxed();
+
+            let total = match losses.last() {
+                Some(total) => (total.clone() + loss).boxed(),
+                None => loss,
+            };
+            losses.push(total);
+        }
 
-        let start = Instant::now();
-        let loss = model.fit(&train_mat).unwrap();
-        let elapsed = start.elapsed();
-        let train_mrr = mrr_score(&model, &train_mat).unwrap();
-        let test_mrr = mrr_score(&model, &test_mat).unwrap();
+        let uniform_sampler = UniformSampler::new(params.hyper.num_items);
+        let optimizer = params.optimizer(params.hyper.learning_rate);
+        let mut rng = XorShiftRng::from_seed(params.rng().gen());
+        let mut loss_value = 0.0_f64;
+        let mut examples = 0;
+
+        for epoch in 0..params.hyper.num_epochs {
+            if params.is_cancelled() {
+                return Err(FittingError::Cancelled.into());
+            }
+
+            rng.shuffle(&mut sequences);
+            params.start_epoch(&model, epoch);
+
+            for (user_id, item_ids, timestamps, teacher_users) in &sequences {
+                set_time_deltas(&model, Some(*timestamps));
+                set_user(&model, *user_id);
+
+                let sampler = params.negative_sampler().unwrap_or(&uniform_sampler);
+                let (inputs, outputs, negatives, _) = model.state();
+
+                for (idx, teacher_user) in teacher_users.iter().enumerate() {
+                    let output_id = item_ids[idx + 1];
+                    let negative_id = sampler.sample_negative(*user_id, output_id, &mut rng);
+                    let scores = teacher.predict(teacher_user, &[output_id, negative_id])?;
+
+                    inputs[idx].set_value(item_ids[idx].index());
+                    outputs[idx].set_value(output_id.index());
+                    negatives[idx].set_value(negative_id.index());
+                    targets[idx].0.set_value(&Arr::from_elem((1, 1), scores[0]));
+                    targets[idx].1.set_value(&Arr::from_elem((1, 1), scores[1]));
+                }
+
+                let loss = &mut losses[teacher_users.len() - 1];
+                loss.forward();
+                loss_value += f64::from(loss.value().scalar_sum());
+                examples += teacher_users.len();
+                loss.backward(1.0);
+
+                let trainable: Vec<_> = loss
+                    .parameters()
+                    .iter()
+                    .filter(|parameter| !params.is_frozen(parameter))
+                    .cloned()
+                    .collect();
+                optimizer.step(&trainable);
+            }
+        }
 
-        println!(
-            "Train MRR {} at loss {} and test MRR {} (in {:?})",
-            train_mrr, loss, test_mrr, elapsed
+        let loss = (loss_value / examples as f64) as f32;
+
+        params.num_users = Some(interactions.num_users());
+
+        let tags = params
+            .metadata
+            .take()
+            .map(|metadata| metadata.tags)
+            .unwrap_or_default();
+        let fit = FitStats {
+            num_epochs: params.hyper.num_epochs,
+            loss,
+            duration: start.elapsed(),
+        };
+        let mut metadata = ModelMetadata::new(
+            params.hyperparameters(),
+            DatasetStats::from_interactions(interactions),
+            fit,
         );
+        metadata.tags = tags;
+        params.metadata = Some(metadata);
 
-        (test_mrr, train_mrr)
+        Ok(loss)
     }
 
-    #[async_std::test]
-    async fn mrr_test_single_thread() {
-        let data = download_movielens_100k().await.unwrap();
+    /// Return whether the model has been successfully fitted at least once.
+    /// Inference on an unfitted model returns
+    /// [PredictionError::ModelNotFitted].
+    pub fn is_fitted(&self) -> bool {
+        self.params.is_fitted()
+    }
 
-        let hyperparameters = Hyperparameters::new(data.num_items(), 128)
-            .embedding_dim(32)
-            .learning_rate(0.16)
-            .l2_penalty(0.0004)
-            .loss(Loss::Hinge)
+    /// Return the record of the last successful fit, or `None` if the
+    /// model has not been fitted. See [ModelMetadata].
+    pub fn metadata(&self) -> Option<&ModelMetadata> {
+        self.params.metadata.as_ref()
+    }
+
+    /// Return the record of the last successful fit mutably, for example
+    /// to add tags.
+    pub fn metadata_mut(&mut self) -> Option<&mut ModelMetadata> {
+        self.params.metadata.as_mut()
+    }
+
+    /// Return the number of items the model scores.
+    pub fn num_items(&self) -> usize {
+        self.params.hyper.num_items
+    }
+
+    /// Export the model as a versioned JSON document, with the
+    /// hyperparameters in plain JSON and the parameters as exact
+    /// base64-encoded blobs. See [crate::models::json].
+    pub fn to_json(&self) -> Result<String, ExportError> {
+        json::to_json(self, "ewma")
+    }
+
+    /// Read a model exported with [ImplicitEWMAModel::to_json].
+    pub fn from_json(json: &str) -> Result<Self, ExportError> {
+        json::from_json(json, "ewma")
+    }
+
+    /// Save the model in the versioned binary format of
+    /// [crate::models::format].
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        format::write_model(self, "ewma", writer)
+    }
+
+    /// Load a model saved with [ImplicitEWMAModel::write], by this or an older
+    /// version of the crate.
+    pub fn read<R: Read>(reader: R) -> Result<Self, ModelLoadError> {
+        format::read_model(reader, "ewma")
+    }
+
+    /// Export the prediction path of the model to an ONNX file at `path`.
+    /// See [crate::models::onnx] for the inputs and outputs of the graph.
+    ///
+    /// Time-aware models cannot be exported, as the graph has no
+    /// timestamp input.
+    #[cfg(feature = "onnx")]
+    pub fn export_onnx<P: AsRef<Path>>(&self, path: P) -> Result<(), OnnxError> {
+        self.onnx_graph()?.write(path)
+    }
+
+    #[cfg(feature = "onnx")]
+    fn onnx_graph(&self) -> Result<PoolingGraph, OnnxError> {
+        let params = &self.params;
+
+        if !params.is_fitted() {
+            return Err(OnnxError::ModelNotFitted);
+        }
+        if params.time_decay.is_some() {
+            return Err(OnnxError::Unsupported("time-aware decay"));
+        }
+
+        let dim = params.hyper.item_embedding_dim;
+        let history_length = params
+            .hyper
+            .max_history
+            .map_or(params.hyper.max_sequence_length, |max_history| {
+                max_history.min(params.hyper.max_sequence_length)
+            });
+        let shape = (history_length, dim);
+        // Number of steps from each position to the most recent item.
+        let age = |row: usize| (history_length - 1 - row) as i32;
+
+        let (recent, first) = if let Some(position_logits) = &params.position_logits {
+            let logits = position_logits.value();
+            let recent = Array2::from_shape_fn(shape, |(row, _)| {
+                logits
+                    .get((0, age(row) as usize))
+                    .map_or(0.0, |logit| logit.exp())
+            });
+
+            (recent, Array2::zeros(shape))
+        } else if let Some(decay) = params.hyper.fixed_decay {
+            let recent = Array2::from_shape_fn(shape, |(row, _)| (1.0 - decay).powi(age(row)));
+
+            (recent, Array2::zeros(shape))
+        } else {
+            // The oldest item starts the average with all of its weight,
+            // later items enter with a weight of `decay`.
+            let (lo, hi) = params.hyper.decay_bounds;
+            let decays: Vec<Vec<f32>> = iter::once(&params.alpha)
+                .chain(params.timescale_alphas.iter())
+                .map(|alpha| {
+                    alpha
+                        .value()
+                        .iter()
+                        .map(|&alpha| effective_decay(alpha, lo, hi))
+                        .collect()
+                })
+                .collect();
+
+            // Softmax over the timescales, separately for each dimension.
+            let mixing: Vec<Vec<f32>> = if params.timescale_mixing.is_empty() {
+                vec![vec![1.0; dim]]
+            } else {
+                let weights: Vec<Vec<f32>> = params
+                    .timescale_mixing
+                    .iter()
+                    .map(|mixing| mixing.value().iter().map(|x| x.exp()).collect())
+                    .collect();
+
+                weights
+                    .iter()
+                    .map(|weight| {
+                        (0..dim)
+                            .map(|col| weight[col] / weights.iter().map(|x| x[col]).sum::<f32>())
+                            .collect()
+                    })
+                    .collect()
+            };
+
+            let weight = |row: usize, col: usize, is_first: bool| -> f32 {
+                izip!(&decays, &mixing)
+                    .map(|(decay, mixing)| {
+                        let decay = decay[col];
+                        let entry_weight = if is_first { 1.0 - decay } else { decay };
+                        mixing[col] * entry_weight * (1.0 - decay).powi(age(row))
+                    })
+                    .sum()
+            };
+
+            (
+                Array2::from_shape_fn(shape, |(row, col)| weight(row, col, false)),
+                Array2::from_shape_fn(shape, |(row, col)| weight(row, col, true)),
+            )
+        };
+
+        Ok(PoolingGraph {
+            item_embeddings: params.item_embedding.value(),
+            item_biases: params.item_biases.value(),
+            recent,
+            first,
+        })
+    }
+
+    /// Grow the item embedding and bias tables to `new_num_items` items,
+    /// initializing the new rows according to `init`. Existing items are
+    /// unaffected, and the new items can be scored and fitted immediately.
+    ///
+    /// Optimizer state for the item embeddings and biases is reset.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_num_items` is smaller than the current number of items.
+    pub fn grow_items(&mut self, new_num_items: usize, init: GrowInit) {
+        let params = &mut self.params;
+        let num_items = params.hyper.num_items;
+        assert!(
+            new_num_items >= num_items,
+            "Cannot shrink the number of items from {} to {}.",
+            num_items,
+            new_num_items
+        );
+
+        let embedding_dim = params.hyper.item_embedding_dim;
+
+        let (new_embeddings, new_biases) = match init {
+            GrowInit::Random => (
+                embedding_init(
+                    new_num_items - num_items,
+                    embedding_dim,
+                    &mut params.hyper.rng,
+                ),
+                Arr::zeros((new_num_items - num_items, 1)),
+            ),
+            GrowInit::Zeros => (
+                Arr::zeros((new_num_items - num_items, embedding_dim)),
+                Arr::zeros((new_num_items - num_items, 1)),
+            ),
+            GrowInit::MeanOfExisting => {
+                let mean_item = params.mean_item();
+
+                (
+                    Arr::from_shape_fn((new_num_items - num_items, embedding_dim), |(_, col)| {
+                        mean_item.embedding[col]
+                    }),
+                    Arr::from_elem((new_num_items - num_items, 1), mean_item.bias),
+                )
+            }
+        };
+
+        let grow = |old: &Arr, new: &Arr| {
+            Arr::from_shape_fn((new_num_items, old.shape()[1]), |(row, col)| {
+                if row < num_items {
+                    old[(row, col)]
+                } else {
+                    new[(row - num_items, col)]
+                }
+            })
+        };
+
+        let item_embedding = grow(params.item_embedding.value(), &new_embeddings);
+        let item_biases = grow(params.item_biases.value(), &new_biases);
+
+        params.item_embedding = Arc::new(wyrm::HogwildParameter::new(item_embedding));
+        params.item_biases = Arc::new(wyrm::HogwildParameter::new(item_biases));
+        params.hyper.num_items = new_num_items;
+        params.mean_item = OnceLock::new();
+    }
+
+    /// Whether `item_ids` holds ids the model does not know, and pools as
+    /// the mean item.
+    fn has_mean_items(&self, item_ids: &[ItemId]) -> bool {
+        let hyper = &self.params.hyper;
+
+        hyper.unknown_items == UnknownItems::MeanItem
+            && item_ids
+                .iter()
+                .any(|item_id| item_id.index() >= hyper.num_items)
+    }
+
+    /// Compute the representation of `item_ids`, with unknown ids standing
+    /// for the mean item if the model is configured to accept them.
+    fn history_representation(
+        &self,
+        item_ids: &[ItemId],
+        timestamps: Option<&[Timestamp]>,
+    ) -> Result<ImplicitUser, PredictionError> {
+        if self.has_mean_items(item_ids) {
+            let positions: Vec<_> = (0..item_ids.len()).map(ItemId::from_index).collect();
+
+            sequence_representation(
+                &self.params.history_parameters(item_ids),
+                &positions,
+                None,
+                timestamps,
+            )
+        } else {
+            sequence_representation(&self.params, item_ids, None, timestamps)
+        }
+    }
+
+    /// Return the current effective decay for each embedding dimension:
+    /// the weight given to the most recent item. For time-aware models,
+    /// this is the decay over a single time unit; for models with several
+    /// timescales, that of the first timescale.
+    pub fn decay(&self) -> Vec<f32> {
+        let hyper = &self.params.hyper;
+
+        if let Some(time_decay) = &self.params.time_decay {
+            return time_decay
+                .value()
+                .iter()
+                .map(|log_inverse_half_life| 1.0 - 0.5_f32.powf(log_inverse_half_life.exp()))
+                .collect();
+        }
+
+        if let Some(decay) = hyper.fixed_decay {
+            return vec![decay; hyper.item_embedding_dim];
+        }
+
+        let (lo, hi) = hyper.decay_bounds;
+
+        self.params
+            .alpha
+            .value()
+            .iter()
+            .map(|&alpha| effective_decay(alpha, lo, hi))
+            .collect()
+    }
+
+    /// Return the current effective decays of each timescale,
+    /// as in [ImplicitEWMAModel::decay].
+    pub fn timescale_decays(&self) -> Vec<Vec<f32>> {
+        let (lo, hi) = self.params.hyper.decay_bounds;
+
+        iter::once(self.decay())
+            .chain(self.params.timescale_alphas.iter().map(|alpha| {
+                alpha
+                    .value()
+                    .iter()
+                    .map(|&alpha| effective_decay(alpha, lo, hi))
+                    .collect()
+            }))
+            .collect()
+    }
+
+    /// Return a human-readable summary of the model's parameters
+    /// and their shapes.
+    pub fn summary(&self) -> String {
+        let params = &self.params;
+        let mut lines = vec![
+            format!(
+                "item_embedding: {:?}",
+                params.item_embedding.value().shape()
+            ),
+            format!("item_biases: {:?}", params.item_biases.value().shape()),
+        ];
+
+        if let Some(ref position_logits) = params.position_logits {
+            lines.push(format!(
+                "position_logits: {:?}",
+                position_logits.value().shape()
+            ));
+        } else if let Some(ref time_decay) = params.time_decay {
+            lines.push(format!("time_decay: {:?}", time_decay.value().shape()));
+        } else if let Some(decay) = params.hyper.fixed_decay {
+            lines.push(format!("fixed decay: {}", decay));
+        } else {
+            lines.push(format!(
+                "alpha ({} timescales): {:?}",
+                params.hyper.num_timescales,
+                params.alpha.value().shape()
+            ));
+
+            for (idx, mixing) in params.timescale_mixing.iter().enumerate() {
+                lines.push(format!(
+                    "timescale_mixing_{}: {:?}",
+                    idx,
+                    mixing.value().shape()
+                ));
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    /// Return the softmax-normalized weights of each position, most recent
+    /// first, if the model uses [Pooling::LearnedPositions].
+    pub fn position_weights(&self) -> Option<Vec<f32>> {
+        self.params.position_logits.as_ref().map(|position_logits| {
+            let weights: Vec<f32> = position_logits.value().iter().map(|x| x.exp()).collect();
+            let total: f32 = weights.iter().sum();
+
+            weights.iter().map(|weight| weight / total).collect()
+        })
+    }
+
+    /// Return the representation of a user with no interactions, to be
+    /// extended with [ImplicitEWMAModel::advance_representation].
+    pub fn initial_representation(&self) -> ImplicitUser {
+        ImplicitUser {
+            user_embedding: Vec::new(),
+            num_interactions: 0,
+            user_bias: None,
+        }
+    }
+
+    /// Like [ImplicitEWMAModel::initial_representation], carrying the bias
+    /// of `user_id` if the model learns user biases and knows the user.
+    pub fn initial_representation_for_user(&self, user_id: UserId) -> ImplicitUser {
+        ImplicitUser {
+            user_bias: self.user_bias(user_id),
+            ..self.initial_representation()
+        }
+    }
+
+    /// Update `state` with an interaction with `new_item`, in a single
+    /// decay-and-add step rather than recomputing from the full history.
+    /// Time-aware models assume one time unit has passed.
+    ///
+    /// The result matches [OnlineRankingModel::user_representation] as long
+    /// as the history is no longer than the maximum sequence length.
+    ///
+    /// # Errors
+    ///
+    /// Returns [PredictionError::ItemIdOutOfRange] if `new_item` is unknown
+    /// and the model rejects unknown items, and
+    /// [PredictionError::IncrementalUpdateUnsupported] if the model learns
+    /// more than one timescale or uses [Pooling::LearnedPositions].
+    pub fn advance_representation(
+        &self,
+        state: &ImplicitUser,
+        new_item: ItemId,
+    ) -> Result<ImplicitUser, PredictionError> {
+        self.advance_representation_with_time_delta(state, new_item, 1.0)
+    }
+
+    /// Update `state` with an interaction with `new_item` that happened
+    /// `time_delta` time units after the previous one. Models built without
+    /// [Hyperparameters::time_aware] ignore the time delta.
+    ///
+    /// # Errors
+    ///
+    /// Returns [PredictionError::ItemIdOutOfRange] if `new_item` is unknown
+    /// and the model rejects unknown items, and
+    /// [PredictionError::IncrementalUpdateUnsupported] if the model learns
+    /// more than one timescale or uses [Pooling::LearnedPositions].
+    pub fn advance_representation_with_time_delta(
+        &self,
+        state: &ImplicitUser,
+        new_item: ItemId,
+        time_delta: f32,
+    ) -> Result<ImplicitUser, PredictionError> {
+        let hyper = &self.params.hyper;
+        if hyper.unknown_items == UnknownItems::Error {
+            check_item_ids(&[new_item], hyper.num_items)?;
+        }
+
+        let learns_timescales = hyper.fixed_decay.is_none() && hyper.half_life_init.is_none();
+        let multiple_timescales = learns_timescales && hyper.num_timescales > 1;
+        if multiple_timescales || self.params.position_logits.is_some() {
+            return Err(PredictionError::IncrementalUpdateUnsupported);
+        }
+
+        let embeddings = self.params.item_embedding.value();
+        let row;
+        let item_embedding: &[f32] = if new_item.index() < hyper.num_items {
+            row = embeddings.row(new_item.index());
+            row.as_slice().unwrap()
+        } else {
+            &self.params.mean_item().embedding
+        };
+
+        if state.num_interactions == 0 {
+            return Ok(ImplicitUser {
+                user_embedding: item_embedding.to_vec(),
+                num_interactions: 1,
+                user_bias: state.user_bias,
+            });
+        }
+
+        // The weight given to the new item, for every dimension.
+        let decays: Vec<f32> = if let Some(time_decay) = &self.params.time_decay {
+            time_decay
+                .value()
+                .iter()
+                .map(|log_inverse_half_life| {
+                    1.0 - (-LN_2 * (time_delta * log_inverse_half_life.exp())).exp()
+                })
+                .collect()
+        } else if let Some(decay) = hyper.fixed_decay {
+            // Same recurrence as the normalizer in the model graph.
+            let normalizer = (0..state.num_interactions)
+                .fold(1.0, |normalizer, _| (1.0 - decay) * normalizer + 1.0);
+            vec![1.0 / normalizer; hyper.item_embedding_dim]
+        } else {
+            self.decay()
+        };
+
+        let user_embedding = izip!(&state.user_embedding, item_embedding, decays)
+            .map(|(&previous, &item, decay)| (1.0 - decay) * previous + decay * item)
+            .collect();
+
+        Ok(ImplicitUser {
+            user_embedding,
+            num_interactions: state.num_interactions + 1,
+            user_bias: state.user_bias,
+        })
+    }
+
+    /// Compute the representation of `user_id` from past interactions,
+    /// carrying the user's bias if the model learns user biases and knows
+    /// the user. See [Hyperparameters::use_user_biases].
+    pub fn user_representation_for_user(
+        &self,
+        user_id: UserId,
+        item_ids: &[ItemId],
+    ) -> Result<ImplicitUser, PredictionError> {
+        let mut user = self.history_representation(item_ids, None)?;
+        user.user_bias = self.user_bias(user_id);
+
+        Ok(user)
+    }
+
+    /// The learned bias of `user_id`, or `None` if the model has no user
+    /// biases or did not see the user when fitted.
+    fn user_bias(&self, user_id: UserId) -> Option<f32> {
+        let user_biases = self.params.user_biases.as_ref()?.value();
+
+        if user_id.index() < user_biases.shape()[0] {
+            Some(user_biases[(user_id.index(), 0)])
+        } else {
+            None
+        }
+    }
+
+    /// Compute a user representation from past interactions and their
+    /// timestamps, which must be aligned with `item_ids`. Models built
+    /// without [Hyperparameters::time_aware] ignore the timestamps.
+    pub fn user_representation_with_timestamps(
+        &self,
+        item_ids: &[ItemId],
+        timestamps: &[Timestamp],
+    ) -> Result<ImplicitUser, PredictionError> {
+        self.history_representation(item_ids, Some(timestamps))
+    }
+
+    /// Compute the perplexity of the model on `interactions`: the
+    /// exponent of the negative mean log-probability of each observed next
+    /// item, with item probabilities given by the softmax of the scores of
+    /// all items. Every item but the first of each user is predicted from
+    /// the items preceding it.
+    ///
+    /// A perfect model has a perplexity of 1, and one that scores all
+    /// items equally a perplexity of the number of items.
+    #[cfg(feature = "training")]
+    pub fn perplexity(
+        &self,
+        interactions: &CompressedInteractions,
+    ) -> Result<f32, PredictionError> {
+        sequence_perplexity(&self.params, interactions)
+    }
+
+    /// Score only the `candidates` and return the `k` highest-scoring
+    /// `(item_id, score)` pairs, best first.
+    ///
+    /// Only the candidates' embedding rows are read, so this is suitable
+    /// for serving very large catalogs behind a coarse candidate generator.
+    pub fn predict_candidates(
+        &self,
+        user: &ImplicitUser,
+        candidates: &[ItemId],
+        k: usize,
+    ) -> Result<Vec<(ItemId, f32)>, PredictionError> {
+        let mut scored = predict_candidates(&self.params, user, candidates, k)?;
+        for (_, score) in &mut scored {
+            *score += user.user_bias.unwrap_or(0.0);
+        }
+
+        Ok(scored)
+    }
+}
+
+impl ItemEmbeddings for ImplicitEWMAModel {
+    fn item_embeddings(&self) -> Array2<f32> {
+        scoring_vectors(
+            self.params.item_embedding.value(),
+            self.params.item_biases.value(),
+        )
+    }
+}
+
+impl OnlineRankingModel for ImplicitEWMAModel {
+    type UserRepresentation = ImplicitUser;
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        self.history_representation(item_ids, None)
+    }
+
+    fn user_representations(
+        &self,
+        histories: &[&[ItemId]],
+    ) -> Result<Vec<Self::UserRepresentation>, PredictionError> {
+        if histories
+            .iter()
+            .any(|item_ids| self.has_mean_items(item_ids))
+        {
+            histories
+                .iter()
+                .map(|item_ids| self.history_representation(item_ids, None))
+                .collect()
+        } else {
+            self.params.user_representations(histories)
+        }
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        let mut predictions = self.params.predict(user, item_ids)?;
+        add_user_bias(user, &mut predictions);
+
+        Ok(predictions)
+    }
+
+    fn predict_into(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+        out: &mut [f32],
+    ) -> Result<(), PredictionError> {
+        self.params.predict_into(user, item_ids, out)?;
+        add_user_bias(user, out);
+
+        Ok(())
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        let mut predictions = self.params.predict_all_items(user, num_items)?;
+        add_user_bias(user, &mut predictions);
+
+        Ok(predictions)
+    }
+}
+
+/// Add the bias of `user`, if it has one, to all of its `scores`.
+fn add_user_bias(user: &ImplicitUser, scores: &mut [f32]) {
+    if let Some(user_bias) = user.user_bias {
+        for score in scores {
+            *score += user_bias;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::data::{synthetic_interactions, user_based_split, Interaction, Interactions};
+    #[cfg(feature = "datasets")]
+    use crate::datasets::download_movielens_100k;
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, times, UserId};
+    use crate::models::sampling::PopularitySampler;
+    use crate::models::sequence_model::{fit_sequence_model_with_hook, warmup_learning_rate};
+
+    fn run_test(mut data: Interactions, hyperparameters: Hyperparameters) -> (f32, f32) {
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+
+        let (train, test) = user_based_split(&mut data, &mut rng, 0.2);
+        let train_mat = train.to_compressed();
+        let test_mat = test.to_compressed();
+
+        let mut model = hyperparameters.rng(rng).build();
+
+        let start = Instant::now();
+        let loss = model.fit(&train_mat).unwrap();
+        let elapsed = start.elapsed();
+        let train_mrr = mrr_score(&model, &train_mat).unwrap();
+        let test_mrr = mrr_score(&model, &test_mat).unwrap();
+
+        println!(
+            "Train MRR {} at loss {} and test MRR {} (in {:?})",
+            train_mrr, loss, test_mrr, elapsed
+        );
+
+        (test_mrr, train_mrr)
+    }
+
+    #[cfg(feature = "datasets")]
+    #[async_std::test]
+    async fn mrr_test_single_thread() {
+        let data = download_movielens_100k().await.unwrap();
+
+        let hyperparameters = Hyperparameters::new(data.num_items(), 128)
+            .embedding_dim(32)
+            .learning_rate(0.16)
+            .l2_penalty(0.0004)
+            .loss(Loss::Hinge)
             .optimizer(Optimizer::Adagrad)
             .num_epochs(10)
             .num_threads(1);
@@ -483,6 +2370,7 @@ mod tests {
         assert!(test_mrr > expected_mrr)
     }
 
+    #[cfg(feature = "datasets")]
     #[async_std::test]
     async fn mrr_test_warp() {
         let data = download_movielens_100k().await.unwrap();
@@ -505,4 +2393,1467 @@ mod tests {
 
         assert!(test_mrr > expected_mrr)
     }
+
+    #[test]
+    fn custom_negative_sampler() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+
+        let mut model = Hyperparameters::new(20, 10)
+            .negative_sampler(Box::new(PopularitySampler::new(&data)))
+            .num_epochs(2)
+            .num_threads(1)
+            .build();
+
+        assert!(model.fit(&data).unwrap().is_finite());
+    }
+
+    /// Mark a freshly built `model` as fitted, so that inference uses its
+    /// initial parameters.
+    fn assume_fitted(mut model: ImplicitEWMAModel) -> ImplicitEWMAModel {
+        model.params.num_users = Some(0);
+        model
+    }
+
+    fn item_embedding(model: &ImplicitEWMAModel, item_id: ItemId) -> Vec<f32> {
+        model
+            .params
+            .item_embedding
+            .value()
+            .row(item_id.index())
+            .to_vec()
+    }
+
+    #[test]
+    fn fixed_decay_last_item() {
+        let model = assume_fitted(
+            Hyperparameters::new(10, 5)
+                .fixed_decay(1.0 - 1e-6)
+                .from_seed([42; 16])
+                .build(),
+        );
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+
+        for (x, y) in izip!(&user.user_embedding, item_embedding(&model, ItemId(3))) {
+            assert!((x - y).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn fixed_decay_average() {
+        let model = assume_fitted(
+            Hyperparameters::new(10, 5)
+                .fixed_decay(1e-6)
+                .from_seed([42; 16])
+                .build(),
+        );
+
+        let history = items(&[1, 2, 3, 4]);
+        let user = model.user_representation(&history).unwrap();
+
+        for (dim, &x) in user.user_embedding.iter().enumerate() {
+            let average = history
+                .iter()
+                .map(|&item_id| item_embedding(&model, item_id)[dim])
+                .sum::<f32>()
+                / history.len() as f32;
+
+            assert!((x - average).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn fixed_decay_serialization() {
+        let model = Hyperparameters::new(10, 5).fixed_decay(0.5).build();
+        let serialized = serde_json::to_string(&model).unwrap();
+        let deserialized: ImplicitEWMAModel = serde_json::from_str(&serialized).unwrap();
+
+        assert_eq!(deserialized.params.hyper.fixed_decay, Some(0.5));
+    }
+
+    #[test]
+    fn json_export() {
+        let model = assume_fitted(
+            Hyperparameters::new(10, 5)
+                .time_aware(5.0)
+                .from_seed([42; 16])
+                .build(),
+        );
+
+        let json = model.to_json().unwrap();
+        let deserialized = ImplicitEWMAModel::from_json(&json).unwrap();
+
+        let item_ids: Vec<_> = (0..10).map(ItemId).collect();
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let deserialized_user = deserialized
+            .user_representation(&items(&[1, 2, 3]))
+            .unwrap();
+        assert_eq!(
+            model.predict(&user, &item_ids).unwrap(),
+            deserialized.predict(&deserialized_user, &item_ids).unwrap()
+        );
+
+        let tampered = json.replacen("\"format_version\": 1", "\"format_version\": 0", 1);
+        match ImplicitEWMAModel::from_json(&tampered) {
+            Err(ExportError::UnsupportedVersion { version: 0 }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "onnx")]
+    #[test]
+    fn onnx_export() {
+        let interactions: Vec<_> = (0..20)
+            .flat_map(|user_id| {
+                (0..10).map(move |t| {
+                    Interaction::new(
+                        UserId(user_id),
+                        ItemId((user_id * 3 + t) % 15),
+                        Timestamp(t as i64),
+                    )
+                })
+            })
+            .collect();
+        let data = Interactions::from(interactions).to_compressed();
+
+        let configurations = vec![
+            Hyperparameters::new(15, 6),
+            Hyperparameters::new(15, 6).num_timescales(2),
+            Hyperparameters::new(15, 6).fixed_decay(0.3),
+            Hyperparameters::new(15, 6).pooling(Pooling::LearnedPositions { n: 3 }),
+            Hyperparameters::new(15, 8).max_history(4),
+        ];
+
+        for hyperparameters in configurations {
+            let mut model = hyperparameters
+                .learning_rate(0.1)
+                .num_epochs(2)
+                .num_threads(1)
+                .from_seed([42; 16])
+                .build();
+            model.fit(&data).unwrap();
+            let graph = model.onnx_graph().unwrap();
+
+            for history in &[
+                items(&[3]),
+                items(&[1, 2, 3]),
+                items(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]),
+            ] {
+                let expected = model.user_representation(history).unwrap();
+                for (x, y) in izip!(graph.user_embedding(history), expected.user_embedding) {
+                    assert!((x - y).abs() < 1e-5, "{} != {}", x, y);
+                }
+            }
+        }
+
+        let path = std::env::temp_dir().join("sbr_ewma.onnx");
+        let mut model = Hyperparameters::new(15, 6)
+            .num_epochs(0)
+            .num_threads(1)
+            .build();
+        match model.export_onnx(&path) {
+            Err(OnnxError::ModelNotFitted) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        model.fit(&data).unwrap();
+        model.export_onnx(&path).unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() > 15 * 16 * 4);
+        std::fs::remove_file(&path).unwrap();
+
+        let model = assume_fitted(Hyperparameters::new(15, 6).time_aware(5.0).build());
+        match model.export_onnx(&path) {
+            Err(OnnxError::Unsupported(_)) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn hyperparameters_without_warmup_steps() {
+        // Serialized before warmup was added.
+        let mut value = serde_json::to_value(Hyperparameters::new(10, 5)).unwrap();
+        value.as_object_mut().unwrap().remove("warmup_steps");
+
+        let hyper: Hyperparameters = serde_json::from_value(value).unwrap();
+        assert_eq!(hyper.warmup_steps, 0);
+    }
+
+    #[test]
+    fn invalid_hyperparameters() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+
+        let cases = vec![
+            ("fixed_decay", Hyperparameters::new(20, 10).fixed_decay(0.0)),
+            ("fixed_decay", Hyperparameters::new(20, 10).fixed_decay(1.0)),
+            (
+                "decay_bounds",
+                Hyperparameters::new(20, 10).decay_bounds(0.6, 0.2),
+            ),
+            (
+                "decay_bounds",
+                Hyperparameters::new(20, 10).decay_bounds(0.0, 1.5),
+            ),
+            (
+                "num_timescales",
+                Hyperparameters::new(20, 10).num_timescales(0),
+            ),
+            (
+                "pooling",
+                Hyperparameters::new(20, 10).pooling(Pooling::LearnedPositions { n: 0 }),
+            ),
+            (
+                "half_life_init",
+                Hyperparameters::new(20, 10).time_aware(0.0),
+            ),
+            ("max_history", Hyperparameters::new(20, 10).max_history(0)),
+            (
+                "anneal_alpha",
+                Hyperparameters::new(20, 10).anneal_alpha(true, 1.5),
+            ),
+        ];
+
+        for (expected, hyper) in cases {
+            let mut model = hyper.num_epochs(1).num_threads(1).build();
+            match model.fit(&data) {
+                Err(FittingError::InvalidHyperparameter { name, .. }) if name == expected => {}
+                other => panic!("Unexpected result for {}: {:?}", expected, other),
+            }
+        }
+    }
+
+    #[test]
+    fn decay_init_and_bounds() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+
+        let mut model = Hyperparameters::new(20, 10)
+            .decay_bounds(0.2, 0.6)
+            .decay_init(0.3)
+            .learning_rate(0.5)
+            .num_epochs(1)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+
+        assert!(model
+            .decay()
+            .iter()
+            .all(|&decay| (decay - 0.3).abs() < 1e-5));
+
+        for _ in 0..10 {
+            model.fit(&data).unwrap();
+            assert!(model
+                .decay()
+                .iter()
+                .all(|&decay| decay >= 0.2 && decay <= 0.6));
+        }
+
+        let mut model = Hyperparameters::new(20, 10)
+            .decay_bounds(0.2, 0.6)
+            .decay_init(0.7)
+            .build();
+        match model.fit(&data) {
+            Err(FittingError::InvalidHyperparameter {
+                name: "decay_init", ..
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn time_aware_gaps() {
+        let model = assume_fitted(
+            Hyperparameters::new(10, 5)
+                .time_aware(10.0)
+                .from_seed([42; 16])
+                .build(),
+        );
+        let item_ids = items(&[1, 2, 3]);
+
+        let close = model
+            .user_representation_with_timestamps(&item_ids, &times(&[0, 1, 2]))
+            .unwrap();
+        let far = model
+            .user_representation_with_timestamps(&item_ids, &times(&[0, 20, 40]))
+            .unwrap();
+
+        assert!(
+            izip!(&close.user_embedding, &far.user_embedding).any(|(x, y)| (x - y).abs() > 1e-4)
+        );
+
+        // A gap of many half-lives discards the history.
+        let reset = model
+            .user_representation_with_timestamps(&item_ids, &times(&[0, 1, 10_000]))
+            .unwrap();
+
+        for (x, y) in izip!(&reset.user_embedding, item_embedding(&model, ItemId(3))) {
+            assert!((x - y).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn time_aware_fixed_half_life() {
+        let interactions: Vec<_> = (0..20)
+            .flat_map(|user_id| {
+                (0..10).map(move |t| {
+                    Interaction::new(
+                        UserId(user_id),
+                        ItemId((user_id + t) % 20),
+                        Timestamp((t * t) as i64),
+                    )
+                })
+            })
+            .collect();
+        let data = Interactions::from(interactions).to_compressed();
+
+        let mut model = Hyperparameters::new(20, 10)
+            .time_aware(5.0)
+            .learn_half_life(false)
+            .num_epochs(2)
+            .num_threads(1)
+            .build();
+        let decay = model.decay();
+
+        assert!(model.fit(&data).unwrap().is_finite());
+        assert_eq!(model.decay(), decay);
+    }
+
+    #[test]
+    fn time_aware_negative_timestamps() {
+        // Only time differences matter, so moving every interaction
+        // before 1970 leaves the fitted model unchanged.
+        let fit = |offset: i64| {
+            let interactions: Vec<_> = (0..20)
+                .flat_map(|user_id| {
+                    (0..10).map(move |t| {
+                        Interaction::new(
+                            UserId(user_id),
+                            ItemId((user_id + t) % 20),
+                            Timestamp((t * t) as i64 + offset),
+                        )
+                    })
+                })
+                .collect();
+            let data = Interactions::from(interactions).to_compressed();
+
+            let mut model = Hyperparameters::new(20, 10)
+                .time_aware(5.0)
+                .num_epochs(2)
+                .num_threads(1)
+                .from_seed([42; 16])
+                .build();
+            let loss = model.fit(&data).unwrap();
+            let user = model
+                .user_representation_with_timestamps(
+                    &items(&[1, 2, 3]),
+                    &times(&[offset, offset + 4, offset + 9]),
+                )
+                .unwrap();
+
+            (loss, model.predict_all_items(&user, 20).unwrap())
+        };
+
+        assert_eq!(fit(0), fit(-1_000_000_000));
+    }
+
+    #[test]
+    fn timescale_parameters() {
+        let model = assume_fitted(
+            Hyperparameters::new(10, 5)
+                .embedding_dim(4)
+                .num_timescales(3)
+                .build(),
+        );
+
+        let decays = model.timescale_decays();
+        assert_eq!(decays.len(), 3);
+        assert!((decays[0][0] - 0.25).abs() < 1e-5);
+        assert!((decays[2][0] - 0.75).abs() < 1e-5);
+
+        assert!(model.summary().contains("alpha (3 timescales): [1, 4]"));
+        assert!(model.summary().contains("timescale_mixing_2: [1, 4]"));
+
+        let serialized = serde_json::to_string(&model).unwrap();
+        let deserialized: ImplicitEWMAModel = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.timescale_decays(), decays);
+
+        // A convex combination of averages of a single item is that item.
+        let user = model.user_representation(&items(&[3, 3, 3])).unwrap();
+        for (x, y) in izip!(&user.user_embedding, item_embedding(&model, ItemId(3))) {
+            assert!((x - y).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn multiple_timescales() {
+        // Users walk over twenty positions, each with two variants. The next
+        // position follows from the previous item, and the variant from the
+        // user's long-term group (the first item), except for every fifth
+        // interaction, which takes the other variant.
+        let num_items = 42;
+        let interactions: Vec<_> = (0..400)
+            .flat_map(|user_id| {
+                let group = user_id % 2;
+                (0..9).map(move |t| {
+                    let item_id = if t == 0 {
+                        group
+                    } else {
+                        let position = (user_id / 2 + t) % 20;
+                        let variant = if (user_id + t) % 5 == 0 {
+                            1 - group
+                        } else {
+                            group
+                        };
+                        2 + 2 * position + variant
+                    };
+                    Interaction::new(UserId(user_id), ItemId(item_id), Timestamp(t as i64))
+                })
+            })
+            .collect();
+        let data = Interactions::from(interactions);
+
+        let build = |hyperparameters: Hyperparameters| {
+            hyperparameters
+                .embedding_dim(16)
+                .learning_rate(0.1)
+                .optimizer(Optimizer::Adagrad)
+                .loss(Loss::Hinge)
+                .num_epochs(20)
+                .num_threads(1)
+        };
+
+        let (short, _) = run_test(
+            data.clone(),
+            build(Hyperparameters::new(num_items, 20).fixed_decay(0.99)),
+        );
+        let (long, _) = run_test(
+            data.clone(),
+            build(Hyperparameters::new(num_items, 20).fixed_decay(0.01)),
+        );
+        let (mixed, _) = run_test(
+            data,
+            build(Hyperparameters::new(num_items, 20).num_timescales(2)),
+        );
+
+        assert!(mixed > short && mixed > long);
+    }
+
+    #[test]
+    fn advance_representation() {
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+        let item_range = Uniform::new(0, 50);
+
+        let models = vec![
+            Hyperparameters::new(50, 10).from_seed([1; 16]).build(),
+            Hyperparameters::new(50, 10)
+                .fixed_decay(0.3)
+                .from_seed([2; 16])
+                .build(),
+            Hyperparameters::new(50, 10)
+                .decay_bounds(0.1, 0.5)
+                .decay_init(0.2)
+                .from_seed([3; 16])
+                .build(),
+        ]
+        .into_iter()
+        .map(assume_fitted)
+        .collect::<Vec<_>>();
+
+        for model in &models {
+            for _ in 0..20 {
+                let history: Vec<_> = (0..Uniform::new(1, 11).sample(&mut rng))
+                    .map(|_| ItemId(item_range.sample(&mut rng)))
+                    .collect();
+
+                let incremental = history
+                    .iter()
+                    .fold(model.initial_representation(), |state, &item_id| {
+                        model.advance_representation(&state, item_id).unwrap()
+                    });
+                let batch = model.user_representation(&history).unwrap();
+
+                for (x, y) in izip!(&incremental.user_embedding, &batch.user_embedding) {
+                    assert!((x - y).abs() < 1e-5);
+                }
+            }
+        }
+
+        match models[0].advance_representation(&models[0].initial_representation(), ItemId(50)) {
+            Err(PredictionError::ItemIdOutOfRange {
+                item_id: ItemId(50),
+                num_items: 50,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // Unknown items can stand for the mean item, even without known items.
+        let empty = assume_fitted(
+            Hyperparameters::new(0, 10)
+                .unknown_items(UnknownItems::MeanItem)
+                .build(),
+        );
+        let unknown = empty
+            .advance_representation(&empty.initial_representation(), ItemId(50))
+            .unwrap();
+        assert!(unknown.user_embedding.iter().all(|&x| x == 0.0));
+
+        for hyper in vec![
+            Hyperparameters::new(50, 10).num_timescales(2),
+            Hyperparameters::new(50, 10).pooling(Pooling::LearnedPositions { n: 3 }),
+        ] {
+            let model = assume_fitted(hyper.build());
+            match model.advance_representation(&model.initial_representation(), ItemId(1)) {
+                Err(PredictionError::IncrementalUpdateUnsupported) => {}
+                other => panic!("Unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn advance_representation_time_aware() {
+        let model = assume_fitted(
+            Hyperparameters::new(50, 10)
+                .time_aware(4.0)
+                .from_seed([42; 16])
+                .build(),
+        );
+        let item_ids = items(&[3, 8, 1, 40]);
+        let timestamps = times(&[0, 2, 9, 10]);
+
+        let mut incremental = model
+            .advance_representation(&model.initial_representation(), item_ids[0])
+            .unwrap();
+        for idx in 1..item_ids.len() {
+            let time_delta = (timestamps[idx] - timestamps[idx - 1]) as f32;
+            incremental = model
+                .advance_representation_with_time_delta(&incremental, item_ids[idx], time_delta)
+                .unwrap();
+        }
+
+        let batch = model
+            .user_representation_with_timestamps(&item_ids, &timestamps)
+            .unwrap();
+
+        for (x, y) in izip!(&incremental.user_embedding, &batch.user_embedding) {
+            assert!((x - y).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn sparse_updates() {
+        let mut data = Interactions::new(20, 1000);
+        for interaction in synthetic_interactions(20, 10, 5).data() {
+            data.push(interaction.clone());
+        }
+        let data = data.to_compressed();
+
+        // Negatives are only drawn from items in the data.
+        let mut model = Hyperparameters::new(1000, 5)
+            .l2_penalty(0.1)
+            .negative_sampler(Box::new(PopularitySampler::new(&data)))
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        let before = model.params.item_embedding.value().to_owned();
+
+        model.fit(&data).unwrap();
+        let after = model.params.item_embedding.value().to_owned();
+
+        // Sequences and negatives only contain items below 10: only those
+        // rows change.
+        assert!((0..10).any(|row| before.row(row) != after.row(row)));
+        assert!((10..1000).all(|row| before.row(row) == after.row(row)));
+    }
+
+    #[test]
+    fn learned_positions() {
+        // Every user repeats three items, so the next item is always
+        // the third-to-last one.
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+        let item_range = Uniform::new(0, 50);
+
+        let mut interactions = Vec::new();
+        for user_id in 0..500 {
+            let mut items = Vec::new();
+            while items.len() < 3 {
+                let item_id = ItemId(item_range.sample(&mut rng));
+                if !items.contains(&item_id) {
+                    items.push(item_id);
+                }
+            }
+
+            for t in 0..12 {
+                interactions.push(Interaction::new(
+                    UserId(user_id),
+                    items[t % 3],
+                    Timestamp(t as i64),
+                ));
+            }
+        }
+        let data = Interactions::from(interactions).to_compressed();
+
+        let mut model = Hyperparameters::new(50, 12)
+            .pooling(Pooling::LearnedPositions { n: 4 })
+            .learning_rate(0.1)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(10)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        assert_eq!(model.position_weights(), Some(vec![0.25; 4]));
+
+        model.fit(&data).unwrap();
+
+        let weights = model.position_weights().unwrap();
+        let best = (0..4)
+            .max_by(|&x, &y| weights[x].partial_cmp(&weights[y]).unwrap())
+            .unwrap();
+        assert_eq!(best, 2);
+
+        assert!(model.summary().contains("position_logits: [1, 4]"));
+
+        let serialized = serde_json::to_string(&model).unwrap();
+        let deserialized: ImplicitEWMAModel = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.position_weights(), Some(weights));
+    }
+
+    #[test]
+    fn grow_items() {
+        for &init in &[GrowInit::Random, GrowInit::Zeros, GrowInit::MeanOfExisting] {
+            let mut model = assume_fitted(
+                Hyperparameters::new(10, 5)
+                    .unknown_items(UnknownItems::MeanItem)
+                    .from_seed([42; 16])
+                    .build(),
+            );
+            let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+            let old_items: Vec<_> = (0..10).map(ItemId).collect();
+            let predictions = model.predict(&user, &old_items).unwrap();
+
+            // Before growth, unknown items stand for the mean item.
+            let unknown_user = model.user_representation(&items(&[1, 2, 30])).unwrap();
+            let unknown_predictions = model.predict(&user, &items(&[10, 14])).unwrap();
+
+            model.grow_items(15, init);
+
+            assert_eq!(model.predict(&user, &old_items).unwrap(), predictions);
+            assert_eq!(model.predict(&user, &items(&[10, 14])).unwrap().len(), 2);
+
+            if init == GrowInit::MeanOfExisting {
+                assert_eq!(
+                    model
+                        .user_representation(&items(&[1, 2, 12]))
+                        .unwrap()
+                        .user_embedding,
+                    unknown_user.user_embedding
+                );
+                assert_eq!(
+                    model.predict(&user, &items(&[10, 14])).unwrap(),
+                    unknown_predictions
+                );
+            }
+
+            let embeddings = model.params.item_embedding.value().to_owned();
+            assert_eq!(embeddings.shape(), &[15, 16]);
+            assert_eq!(model.params.item_biases.value().shape(), &[15, 1]);
+
+            let new_row = embeddings.row(12);
+            match init {
+                GrowInit::Random => assert!(new_row.iter().any(|&x| x != 0.0)),
+                GrowInit::Zeros => assert!(new_row.iter().all(|&x| x == 0.0)),
+                GrowInit::MeanOfExisting => {
+                    for col in 0..16 {
+                        let mean = (0..10).map(|row| embeddings[(row, col)]).sum::<f32>() / 10.0;
+                        assert!((new_row[col] - mean).abs() < 1e-6);
+                    }
+                }
+            }
+
+            let serialized = serde_json::to_string(&model).unwrap();
+            let deserialized: ImplicitEWMAModel = serde_json::from_str(&serialized).unwrap();
+            assert_eq!(deserialized.params.hyper.num_items, 15);
+            assert_eq!(
+                deserialized.params.item_embedding.value().shape(),
+                &[15, 16]
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_items() {
+        let model = assume_fitted(Hyperparameters::new(10, 5).from_seed([42; 16]).build());
+        let user = model.user_representation(&items(&[1, 2])).unwrap();
+
+        let expect_out_of_range = |result: Result<(), PredictionError>| match result {
+            Err(PredictionError::ItemIdOutOfRange {
+                item_id: ItemId(12),
+                num_items: 10,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        };
+        expect_out_of_range(model.user_representation(&items(&[1, 12])).map(|_| ()));
+        expect_out_of_range(
+            model
+                .user_representations(&[&items(&[1, 2])[..], &items(&[12])[..]])
+                .map(|_| ()),
+        );
+        expect_out_of_range(model.predict(&user, &items(&[3, 12])).map(|_| ()));
+        expect_out_of_range(
+            model
+                .predict_candidates(&user, &items(&[3, 12]), 1)
+                .map(|_| ()),
+        );
+        match model.predict_all_items(&user, 12) {
+            Err(PredictionError::ItemIdOutOfRange { .. }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let model = assume_fitted(
+            Hyperparameters::new(10, 5)
+                .unknown_items(UnknownItems::MeanItem)
+                .from_seed([42; 16])
+                .build(),
+        );
+        let histories = [items(&[1, 12]), items(&[3, 4, 5]), items(&[20, 2])];
+        let history_slices: Vec<_> = histories.iter().map(|history| &history[..]).collect();
+        let batch = model.user_representations(&history_slices).unwrap();
+        for (history, user) in izip!(&histories, &batch) {
+            assert_eq!(
+                model.user_representation(history).unwrap().user_embedding,
+                user.user_embedding
+            );
+        }
+
+        // All unknown ids are scored alike, and as the mean item.
+        let mean_item = model.params.mean_item();
+        let expected = mean_item.bias
+            + izip!(&user.user_embedding, &mean_item.embedding)
+                .map(|(x, y)| x * y)
+                .sum::<f32>();
+        let scores = model.predict(&user, &items(&[10, 25])).unwrap();
+        assert_eq!(scores[0], scores[1]);
+        assert!((scores[0] - expected).abs() < 1e-5);
+
+        let all_scores = model.predict_all_items(&user, 12).unwrap();
+        assert_eq!(all_scores.len(), 12);
+        assert_eq!(&all_scores[10..], &scores[..]);
+    }
+
+    #[test]
+    fn predict_all_items_matches_predict() {
+        let model = assume_fitted(Hyperparameters::new(100, 10).from_seed([42; 16]).build());
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+
+        let item_ids: Vec<_> = (0..100).map(ItemId).collect();
+        let expected = model.predict(&user, &item_ids).unwrap();
+
+        for (x, y) in izip!(model.predict_all_items(&user, 100).unwrap(), expected) {
+            assert!((x - y).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn predict_into_matches_predict() {
+        let model = assume_fitted(Hyperparameters::new(100, 10).from_seed([42; 16]).build());
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+
+        let item_ids = items(&[5, 17, 42, 99, 0, 63]);
+        let mut out = vec![0.0; item_ids.len()];
+        model.predict_into(&user, &item_ids, &mut out).unwrap();
+        assert_eq!(out, model.predict(&user, &item_ids).unwrap());
+
+        match model.predict_into(&user, &item_ids, &mut out[1..]) {
+            Err(PredictionError::OutputLengthMismatch {
+                num_predictions: 6,
+                output_len: 5,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn predict_candidates_matches_predict() {
+        let model = assume_fitted(Hyperparameters::new(100, 10).from_seed([42; 16]).build());
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+
+        let candidates = items(&[5, 17, 42, 99, 0, 63]);
+        let scores = model.predict(&user, &candidates).unwrap();
+
+        let mut expected: Vec<_> = candidates.iter().cloned().zip(scores).collect();
+        expected.sort_by(|x, y| y.1.partial_cmp(&x.1).unwrap());
+        expected.truncate(3);
+
+        assert_eq!(
+            model.predict_candidates(&user, &candidates, 3).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn max_history() {
+        // A few users with very long, regular histories.
+        let num_items = 50;
+        let data = synthetic_interactions(10, num_items, 2000).to_compressed();
+
+        let hyperparameters = Hyperparameters::new(num_items, 20)
+            .embedding_dim(8)
+            .num_epochs(1)
+            .num_threads(1)
+            .from_seed([42; 16]);
+
+        // The losses of three successive one-epoch fits, and the number of
+        // optimizer steps they took.
+        let fit = |hyperparameters: Hyperparameters| {
+            let mut model = hyperparameters.build();
+            let steps = AtomicUsize::new(0);
+            let losses: Vec<f32> = (0..3)
+                .map(|_| {
+                    fit_sequence_model_with_hook(&data, &mut model.params, &|_, _| {
+                        steps.fetch_add(1, Ordering::SeqCst);
+                    })
+                    .unwrap()
+                })
+                .collect();
+
+            (losses, steps.into_inner())
+        };
+
+        let (uncapped_losses, uncapped_steps) = fit(hyperparameters.clone());
+        let (capped_losses, capped_steps) = fit(hyperparameters.max_history(1800));
+
+        // A generous cap closely follows the uncapped loss trajectory, in
+        // fewer steps.
+        for (capped, uncapped) in izip!(&capped_losses, &uncapped_losses) {
+            assert!((capped - uncapped).abs() < 0.25 * uncapped_losses[0]);
+        }
+        assert!(capped_steps < uncapped_steps);
+
+        // Representations only look at the most recent interactions.
+        let history: Vec<_> = (0..1000).map(|t| ItemId(t % num_items as u64)).collect();
+        let model = assume_fitted(
+            Hyperparameters::new(num_items, 20)
+                .max_history(5)
+                .from_seed([42; 16])
+                .build(),
+        );
+        assert_eq!(
+            model.user_representation(&history).unwrap().user_embedding,
+            model
+                .user_representation(&history[history.len() - 5..])
+                .unwrap()
+                .user_embedding
+        );
+    }
+
+    #[test]
+    fn bias_term() {
+        // Item `i` is interacted with by `i + 1` users.
+        let interactions: Vec<_> = (0..5)
+            .flat_map(|item_id| {
+                (0..=item_id).flat_map(move |user_id| {
+                    vec![
+                        Interaction::new(
+                            UserId(user_id),
+                            ItemId(item_id),
+                            Timestamp(2 * item_id as i64),
+                        ),
+                        Interaction::new(
+                            UserId(user_id),
+                            ItemId((item_id + 1) % 5),
+                            Timestamp(2 * item_id as i64 + 1),
+                        ),
+                    ]
+                })
+            })
+            .collect();
+        let data = Interactions::from(interactions).to_compressed();
+
+        let counts: Vec<f32> = (0..5)
+            .map(|item_id| {
+                data.iter_users()
+                    .flat_map(|user| user.item_ids.iter())
+                    .filter(|&&x| x == ItemId(item_id))
+                    .count() as f32
+            })
+            .collect();
+
+        // By default, the biases start at zero.
+        let mut model = Hyperparameters::new(5, 10)
+            .num_epochs(0)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+        assert!(model.params.item_biases.value().iter().all(|&x| x == 0.0));
+
+        // Without training, opted-in biases are the log popularity.
+        let mut model = Hyperparameters::new(5, 10)
+            .popularity_bias_init(true)
+            .num_epochs(0)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+        let biases: Vec<f32> = model.params.item_biases.value().iter().cloned().collect();
+        assert_eq!(
+            biases,
+            counts.iter().map(|count| count.ln_1p()).collect::<Vec<_>>()
+        );
+
+        // Disabled biases are never initialized or trained.
+        let mut model = Hyperparameters::new(5, 10)
+            .bias_term(false)
+            .popularity_bias_init(true)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+        assert!(model.params.item_biases.value().iter().all(|&x| x == 0.0));
+
+        let user = model.user_representation(&items(&[0, 1])).unwrap();
+        let dot = wyrm::simd_dot(&user.user_embedding, &item_embedding(&model, ItemId(3)));
+        assert_eq!(model.predict(&user, &items(&[3])).unwrap(), vec![dot]);
+    }
+
+    #[test]
+    fn interaction_weights() {
+        let interactions = synthetic_interactions(20, 10, 10).data().to_vec();
+        let hyperparameters = Hyperparameters::new(10, 10)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16]);
+
+        let reweighted = |weight: f32| {
+            Interactions::from(
+                interactions
+                    .iter()
+                    .map(|x| x.clone().with_weight(weight))
+                    .collect::<Vec<_>>(),
+            )
+            .to_compressed()
+        };
+
+        // Unit weights are the same as no weights.
+        let unweighted = Interactions::from(interactions.clone()).to_compressed();
+        assert_eq!(
+            hyperparameters.clone().build().fit(&unweighted).unwrap(),
+            hyperparameters
+                .clone()
+                .build()
+                .fit(&reweighted(1.0))
+                .unwrap()
+        );
+
+        // Zero-weight interactions leave the embeddings untouched.
+        let mut model = hyperparameters.build();
+        let initial = model.params.item_embedding.value().clone();
+        assert_eq!(model.fit(&reweighted(0.0)).unwrap(), 0.0);
+        assert_eq!(*model.params.item_embedding.value(), initial);
+    }
+
+    #[test]
+    fn anneal_alpha() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+        let history = items(&[1, 2, 3, 4]);
+
+        for hyperparameters in vec![
+            Hyperparameters::new(20, 10).fixed_decay(0.5),
+            Hyperparameters::new(20, 10),
+        ] {
+            // Outside training, annealed models use the target decay.
+            let model = assume_fitted(hyperparameters.clone().from_seed([42; 16]).build());
+            let annealed = assume_fitted(
+                hyperparameters
+                    .clone()
+                    .anneal_alpha(true, 0.0)
+                    .from_seed([42; 16])
+                    .build(),
+            );
+            let expected = model.user_representation(&history).unwrap();
+            let actual = annealed.user_representation(&history).unwrap();
+            for (x, y) in izip!(&expected.user_embedding, &actual.user_embedding) {
+                assert!((x - y).abs() < 1e-5);
+            }
+
+            let mut annealed = hyperparameters
+                .anneal_alpha(true, 0.0)
+                .num_epochs(4)
+                .num_threads(1)
+                .from_seed([42; 16])
+                .build();
+            assert!(annealed.fit(&data).unwrap().is_finite());
+        }
+
+        // Progress reaches the target halfway through training.
+        let model = Hyperparameters::new(20, 10)
+            .anneal_alpha(true, 0.1)
+            .num_epochs(10)
+            .build();
+        let graph = model.params.build();
+        let progress = |epoch| {
+            model.params.start_epoch(&graph, epoch);
+            graph.anneal_progress.as_ref().unwrap().value()[(0, 0)]
+        };
+        assert_eq!(progress(0), 0.0);
+        assert!((progress(2) - 0.4).abs() < 1e-6);
+        assert_eq!(progress(5), 1.0);
+        assert_eq!(progress(9), 1.0);
+
+        let model = Hyperparameters::new(20, 10)
+            .anneal_alpha(false, 0.1)
+            .build();
+        assert!(model.params.build().anneal_progress.is_none());
+    }
+
+    #[test]
+    fn annealed_training_decay() {
+        let history = items(&[1, 2, 3, 4]);
+
+        // Fixed decays give a normalized average, learned ones weight each
+        // new item by the decay.
+        for (hyperparameters, normalized) in vec![
+            (
+                Hyperparameters::new(20, history.len()).fixed_decay(0.5),
+                true,
+            ),
+            (Hyperparameters::new(20, history.len()), false),
+        ] {
+            let model = hyperparameters
+                .anneal_alpha(true, 0.0)
+                .num_epochs(4)
+                .from_seed([42; 16])
+                .build();
+            let target = model.decay()[0];
+
+            // The state the graph should compute with a decay of `decay`.
+            let expected = |decay: f32| {
+                let mut state = item_embedding(&model, history[0]);
+                let mut normalizer = 1.0;
+                for &item_id in &history[1..] {
+                    normalizer = (1.0 - decay) * normalizer + 1.0;
+                    let weight = if normalized { 1.0 / normalizer } else { decay };
+                    for (x, y) in izip!(&mut state, item_embedding(&model, item_id)) {
+                        *x = (1.0 - weight) * *x + weight * y;
+                    }
+                }
+                state
+            };
+
+            // The decay goes from zero at epoch 0 to the target over the
+            // first two epochs.
+            for (epoch, decay) in vec![(0, 0.0), (1, target / 2.0), (2, target), (3, target)] {
+                let expected = expected(decay);
+
+                let graph = model.params.build();
+                for (input, item_id) in izip!(&graph.inputs, &history) {
+                    input.set_value(item_id.index());
+                }
+                model.params.start_epoch(&graph, epoch);
+
+                let state = graph.hidden_states.last().unwrap();
+                state.forward();
+
+                for (x, y) in izip!(state.value().iter(), expected) {
+                    assert!((x - y).abs() < 1e-5, "Epoch {}: {} != {}", epoch, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn batched_user_representations() {
+        let model = assume_fitted(Hyperparameters::new(20, 10).from_seed([42; 16]).build());
+
+        // Shorter histories follow longer ones, and the longest is truncated.
+        let histories = vec![
+            items(&[1, 2, 3, 4, 5]),
+            items(&[6]),
+            items(&[7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18]),
+            items(&[3, 2]),
+        ];
+        let histories: Vec<&[ItemId]> = histories.iter().map(|x| &x[..]).collect();
+
+        let batched = model.user_representations(&histories).unwrap();
+        assert_eq!(batched.len(), histories.len());
+
+        for (history, user) in izip!(histories, batched) {
+            let expected = model.user_representation(history).unwrap();
+            assert_eq!(user.user_embedding, expected.user_embedding);
+            assert_eq!(user.num_interactions, expected.num_interactions);
+        }
+    }
+
+    #[test]
+    fn batched_losses_match_single_sequences() {
+        let sequences: Vec<Vec<usize>> =
+            vec![vec![1, 2, 3, 4, 5, 6], vec![7, 8, 9], vec![10, 11, 12, 13]];
+        let negative = |item: usize| (item + 5) % 20;
+        let configs = vec![
+            Hyperparameters::new(20, 6),
+            Hyperparameters::new(20, 6).num_timescales(2),
+            Hyperparameters::new(20, 6).time_aware(2.0),
+            Hyperparameters::new(20, 6).pooling(Pooling::LearnedPositions { n: 3 }),
+        ];
+
+        for hyper in configs {
+            let params = hyper.embedding_dim(8).from_seed([42; 16]).build().params;
+
+            // One row per sequence, and an empty row.
+            let batched = params.build_batch(4);
+            let (inputs, outputs, negatives, _) = batched.state();
+            for idx in 0..5 {
+                let column = |offset: usize| -> Vec<_> {
+                    (0..4)
+                        .map(|row| match sequences.get(row) {
+                            Some(sequence) if idx + 1 < sequence.len() => sequence[idx + offset],
+                            _ => 0,
+                        })
+                        .collect()
+                };
+                let negative_ids: Vec<_> = column(1).into_iter().map(negative).collect();
+                inputs[idx].set_value(&column(0)[..]);
+                outputs[idx].set_value(&column(1)[..]);
+                negatives[idx].set_value(&negative_ids[..]);
+            }
+            for (idx, weight) in batched.weights().iter().enumerate() {
+                weight.set_value(&Arr::from_shape_fn((4, 1), |(row, _)| {
+                    match sequences.get(row) {
+                        Some(sequence) if idx + 1 < sequence.len() => 1.0,
+                        _ => 0.0,
+                    }
+                }));
+            }
+            for delta in batched.time_deltas() {
+                delta.set_value(&Arr::from_shape_fn((4, 8), |(row, _)| row as f32 + 1.0));
+            }
+
+            let mut batched = batched;
+            let batched_loss = &mut batched.losses()[4];
+            batched_loss.forward();
+
+            let mut expected = 0.0;
+            for (row, sequence) in sequences.iter().enumerate() {
+                let mut single = params.build();
+                {
+                    let (inputs, outputs, negatives, _) = single.state();
+                    for (idx, pair) in sequence.windows(2).enumerate() {
+                        inputs[idx].set_value(pair[0]);
+                        outputs[idx].set_value(pair[1]);
+                        negatives[idx].set_value(negative(pair[1]));
+                    }
+                    for delta in single.time_deltas() {
+                        delta.set_value(&Arr::from_elem((1, 8), row as f32 + 1.0));
+                    }
+                }
+
+                let loss = &mut single.losses()[sequence.len() - 2];
+                loss.forward();
+                expected += loss.value().scalar_sum();
+            }
+
+            let actual = batched_loss.value().scalar_sum();
+            assert!(
+                (actual - expected).abs() < 1e-4,
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn batched_fit() {
+        let data = synthetic_interactions(50, 20, 10).to_compressed();
+
+        for batch_size in vec![1, 8] {
+            let mut model = Hyperparameters::new(20, 10)
+                .embedding_dim(16)
+                .learning_rate(0.1)
+                .optimizer(Optimizer::Adagrad)
+                .num_epochs(20)
+                .num_threads(1)
+                .sequence_batch_size(batch_size)
+                .from_seed([42; 16])
+                .build();
+
+            let untrained = assume_fitted(model.clone()).perplexity(&data).unwrap();
+            let loss = model.fit(&data).unwrap();
+            let trained = model.perplexity(&data).unwrap();
+
+            assert!(loss.is_finite());
+            assert!(trained < untrained);
+        }
+
+        let mut model = Hyperparameters::new(20, 10).sequence_batch_size(0).build();
+        match model.fit(&data) {
+            Err(FittingError::InvalidHyperparameter {
+                name: "sequence_batch_size",
+                ..
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn perplexity() {
+        let data = synthetic_interactions(50, 20, 10).to_compressed();
+        let mut model = Hyperparameters::new(20, 10)
+            .embedding_dim(16)
+            .learning_rate(0.1)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(20)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+
+        // An untrained model is close to uniform.
+        let untrained = assume_fitted(model.clone()).perplexity(&data).unwrap();
+        assert!(untrained > 10.0 && untrained < 40.0);
+
+        model.fit(&data).unwrap();
+        let trained = model.perplexity(&data).unwrap();
+        assert!(trained >= 1.0 && trained < untrained);
+    }
+
+    #[test]
+    fn warmup_learning_rates() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+
+        for parallelism in vec![Parallelism::Synchronous, Parallelism::Asynchronous] {
+            let mut model = Hyperparameters::new(20, 10)
+                .learning_rate(0.5)
+                .warmup_steps(4)
+                .parallelism(parallelism.clone())
+                .num_epochs(2)
+                .num_threads(1)
+                .from_seed([42; 16])
+                .build();
+
+            let steps = Mutex::new(Vec::new());
+            fit_sequence_model_with_hook(&data, &mut model.params, &|step, learning_rate| {
+                steps.lock().unwrap().push((step, learning_rate))
+            })
+            .unwrap();
+
+            let steps = steps.into_inner().unwrap();
+            assert!(steps.len() > 4);
+            assert_eq!(
+                steps[..5].iter().map(|&(_, rate)| rate).collect::<Vec<_>>(),
+                vec![0.125, 0.25, 0.375, 0.5, 0.5]
+            );
+            for (idx, &(step, rate)) in steps.iter().enumerate() {
+                assert_eq!(step, idx);
+                assert_eq!(rate, warmup_learning_rate(0.5, 4, step));
+            }
+        }
+
+        // Hogwild threads share one step counter.
+        let mut model = Hyperparameters::new(20, 10)
+            .warmup_steps(4)
+            .parallelism(Parallelism::Asynchronous)
+            .num_epochs(2)
+            .num_threads(2)
+            .from_seed([42; 16])
+            .build();
+        let steps = Mutex::new(Vec::new());
+        fit_sequence_model_with_hook(&data, &mut model.params, &|step, _| {
+            steps.lock().unwrap().push(step)
+        })
+        .unwrap();
+
+        let mut steps = steps.into_inner().unwrap();
+        steps.sort();
+        assert_eq!(steps, (0..steps.len()).collect::<Vec<_>>());
+    }
+
+    /// Scores every item alike, but higher for users whose first item has
+    /// an even id than for the others.
+    #[derive(Debug)]
+    struct SkewedTeacher;
+
+    impl OnlineRankingModel for SkewedTeacher {
+        type UserRepresentation = f32;
+
+        fn user_representation(&self, item_ids: &[ItemId]) -> Result<f32, PredictionError> {
+            Ok(if item_ids[0].index() % 2 == 0 {
+                1.0
+            } else {
+                -1.0
+            })
+        }
+
+        fn predict(&self, user: &f32, item_ids: &[ItemId]) -> Result<Vec<f32>, PredictionError> {
+            Ok(vec![*user; item_ids.len()])
+        }
+    }
+
+    #[test]
+    fn user_biases() {
+        // Each user starts from its own id, so the teacher scores even
+        // users high and odd users low.
+        let data = synthetic_interactions(20, 10, 8).to_compressed();
+        let hyperparameters = Hyperparameters::new(10, 8)
+            .learning_rate(0.5)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(30)
+            .num_threads(1)
+            .from_seed([42; 16]);
+
+        let mut without_biases = hyperparameters.clone().build();
+        let loss_without_biases = without_biases
+            .fit_to_teacher(&data, &SkewedTeacher)
+            .unwrap();
+        let mut model = hyperparameters.use_user_biases(true).build();
+        let loss = model.fit_to_teacher(&data, &SkewedTeacher).unwrap();
+        assert!(loss < loss_without_biases);
+
+        let biases = model.params.user_biases.as_ref().unwrap().value().clone();
+        assert_eq!(biases.shape(), &[20, 1]);
+        for (user_idx, &bias) in biases.iter().enumerate() {
+            assert_eq!(bias > 0.0, user_idx % 2 == 0);
+        }
+
+        // Only representations for a known user carry its bias, which
+        // shifts all of its scores.
+        let history = items(&[3, 4, 5]);
+        let user = model.user_representation(&history).unwrap();
+        let known = model
+            .user_representation_for_user(UserId(2), &history)
+            .unwrap();
+        let unknown = model
+            .user_representation_for_user(UserId(20), &history)
+            .unwrap();
+        assert_eq!(known.user_bias, Some(biases[(2, 0)]));
+        assert_eq!(unknown.user_bias, None);
+
+        let scores = model.predict(&user, &items(&[1, 2])).unwrap();
+        let known_scores = model.predict(&known, &items(&[1, 2])).unwrap();
+        for (score, known_score) in izip!(&scores, &known_scores) {
+            assert!((known_score - score - biases[(2, 0)]).abs() < 1e-5);
+        }
+        let advanced = model
+            .advance_representation(&model.initial_representation_for_user(UserId(2)), ItemId(3))
+            .unwrap();
+        assert_eq!(advanced.user_bias, known.user_bias);
+
+        let deserialized = ImplicitEWMAModel::from_json(&model.to_json().unwrap()).unwrap();
+        assert_eq!(
+            deserialized.params.user_biases.as_ref().unwrap().value(),
+            &biases
+        );
+    }
+
+    #[test]
+    fn user_biases_track_activity() {
+        // Even users interact with many items and odd users with few, in
+        // the same cycle over the items, so their histories look alike.
+        let num_items = 10;
+        let interactions: Vec<_> = (0..20)
+            .flat_map(|user_id| {
+                let num_interactions = if user_id % 2 == 0 { 40 } else { 5 };
+                (0..num_interactions).map(move |t| {
+                    Interaction::new(
+                        UserId(user_id),
+                        ItemId((user_id + t) % num_items),
+                        Timestamp(t as i64),
+                    )
+                })
+            })
+            .collect();
+        let data = Interactions::from(interactions).to_compressed();
+
+        let mut model = Hyperparameters::new(num_items, 8)
+            .use_user_biases(true)
+            .learning_rate(0.5)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(30)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        assert!(model.fit(&data).unwrap().is_finite());
+
+        let biases = model.params.user_biases.as_ref().unwrap().value().clone();
+        let lowest_active = (0..20)
+            .step_by(2)
+            .map(|user_idx| biases[(user_idx, 0)])
+            .fold(f32::INFINITY, f32::min);
+        let highest_inactive = (1..20)
+            .step_by(2)
+            .map(|user_idx| biases[(user_idx, 0)])
+            .fold(f32::NEG_INFINITY, f32::max);
+        assert!(
+            lowest_active > highest_inactive,
+            "{} <= {}",
+            lowest_active,
+            highest_inactive
+        );
+
+        // The logistic loss over all items, rather than sampled negatives,
+        // is lower with the learned biases than without them.
+        let negative_weights = model.params.negative_weights.clone().unwrap();
+        let softplus = |x: f32| (1.0 + x.exp()).ln();
+        let logistic_loss = |with_biases: bool| -> f32 {
+            data.iter_users()
+                .map(|user| {
+                    (1..user.item_ids.len())
+                        .map(|t| {
+                            let history = &user.item_ids[..t];
+                            let representation = if with_biases {
+                                model.user_representation_for_user(user.user_id, history)
+                            } else {
+                                model.user_representation(history)
+                            }
+                            .unwrap();
+                            let scores =
+                                model.predict_all_items(&representation, num_items).unwrap();
+                            let negative_loss =
+                                scores.iter().map(|&score| softplus(score)).sum::<f32>()
+                                    / num_items as f32;
+
+                            softplus(-scores[user.item_ids[t].index()])
+                                + negative_weights[user.user_id.index()] * negative_loss
+                        })
+                        .sum::<f32>()
+                })
+                .sum()
+        };
+        assert!(logistic_loss(true) < logistic_loss(false));
+    }
+
+    #[test]
+    fn fit_to_teacher_invalid_inputs() {
+        let data = synthetic_interactions(20, 10, 8).to_compressed();
+        let hyperparameters = Hyperparameters::new(10, 8)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16]);
+
+        let cases = vec![
+            ("num_epochs", hyperparameters.clone().num_epochs(0)),
+            (
+                "sequence_batch_size",
+                hyperparameters.clone().sequence_batch_size(4),
+            ),
+            ("warmup_steps", hyperparameters.clone().warmup_steps(10)),
+        ];
+
+        for (expected, hyper) in cases {
+            let mut model = hyper.build();
+            match model.fit_to_teacher(&data, &SkewedTeacher) {
+                Err(Error::Fitting(FittingError::InvalidHyperparameter { name, .. }))
+                    if name == expected => {}
+                other => panic!("Unexpected result for {}: {:?}", expected, other),
+            }
+            assert!(!model.is_fitted());
+        }
+
+        let mut model = hyperparameters.build();
+        model.fit_to_teacher(&data, &SkewedTeacher).unwrap();
+        let more_users = synthetic_interactions(30, 10, 8).to_compressed();
+        match model.fit_to_teacher(&more_users, &SkewedTeacher) {
+            Err(Error::Fitting(FittingError::UserCountMismatch {
+                model_users: 20,
+                data_users: 30,
+            })) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
 }
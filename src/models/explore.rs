@@ -0,0 +1,289 @@
+//! Exploration at serving time.
+//!
+//! A model only gathers feedback on the items it recommends. [EpsilonGreedy]
+//! replaces a random fraction of a model's top-k slots with items drawn
+//! from an exploration distribution, and reports which slots were explored
+//! so that the resulting feedback can be told apart (or reweighted) later.
+use std::collections::HashSet;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::top_k;
+use crate::data::CompressedInteractions;
+use crate::ids::ItemId;
+use crate::{OnlineRankingModel, PredictionError};
+
+/// Distribution of the items substituted into explored slots.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Exploration {
+    /// Uniform over the catalog.
+    Uniform,
+    /// Proportional to the given per-item weights.
+    Weighted(Vec<f32>),
+}
+
+impl Exploration {
+    /// Explore items in proportion to their number of interactions.
+    /// Items without interactions are never explored.
+    pub fn popularity(interactions: &CompressedInteractions) -> Self {
+        let mut counts = vec![0.0; interactions.num_items()];
+
+        for user in interactions.iter_users() {
+            for &item_id in user.item_ids {
+                counts[item_id.index()] += 1.0;
+            }
+        }
+
+        Exploration::Weighted(counts)
+    }
+}
+
+/// Wraps a model with epsilon-greedy exploration of its top-k predictions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EpsilonGreedy<M> {
+    model: M,
+    num_items: usize,
+    epsilon: f32,
+    exploration: Exploration,
+}
+
+impl<M: OnlineRankingModel> EpsilonGreedy<M> {
+    /// Explore each of the top-k slots of `model` over `num_items` items
+    /// with probability `epsilon`, uniformly over the catalog.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `epsilon` is not in `[0, 1]`.
+    pub fn new(model: M, num_items: usize, epsilon: f32) -> Self {
+        assert!(
+            epsilon >= 0.0 && epsilon <= 1.0,
+            "Epsilon must be between 0 and 1."
+        );
+
+        EpsilonGreedy {
+            model,
+            num_items,
+            epsilon,
+            exploration: Exploration::Uniform,
+        }
+    }
+
+    /// Set the distribution of explored items. Defaults to
+    /// [Exploration::Uniform].
+    ///
+    /// # Panics
+    ///
+    /// Panics if [Exploration::Weighted] does not have one non-negative
+    /// weight per item.
+    pub fn exploration(mut self, exploration: Exploration) -> Self {
+        if let Exploration::Weighted(weights) = &exploration {
+            assert_eq!(weights.len(), self.num_items, "Need one weight per item.");
+            assert!(
+                weights.iter().all(|&x| x >= 0.0),
+                "Weights must be non-negative."
+            );
+        }
+        self.exploration = exploration;
+        self
+    }
+
+    /// Return the model's top `k` items for `user` as `(item_id, explored)`
+    /// pairs, where each slot independently holds, with probability
+    /// `epsilon`, an item sampled from the exploration distribution instead
+    /// of the model's item.
+    ///
+    /// Explored items are never items of the model's own top `k`, items
+    /// already in the list, or items in `exclude`. If no such item can be
+    /// sampled, the slot keeps the model's item.
+    pub fn predict_top_k<R: Rng>(
+        &self,
+        user: &M::UserRepresentation,
+        k: usize,
+        exclude: &[ItemId],
+        rng: &mut R,
+    ) -> Result<Vec<(ItemId, bool)>, PredictionError> {
+        let scores = self.model.predict_all_items(user, self.num_items)?;
+
+        if !scores.iter().all(|score| score.is_finite()) {
+            return Err(PredictionError::InvalidPredictionValue);
+        }
+
+        let exclude: HashSet<_> = exclude.iter().collect();
+        let exploited = top_k(
+            scores
+                .into_iter()
+                .enumerate()
+                .map(|(idx, score)| (ItemId::from_index(idx), score))
+                .filter(|(item_id, _)| !exclude.contains(item_id))
+                .collect(),
+            k,
+        );
+
+        // Weights of the items that can still be explored.
+        let mut weights = match &self.exploration {
+            Exploration::Uniform => vec![1.0; self.num_items],
+            Exploration::Weighted(weights) => weights.clone(),
+        };
+        for &item_id in exclude
+            .into_iter()
+            .chain(exploited.iter().map(|(item_id, _)| item_id))
+        {
+            if let Some(weight) = weights.get_mut(item_id.index()) {
+                *weight = 0.0;
+            }
+        }
+        let mut total: f32 = weights.iter().sum();
+
+        Ok(exploited
+            .into_iter()
+            .map(|(item_id, _)| {
+                if total <= 0.0 || rng.gen::<f32>() >= self.epsilon {
+                    return (item_id, false);
+                }
+
+                let mut threshold = rng.gen::<f32>() * total;
+                let explored = weights
+                    .iter()
+                    .position(|&weight| {
+                        threshold -= weight;
+                        threshold < 0.0
+                    })
+                    // Guard against rounding leaving a positive threshold.
+                    .unwrap_or_else(|| weights.iter().rposition(|&x| x > 0.0).unwrap());
+
+                total -= weights[explored];
+                weights[explored] = 0.0;
+
+                (ItemId::from_index(explored), true)
+            })
+            .collect())
+    }
+
+    /// Return the wrapped model.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+}
+
+impl<M: OnlineRankingModel> OnlineRankingModel for EpsilonGreedy<M> {
+    type UserRepresentation = M::UserRepresentation;
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        self.model.user_representation(item_ids)
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.model.predict(user, item_ids)
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.model.predict_all_items(user, num_items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+    use crate::models::baselines::{BaselineUser, Random};
+
+    fn model_top_k(k: usize, exclude: &[ItemId]) -> Vec<ItemId> {
+        let scores = Random::new(7).predict_all_items(&BaselineUser, 50).unwrap();
+
+        top_k(
+            scores
+                .into_iter()
+                .enumerate()
+                .map(|(idx, score)| (ItemId::from_index(idx), score))
+                .filter(|(item_id, _)| !exclude.contains(item_id))
+                .collect(),
+            k,
+        )
+        .into_iter()
+        .map(|(item_id, _)| item_id)
+        .collect()
+    }
+
+    fn assert_distinct(items: &[(ItemId, bool)]) {
+        let distinct: HashSet<_> = items.iter().map(|&(item_id, _)| item_id).collect();
+        assert_eq!(distinct.len(), items.len());
+    }
+
+    #[test]
+    fn no_exploration() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let model = EpsilonGreedy::new(Random::new(7), 50, 0.0);
+
+        let items = model
+            .predict_top_k(&BaselineUser, 10, &[ItemId(3)], &mut rng)
+            .unwrap();
+
+        assert!(items.iter().all(|&(_, explored)| !explored));
+        assert_eq!(
+            items
+                .iter()
+                .map(|&(item_id, _)| item_id)
+                .collect::<Vec<_>>(),
+            model_top_k(10, &[ItemId(3)])
+        );
+    }
+
+    #[test]
+    fn full_exploration() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let exploited = model_top_k(10, &[]);
+
+        for exploration in vec![
+            Exploration::Uniform,
+            Exploration::Weighted((0..50).map(|x| x as f32).collect()),
+        ] {
+            let model = EpsilonGreedy::new(Random::new(7), 50, 1.0).exploration(exploration);
+
+            for _ in 0..20 {
+                let items = model
+                    .predict_top_k(&BaselineUser, 10, &[ItemId(0), ItemId(1)], &mut rng)
+                    .unwrap();
+
+                assert_eq!(items.len(), 10);
+                assert!(items.iter().all(|&(_, explored)| explored));
+                assert!(items
+                    .iter()
+                    .all(|(item_id, _)| !exploited.contains(item_id) && item_id.index() > 1));
+                assert_distinct(&items);
+            }
+        }
+    }
+
+    #[test]
+    fn never_duplicates() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+
+        // Only 3 items outside the top 40 can be explored.
+        let model = EpsilonGreedy::new(Random::new(7), 43, 0.5);
+        let mut num_explored = 0;
+
+        for _ in 0..50 {
+            let items = model
+                .predict_top_k(&BaselineUser, 40, &[], &mut rng)
+                .unwrap();
+
+            assert_eq!(items.len(), 40);
+            assert_distinct(&items);
+            num_explored += items.iter().filter(|&&(_, explored)| explored).count();
+        }
+
+        assert!(num_explored > 0);
+    }
+}
@@ -0,0 +1,573 @@
+//! Factored item similarity model (FISM).
+//!
+//! The score of a candidate item `c` for a user with history `H` is
+//! ```text
+//! score(c | H) = b_c + |H|^(-alpha) * sum_{h in H} p_h . q_c
+//! ```
+//! where `p` and `q` are separate "source" and "target" item embeddings,
+//! so that the item-item similarity `p_h . q_c` is a learned low-rank
+//! factorization. The user representation is the normalized sum of the
+//! source embeddings of the history, so prediction is a single dot
+//! product per item.
+//!
+//! In training, the candidate itself is excluded from the history it is
+//! scored against: otherwise, the model could rank items in the training
+//! data highly simply by making `p_i . q_i` large for every item.
+use std::sync::Arc;
+use std::time::Instant;
+
+use rand;
+use rand::distributions::{Distribution, Normal};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use serde::{Deserialize, Serialize};
+
+use wyrm;
+use wyrm::optim::{Optimizer as Optim, Optimizers};
+use wyrm::{Arr, BoxedNode, DataInput, Variable};
+
+use super::sequence_model::score_all_items;
+use super::{ImplicitUser, Loss, Optimizer};
+use crate::data::CompressedInteractions;
+use crate::ids::ItemId;
+use crate::rng::SeedSequence;
+use crate::{FittingError, OnlineRankingModel, PredictionError};
+
+fn embedding_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
+    let normal = Normal::new(0.0, 1.0 / cols as f64);
+    Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
+}
+
+/// The normalization `n^(-alpha)` of a history of `n` items.
+fn normalization(num_items: usize, alpha: f32) -> f32 {
+    if num_items == 0 {
+        0.0
+    } else {
+        (num_items as f32).powf(-alpha)
+    }
+}
+
+/// Hyperparameters describing the [Fism] model.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hyperparameters {
+    num_items: usize,
+    embedding_dim: usize,
+    alpha: f32,
+    history_samples: usize,
+    learning_rate: f32,
+    l2_penalty: f32,
+    loss: Loss,
+    optimizer: Optimizer,
+    rng: XorShiftRng,
+    num_epochs: usize,
+}
+
+impl Hyperparameters {
+    /// Build new hyperparameters.
+    pub fn new(num_items: usize) -> Self {
+        Hyperparameters {
+            num_items,
+            embedding_dim: 16,
+            alpha: 0.5,
+            history_samples: 32,
+            learning_rate: 0.01,
+            l2_penalty: 0.0,
+            loss: Loss::BPR,
+            optimizer: Optimizer::Adam,
+            rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
+            num_epochs: 10,
+        }
+    }
+
+    /// Set the learning rate.
+    pub fn learning_rate(mut self, learning_rate: f32) -> Self {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    /// Set the L2 penalty.
+    pub fn l2_penalty(mut self, l2_penalty: f32) -> Self {
+        self.l2_penalty = l2_penalty;
+        self
+    }
+
+    /// Set the embedding dimensionality.
+    pub fn embedding_dim(mut self, embedding_dim: usize) -> Self {
+        self.embedding_dim = embedding_dim;
+        self
+    }
+
+    /// Set the exponent of the history length normalization. With 0,
+    /// similarities are summed; with 1, they are averaged. Defaults to 0.5.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alpha` is not between 0 and 1.
+    pub fn alpha(mut self, alpha: f32) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&alpha),
+            "Alpha must be between 0 and 1."
+        );
+        self.alpha = alpha;
+        self
+    }
+
+    /// Set the number of history items used for each training example.
+    /// Longer histories are subsampled, and their sum rescaled to estimate
+    /// the sum over the whole history. Defaults to 32.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `history_samples` is zero.
+    pub fn history_samples(mut self, history_samples: usize) -> Self {
+        assert!(history_samples > 0, "History samples must be at least 1.");
+        self.history_samples = history_samples;
+        self
+    }
+
+    /// Set the number of epochs to run per each `fit` call.
+    pub fn num_epochs(mut self, num_epochs: usize) -> Self {
+        self.num_epochs = num_epochs;
+        self
+    }
+
+    /// Set the loss function. [Loss::WARP] is treated as [Loss::Hinge],
+    /// with a single uniformly sampled negative per positive.
+    pub fn loss(mut self, loss: Loss) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    /// Set the optimizer type.
+    pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    /// Set the random number generator.
+    pub fn rng(mut self, rng: XorShiftRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    /// Set the random number generator from seed.
+    pub fn from_seed(mut self, seed: [u8; 16]) -> Self {
+        self.rng = XorShiftRng::from_seed(seed);
+        self
+    }
+
+    /// Set the random number generator to the one named `"fism"` in
+    /// `seeds`, so that a single seed pins the whole experiment.
+    pub fn seed_sequence(mut self, seeds: &SeedSequence) -> Self {
+        self.rng = seeds.derive("fism");
+        self
+    }
+
+    /// Build a model out of the chosen hyperparameters.
+    pub fn build(mut self) -> Fism {
+        let source_embedding = Arc::new(wyrm::HogwildParameter::new(embedding_init(
+            self.num_items,
+            self.embedding_dim,
+            &mut self.rng,
+        )));
+        let target_embedding = Arc::new(wyrm::HogwildParameter::new(embedding_init(
+            self.num_items,
+            self.embedding_dim,
+            &mut self.rng,
+        )));
+        let item_biases = Arc::new(wyrm::HogwildParameter::new(Arr::zeros((self.num_items, 1))));
+
+        Fism {
+            hyper: self,
+            source_embedding,
+            target_embedding,
+            item_biases,
+        }
+    }
+
+    fn optimizer_for(&self) -> Optimizers {
+        match self.optimizer {
+            Optimizer::Adagrad => Optimizers::Adagrad(
+                wyrm::optim::Adagrad::new()
+                    .learning_rate(self.learning_rate)
+                    .l2_penalty(self.l2_penalty),
+            ),
+            Optimizer::Adam => Optimizers::Adam(
+                wyrm::optim::Adam::new()
+                    .learning_rate(self.learning_rate)
+                    .l2_penalty(self.l2_penalty),
+            ),
+        }
+    }
+}
+
+/// The computation graph scoring a positive and a negative item against
+/// a fixed number of (weighted) history slots.
+struct TrainingGraph {
+    history: Vec<Variable<wyrm::IndexInputNode>>,
+    history_weights: Vec<Variable<wyrm::InputNode>>,
+    positive_input: Variable<wyrm::IndexInputNode>,
+    negative_input: Variable<wyrm::IndexInputNode>,
+    positive: Variable<BoxedNode>,
+    loss: Variable<BoxedNode>,
+    alpha: f32,
+}
+
+impl TrainingGraph {
+    /// Set the inputs for scoring `positive` and `negative` against the
+    /// user's items, excluding `positive` from them.
+    fn set_example<R: Rng>(
+        &self,
+        user_items: &[ItemId],
+        positive: ItemId,
+        negative: ItemId,
+        rng: &mut R,
+    ) {
+        let mut history: Vec<_> = user_items
+            .iter()
+            .cloned()
+            .filter(|&item_id| item_id != positive)
+            .collect();
+        let num_history = history.len();
+
+        if num_history > self.history.len() {
+            rng.shuffle(&mut history);
+            history.truncate(self.history.len());
+        }
+
+        let weight = if history.is_empty() {
+            0.0
+        } else {
+            normalization(num_history, self.alpha) * num_history as f32 / history.len() as f32
+        };
+
+        for (idx, (input, input_weight)) in izip!(&self.history, &self.history_weights).enumerate()
+        {
+            let shape = input_weight.value().dim();
+
+            match history.get(idx) {
+                Some(&item_id) => {
+                    input.set_value(item_id.index());
+                    input_weight.set_value(&Arr::from_elem(shape, weight));
+                }
+                None => {
+                    input.set_value(0);
+                    input_weight.set_value(&Arr::zeros(shape));
+                }
+            }
+        }
+
+        self.positive_input.set_value(positive.index());
+        self.negative_input.set_value(negative.index());
+    }
+}
+
+/// Factored item similarity model.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Fism {
+    hyper: Hyperparameters,
+    source_embedding: Arc<wyrm::HogwildParameter>,
+    target_embedding: Arc<wyrm::HogwildParameter>,
+    item_biases: Arc<wyrm::HogwildParameter>,
+}
+
+impl Clone for Fism {
+    fn clone(&self) -> Self {
+        Fism {
+            hyper: self.hyper.clone(),
+            source_embedding: Arc::new(self.source_embedding.as_ref().clone()),
+            target_embedding: Arc::new(self.target_embedding.as_ref().clone()),
+            item_biases: Arc::new(self.item_biases.as_ref().clone()),
+        }
+    }
+}
+
+impl Fism {
+    fn training_graph(&self) -> TrainingGraph {
+        let sources = wyrm::ParameterNode::shared(self.source_embedding.clone());
+        let targets = wyrm::ParameterNode::shared(self.target_embedding.clone());
+        let biases = wyrm::ParameterNode::shared(self.item_biases.clone());
+
+        let history: Vec<_> = (0..self.hyper.history_samples)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let history_weights: Vec<_> = (0..self.hyper.history_samples)
+            .map(|_| wyrm::InputNode::new(Arr::zeros((1, self.hyper.embedding_dim))))
+            .collect();
+        let positive_input = wyrm::IndexInputNode::new(&[0; 1]);
+        let negative_input = wyrm::IndexInputNode::new(&[0; 1]);
+
+        let user_vector = izip!(&history, &history_weights)
+            .map(|(input, weight)| (weight.clone() * sources.index(input)).boxed())
+            .fold(None, |total: Option<Variable<BoxedNode>>, x| match total {
+                Some(total) => Some((total + x).boxed()),
+                None => Some(x),
+            })
+            .unwrap();
+
+        let positive = (user_vector.vector_dot(&targets.index(&positive_input))
+            + biases.index(&positive_input))
+        .boxed();
+        let negative =
+            user_vector.vector_dot(&targets.index(&negative_input)) + biases.index(&negative_input);
+
+        let loss = match self.hyper.loss {
+            Loss::BPR => (negative - positive.clone()).sigmoid().boxed(),
+            Loss::Hinge | Loss::WARP => (1.0 + negative - positive.clone()).relu().boxed(),
+        };
+
+        TrainingGraph {
+            history,
+            history_weights,
+            positive_input,
+            negative_input,
+            positive,
+            loss,
+            alpha: self.hyper.alpha,
+        }
+    }
+
+    /// Fit the model, returning the average loss of the last epoch.
+    pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<f32, FittingError> {
+        if interactions.num_items() != self.hyper.num_items {
+            return Err(FittingError::ItemCountMismatch {
+                model_items: self.hyper.num_items,
+                data_items: interactions.num_items(),
+            });
+        }
+
+        let user_items: Vec<&[ItemId]> = interactions
+            .iter_users()
+            .map(|user| user.item_ids)
+            .collect();
+
+        let mut pairs: Vec<(usize, ItemId)> = user_items
+            .iter()
+            .enumerate()
+            .flat_map(|(user_idx, item_ids)| {
+                item_ids.iter().map(move |&item_id| (user_idx, item_id))
+            })
+            .collect();
+
+        if pairs.is_empty() {
+            return Err(FittingError::NoInteractions);
+        }
+
+        let mut graph = self.training_graph();
+        let optimizer = self.hyper.optimizer_for();
+        let mut rng = self.hyper.rng.clone();
+        let mut loss_value = 0.0;
+
+        for epoch in 0..self.hyper.num_epochs {
+            let epoch_start = Instant::now();
+            rng.shuffle(&mut pairs);
+            loss_value = 0.0;
+
+            for &(user_idx, item_id) in &pairs {
+                let negative = ItemId::from_index(rng.gen_range(0, self.hyper.num_items));
+                graph.set_example(user_items[user_idx], item_id, negative, &mut rng);
+
+                graph.loss.forward();
+                graph.loss.backward(1.0);
+                loss_value += graph.loss.value().scalar_sum();
+
+                optimizer.step(&graph.loss.parameters());
+            }
+
+            let elapsed = epoch_start.elapsed();
+            log_info!(
+                "Epoch {}: loss {:.4}, {:.0} examples/s ({} in {:.2?})",
+                epoch,
+                loss_value / pairs.len() as f32,
+                pairs.len() as f64 / elapsed.as_secs_f64().max(1e-9),
+                pairs.len(),
+                elapsed
+            );
+        }
+
+        self.hyper.rng = rng;
+
+        Ok(loss_value / pairs.len() as f32)
+    }
+}
+
+impl OnlineRankingModel for Fism {
+    type UserRepresentation = ImplicitUser;
+
+    /// The normalized sum of the source embeddings of the items in the
+    /// history. Empty histories give a zero vector, so that items are
+    /// ranked by their biases alone.
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        let source_embedding = self.source_embedding.value();
+        let weight = normalization(item_ids.len(), self.hyper.alpha);
+        let mut user_embedding = vec![0.0; self.hyper.embedding_dim];
+
+        for &item_id in item_ids {
+            for (value, &factor) in user_embedding
+                .iter_mut()
+                .zip(source_embedding.row(item_id.index()))
+            {
+                *value += weight * factor;
+            }
+        }
+
+        Ok(ImplicitUser {
+            user_embedding,
+            num_interactions: item_ids.len(),
+            user_bias: None,
+        })
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        let target_embedding = self.target_embedding.value();
+        let item_biases = self.item_biases.value();
+
+        item_ids
+            .iter()
+            .map(|&item_id| {
+                let score = wyrm::simd_dot(
+                    &user.user_embedding,
+                    target_embedding.row(item_id.index()).as_slice().unwrap(),
+                ) + item_biases[(item_id.index(), 0)];
+
+                if score.is_finite() {
+                    Ok(score)
+                } else {
+                    Err(PredictionError::InvalidPredictionValue)
+                }
+            })
+            .collect()
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        let scores = score_all_items(
+            self.target_embedding.value(),
+            self.item_biases.value(),
+            &user.user_embedding,
+            num_items,
+        );
+
+        if scores.iter().all(|score| score.is_finite()) {
+            Ok(scores)
+        } else {
+            Err(PredictionError::InvalidPredictionValue)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, Timestamp, UserId};
+    use crate::models::baselines::Random;
+
+    /// Two blocks of users, each interacting only with its own block of items.
+    fn block_data() -> CompressedInteractions {
+        let interactions: Vec<_> = (0..100)
+            .flat_map(|user_id| {
+                let block = user_id / 50;
+                (0..5).map(move |t| {
+                    Interaction::new(
+                        UserId(user_id),
+                        ItemId(block * 10 + (user_id * 3 + t * 7) % 10),
+                        Timestamp(t as i64),
+                    )
+                })
+            })
+            .collect();
+
+        Interactions::from(interactions).to_compressed()
+    }
+
+    #[test]
+    fn converges() {
+        let data = block_data();
+
+        let mut model = Hyperparameters::new(data.num_items())
+            .embedding_dim(8)
+            .learning_rate(0.05)
+            .num_epochs(20)
+            .from_seed([42; 16])
+            .build();
+        let untrained_mrr = mrr_score(&model, &data).unwrap();
+        let loss = model.fit(&data).unwrap();
+        assert!(loss.is_finite());
+
+        let mrr = mrr_score(&model, &data).unwrap();
+        let random_mrr = mrr_score(&Random::new(42), &data).unwrap();
+        println!(
+            "FISM MRR {} (untrained {}, random {})",
+            mrr, untrained_mrr, random_mrr
+        );
+        assert!(mrr > untrained_mrr);
+        assert!(mrr > 2.0 * random_mrr);
+    }
+
+    #[test]
+    fn serialization() {
+        let data = block_data();
+        let mut model = Hyperparameters::new(data.num_items())
+            .num_epochs(2)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+
+        let serialized = serde_json::to_string(&model).unwrap();
+        let deserialized: Fism = serde_json::from_str(&serialized).unwrap();
+
+        let user = model.user_representation(&items(&[0, 1, 2])).unwrap();
+        let deserialized_user = deserialized
+            .user_representation(&items(&[0, 1, 2]))
+            .unwrap();
+        assert_eq!(
+            model.predict(&user, &items(&[0, 5, 15])).unwrap(),
+            deserialized
+                .predict(&deserialized_user, &items(&[0, 5, 15]))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn candidate_excluded_from_history() {
+        let model = Hyperparameters::new(4)
+            .embedding_dim(2)
+            .alpha(1.0)
+            .history_samples(4)
+            .from_seed([42; 16])
+            .build();
+        let graph = model.training_graph();
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+
+        // Item 1 appears twice in the history, and neither occurrence
+        // contributes to its own score: the history is {0, 2}.
+        graph.set_example(&items(&[0, 1, 2, 1]), ItemId(1), ItemId(3), &mut rng);
+        graph.positive.forward();
+        let score = graph.positive.value().scalar_sum();
+
+        let sources = model.source_embedding.value();
+        let targets = model.target_embedding.value();
+        let expected: f32 = (0..2)
+            .map(|dim| (sources[(0, dim)] + sources[(2, dim)]) / 2.0 * targets[(1, dim)])
+            .sum();
+
+        assert!((score - expected).abs() < 1e-6);
+
+        // At prediction time, the whole history is used.
+        let user = model.user_representation(&items(&[0, 2])).unwrap();
+        let prediction = model.predict(&user, &items(&[1])).unwrap()[0];
+        assert!((prediction - expected).abs() < 1e-6);
+    }
+}
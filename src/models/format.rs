@@ -0,0 +1,583 @@
+//! Versioned binary model format.
+//!
+//! A model file starts with a header: the magic bytes `SBRM`, the format
+//! version as a little-endian `u32`, and the model kind. It is followed by
+//! a count of sections and the sections themselves, each a name and a
+//! length-prefixed payload:
+//!
+//! - `metadata`: the [ModelMetadata] of the model, as JSON. Added in
+//!   version 2; models read from version 1 files have no metadata.
+//! - `hyperparameters`: the hyperparameters, as JSON.
+//! - `tensors`: the remaining fields of the model as JSON, with the data
+//!   of every parameter matrix replaced by a reference to one of the raw
+//!   little-endian `f32` buffers that follow it.
+//!
+//! Strings are prefixed by their length as a `u32`, and payloads and
+//! buffers by their length as a `u64`. Readers skip sections they do not
+//! know, and fill in defaults for optional sections that are missing, so
+//! that files written by older versions of the crate keep loading.
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::metadata::ModelMetadata;
+
+/// Magic bytes at the start of every model file.
+pub const MAGIC: &[u8; 4] = b"SBRM";
+
+/// Version of the format written by this version of the crate.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Versions of the format this version of the crate can read.
+pub const SUPPORTED_VERSIONS: &[u32] = &[1, 2];
+
+const METADATA: &str = "metadata";
+const HYPERPARAMETERS: &str = "hyperparameters";
+const TENSORS: &str = "tensors";
+
+/// Key replacing the `data` key of serialized arrays, holding the index of
+/// their buffer in the `tensors` section.
+const TENSOR_KEY: &str = "tensor";
+
+/// Errors when loading a model file.
+#[derive(Debug)]
+pub enum ModelLoadError {
+    /// The file cannot be read.
+    Io(io::Error),
+    /// A section does not hold valid JSON, or does not describe a model.
+    Json(serde_json::Error),
+    /// The file is not a model file, or is truncated.
+    Malformed(String),
+    /// The file was written in a format version this version of the crate
+    /// cannot read.
+    UnsupportedVersion {
+        /// The version of the file.
+        found: u32,
+        /// The versions that can be read.
+        supported: &'static [u32],
+    },
+    /// The file holds a different kind of model.
+    WrongModel {
+        /// The model being loaded.
+        expected: &'static str,
+        /// The model in the file.
+        found: String,
+    },
+}
+
+impl fmt::Display for ModelLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelLoadError::Io(err) => write!(f, "I/O error: {}", err),
+            ModelLoadError::Json(err) => write!(f, "JSON error: {}", err),
+            ModelLoadError::Malformed(reason) => write!(f, "Malformed model file: {}", reason),
+            ModelLoadError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "Unsupported format version {}: supported versions are {:?}.",
+                found, supported
+            ),
+            ModelLoadError::WrongModel { expected, found } => write!(
+                f,
+                "Expected a {} model, but the file holds a {} model.",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl error::Error for ModelLoadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ModelLoadError::Io(err) => Some(err),
+            ModelLoadError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ModelLoadError {
+    fn from(err: io::Error) -> Self {
+        ModelLoadError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ModelLoadError {
+    fn from(err: serde_json::Error) -> Self {
+        ModelLoadError::Json(err)
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn error::Error + Send + Sync>>>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn write_bytes<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn write_str<W: Write>(writer: &mut W, string: &str) -> io::Result<()> {
+    writer.write_all(&(string.len() as u32).to_le_bytes())?;
+    writer.write_all(string.as_bytes())
+}
+
+/// A cursor over the bytes of a file, failing with
+/// [ModelLoadError::Malformed] on reads past the end.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ModelLoadError> {
+        if len > self.bytes.len() {
+            return Err(ModelLoadError::Malformed(
+                "Unexpected end of file.".to_owned(),
+            ));
+        }
+
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, ModelLoadError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, ModelLoadError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], ModelLoadError> {
+        let len = self.u64()?;
+        if len > self.bytes.len() as u64 {
+            return Err(ModelLoadError::Malformed(
+                "Unexpected end of file.".to_owned(),
+            ));
+        }
+
+        self.take(len as usize)
+    }
+
+    fn str(&mut self) -> Result<&'a str, ModelLoadError> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?)
+            .map_err(|_| ModelLoadError::Malformed("Invalid UTF-8 string.".to_owned()))
+    }
+}
+
+/// Move the data of each serialized `f32` array in `value` into `buffers`,
+/// leaving the index of its buffer in its place.
+fn take_tensors(value: &mut Value, buffers: &mut Vec<Vec<u8>>) {
+    match value {
+        Value::Object(map) => {
+            let is_array = map.len() == 3 && map.contains_key("v") && map.contains_key("dim");
+
+            let buffer = match map.get("data") {
+                Some(Value::Array(data)) if is_array => data
+                    .iter()
+                    .map(|x| match x.as_f64() {
+                        Some(x) if f64::from(x as f32) == x => Some((x as f32).to_le_bytes()),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .map(|bytes| bytes.concat()),
+                _ => None,
+            };
+
+            match buffer {
+                Some(buffer) => {
+                    map.remove("data");
+                    map.insert(TENSOR_KEY.to_owned(), json!(buffers.len()));
+                    buffers.push(buffer);
+                }
+                None => map
+                    .values_mut()
+                    .for_each(|value| take_tensors(value, buffers)),
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| take_tensors(value, buffers)),
+        _ => {}
+    }
+}
+
+/// Undo [take_tensors].
+fn put_tensors(value: &mut Value, buffers: &[&[u8]]) -> Result<(), ModelLoadError> {
+    match value {
+        Value::Object(map) => match map.remove(TENSOR_KEY) {
+            Some(index) => {
+                let buffer = index
+                    .as_u64()
+                    .and_then(|index| buffers.get(index as usize))
+                    .ok_or_else(|| {
+                        ModelLoadError::Malformed(format!("Invalid tensor {}.", index))
+                    })?;
+
+                if buffer.len() % 4 != 0 {
+                    return Err(ModelLoadError::Malformed(format!(
+                        "Tensor of {} bytes.",
+                        buffer.len()
+                    )));
+                }
+
+                let data = buffer
+                    .chunks(4)
+                    .map(|x| json!(f32::from_le_bytes([x[0], x[1], x[2], x[3]])))
+                    .collect();
+                map.insert("data".to_owned(), Value::Array(data));
+
+                Ok(())
+            }
+            None => map
+                .values_mut()
+                .try_for_each(|value| put_tensors(value, buffers)),
+        },
+        Value::Array(values) => values
+            .iter_mut()
+            .try_for_each(|value| put_tensors(value, buffers)),
+        _ => Ok(()),
+    }
+}
+
+/// Remove `params.{key}` from a serialized model.
+fn take_param(model: &mut Value, key: &str) -> Value {
+    model
+        .get_mut("params")
+        .and_then(Value::as_object_mut)
+        .and_then(|params| params.remove(key))
+        .unwrap_or(Value::Null)
+}
+
+/// Write the header of a file of `version`, holding a model of `kind`,
+/// and the given sections.
+fn write_sections<W: Write>(
+    mut writer: W,
+    version: u32,
+    kind: &str,
+    sections: &[(&str, Vec<u8>)],
+) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&version.to_le_bytes())?;
+    write_str(&mut writer, kind)?;
+    writer.write_all(&(sections.len() as u32).to_le_bytes())?;
+
+    for (name, payload) in sections {
+        write_str(&mut writer, name)?;
+        write_bytes(&mut writer, payload)?;
+    }
+
+    writer.flush()
+}
+
+/// Split a serialized model into its `hyperparameters` and `tensors`
+/// sections, returning them along with its metadata.
+fn split_model<T: Serialize>(model: &T) -> io::Result<(Value, Vec<u8>, Vec<u8>)> {
+    let mut model = serde_json::to_value(model).map_err(invalid_data)?;
+    let metadata = take_param(&mut model, METADATA);
+    let hyperparameters = serde_json::to_vec(&take_param(&mut model, "hyper"))?;
+
+    let mut buffers = Vec::new();
+    take_tensors(&mut model, &mut buffers);
+
+    let mut tensors = Vec::new();
+    write_bytes(&mut tensors, &serde_json::to_vec(&model)?)?;
+    tensors.extend_from_slice(&(buffers.len() as u32).to_le_bytes());
+    for buffer in &buffers {
+        write_bytes(&mut tensors, buffer)?;
+    }
+
+    Ok((metadata, hyperparameters, tensors))
+}
+
+/// Write `model`, of kind `kind`, in the current format version.
+pub(crate) fn write_model<T: Serialize, W: Write>(
+    model: &T,
+    kind: &str,
+    writer: W,
+) -> io::Result<()> {
+    let (metadata, hyperparameters, tensors) = split_model(model)?;
+
+    write_sections(
+        writer,
+        FORMAT_VERSION,
+        kind,
+        &[
+            (METADATA, serde_json::to_vec(&metadata)?),
+            (HYPERPARAMETERS, hyperparameters),
+            (TENSORS, tensors),
+        ],
+    )
+}
+
+/// The header and sections of a model file.
+struct Sections<'a> {
+    kind: &'a str,
+    sections: Vec<(&'a str, &'a [u8])>,
+}
+
+impl<'a> Sections<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self, ModelLoadError> {
+        let mut cursor = Cursor { bytes };
+
+        if cursor.take(4).ok() != Some(&MAGIC[..]) {
+            return Err(ModelLoadError::Malformed("Not a model file.".to_owned()));
+        }
+
+        let version = cursor.u32()?;
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(ModelLoadError::UnsupportedVersion {
+                found: version,
+                supported: SUPPORTED_VERSIONS,
+            });
+        }
+
+        let kind = cursor.str()?;
+        let mut sections = Vec::new();
+        for _ in 0..cursor.u32()? {
+            sections.push((cursor.str()?, cursor.bytes()?));
+        }
+
+        Ok(Sections { kind, sections })
+    }
+
+    fn get(&self, name: &str) -> Option<&'a [u8]> {
+        self.sections
+            .iter()
+            .find(|(section, _)| *section == name)
+            .map(|&(_, payload)| payload)
+    }
+
+    fn require(&self, name: &str) -> Result<&'a [u8], ModelLoadError> {
+        self.get(name)
+            .ok_or_else(|| ModelLoadError::Malformed(format!("Missing {} section.", name)))
+    }
+
+    fn metadata(&self) -> Result<Value, ModelLoadError> {
+        // Version 1 files have no metadata section.
+        match self.get(METADATA) {
+            Some(payload) => Ok(serde_json::from_slice(payload)?),
+            None => Ok(Value::Null),
+        }
+    }
+}
+
+fn read_all<R: Read>(mut reader: R) -> Result<Vec<u8>, ModelLoadError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+/// Read a model of kind `kind` from a file written by [write_model], or by
+/// an older version of the crate.
+pub(crate) fn read_model<T: DeserializeOwned, R: Read>(
+    reader: R,
+    kind: &'static str,
+) -> Result<T, ModelLoadError> {
+    let bytes = read_all(reader)?;
+    let sections = Sections::parse(&bytes)?;
+
+    if sections.kind != kind {
+        return Err(ModelLoadError::WrongModel {
+            expected: kind,
+            found: sections.kind.to_owned(),
+        });
+    }
+
+    let mut cursor = Cursor {
+        bytes: sections.require(TENSORS)?,
+    };
+    let mut model: Value = serde_json::from_slice(cursor.bytes()?)?;
+    let mut buffers = Vec::new();
+    for _ in 0..cursor.u32()? {
+        buffers.push(cursor.bytes()?);
+    }
+    put_tensors(&mut model, &buffers)?;
+
+    let params = model
+        .get_mut("params")
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| ModelLoadError::Malformed("Missing params.".to_owned()))?;
+    params.insert(
+        "hyper".to_owned(),
+        serde_json::from_slice(sections.require(HYPERPARAMETERS)?)?,
+    );
+    params.insert(METADATA.to_owned(), sections.metadata()?);
+
+    Ok(serde_json::from_value(model)?)
+}
+
+/// Read the [ModelMetadata] of a model file of any kind, without decoding
+/// its parameters. Returns `None` if the model was saved before it was
+/// fitted, or by a version of the crate that did not record metadata.
+pub fn read_metadata<R: Read>(reader: R) -> Result<Option<ModelMetadata>, ModelLoadError> {
+    let bytes = read_all(reader)?;
+
+    Ok(serde_json::from_value(
+        Sections::parse(&bytes)?.metadata()?,
+    )?)
+}
+
+/// Read the model kind of a model file, such as `"ewma"` or `"lstm"`.
+pub fn read_kind<R: Read>(reader: R) -> Result<String, ModelLoadError> {
+    let bytes = read_all(reader)?;
+
+    Ok(Sections::parse(&bytes)?.kind.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::ids::items;
+    use crate::models::ewma::{Hyperparameters, ImplicitEWMAModel};
+    use crate::OnlineRankingModel;
+
+    /// The version 1 writer, frozen: it must keep writing the files that
+    /// version 1 of the crate wrote, without a metadata section.
+    fn write_v1<T: Serialize, W: Write>(model: &T, kind: &str, writer: W) -> io::Result<()> {
+        let (_, hyperparameters, tensors) = split_model(model)?;
+
+        write_sections(
+            writer,
+            1,
+            kind,
+            &[(HYPERPARAMETERS, hyperparameters), (TENSORS, tensors)],
+        )
+    }
+
+    /// A fitted model, made without the `training` feature by marking an
+    /// unfitted model as fitted, as in the JSON tests.
+    fn fitted_model() -> ImplicitEWMAModel {
+        let json = Hyperparameters::new(10, 5)
+            .from_seed([42; 16])
+            .build()
+            .to_json()
+            .unwrap();
+        let mut document: Value = serde_json::from_str(&json).unwrap();
+        document["model"]["params"]["num_users"] = json!(3);
+
+        ImplicitEWMAModel::from_json(&document.to_string()).unwrap()
+    }
+
+    fn predictions(model: &ImplicitEWMAModel) -> Vec<f32> {
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        model.predict_all_items(&user, 10).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let model = fitted_model();
+
+        let mut bytes = Vec::new();
+        model.write(&mut bytes).unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(read_kind(&bytes[..]).unwrap(), "ewma");
+        assert_eq!(read_metadata(&bytes[..]).unwrap(), None);
+
+        let read = ImplicitEWMAModel::read(&bytes[..]).unwrap();
+        assert_eq!(predictions(&read), predictions(&model));
+        assert_eq!(read.to_json().unwrap(), model.to_json().unwrap());
+    }
+
+    #[test]
+    fn reads_version_1_fixture() {
+        // The fixture was written by the version of the crate that
+        // introduced the format, and must never be regenerated: later
+        // versions have to keep reading it as it is.
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("ewma_v1.sbrm");
+        let bytes = fs::read(&path)
+            .unwrap_or_else(|err| panic!("Cannot read fixture {}: {}", path.display(), err));
+        assert_eq!(
+            u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            1
+        );
+        assert_eq!(read_metadata(&bytes[..]).unwrap(), None);
+
+        let model = ImplicitEWMAModel::read(&bytes[..]).unwrap();
+        assert!(model.is_fitted());
+        assert!(model.metadata().is_none());
+        assert_eq!(model.num_items(), 10);
+
+        // Scores of the fixture's model for the history [1, 2, 3], with the
+        // initial decay of 0.5: the user is 0.25 * (i_1 + i_2) + 0.5 * i_3.
+        let expected = [
+            0.0174829,
+            0.0264099,
+            0.0365271,
+            0.0646925,
+            0.00393034,
+            -0.0103729,
+            -0.0256755,
+            -0.00970995,
+            0.0018272,
+            0.0244155,
+        ];
+        for (score, expected) in predictions(&model).iter().zip(&expected) {
+            assert!((score - expected).abs() < 1e-6, "{} != {}", score, expected);
+        }
+
+        // Saving upgrades the file to the current version.
+        let mut upgraded = Vec::new();
+        model.write(&mut upgraded).unwrap();
+        let reread = ImplicitEWMAModel::read(&upgraded[..]).unwrap();
+        assert_eq!(predictions(&reread), predictions(&model));
+    }
+
+    #[test]
+    fn reads_version_1_writer() {
+        let model = fitted_model();
+
+        let mut bytes = Vec::new();
+        write_v1(&model, "ewma", &mut bytes).unwrap();
+
+        let read = ImplicitEWMAModel::read(&bytes[..]).unwrap();
+        assert!(read.metadata().is_none());
+        assert_eq!(predictions(&read), predictions(&model));
+    }
+
+    #[test]
+    fn errors() {
+        let mut bytes = Vec::new();
+        fitted_model().write(&mut bytes).unwrap();
+
+        let mut future = bytes.clone();
+        future[4..8].copy_from_slice(&7u32.to_le_bytes());
+        match ImplicitEWMAModel::read(&future[..]) {
+            Err(ModelLoadError::UnsupportedVersion {
+                found: 7,
+                supported,
+            }) => {
+                assert_eq!(supported, SUPPORTED_VERSIONS)
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        match crate::models::lstm::ImplicitLSTMModel::read(&bytes[..]) {
+            Err(ModelLoadError::WrongModel { found, .. }) => assert_eq!(found, "ewma"),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        for len in &[0, 3, 10, bytes.len() - 1] {
+            match ImplicitEWMAModel::read(&bytes[..*len]) {
+                Err(ModelLoadError::Malformed(_)) => {}
+                other => panic!("Unexpected result for {} bytes: {:?}", len, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Frecency (frequency and recency) heuristic scorer.
+//!
+//! Items are scored by how often and how recently the user interacted
+//! with them: each past occurrence of an item contributes
+//! `1 / (1 + alpha * (current_time - occurrence_time))`, where the current
+//! time is the timestamp of the most recent interaction. Items the user
+//! has never interacted with score 0. Despite needing no training, this
+//! is a strong baseline on datasets with many repeat interactions.
+use serde::{Deserialize, Serialize};
+
+use crate::data::CompressedInteractionsUser;
+use crate::ids::{ItemId, Timestamp, UserId};
+use crate::{OnlineRankingModel, PredictionError};
+
+/// User representation of the [FrecencyScorer]: the raw history.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrecencyUser {
+    /// The user, if known.
+    pub user_id: Option<UserId>,
+    /// Past items, from earliest to latest.
+    pub item_ids: Vec<ItemId>,
+    /// Timestamps of the past items.
+    pub timestamps: Vec<Timestamp>,
+}
+
+/// Scores items by the frecency of the user's past interactions with them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FrecencyScorer {
+    alpha: f32,
+}
+
+impl FrecencyScorer {
+    /// Create a scorer with decay rate `alpha`: an occurrence `dt` time
+    /// units before the current time has weight `1 / (1 + alpha * dt)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alpha` is negative.
+    pub fn new(alpha: f32) -> Self {
+        assert!(alpha >= 0.0, "Decay rate must be non-negative.");
+        FrecencyScorer { alpha }
+    }
+
+    /// Create a scorer whose occurrence weights halve `half_life` time
+    /// units after the occurrence, that is with `alpha = 1 / half_life`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `half_life` is not positive.
+    pub fn with_half_life(half_life: f32) -> Self {
+        assert!(half_life > 0.0, "Half-life must be positive.");
+        FrecencyScorer::new(1.0 / half_life)
+    }
+
+    /// Compute a user representation from past interactions and their
+    /// timestamps, which must be aligned with `item_ids`.
+    pub fn user_representation_with_timestamps(
+        &self,
+        item_ids: &[ItemId],
+        timestamps: &[Timestamp],
+    ) -> FrecencyUser {
+        assert_eq!(
+            item_ids.len(),
+            timestamps.len(),
+            "Every item must have a timestamp."
+        );
+
+        FrecencyUser {
+            user_id: None,
+            item_ids: item_ids.to_owned(),
+            timestamps: timestamps.to_owned(),
+        }
+    }
+
+    /// Compute the representation of a user in compressed interactions.
+    pub fn user_representation_for(&self, user: &CompressedInteractionsUser) -> FrecencyUser {
+        FrecencyUser {
+            user_id: Some(user.user_id),
+            item_ids: user.item_ids.to_owned(),
+            timestamps: user.timestamps.to_owned(),
+        }
+    }
+}
+
+impl OnlineRankingModel for FrecencyScorer {
+    type UserRepresentation = FrecencyUser;
+
+    /// Without timestamps, interactions are taken to be one time unit apart.
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        let timestamps: Vec<Timestamp> = (0..item_ids.len() as i64).map(Timestamp).collect();
+        Ok(self.user_representation_with_timestamps(item_ids, &timestamps))
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        let current_time = user.timestamps.iter().cloned().max().unwrap_or_default();
+
+        Ok(item_ids
+            .iter()
+            .map(|&item_id| {
+                izip!(&user.item_ids, &user.timestamps)
+                    .filter(|&(&past_item, _)| past_item == item_id)
+                    .map(|(_, &timestamp)| {
+                        1.0 / (1.0 + self.alpha * (current_time - timestamp) as f32)
+                    })
+                    .sum()
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::ids::{items, times};
+
+    #[test]
+    fn frecency_scores() {
+        let scorer = FrecencyScorer::new(1.0);
+        let user = scorer
+            .user_representation_with_timestamps(&items(&[1, 2, 1, 3]), &times(&[0, 5, 8, 10]));
+
+        // Item 1 occurred 10 and 2 time units ago, item 2 5 ago, item 3 now.
+        let scores = scorer.predict(&user, &items(&[1, 2, 3, 4])).unwrap();
+        assert_eq!(scores, vec![1.0 / 11.0 + 1.0 / 3.0, 1.0 / 6.0, 1.0, 0.0]);
+
+        // Doubling the half-life halves the decay rate.
+        let scorer = FrecencyScorer::with_half_life(2.0);
+        let scores = scorer.predict(&user, &items(&[2])).unwrap();
+        assert_eq!(scores, vec![1.0 / 3.5]);
+
+        // Without timestamps, positions are used.
+        let user = scorer.user_representation(&items(&[4, 4])).unwrap();
+        assert_eq!(user.timestamps, times(&[0, 1]));
+
+        let interactions = Interactions::from(vec![
+            Interaction::new(UserId(3), ItemId(7), Timestamp(100)),
+            Interaction::new(UserId(3), ItemId(7), Timestamp(102)),
+        ])
+        .to_compressed();
+        let user = scorer.user_representation_for(&interactions.get_user(UserId(3)).unwrap());
+        assert_eq!(user.user_id, Some(UserId(3)));
+        assert_eq!(
+            scorer.predict(&user, &items(&[7])).unwrap(),
+            vec![1.0 / 2.0 + 1.0]
+        );
+
+        // Empty histories score everything 0.
+        let user = scorer.user_representation(&items(&[])).unwrap();
+        assert_eq!(
+            scorer.predict(&user, &items(&[1, 2])).unwrap(),
+            vec![0.0, 0.0]
+        );
+    }
+}
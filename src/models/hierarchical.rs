@@ -0,0 +1,789 @@
+//! Hierarchical model combining long-term taste with short-term session intent.
+//!
+//! The user representation combines two exponentially-weighted averages of
+//! past item embeddings:
+//!
+//! - a long-term average over the whole history, with a small learned decay
+//!   (at most 0.1), capturing persistent taste, and
+//! - a short-term average over the current session, with a larger learned decay,
+//!   capturing the intent of the session. It restarts whenever two consecutive
+//!   interactions are more than [Hyperparameters::session_gap] apart.
+//!
+//! The two are combined by a learned, per-dimension gate:
+//! ```text
+//! g = sigmoid(W_l * long + W_s * short + b)
+//! u = g * long + (1 - g) * short
+//! ```
+//!
+//! Session boundaries are only visible when the model is given timestamps:
+//! use [ImplicitHierarchicalModel::user_representation_with_timestamps] to
+//! compute representations for prediction.
+use std::sync::Arc;
+
+use rand;
+use rand::distributions::{Distribution, Normal};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use serde::{Deserialize, Serialize};
+
+use wyrm;
+use wyrm::optim::Optimizers;
+use wyrm::{Arr, BoxedNode, Variable};
+
+use super::metadata::ModelMetadata;
+use super::sampling::MinibatchSampler;
+#[cfg(feature = "training")]
+use super::sequence_model::fit_sequence_model;
+use super::sequence_model::{
+    score_all_items, sequence_representation, SequenceModel, SequenceModelParameters,
+};
+use super::{ImplicitUser, Loss, Optimizer, Parallelism};
+use crate::config;
+#[cfg(feature = "training")]
+use crate::data::CompressedInteractions;
+use crate::ids::{ItemId, Timestamp};
+use crate::rng::SeedSequence;
+#[cfg(feature = "training")]
+use crate::FittingError;
+use crate::{OnlineRankingModel, PredictionError};
+
+/// The largest decay of the long-term average.
+const MAX_LONG_TERM_DECAY: f32 = 0.1;
+
+/// How sharply the session indicator switches from 0 to 1 around the gap,
+/// in units of the gap.
+const SESSION_BOUNDARY_SHARPNESS: f32 = 50.0;
+
+fn embedding_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
+    let normal = Normal::new(0.0, 1.0 / cols as f64);
+    Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
+}
+
+fn dense_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
+    let normal = Normal::new(0.0, (2.0 / (rows + cols) as f64).sqrt());
+    Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
+}
+
+/// Which parts of the user representation to use. Models with a single
+/// component are mostly useful as ablations of the full model.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Components {
+    /// Only the long-term average.
+    LongTerm,
+    /// Only the short-term, per-session average.
+    ShortTerm,
+    /// Both, combined by the learned gate.
+    Both,
+}
+
+/// Hyperparameters for the [ImplicitHierarchicalModel].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hyperparameters {
+    num_items: usize,
+    max_sequence_length: usize,
+    item_embedding_dim: usize,
+    session_gap: u64,
+    components: Components,
+    learning_rate: f32,
+    l2_penalty: f32,
+    loss: Loss,
+    optimizer: Optimizer,
+    parallelism: Parallelism,
+    rng: XorShiftRng,
+    num_threads: usize,
+    num_epochs: usize,
+    #[serde(default)]
+    accumulate_f64: bool,
+}
+
+impl Hyperparameters {
+    /// Build new hyperparameters.
+    pub fn new(num_items: usize, max_sequence_length: usize) -> Self {
+        Hyperparameters {
+            num_items,
+            max_sequence_length,
+            item_embedding_dim: 16,
+            session_gap: 1800,
+            components: Components::Both,
+            learning_rate: 0.01,
+            l2_penalty: 0.0,
+            loss: Loss::BPR,
+            optimizer: Optimizer::Adam,
+            parallelism: Parallelism::Synchronous,
+            rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
+            num_threads: config::num_threads(),
+            num_epochs: 10,
+            accumulate_f64: false,
+        }
+    }
+
+    /// Set the learning rate.
+    pub fn learning_rate(mut self, learning_rate: f32) -> Self {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    /// Set the l2 penalty.
+    pub fn l2_penalty(mut self, l2_penalty: f32) -> Self {
+        self.l2_penalty = l2_penalty;
+        self
+    }
+
+    /// Set the embedding dimensionality.
+    pub fn embedding_dim(mut self, embedding_dim: usize) -> Self {
+        self.item_embedding_dim = embedding_dim;
+        self
+    }
+
+    /// Set the largest gap between two interactions of the same session.
+    /// A longer gap starts a new session. Defaults to 1800, half an hour
+    /// if timestamps are in seconds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `session_gap` is zero.
+    pub fn session_gap(mut self, session_gap: u64) -> Self {
+        assert!(session_gap > 0, "Session gap must be positive.");
+        self.session_gap = session_gap;
+        self
+    }
+
+    /// Set which components make up the user representation.
+    /// Defaults to [Components::Both].
+    pub fn components(mut self, components: Components) -> Self {
+        self.components = components;
+        self
+    }
+
+    /// Set the number of epochs to run per each `fit` call.
+    pub fn num_epochs(mut self, num_epochs: usize) -> Self {
+        self.num_epochs = num_epochs;
+        self
+    }
+
+    /// Set the loss function.
+    pub fn loss(mut self, loss: Loss) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    /// Set number of threads to be used.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// Accumulate training losses in `f64` rather than `f32`. Defaults to
+    /// false.
+    ///
+    /// Summed in `f32`, many small losses lose precision, so that the
+    /// reported loss depends on summation order. Parameters stay `f32`
+    /// either way.
+    pub fn accumulate_f64(mut self, enabled: bool) -> Self {
+        self.accumulate_f64 = enabled;
+        self
+    }
+
+    /// Set the type of paralellism.
+    pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Set the random number generator.
+    pub fn rng(mut self, rng: XorShiftRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    /// Set the random number generator from seed.
+    pub fn from_seed(mut self, seed: [u8; 16]) -> Self {
+        self.rng = XorShiftRng::from_seed(seed);
+        self
+    }
+
+    /// Set the random number generator to the one named `"hierarchical"` in
+    /// `seeds`, so that a single seed pins the whole experiment.
+    pub fn seed_sequence(mut self, seeds: &SeedSequence) -> Self {
+        self.rng = seeds.derive("hierarchical");
+        self
+    }
+
+    /// Set the optimizer type.
+    pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    fn build_params(mut self) -> Parameters {
+        let dim = self.item_embedding_dim;
+
+        let item_embedding = Arc::new(wyrm::HogwildParameter::new(embedding_init(
+            self.num_items,
+            dim,
+            &mut self.rng,
+        )));
+        let item_biases = Arc::new(wyrm::HogwildParameter::new(Arr::zeros((self.num_items, 1))));
+
+        // Start with a long-term decay of 0.02 and a short-term decay of 0.5.
+        let long_term_alpha = Arc::new(wyrm::HogwildParameter::new(Arr::from_elem(
+            (1, dim),
+            4.0_f32.ln(),
+        )));
+        let short_term_alpha = Arc::new(wyrm::HogwildParameter::new(Arr::zeros((1, dim))));
+
+        let gate_long_term = Arc::new(wyrm::HogwildParameter::new(dense_init(
+            dim,
+            dim,
+            &mut self.rng,
+        )));
+        let gate_short_term = Arc::new(wyrm::HogwildParameter::new(dense_init(
+            dim,
+            dim,
+            &mut self.rng,
+        )));
+        let gate_bias = Arc::new(wyrm::HogwildParameter::new(Arr::zeros((1, dim))));
+
+        Parameters {
+            hyper: self,
+            num_users: None,
+            metadata: None,
+            item_embedding,
+            item_biases,
+            long_term_alpha,
+            short_term_alpha,
+            gate_long_term,
+            gate_short_term,
+            gate_bias,
+        }
+    }
+
+    /// Build a model out of the chosen hyperparameters.
+    pub fn build(self) -> ImplicitHierarchicalModel {
+        ImplicitHierarchicalModel {
+            params: self.build_params(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Parameters {
+    hyper: Hyperparameters,
+    num_users: Option<usize>,
+    #[serde(default)]
+    metadata: Option<ModelMetadata>,
+    item_embedding: Arc<wyrm::HogwildParameter>,
+    item_biases: Arc<wyrm::HogwildParameter>,
+    long_term_alpha: Arc<wyrm::HogwildParameter>,
+    short_term_alpha: Arc<wyrm::HogwildParameter>,
+    gate_long_term: Arc<wyrm::HogwildParameter>,
+    gate_short_term: Arc<wyrm::HogwildParameter>,
+    gate_bias: Arc<wyrm::HogwildParameter>,
+}
+
+impl Clone for Parameters {
+    fn clone(&self) -> Self {
+        Parameters {
+            hyper: self.hyper.clone(),
+            num_users: self.num_users,
+            metadata: self.metadata.clone(),
+            item_embedding: Arc::new(self.item_embedding.as_ref().clone()),
+            item_biases: Arc::new(self.item_biases.as_ref().clone()),
+            long_term_alpha: Arc::new(self.long_term_alpha.as_ref().clone()),
+            short_term_alpha: Arc::new(self.short_term_alpha.as_ref().clone()),
+            gate_long_term: Arc::new(self.gate_long_term.as_ref().clone()),
+            gate_short_term: Arc::new(self.gate_short_term.as_ref().clone()),
+            gate_bias: Arc::new(self.gate_bias.as_ref().clone()),
+        }
+    }
+}
+
+impl SequenceModelParameters for Parameters {
+    type Output = Model;
+    fn num_items(&self) -> usize {
+        self.hyper.num_items
+    }
+    fn num_users(&mut self) -> &mut Option<usize> {
+        &mut self.num_users
+    }
+    fn metadata(&mut self) -> &mut Option<ModelMetadata> {
+        &mut self.metadata
+    }
+    fn hyperparameters(&self) -> String {
+        serde_json::to_string(&self.hyper).unwrap_or_default()
+    }
+    fn max_sequence_length(&self) -> usize {
+        self.hyper.max_sequence_length
+    }
+    fn num_threads(&self) -> usize {
+        self.hyper.num_threads
+    }
+    fn accumulate_f64(&self) -> bool {
+        self.hyper.accumulate_f64
+    }
+    fn rng(&mut self) -> &mut XorShiftRng {
+        &mut self.hyper.rng
+    }
+    fn negative_sampler(&self) -> Option<&dyn MinibatchSampler> {
+        None
+    }
+    fn learning_rate(&self) -> f32 {
+        self.hyper.learning_rate
+    }
+    fn warmup_steps(&self) -> usize {
+        0
+    }
+    fn optimizer(&self, learning_rate: f32) -> Optimizers {
+        match self.hyper.optimizer {
+            Optimizer::Adagrad => Optimizers::Adagrad(
+                wyrm::optim::Adagrad::new()
+                    .learning_rate(learning_rate)
+                    .l2_penalty(self.hyper.l2_penalty),
+            ),
+
+            Optimizer::Adam => Optimizers::Adam(
+                wyrm::optim::Adam::new()
+                    .learning_rate(learning_rate)
+                    .l2_penalty(self.hyper.l2_penalty),
+            ),
+        }
+    }
+    fn parallelism(&self) -> &Parallelism {
+        &self.hyper.parallelism
+    }
+    fn loss(&self) -> &Loss {
+        &self.hyper.loss
+    }
+    fn num_epochs(&self) -> usize {
+        self.hyper.num_epochs
+    }
+    fn build(&self) -> Self::Output {
+        let dim = self.hyper.item_embedding_dim;
+        let item_embeddings = wyrm::ParameterNode::shared(self.item_embedding.clone());
+        let item_biases = wyrm::ParameterNode::shared(self.item_biases.clone());
+
+        let inputs: Vec<_> = (0..self.hyper.max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let outputs: Vec<_> = (0..self.hyper.max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let negatives: Vec<_> = (0..self.hyper.max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let time_deltas: Vec<_> = (0..self.hyper.max_sequence_length)
+            .map(|_| wyrm::InputNode::new(Arr::zeros((1, dim))))
+            .collect();
+
+        let input_embeddings: Vec<_> = inputs
+            .iter()
+            .map(|input| item_embeddings.index(input).boxed())
+            .collect();
+        let ones = wyrm::InputNode::new(Arr::ones((1, dim))).boxed();
+
+        // Both averages are kept as a decayed sum of embeddings divided by
+        // the decayed sum of weights, so that they are weighted means even
+        // when the long-term decay is close to zero.
+        let long_term_states: Vec<Variable<BoxedNode>> = {
+            let alpha = wyrm::ParameterNode::shared(self.long_term_alpha.clone());
+            let retain = 1.0 - MAX_LONG_TERM_DECAY * (1.0 - alpha.sigmoid());
+            let retain = retain.boxed();
+
+            let mut sum = input_embeddings[0].clone();
+            let mut weight = ones.clone();
+            let mut states = vec![input_embeddings[0].clone()];
+
+            for input in &input_embeddings[1..] {
+                sum = (retain.clone() * sum + input.clone()).boxed();
+                weight = (retain.clone() * weight + ones.clone()).boxed();
+                states.push((sum.clone() / weight.clone()).boxed());
+            }
+
+            states
+        };
+
+        let short_term_states: Vec<Variable<BoxedNode>> = {
+            let alpha = wyrm::ParameterNode::shared(self.short_term_alpha.clone());
+            let retain = alpha.sigmoid().boxed();
+            let scale = SESSION_BOUNDARY_SHARPNESS / self.hyper.session_gap as f32;
+
+            let mut sum = input_embeddings[0].clone();
+            let mut weight = ones.clone();
+            let mut states = vec![input_embeddings[0].clone()];
+
+            for (input, time_delta) in izip!(&input_embeddings[1..], &time_deltas[1..]) {
+                // Close to 1 within a session and to 0 across a boundary.
+                let same_session =
+                    (SESSION_BOUNDARY_SHARPNESS - scale * time_delta.clone()).sigmoid();
+                let session_retain = (same_session * retain.clone()).boxed();
+
+                sum = (session_retain.clone() * sum + input.clone()).boxed();
+                weight = (session_retain * weight + ones.clone()).boxed();
+                states.push((sum.clone() / weight.clone()).boxed());
+            }
+
+            states
+        };
+
+        let hidden_states: Vec<Variable<BoxedNode>> = match self.hyper.components {
+            Components::LongTerm => long_term_states,
+            Components::ShortTerm => short_term_states,
+            Components::Both => {
+                let gate_long_term = wyrm::ParameterNode::shared(self.gate_long_term.clone());
+                let gate_short_term = wyrm::ParameterNode::shared(self.gate_short_term.clone());
+                let gate_bias = wyrm::ParameterNode::shared(self.gate_bias.clone());
+
+                izip!(long_term_states, short_term_states)
+                    .map(|(long_term, short_term)| {
+                        let gate = (long_term.dot(&gate_long_term)
+                            + short_term.dot(&gate_short_term)
+                            + gate_bias.clone())
+                        .sigmoid()
+                        .boxed();
+
+                        (gate.clone() * long_term + (1.0 - gate) * short_term).boxed()
+                    })
+                    .collect()
+            }
+        };
+
+        let positive_predictions: Vec<_> = izip!(&hidden_states, &outputs)
+            .map(|(hidden_state, output)| {
+                hidden_state.vector_dot(&item_embeddings.index(output)) + item_biases.index(output)
+            })
+            .collect();
+        let negative_predictions: Vec<_> = izip!(&hidden_states, &negatives)
+            .map(|(hidden_state, negative)| {
+                hidden_state.vector_dot(&item_embeddings.index(negative))
+                    + item_biases.index(negative)
+            })
+            .collect();
+
+        let losses: Vec<_> = positive_predictions
+            .into_iter()
+            .zip(negative_predictions.into_iter())
+            .map(|(pos, neg)| match self.hyper.loss {
+                Loss::BPR => (neg - pos).sigmoid().boxed(),
+                Loss::Hinge | Loss::WARP => (1.0 + neg - pos).relu().boxed(),
+            })
+            .collect();
+
+        let mut summed_losses = Vec::with_capacity(losses.len());
+        summed_losses.push(losses[0].clone());
+
+        for loss in &losses[1..] {
+            let loss = (summed_losses.last().unwrap().clone() + loss.clone()).boxed();
+            summed_losses.push(loss);
+        }
+
+        Model {
+            inputs,
+            outputs,
+            negatives,
+            time_deltas,
+            hidden_states,
+            summed_losses,
+        }
+    }
+    fn predict_single(&self, user: &[f32], item_idx: usize) -> f32 {
+        let embeddings = self.item_embedding.value();
+        let biases = self.item_biases.value();
+
+        let embedding = embeddings.row(item_idx);
+        let bias = biases[(item_idx, 0)];
+        let dot = wyrm::simd_dot(user, embedding.as_slice().unwrap());
+
+        bias + dot
+    }
+    fn predict_all(&self, user: &[f32], num_items: usize) -> Vec<f32> {
+        score_all_items(
+            self.item_embedding.value(),
+            self.item_biases.value(),
+            user,
+            num_items,
+        )
+    }
+}
+
+struct Model {
+    inputs: Vec<Variable<wyrm::IndexInputNode>>,
+    outputs: Vec<Variable<wyrm::IndexInputNode>>,
+    negatives: Vec<Variable<wyrm::IndexInputNode>>,
+    time_deltas: Vec<Variable<wyrm::InputNode>>,
+    hidden_states: Vec<Variable<BoxedNode>>,
+    summed_losses: Vec<Variable<BoxedNode>>,
+}
+
+impl SequenceModel for Model {
+    fn state(
+        &self,
+    ) -> (
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<BoxedNode>],
+    ) {
+        (
+            &self.inputs,
+            &self.outputs,
+            &self.negatives,
+            &self.hidden_states,
+        )
+    }
+    fn losses(&mut self) -> &mut [Variable<BoxedNode>] {
+        &mut self.summed_losses
+    }
+    fn hidden_states(&mut self) -> &mut [Variable<BoxedNode>] {
+        &mut self.hidden_states
+    }
+    fn contexts(&self) -> &[Variable<wyrm::IndexInputNode>] {
+        &[]
+    }
+    fn time_deltas(&self) -> &[Variable<wyrm::InputNode>] {
+        &self.time_deltas
+    }
+    fn dropout_masks(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn weights(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn set_users(&self, _user_ids: &[usize]) {}
+}
+
+/// A hierarchical long-term/short-term model for implicit feedback.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImplicitHierarchicalModel {
+    params: Parameters,
+}
+
+impl ImplicitHierarchicalModel {
+    /// Fit the model.
+    ///
+    /// Returns the loss value.
+    #[cfg(feature = "training")]
+    pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<f32, FittingError> {
+        fit_sequence_model(interactions, &mut self.params)
+    }
+
+    /// Return the record of the last successful fit, or `None` if the
+    /// model has not been fitted. See [ModelMetadata].
+    pub fn metadata(&self) -> Option<&ModelMetadata> {
+        self.params.metadata.as_ref()
+    }
+
+    /// Return the record of the last successful fit mutably, for example
+    /// to add tags.
+    pub fn metadata_mut(&mut self) -> Option<&mut ModelMetadata> {
+        self.params.metadata.as_mut()
+    }
+
+    /// Compute a user representation from past interactions and their
+    /// timestamps, which must be aligned with `item_ids`. Without
+    /// timestamps, the whole history is treated as a single session.
+    pub fn user_representation_with_timestamps(
+        &self,
+        item_ids: &[ItemId],
+        timestamps: &[Timestamp],
+    ) -> Result<ImplicitUser, PredictionError> {
+        sequence_representation(&self.params, item_ids, None, Some(timestamps))
+    }
+}
+
+impl OnlineRankingModel for ImplicitHierarchicalModel {
+    type UserRepresentation = ImplicitUser;
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        self.params.user_representation(item_ids)
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.params.predict(user, item_ids)
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.params.predict_all_items(user, num_items)
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use rand::distributions::Uniform;
+
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::ids::{items, times, UserId};
+
+    const NUM_TASTES: usize = 4;
+    const NUM_INTENTS: usize = 4;
+    const NUM_VARIANTS: usize = 10;
+
+    /// Item ids `0..NUM_INTENTS` are generic items that open a session
+    /// with the corresponding intent; the rest each belong to a taste,
+    /// an intent, and a variant.
+    fn item_id(taste: usize, intent: usize, variant: usize) -> ItemId {
+        ItemId::from_index(NUM_INTENTS + (taste * NUM_INTENTS + intent) * NUM_VARIANTS + variant)
+    }
+
+    /// Every user has a persistent taste and a series of sessions an hour
+    /// apart. Each session opens with the generic item of its intent,
+    /// followed by items matching both the user's taste and the intent.
+    /// The last session is a generic item followed by an item the user
+    /// has not seen before.
+    fn taste_and_intent_data(num_users: usize) -> CompressedInteractions {
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+        let taste_range = Uniform::new(0, NUM_TASTES);
+        let intent_range = Uniform::new(0, NUM_INTENTS);
+        let variant_range = Uniform::new(0, NUM_VARIANTS);
+
+        let mut interactions = Interactions::new(
+            num_users,
+            NUM_INTENTS + NUM_TASTES * NUM_INTENTS * NUM_VARIANTS,
+        );
+        for user_id in 0..num_users {
+            let taste = taste_range.sample(&mut rng);
+            let user = UserId::from_index(user_id);
+            let mut timestamp = Timestamp(0);
+            let mut seen = Vec::new();
+
+            for session in 0..5 {
+                let intent = intent_range.sample(&mut rng);
+                interactions.push(Interaction::new(
+                    user,
+                    ItemId::from_index(intent),
+                    timestamp,
+                ));
+                timestamp += 60;
+
+                let session_length = if session == 4 { 1 } else { 3 };
+                for _ in 0..session_length {
+                    let mut item = item_id(taste, intent, variant_range.sample(&mut rng));
+                    if session == 4 {
+                        while seen.contains(&item) {
+                            item = item_id(taste, intent, variant_range.sample(&mut rng));
+                        }
+                    }
+                    seen.push(item);
+
+                    interactions.push(Interaction::new(user, item, timestamp));
+                    timestamp += 60;
+                }
+
+                timestamp += 3600;
+            }
+        }
+
+        interactions.to_compressed()
+    }
+
+    /// MRR of the last item of every user, computing representations with
+    /// timestamps and excluding previously seen items.
+    fn mrr(model: &ImplicitHierarchicalModel, data: &CompressedInteractions) -> f32 {
+        let mrrs: Vec<f32> = data
+            .iter_users()
+            .map(|user| {
+                let split = user.item_ids.len() - 1;
+                let representation = model
+                    .user_representation_with_timestamps(
+                        &user.item_ids[..split],
+                        &user.timestamps[..split],
+                    )
+                    .unwrap();
+                let mut predictions = model
+                    .predict_all_items(&representation, data.num_items())
+                    .unwrap();
+
+                for &item_id in &user.item_ids[..split] {
+                    predictions[item_id.index()] = std::f32::MIN;
+                }
+
+                let test_score = predictions[user.item_ids[split].index()];
+                let rank = predictions.iter().filter(|&&x| x >= test_score).count();
+
+                1.0 / rank as f32
+            })
+            .collect();
+
+        mrrs.iter().sum::<f32>() / mrrs.len() as f32
+    }
+
+    #[test]
+    fn beats_components() {
+        let data = taste_and_intent_data(500);
+
+        let fit = |components: Components| {
+            let mut model = Hyperparameters::new(data.num_items(), 20)
+                .embedding_dim(16)
+                .components(components)
+                .learning_rate(0.05)
+                .num_epochs(20)
+                .num_threads(1)
+                .from_seed([42; 16])
+                .build();
+            model.fit(&data).unwrap();
+
+            mrr(&model, &data)
+        };
+
+        let both_mrr = fit(Components::Both);
+        let long_term_mrr = fit(Components::LongTerm);
+        let short_term_mrr = fit(Components::ShortTerm);
+
+        println!(
+            "Hierarchical MRR {}, long-term {}, short-term {}",
+            both_mrr, long_term_mrr, short_term_mrr
+        );
+        assert!(both_mrr > long_term_mrr);
+        assert!(both_mrr > short_term_mrr);
+    }
+
+    #[test]
+    fn sessions_reset_short_term_state() {
+        let model = Hyperparameters::new(10, 5)
+            .components(Components::ShortTerm)
+            .session_gap(100)
+            .from_seed([42; 16])
+            .build();
+        let embedding = |item_id: ItemId| {
+            model
+                .params
+                .item_embedding
+                .value()
+                .row(item_id.index())
+                .to_vec()
+        };
+
+        // A long gap before the last item: only it remains.
+        let user = model
+            .user_representation_with_timestamps(&items(&[1, 2, 3]), &times(&[0, 10, 1000]))
+            .unwrap();
+        assert!(
+            izip!(&user.user_embedding, embedding(ItemId(3))).all(|(x, y)| (x - y).abs() < 1e-4)
+        );
+
+        // Within a session, earlier items still contribute.
+        let user = model
+            .user_representation_with_timestamps(&items(&[1, 2, 3]), &times(&[0, 10, 20]))
+            .unwrap();
+        assert!(
+            izip!(&user.user_embedding, embedding(ItemId(3))).any(|(x, y)| (x - y).abs() > 1e-4)
+        );
+
+        let serialized = serde_json::to_string(&model).unwrap();
+        let deserialized: ImplicitHierarchicalModel = serde_json::from_str(&serialized).unwrap();
+        let deserialized_user = deserialized
+            .user_representation_with_timestamps(&items(&[1, 2, 3]), &times(&[0, 10, 20]))
+            .unwrap();
+        assert_eq!(user.user_embedding, deserialized_user.user_embedding);
+    }
+}
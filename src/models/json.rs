@@ -0,0 +1,298 @@
+//! Versioned, human-readable JSON export of models.
+//!
+//! The document holds the model type, the format version and the model
+//! itself: hyperparameters are written as plain JSON, while the values of
+//! every parameter matrix are replaced by a base64 encoding of their raw
+//! little-endian `f32` bytes, alongside their shape. This keeps the
+//! document readable while round-tripping parameters exactly.
+use std::error;
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use super::metadata::ModelMetadata;
+
+/// Version of the JSON format written by this version of the crate.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// Errors when exporting or importing JSON models.
+#[derive(Debug)]
+pub enum ExportError {
+    /// The document is not valid JSON, or does not describe a model.
+    Json(serde_json::Error),
+    /// A parameter blob is not valid base64.
+    Base64(base64::DecodeError),
+    /// The document is missing a field, or a field has the wrong type.
+    Malformed(String),
+    /// The document was written in a format version this version of
+    /// the crate cannot read.
+    UnsupportedVersion {
+        /// The version of the document.
+        version: u64,
+    },
+    /// The document describes a different type of model.
+    WrongModel {
+        /// The model being loaded.
+        expected: &'static str,
+        /// The model in the document.
+        found: String,
+    },
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::Json(err) => write!(f, "JSON error: {}", err),
+            ExportError::Base64(err) => write!(f, "Invalid parameter blob: {}", err),
+            ExportError::Malformed(reason) => write!(f, "Malformed model document: {}", reason),
+            ExportError::UnsupportedVersion { version } => write!(
+                f,
+                "Unsupported format version {}: only version {} can be read.",
+                version, FORMAT_VERSION
+            ),
+            ExportError::WrongModel { expected, found } => write!(
+                f,
+                "Expected a {} model, but the document holds a {} model.",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            ExportError::Json(err) => Some(err),
+            ExportError::Base64(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(err: serde_json::Error) -> Self {
+        ExportError::Json(err)
+    }
+}
+
+impl From<base64::DecodeError> for ExportError {
+    fn from(err: base64::DecodeError) -> Self {
+        ExportError::Base64(err)
+    }
+}
+
+/// Key of the base64-encoded data of a parameter matrix, which replaces
+/// the `data` key of serialized arrays.
+const BLOB_KEY: &str = "data_f32le";
+
+/// Replace the data of each serialized array in `value` by a base64 blob,
+/// as long as all its elements are exactly representable as `f32`.
+fn encode_arrays(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            let is_array = map.len() == 3 && map.contains_key("v") && map.contains_key("dim");
+
+            let blob = match map.get("data") {
+                Some(Value::Array(data)) if is_array => data
+                    .iter()
+                    .map(|x| match x.as_f64() {
+                        Some(x) if f64::from(x as f32) == x => Some((x as f32).to_le_bytes()),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .map(|bytes| base64::encode(bytes.concat())),
+                _ => None,
+            };
+
+            match blob {
+                Some(blob) => {
+                    map.remove("data");
+                    map.insert(BLOB_KEY.to_owned(), Value::String(blob));
+                }
+                None => map.values_mut().for_each(encode_arrays),
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(encode_arrays),
+        _ => {}
+    }
+}
+
+/// Undo [encode_arrays].
+fn decode_arrays(value: &mut Value) -> Result<(), ExportError> {
+    match value {
+        Value::Object(map) => match map.remove(BLOB_KEY) {
+            Some(Value::String(blob)) => {
+                let bytes = base64::decode(&blob)?;
+
+                if bytes.len() % 4 != 0 {
+                    return Err(ExportError::Malformed(format!(
+                        "Parameter blob of {} bytes.",
+                        bytes.len()
+                    )));
+                }
+
+                let data = bytes
+                    .chunks(4)
+                    .map(|x| json!(f32::from_le_bytes([x[0], x[1], x[2], x[3]])))
+                    .collect();
+                map.insert("data".to_owned(), Value::Array(data));
+
+                Ok(())
+            }
+            Some(_) => Err(ExportError::Malformed(format!(
+                "{} is not a string.",
+                BLOB_KEY
+            ))),
+            None => map.values_mut().try_for_each(decode_arrays),
+        },
+        Value::Array(values) => values.iter_mut().try_for_each(decode_arrays),
+        _ => Ok(()),
+    }
+}
+
+/// Write `model`, of type `model_type`, as a JSON document.
+pub(crate) fn to_json<T: Serialize>(model: &T, model_type: &str) -> Result<String, ExportError> {
+    let mut model = serde_json::to_value(model)?;
+    encode_arrays(&mut model);
+
+    let mut document = Map::new();
+    document.insert("format_version".to_owned(), json!(FORMAT_VERSION));
+    document.insert("model_type".to_owned(), json!(model_type));
+    document.insert("model".to_owned(), model);
+
+    Ok(serde_json::to_string_pretty(&document)?)
+}
+
+/// Check that `document` was written in a format version this version of
+/// the crate can read.
+fn check_version(document: &Map<String, Value>) -> Result<(), ExportError> {
+    match document.get("format_version").map(Value::as_u64) {
+        Some(Some(FORMAT_VERSION)) => Ok(()),
+        Some(Some(version)) => Err(ExportError::UnsupportedVersion { version }),
+        _ => Err(ExportError::Malformed(
+            "Missing or invalid format_version.".to_owned(),
+        )),
+    }
+}
+
+/// Read a model of type `model_type` from a JSON document written by
+/// [to_json], checking its format version.
+pub(crate) fn from_json<T: DeserializeOwned>(
+    json: &str,
+    model_type: &'static str,
+) -> Result<T, ExportError> {
+    let mut document: Map<String, Value> = serde_json::from_str(json)?;
+    check_version(&document)?;
+
+    match document.get("model_type").and_then(Value::as_str) {
+        Some(found) if found == model_type => {}
+        Some(found) => {
+            return Err(ExportError::WrongModel {
+                expected: model_type,
+                found: found.to_owned(),
+            })
+        }
+        None => {
+            return Err(ExportError::Malformed(
+                "Missing or invalid model_type.".to_owned(),
+            ))
+        }
+    }
+
+    let mut model = document
+        .remove("model")
+        .ok_or_else(|| ExportError::Malformed("Missing model.".to_owned()))?;
+    decode_arrays(&mut model)?;
+
+    Ok(serde_json::from_value(model)?)
+}
+
+/// Read the [ModelMetadata] of an exported model of any type, without
+/// decoding its parameters. Returns `None` if the model was exported
+/// before it was fitted.
+pub fn read_metadata(json: &str) -> Result<Option<ModelMetadata>, ExportError> {
+    let mut document: Map<String, Value> = serde_json::from_str(json)?;
+    check_version(&document)?;
+
+    let metadata = document
+        .get_mut("model")
+        .and_then(|model| model.get_mut("params"))
+        .and_then(|params| params.get_mut("metadata"))
+        .map(Value::take)
+        .unwrap_or(Value::Null);
+
+    Ok(serde_json::from_value(metadata)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+
+    use super::*;
+    use crate::ids::{items, ItemId};
+    use crate::models::ewma::{Hyperparameters, ImplicitEWMAModel};
+    use crate::{OnlineRankingModel, PredictionError};
+
+    #[test]
+    fn exact_round_trip() {
+        let values = vec![
+            0.1,
+            -1.0 / 3.0,
+            std::f32::MAX,
+            std::f32::MIN_POSITIVE,
+            1e-45,
+            -0.0,
+        ];
+        let array = Array2::from_shape_vec((2, 3), values).unwrap();
+
+        let json = to_json(&array, "array").unwrap();
+        assert!(json.contains(BLOB_KEY));
+
+        let read: Array2<f32> = from_json(&json, "array").unwrap();
+        for (x, y) in read.iter().zip(array.iter()) {
+            assert_eq!(x.to_bits(), y.to_bits());
+        }
+
+        match from_json::<Array2<f32>>(&json, "other") {
+            Err(ExportError::WrongModel { found, .. }) => assert_eq!(found, "array"),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn load_and_predict() {
+        // Runs without the `training` feature: the model is loaded as it
+        // would be at serving time, and only marked as fitted.
+        let json = Hyperparameters::new(10, 5)
+            .from_seed([42; 16])
+            .build()
+            .to_json()
+            .unwrap();
+
+        let model = ImplicitEWMAModel::from_json(&json).unwrap();
+        match model.user_representation(&items(&[1, 2, 3])) {
+            Err(PredictionError::ModelNotFitted) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut document: Value = serde_json::from_str(&json).unwrap();
+        document["model"]["params"]["num_users"] = json!(0);
+        let model = ImplicitEWMAModel::from_json(&document.to_string()).unwrap();
+
+        let item_ids: Vec<_> = (0..10).map(ItemId).collect();
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let predictions = model.predict(&user, &item_ids).unwrap();
+        assert!(predictions.iter().all(|score| score.is_finite()));
+
+        let mut out = vec![0.0; 10];
+        model.predict_into(&user, &item_ids, &mut out).unwrap();
+        assert_eq!(out, predictions);
+
+        let ranked = model.rank_items(&user, &item_ids).unwrap();
+        assert!(ranked
+            .windows(2)
+            .all(|x| predictions[x[0]] >= predictions[x[1]]));
+    }
+}
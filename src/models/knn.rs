@@ -0,0 +1,295 @@
+//! Session-based k-nearest-neighbours model.
+//!
+//! The model finds the training sequences most similar to the current
+//! session and recommends the items they contain, weighting each
+//! neighbour's vote by its similarity. Similarity is computed over item
+//! sets, with more recent items of the current session counting more.
+//! Despite needing no training, this is competitive with neural models
+//! on short-session data.
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::top_k;
+use crate::data::CompressedInteractions;
+use crate::ids::{ItemId, Timestamp};
+use crate::{FittingError, OnlineRankingModel, PredictionError};
+
+/// Similarity between the current session and training sessions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Similarity {
+    /// Weighted overlap divided by the geometric mean of the set sizes.
+    Cosine,
+    /// Weighted overlap divided by the size of the union of the sets.
+    Jaccard,
+}
+
+/// User representation of [SessionKnn]: the distinct items of the
+/// current session with their recency weights.
+#[derive(Clone, Debug, Default)]
+pub struct KnnUser {
+    item_weights: HashMap<ItemId, f32>,
+}
+
+/// A training session: its distinct items, sorted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Session {
+    item_ids: Vec<ItemId>,
+}
+
+/// Session-based kNN model.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionKnn {
+    num_items: usize,
+    num_neighbours: usize,
+    similarity: Similarity,
+    max_sessions_per_item: usize,
+    sessions: Vec<Session>,
+    item_sessions: HashMap<ItemId, Vec<usize>>,
+}
+
+impl SessionKnn {
+    /// Create an unfitted model over `num_items` items, voting with
+    /// `num_neighbours` nearest sessions.
+    pub fn new(num_items: usize, num_neighbours: usize) -> Self {
+        SessionKnn {
+            num_items,
+            num_neighbours,
+            similarity: Similarity::Cosine,
+            max_sessions_per_item: 1000,
+            sessions: Vec::new(),
+            item_sessions: HashMap::new(),
+        }
+    }
+
+    /// Set the session similarity. Defaults to [Similarity::Cosine].
+    pub fn similarity(mut self, similarity: Similarity) -> Self {
+        self.similarity = similarity;
+        self
+    }
+
+    /// Only index the `max_sessions_per_item` most recent sessions
+    /// containing each item, bounding prediction cost for popular
+    /// items. Defaults to 1000.
+    pub fn max_sessions_per_item(mut self, max_sessions_per_item: usize) -> Self {
+        self.max_sessions_per_item = max_sessions_per_item;
+        self
+    }
+
+    /// Fit the model by indexing each user's sequence as a session.
+    pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<(), FittingError> {
+        if interactions.num_items() != self.num_items {
+            return Err(FittingError::ItemCountMismatch {
+                model_items: self.num_items,
+                data_items: interactions.num_items(),
+            });
+        }
+
+        let mut sessions = Vec::new();
+        let mut last_timestamps: Vec<Timestamp> = Vec::new();
+
+        for user in interactions.iter_users().filter(|user| !user.is_empty()) {
+            let mut item_ids = user.item_ids.to_vec();
+            item_ids.sort_unstable();
+            item_ids.dedup();
+
+            sessions.push(Session { item_ids });
+            last_timestamps.push(*user.timestamps.last().unwrap());
+        }
+
+        if sessions.is_empty() {
+            return Err(FittingError::NoInteractions);
+        }
+
+        let mut item_sessions: HashMap<ItemId, Vec<usize>> = HashMap::new();
+        for (session_id, session) in sessions.iter().enumerate() {
+            for &item_id in &session.item_ids {
+                item_sessions.entry(item_id).or_default().push(session_id);
+            }
+        }
+
+        for session_ids in item_sessions.values_mut() {
+            session_ids.sort_by_key(|&session_id| std::cmp::Reverse(last_timestamps[session_id]));
+            session_ids.truncate(self.max_sessions_per_item);
+        }
+
+        self.sessions = sessions;
+        self.item_sessions = item_sessions;
+
+        Ok(())
+    }
+
+    /// The similarity of `user` to the training session `session_id`.
+    fn session_similarity(&self, user: &KnnUser, session_id: usize) -> f32 {
+        let session = &self.sessions[session_id];
+
+        let (overlap, num_shared) = session
+            .item_ids
+            .iter()
+            .filter_map(|item_id| user.item_weights.get(item_id))
+            .fold((0.0, 0), |(overlap, num_shared), weight| {
+                (overlap + weight, num_shared + 1)
+            });
+
+        let session_size = session.item_ids.len() as f32;
+        let user_size = user.item_weights.len() as f32;
+
+        match self.similarity {
+            Similarity::Cosine => overlap / (session_size * user_size).sqrt(),
+            Similarity::Jaccard => overlap / (session_size + user_size - num_shared as f32),
+        }
+    }
+
+    /// Return the `num_neighbours` training sessions most similar to
+    /// `user`, as `(session_id, similarity)` pairs, best first.
+    fn neighbours(&self, user: &KnnUser) -> Vec<(usize, f32)> {
+        let candidates: HashSet<usize> = user
+            .item_weights
+            .keys()
+            .filter_map(|item_id| self.item_sessions.get(item_id))
+            .flatten()
+            .cloned()
+            .collect();
+
+        top_k(
+            candidates
+                .into_iter()
+                .map(|session_id| (session_id, self.session_similarity(user, session_id)))
+                .collect(),
+            self.num_neighbours,
+        )
+    }
+}
+
+impl OnlineRankingModel for SessionKnn {
+    type UserRepresentation = KnnUser;
+
+    /// Weight the item at position `i` (counting from 1) of a session
+    /// of length `n` by `i / n`; repeated items keep their latest weight.
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        let length = item_ids.len() as f32;
+
+        Ok(KnnUser {
+            item_weights: item_ids
+                .iter()
+                .enumerate()
+                .map(|(idx, &item_id)| (item_id, (idx + 1) as f32 / length))
+                .collect(),
+        })
+    }
+
+    /// Score items by the summed similarity of the nearest sessions
+    /// containing them. Items in no neighbouring session score 0.
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        let mut votes: HashMap<ItemId, f32> = HashMap::new();
+
+        for (session_id, similarity) in self.neighbours(user) {
+            for &item_id in &self.sessions[session_id].item_ids {
+                *votes.entry(item_id).or_insert(0.0) += similarity;
+            }
+        }
+
+        Ok(item_ids
+            .iter()
+            .map(|item_id| votes.get(item_id).cloned().unwrap_or(0.0))
+            .collect())
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, UserId};
+
+    /// One user per session, with sessions ten time units apart.
+    fn sessions(sessions: &[&[u64]]) -> CompressedInteractions {
+        let interactions: Vec<_> = sessions
+            .iter()
+            .enumerate()
+            .flat_map(|(user_id, item_ids)| {
+                item_ids.iter().enumerate().map(move |(t, &item_id)| {
+                    Interaction::new(
+                        UserId::from_index(user_id),
+                        ItemId(item_id),
+                        Timestamp((user_id * 10 + t) as i64),
+                    )
+                })
+            })
+            .collect();
+
+        let mut data = Interactions::new(sessions.len(), 10);
+        for interaction in interactions {
+            data.push(interaction);
+        }
+
+        data.to_compressed()
+    }
+
+    #[test]
+    fn nearest_sessions() {
+        let data = sessions(&[&[0, 1, 2], &[0, 1, 3], &[4, 5], &[0, 4, 6, 7]]);
+
+        let mut model = SessionKnn::new(10, 2).similarity(Similarity::Jaccard);
+        model.fit(&data).unwrap();
+
+        // With equal weights, the session {0, 1} has Jaccard similarity 2/3
+        // to sessions 0 and 1, and 1/5 to session 3.
+        let user = KnnUser {
+            item_weights: vec![(ItemId(0), 1.0), (ItemId(1), 1.0)]
+                .into_iter()
+                .collect(),
+        };
+        let mut neighbours = model.neighbours(&user);
+        neighbours.sort_by_key(|&(session_id, _)| session_id);
+        assert_eq!(neighbours, vec![(0, 2.0 / 3.0), (1, 2.0 / 3.0)]);
+
+        let scores = model.predict(&user, &items(&[2, 3, 4, 6])).unwrap();
+        assert_eq!(scores, vec![2.0 / 3.0, 2.0 / 3.0, 0.0, 0.0]);
+
+        // Recency weighting: the last item counts fully, the first half.
+        let user = model.user_representation(&items(&[1, 4])).unwrap();
+        assert_eq!(user.item_weights[&ItemId(1)], 0.5);
+        assert_eq!(user.item_weights[&ItemId(4)], 1.0);
+
+        let neighbours = model.neighbours(&user);
+        assert_eq!(neighbours[0], (2, 1.0 / 3.0));
+
+        assert!(mrr_score(&model, &data).unwrap() > 0.0);
+    }
+
+    #[test]
+    fn cosine_and_sampling() {
+        let data = sessions(&[&[0, 1], &[0, 2], &[0, 3]]);
+
+        let mut model = SessionKnn::new(10, 10).max_sessions_per_item(2);
+        model.fit(&data).unwrap();
+
+        // Only the two most recent sessions containing item 0 are indexed.
+        assert_eq!(model.item_sessions[&ItemId(0)], vec![2, 1]);
+
+        let user = model.user_representation(&items(&[0])).unwrap();
+        let scores = model.predict(&user, &items(&[1, 2, 3])).unwrap();
+        let expected = 1.0 / 2.0_f32.sqrt();
+        assert_eq!(scores, vec![0.0, expected, expected]);
+
+        // Empty sessions have no neighbours.
+        let user = model.user_representation(&[]).unwrap();
+        assert_eq!(
+            model.predict(&user, &items(&[0, 1])).unwrap(),
+            vec![0.0, 0.0]
+        );
+
+        let serialized = bincode::serialize(&model).unwrap();
+        let deserialized: SessionKnn = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(deserialized.item_sessions, model.item_sessions);
+    }
+}
@@ -1,29 +1,77 @@
 //! Module for LSTM-based models.
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use rand;
 use rand::distributions::{Distribution, Normal, Uniform};
 use rand::{Rng, SeedableRng, XorShiftRng};
-use rayon;
 use serde::{Deserialize, Serialize};
 
-use ndarray::Axis;
+use ndarray::Array2;
 
 use wyrm;
 use wyrm::nn;
 use wyrm::optim::Optimizers;
 use wyrm::{Arr, BoxedNode, Variable};
 
-use super::sequence_model::{fit_sequence_model, SequenceModel, SequenceModelParameters};
-use super::{ImplicitUser, Loss, Optimizer, Parallelism};
-use crate::data::CompressedInteractions;
-use crate::{FittingError, ItemId, OnlineRankingModel, PredictionError};
+use super::format::{self, ModelLoadError};
+use super::json::{self, ExportError};
+use super::metadata::ModelMetadata;
+use super::sampling::MinibatchSampler;
+#[cfg(feature = "training")]
+use super::sequence_model::{
+    fit_sequence_model, invalid_hyperparameter, sequence_gradient_norm, sequence_perplexity,
+    validate_interactions,
+};
+use super::sequence_model::{
+    predict_candidates, score_all_items, sequence_representation, sequence_representations,
+    sequence_rollout, SequenceModel, SequenceModelParameters,
+};
+use super::{scoring_vectors, ImplicitUser, ItemEmbeddings, Loss, Optimizer, Parallelism};
+use crate::config;
+#[cfg(feature = "training")]
+use crate::data::{CompressedInteractions, TripletMinibatch};
+use crate::ids::ItemId;
+use crate::rng::SeedSequence;
+#[cfg(feature = "training")]
+use crate::FittingError;
+use crate::{OnlineRankingModel, PredictionError};
 
 fn embedding_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
     let normal = Normal::new(0.0, 1.0 / cols as f64);
     Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
 }
 
+fn has_shape(matrix: &[Vec<f32>], rows: usize, cols: usize) -> bool {
+    matrix.len() == rows && matrix.iter().all(|row| row.len() == cols)
+}
+
+fn pretrained_init(matrix: &[Vec<f32>], rows: usize, cols: usize) -> wyrm::Arr {
+    Arr::from_shape_fn((rows, cols), |(row, col)| matrix[row][col])
+}
+
+fn dense_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
+    let normal = Normal::new(0.0, (2.0 / (rows + cols) as f64).sqrt());
+    Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
+}
+
+/// Maximum sequence length in `epoch` under the (sorted) curriculum `schedule`.
+fn curriculum_sequence_length(
+    schedule: &[(usize, usize)],
+    epoch: usize,
+    max_sequence_length: usize,
+) -> usize {
+    let sequence_length = schedule
+        .iter()
+        .rev()
+        .find(|&&(start, _)| start <= epoch)
+        .or_else(|| schedule.first())
+        .map_or(max_sequence_length, |&(_, sequence_length)| sequence_length);
+
+    sequence_length.min(max_sequence_length)
+}
+
 /// Type of LSTM layer to use.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum LSTMVariant {
@@ -49,6 +97,31 @@ pub struct Hyperparameters {
     rng: XorShiftRng,
     num_threads: usize,
     num_epochs: usize,
+    #[serde(default)]
+    accumulate_f64: bool,
+    #[serde(default)]
+    warmup_steps: usize,
+    #[serde(skip)]
+    negative_sampler: Option<Arc<dyn MinibatchSampler>>,
+    projection_dims: Vec<usize>,
+    context_feature: Option<(usize, usize)>,
+    #[serde(skip)]
+    pretrained_embeddings: Option<Vec<Vec<f32>>>,
+    freeze_embeddings: bool,
+    #[serde(default)]
+    curriculum_schedule: Vec<(usize, usize)>,
+    #[serde(default = "default_tied_embeddings")]
+    tied_embeddings: bool,
+    #[serde(default)]
+    use_layer_norm: bool,
+    #[serde(default)]
+    return_time_weight: Option<f32>,
+    #[serde(skip)]
+    cancellation_flag: Option<Arc<AtomicBool>>,
+}
+
+fn default_tied_embeddings() -> bool {
+    true
 }
 
 impl Hyperparameters {
@@ -65,8 +138,20 @@ impl Hyperparameters {
             optimizer: Optimizer::Adam,
             parallelism: Parallelism::Synchronous,
             rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
-            num_threads: rayon::current_num_threads(),
+            num_threads: config::num_threads(),
             num_epochs: 10,
+            accumulate_f64: false,
+            warmup_steps: 0,
+            negative_sampler: None,
+            projection_dims: Vec::new(),
+            context_feature: None,
+            pretrained_embeddings: None,
+            freeze_embeddings: false,
+            curriculum_schedule: Vec::new(),
+            tied_embeddings: default_tied_embeddings(),
+            use_layer_norm: false,
+            return_time_weight: None,
+            cancellation_flag: None,
         }
     }
 
@@ -94,6 +179,27 @@ impl Hyperparameters {
         self
     }
 
+    /// Set the number of minibatches over which the learning rate is
+    /// linearly ramped up from zero to its configured value. Warmups longer
+    /// than 100 steps ramp up in 100 equal increments.
+    pub fn warmup_steps(mut self, warmup_steps: usize) -> Self {
+        self.warmup_steps = warmup_steps;
+        self
+    }
+
+    /// Train on progressively longer sequences. Each `(epoch, max_seq_len)`
+    /// milestone limits training sequences to `max_seq_len` interactions
+    /// from `epoch` (zero-indexed, counted within each `fit` call) onwards;
+    /// before the first milestone, its `max_seq_len` applies.
+    ///
+    /// The global `max_sequence_length` remains a hard cap. Sequences
+    /// are split rather than truncated, so all data is used every epoch.
+    pub fn curriculum_schedule(mut self, mut milestones: Vec<(usize, usize)>) -> Self {
+        milestones.sort_by_key(|&(epoch, _)| epoch);
+        self.curriculum_schedule = milestones;
+        self
+    }
+
     /// Set the loss function.
     pub fn loss(mut self, loss: Loss) -> Self {
         self.loss = loss;
@@ -106,12 +212,117 @@ impl Hyperparameters {
         self
     }
 
+    /// Set the sizes of the dense + ReLU layers the LSTM output is passed
+    /// through before scoring. A final dense layer maps the result back
+    /// to the embedding dimension. An empty vector (the default) means
+    /// no projection.
+    pub fn projection_dims(mut self, projection_dims: Vec<usize>) -> Self {
+        self.projection_dims = projection_dims;
+        self
+    }
+
+    /// Use a categorical context feature (such as the hour of day) with
+    /// `cardinality` distinct values, attached to each interaction via
+    /// [Interaction::with_context](crate::data::Interaction::with_context).
+    ///
+    /// Each context value gets a `dim`-dimensional embedding, which is
+    /// projected to the item embedding dimension and added to the item
+    /// embedding at every step. Interactions without a context use context 0.
+    pub fn context_feature(mut self, cardinality: usize, dim: usize) -> Self {
+        self.context_feature = Some((cardinality, dim));
+        self
+    }
+
+    /// Set whether items are scored with the input item embeddings
+    /// (`true`, the default) or with a separate output embedding matrix.
+    ///
+    /// Tying the embeddings halves the number of item parameters and often
+    /// generalizes better. It requires the user representation to have the
+    /// same dimensionality as the item embeddings, which always holds here:
+    /// the LSTM hidden state and any projection layers output `embedding_dim`
+    /// values.
+    pub fn tied_embeddings(mut self, enabled: bool) -> Self {
+        self.tied_embeddings = enabled;
+        self
+    }
+
+    /// Set whether to apply layer normalization within the LSTM cell.
+    /// Defaults to false.
+    ///
+    /// When enabled, the input and recurrent projections of each gate are
+    /// separately normalized to zero mean and unit variance across the
+    /// hidden dimension, then scaled and shifted by learned per-dimension
+    /// gains and biases, before the gate non-linearity. This usually makes
+    /// training more stable at the cost of slower steps.
+    pub fn use_layer_norm(mut self, enabled: bool) -> Self {
+        self.use_layer_norm = enabled;
+        self
+    }
+
+    /// Jointly train a regression head predicting, from the user
+    /// representation after each step, the log of one plus the time to
+    /// the user's next interaction. Its squared error is added to the
+    /// ranking loss, weighted by `weight`.
+    ///
+    /// Targets come from the interaction timestamps. Required for
+    /// [ImplicitLSTMModel::predict_return_time].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is not positive.
+    pub fn predict_return_time(mut self, weight: f32) -> Self {
+        assert!(weight > 0.0, "Return time weight must be positive.");
+        self.return_time_weight = Some(weight);
+        self
+    }
+
+    /// Initialize the item embeddings from pretrained vectors, one row per item.
+    ///
+    /// The matrix must have `num_items` rows of `embedding_dim` values each;
+    /// fitting returns [FittingError::InvalidHyperparameter] otherwise. If
+    /// `freeze` is true, the embeddings are excluded from gradient updates
+    /// (and so from the L2 penalty) while the rest of the model trains.
+    pub fn pretrained_embeddings(mut self, matrix: Vec<Vec<f32>>, freeze: bool) -> Self {
+        self.pretrained_embeddings = Some(matrix);
+        self.freeze_embeddings = freeze;
+        self
+    }
+
+    /// Set the strategy used to sample negative items during training.
+    /// Defaults to sampling uniformly from all items.
+    ///
+    /// The sampler is not serialized: deserialized models fall back
+    /// to uniform sampling.
+    pub fn negative_sampler(mut self, sampler: Box<dyn MinibatchSampler>) -> Self {
+        self.negative_sampler = Some(Arc::from(sampler));
+        self
+    }
+
+    /// Set a flag that cancels fitting when set: `fit` stops at the start
+    /// of the next epoch and returns [FittingError::Cancelled], keeping the
+    /// updates made so far. The flag is not serialized.
+    pub fn cancellation_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancellation_flag = Some(flag);
+        self
+    }
+
     /// Set number of threads to be used.
     pub fn num_threads(mut self, num_threads: usize) -> Self {
         self.num_threads = num_threads;
         self
     }
 
+    /// Accumulate training losses in `f64` rather than `f32`. Defaults to
+    /// false.
+    ///
+    /// Summed in `f32`, many small losses lose precision, so that the
+    /// reported loss depends on summation order. Parameters stay `f32`
+    /// either way.
+    pub fn accumulate_f64(mut self, enabled: bool) -> Self {
+        self.accumulate_f64 = enabled;
+        self
+    }
+
     /// Set the type of paralellism.
     pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
         self.parallelism = parallelism;
@@ -131,6 +342,13 @@ impl Hyperparameters {
         self
     }
 
+    /// Set the random number generator to the one named `"lstm"` in
+    /// `seeds`, so that a single seed pins the whole experiment.
+    pub fn seed_sequence(mut self, seeds: &SeedSequence) -> Self {
+        self.rng = seeds.derive("lstm");
+        self
+    }
+
     /// Set the optimizer type.
     pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
         self.optimizer = optimizer;
@@ -166,17 +384,42 @@ impl Hyperparameters {
                 Parallelism::Synchronous
             },
             rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
-            num_threads: Uniform::new(1, rayon::current_num_threads() + 1).sample(rng),
+            num_threads: Uniform::new(1, config::num_threads() + 1).sample(rng),
             num_epochs: 2_usize.pow(Uniform::new(3, 7).sample(rng)),
+            accumulate_f64: false,
+            warmup_steps: 0,
+            negative_sampler: None,
+            projection_dims: Vec::new(),
+            context_feature: None,
+            pretrained_embeddings: None,
+            freeze_embeddings: false,
+            curriculum_schedule: Vec::new(),
+            tied_embeddings: default_tied_embeddings(),
+            use_layer_norm: false,
+            return_time_weight: None,
+            cancellation_flag: None,
         }
     }
 
+    fn is_cancelled(&self) -> bool {
+        self.cancellation_flag
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::SeqCst))
+    }
+
     fn build_params(mut self) -> Parameters {
-        let item_embeddings = Arc::new(wyrm::HogwildParameter::new(embedding_init(
-            self.num_items,
-            self.item_embedding_dim,
-            &mut self.rng,
-        )));
+        let item_embeddings = Arc::new(wyrm::HogwildParameter::new(
+            match self.pretrained_embeddings.take() {
+                Some(matrix) if has_shape(&matrix, self.num_items, self.item_embedding_dim) => {
+                    pretrained_init(&matrix, self.num_items, self.item_embedding_dim)
+                }
+                other => {
+                    // A mismatched matrix is kept so that fitting can reject it.
+                    self.pretrained_embeddings = other;
+                    embedding_init(self.num_items, self.item_embedding_dim, &mut self.rng)
+                }
+            },
+        ));
 
         let item_biases = Arc::new(wyrm::HogwildParameter::new(Arr::zeros((self.num_items, 1))));
         let lstm_params = nn::lstm::Parameters::new(
@@ -185,11 +428,81 @@ impl Hyperparameters {
             &mut self.rng,
         );
 
+        let layer_norm_lstm = if self.use_layer_norm {
+            Some(LayerNormLSTM::new(
+                self.item_embedding_dim,
+                self.item_embedding_dim,
+                &mut self.rng,
+            ))
+        } else {
+            None
+        };
+
+        let mut layer_dims = Vec::with_capacity(self.projection_dims.len() + 2);
+        if !self.projection_dims.is_empty() {
+            layer_dims.push(self.item_embedding_dim);
+            layer_dims.extend_from_slice(&self.projection_dims);
+            layer_dims.push(self.item_embedding_dim);
+        }
+
+        let projection = layer_dims
+            .windows(2)
+            .map(|dims| DenseLayer {
+                weights: Arc::new(wyrm::HogwildParameter::new(dense_init(
+                    dims[0],
+                    dims[1],
+                    &mut self.rng,
+                ))),
+                biases: Arc::new(wyrm::HogwildParameter::new(Arr::zeros((1, dims[1])))),
+            })
+            .collect();
+
+        let context = self
+            .context_feature
+            .map(|(cardinality, dim)| ContextParameters {
+                embedding: Arc::new(wyrm::HogwildParameter::new(embedding_init(
+                    cardinality,
+                    dim,
+                    &mut self.rng,
+                ))),
+                projection: Arc::new(wyrm::HogwildParameter::new(dense_init(
+                    dim,
+                    self.item_embedding_dim,
+                    &mut self.rng,
+                ))),
+            });
+
+        let output_embedding = if self.tied_embeddings {
+            None
+        } else {
+            Some(Arc::new(wyrm::HogwildParameter::new(embedding_init(
+                self.num_items,
+                self.item_embedding_dim,
+                &mut self.rng,
+            ))))
+        };
+
+        let return_time = self.return_time_weight.map(|_| DenseLayer {
+            weights: Arc::new(wyrm::HogwildParameter::new(dense_init(
+                self.item_embedding_dim,
+                1,
+                &mut self.rng,
+            ))),
+            biases: Arc::new(wyrm::HogwildParameter::new(Arr::zeros((1, 1)))),
+        });
+
         Parameters {
             hyper: self,
+            num_users: None,
+            metadata: None,
             item_embedding: item_embeddings,
+            output_embedding,
             item_biases,
             lstm: lstm_params,
+            layer_norm_lstm,
+            projection,
+            context,
+            return_time,
         }
     }
 
@@ -201,47 +514,328 @@ impl Hyperparameters {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct DenseLayer {
+    weights: Arc<wyrm::HogwildParameter>,
+    biases: Arc<wyrm::HogwildParameter>,
+}
+
+impl Clone for DenseLayer {
+    fn clone(&self) -> Self {
+        DenseLayer {
+            weights: Arc::new(self.weights.as_ref().clone()),
+            biases: Arc::new(self.biases.as_ref().clone()),
+        }
+    }
+}
+
+/// Parameters of one gate of a [LayerNormLSTM].
+#[derive(Debug, Serialize, Deserialize)]
+struct LayerNormGate {
+    input_weights: Arc<wyrm::HogwildParameter>,
+    recurrent_weights: Arc<wyrm::HogwildParameter>,
+    biases: Arc<wyrm::HogwildParameter>,
+    input_gain: Arc<wyrm::HogwildParameter>,
+    input_shift: Arc<wyrm::HogwildParameter>,
+    recurrent_gain: Arc<wyrm::HogwildParameter>,
+    recurrent_shift: Arc<wyrm::HogwildParameter>,
+}
+
+impl LayerNormGate {
+    fn new<T: Rng>(input_dim: usize, hidden_dim: usize, rng: &mut T) -> Self {
+        let parameter = |value: Arr| Arc::new(wyrm::HogwildParameter::new(value));
+
+        LayerNormGate {
+            input_weights: parameter(dense_init(input_dim, hidden_dim, rng)),
+            recurrent_weights: parameter(dense_init(hidden_dim, hidden_dim, rng)),
+            biases: parameter(Arr::zeros((1, hidden_dim))),
+            input_gain: parameter(Arr::ones((1, hidden_dim))),
+            input_shift: parameter(Arr::zeros((1, hidden_dim))),
+            recurrent_gain: parameter(Arr::ones((1, hidden_dim))),
+            recurrent_shift: parameter(Arr::zeros((1, hidden_dim))),
+        }
+    }
+
+    /// The gate pre-activation for `input` given the previous `hidden` state.
+    fn preactivation(
+        &self,
+        input: &Variable<BoxedNode>,
+        hidden: &Variable<BoxedNode>,
+        norm: &LayerNorm,
+    ) -> Variable<BoxedNode> {
+        let shared = |parameter: &Arc<wyrm::HogwildParameter>| {
+            wyrm::ParameterNode::shared(parameter.clone())
+        };
+
+        let input_projection = norm.forward(
+            input.dot(&shared(&self.input_weights)).boxed(),
+            &shared(&self.input_gain),
+            &shared(&self.input_shift),
+        );
+        let recurrent_projection = norm.forward(
+            hidden.dot(&shared(&self.recurrent_weights)).boxed(),
+            &shared(&self.recurrent_gain),
+            &shared(&self.recurrent_shift),
+        );
+
+        (input_projection + recurrent_projection + shared(&self.biases)).boxed()
+    }
+}
+
+impl Clone for LayerNormGate {
+    fn clone(&self) -> Self {
+        let deep = |parameter: &Arc<wyrm::HogwildParameter>| Arc::new(parameter.as_ref().clone());
+
+        LayerNormGate {
+            input_weights: deep(&self.input_weights),
+            recurrent_weights: deep(&self.recurrent_weights),
+            biases: deep(&self.biases),
+            input_gain: deep(&self.input_gain),
+            input_shift: deep(&self.input_shift),
+            recurrent_gain: deep(&self.recurrent_gain),
+            recurrent_shift: deep(&self.recurrent_shift),
+        }
+    }
+}
+
+/// Added to the variance in layer normalization for numerical stability.
+const LAYER_NORM_EPSILON: f32 = 1e-5;
+
+/// Constant inputs for normalizing row vectors of `hidden_dim` values.
+struct LayerNorm {
+    /// Column vector averaging a row.
+    mean_weights: Variable<wyrm::InputNode>,
+    /// Row of ones broadcasting a scalar to a row.
+    broadcast: Variable<wyrm::InputNode>,
+}
+
+impl LayerNorm {
+    fn new(hidden_dim: usize) -> Self {
+        LayerNorm {
+            mean_weights: wyrm::InputNode::new(Arr::from_elem(
+                (hidden_dim, 1),
+                1.0 / hidden_dim as f32,
+            )),
+            broadcast: wyrm::InputNode::new(Arr::ones((1, hidden_dim))),
+        }
+    }
+
+    /// Normalize `x` to zero mean and unit variance, then scale by
+    /// `gain` and shift by `shift`.
+    fn forward(
+        &self,
+        x: Variable<BoxedNode>,
+        gain: &Variable<wyrm::ParameterNode>,
+        shift: &Variable<wyrm::ParameterNode>,
+    ) -> Variable<BoxedNode> {
+        let mean = x.dot(&self.mean_weights).dot(&self.broadcast);
+        let centered = (x - mean).boxed();
+        let variance = (centered.clone() * centered.clone()).dot(&self.mean_weights);
+
+        // There is no square root operation: compute 1 / sqrt(v) as exp(-ln(v) / 2).
+        let inverse_std = (-0.5 * (LAYER_NORM_EPSILON + variance).ln())
+            .exp()
+            .dot(&self.broadcast);
+
+        (centered * inverse_std * gain.clone() + shift.clone()).boxed()
+    }
+}
+
+/// An LSTM layer with layer-normalized gate projections.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct LayerNormLSTM {
+    hidden_dim: usize,
+    input_gate: LayerNormGate,
+    forget_gate: LayerNormGate,
+    output_gate: LayerNormGate,
+    cell: LayerNormGate,
+}
+
+impl LayerNormLSTM {
+    fn new<T: Rng>(input_dim: usize, hidden_dim: usize, rng: &mut T) -> Self {
+        LayerNormLSTM {
+            hidden_dim,
+            input_gate: LayerNormGate::new(input_dim, hidden_dim, rng),
+            forget_gate: LayerNormGate::new(input_dim, hidden_dim, rng),
+            output_gate: LayerNormGate::new(input_dim, hidden_dim, rng),
+            cell: LayerNormGate::new(input_dim, hidden_dim, rng),
+        }
+    }
+
+    /// Run the layer over `inputs`, returning the hidden state at each step.
+    /// In the coupled variant, the forget gate is one minus the input gate.
+    fn forward(&self, inputs: &[Variable<BoxedNode>], coupled: bool) -> Vec<Variable<BoxedNode>> {
+        let norm = LayerNorm::new(self.hidden_dim);
+
+        let mut hidden = wyrm::InputNode::new(Arr::zeros((1, self.hidden_dim))).boxed();
+        let mut cell = wyrm::InputNode::new(Arr::zeros((1, self.hidden_dim))).boxed();
+        let mut hidden_states = Vec::with_capacity(inputs.len());
+
+        for input in inputs {
+            let input_gate = self
+                .input_gate
+                .preactivation(input, &hidden, &norm)
+                .sigmoid()
+                .boxed();
+            let forget_gate = if coupled {
+                (1.0 - input_gate.clone()).boxed()
+            } else {
+                self.forget_gate
+                    .preactivation(input, &hidden, &norm)
+                    .sigmoid()
+                    .boxed()
+            };
+            let output_gate = self
+                .output_gate
+                .preactivation(input, &hidden, &norm)
+                .sigmoid();
+            let candidate = self.cell.preactivation(input, &hidden, &norm).tanh();
+
+            cell = (forget_gate * cell + input_gate * candidate).boxed();
+            hidden = (output_gate * cell.clone().tanh()).boxed();
+            hidden_states.push(hidden.clone());
+        }
+
+        hidden_states
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ContextParameters {
+    embedding: Arc<wyrm::HogwildParameter>,
+    projection: Arc<wyrm::HogwildParameter>,
+}
+
+impl Clone for ContextParameters {
+    fn clone(&self) -> Self {
+        ContextParameters {
+            embedding: Arc::new(self.embedding.as_ref().clone()),
+            projection: Arc::new(self.projection.as_ref().clone()),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Parameters {
     hyper: Hyperparameters,
+    #[serde(default)]
+    num_users: Option<usize>,
+    #[serde(default)]
+    metadata: Option<ModelMetadata>,
     item_embedding: Arc<wyrm::HogwildParameter>,
+    /// Embeddings used to score items, if not tied to `item_embedding`.
+    #[serde(default)]
+    output_embedding: Option<Arc<wyrm::HogwildParameter>>,
     item_biases: Arc<wyrm::HogwildParameter>,
     lstm: nn::lstm::Parameters,
+    /// Replaces `lstm` if layer normalization is enabled.
+    #[serde(default)]
+    layer_norm_lstm: Option<LayerNormLSTM>,
+    projection: Vec<DenseLayer>,
+    context: Option<ContextParameters>,
+    /// Regression head predicting the log time to the next interaction.
+    #[serde(default)]
+    return_time: Option<DenseLayer>,
 }
 
 impl Clone for Parameters {
     fn clone(&self) -> Self {
         Parameters {
             hyper: self.hyper.clone(),
+            num_users: self.num_users,
+            metadata: self.metadata.clone(),
             item_embedding: Arc::new(self.item_embedding.as_ref().clone()),
+            output_embedding: self
+                .output_embedding
+                .as_ref()
+                .map(|embedding| Arc::new(embedding.as_ref().clone())),
             item_biases: Arc::new(self.item_biases.as_ref().clone()),
             lstm: self.lstm.clone(),
+            layer_norm_lstm: self.layer_norm_lstm.clone(),
+            projection: self.projection.clone(),
+            context: self.context.clone(),
+            return_time: self.return_time.clone(),
         }
     }
 }
 
+impl Parameters {
+    /// The embeddings used to score items.
+    fn scoring_embedding(&self) -> &Arc<wyrm::HogwildParameter> {
+        self.output_embedding
+            .as_ref()
+            .unwrap_or(&self.item_embedding)
+    }
+}
+
 impl SequenceModelParameters for Parameters {
     type Output = Model;
+    fn num_items(&self) -> usize {
+        self.hyper.num_items
+    }
+    fn num_users(&mut self) -> &mut Option<usize> {
+        &mut self.num_users
+    }
+    fn metadata(&mut self) -> &mut Option<ModelMetadata> {
+        &mut self.metadata
+    }
+    fn hyperparameters(&self) -> String {
+        serde_json::to_string(&self.hyper).unwrap_or_default()
+    }
     fn max_sequence_length(&self) -> usize {
         self.hyper.max_sequence_length
     }
     fn num_threads(&self) -> usize {
         self.hyper.num_threads
     }
+    fn accumulate_f64(&self) -> bool {
+        self.hyper.accumulate_f64
+    }
     fn rng(&mut self) -> &mut XorShiftRng {
         &mut self.hyper.rng
     }
-    fn optimizer(&self) -> Optimizers {
+    fn negative_sampler(&self) -> Option<&dyn MinibatchSampler> {
+        self.hyper.negative_sampler.as_ref().map(|x| x.as_ref())
+    }
+    fn learning_rate(&self) -> f32 {
+        self.hyper.learning_rate
+    }
+    fn warmup_steps(&self) -> usize {
+        self.hyper.warmup_steps
+    }
+    fn context_cardinality(&self) -> Option<usize> {
+        self.hyper
+            .context_feature
+            .map(|(cardinality, _)| cardinality)
+    }
+    fn sequence_length(&self, epoch: usize) -> usize {
+        curriculum_sequence_length(
+            &self.hyper.curriculum_schedule,
+            epoch,
+            self.hyper.max_sequence_length,
+        )
+    }
+    fn is_cancelled(&self) -> bool {
+        self.hyper.is_cancelled()
+    }
+    fn is_fitted(&self) -> bool {
+        self.num_users.is_some()
+    }
+    fn is_frozen(&self, parameter: &Variable<wyrm::ParameterNode>) -> bool {
+        self.hyper.freeze_embeddings
+            && parameter.value().as_ptr() == self.item_embedding.value().as_ptr()
+    }
+    fn optimizer(&self, learning_rate: f32) -> Optimizers {
         match self.hyper.optimizer {
             Optimizer::Adagrad => Optimizers::Adagrad(
                 wyrm::optim::Adagrad::new()
-                    .learning_rate(self.hyper.learning_rate)
+                    .learning_rate(learning_rate)
                     .l2_penalty(self.hyper.l2_penalty),
             ),
 
             Optimizer::Adam => Optimizers::Adam(
                 wyrm::optim::Adam::new()
-                    .learning_rate(self.hyper.learning_rate)
+                    .learning_rate(learning_rate)
                     .l2_penalty(self.hyper.l2_penalty),
             ),
         }
@@ -269,17 +863,48 @@ impl SequenceModelParameters for Parameters {
             .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
             .collect();
 
-        let input_embeddings: Vec<_> = inputs
-            .iter()
-            .map(|input| item_embeddings.index(input))
-            .collect();
+        let contexts: Vec<_> = if self.context.is_some() {
+            (0..self.hyper.max_sequence_length)
+                .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let time_deltas: Vec<_> = if self.return_time.is_some() {
+            (0..self.hyper.max_sequence_length)
+                .map(|_| wyrm::InputNode::new(Arr::zeros((1, 1))))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let input_embeddings: Vec<_> = match self.context {
+            Some(ref context) => {
+                let context_embeddings = wyrm::ParameterNode::shared(context.embedding.clone());
+                let context_projection = wyrm::ParameterNode::shared(context.projection.clone());
+
+                izip!(inputs.iter(), contexts.iter())
+                    .map(|(input, context)| {
+                        (item_embeddings.index(input)
+                            + context_embeddings.index(context).dot(&context_projection))
+                        .boxed()
+                    })
+                    .collect()
+            }
+            None => inputs
+                .iter()
+                .map(|input| item_embeddings.index(input).boxed())
+                .collect(),
+        };
+        let scoring_embeddings = wyrm::ParameterNode::shared(self.scoring_embedding().clone());
         let negative_embeddings: Vec<_> = negatives
             .iter()
-            .map(|negative| item_embeddings.index(negative))
+            .map(|negative| scoring_embeddings.index(negative))
             .collect();
         let output_embeddings: Vec<_> = outputs
             .iter()
-            .map(|output| item_embeddings.index(output))
+            .map(|output| scoring_embeddings.index(output))
             .collect();
         let output_biases: Vec<_> = outputs
             .iter()
@@ -290,12 +915,41 @@ impl SequenceModelParameters for Parameters {
             .map(|negative| item_biases.index(negative))
             .collect();
 
-        let layer = match self.hyper.lstm_type {
-            LSTMVariant::Normal => self.lstm.build(),
-            LSTMVariant::Coupled => self.lstm.build_coupled(),
+        let mut hidden = match self.layer_norm_lstm {
+            Some(ref layer) => layer.forward(
+                &input_embeddings,
+                self.hyper.lstm_type == LSTMVariant::Coupled,
+            ),
+            None => {
+                let layer = match self.hyper.lstm_type {
+                    LSTMVariant::Normal => self.lstm.build(),
+                    LSTMVariant::Coupled => self.lstm.build_coupled(),
+                };
+
+                layer.forward(&input_embeddings)
+            }
         };
 
-        let hidden = layer.forward(&input_embeddings);
+        let num_layers = self.projection.len();
+        for (layer_idx, dense) in self.projection.iter().enumerate() {
+            let weights = wyrm::ParameterNode::shared(dense.weights.clone());
+            let biases = wyrm::ParameterNode::shared(dense.biases.clone());
+
+            hidden = hidden
+                .into_iter()
+                .map(|hidden_state| {
+                    let output = hidden_state.dot(&weights) + biases.clone();
+
+                    // The last layer maps back to the embedding space
+                    // and is left linear.
+                    if layer_idx + 1 < num_layers {
+                        output.relu().boxed()
+                    } else {
+                        output.boxed()
+                    }
+                })
+                .collect();
+        }
 
         let positive_predictions: Vec<_> =
             izip!(hidden.iter(), output_embeddings.iter(), output_biases)
@@ -319,6 +973,37 @@ impl SequenceModelParameters for Parameters {
             })
             .collect();
 
+        // The time delta of the next step is the gap the hidden state at
+        // this step should predict; the last step has no target.
+        let losses: Vec<_> = match (&self.return_time, self.hyper.return_time_weight) {
+            (Some(head), Some(weight)) => {
+                let weights = wyrm::ParameterNode::shared(head.weights.clone());
+                let biases = wyrm::ParameterNode::shared(head.biases.clone());
+
+                losses
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, loss)| match time_deltas.get(idx + 1) {
+                        Some(next_delta) => {
+                            let error = (hidden[idx].dot(&weights) + biases.clone()
+                                - (1.0 + next_delta.clone()).ln())
+                            .boxed();
+                            (loss + weight * (error.clone() * error)).boxed()
+                        }
+                        None => loss,
+                    })
+                    .collect()
+            }
+            _ => losses,
+        };
+
+        let loss_weights: Vec<_> = (0..losses.len())
+            .map(|_| wyrm::InputNode::new(Arr::ones((1, 1))))
+            .collect();
+        let losses: Vec<_> = izip!(losses, &loss_weights)
+            .map(|(loss, weight)| (loss * weight.clone()).boxed())
+            .collect();
+
         let mut summed_losses = Vec::with_capacity(losses.len());
         summed_losses.push(losses[0].clone());
 
@@ -331,29 +1016,43 @@ impl SequenceModelParameters for Parameters {
             inputs,
             outputs,
             negatives,
+            contexts,
+            time_deltas,
+            loss_weights,
             hidden_states: hidden,
             summed_losses,
         }
     }
     fn predict_single(&self, user: &[f32], item_idx: usize) -> f32 {
-        let item_embeddings = &self.item_embedding;
+        let item_embeddings = self.scoring_embedding();
         let item_biases = &self.item_biases;
 
         let embeddings = item_embeddings.value();
         let biases = item_biases.value();
 
-        let embedding = embeddings.subview(Axis(0), item_idx);
+        let embedding = embeddings.row(item_idx);
         let bias = biases[(item_idx, 0)];
         let dot = wyrm::simd_dot(user, embedding.as_slice().unwrap());
 
         bias + dot
     }
+    fn predict_all(&self, user: &[f32], num_items: usize) -> Vec<f32> {
+        score_all_items(
+            self.scoring_embedding().value(),
+            self.item_biases.value(),
+            user,
+            num_items,
+        )
+    }
 }
 
 struct Model {
     inputs: Vec<Variable<wyrm::IndexInputNode>>,
     outputs: Vec<Variable<wyrm::IndexInputNode>>,
     negatives: Vec<Variable<wyrm::IndexInputNode>>,
+    contexts: Vec<Variable<wyrm::IndexInputNode>>,
+    time_deltas: Vec<Variable<wyrm::InputNode>>,
+    loss_weights: Vec<Variable<wyrm::InputNode>>,
     hidden_states: Vec<Variable<BoxedNode>>,
     summed_losses: Vec<Variable<BoxedNode>>,
 }
@@ -380,6 +1079,62 @@ impl SequenceModel for Model {
     fn hidden_states(&mut self) -> &mut [Variable<BoxedNode>] {
         &mut self.hidden_states
     }
+    fn contexts(&self) -> &[Variable<wyrm::IndexInputNode>] {
+        &self.contexts
+    }
+    fn time_deltas(&self) -> &[Variable<wyrm::InputNode>] {
+        &self.time_deltas
+    }
+    fn dropout_masks(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn weights(&self) -> &[Variable<wyrm::InputNode>] {
+        &self.loss_weights
+    }
+    fn set_users(&self, _user_ids: &[usize]) {}
+}
+
+/// Check that a model with hyperparameters `hyper` can be fitted on
+/// `interactions`, before any work is done.
+#[cfg(feature = "training")]
+fn validate_inputs(
+    hyper: &Hyperparameters,
+    interactions: &CompressedInteractions,
+) -> Result<(), FittingError> {
+    if hyper.is_cancelled() {
+        return Err(FittingError::Cancelled);
+    }
+    if hyper.item_embedding_dim == 0 {
+        return Err(invalid_hyperparameter(
+            "embedding_dim",
+            hyper.item_embedding_dim,
+        ));
+    }
+    if hyper.max_sequence_length == 0 {
+        return Err(invalid_hyperparameter(
+            "max_sequence_length",
+            hyper.max_sequence_length,
+        ));
+    }
+    if !(hyper.learning_rate.is_finite() && hyper.learning_rate > 0.0) {
+        return Err(invalid_hyperparameter("learning_rate", hyper.learning_rate));
+    }
+    if hyper.num_threads == 0 {
+        return Err(invalid_hyperparameter("num_threads", hyper.num_threads));
+    }
+    if let Some(ref matrix) = hyper.pretrained_embeddings {
+        return Err(invalid_hyperparameter(
+            "pretrained_embeddings",
+            format!(
+                "{} rows, expected ({}, {})",
+                matrix.len(),
+                hyper.num_items,
+                hyper.item_embedding_dim
+            ),
+        ));
+    }
+
+    validate_interactions(interactions, hyper.num_items)
 }
 
 /// An LSTM-based sequence model for implicit feedback.
@@ -392,9 +1147,318 @@ impl ImplicitLSTMModel {
     /// Fit the model.
     ///
     /// Returns the loss value.
+    #[cfg(feature = "training")]
     pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<f32, FittingError> {
+        validate_inputs(&self.params.hyper, interactions)?;
         fit_sequence_model(interactions, &mut self.params)
     }
+
+    /// Return whether the model has been successfully fitted at least once.
+    /// Inference on an unfitted model returns
+    /// [PredictionError::ModelNotFitted].
+    pub fn is_fitted(&self) -> bool {
+        self.params.is_fitted()
+    }
+
+    /// Return the record of the last successful fit, or `None` if the
+    /// model has not been fitted. See [ModelMetadata].
+    pub fn metadata(&self) -> Option<&ModelMetadata> {
+        self.params.metadata.as_ref()
+    }
+
+    /// Return the record of the last successful fit mutably, for example
+    /// to add tags.
+    pub fn metadata_mut(&mut self) -> Option<&mut ModelMetadata> {
+        self.params.metadata.as_mut()
+    }
+
+    /// Return the number of items the model scores.
+    pub fn num_items(&self) -> usize {
+        self.params.hyper.num_items
+    }
+
+    /// Export the model as a versioned JSON document, with the
+    /// hyperparameters in plain JSON and the parameters as exact
+    /// base64-encoded blobs. See [crate::models::json].
+    pub fn to_json(&self) -> Result<String, ExportError> {
+        json::to_json(self, "lstm")
+    }
+
+    /// Read a model exported with [ImplicitLSTMModel::to_json].
+    pub fn from_json(json: &str) -> Result<Self, ExportError> {
+        json::from_json(json, "lstm")
+    }
+
+    /// Save the model in the versioned binary format of
+    /// [crate::models::format].
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
+        format::write_model(self, "lstm", writer)
+    }
+
+    /// Load a model saved with [ImplicitLSTMModel::write], by this or an older
+    /// version of the crate.
+    pub fn read<R: Read>(reader: R) -> Result<Self, ModelLoadError> {
+        format::read_model(reader, "lstm")
+    }
+
+    /// Compute the global L2 norm of the gradient of the training loss on
+    /// the sequences of the users in `batch`, without updating the model.
+    ///
+    /// Each user contributes their most recent interactions in
+    /// `interactions`, up to the maximum sequence length, as in training.
+    /// Useful for choosing gradient clipping thresholds and diagnosing
+    /// vanishing or exploding gradients.
+    #[cfg(feature = "training")]
+    pub fn gradient_norm(
+        &self,
+        batch: &TripletMinibatch,
+        interactions: &CompressedInteractions,
+    ) -> Result<f32, FittingError> {
+        if interactions.num_items() != self.params.hyper.num_items {
+            return Err(FittingError::ItemCountMismatch {
+                model_items: self.params.hyper.num_items,
+                data_items: interactions.num_items(),
+            });
+        }
+
+        let mut user_ids = batch.user_ids.to_vec();
+        user_ids.sort_unstable();
+        user_ids.dedup();
+
+        let sequences: Vec<_> = user_ids
+            .into_iter()
+            .filter_map(|user_id| interactions.get_user(user_id))
+            .map(|user| user.most_recent(self.params.hyper.max_sequence_length))
+            .filter(|user| user.len() > 2)
+            .collect();
+
+        if sequences.is_empty() {
+            return Err(FittingError::NoInteractions);
+        }
+
+        let mut rng = self.params.hyper.rng.clone();
+
+        Ok(sequence_gradient_norm(&self.params, &sequences, &mut rng))
+    }
+
+    /// Compute the perplexity of the model on `interactions`: the
+    /// exponent of the negative mean log-probability of each observed next
+    /// item, with item probabilities given by the softmax of the scores of
+    /// all items. Every item but the first of each user is predicted from
+    /// the items preceding it.
+    ///
+    /// A perfect model has a perplexity of 1, and one that scores all
+    /// items equally a perplexity of the number of items.
+    #[cfg(feature = "training")]
+    pub fn perplexity(
+        &self,
+        interactions: &CompressedInteractions,
+    ) -> Result<f32, PredictionError> {
+        sequence_perplexity(&self.params, interactions)
+    }
+
+    /// Score only the `candidates` and return the `k` highest-scoring
+    /// `(item_id, score)` pairs, best first.
+    ///
+    /// Only the candidates' embedding rows are read, so this is suitable
+    /// for serving very large catalogs behind a coarse candidate generator.
+    pub fn predict_candidates(
+        &self,
+        user: &ImplicitUser,
+        candidates: &[ItemId],
+        k: usize,
+    ) -> Result<Vec<(ItemId, f32)>, PredictionError> {
+        predict_candidates(&self.params, user, candidates, k)
+    }
+
+    /// Compute a user representation from past interactions and the
+    /// context features of each of those interactions.
+    ///
+    /// `contexts` must be aligned with `item_ids`, or
+    /// [PredictionError::ContextLengthMismatch] is returned, and every
+    /// context must be smaller than the cardinality of the context feature,
+    /// or [PredictionError::ContextOutOfRange] is returned. Models built
+    /// without [Hyperparameters::context_feature] ignore the contexts.
+    pub fn user_representation_with_context(
+        &self,
+        item_ids: &[ItemId],
+        contexts: &[usize],
+    ) -> Result<ImplicitUser, PredictionError> {
+        sequence_representation(&self.params, item_ids, Some(contexts), None)
+    }
+
+    /// Compute the representations of several users at once, as
+    /// [ImplicitLSTMModel::user_representation_with_context] does for
+    /// each history and its contexts.
+    pub fn user_representations_with_context(
+        &self,
+        histories: &[&[ItemId]],
+        contexts: &[&[usize]],
+    ) -> Result<Vec<ImplicitUser>, PredictionError> {
+        sequence_representations(&self.params, histories, Some(contexts))
+    }
+
+    /// Predict the time until the next interaction of `user`, in the
+    /// units of the training timestamps.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the model was built without
+    /// [Hyperparameters::predict_return_time].
+    pub fn predict_return_time(&self, user: &ImplicitUser) -> Result<f32, PredictionError> {
+        let head = self
+            .params
+            .return_time
+            .as_ref()
+            .expect("The model was built without a return time head.");
+
+        if !self.is_fitted() {
+            return Err(PredictionError::ModelNotFitted);
+        }
+
+        let log_gap = head.biases.value()[(0, 0)]
+            + user
+                .user_embedding
+                .iter()
+                .zip(head.weights.value().iter())
+                .map(|(x, weight)| x * weight)
+                .sum::<f32>();
+        let gap = log_gap.exp() - 1.0;
+
+        if gap.is_finite() {
+            Ok(gap.max(0.0))
+        } else {
+            Err(PredictionError::InvalidPredictionValue)
+        }
+    }
+
+    /// Roll out a plausible continuation of `history`, `steps` items long.
+    ///
+    /// At each step all items are scored and the next item is sampled from
+    /// the softmax of the scores divided by `temperature`; a temperature of 0
+    /// always picks the highest-scoring item. If `forbid_repeats` is true,
+    /// items already in the history or the continuation are never picked,
+    /// and the rollout stops early if the catalog is exhausted.
+    ///
+    /// While the sequence is shorter than the maximum sequence length, each
+    /// step only runs the LSTM over the newly appended item rather than
+    /// recomputing the representation from the whole sequence.
+    pub fn generate<R: Rng>(
+        &self,
+        history: &[ItemId],
+        steps: usize,
+        rng: &mut R,
+        temperature: f32,
+        forbid_repeats: bool,
+    ) -> Result<Vec<ItemId>, PredictionError> {
+        let num_items = self.params.hyper.num_items;
+
+        sequence_rollout(&self.params, history, steps, |user, sequence| {
+            let mut scores = self.predict_all_items(user, num_items)?;
+
+            if forbid_repeats {
+                for &item_id in sequence {
+                    scores[item_id.index()] = std::f32::NEG_INFINITY;
+                }
+            }
+
+            let (best_item, &best_score) = match scores
+                .iter()
+                .enumerate()
+                .max_by(|x, y| x.1.partial_cmp(y.1).unwrap())
+            {
+                Some(best) if *best.1 > std::f32::NEG_INFINITY => best,
+                _ => return Ok(None),
+            };
+
+            let next_item = if temperature <= 0.0 {
+                best_item
+            } else {
+                let weights: Vec<f32> = scores
+                    .iter()
+                    .map(|score| ((score - best_score) / temperature).exp())
+                    .collect();
+                let mut threshold = rng.gen::<f32>() * weights.iter().sum::<f32>();
+
+                weights
+                    .iter()
+                    .position(|weight| {
+                        threshold -= weight;
+                        threshold <= 0.0
+                    })
+                    .unwrap_or(best_item)
+            };
+
+            Ok(Some(ItemId::from_index(next_item)))
+        })
+    }
+
+    /// Return a human-readable summary of the model's parameters
+    /// and their shapes.
+    pub fn summary(&self) -> String {
+        let hyper = &self.params.hyper;
+        let mut lines = vec![
+            format!(
+                "item_embedding: {:?}",
+                self.params.item_embedding.value().shape()
+            ),
+            format!("item_biases: {:?}", self.params.item_biases.value().shape()),
+            format!(
+                "lstm ({:?}{}): [{}, {}]",
+                hyper.lstm_type,
+                if hyper.use_layer_norm {
+                    ", layer norm"
+                } else {
+                    ""
+                },
+                hyper.item_embedding_dim,
+                hyper.item_embedding_dim
+            ),
+        ];
+
+        if let Some(ref output_embedding) = self.params.output_embedding {
+            lines.insert(
+                1,
+                format!("output_embedding: {:?}", output_embedding.value().shape()),
+            );
+        }
+
+        if let Some(ref context) = self.params.context {
+            lines.push(format!(
+                "context: embedding {:?}, projection {:?}",
+                context.embedding.value().shape(),
+                context.projection.value().shape()
+            ));
+        }
+
+        for (idx, layer) in self.params.projection.iter().enumerate() {
+            lines.push(format!(
+                "projection_{}: weights {:?}, biases {:?}",
+                idx,
+                layer.weights.value().shape(),
+                layer.biases.value().shape()
+            ));
+        }
+
+        if let Some(ref head) = self.params.return_time {
+            lines.push(format!(
+                "return_time: weights {:?}, biases {:?}",
+                head.weights.value().shape(),
+                head.biases.value().shape()
+            ));
+        }
+
+        lines.join("\n")
+    }
+}
+
+impl ItemEmbeddings for ImplicitLSTMModel {
+    fn item_embeddings(&self) -> Array2<f32> {
+        scoring_vectors(
+            self.params.scoring_embedding().value(),
+            self.params.item_biases.value(),
+        )
+    }
 }
 
 impl OnlineRankingModel for ImplicitLSTMModel {
@@ -406,6 +1470,13 @@ impl OnlineRankingModel for ImplicitLSTMModel {
         self.params.user_representation(item_ids)
     }
 
+    fn user_representations(
+        &self,
+        histories: &[&[ItemId]],
+    ) -> Result<Vec<Self::UserRepresentation>, PredictionError> {
+        self.params.user_representations(histories)
+    }
+
     fn predict(
         &self,
         user: &Self::UserRepresentation,
@@ -413,17 +1484,40 @@ impl OnlineRankingModel for ImplicitLSTMModel {
     ) -> Result<Vec<f32>, PredictionError> {
         self.params.predict(user, item_ids)
     }
+
+    fn predict_into(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+        out: &mut [f32],
+    ) -> Result<(), PredictionError> {
+        self.params.predict_into(user, item_ids, out)
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.params.predict_all_items(user, num_items)
+    }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "training"))]
 mod tests {
+    #[cfg(feature = "datasets")]
     use std::time::Instant;
 
     use super::*;
-    use crate::data::{user_based_split, Interactions};
+    #[cfg(feature = "datasets")]
+    use crate::data::user_based_split;
+    use crate::data::{synthetic_interactions, Interaction, Interactions};
+    #[cfg(feature = "datasets")]
     use crate::datasets::download_movielens_100k;
     use crate::evaluation::mrr_score;
+    use crate::ids::{items, ItemId, Timestamp, UserId};
 
+    #[cfg(feature = "datasets")]
     fn run_test(mut data: Interactions, hyperparameters: Hyperparameters) -> (f32, f32) {
         let mut rng = rand::XorShiftRng::from_seed([42; 16]);
 
@@ -447,6 +1541,7 @@ mod tests {
         (test_mrr, train_mrr)
     }
 
+    #[cfg(feature = "datasets")]
     #[async_std::test]
     async fn mrr_test_single_thread() {
         let data = download_movielens_100k().await.unwrap();
@@ -471,6 +1566,7 @@ mod tests {
         assert!(test_mrr > expected_mrr)
     }
 
+    #[cfg(feature = "datasets")]
     #[async_std::test]
     async fn mrr_test_two_threads() {
         let data = download_movielens_100k().await.unwrap();
@@ -495,6 +1591,7 @@ mod tests {
         assert!(test_mrr > expected_mrr)
     }
 
+    #[cfg(feature = "datasets")]
     #[async_std::test]
     async fn mrr_test_warp() {
         let data = download_movielens_100k().await.unwrap();
@@ -529,4 +1626,877 @@ mod tests {
         }
     }
 
+    #[test]
+    fn item_count_mismatch() {
+        let data = Interactions::new(100, 200).to_compressed();
+        let mut model = Hyperparameters::new(100, 100).build();
+        match model.fit(&data) {
+            Err(FittingError::ItemCountMismatch {
+                model_items: 100,
+                data_items: 200,
+            }) => {}
+            _ => panic!("No error returned."),
+        }
+    }
+
+    #[test]
+    fn user_count_mismatch() {
+        let data = synthetic_interactions(20, 20, 10);
+        let mut model = Hyperparameters::new(20, 10)
+            .num_epochs(1)
+            .num_threads(1)
+            .build();
+        model.fit(&data.to_compressed()).unwrap();
+
+        let more_users = Interactions::from(
+            data.data()
+                .iter()
+                .cloned()
+                .chain(Some(Interaction::new(UserId(29), ItemId(0), Timestamp(0))))
+                .collect::<Vec<_>>(),
+        );
+
+        match model.fit(&more_users.to_compressed()) {
+            Err(FittingError::UserCountMismatch {
+                model_users: 20,
+                data_users: 30,
+            }) => {}
+            _ => panic!("No error returned."),
+        }
+    }
+
+    /// Mark a freshly built `model` as fitted, so that inference uses its
+    /// initial parameters.
+    fn assume_fitted(mut model: ImplicitLSTMModel) -> ImplicitLSTMModel {
+        model.params.num_users = Some(0);
+        model
+    }
+
+    #[test]
+    fn context_feature() {
+        let num_users = 100;
+        let num_items = 20;
+        let sequence_length = 15;
+
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+        let mut interactions = Vec::new();
+        for user_id in 0..num_users {
+            let mut item_id = user_id % num_items;
+            for timestamp in 0..sequence_length {
+                // The next item depends on the context of the current one,
+                // which cannot be told from the history.
+                let context = rng.gen_range(0, 2);
+                interactions.push(
+                    Interaction::new(
+                        UserId::from_index(user_id),
+                        ItemId::from_index(item_id),
+                        Timestamp(timestamp as i64),
+                    )
+                    .with_context(context),
+                );
+                item_id = (item_id + 1 + context * 5) % num_items;
+            }
+        }
+        let data = Interactions::from(interactions).to_compressed();
+
+        let accuracy = |model: &ImplicitLSTMModel| {
+            let mut hits = 0;
+            let mut total = 0;
+            for user in data.iter_users() {
+                let contexts = user.contexts.unwrap();
+                for idx in 1..user.len() {
+                    let representation = model
+                        .user_representation_with_context(&user.item_ids[..idx], &contexts[..idx])
+                        .unwrap();
+                    let items: Vec<_> = (0..num_items).map(ItemId::from_index).collect();
+                    let best = model
+                        .predict_candidates(&representation, &items, 1)
+                        .unwrap();
+                    if best[0].0 == user.item_ids[idx] {
+                        hits += 1;
+                    }
+                    total += 1;
+                }
+            }
+            hits as f32 / total as f32
+        };
+
+        let hyperparameters = Hyperparameters::new(num_items, sequence_length)
+            .embedding_dim(16)
+            .learning_rate(0.1)
+            .loss(Loss::Hinge)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(30)
+            .num_threads(1)
+            .from_seed([42; 16]);
+
+        let mut with_context = hyperparameters.clone().context_feature(2, 4).build();
+        let mut without_context = hyperparameters.build();
+        with_context.fit(&data).unwrap();
+        without_context.fit(&data).unwrap();
+
+        assert!(accuracy(&with_context) > accuracy(&without_context));
+    }
+
+    #[test]
+    fn context_inputs() {
+        let model = assume_fitted(
+            Hyperparameters::new(20, 10)
+                .embedding_dim(8)
+                .context_feature(2, 4)
+                .from_seed([42; 16])
+                .build(),
+        );
+        let history = items(&[1, 2, 3]);
+
+        match model.user_representation_with_context(&history, &[0, 1]) {
+            Err(PredictionError::ContextLengthMismatch {
+                num_items: 3,
+                num_contexts: 2,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match model.user_representation_with_context(&history, &[0, 2, 1]) {
+            Err(PredictionError::ContextOutOfRange {
+                context: 2,
+                cardinality: 2,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // Without contexts, every step uses context 0, whatever the
+        // graph was last used with.
+        let zeros = model
+            .user_representation_with_context(&history, &[0, 0, 0])
+            .unwrap();
+        let ones = model
+            .user_representation_with_context(&history, &[1, 1, 1])
+            .unwrap();
+        assert_ne!(zeros.user_embedding, ones.user_embedding);
+
+        let histories = vec![&history[..], &history[..], &history[..]];
+        let batched = model
+            .user_representations_with_context(&histories, &[&[1, 1, 1], &[0, 0, 0], &[1, 1, 1]])
+            .unwrap();
+        assert_eq!(batched[0].user_embedding, ones.user_embedding);
+        assert_eq!(batched[1].user_embedding, zeros.user_embedding);
+        assert_eq!(batched[2].user_embedding, ones.user_embedding);
+
+        let batched = model
+            .user_representations(&[&history[..], &history[..]])
+            .unwrap();
+        assert!(batched
+            .iter()
+            .all(|user| user.user_embedding == zeros.user_embedding));
+
+        match model.user_representations_with_context(&histories, &[&[0, 0, 0]]) {
+            Err(PredictionError::ContextLengthMismatch {
+                num_items: 3,
+                num_contexts: 1,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn pretrained_embeddings() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+        let pretrained: Vec<Vec<f32>> = (0..20)
+            .map(|row| (0..8).map(|col| (row * 8 + col) as f32 / 160.0).collect())
+            .collect();
+
+        let hyperparameters = Hyperparameters::new(20, 10)
+            .embedding_dim(8)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16]);
+
+        let mut frozen = hyperparameters
+            .clone()
+            .pretrained_embeddings(pretrained.clone(), true)
+            .build();
+        let mut unfrozen = hyperparameters
+            .pretrained_embeddings(pretrained.clone(), false)
+            .build();
+
+        frozen.fit(&data).unwrap();
+        unfrozen.fit(&data).unwrap();
+
+        let embeddings = |model: &ImplicitLSTMModel| {
+            let value = model.params.item_embedding.value();
+            (0..20)
+                .map(|row| (0..8).map(|col| value[(row, col)]).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(embeddings(&frozen), pretrained);
+        assert_ne!(embeddings(&unfrozen), pretrained);
+    }
+
+    #[test]
+    fn pretrained_embeddings_shape_mismatch() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+        let mut model = Hyperparameters::new(20, 10)
+            .embedding_dim(8)
+            .pretrained_embeddings(vec![vec![0.0; 8]; 19], false)
+            .build();
+
+        match model.fit(&data) {
+            Err(FittingError::InvalidHyperparameter { name, .. }) => {
+                assert_eq!(name, "pretrained_embeddings")
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn generate() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+        let mut model = Hyperparameters::new(20, 10)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+
+        let history = items(&[1, 2, 3]);
+
+        let greedy = model.generate(&history, 5, &mut rng, 0.0, false).unwrap();
+        assert_eq!(greedy.len(), 5);
+        assert_eq!(
+            greedy,
+            model.generate(&history, 5, &mut rng, 0.0, false).unwrap()
+        );
+
+        let sampled = model.generate(&history, 15, &mut rng, 1.0, true).unwrap();
+        assert_eq!(sampled.len(), 15);
+        assert!(sampled.iter().all(|item_id| item_id.index() < 20));
+        assert!(sampled.iter().all(|item_id| !history.contains(item_id)));
+
+        let exhausted = model.generate(&history, 30, &mut rng, 1.0, true).unwrap();
+        assert_eq!(exhausted.len(), 17);
+
+        // Greedy generation past the maximum sequence length matches
+        // recomputing the representation from the full sequence.
+        let mut sequence = history.clone();
+        for _ in 0..15 {
+            let user = model.user_representation(&sequence).unwrap();
+            let scores = model.predict_all_items(&user, 20).unwrap();
+            let best_item = scores
+                .iter()
+                .enumerate()
+                .max_by(|x, y| x.1.partial_cmp(y.1).unwrap())
+                .unwrap()
+                .0;
+            sequence.push(ItemId::from_index(best_item));
+        }
+
+        assert_eq!(
+            model.generate(&history, 15, &mut rng, 0.0, false).unwrap(),
+            &sequence[history.len()..]
+        );
+    }
+
+    #[test]
+    fn curriculum_schedule() {
+        let schedule = [(2, 4), (5, 8), (10, 64)];
+
+        let lengths: Vec<_> = (0..12)
+            .map(|epoch| curriculum_sequence_length(&schedule, epoch, 32))
+            .collect();
+
+        assert_eq!(lengths, vec![4, 4, 4, 4, 4, 8, 8, 8, 8, 8, 32, 32]);
+        assert_eq!(curriculum_sequence_length(&[], 3, 32), 32);
+
+        let data = synthetic_interactions(20, 10, 20).to_compressed();
+        let mut model = Hyperparameters::new(10, 20)
+            .curriculum_schedule(vec![(2, 20), (0, 5)])
+            .num_epochs(3)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+
+        assert!(model.fit(&data).unwrap().is_finite());
+    }
+
+    #[test]
+    fn layer_norm() {
+        let data = synthetic_interactions(50, 20, 10).to_compressed();
+
+        let fit = |use_layer_norm: bool| {
+            let mut model = Hyperparameters::new(20, 10)
+                .embedding_dim(8)
+                .learning_rate(0.05)
+                .lstm_variant(LSTMVariant::Normal)
+                .use_layer_norm(use_layer_norm)
+                .num_epochs(10)
+                .num_threads(1)
+                .from_seed([42; 16])
+                .build();
+            let loss = model.fit(&data).unwrap();
+            (model, loss)
+        };
+
+        let (_, plain_loss) = fit(false);
+        let (model, layer_norm_loss) = fit(true);
+
+        assert!(model
+            .summary()
+            .contains("lstm (Normal, layer norm): [8, 8]"));
+        assert!(layer_norm_loss.is_finite());
+        assert!(layer_norm_loss < plain_loss);
+
+        // Gains and shifts are trained along with the other parameters.
+        let gate = &model.params.layer_norm_lstm.as_ref().unwrap().output_gate;
+        assert!(gate.input_gain.value().iter().any(|&x| x != 1.0));
+        assert!(gate.recurrent_shift.value().iter().any(|&x| x != 0.0));
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let serialized = bincode::serialize(&model).unwrap();
+        let deserialized: ImplicitLSTMModel = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(
+            deserialized
+                .user_representation(&items(&[1, 2, 3]))
+                .unwrap()
+                .user_embedding,
+            user.user_embedding
+        );
+    }
+
+    #[test]
+    fn untied_embeddings() {
+        let tied = Hyperparameters::new(20, 10).build();
+        assert!(tied.params.output_embedding.is_none());
+
+        let mut model = Hyperparameters::new(20, 10)
+            .embedding_dim(8)
+            .tied_embeddings(false)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        assert!(model.summary().contains("output_embedding: [20, 8]"));
+
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+        let input_before = model.params.item_embedding.value().to_owned();
+        let output_before = model
+            .params
+            .output_embedding
+            .as_ref()
+            .unwrap()
+            .value()
+            .to_owned();
+
+        assert!(model.fit(&data).unwrap().is_finite());
+        assert!(*model.params.item_embedding.value() != input_before);
+        assert!(*model.params.output_embedding.as_ref().unwrap().value() != output_before);
+
+        // Scores come from the output embeddings.
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let output_embedding = model.params.output_embedding.as_ref().unwrap().value();
+        let expected = wyrm::simd_dot(
+            &user.user_embedding,
+            output_embedding.row(5).as_slice().unwrap(),
+        ) + model.params.item_biases.value()[(5, 0)];
+        assert!((model.predict(&user, &items(&[5])).unwrap()[0] - expected).abs() < 1e-5);
+
+        let serialized = bincode::serialize(&model).unwrap();
+        let deserialized: ImplicitLSTMModel = bincode::deserialize(&serialized).unwrap();
+        assert!(deserialized.params.output_embedding.is_some());
+    }
+
+    #[test]
+    fn projection_shapes() {
+        let model = assume_fitted(
+            Hyperparameters::new(50, 10)
+                .embedding_dim(8)
+                .projection_dims(vec![16, 4])
+                .build(),
+        );
+
+        let shapes: Vec<_> = model
+            .params
+            .projection
+            .iter()
+            .map(|layer| {
+                (
+                    layer.weights.value().shape().to_owned(),
+                    layer.biases.value().shape().to_owned(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            shapes,
+            vec![
+                (vec![8, 16], vec![1, 16]),
+                (vec![16, 4], vec![1, 4]),
+                (vec![4, 8], vec![1, 8]),
+            ]
+        );
+
+        let summary = model.summary();
+        for idx in 0..3 {
+            assert!(summary.contains(&format!("projection_{}", idx)));
+        }
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        assert_eq!(user.user_embedding.len(), 8);
+    }
+
+    #[test]
+    fn projection_serialization() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+        let mut model = Hyperparameters::new(20, 10)
+            .embedding_dim(8)
+            .projection_dims(vec![16])
+            .num_epochs(1)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+
+        let serialized = bincode::serialize(&model).unwrap();
+        let deserialized: ImplicitLSTMModel = bincode::deserialize(&serialized).unwrap();
+
+        assert_eq!(deserialized.params.projection.len(), 2);
+
+        let item_ids: Vec<_> = (0..20).map(ItemId).collect();
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let deserialized_user = deserialized
+            .user_representation(&items(&[1, 2, 3]))
+            .unwrap();
+
+        assert_eq!(
+            model.predict(&user, &item_ids).unwrap(),
+            deserialized.predict(&deserialized_user, &item_ids).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_export() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+        let mut model = Hyperparameters::new(20, 10)
+            .embedding_dim(8)
+            .projection_dims(vec![16])
+            .num_epochs(1)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+
+        let json = model.to_json().unwrap();
+        let deserialized = ImplicitLSTMModel::from_json(&json).unwrap();
+        assert!(deserialized.is_fitted());
+
+        let item_ids: Vec<_> = (0..20).map(ItemId).collect();
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let deserialized_user = deserialized
+            .user_representation(&items(&[1, 2, 3]))
+            .unwrap();
+
+        assert_eq!(
+            model.predict(&user, &item_ids).unwrap(),
+            deserialized.predict(&deserialized_user, &item_ids).unwrap()
+        );
+
+        let tampered = json.replacen("\"format_version\": 1", "\"format_version\": 7", 1);
+        match ImplicitLSTMModel::from_json(&tampered) {
+            Err(ExportError::UnsupportedVersion { version: 7 }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        match crate::models::ewma::ImplicitEWMAModel::from_json(&json) {
+            Err(ExportError::WrongModel { .. }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn projection_trains() {
+        let data = synthetic_interactions(50, 20, 20).to_compressed();
+        let mut model = Hyperparameters::new(20, 20)
+            .embedding_dim(16)
+            .projection_dims(vec![16])
+            .learning_rate(0.1)
+            .loss(Loss::Hinge)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(20)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+
+        let loss = model.fit(&data).unwrap();
+        let mrr = mrr_score(&model, &data).unwrap();
+
+        assert!(loss.is_finite());
+        assert!(mrr > 0.2);
+    }
+
+    #[test]
+    fn interaction_weights() {
+        let interactions = synthetic_interactions(50, 20, 20);
+        let hyperparameters = Hyperparameters::new(20, 20)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16]);
+
+        let reweighted = |weight: f32| {
+            let mut weighted = Interactions::new(interactions.num_users(), 20);
+            for interaction in interactions.data() {
+                weighted.push(interaction.clone().with_weight(weight));
+            }
+            weighted.to_compressed()
+        };
+
+        // Unit weights are the same as no weights.
+        let unweighted_loss = hyperparameters
+            .clone()
+            .build()
+            .fit(&interactions.to_compressed())
+            .unwrap();
+        let unit_loss = hyperparameters
+            .clone()
+            .build()
+            .fit(&reweighted(1.0))
+            .unwrap();
+        assert_eq!(unweighted_loss, unit_loss);
+
+        // Zero-weight interactions contribute nothing.
+        let mut model = hyperparameters.build();
+        let initial = model.params.item_embedding.value().clone();
+        assert_eq!(model.fit(&reweighted(0.0)).unwrap(), 0.0);
+        assert_eq!(*model.params.item_embedding.value(), initial);
+    }
+
+    #[test]
+    fn gradient_norm() {
+        let interactions = synthetic_interactions(50, 20, 20);
+        let data = interactions.to_compressed();
+        let triplets = interactions.to_triplet();
+        let batch = triplets.iter_minibatch(64).next().unwrap();
+
+        let model = assume_fitted(
+            Hyperparameters::new(20, 20)
+                .embedding_dim(16)
+                .num_threads(1)
+                .from_seed([42; 16])
+                .build(),
+        );
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let predictions = model.predict(&user, &items(&[0, 5, 10])).unwrap();
+
+        let norm = model.gradient_norm(&batch, &data).unwrap();
+        assert!(norm.is_finite() && norm > 0.0);
+        assert_eq!(model.gradient_norm(&batch, &data).unwrap(), norm);
+
+        // The gradients are not applied.
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        assert_eq!(
+            model.predict(&user, &items(&[0, 5, 10])).unwrap(),
+            predictions
+        );
+
+        let other_items = Interactions::new(50, 30).to_compressed();
+        assert!(model.gradient_norm(&batch, &other_items).is_err());
+    }
+
+    #[test]
+    fn return_time() {
+        // Two populations of users with their own items and their own
+        // (constant) gaps between interactions.
+        let mut interactions = Vec::new();
+        for user_id in 0..60 {
+            let (offset, gap) = if user_id % 2 == 0 { (0, 2) } else { (10, 50) };
+            for step in 0..10 {
+                let item_id = offset + (user_id + step) % 10;
+                interactions.push(Interaction::new(
+                    UserId(user_id),
+                    ItemId(item_id),
+                    Timestamp((step * gap) as i64),
+                ));
+            }
+        }
+        let data = Interactions::from(interactions).to_compressed();
+
+        let hyperparameters = Hyperparameters::new(20, 10)
+            .embedding_dim(16)
+            .learning_rate(0.1)
+            .loss(Loss::Hinge)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(20)
+            .num_threads(1)
+            .from_seed([42; 16]);
+
+        let mut plain = hyperparameters.clone().build();
+        let mut multi_task = hyperparameters.predict_return_time(0.5).build();
+        assert!(multi_task
+            .summary()
+            .contains("return_time: weights [16, 1]"));
+
+        plain.fit(&data).unwrap();
+        assert!(multi_task.fit(&data).unwrap().is_finite());
+
+        let short_gaps = multi_task
+            .user_representation(&items(&[0, 1, 2, 3]))
+            .unwrap();
+        let long_gaps = multi_task
+            .user_representation(&items(&[10, 11, 12, 13]))
+            .unwrap();
+        let short_gap = multi_task.predict_return_time(&short_gaps).unwrap();
+        let long_gap = multi_task.predict_return_time(&long_gaps).unwrap();
+
+        assert!(short_gap > 0.5 && short_gap < 8.0);
+        assert!(long_gap > 15.0 && long_gap < 150.0);
+
+        assert!(mrr_score(&multi_task, &data).unwrap() > 0.9 * mrr_score(&plain, &data).unwrap());
+
+        let serialized = bincode::serialize(&multi_task).unwrap();
+        let deserialized: ImplicitLSTMModel = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(
+            deserialized.predict_return_time(&long_gaps).unwrap(),
+            long_gap
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The model was built without a return time head.")]
+    fn return_time_without_head() {
+        let model = assume_fitted(Hyperparameters::new(20, 10).build());
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let _ = model.predict_return_time(&user);
+    }
+
+    #[test]
+    fn batched_user_representations() {
+        let model = assume_fitted(Hyperparameters::new(20, 10).from_seed([42; 16]).build());
+
+        // Shorter histories follow longer ones, and the longest is truncated.
+        let histories = vec![
+            items(&[1, 2, 3, 4, 5]),
+            items(&[6]),
+            items(&[7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18]),
+            items(&[3, 2]),
+        ];
+        let histories: Vec<&[ItemId]> = histories.iter().map(|x| &x[..]).collect();
+
+        let batched = model.user_representations(&histories).unwrap();
+        assert_eq!(batched.len(), histories.len());
+
+        for (history, user) in izip!(histories, batched) {
+            let expected = model.user_representation(history).unwrap();
+            assert_eq!(user.user_embedding, expected.user_embedding);
+            assert_eq!(user.num_interactions, expected.num_interactions);
+        }
+    }
+
+    #[test]
+    fn predict_into() {
+        let model = assume_fitted(Hyperparameters::new(20, 10).from_seed([42; 16]).build());
+        let item_ids: Vec<_> = (0..20).rev().map(ItemId).collect();
+
+        // The same buffer is reused across users.
+        let mut out = vec![0.0; item_ids.len()];
+        for history in &[items(&[1, 2, 3]), items(&[4]), items(&[5, 6])] {
+            let user = model.user_representation(history).unwrap();
+            model.predict_into(&user, &item_ids, &mut out).unwrap();
+            assert_eq!(out, model.predict(&user, &item_ids).unwrap());
+        }
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        match model.predict_into(&user, &item_ids[1..], &mut out) {
+            Err(PredictionError::OutputLengthMismatch { .. }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn perplexity() {
+        let data = synthetic_interactions(50, 20, 10).to_compressed();
+        let mut model = Hyperparameters::new(20, 10)
+            .embedding_dim(16)
+            .learning_rate(0.1)
+            .optimizer(Optimizer::Adagrad)
+            .num_epochs(20)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+
+        // An untrained model is close to uniform.
+        let untrained = assume_fitted(model.clone()).perplexity(&data).unwrap();
+        assert!(untrained > 10.0 && untrained < 40.0);
+
+        model.fit(&data).unwrap();
+        let trained = model.perplexity(&data).unwrap();
+        assert!(trained >= 1.0 && trained < untrained / 2.0);
+    }
+
+    #[test]
+    fn prediction_errors() {
+        let model = assume_fitted(
+            Hyperparameters::new(20, 10)
+                .embedding_dim(8)
+                .from_seed([42; 16])
+                .build(),
+        );
+
+        match model.user_representation(&items(&[])) {
+            Err(PredictionError::EmptyHistory) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match model.user_representation(&items(&[1, 25])) {
+            Err(PredictionError::ItemIdOutOfRange {
+                item_id: ItemId(25),
+                num_items: 20,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let user = model.user_representation(&items(&[1, 2])).unwrap();
+        match model.predict(&user, &items(&[3, 20])) {
+            Err(PredictionError::ItemIdOutOfRange {
+                item_id: ItemId(20),
+                num_items: 20,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match model.predict_all_items(&user, 30) {
+            Err(PredictionError::ItemIdOutOfRange {
+                item_id: ItemId(29),
+                num_items: 20,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match model.predict_candidates(&user, &items(&[0, 40]), 1) {
+            Err(PredictionError::ItemIdOutOfRange {
+                item_id: ItemId(40),
+                ..
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let broken = assume_fitted(
+            Hyperparameters::new(20, 10)
+                .embedding_dim(8)
+                .pretrained_embeddings(vec![vec![std::f32::NAN; 8]; 20], false)
+                .from_seed([42; 16])
+                .build(),
+        );
+        let user = broken.user_representation(&items(&[1, 2])).unwrap();
+        match broken.predict(&user, &items(&[3])) {
+            Err(PredictionError::InvalidPredictionValue) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let message = format!(
+            "{}",
+            PredictionError::ItemIdOutOfRange {
+                item_id: ItemId(25),
+                num_items: 20
+            }
+        );
+        assert_eq!(message, "Item 25 is out of range for a model of 20 items.");
+    }
+
+    #[test]
+    fn fitting_errors() {
+        let single_items = Interactions::from(
+            (0..20)
+                .map(|x| Interaction::new(UserId(x), ItemId(x), Timestamp(0)))
+                .collect::<Vec<_>>(),
+        );
+        let mut model = Hyperparameters::new(20, 10).build();
+        match model.fit(&single_items.to_compressed()) {
+            Err(FittingError::DegenerateDataset { .. }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut out_of_range = Interactions::new(10, 20);
+        for interaction in synthetic_interactions(10, 20, 5).data() {
+            out_of_range.push(interaction.clone());
+        }
+        out_of_range.push(Interaction::new(UserId(3), ItemId(25), Timestamp(10)));
+        match model.fit(&out_of_range.to_compressed()) {
+            Err(FittingError::ItemIdOutOfRange {
+                item_id: ItemId(25),
+                num_items: 20,
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let data = synthetic_interactions(10, 20, 5).to_compressed();
+        match validate_inputs(&Hyperparameters::new(20, 10).embedding_dim(0), &data) {
+            Err(FittingError::InvalidHyperparameter {
+                name: "embedding_dim",
+                ..
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut model = Hyperparameters::new(20, 10).learning_rate(-1.0).build();
+        match model.fit(&data) {
+            Err(FittingError::InvalidHyperparameter {
+                name: "learning_rate",
+                ..
+            }) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let mut model = Hyperparameters::new(20, 10)
+            .cancellation_flag(Arc::new(AtomicBool::new(true)))
+            .build();
+        match model.fit(&data) {
+            Err(FittingError::Cancelled) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn model_not_fitted() {
+        let data = synthetic_interactions(20, 20, 10).to_compressed();
+        let hyperparameters = Hyperparameters::new(20, 10)
+            .num_epochs(1)
+            .num_threads(1)
+            .from_seed([42; 16]);
+
+        let mut model = hyperparameters.clone().build();
+        assert!(!model.is_fitted());
+        match model.user_representation(&items(&[1, 2])) {
+            Err(PredictionError::ModelNotFitted) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // A cancelled fit does not count.
+        let mut cancelled = hyperparameters
+            .cancellation_flag(Arc::new(AtomicBool::new(true)))
+            .build();
+        assert!(cancelled.fit(&data).is_err());
+        assert!(!cancelled.is_fitted());
+
+        model.fit(&data).unwrap();
+        assert!(model.is_fitted());
+        let user = model.user_representation(&items(&[1, 2])).unwrap();
+
+        let unfitted = Hyperparameters::new(20, 10).build();
+        for result in vec![
+            unfitted.predict(&user, &items(&[3])).map(|_| ()),
+            unfitted.predict_all_items(&user, 20).map(|_| ()),
+            unfitted
+                .predict_candidates(&user, &items(&[3]), 1)
+                .map(|_| ()),
+            unfitted.perplexity(&data).map(|_| ()),
+        ] {
+            match result {
+                Err(PredictionError::ModelNotFitted) => {}
+                other => panic!("Unexpected result: {:?}", other),
+            }
+        }
+
+        let serialized = bincode::serialize(&model).unwrap();
+        let deserialized: ImplicitLSTMModel = bincode::deserialize(&serialized).unwrap();
+        assert!(deserialized.is_fitted());
+    }
 }
@@ -0,0 +1,280 @@
+//! First-order Markov ("people who interacted with X next interacted with Y") model.
+//!
+//! The model counts how often each item follows each other item in users'
+//! sequences, and scores candidates by their transition probability from
+//! the most recent items in the history. Despite its simplicity, this is a
+//! strong and very cheap baseline for session data.
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::CompressedInteractions;
+use crate::ids::ItemId;
+use crate::{FittingError, OnlineRankingModel, PredictionError};
+
+/// User representation of the Markov model: the most recent items of
+/// the history, oldest first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MarkovUser {
+    item_ids: Vec<ItemId>,
+}
+
+/// Sparse transition counts from a source item.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Transitions {
+    total: f32,
+    counts: HashMap<ItemId, f32>,
+}
+
+/// First-order Markov model over item transitions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MarkovModel {
+    num_items: usize,
+    window: usize,
+    history_length: usize,
+    smoothing: f32,
+    transitions: HashMap<ItemId, Transitions>,
+    popularity: Vec<f32>,
+    num_interactions: f32,
+}
+
+impl MarkovModel {
+    /// Create an unfitted model over `num_items` items.
+    pub fn new(num_items: usize) -> Self {
+        MarkovModel {
+            num_items,
+            window: 1,
+            history_length: 1,
+            smoothing: 0.0,
+            transitions: HashMap::new(),
+            popularity: vec![0.0; num_items],
+            num_interactions: 0.0,
+        }
+    }
+
+    /// Count an item as following every item up to `window` steps before
+    /// it, rather than only the immediately preceding item. Defaults to 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn window(mut self, window: usize) -> Self {
+        assert!(window > 0, "Window must be at least 1.");
+        self.window = window;
+        self
+    }
+
+    /// Score candidates from the last `history_length` items of the
+    /// history, weighting the item `d` steps back by `1 / d`. Defaults to 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `history_length` is zero.
+    pub fn history_length(mut self, history_length: usize) -> Self {
+        assert!(history_length > 0, "History length must be at least 1.");
+        self.history_length = history_length;
+        self
+    }
+
+    /// Add `smoothing` to every transition and popularity count
+    /// (add-k smoothing). Defaults to 0.
+    pub fn smoothing(mut self, smoothing: f32) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    /// Fit the model by counting transitions in each user's sequence.
+    pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<(), FittingError> {
+        if interactions.num_items() != self.num_items {
+            return Err(FittingError::ItemCountMismatch {
+                model_items: self.num_items,
+                data_items: interactions.num_items(),
+            });
+        }
+
+        let mut transitions: HashMap<ItemId, Transitions> = HashMap::new();
+        let mut popularity = vec![0.0; self.num_items];
+
+        for user in interactions.iter_users() {
+            for (idx, &item_id) in user.item_ids.iter().enumerate() {
+                popularity[item_id.index()] += 1.0;
+
+                for &source_id in &user.item_ids[idx.saturating_sub(self.window)..idx] {
+                    let source = transitions.entry(source_id).or_default();
+                    source.total += 1.0;
+                    *source.counts.entry(item_id).or_insert(0.0) += 1.0;
+                }
+            }
+        }
+
+        let num_interactions: f32 = popularity.iter().sum();
+
+        if num_interactions == 0.0 {
+            return Err(FittingError::NoInteractions);
+        }
+
+        self.transitions = transitions;
+        self.popularity = popularity;
+        self.num_interactions = num_interactions;
+
+        Ok(())
+    }
+
+    /// The smoothed probability that `item_id` is the next item.
+    fn popularity_probability(&self, item_id: ItemId) -> f32 {
+        let count = self.popularity.get(item_id.index()).cloned().unwrap_or(0.0);
+
+        (count + self.smoothing) / (self.num_interactions + self.smoothing * self.num_items as f32)
+    }
+
+    /// The smoothed probability that `item_id` follows `source_id`, falling
+    /// back to popularity for sources never seen in training.
+    fn transition_probability(&self, source_id: ItemId, item_id: ItemId) -> f32 {
+        match self.transitions.get(&source_id) {
+            Some(source) => {
+                let count = source.counts.get(&item_id).cloned().unwrap_or(0.0);
+                (count + self.smoothing) / (source.total + self.smoothing * self.num_items as f32)
+            }
+            None => self.popularity_probability(item_id),
+        }
+    }
+}
+
+impl OnlineRankingModel for MarkovModel {
+    type UserRepresentation = MarkovUser;
+
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        let start = item_ids.len().saturating_sub(self.history_length);
+
+        Ok(MarkovUser {
+            item_ids: item_ids[start..].to_owned(),
+        })
+    }
+
+    /// Score items by their weighted transition probability from the
+    /// history, or by popularity if the history is empty.
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        if user.item_ids.is_empty() {
+            return Ok(item_ids
+                .iter()
+                .map(|&item_id| self.popularity_probability(item_id))
+                .collect());
+        }
+
+        let weights: Vec<f32> = (1..=user.item_ids.len())
+            .rev()
+            .map(|distance| 1.0 / distance as f32)
+            .collect();
+        let total_weight: f32 = weights.iter().sum();
+
+        Ok(item_ids
+            .iter()
+            .map(|&item_id| {
+                izip!(&user.item_ids, &weights)
+                    .map(|(&source_id, weight)| {
+                        weight * self.transition_probability(source_id, item_id)
+                    })
+                    .sum::<f32>()
+                    / total_weight
+            })
+            .collect())
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, Timestamp, UserId};
+
+    fn data() -> CompressedInteractions {
+        // User 0: 0 -> 1 -> 2, user 1: 0 -> 1 -> 1.
+        Interactions::from(vec![
+            Interaction::new(UserId(0), ItemId(0), Timestamp(0)),
+            Interaction::new(UserId(0), ItemId(1), Timestamp(1)),
+            Interaction::new(UserId(0), ItemId(2), Timestamp(2)),
+            Interaction::new(UserId(1), ItemId(0), Timestamp(0)),
+            Interaction::new(UserId(1), ItemId(1), Timestamp(1)),
+            Interaction::new(UserId(1), ItemId(1), Timestamp(2)),
+        ])
+        .to_compressed()
+    }
+
+    fn assert_close(x: &[f32], y: &[f32]) {
+        assert_eq!(x.len(), y.len());
+        assert!(x.iter().zip(y).all(|(x, y)| (x - y).abs() < 1e-6));
+    }
+
+    #[test]
+    fn transition_table() {
+        let data = data();
+        let mut model = MarkovModel::new(3);
+        model.fit(&data).unwrap();
+
+        let all_items = items(&[0, 1, 2]);
+        let predict = |history: &[ItemId]| {
+            let user = model.user_representation(history).unwrap();
+            model.predict(&user, &all_items).unwrap()
+        };
+
+        // Transitions: 0 -> 1 twice; 1 -> 2 and 1 -> 1 once each.
+        assert_close(&predict(&items(&[0])), &[0.0, 1.0, 0.0]);
+        assert_close(&predict(&items(&[2, 1])), &[0.0, 0.5, 0.5]);
+
+        // Item 2 is never a source: fall back to popularity, as for
+        // empty histories.
+        assert_close(&predict(&items(&[2])), &[2.0 / 6.0, 3.0 / 6.0, 1.0 / 6.0]);
+        assert_close(&predict(&items(&[])), &[2.0 / 6.0, 3.0 / 6.0, 1.0 / 6.0]);
+
+        assert!(mrr_score(&model, &data).unwrap() > 0.0);
+
+        let serialized = bincode::serialize(&model).unwrap();
+        let deserialized: MarkovModel = bincode::deserialize(&serialized).unwrap();
+        let user = deserialized.user_representation(&items(&[0])).unwrap();
+        assert_close(
+            &deserialized.predict(&user, &all_items).unwrap(),
+            &[0.0, 1.0, 0.0],
+        );
+    }
+
+    #[test]
+    fn smoothing_window_and_history() {
+        let data = data();
+
+        let mut model = MarkovModel::new(3).smoothing(1.0);
+        model.fit(&data).unwrap();
+        let user = model.user_representation(&items(&[0])).unwrap();
+        assert_close(
+            &model.predict(&user, &items(&[0, 1, 2])).unwrap(),
+            &[1.0 / 5.0, 3.0 / 5.0, 1.0 / 5.0],
+        );
+
+        // With a window of 2, 0 -> 2 and 0 -> 1 are also counted once each.
+        let mut model = MarkovModel::new(3).window(2);
+        model.fit(&data).unwrap();
+        let user = model.user_representation(&items(&[0])).unwrap();
+        assert_close(
+            &model.predict(&user, &items(&[0, 1, 2])).unwrap(),
+            &[0.0, 0.75, 0.25],
+        );
+
+        // With two history items, the last has weight 1 and the one
+        // before weight 1/2.
+        let mut model = MarkovModel::new(3).history_length(2);
+        model.fit(&data).unwrap();
+        let user = model.user_representation(&items(&[2, 0, 1])).unwrap();
+        assert_eq!(user.item_ids, items(&[0, 1]));
+        assert_close(
+            &model.predict(&user, &items(&[0, 1, 2])).unwrap(),
+            &[0.0, (0.5 * 1.0 + 0.5) / 1.5, 0.5 / 1.5],
+        );
+    }
+}
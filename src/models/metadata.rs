@@ -0,0 +1,185 @@
+//! Records of how a model was fitted, stored with the model.
+//!
+//! Fitting a sequence model replaces its [ModelMetadata], which is then
+//! saved and loaded along with the model, with serde, the JSON export and
+//! the binary [format](super::format). [json::read_metadata] and
+//! [format::read_metadata] read it without loading the model.
+//!
+//! [json::read_metadata]: super::json::read_metadata
+//! [format::read_metadata]: super::format::read_metadata
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::CompressedInteractions;
+use crate::ids::Timestamp;
+
+/// Version of the crate, recorded in new metadata.
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Summary statistics of the data a model was fitted on.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DatasetStats {
+    /// Number of users.
+    pub num_users: usize,
+    /// Number of items.
+    pub num_items: usize,
+    /// Number of interactions.
+    pub num_interactions: usize,
+    /// Earliest and latest timestamps, if there are any interactions.
+    pub timestamp_range: Option<(Timestamp, Timestamp)>,
+}
+
+impl DatasetStats {
+    /// Compute the statistics of `interactions`.
+    pub fn from_interactions(interactions: &CompressedInteractions) -> Self {
+        let mut num_interactions = 0;
+        let mut timestamp_range: Option<(Timestamp, Timestamp)> = None;
+
+        for user in interactions.iter_users() {
+            num_interactions += user.item_ids.len();
+
+            for &timestamp in user.timestamps {
+                timestamp_range = Some(match timestamp_range {
+                    Some((min, max)) => (min.min(timestamp), max.max(timestamp)),
+                    None => (timestamp, timestamp),
+                });
+            }
+        }
+
+        DatasetStats {
+            num_users: interactions.num_users(),
+            num_items: interactions.num_items(),
+            num_interactions,
+            timestamp_range,
+        }
+    }
+}
+
+/// Results of a fit.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FitStats {
+    /// Number of epochs run.
+    pub num_epochs: usize,
+    /// Loss returned by the fit.
+    pub loss: f32,
+    /// Wall-clock duration of the fit.
+    pub duration: Duration,
+}
+
+/// How a model was fitted: its hyperparameters, training data and fit
+/// results, along with tags added by the user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelMetadata {
+    /// The hyperparameters of the model, as a JSON object.
+    pub hyperparameters: String,
+    /// Statistics of the training data.
+    pub dataset: DatasetStats,
+    /// Results of the fit.
+    pub fit: FitStats,
+    /// Version of the crate that fitted the model.
+    pub crate_version: String,
+    /// Free-form tags, such as the name of the training data. Tags are
+    /// kept when the model is fitted again.
+    pub tags: BTreeMap<String, String>,
+}
+
+impl ModelMetadata {
+    /// Record a fit with this version of the crate, without tags.
+    pub fn new(hyperparameters: String, dataset: DatasetStats, fit: FitStats) -> Self {
+        ModelMetadata {
+            hyperparameters,
+            dataset,
+            fit,
+            crate_version: CRATE_VERSION.to_owned(),
+            tags: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use super::*;
+    use crate::data::synthetic_interactions;
+    use crate::models::{ewma, format, json, lstm};
+
+    fn data() -> CompressedInteractions {
+        synthetic_interactions(10, 12, 6).to_compressed()
+    }
+
+    #[test]
+    fn reflects_fit() {
+        let data = data();
+        let mut model = ewma::Hyperparameters::new(12, 6)
+            .num_epochs(3)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        assert!(model.metadata().is_none());
+
+        let loss = model.fit(&data).unwrap();
+        let metadata = model.metadata().unwrap();
+
+        assert_eq!(
+            metadata.dataset,
+            DatasetStats {
+                num_users: 10,
+                num_items: 12,
+                num_interactions: 60,
+                timestamp_range: Some((Timestamp(0), Timestamp(5))),
+            }
+        );
+        assert_eq!(metadata.fit.num_epochs, 3);
+        assert_eq!(metadata.fit.loss, loss);
+        assert_eq!(metadata.crate_version, CRATE_VERSION);
+
+        let hyperparameters: serde_json::Value =
+            serde_json::from_str(&metadata.hyperparameters).unwrap();
+        assert_eq!(hyperparameters["num_epochs"], 3);
+
+        // Tags are kept, and the rest replaced, by the next fit.
+        model
+            .metadata_mut()
+            .unwrap()
+            .tags
+            .insert("data".to_owned(), "synthetic".to_owned());
+        model.fit(&data).unwrap();
+        assert_eq!(model.metadata().unwrap().tags["data"], "synthetic");
+    }
+
+    #[test]
+    fn survives_save_and_load() {
+        let mut model = lstm::Hyperparameters::new(12, 6)
+            .num_epochs(1)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data()).unwrap();
+        model
+            .metadata_mut()
+            .unwrap()
+            .tags
+            .insert("version".to_owned(), "3".to_owned());
+        let metadata = model.metadata().cloned();
+
+        let serialized = bincode::serialize(&model).unwrap();
+        let deserialized: lstm::ImplicitLSTMModel = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(deserialized.metadata().cloned(), metadata);
+
+        let exported = model.to_json().unwrap();
+        let imported = lstm::ImplicitLSTMModel::from_json(&exported).unwrap();
+        assert_eq!(imported.metadata().cloned(), metadata);
+        assert_eq!(json::read_metadata(&exported).unwrap(), metadata);
+
+        let mut bytes = Vec::new();
+        model.write(&mut bytes).unwrap();
+        let read = lstm::ImplicitLSTMModel::read(&bytes[..]).unwrap();
+        assert_eq!(read.metadata().cloned(), metadata);
+        assert_eq!(format::read_metadata(&bytes[..]).unwrap(), metadata);
+
+        // Unfitted models have no metadata.
+        let unfitted = ewma::Hyperparameters::new(12, 6).build().to_json().unwrap();
+        assert_eq!(json::read_metadata(&unfitted).unwrap(), None);
+    }
+}
@@ -0,0 +1,444 @@
+//! Matrix factorization trained with a pairwise ranking loss.
+//!
+//! The model learns user and item factors from `(user, item)` pairs,
+//! ignoring the order of interactions. Because [OnlineRankingModel] only
+//! sees a history of items at prediction time, user factors are not used
+//! for prediction: instead, users are folded in by averaging the factors
+//! of the items in their history. This gives a sequence-free reference
+//! point that can be evaluated with the same tools as the sequence models.
+use std::sync::Arc;
+use std::time::Instant;
+
+use rand;
+use rand::distributions::{Distribution, Normal};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use serde::{Deserialize, Serialize};
+
+use wyrm;
+use wyrm::optim::{Optimizer as Optim, Optimizers};
+use wyrm::{Arr, DataInput};
+
+use super::sequence_model::score_all_items;
+use super::{ImplicitUser, Loss, Optimizer};
+use crate::data::CompressedInteractions;
+use crate::ids::{ItemId, UserId};
+use crate::rng::SeedSequence;
+use crate::{FittingError, OnlineRankingModel, PredictionError};
+
+fn embedding_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
+    let normal = Normal::new(0.0, 1.0 / cols as f64);
+    Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
+}
+
+/// Hyperparameters describing the [BprMf] model.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hyperparameters {
+    num_items: usize,
+    embedding_dim: usize,
+    learning_rate: f32,
+    l2_penalty: f32,
+    loss: Loss,
+    optimizer: Optimizer,
+    rng: XorShiftRng,
+    num_epochs: usize,
+}
+
+impl Hyperparameters {
+    /// Build new hyperparameters.
+    pub fn new(num_items: usize) -> Self {
+        Hyperparameters {
+            num_items,
+            embedding_dim: 16,
+            learning_rate: 0.01,
+            l2_penalty: 0.0,
+            loss: Loss::BPR,
+            optimizer: Optimizer::Adam,
+            rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
+            num_epochs: 10,
+        }
+    }
+
+    /// Set the learning rate.
+    pub fn learning_rate(mut self, learning_rate: f32) -> Self {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    /// Set the L2 penalty.
+    pub fn l2_penalty(mut self, l2_penalty: f32) -> Self {
+        self.l2_penalty = l2_penalty;
+        self
+    }
+
+    /// Set the embedding dimensionality.
+    pub fn embedding_dim(mut self, embedding_dim: usize) -> Self {
+        self.embedding_dim = embedding_dim;
+        self
+    }
+
+    /// Set the number of epochs to run per each `fit` call.
+    pub fn num_epochs(mut self, num_epochs: usize) -> Self {
+        self.num_epochs = num_epochs;
+        self
+    }
+
+    /// Set the loss function. [Loss::WARP] is treated as [Loss::Hinge],
+    /// with a single uniformly sampled negative per positive.
+    pub fn loss(mut self, loss: Loss) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    /// Set the optimizer type.
+    pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    /// Set the random number generator.
+    pub fn rng(mut self, rng: XorShiftRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    /// Set the random number generator from seed.
+    pub fn from_seed(mut self, seed: [u8; 16]) -> Self {
+        self.rng = XorShiftRng::from_seed(seed);
+        self
+    }
+
+    /// Set the random number generator to the one named `"mf"` in
+    /// `seeds`, so that a single seed pins the whole experiment.
+    pub fn seed_sequence(mut self, seeds: &SeedSequence) -> Self {
+        self.rng = seeds.derive("mf");
+        self
+    }
+
+    /// Build a model out of the chosen hyperparameters.
+    pub fn build(mut self) -> BprMf {
+        let item_embedding = Arc::new(wyrm::HogwildParameter::new(embedding_init(
+            self.num_items,
+            self.embedding_dim,
+            &mut self.rng,
+        )));
+        let item_biases = Arc::new(wyrm::HogwildParameter::new(Arr::zeros((self.num_items, 1))));
+
+        BprMf {
+            hyper: self,
+            user_embedding: None,
+            item_embedding,
+            item_biases,
+        }
+    }
+
+    fn optimizer_for(&self) -> Optimizers {
+        match self.optimizer {
+            Optimizer::Adagrad => Optimizers::Adagrad(
+                wyrm::optim::Adagrad::new()
+                    .learning_rate(self.learning_rate)
+                    .l2_penalty(self.l2_penalty),
+            ),
+            Optimizer::Adam => Optimizers::Adam(
+                wyrm::optim::Adam::new()
+                    .learning_rate(self.learning_rate)
+                    .l2_penalty(self.l2_penalty),
+            ),
+        }
+    }
+}
+
+/// Matrix factorization model with fold-in user representations.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BprMf {
+    hyper: Hyperparameters,
+    user_embedding: Option<Arc<wyrm::HogwildParameter>>,
+    item_embedding: Arc<wyrm::HogwildParameter>,
+    item_biases: Arc<wyrm::HogwildParameter>,
+}
+
+impl Clone for BprMf {
+    fn clone(&self) -> Self {
+        BprMf {
+            hyper: self.hyper.clone(),
+            user_embedding: self
+                .user_embedding
+                .as_ref()
+                .map(|embedding| Arc::new(embedding.as_ref().clone())),
+            item_embedding: Arc::new(self.item_embedding.as_ref().clone()),
+            item_biases: Arc::new(self.item_biases.as_ref().clone()),
+        }
+    }
+}
+
+impl BprMf {
+    /// Fit the model, returning the average loss of the last epoch.
+    ///
+    /// User factors are created on the first call. Subsequent calls
+    /// continue training and must use the same number of users.
+    pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<f32, FittingError> {
+        if interactions.num_items() != self.hyper.num_items {
+            return Err(FittingError::ItemCountMismatch {
+                model_items: self.hyper.num_items,
+                data_items: interactions.num_items(),
+            });
+        }
+
+        let num_users = interactions.num_users();
+        let embedding_dim = self.hyper.embedding_dim;
+
+        let user_embedding = match self.user_embedding {
+            Some(ref user_embedding) => {
+                let model_users = user_embedding.value().shape()[0];
+                if model_users != num_users {
+                    return Err(FittingError::UserCountMismatch {
+                        model_users,
+                        data_users: num_users,
+                    });
+                }
+                user_embedding.clone()
+            }
+            None => Arc::new(wyrm::HogwildParameter::new(embedding_init(
+                num_users,
+                embedding_dim,
+                &mut self.hyper.rng,
+            ))),
+        };
+
+        let mut pairs: Vec<(UserId, ItemId)> = interactions
+            .iter_users()
+            .flat_map(|user| {
+                let user_id = user.user_id;
+                user.item_ids.iter().map(move |&item_id| (user_id, item_id))
+            })
+            .collect();
+
+        if pairs.is_empty() {
+            return Err(FittingError::NoInteractions);
+        }
+
+        let user_input = wyrm::IndexInputNode::new(&[0; 1]);
+        let positive_input = wyrm::IndexInputNode::new(&[0; 1]);
+        let negative_input = wyrm::IndexInputNode::new(&[0; 1]);
+
+        let users = wyrm::ParameterNode::shared(user_embedding.clone());
+        let items = wyrm::ParameterNode::shared(self.item_embedding.clone());
+        let biases = wyrm::ParameterNode::shared(self.item_biases.clone());
+
+        let user_vector = users.index(&user_input);
+        let positive =
+            user_vector.vector_dot(&items.index(&positive_input)) + biases.index(&positive_input);
+        let negative =
+            user_vector.vector_dot(&items.index(&negative_input)) + biases.index(&negative_input);
+
+        let mut loss = match self.hyper.loss {
+            Loss::BPR => (negative - positive).sigmoid().boxed(),
+            Loss::Hinge | Loss::WARP => (1.0 + negative - positive).relu().boxed(),
+        };
+
+        let optimizer = self.hyper.optimizer_for();
+        let rng = &mut self.hyper.rng;
+        let mut loss_value = 0.0;
+
+        for epoch in 0..self.hyper.num_epochs {
+            let epoch_start = Instant::now();
+            rng.shuffle(&mut pairs);
+            loss_value = 0.0;
+
+            for &(user_id, item_id) in &pairs {
+                user_input.set_value(user_id.index());
+                positive_input.set_value(item_id.index());
+                negative_input.set_value(rng.gen_range(0, self.hyper.num_items));
+
+                loss.forward();
+                loss.backward(1.0);
+                loss_value += loss.value().scalar_sum();
+
+                optimizer.step(&loss.parameters());
+            }
+
+            let elapsed = epoch_start.elapsed();
+            log_info!(
+                "Epoch {}: loss {:.4}, {:.0} examples/s ({} in {:.2?})",
+                epoch,
+                loss_value / pairs.len() as f32,
+                pairs.len() as f64 / elapsed.as_secs_f64().max(1e-9),
+                pairs.len(),
+                elapsed
+            );
+        }
+
+        self.user_embedding = Some(user_embedding);
+
+        Ok(loss_value / pairs.len() as f32)
+    }
+
+    /// The learned factors of `user_id`, if the model has been fitted
+    /// on a dataset including that user.
+    pub fn user_factors(&self, user_id: UserId) -> Option<Vec<f32>> {
+        let user_embedding = self.user_embedding.as_ref()?.value();
+
+        if user_id.index() < user_embedding.shape()[0] {
+            Some(user_embedding.row(user_id.index()).to_vec())
+        } else {
+            None
+        }
+    }
+}
+
+impl OnlineRankingModel for BprMf {
+    type UserRepresentation = ImplicitUser;
+
+    /// Fold the user in as the average of the factors of the items in
+    /// their history. Empty histories give a zero vector, so that
+    /// items are ranked by their biases alone.
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        let item_embedding = self.item_embedding.value();
+        let mut user_embedding = vec![0.0; self.hyper.embedding_dim];
+
+        for &item_id in item_ids {
+            for (value, &factor) in user_embedding
+                .iter_mut()
+                .zip(item_embedding.row(item_id.index()))
+            {
+                *value += factor / item_ids.len() as f32;
+            }
+        }
+
+        Ok(ImplicitUser {
+            user_embedding,
+            num_interactions: item_ids.len(),
+            user_bias: None,
+        })
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        let item_embedding = self.item_embedding.value();
+        let item_biases = self.item_biases.value();
+
+        item_ids
+            .iter()
+            .map(|&item_id| {
+                let score = wyrm::simd_dot(
+                    &user.user_embedding,
+                    item_embedding.row(item_id.index()).as_slice().unwrap(),
+                ) + item_biases[(item_id.index(), 0)];
+
+                if score.is_finite() {
+                    Ok(score)
+                } else {
+                    Err(PredictionError::InvalidPredictionValue)
+                }
+            })
+            .collect()
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        let scores = score_all_items(
+            self.item_embedding.value(),
+            self.item_biases.value(),
+            &user.user_embedding,
+            num_items,
+        );
+
+        if scores.iter().all(|score| score.is_finite()) {
+            Ok(scores)
+        } else {
+            Err(PredictionError::InvalidPredictionValue)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, Timestamp};
+    use crate::models::baselines::Random;
+
+    /// Two blocks of users, each interacting only with its own block of items.
+    fn block_data() -> CompressedInteractions {
+        let interactions: Vec<_> = (0..100)
+            .flat_map(|user_id| {
+                let block = user_id / 50;
+                (0..5).map(move |t| {
+                    Interaction::new(
+                        UserId(user_id),
+                        ItemId(block * 10 + (user_id * 3 + t * 7) % 10),
+                        Timestamp(t as i64),
+                    )
+                })
+            })
+            .collect();
+
+        Interactions::from(interactions).to_compressed()
+    }
+
+    #[test]
+    fn beats_random() {
+        let data = block_data();
+
+        let mut model = Hyperparameters::new(data.num_items())
+            .embedding_dim(8)
+            .learning_rate(0.05)
+            .num_epochs(20)
+            .from_seed([42; 16])
+            .build();
+        let loss = model.fit(&data).unwrap();
+        assert!(loss.is_finite());
+
+        assert_eq!(model.user_factors(UserId(0)).unwrap().len(), 8);
+        assert!(model.user_factors(UserId(100)).is_none());
+
+        let mrr = mrr_score(&model, &data).unwrap();
+        let random_mrr = mrr_score(&Random::new(42), &data).unwrap();
+        assert!(mrr > 2.0 * random_mrr);
+
+        // Folded-in users prefer items from their own block.
+        let user = model.user_representation(&items(&[0, 1, 2])).unwrap();
+        let scores = model.predict_all_items(&user, data.num_items()).unwrap();
+        let own_block: f32 = scores[..10].iter().sum();
+        let other_block: f32 = scores[10..].iter().sum();
+        assert!(own_block > other_block);
+
+        let serialized = bincode::serialize(&model).unwrap();
+        let deserialized: BprMf = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(
+            deserialized.predict(&user, &items(&[0, 15])).unwrap(),
+            model.predict(&user, &items(&[0, 15])).unwrap()
+        );
+    }
+
+    #[test]
+    fn user_count_mismatch() {
+        let data = block_data();
+        let mut model = Hyperparameters::new(data.num_items())
+            .num_epochs(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+
+        let mut fewer_users = Interactions::new(10, data.num_items());
+        fewer_users.push(Interaction::new(UserId(0), ItemId(0), Timestamp(0)));
+
+        match model.fit(&fewer_users.to_compressed()) {
+            Err(FittingError::UserCountMismatch {
+                model_users,
+                data_users,
+            }) => assert_eq!((model_users, data_users), (100, 10)),
+            _ => panic!("Expected a user count mismatch."),
+        }
+    }
+}
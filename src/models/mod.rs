@@ -1,14 +1,92 @@
 //! Models module.
+use std::cmp::Ordering;
+
+use ndarray::Array2;
 use serde::{Deserialize, Serialize};
 
+pub mod ann;
+pub mod attention;
+pub mod baselines;
+pub mod boxed;
+pub mod clustered;
+pub mod cnn;
+pub mod content;
+#[cfg(feature = "training")]
+pub mod distill;
 pub mod ewma;
+pub mod explore;
+pub mod fism;
+pub mod format;
+pub mod frecency;
+pub mod hierarchical;
+pub mod json;
+pub mod knn;
 pub mod lstm;
+pub mod markov;
+pub mod metadata;
+pub mod mf;
+#[cfg(feature = "onnx")]
+pub mod onnx;
+pub mod pooling;
+pub mod rerank;
+pub mod rules;
+pub mod sampling;
 mod sequence_model;
+pub mod serving;
 
 /// The user representation used by implicit sequence models.
 #[derive(Clone, Debug)]
 pub struct ImplicitUser {
     user_embedding: Vec<f32>,
+    /// Number of interactions the representation summarizes.
+    num_interactions: usize,
+    /// The user's learned bias, added to all of the user's scores. `None`
+    /// if the model has no user biases or the user is unknown to it.
+    user_bias: Option<f32>,
+}
+
+/// User representations that can be written as a dense vector.
+pub trait AsVector {
+    /// Return the vector whose inner product with a row of
+    /// [ItemEmbeddings::item_embeddings] is the model's score for that item.
+    fn as_vector(&self) -> Vec<f32>;
+
+    /// Like [AsVector::as_vector], widened to `f64` for computations
+    /// downstream in double precision.
+    fn as_f64_vec(&self) -> Vec<f64> {
+        self.as_vector().into_iter().map(f64::from).collect()
+    }
+}
+
+impl AsVector for ImplicitUser {
+    /// The user embedding, followed by a 1 multiplying the item bias. The
+    /// user bias is left out: it shifts all of the user's scores alike.
+    fn as_vector(&self) -> Vec<f32> {
+        let mut vector = self.user_embedding.clone();
+        vector.push(1.0);
+        vector
+    }
+}
+
+/// Models scoring items by the inner product of a user vector (see
+/// [AsVector]) with a fixed vector per item.
+pub trait ItemEmbeddings {
+    /// Return the scoring vectors of all items, one per row: the item
+    /// embedding followed by the item bias.
+    fn item_embeddings(&self) -> Array2<f32>;
+}
+
+/// Append the `(num_items, 1)` `biases` to the rows of `embeddings`.
+pub(crate) fn scoring_vectors(embeddings: &wyrm::Arr, biases: &wyrm::Arr) -> Array2<f32> {
+    let (num_items, dim) = embeddings.dim();
+
+    Array2::from_shape_fn((num_items, dim + 1), |(row, col)| {
+        if col < dim {
+            embeddings[(row, col)]
+        } else {
+            biases[(row, 0)]
+        }
+    })
 }
 
 /// The loss used for training the model.
@@ -39,3 +117,17 @@ pub enum Parallelism {
     /// Multiple threads synchronise parameters between minibatches.
     Synchronous,
 }
+
+/// Return the `k` highest-scoring `(id, score)` pairs, best first.
+pub(crate) fn top_k<T>(mut scored: Vec<(T, f32)>, k: usize) -> Vec<(T, f32)> {
+    let by_score = |x: &(T, f32), y: &(T, f32)| y.1.partial_cmp(&x.1).unwrap_or(Ordering::Equal);
+
+    if k < scored.len() {
+        scored.select_nth_unstable_by(k, by_score);
+        scored.truncate(k);
+    }
+
+    scored.sort_by(by_score);
+
+    scored
+}
@@ -0,0 +1,526 @@
+//! Export of the prediction path of pooling-style models to ONNX.
+//!
+//! Supported models represent a user as a weighted sum of the embeddings
+//! of their most recent items, and score candidates by the inner product
+//! of the user representation with the candidate embeddings, plus the
+//! candidate biases. The exported graph (opset 11) has two inputs:
+//! - `item_ids`: `int64[history_length]`, the most recent items of the
+//!   user, oldest first, left-padded with -1;
+//! - `candidates`: `int64[num_candidates]`, the items to score;
+//!
+//! and one output, `scores`: `float[num_candidates]`.
+//!
+//! Each position of the history has a weight per embedding dimension,
+//! with an extra weight added at the position of the oldest item, and the
+//! weights are normalized to sum to one for each dimension. This covers
+//! both exponentially-weighted and windowed averages.
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use ndarray::Array2;
+use wyrm::Arr;
+
+/// ONNX operator set the graph is written against.
+const OPSET_VERSION: i64 = 11;
+/// ONNX IR version matching [OPSET_VERSION].
+const IR_VERSION: i64 = 6;
+
+/// ONNX tensor element types.
+const FLOAT: i64 = 1;
+const INT64: i64 = 7;
+
+/// ONNX attribute types.
+const ATTRIBUTE_INT: i64 = 2;
+const ATTRIBUTE_INTS: i64 = 7;
+
+/// Errors when exporting models to ONNX.
+#[derive(Debug)]
+pub enum OnnxError {
+    /// The model could not be written.
+    Io(io::Error),
+    /// The model has not been fitted yet.
+    ModelNotFitted,
+    /// The model uses a feature the exported graph cannot express.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for OnnxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OnnxError::Io(err) => write!(f, "I/O error: {}", err),
+            OnnxError::ModelNotFitted => write!(f, "The model has not been fitted."),
+            OnnxError::Unsupported(feature) => {
+                write!(f, "ONNX export does not support {}.", feature)
+            }
+        }
+    }
+}
+
+impl error::Error for OnnxError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            OnnxError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for OnnxError {
+    fn from(err: io::Error) -> Self {
+        OnnxError::Io(err)
+    }
+}
+
+/// A protocol buffer message under construction.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn int(mut self, field: u64, value: i64) -> Self {
+        self.varint(field << 3);
+        self.varint(value as u64);
+        self
+    }
+
+    fn bytes(mut self, field: u64, value: &[u8]) -> Self {
+        self.varint((field << 3) | 2);
+        self.varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    fn string(self, field: u64, value: &str) -> Self {
+        self.bytes(field, value.as_bytes())
+    }
+
+    fn message(self, field: u64, value: Message) -> Self {
+        self.bytes(field, &value.0)
+    }
+}
+
+/// A `TensorProto` holding `raw_data` of element type `data_type`.
+fn tensor(name: &str, dims: &[usize], data_type: i64, raw_data: &[u8]) -> Message {
+    dims.iter()
+        .fold(Message::default(), |tensor, &dim| tensor.int(1, dim as i64))
+        .int(2, data_type)
+        .string(8, name)
+        .bytes(9, raw_data)
+}
+
+fn float_tensor<'a, I: IntoIterator<Item = &'a f32>>(
+    name: &str,
+    dims: &[usize],
+    values: I,
+) -> Message {
+    let raw_data: Vec<u8> = values
+        .into_iter()
+        .flat_map(|value| value.to_le_bytes().to_vec())
+        .collect();
+
+    tensor(name, dims, FLOAT, &raw_data)
+}
+
+fn int_tensor(name: &str, dims: &[usize], values: &[i64]) -> Message {
+    let raw_data: Vec<u8> = values
+        .iter()
+        .flat_map(|value| value.to_le_bytes().to_vec())
+        .collect();
+
+    tensor(name, dims, INT64, &raw_data)
+}
+
+fn int_attribute(name: &str, value: i64) -> Message {
+    Message::default()
+        .string(1, name)
+        .int(3, value)
+        .int(20, ATTRIBUTE_INT)
+}
+
+fn ints_attribute(name: &str, values: &[i64]) -> Message {
+    values
+        .iter()
+        .fold(Message::default().string(1, name), |attribute, &value| {
+            attribute.int(8, value)
+        })
+        .int(20, ATTRIBUTE_INTS)
+}
+
+/// A `NodeProto` applying `op_type` to `inputs`.
+fn node(op_type: &str, inputs: &[&str], output: &str, attributes: Vec<Message>) -> Message {
+    let node = inputs
+        .iter()
+        .fold(Message::default(), |node, input| node.string(1, input))
+        .string(2, output)
+        .string(3, output)
+        .string(4, op_type);
+
+    attributes
+        .into_iter()
+        .fold(node, |node, attribute| node.message(5, attribute))
+}
+
+/// A one-dimensional tensor `ValueInfoProto`, of length `length` or of
+/// the symbolic length `length_name` if `length` is `None`.
+fn vector_info(name: &str, elem_type: i64, length: Option<usize>, length_name: &str) -> Message {
+    let dim = match length {
+        Some(length) => Message::default().int(1, length as i64),
+        None => Message::default().string(2, length_name),
+    };
+    let tensor_type = Message::default()
+        .int(1, elem_type)
+        .message(2, Message::default().message(1, dim));
+
+    Message::default()
+        .string(1, name)
+        .message(2, Message::default().message(1, tensor_type))
+}
+
+/// The parameters of a pooling-style model needed to score items.
+pub(crate) struct PoolingGraph<'a> {
+    /// Item embeddings, one per row.
+    pub item_embeddings: &'a Arr,
+    /// Item biases, as a column.
+    pub item_biases: &'a Arr,
+    /// Weight of each history position, most recent last, as a row per
+    /// position and a column per embedding dimension.
+    pub recent: Array2<f32>,
+    /// Weight added at the history position holding the oldest item.
+    pub first: Array2<f32>,
+}
+
+impl<'a> PoolingGraph<'a> {
+    /// Encode the graph as a serialized `ModelProto`.
+    fn to_bytes(&self) -> Vec<u8> {
+        let (num_items, dim) = self.item_embeddings.dim();
+        let history_length = self.recent.nrows();
+
+        // Subtracting the shifted mask leaves a one at the oldest item
+        // of a left-padded history.
+        let first_selector = Array2::from_shape_fn((history_length, history_length), |(x, y)| {
+            if x == y {
+                1.0
+            } else if x == y + 1 {
+                -1.0
+            } else {
+                0.0
+            }
+        });
+
+        let initializers = vec![
+            float_tensor(
+                "item_embeddings",
+                &[num_items, dim],
+                self.item_embeddings.iter(),
+            ),
+            float_tensor("item_biases", &[num_items], self.item_biases.iter()),
+            float_tensor("recent_weights", &[history_length, dim], self.recent.iter()),
+            float_tensor("first_weights", &[history_length, dim], self.first.iter()),
+            float_tensor(
+                "first_selector",
+                &[history_length, history_length],
+                first_selector.iter(),
+            ),
+            int_tensor("padding", &[], &[-1]),
+            int_tensor("column_shape", &[2], &[history_length as i64, 1]),
+            int_tensor("vector_shape", &[1], &[-1]),
+        ];
+
+        let nodes = vec![
+            node("Greater", &["item_ids", "padding"], "is_item", vec![]),
+            node(
+                "Cast",
+                &["is_item"],
+                "is_item_int",
+                vec![int_attribute("to", INT64)],
+            ),
+            node("Mul", &["item_ids", "is_item_int"], "history_ids", vec![]),
+            node(
+                "Cast",
+                &["is_item"],
+                "mask_vector",
+                vec![int_attribute("to", FLOAT)],
+            ),
+            node("Reshape", &["mask_vector", "column_shape"], "mask", vec![]),
+            node("MatMul", &["first_selector", "mask"], "first", vec![]),
+            node("Mul", &["mask", "recent_weights"], "recent_part", vec![]),
+            node("Mul", &["first", "first_weights"], "first_part", vec![]),
+            node(
+                "Add",
+                &["recent_part", "first_part"],
+                "unnormalized_weights",
+                vec![],
+            ),
+            node(
+                "ReduceSum",
+                &["unnormalized_weights"],
+                "normalizer",
+                vec![ints_attribute("axes", &[0]), int_attribute("keepdims", 1)],
+            ),
+            node(
+                "Div",
+                &["unnormalized_weights", "normalizer"],
+                "weights",
+                vec![],
+            ),
+            node(
+                "Gather",
+                &["item_embeddings", "history_ids"],
+                "history",
+                vec![int_attribute("axis", 0)],
+            ),
+            node("Mul", &["weights", "history"], "weighted_history", vec![]),
+            node(
+                "ReduceSum",
+                &["weighted_history"],
+                "user",
+                vec![ints_attribute("axes", &[0]), int_attribute("keepdims", 1)],
+            ),
+            node(
+                "Transpose",
+                &["user"],
+                "user_column",
+                vec![ints_attribute("perm", &[1, 0])],
+            ),
+            node(
+                "Gather",
+                &["item_embeddings", "candidates"],
+                "candidate_embeddings",
+                vec![int_attribute("axis", 0)],
+            ),
+            node(
+                "MatMul",
+                &["candidate_embeddings", "user_column"],
+                "dot_column",
+                vec![],
+            ),
+            node("Reshape", &["dot_column", "vector_shape"], "dots", vec![]),
+            node(
+                "Gather",
+                &["item_biases", "candidates"],
+                "candidate_biases",
+                vec![int_attribute("axis", 0)],
+            ),
+            node("Add", &["dots", "candidate_biases"], "scores", vec![]),
+        ];
+
+        let graph = Message::default().string(2, "recommender");
+        let graph = nodes
+            .into_iter()
+            .fold(graph, |graph, node| graph.message(1, node));
+        let graph = initializers
+            .into_iter()
+            .fold(graph, |graph, initializer| graph.message(5, initializer))
+            .message(
+                11,
+                vector_info("item_ids", INT64, Some(history_length), "history_length"),
+            )
+            .message(11, vector_info("candidates", INT64, None, "num_candidates"))
+            .message(12, vector_info("scores", FLOAT, None, "num_candidates"));
+
+        Message::default()
+            .int(1, IR_VERSION)
+            .string(2, "recommenders")
+            .string(3, env!("CARGO_PKG_VERSION"))
+            .message(7, graph)
+            .message(8, Message::default().int(2, OPSET_VERSION))
+            .0
+    }
+
+    /// Write the graph to `path`.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), OnnxError> {
+        fs::write(path, self.to_bytes())?;
+
+        Ok(())
+    }
+
+    /// Compute the user representation as the exported graph does.
+    #[cfg(test)]
+    pub fn user_embedding(&self, item_ids: &[crate::ids::ItemId]) -> Vec<f32> {
+        let (history_length, dim) = self.recent.dim();
+        let item_ids = &item_ids[item_ids.len().saturating_sub(history_length)..];
+        let start = history_length - item_ids.len();
+
+        let mut user = vec![0.0; dim];
+
+        for col in 0..dim {
+            let weights: Vec<f32> = (start..history_length)
+                .map(|row| {
+                    if row == start {
+                        self.recent[(row, col)] + self.first[(row, col)]
+                    } else {
+                        self.recent[(row, col)]
+                    }
+                })
+                .collect();
+            let normalizer: f32 = weights.iter().sum();
+
+            for (&item_id, weight) in item_ids.iter().zip(weights) {
+                user[col] += weight / normalizer * self.item_embeddings[(item_id.index(), col)];
+            }
+        }
+
+        user
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    /// A decoded protocol buffer field value.
+    #[derive(Debug)]
+    enum Field<'a> {
+        Varint(u64),
+        Bytes(&'a [u8]),
+    }
+
+    fn read_varint(bytes: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+
+        loop {
+            let byte = bytes[*pos];
+            *pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            shift += 7;
+
+            if byte < 0x80 {
+                return value;
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<(u64, Field)> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+
+        while pos < bytes.len() {
+            let key = read_varint(bytes, &mut pos);
+            let value = match key & 7 {
+                0 => Field::Varint(read_varint(bytes, &mut pos)),
+                2 => {
+                    let len = read_varint(bytes, &mut pos) as usize;
+                    pos += len;
+                    Field::Bytes(&bytes[pos - len..pos])
+                }
+                wire_type => panic!("Unexpected wire type {}", wire_type),
+            };
+            fields.push((key >> 3, value));
+        }
+
+        fields
+    }
+
+    fn messages<'a>(fields: &[(u64, Field<'a>)], field: u64) -> Vec<&'a [u8]> {
+        fields
+            .iter()
+            .filter_map(|(number, value)| match value {
+                Field::Bytes(bytes) if *number == field => Some(*bytes),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn strings(fields: &[(u64, Field)], field: u64) -> Vec<String> {
+        messages(fields, field)
+            .into_iter()
+            .map(|bytes| String::from_utf8(bytes.to_vec()).unwrap())
+            .collect()
+    }
+
+    fn ints(fields: &[(u64, Field)], field: u64) -> Vec<u64> {
+        fields
+            .iter()
+            .filter_map(|(number, value)| match value {
+                Field::Varint(value) if *number == field => Some(*value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn graph_structure() {
+        let item_embeddings = Arr::from_shape_fn((5, 3), |(row, col)| (row * 3 + col) as f32);
+        let item_biases = Arr::from_shape_fn((5, 1), |(row, _)| row as f32 / 10.0);
+        let graph = PoolingGraph {
+            item_embeddings: &item_embeddings,
+            item_biases: &item_biases,
+            recent: Array2::ones((4, 3)),
+            first: Array2::zeros((4, 3)),
+        };
+
+        let bytes = graph.to_bytes();
+        let model = decode(&bytes);
+        assert_eq!(ints(&model, 1), vec![IR_VERSION as u64]);
+        let opset = decode(messages(&model, 8)[0]);
+        assert_eq!(ints(&opset, 2), vec![OPSET_VERSION as u64]);
+
+        let graph = decode(messages(&model, 7)[0]);
+
+        // Every node input is defined before it is used.
+        let mut defined: HashSet<String> = messages(&graph, 11)
+            .into_iter()
+            .map(|input| strings(&decode(input), 1)[0].clone())
+            .collect();
+        assert_eq!(defined.len(), 2);
+
+        for initializer in messages(&graph, 5) {
+            let initializer = decode(initializer);
+            let name = strings(&initializer, 8)[0].clone();
+            let dims = ints(&initializer, 1);
+            let raw_data = messages(&initializer, 9)[0];
+            let element_size = match ints(&initializer, 2)[0] as i64 {
+                FLOAT => 4,
+                INT64 => 8,
+                data_type => panic!("Unexpected data type {}", data_type),
+            };
+            assert_eq!(
+                raw_data.len(),
+                element_size * dims.iter().product::<u64>() as usize
+            );
+
+            if name == "item_embeddings" {
+                assert_eq!(dims, vec![5, 3]);
+                let values: Vec<f32> = raw_data
+                    .chunks(4)
+                    .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+                    .collect();
+                assert_eq!(values, item_embeddings.iter().cloned().collect::<Vec<_>>());
+            }
+
+            defined.insert(name);
+        }
+
+        let mut op_types = Vec::new();
+        for node in messages(&graph, 1) {
+            let node = decode(node);
+
+            for input in strings(&node, 1) {
+                assert!(defined.contains(&input), "Undefined input {}", input);
+            }
+
+            op_types.push(strings(&node, 4)[0].clone());
+            defined.insert(strings(&node, 2)[0].clone());
+        }
+
+        assert!(op_types.contains(&"Gather".to_owned()));
+        assert!(op_types.contains(&"MatMul".to_owned()));
+
+        let output = decode(messages(&graph, 12)[0]);
+        assert_eq!(strings(&output, 1), vec!["scores".to_owned()]);
+        assert!(defined.contains("scores"));
+    }
+}
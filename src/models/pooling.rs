@@ -0,0 +1,686 @@
+//! Average-pooling (neural bag-of-items) model.
+//!
+//! The representation of a user is the unweighted mean of the embeddings
+//! of the items in their history, optionally restricted to the last `n`
+//! items. The model is trained with the same losses as the sequence models
+//! but ignores the order of the history, so comparing it to them isolates
+//! the contribution of sequence order.
+#[cfg(feature = "onnx")]
+use std::path::Path;
+use std::sync::Arc;
+
+use rand;
+use rand::distributions::{Distribution, Normal};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "onnx")]
+use ndarray::Array2;
+
+use wyrm;
+use wyrm::optim::Optimizers;
+use wyrm::{Arr, BoxedNode, Variable};
+
+use super::metadata::ModelMetadata;
+#[cfg(feature = "onnx")]
+use super::onnx::{OnnxError, PoolingGraph};
+use super::sampling::MinibatchSampler;
+#[cfg(feature = "training")]
+use super::sequence_model::fit_sequence_model;
+use super::sequence_model::{score_all_items, SequenceModel, SequenceModelParameters};
+use super::{ImplicitUser, Loss, Optimizer, Parallelism};
+use crate::config;
+#[cfg(feature = "training")]
+use crate::data::CompressedInteractions;
+use crate::ids::ItemId;
+use crate::rng::SeedSequence;
+#[cfg(feature = "training")]
+use crate::FittingError;
+use crate::{OnlineRankingModel, PredictionError};
+
+fn embedding_init<T: Rng>(rows: usize, cols: usize, rng: &mut T) -> wyrm::Arr {
+    let normal = Normal::new(0.0, 1.0 / cols as f64);
+    Arr::zeros((rows, cols)).map(|_| normal.sample(rng) as f32)
+}
+
+/// Hyperparameters for the [ImplicitPoolingModel].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hyperparameters {
+    num_items: usize,
+    max_sequence_length: usize,
+    item_embedding_dim: usize,
+    window: Option<usize>,
+    learning_rate: f32,
+    l2_penalty: f32,
+    loss: Loss,
+    optimizer: Optimizer,
+    parallelism: Parallelism,
+    rng: XorShiftRng,
+    num_threads: usize,
+    num_epochs: usize,
+    #[serde(default)]
+    accumulate_f64: bool,
+}
+
+impl Hyperparameters {
+    /// Build new hyperparameters.
+    pub fn new(num_items: usize, max_sequence_length: usize) -> Self {
+        Hyperparameters {
+            num_items,
+            max_sequence_length,
+            item_embedding_dim: 16,
+            window: None,
+            learning_rate: 0.01,
+            l2_penalty: 0.0,
+            loss: Loss::BPR,
+            optimizer: Optimizer::Adam,
+            parallelism: Parallelism::Synchronous,
+            rng: XorShiftRng::from_seed(rand::thread_rng().gen()),
+            num_threads: config::num_threads(),
+            num_epochs: 10,
+            accumulate_f64: false,
+        }
+    }
+
+    /// Set the learning rate.
+    pub fn learning_rate(mut self, learning_rate: f32) -> Self {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    /// Set the l2 penalty.
+    pub fn l2_penalty(mut self, l2_penalty: f32) -> Self {
+        self.l2_penalty = l2_penalty;
+        self
+    }
+
+    /// Set the embedding dimensionality.
+    pub fn embedding_dim(mut self, embedding_dim: usize) -> Self {
+        self.item_embedding_dim = embedding_dim;
+        self
+    }
+
+    /// Only average the last `window` items of the history. Defaults
+    /// to averaging the whole history (up to the maximum sequence length).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn window(mut self, window: usize) -> Self {
+        assert!(window > 0, "Window must be at least 1.");
+        self.window = Some(window);
+        self
+    }
+
+    /// Set the number of epochs to run per each `fit` call.
+    pub fn num_epochs(mut self, num_epochs: usize) -> Self {
+        self.num_epochs = num_epochs;
+        self
+    }
+
+    /// Set the loss function.
+    pub fn loss(mut self, loss: Loss) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    /// Set number of threads to be used.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// Accumulate training losses in `f64` rather than `f32`. Defaults to
+    /// false.
+    ///
+    /// Summed in `f32`, many small losses lose precision, so that the
+    /// reported loss depends on summation order. Parameters stay `f32`
+    /// either way.
+    pub fn accumulate_f64(mut self, enabled: bool) -> Self {
+        self.accumulate_f64 = enabled;
+        self
+    }
+
+    /// Set the type of paralellism.
+    pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Set the random number generator.
+    pub fn rng(mut self, rng: XorShiftRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
+    /// Set the random number generator from seed.
+    pub fn from_seed(mut self, seed: [u8; 16]) -> Self {
+        self.rng = XorShiftRng::from_seed(seed);
+        self
+    }
+
+    /// Set the random number generator to the one named `"pooling"` in
+    /// `seeds`, so that a single seed pins the whole experiment.
+    pub fn seed_sequence(mut self, seeds: &SeedSequence) -> Self {
+        self.rng = seeds.derive("pooling");
+        self
+    }
+
+    /// Set the optimizer type.
+    pub fn optimizer(mut self, optimizer: Optimizer) -> Self {
+        self.optimizer = optimizer;
+        self
+    }
+
+    fn build_params(mut self) -> Parameters {
+        let item_embedding = Arc::new(wyrm::HogwildParameter::new(embedding_init(
+            self.num_items,
+            self.item_embedding_dim,
+            &mut self.rng,
+        )));
+        let item_biases = Arc::new(wyrm::HogwildParameter::new(Arr::zeros((self.num_items, 1))));
+
+        Parameters {
+            hyper: self,
+            num_users: None,
+            metadata: None,
+            item_embedding,
+            item_biases,
+        }
+    }
+
+    /// Build a model out of the chosen hyperparameters.
+    pub fn build(self) -> ImplicitPoolingModel {
+        ImplicitPoolingModel {
+            params: self.build_params(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Parameters {
+    hyper: Hyperparameters,
+    num_users: Option<usize>,
+    #[serde(default)]
+    metadata: Option<ModelMetadata>,
+    item_embedding: Arc<wyrm::HogwildParameter>,
+    item_biases: Arc<wyrm::HogwildParameter>,
+}
+
+impl Clone for Parameters {
+    fn clone(&self) -> Self {
+        Parameters {
+            hyper: self.hyper.clone(),
+            num_users: self.num_users,
+            metadata: self.metadata.clone(),
+            item_embedding: Arc::new(self.item_embedding.as_ref().clone()),
+            item_biases: Arc::new(self.item_biases.as_ref().clone()),
+        }
+    }
+}
+
+impl SequenceModelParameters for Parameters {
+    type Output = Model;
+    fn num_items(&self) -> usize {
+        self.hyper.num_items
+    }
+    fn num_users(&mut self) -> &mut Option<usize> {
+        &mut self.num_users
+    }
+    fn metadata(&mut self) -> &mut Option<ModelMetadata> {
+        &mut self.metadata
+    }
+    fn hyperparameters(&self) -> String {
+        serde_json::to_string(&self.hyper).unwrap_or_default()
+    }
+    fn max_sequence_length(&self) -> usize {
+        self.hyper.max_sequence_length
+    }
+    fn num_threads(&self) -> usize {
+        self.hyper.num_threads
+    }
+    fn accumulate_f64(&self) -> bool {
+        self.hyper.accumulate_f64
+    }
+    fn rng(&mut self) -> &mut XorShiftRng {
+        &mut self.hyper.rng
+    }
+    fn negative_sampler(&self) -> Option<&dyn MinibatchSampler> {
+        None
+    }
+    fn learning_rate(&self) -> f32 {
+        self.hyper.learning_rate
+    }
+    fn warmup_steps(&self) -> usize {
+        0
+    }
+    fn optimizer(&self, learning_rate: f32) -> Optimizers {
+        match self.hyper.optimizer {
+            Optimizer::Adagrad => Optimizers::Adagrad(
+                wyrm::optim::Adagrad::new()
+                    .learning_rate(learning_rate)
+                    .l2_penalty(self.hyper.l2_penalty),
+            ),
+
+            Optimizer::Adam => Optimizers::Adam(
+                wyrm::optim::Adam::new()
+                    .learning_rate(learning_rate)
+                    .l2_penalty(self.hyper.l2_penalty),
+            ),
+        }
+    }
+    fn parallelism(&self) -> &Parallelism {
+        &self.hyper.parallelism
+    }
+    fn loss(&self) -> &Loss {
+        &self.hyper.loss
+    }
+    fn num_epochs(&self) -> usize {
+        self.hyper.num_epochs
+    }
+    fn build(&self) -> Self::Output {
+        let item_embeddings = wyrm::ParameterNode::shared(self.item_embedding.clone());
+        let item_biases = wyrm::ParameterNode::shared(self.item_biases.clone());
+
+        let inputs: Vec<_> = (0..self.hyper.max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let outputs: Vec<_> = (0..self.hyper.max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+        let negatives: Vec<_> = (0..self.hyper.max_sequence_length)
+            .map(|_| wyrm::IndexInputNode::new(&[0; 1]))
+            .collect();
+
+        // Running sums of the input embeddings: the sum over a window is
+        // the difference of two of them.
+        let mut prefix_sums: Vec<Variable<BoxedNode>> = Vec::with_capacity(inputs.len());
+        for input in &inputs {
+            let embedding = item_embeddings.index(input).boxed();
+            let prefix_sum = match prefix_sums.last() {
+                Some(previous) => (previous.clone() + embedding).boxed(),
+                None => embedding,
+            };
+            prefix_sums.push(prefix_sum);
+        }
+
+        let hidden_states: Vec<_> = (0..prefix_sums.len())
+            .map(|idx| match self.hyper.window {
+                Some(window) if idx >= window => ((1.0 / window as f32)
+                    * (prefix_sums[idx].clone() - prefix_sums[idx - window].clone()))
+                .boxed(),
+                _ => ((1.0 / (idx + 1) as f32) * prefix_sums[idx].clone()).boxed(),
+            })
+            .collect();
+
+        let positive_predictions: Vec<_> = izip!(&hidden_states, &outputs)
+            .map(|(hidden_state, output)| {
+                hidden_state.vector_dot(&item_embeddings.index(output)) + item_biases.index(output)
+            })
+            .collect();
+        let negative_predictions: Vec<_> = izip!(&hidden_states, &negatives)
+            .map(|(hidden_state, negative)| {
+                hidden_state.vector_dot(&item_embeddings.index(negative))
+                    + item_biases.index(negative)
+            })
+            .collect();
+
+        let losses: Vec<_> = positive_predictions
+            .into_iter()
+            .zip(negative_predictions.into_iter())
+            .map(|(pos, neg)| match self.hyper.loss {
+                Loss::BPR => (neg - pos).sigmoid().boxed(),
+                Loss::Hinge | Loss::WARP => (1.0 + neg - pos).relu().boxed(),
+            })
+            .collect();
+
+        let mut summed_losses = Vec::with_capacity(losses.len());
+        summed_losses.push(losses[0].clone());
+
+        for loss in &losses[1..] {
+            let loss = (summed_losses.last().unwrap().clone() + loss.clone()).boxed();
+            summed_losses.push(loss);
+        }
+
+        Model {
+            inputs,
+            outputs,
+            negatives,
+            hidden_states,
+            summed_losses,
+        }
+    }
+    fn predict_single(&self, user: &[f32], item_idx: usize) -> f32 {
+        let embeddings = self.item_embedding.value();
+        let biases = self.item_biases.value();
+
+        let embedding = embeddings.row(item_idx);
+        let bias = biases[(item_idx, 0)];
+        let dot = wyrm::simd_dot(user, embedding.as_slice().unwrap());
+
+        bias + dot
+    }
+    fn predict_all(&self, user: &[f32], num_items: usize) -> Vec<f32> {
+        score_all_items(
+            self.item_embedding.value(),
+            self.item_biases.value(),
+            user,
+            num_items,
+        )
+    }
+}
+
+struct Model {
+    inputs: Vec<Variable<wyrm::IndexInputNode>>,
+    outputs: Vec<Variable<wyrm::IndexInputNode>>,
+    negatives: Vec<Variable<wyrm::IndexInputNode>>,
+    hidden_states: Vec<Variable<BoxedNode>>,
+    summed_losses: Vec<Variable<BoxedNode>>,
+}
+
+impl SequenceModel for Model {
+    fn state(
+        &self,
+    ) -> (
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<wyrm::IndexInputNode>],
+        &[Variable<BoxedNode>],
+    ) {
+        (
+            &self.inputs,
+            &self.outputs,
+            &self.negatives,
+            &self.hidden_states,
+        )
+    }
+    fn losses(&mut self) -> &mut [Variable<BoxedNode>] {
+        &mut self.summed_losses
+    }
+    fn hidden_states(&mut self) -> &mut [Variable<BoxedNode>] {
+        &mut self.hidden_states
+    }
+    fn contexts(&self) -> &[Variable<wyrm::IndexInputNode>] {
+        &[]
+    }
+    fn time_deltas(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn dropout_masks(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn weights(&self) -> &[Variable<wyrm::InputNode>] {
+        &[]
+    }
+    fn set_users(&self, _user_ids: &[usize]) {}
+}
+
+/// An average-pooling model for implicit feedback.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImplicitPoolingModel {
+    params: Parameters,
+}
+
+impl ImplicitPoolingModel {
+    /// Fit the model.
+    ///
+    /// Returns the loss value.
+    #[cfg(feature = "training")]
+    pub fn fit(&mut self, interactions: &CompressedInteractions) -> Result<f32, FittingError> {
+        fit_sequence_model(interactions, &mut self.params)
+    }
+
+    /// Return the record of the last successful fit, or `None` if the
+    /// model has not been fitted. See [ModelMetadata].
+    pub fn metadata(&self) -> Option<&ModelMetadata> {
+        self.params.metadata.as_ref()
+    }
+
+    /// Return the record of the last successful fit mutably, for example
+    /// to add tags.
+    pub fn metadata_mut(&mut self) -> Option<&mut ModelMetadata> {
+        self.params.metadata.as_mut()
+    }
+
+    /// Export the prediction path of the model to an ONNX file at `path`.
+    /// See [crate::models::onnx] for the inputs and outputs of the graph.
+    #[cfg(feature = "onnx")]
+    pub fn export_onnx<P: AsRef<Path>>(&self, path: P) -> Result<(), OnnxError> {
+        self.onnx_graph().write(path)
+    }
+
+    #[cfg(feature = "onnx")]
+    fn onnx_graph(&self) -> PoolingGraph {
+        let hyper = &self.params.hyper;
+        let shape = (hyper.max_sequence_length, hyper.item_embedding_dim);
+        let window = hyper.window.unwrap_or(hyper.max_sequence_length);
+
+        PoolingGraph {
+            item_embeddings: self.params.item_embedding.value(),
+            item_biases: self.params.item_biases.value(),
+            recent: Array2::from_shape_fn(shape, |(row, _)| {
+                if hyper.max_sequence_length - row <= window {
+                    1.0
+                } else {
+                    0.0
+                }
+            }),
+            first: Array2::zeros(shape),
+        }
+    }
+}
+
+impl OnlineRankingModel for ImplicitPoolingModel {
+    type UserRepresentation = ImplicitUser;
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        self.params.user_representation(item_ids)
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.params.predict(user, item_ids)
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.params.predict_all_items(user, num_items)
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use rand::distributions::Uniform;
+
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::evaluation::mrr_score;
+    use crate::ids::{items, Timestamp, UserId};
+    use crate::models::lstm;
+
+    fn item_embedding(model: &ImplicitPoolingModel, item_id: ItemId) -> Vec<f32> {
+        model
+            .params
+            .item_embedding
+            .value()
+            .row(item_id.index())
+            .to_vec()
+    }
+
+    #[test]
+    fn mean_of_history() {
+        let model = Hyperparameters::new(10, 5).from_seed([42; 16]).build();
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let expected: Vec<_> = izip!(
+            item_embedding(&model, ItemId(1)),
+            item_embedding(&model, ItemId(2)),
+            item_embedding(&model, ItemId(3))
+        )
+        .map(|(x, y, z)| (x + y + z) / 3.0)
+        .collect();
+        assert!(izip!(&user.user_embedding, &expected).all(|(x, y)| (x - y).abs() < 1e-6));
+
+        // Order does not matter; with a window, only the last items do.
+        let shuffled = model.user_representation(&items(&[3, 1, 2])).unwrap();
+        assert!(izip!(&user.user_embedding, &shuffled.user_embedding)
+            .all(|(x, y)| (x - y).abs() < 1e-6));
+
+        let model = Hyperparameters::new(10, 5)
+            .window(1)
+            .from_seed([42; 16])
+            .build();
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        assert!(
+            izip!(&user.user_embedding, item_embedding(&model, ItemId(3)))
+                .all(|(x, y)| (x - y).abs() < 1e-6)
+        );
+    }
+
+    #[cfg(feature = "onnx")]
+    #[test]
+    fn onnx_export() {
+        for window in &[None, Some(2)] {
+            let mut hyperparameters = Hyperparameters::new(10, 5).from_seed([42; 16]);
+            if let Some(window) = *window {
+                hyperparameters = hyperparameters.window(window);
+            }
+            let model = hyperparameters.build();
+            let graph = model.onnx_graph();
+
+            for history in &[&[3][..], &[1, 2, 3], &[0, 1, 2, 3, 4, 5, 6, 7]] {
+                let expected = model.user_representation(history).unwrap();
+                for (x, y) in izip!(graph.user_embedding(history), expected.user_embedding) {
+                    assert!((x - y).abs() < 1e-5, "{} != {}", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fit_and_serialize() {
+        let data = Interactions::from(
+            (0..40)
+                .map(|t| Interaction::new(UserId(t % 4), ItemId(t % 7), Timestamp(t as i64)))
+                .collect::<Vec<_>>(),
+        )
+        .to_compressed();
+
+        let mut model = Hyperparameters::new(7, 10)
+            .window(3)
+            .num_epochs(2)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        assert!(model.fit(&data).unwrap().is_finite());
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let predictions = model
+            .predict(&user, &items(&[0, 1, 2, 3, 4, 5, 6]))
+            .unwrap();
+
+        let serialized = serde_json::to_string(&model).unwrap();
+        let deserialized: ImplicitPoolingModel = serde_json::from_str(&serialized).unwrap();
+        let user = deserialized
+            .user_representation(&items(&[1, 2, 3]))
+            .unwrap();
+        assert_eq!(
+            deserialized
+                .predict(&user, &items(&[0, 1, 2, 3, 4, 5, 6]))
+                .unwrap(),
+            predictions
+        );
+    }
+
+    /// Return the test MRRs of an LSTM and a pooling model fit on `data`.
+    fn compare_with_lstm(data: Interactions) -> (f32, f32) {
+        let data = data.to_compressed();
+
+        let mut lstm = lstm::Hyperparameters::new(40, 12)
+            .embedding_dim(32)
+            .learning_rate(0.05)
+            .num_epochs(20)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        lstm.fit(&data).unwrap();
+
+        let mut pooling = Hyperparameters::new(40, 12)
+            .embedding_dim(32)
+            .learning_rate(0.05)
+            .num_epochs(20)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        pooling.fit(&data).unwrap();
+
+        let lstm_mrr = mrr_score(&lstm, &data).unwrap();
+        let pooling_mrr = mrr_score(&pooling, &data).unwrap();
+        println!("LSTM MRR {}, pooling MRR {}", lstm_mrr, pooling_mrr);
+
+        (lstm_mrr, pooling_mrr)
+    }
+
+    #[test]
+    fn order_contribution() {
+        let mut rng = rand::XorShiftRng::from_seed([42; 16]);
+
+        // Every user walks through the items along four interleaved paths:
+        // the next item follows the one four steps back, so it is
+        // determined by the order of the history.
+        let item_range = Uniform::new(0, 40);
+        let mut interactions = Vec::new();
+        for user_id in 0..300 {
+            let start = item_range.sample(&mut rng);
+            let mut paths: Vec<_> = (0..4).map(|path| start + 10 * path).collect();
+            rng.shuffle(&mut paths);
+
+            for t in 0..12 {
+                interactions.push(Interaction::new(
+                    UserId(user_id),
+                    ItemId((paths[t % 4] + t as u64 / 4) % 40),
+                    Timestamp(t as i64),
+                ));
+            }
+        }
+
+        let (lstm_mrr, pooling_mrr) = compare_with_lstm(Interactions::from(interactions));
+        assert!(lstm_mrr > pooling_mrr);
+
+        // Every user draws distinct items at random from one of four
+        // clusters: only the set of past items is informative.
+        let cluster_range = Uniform::new(0, 4);
+        let offset_range = Uniform::new(0, 10);
+        let mut interactions = Vec::new();
+        for user_id in 0..300 {
+            let cluster = cluster_range.sample(&mut rng);
+            let mut items = Vec::new();
+            while items.len() < 7 {
+                let item_id = cluster * 10 + offset_range.sample(&mut rng);
+                if !items.contains(&item_id) {
+                    items.push(item_id);
+                }
+            }
+
+            for (t, &item_id) in items.iter().enumerate() {
+                interactions.push(Interaction::new(
+                    UserId(user_id),
+                    ItemId(item_id),
+                    Timestamp(t as i64),
+                ));
+            }
+        }
+
+        let (lstm_mrr, pooling_mrr) = compare_with_lstm(Interactions::from(interactions));
+        assert!(lstm_mrr < 1.25 * pooling_mrr && pooling_mrr < 1.25 * lstm_mrr);
+    }
+}
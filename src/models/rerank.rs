@@ -0,0 +1,344 @@
+//! Re-ranking of model predictions.
+//!
+//! [Mmr] diversifies top-k lists with maximal marginal relevance: it takes
+//! the best candidates of a wrapped model and greedily selects the items
+//! that are relevant but dissimilar to the items already selected.
+use std::collections::HashSet;
+use std::f32;
+
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+
+use super::top_k;
+use crate::ids::ItemId;
+use crate::{OnlineRankingModel, PredictionError};
+
+/// Wraps a model with maximal-marginal-relevance re-ranking of its
+/// top-k predictions, using cosine similarity between item embeddings.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Mmr<M> {
+    model: M,
+    embeddings: Array2<f32>,
+    lambda: f32,
+    num_candidates: usize,
+}
+
+impl<M: OnlineRankingModel> Mmr<M> {
+    /// Re-rank the predictions of `model` using the similarities of the
+    /// rows of `embeddings`, one per item.
+    pub fn new(model: M, embeddings: &Array2<f32>) -> Self {
+        let mut embeddings = embeddings.to_owned();
+
+        for mut row in embeddings.outer_iter_mut() {
+            let norm = row.dot(&row).sqrt();
+
+            if norm > 0.0 {
+                row /= norm;
+            }
+        }
+
+        Mmr {
+            model,
+            embeddings,
+            lambda: 0.5,
+            num_candidates: 100,
+        }
+    }
+
+    /// Set the trade-off between relevance and diversity: 1 keeps the
+    /// model's ranking, 0 only maximizes diversity. Defaults to 0.5.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `lambda` is not in `[0, 1]`.
+    pub fn lambda(mut self, lambda: f32) -> Self {
+        assert!(
+            lambda >= 0.0 && lambda <= 1.0,
+            "Lambda must be between 0 and 1."
+        );
+        self.lambda = lambda;
+        self
+    }
+
+    /// Re-rank the `num_candidates` items the model scores highest.
+    /// Defaults to 100.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_candidates` is zero.
+    pub fn num_candidates(mut self, num_candidates: usize) -> Self {
+        assert!(num_candidates > 0, "Must re-rank at least one candidate.");
+        self.num_candidates = num_candidates;
+        self
+    }
+
+    fn similarity(&self, x: ItemId, y: ItemId) -> f32 {
+        self.embeddings
+            .row(x.index())
+            .dot(&self.embeddings.row(y.index()))
+    }
+
+    /// Return `k` items for `user` as `(item_id, score)` pairs in the
+    /// order they were selected, where `score` is the wrapped model's score.
+    ///
+    /// Each step selects the candidate maximizing
+    /// `lambda * relevance - (1 - lambda) * max_similarity`, where
+    /// `relevance` is the model score min-max scaled to `[0, 1]` over the
+    /// candidates and `max_similarity` the largest cosine similarity to
+    /// an already selected item. Items in `exclude` are never returned.
+    pub fn predict_top_k(
+        &self,
+        user: &M::UserRepresentation,
+        k: usize,
+        exclude: &[ItemId],
+    ) -> Result<Vec<(ItemId, f32)>, PredictionError> {
+        let scores = self
+            .model
+            .predict_all_items(user, self.embeddings.nrows())?;
+
+        if !scores.iter().all(|score| score.is_finite()) {
+            return Err(PredictionError::InvalidPredictionValue);
+        }
+
+        let exclude: HashSet<_> = exclude.iter().collect();
+        let mut candidates = top_k(
+            scores
+                .into_iter()
+                .enumerate()
+                .map(|(idx, score)| (ItemId::from_index(idx), score))
+                .filter(|(item_id, _)| !exclude.contains(item_id))
+                .collect(),
+            self.num_candidates,
+        );
+
+        let max_score = candidates.first().map(|x| x.1).unwrap_or(0.0);
+        let min_score = candidates.last().map(|x| x.1).unwrap_or(0.0);
+        let relevance = |score: f32| {
+            if max_score > min_score {
+                (score - min_score) / (max_score - min_score)
+            } else {
+                1.0
+            }
+        };
+
+        let mut max_similarities = vec![f32::NEG_INFINITY; candidates.len()];
+        let mut selected = Vec::with_capacity(k);
+
+        while selected.len() < k && !candidates.is_empty() {
+            let marginal_relevance = |idx: usize| {
+                let diversity_penalty = if selected.is_empty() {
+                    0.0
+                } else {
+                    max_similarities[idx]
+                };
+
+                self.lambda * relevance(candidates[idx].1) - (1.0 - self.lambda) * diversity_penalty
+            };
+
+            // Candidates are sorted by score, so prefer lower indices on ties.
+            let best_idx = (0..candidates.len())
+                .max_by(|&x, &y| {
+                    marginal_relevance(x)
+                        .partial_cmp(&marginal_relevance(y))
+                        .unwrap()
+                        .then(y.cmp(&x))
+                })
+                .unwrap();
+
+            let best = candidates.remove(best_idx);
+            max_similarities.remove(best_idx);
+
+            for ((item_id, _), max_similarity) in candidates.iter().zip(&mut max_similarities) {
+                *max_similarity = max_similarity.max(self.similarity(best.0, *item_id));
+            }
+
+            selected.push(best);
+        }
+
+        Ok(selected)
+    }
+
+    /// Return the wrapped model.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+}
+
+impl<M: OnlineRankingModel> OnlineRankingModel for Mmr<M> {
+    type UserRepresentation = M::UserRepresentation;
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        self.model.user_representation(item_ids)
+    }
+
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.model.predict(user, item_ids)
+    }
+
+    fn predict_all_items(
+        &self,
+        user: &Self::UserRepresentation,
+        num_items: usize,
+    ) -> Result<Vec<f32>, PredictionError> {
+        self.model.predict_all_items(user, num_items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::distributions::{Distribution, Normal};
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+    use crate::ids::items;
+
+    /// Scores every item the same for every user.
+    #[derive(Debug)]
+    struct FixedScores(Vec<f32>);
+
+    impl OnlineRankingModel for FixedScores {
+        type UserRepresentation = ();
+
+        fn user_representation(&self, _item_ids: &[ItemId]) -> Result<(), PredictionError> {
+            Ok(())
+        }
+
+        fn predict(&self, _user: &(), item_ids: &[ItemId]) -> Result<Vec<f32>, PredictionError> {
+            Ok(item_ids
+                .iter()
+                .map(|&item_id| self.0[item_id.index()])
+                .collect())
+        }
+    }
+
+    /// Mean cosine distance between the pairs of recommended items.
+    fn intra_list_diversity(embeddings: &Array2<f32>, item_ids: &[ItemId]) -> f32 {
+        let cosine = |x: ItemId, y: ItemId| {
+            let (x, y) = (embeddings.row(x.index()), embeddings.row(y.index()));
+            x.dot(&y) / (x.dot(&x).sqrt() * y.dot(&y).sqrt())
+        };
+
+        let mut total = 0.0;
+        let mut num_pairs = 0;
+
+        for (idx, &x) in item_ids.iter().enumerate() {
+            for &y in &item_ids[idx + 1..] {
+                total += 1.0 - cosine(x, y);
+                num_pairs += 1;
+            }
+        }
+
+        total / num_pairs as f32
+    }
+
+    #[test]
+    fn diversifies_near_duplicates() {
+        // 10 orthogonal clusters of 10 near-duplicate items; the model
+        // prefers low-numbered clusters and scores duplicates almost equally.
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let normal = Normal::new(0.0, 0.02);
+        let (num_clusters, cluster_size, dim) = (10, 10, 16);
+        let embeddings =
+            Array2::from_shape_fn((num_clusters * cluster_size, dim), |(item_id, col)| {
+                let center = if item_id / cluster_size == col {
+                    1.0
+                } else {
+                    0.0
+                };
+                center + normal.sample(&mut rng) as f32
+            });
+        let scores: Vec<f32> = (0..num_clusters * cluster_size)
+            .map(|item_id| {
+                1.0 - (item_id / cluster_size) as f32 / num_clusters as f32
+                    - (item_id % cluster_size) as f32 / 1000.0
+            })
+            .collect();
+
+        let model = FixedScores(scores.clone());
+        let scored = scores
+            .iter()
+            .enumerate()
+            .map(|(idx, &score)| (ItemId::from_index(idx), score))
+            .collect();
+        let plain: Vec<_> = top_k(scored, 10)
+            .into_iter()
+            .map(|(item_id, _)| item_id)
+            .collect();
+
+        let mmr = Mmr::new(FixedScores(scores.clone()), &embeddings)
+            .lambda(0.6)
+            .num_candidates(50);
+        let reranked: Vec<_> = mmr
+            .predict_top_k(&(), 10, &[])
+            .unwrap()
+            .into_iter()
+            .map(|(item_id, _)| item_id)
+            .collect();
+
+        assert_eq!(reranked.len(), 10);
+        assert_eq!(reranked[0], ItemId(0));
+        assert!(
+            intra_list_diversity(&embeddings, &reranked)
+                > intra_list_diversity(&embeddings, &plain) + 0.2
+        );
+
+        // If users pick items in proportion to their scores, the expected
+        // hit rate of a list is its share of the total score.
+        let total: f32 = scores.iter().sum();
+        let hit_rate = |recommended: &[ItemId]| {
+            recommended
+                .iter()
+                .map(|&item_id| scores[item_id.index()])
+                .sum::<f32>()
+                / total
+        };
+        assert!(hit_rate(&reranked) > 0.9 * hit_rate(&plain));
+
+        // The wrapper does not change the model's own scores.
+        assert_eq!(
+            mmr.predict(&(), &items(&[3, 42])).unwrap(),
+            model.predict(&(), &items(&[3, 42])).unwrap()
+        );
+    }
+
+    #[test]
+    fn respects_exclusions_and_lambda() {
+        let embeddings =
+            Array2::from_shape_vec((3, 2), vec![1.0, 0.0, 1.0, 0.01, 0.0, 1.0]).unwrap();
+        let model = FixedScores(vec![1.0, 0.9, 0.5]);
+
+        // With lambda 1, the model's ranking is kept.
+        let mmr = Mmr::new(model, &embeddings).lambda(1.0);
+        let selected: Vec<_> = mmr
+            .predict_top_k(&(), 3, &[])
+            .unwrap()
+            .into_iter()
+            .map(|x| x.0)
+            .collect();
+        assert_eq!(selected, items(&[0, 1, 2]));
+
+        // Otherwise the near-duplicate of item 0 drops.
+        let mmr = mmr.lambda(0.5);
+        let selected: Vec<_> = mmr
+            .predict_top_k(&(), 2, &[])
+            .unwrap()
+            .into_iter()
+            .map(|x| x.0)
+            .collect();
+        assert_eq!(selected, items(&[0, 2]));
+
+        let selected: Vec<_> = mmr
+            .predict_top_k(&(), 3, &[ItemId(0)])
+            .unwrap()
+            .into_iter()
+            .map(|x| x.0)
+            .collect();
+        assert_eq!(selected, items(&[1, 2]));
+    }
+}
@@ -0,0 +1,416 @@
+//! Sequential association-rule model.
+//!
+//! The model mines rules of the form `{A, B} -> C` from users' sequences:
+//! the rule holds at a position of a sequence if `A` and `B` are among the
+//! `window` items before it and `C` is the item at that position. Its
+//! support is the number of positions where it holds, and its confidence
+//! the fraction of the positions following `{A, B}` at which it holds.
+//!
+//! Candidates are scored by the confidence of the best rule fired by the
+//! user's recent items, so every recommendation can be explained by the
+//! rule that produced it. Items no rule recommends are ranked below all
+//! rule recommendations, by popularity.
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::CompressedInteractions;
+use crate::ids::ItemId;
+use crate::{FittingError, OnlineRankingModel, PredictionError};
+
+/// An association rule `antecedent -> consequent`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    antecedent: Vec<ItemId>,
+    consequent: ItemId,
+    support: usize,
+    confidence: f32,
+}
+
+impl Rule {
+    /// Return the items that fire the rule, in ascending order.
+    pub fn antecedent(&self) -> &[ItemId] {
+        &self.antecedent
+    }
+
+    /// Return the recommended item.
+    pub fn consequent(&self) -> ItemId {
+        self.consequent
+    }
+
+    /// Return the number of training positions where the rule holds.
+    pub fn support(&self) -> usize {
+        self.support
+    }
+
+    /// Return the fraction of training positions following the
+    /// antecedent where the rule holds.
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+}
+
+/// User representation of the rule model: the distinct items among the
+/// last `window` items of the history, in ascending order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RuleUser {
+    item_ids: Vec<ItemId>,
+}
+
+/// Association-rule model over item sequences.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RuleModel {
+    num_items: usize,
+    window: usize,
+    max_rules_per_antecedent: usize,
+    max_antecedent_len: usize,
+    rules: HashMap<Vec<ItemId>, Vec<Rule>>,
+    popularity: Vec<f32>,
+    num_interactions: f32,
+}
+
+/// Return all non-empty subsets of the ascending `item_ids` with at most
+/// `max_len` elements, each in ascending order.
+fn subsets(item_ids: &[ItemId], max_len: usize) -> Vec<Vec<ItemId>> {
+    let mut subsets = Vec::new();
+    let mut stack: Vec<(Vec<ItemId>, usize)> = vec![(Vec::new(), 0)];
+
+    while let Some((prefix, start)) = stack.pop() {
+        if prefix.len() == max_len {
+            continue;
+        }
+
+        for (idx, &item_id) in item_ids.iter().enumerate().skip(start) {
+            let mut subset = prefix.clone();
+            subset.push(item_id);
+
+            subsets.push(subset.clone());
+            stack.push((subset, idx + 1));
+        }
+    }
+
+    subsets
+}
+
+/// Return the distinct items of `item_ids` in ascending order.
+fn distinct(item_ids: &[ItemId]) -> Vec<ItemId> {
+    let mut item_ids = item_ids.to_owned();
+    item_ids.sort();
+    item_ids.dedup();
+    item_ids
+}
+
+impl RuleModel {
+    /// Create an unfitted model over `num_items` items, which has no rules
+    /// and scores all items equally.
+    pub fn new(num_items: usize) -> Self {
+        RuleModel {
+            num_items,
+            window: 3,
+            max_rules_per_antecedent: 10,
+            max_antecedent_len: 1,
+            rules: HashMap::new(),
+            popularity: vec![0.0; num_items],
+            num_interactions: 0.0,
+        }
+    }
+
+    /// Mine rules whose antecedents are among the `window` items before
+    /// the consequent, and fire rules from the last `window` items of the
+    /// history. Defaults to 3.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is zero.
+    pub fn window(mut self, window: usize) -> Self {
+        assert!(window > 0, "Window must be at least 1.");
+        self.window = window;
+        self
+    }
+
+    /// Keep only the `max_rules` most confident rules for each antecedent.
+    /// Defaults to 10.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_rules` is zero.
+    pub fn max_rules_per_antecedent(mut self, max_rules: usize) -> Self {
+        assert!(max_rules > 0, "Must keep at least one rule per antecedent.");
+        self.max_rules_per_antecedent = max_rules;
+        self
+    }
+
+    /// Mine the rules with antecedents of at most `max_antecedent_len`
+    /// items, at least `min_support` supporting positions, and confidence
+    /// of at least `min_confidence`.
+    ///
+    /// The number of candidate antecedents grows combinatorially with
+    /// `max_antecedent_len` and the window, so both should be kept small.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_antecedent_len` is zero or `min_confidence` is not
+    /// in `[0, 1]`.
+    pub fn fit(
+        &mut self,
+        interactions: &CompressedInteractions,
+        min_support: usize,
+        min_confidence: f32,
+        max_antecedent_len: usize,
+    ) -> Result<(), FittingError> {
+        assert!(
+            max_antecedent_len > 0,
+            "Antecedents must have at least one item."
+        );
+        assert!(
+            min_confidence >= 0.0 && min_confidence <= 1.0,
+            "Minimum confidence must be between 0 and 1."
+        );
+
+        if interactions.num_items() != self.num_items {
+            return Err(FittingError::ItemCountMismatch {
+                model_items: self.num_items,
+                data_items: interactions.num_items(),
+            });
+        }
+
+        let mut antecedent_counts: HashMap<Vec<ItemId>, usize> = HashMap::new();
+        let mut rule_counts: HashMap<(Vec<ItemId>, ItemId), usize> = HashMap::new();
+        let mut popularity = vec![0.0; self.num_items];
+
+        for user in interactions.iter_users() {
+            for (idx, &item_id) in user.item_ids.iter().enumerate() {
+                popularity[item_id.index()] += 1.0;
+
+                let preceding = distinct(&user.item_ids[idx.saturating_sub(self.window)..idx]);
+
+                for antecedent in subsets(&preceding, max_antecedent_len) {
+                    *antecedent_counts.entry(antecedent.clone()).or_insert(0) += 1;
+
+                    if !antecedent.contains(&item_id) {
+                        *rule_counts.entry((antecedent, item_id)).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        let num_interactions: f32 = popularity.iter().sum();
+
+        if num_interactions == 0.0 {
+            return Err(FittingError::NoInteractions);
+        }
+
+        let mut rules: HashMap<Vec<ItemId>, Vec<Rule>> = HashMap::new();
+
+        for ((antecedent, consequent), support) in rule_counts {
+            let confidence = support as f32 / antecedent_counts[&antecedent] as f32;
+
+            if support >= min_support && confidence >= min_confidence {
+                rules.entry(antecedent.clone()).or_default().push(Rule {
+                    antecedent,
+                    consequent,
+                    support,
+                    confidence,
+                });
+            }
+        }
+
+        for antecedent_rules in rules.values_mut() {
+            antecedent_rules.sort_by(|x, y| {
+                y.confidence
+                    .partial_cmp(&x.confidence)
+                    .unwrap()
+                    .then(y.support.cmp(&x.support))
+                    .then(x.consequent.cmp(&y.consequent))
+            });
+            antecedent_rules.truncate(self.max_rules_per_antecedent);
+        }
+
+        self.max_antecedent_len = max_antecedent_len;
+        self.rules = rules;
+        self.popularity = popularity;
+        self.num_interactions = num_interactions;
+
+        Ok(())
+    }
+
+    /// Iterate over all mined rules, in no particular order.
+    pub fn rules(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.values().flat_map(|rules| rules.iter())
+    }
+
+    /// Return the best rule fired by `user` for each recommended item.
+    fn fired_rules(&self, user: &RuleUser) -> HashMap<ItemId, &Rule> {
+        let mut fired: HashMap<ItemId, &Rule> = HashMap::new();
+
+        for antecedent in subsets(&user.item_ids, self.max_antecedent_len) {
+            for rule in self.rules.get(&antecedent).into_iter().flatten() {
+                let best = fired.entry(rule.consequent).or_insert(rule);
+
+                if (rule.confidence, rule.support) > (best.confidence, best.support) {
+                    *best = rule;
+                }
+            }
+        }
+
+        fired
+    }
+
+    /// Return the rule that the recent items of `history` fire to
+    /// recommend `item_id`, or `None` if `item_id` is only ranked by
+    /// popularity.
+    pub fn explain(&self, history: &[ItemId], item_id: ItemId) -> Option<Rule> {
+        let user = self.user_representation(history).ok()?;
+
+        self.fired_rules(&user).remove(&item_id).cloned()
+    }
+}
+
+impl OnlineRankingModel for RuleModel {
+    type UserRepresentation = RuleUser;
+
+    fn user_representation(
+        &self,
+        item_ids: &[ItemId],
+    ) -> Result<Self::UserRepresentation, PredictionError> {
+        let start = item_ids.len().saturating_sub(self.window);
+
+        Ok(RuleUser {
+            item_ids: distinct(&item_ids[start..]),
+        })
+    }
+
+    /// Score items recommended by a rule by one plus the best rule's
+    /// confidence, and all other items by their popularity, scaled to
+    /// stay below 1.
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        let fired = self.fired_rules(user);
+
+        Ok(item_ids
+            .iter()
+            .map(|item_id| match fired.get(item_id) {
+                Some(rule) => 1.0 + rule.confidence,
+                None => {
+                    let count = self.popularity.get(item_id.index()).cloned().unwrap_or(0.0);
+                    count / (self.num_interactions + 1.0)
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::ids::{items, Timestamp, UserId};
+
+    fn data() -> CompressedInteractions {
+        // 0 and 1 together are always followed by 2; either alone is
+        // followed by 2 only half the time.
+        let sequences = vec![vec![0, 1, 2], vec![1, 0, 2], vec![0, 3], vec![1, 4]];
+        let interactions: Vec<_> = sequences
+            .into_iter()
+            .enumerate()
+            .flat_map(|(user_id, items)| {
+                items
+                    .into_iter()
+                    .enumerate()
+                    .map(move |(timestamp, item_id)| {
+                        Interaction::new(
+                            UserId::from_index(user_id),
+                            ItemId(item_id),
+                            Timestamp(timestamp as i64),
+                        )
+                    })
+            })
+            .collect();
+
+        Interactions::from(interactions).to_compressed()
+    }
+
+    #[test]
+    fn subsets_are_sorted_and_bounded() {
+        let mut all = subsets(&items(&[1, 2, 3]), 2);
+        all.sort();
+        assert_eq!(
+            all,
+            vec![
+                items(&[1]),
+                items(&[1, 2]),
+                items(&[1, 3]),
+                items(&[2]),
+                items(&[2, 3]),
+                items(&[3])
+            ]
+        );
+    }
+
+    #[test]
+    fn mines_rules() {
+        let data = data();
+        let mut model = RuleModel::new(data.num_items());
+        model.fit(&data, 2, 0.6, 2).unwrap();
+
+        let rules: Vec<_> = model.rules().collect();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].antecedent(), &items(&[0, 1])[..]);
+        assert_eq!(rules[0].consequent(), ItemId(2));
+        assert_eq!(rules[0].support(), 2);
+        assert_eq!(rules[0].confidence(), 1.0);
+
+        // Lowering the confidence threshold admits the single-item rules.
+        model.fit(&data, 2, 0.5, 2).unwrap();
+        let mut antecedents: Vec<_> = model
+            .rules()
+            .map(|rule| rule.antecedent().to_owned())
+            .collect();
+        antecedents.sort();
+        assert_eq!(antecedents, vec![items(&[0]), items(&[0, 1]), items(&[1])]);
+
+        // Storage is bounded per antecedent.
+        let mut model = RuleModel::new(data.num_items()).max_rules_per_antecedent(1);
+        model.fit(&data, 1, 0.0, 1).unwrap();
+        assert_eq!(
+            model
+                .rules()
+                .filter(|rule| rule.antecedent() == [ItemId(0)])
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn predictions_and_explanations() {
+        let data = data();
+        let mut model = RuleModel::new(data.num_items());
+        model.fit(&data, 2, 0.5, 2).unwrap();
+
+        let history = items(&[3, 1, 0]);
+        let user = model.user_representation(&history).unwrap();
+        let scores = model.predict(&user, &items(&[0, 1, 2, 3, 4])).unwrap();
+        assert_eq!(scores[2], 2.0);
+        assert!(scores
+            .iter()
+            .enumerate()
+            .all(|(idx, &score)| idx == 2 || score < 1.0));
+
+        let rule = model.explain(&history, ItemId(2)).unwrap();
+        assert_eq!(rule.antecedent(), &items(&[0, 1])[..]);
+        assert!(rule
+            .antecedent()
+            .iter()
+            .all(|item_id| history.contains(item_id)));
+
+        // Item 1 alone fires the weaker rule, and nothing recommends 4.
+        assert_eq!(
+            model.explain(&items(&[1]), ItemId(2)).unwrap().confidence(),
+            0.5
+        );
+        assert!(model.explain(&history, ItemId(4)).is_none());
+    }
+}
@@ -0,0 +1,179 @@
+//! Negative sampling strategies used when fitting sequence models.
+//!
+//! Each training step pairs an observed (positive) item with a sampled
+//! negative item. The strategy for choosing negatives can be changed by
+//! passing a [MinibatchSampler] to the `negative_sampler` method of the
+//! model hyperparameters.
+use std::fmt::Debug;
+
+use rand::distributions::{Distribution, Uniform};
+use rand::RngCore;
+
+use crate::data::CompressedInteractions;
+use crate::ids::{ItemId, Timestamp, UserId};
+
+/// Trait describing strategies for sampling negative items.
+pub trait MinibatchSampler: Debug + Send + Sync {
+    /// Sample a negative item for the `positive` item of user `user_id`.
+    fn sample_negative(&self, user_id: UserId, positive: ItemId, rng: &mut dyn RngCore) -> ItemId;
+}
+
+/// Samples negatives uniformly from all items. This is the default.
+#[derive(Clone, Debug)]
+pub struct UniformSampler {
+    item_range: Uniform<usize>,
+}
+
+impl UniformSampler {
+    /// Create a sampler over `num_items` items.
+    pub fn new(num_items: usize) -> Self {
+        UniformSampler {
+            item_range: Uniform::new(0, num_items),
+        }
+    }
+}
+
+impl MinibatchSampler for UniformSampler {
+    fn sample_negative(&self, _: UserId, _: ItemId, rng: &mut dyn RngCore) -> ItemId {
+        ItemId::from_index(self.item_range.sample(rng))
+    }
+}
+
+/// Samples negatives in proportion to how often they appear in
+/// the interactions, so that popular items are used as negatives
+/// more often.
+#[derive(Clone, Debug)]
+pub struct PopularitySampler {
+    cumulative_counts: Vec<usize>,
+}
+
+impl PopularitySampler {
+    /// Create a sampler from the item counts in `interactions`.
+    pub fn new(interactions: &CompressedInteractions) -> Self {
+        let mut counts = vec![0; interactions.num_items()];
+
+        for user in interactions.iter_users() {
+            for &item_id in user.item_ids {
+                counts[item_id.index()] += 1;
+            }
+        }
+
+        Self::from_counts(&counts)
+    }
+
+    fn from_counts(counts: &[usize]) -> Self {
+        let cumulative_counts = counts
+            .iter()
+            .scan(0, |total, &count| {
+                *total += count;
+                Some(*total)
+            })
+            .collect();
+
+        PopularitySampler { cumulative_counts }
+    }
+}
+
+impl MinibatchSampler for PopularitySampler {
+    fn sample_negative(&self, _: UserId, _: ItemId, rng: &mut dyn RngCore) -> ItemId {
+        let total = *self.cumulative_counts.last().unwrap_or(&0);
+
+        if total == 0 {
+            return ItemId::from_index(
+                Uniform::new(0, self.cumulative_counts.len().max(1)).sample(rng),
+            );
+        }
+
+        let threshold = Uniform::new(0, total).sample(rng);
+
+        // First item whose cumulative count exceeds the threshold.
+        ItemId::from_index(
+            self.cumulative_counts
+                .partition_point(|&count| count <= threshold),
+        )
+    }
+}
+
+/// Samples negatives uniformly from the items that were interacted
+/// with between two timestamps (inclusive), so that negatives are
+/// drawn from the catalog as it was at that time.
+#[derive(Clone, Debug)]
+pub struct TimeBoundedSampler {
+    item_ids: Vec<ItemId>,
+}
+
+impl TimeBoundedSampler {
+    /// Create a sampler over the items interacted with in
+    /// `interactions` between `start` and `end`.
+    pub fn new(interactions: &CompressedInteractions, start: Timestamp, end: Timestamp) -> Self {
+        let mut seen = vec![false; interactions.num_items()];
+
+        for user in interactions.iter_users() {
+            for (&item_id, &timestamp) in izip!(user.item_ids, user.timestamps) {
+                if timestamp >= start && timestamp <= end {
+                    seen[item_id.index()] = true;
+                }
+            }
+        }
+
+        TimeBoundedSampler {
+            item_ids: seen
+                .iter()
+                .enumerate()
+                .filter(|(_, seen)| **seen)
+                .map(|(item_idx, _)| ItemId::from_index(item_idx))
+                .collect(),
+        }
+    }
+}
+
+impl MinibatchSampler for TimeBoundedSampler {
+    fn sample_negative(&self, _: UserId, positive: ItemId, rng: &mut dyn RngCore) -> ItemId {
+        if self.item_ids.is_empty() {
+            return positive;
+        }
+
+        self.item_ids[Uniform::new(0, self.item_ids.len()).sample(rng)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+
+    #[test]
+    fn popularity_sampler() {
+        let sampler = PopularitySampler::from_counts(&[0, 90, 0, 10, 0]);
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+
+        let mut counts = vec![0; 5];
+        for _ in 0..10_000 {
+            let negative = sampler.sample_negative(UserId(0), ItemId(0), &mut rng);
+            counts[negative.index()] += 1;
+        }
+
+        assert_eq!(counts[0] + counts[2] + counts[4], 0);
+        assert!(counts[1] > 8_500 && counts[3] > 500);
+    }
+
+    #[test]
+    fn time_bounded_sampler() {
+        let interactions = Interactions::from(vec![
+            Interaction::new(UserId(0), ItemId(0), Timestamp(1)),
+            Interaction::new(UserId(0), ItemId(1), Timestamp(5)),
+            Interaction::new(UserId(1), ItemId(2), Timestamp(6)),
+            Interaction::new(UserId(1), ItemId(3), Timestamp(10)),
+        ])
+        .to_compressed();
+        let sampler = TimeBoundedSampler::new(&interactions, Timestamp(5), Timestamp(6));
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+
+        for _ in 0..100 {
+            let negative = sampler.sample_negative(UserId(0), ItemId(0), &mut rng);
+            assert!(negative == ItemId(1) || negative == ItemId(2));
+        }
+    }
+}
@@ -1,27 +1,129 @@
-use rand::distributions::Distribution;
-use rand::distributions::Uniform;
-use rand::{Rng, SeedableRng, XorShiftRng};
+//! Shared fitting and inference code of the sequence models.
+//!
+//! Fitting, which trains on several threads, and perplexity evaluation
+//! require the `training` feature; inference is always available.
+#[cfg(feature = "training")]
+use std::collections::hash_map::Entry;
+#[cfg(feature = "training")]
+use std::collections::HashMap;
+#[cfg(feature = "training")]
+use std::fmt::Display;
+#[cfg(feature = "training")]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "training")]
+use std::sync::{Mutex, OnceLock};
+#[cfg(feature = "training")]
+use std::time::Instant;
+
+use rand::XorShiftRng;
+#[cfg(feature = "training")]
+use rand::{Rng, SeedableRng};
+#[cfg(feature = "training")]
 use rayon::prelude::*;
 
 use wyrm;
-use wyrm::optim::{Optimizer as Optim, Optimizers, Synchronizable};
-use wyrm::{BoxedNode, DataInput, Variable};
+use wyrm::optim::Optimizers;
+#[cfg(feature = "training")]
+use wyrm::optim::{Optimizer as Optim, Synchronizable};
+use wyrm::{Arr, BoxedNode, DataInput, Variable};
 
-use super::{ImplicitUser, Loss, Parallelism};
-use crate::data::CompressedInteractions;
-use crate::{FittingError, ItemId, OnlineRankingModel, PredictionError};
+use super::metadata::ModelMetadata;
+#[cfg(feature = "training")]
+use super::metadata::{DatasetStats, FitStats};
+use super::sampling::MinibatchSampler;
+#[cfg(feature = "training")]
+use super::sampling::UniformSampler;
+use super::{top_k, ImplicitUser, Loss, Parallelism};
+#[cfg(feature = "training")]
+use crate::config;
+#[cfg(feature = "training")]
+use crate::data::{CompressedInteractions, CompressedInteractionsUser};
+#[cfg(feature = "training")]
+use crate::ids::UserId;
+use crate::ids::{ItemId, Timestamp};
+#[cfg(feature = "training")]
+use crate::FittingError;
+use crate::{check_output_len, OnlineRankingModel, PredictionError};
 
 pub trait SequenceModelParameters {
     type Output;
+    fn num_items(&self) -> usize;
+    fn num_users(&mut self) -> &mut Option<usize>;
+    /// The record of the last successful fit, replaced by each fit.
+    fn metadata(&mut self) -> &mut Option<ModelMetadata>;
+    /// The hyperparameters, as a JSON object.
+    fn hyperparameters(&self) -> String;
     fn max_sequence_length(&self) -> usize;
+    /// The maximum length of training sequences in (zero-indexed) `epoch`.
+    fn sequence_length(&self, _epoch: usize) -> usize {
+        self.max_sequence_length()
+    }
+    /// The number of most recent interactions of each user to use, if limited.
+    fn max_history(&self) -> Option<usize> {
+        None
+    }
     fn num_threads(&self) -> usize;
+    /// Whether training losses are summed in `f64` rather than `f32`.
+    fn accumulate_f64(&self) -> bool {
+        false
+    }
     fn rng(&mut self) -> &mut XorShiftRng;
-    fn optimizer(&self) -> Optimizers;
+    /// The negative sampler to use, if different from uniform sampling.
+    fn negative_sampler(&self) -> Option<&dyn MinibatchSampler>;
+    fn learning_rate(&self) -> f32;
+    fn warmup_steps(&self) -> usize;
+    /// The probability of zeroing each dropout-masked activation in training.
+    fn dropout(&self) -> f32 {
+        0.0
+    }
+    /// The number of distinct values of the context feature, if the model
+    /// uses one.
+    fn context_cardinality(&self) -> Option<usize> {
+        None
+    }
+    /// Whether `parameter` should be excluded from gradient updates.
+    fn is_frozen(&self, _parameter: &Variable<wyrm::ParameterNode>) -> bool {
+        false
+    }
+    /// Whether fitting should stop at the start of the next epoch.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+    /// Whether the model has been successfully fitted, and can be used
+    /// for inference.
+    fn is_fitted(&self) -> bool {
+        true
+    }
+    fn optimizer(&self, learning_rate: f32) -> Optimizers;
     fn parallelism(&self) -> &Parallelism;
     fn loss(&self) -> &Loss;
     fn num_epochs(&self) -> usize;
+    /// Prepare `model` for training in (zero-indexed) `epoch`.
+    fn start_epoch(&self, _model: &Self::Output, _epoch: usize) {}
+    /// The number of sequences processed together in each training step.
+    fn sequence_batch_size(&self) -> usize {
+        1
+    }
     fn build(&self) -> Self::Output;
+    /// Build a model processing `batch_size` sequences at once, one per row
+    /// of its inputs. Only called with batch sizes above 1 by models whose
+    /// [sequence_batch_size](SequenceModelParameters::sequence_batch_size)
+    /// allows it.
+    fn build_batch(&self, _batch_size: usize) -> Self::Output {
+        self.build()
+    }
+    /// Whether item ids not smaller than [num_items](SequenceModelParameters::num_items)
+    /// can be scored, rather than rejected with [PredictionError::ItemIdOutOfRange].
+    fn scores_unknown_items(&self) -> bool {
+        false
+    }
     fn predict_single(&self, user: &[f32], item_idx: usize) -> f32;
+    /// Score items `0..num_items`.
+    fn predict_all(&self, user: &[f32], num_items: usize) -> Vec<f32> {
+        (0..num_items)
+            .map(|item_idx| self.predict_single(user, item_idx))
+            .collect()
+    }
 }
 
 /// Trait expressing a sequence model.
@@ -42,13 +144,330 @@ pub trait SequenceModel {
         &[Variable<BoxedNode>],
     );
     fn hidden_states(&mut self) -> &mut [Variable<BoxedNode>];
+    /// Return the per-step context inputs. Empty if the model
+    /// does not use context features.
+    fn contexts(&self) -> &[Variable<wyrm::IndexInputNode>];
+    /// Return the per-step inputs holding the time elapsed since the
+    /// previous interaction. Empty if the model ignores timestamps.
+    fn time_deltas(&self) -> &[Variable<wyrm::InputNode>];
+    /// Return the inputs holding dropout masks, which are all ones
+    /// outside training. Empty if the model does not use dropout.
+    fn dropout_masks(&self) -> &[Variable<wyrm::InputNode>];
+    /// Return the per-step inputs holding the weight of each step's
+    /// target interaction, by which its loss is multiplied. Empty if
+    /// the model ignores interaction weights.
+    fn weights(&self) -> &[Variable<wyrm::InputNode>];
+    /// Set the index of the user each sequence belongs to. Models
+    /// without per-user parameters ignore it.
+    fn set_users(&self, user_ids: &[usize]);
+}
+
+/// A contiguous chunk of a single user's interactions.
+#[cfg(feature = "training")]
+#[derive(Clone, Copy)]
+struct Subsequence<'a> {
+    user_id: UserId,
+    item_ids: &'a [ItemId],
+    timestamps: &'a [Timestamp],
+    contexts: Option<&'a [usize]>,
+    weights: Option<&'a [f32]>,
+}
+
+#[cfg(feature = "training")]
+impl<'a> Subsequence<'a> {
+    /// Split into consecutive pieces of at most `length` interactions.
+    fn chunks(self, length: usize) -> impl Iterator<Item = Subsequence<'a>> {
+        let length = length.max(1);
+
+        (0..self.item_ids.len()).step_by(length).map(move |start| {
+            let stop = (start + length).min(self.item_ids.len());
+
+            Subsequence {
+                user_id: self.user_id,
+                item_ids: &self.item_ids[start..stop],
+                timestamps: &self.timestamps[start..stop],
+                contexts: self.contexts.map(|x| &x[start..stop]),
+                weights: self.weights.map(|x| &x[start..stop]),
+            }
+        })
+    }
+}
+
+/// The time elapsed before step `idx` of a sequence with `timestamps`,
+/// or one time unit if there are none.
+fn time_delta(timestamps: Option<&[Timestamp]>, idx: usize) -> f32 {
+    match timestamps {
+        Some(timestamps) if idx > 0 && idx < timestamps.len() => {
+            timestamps[idx].saturating_sub(timestamps[idx - 1]) as f32
+        }
+        Some(_) if idx == 0 => 0.0,
+        _ => 1.0,
+    }
+}
+
+/// Set the time delta inputs of `model` from the gaps between consecutive
+/// `timestamps`, or to one time unit per step if there are none.
+pub fn set_time_deltas<U: SequenceModel>(model: &U, timestamps: Option<&[Timestamp]>) {
+    for (idx, input) in model.time_deltas().iter().enumerate() {
+        let shape = input.value().dim();
+        input.set_value(&Arr::from_elem(shape, time_delta(timestamps, idx)));
+    }
+}
+
+/// Set the weight inputs of `model` to the weights of each step's target,
+/// the interaction following it, or to 1 if there are no weights.
+#[cfg(feature = "training")]
+fn set_weights<U: SequenceModel>(model: &U, weights: Option<&[f32]>) {
+    for (idx, input) in model.weights().iter().enumerate() {
+        let weight = weights
+            .and_then(|weights| weights.get(idx + 1))
+            .cloned()
+            .unwrap_or(1.0);
+
+        let shape = input.value().dim();
+        input.set_value(&Arr::from_elem(shape, weight));
+    }
+}
+
+/// Set the user of `model`'s single sequence to `user_id`.
+#[cfg(feature = "training")]
+pub fn set_user<U: SequenceModel>(model: &U, user_id: UserId) {
+    model.set_users(&[user_id.index()]);
+}
+
+/// Resample the dropout masks of `model`, zeroing each entry with
+/// probability `dropout` and scaling the rest by `1 / (1 - dropout)`
+/// so that no rescaling is needed outside training.
+#[cfg(feature = "training")]
+fn resample_dropout_masks<U: SequenceModel, R: Rng>(model: &U, dropout: f32, rng: &mut R) {
+    let scale = 1.0 / (1.0 - dropout);
+
+    for mask in model.dropout_masks() {
+        let shape = mask.value().dim();
+        mask.set_value(&Arr::from_shape_fn(shape, |_| {
+            if rng.gen::<f32>() < dropout {
+                0.0
+            } else {
+                scale
+            }
+        }));
+    }
+}
+
+/// Set the inputs of `model` to train on `subsequence`, returning the
+/// index of the loss to minimize and the number of examples it covers.
+#[cfg(feature = "training")]
+fn set_sequence<U: SequenceModel, T: SequenceModelParameters<Output = U>>(
+    parameters: &T,
+    model: &U,
+    subsequence: &Subsequence,
+    sampler: &dyn MinibatchSampler,
+    thread_rng: &mut XorShiftRng,
+) -> (usize, usize) {
+    let item_ids = subsequence.item_ids;
+
+    if let Some(contexts) = subsequence.contexts {
+        for (&context, input) in izip!(contexts, model.contexts()) {
+            input.set_value(context);
+        }
+    }
+
+    set_time_deltas(model, Some(subsequence.timestamps));
+    set_weights(model, subsequence.weights);
+    set_user(model, subsequence.user_id);
+    resample_dropout_masks(model, parameters.dropout(), thread_rng);
+
+    let (inputs, outputs, negatives, hidden_states) = model.state();
+
+    for (&input_id, &output_id, input, output, negative, hidden) in izip!(
+        item_ids,
+        item_ids.iter().skip(1),
+        inputs,
+        outputs,
+        negatives,
+        hidden_states
+    ) {
+        input.set_value(input_id.index());
+
+        let negative_idx = if parameters.loss() == &Loss::WARP {
+            hidden.forward();
+            let hidden_state = hidden.value();
+
+            sample_warp_negative(
+                parameters,
+                hidden_state.as_slice().unwrap(),
+                subsequence.user_id,
+                output_id.index(),
+                sampler,
+                thread_rng,
+            )
+        } else {
+            sampler
+                .sample_negative(subsequence.user_id, output_id, thread_rng)
+                .index()
+        };
+
+        output.set_value(output_id.index());
+        negative.set_value(negative_idx);
+    }
+
+    // Get the loss at the end of the sequence.
+    let loss_idx = item_ids.len().saturating_sub(2);
+
+    (loss_idx, loss_idx + 1)
+}
+
+/// Set the inputs of `model`, built for `batch_size` sequences, to train on
+/// `batch`, one subsequence per row. Steps past the end of a subsequence,
+/// and the rows of a batch shorter than `batch_size`, are given zero
+/// weight. Returns the index of the loss to minimize and the number of
+/// examples it covers.
+#[cfg(feature = "training")]
+fn set_batch<U: SequenceModel, T: SequenceModelParameters<Output = U>>(
+    parameters: &T,
+    model: &U,
+    batch: &[Subsequence],
+    batch_size: usize,
+    sampler: &dyn MinibatchSampler,
+    thread_rng: &mut XorShiftRng,
+) -> (usize, usize) {
+    let max_len = batch
+        .iter()
+        .map(|subsequence| subsequence.item_ids.len())
+        .max()
+        .unwrap_or(0);
+
+    for (idx, input) in model.contexts().iter().enumerate() {
+        let contexts: Vec<_> = (0..batch_size)
+            .map(|row| {
+                batch
+                    .get(row)
+                    .and_then(|subsequence| subsequence.contexts)
+                    .and_then(|contexts| contexts.get(idx))
+                    .cloned()
+                    .unwrap_or(0)
+            })
+            .collect();
+        input.set_value(&contexts[..]);
+    }
+    for (idx, input) in model.time_deltas().iter().enumerate() {
+        let shape = input.value().dim();
+        input.set_value(&Arr::from_shape_fn(shape, |(row, _)| {
+            time_delta(
+                batch.get(row).map(|subsequence| subsequence.timestamps),
+                idx,
+            )
+        }));
+    }
+    for (idx, input) in model.weights().iter().enumerate() {
+        let shape = input.value().dim();
+        input.set_value(&Arr::from_shape_fn(shape, |(row, _)| {
+            match batch.get(row) {
+                Some(subsequence) if idx + 1 < subsequence.item_ids.len() => subsequence
+                    .weights
+                    .and_then(|weights| weights.get(idx + 1))
+                    .cloned()
+                    .unwrap_or(1.0),
+                _ => 0.0,
+            }
+        }));
+    }
+    let user_ids: Vec<_> = (0..batch_size)
+        .map(|row| {
+            batch
+                .get(row)
+                .map_or(0, |subsequence| subsequence.user_id.index())
+        })
+        .collect();
+    model.set_users(&user_ids);
+    resample_dropout_masks(model, parameters.dropout(), thread_rng);
+
+    let (inputs, outputs, negatives, hidden_states) = model.state();
+
+    for (idx, input, output, negative, hidden) in
+        izip!(0..max_len - 1, inputs, outputs, negatives, hidden_states)
+    {
+        // The rows with a target at this step, and empty rows elsewhere.
+        let rows: Vec<_> = (0..batch_size)
+            .map(|row| {
+                batch
+                    .get(row)
+                    .filter(|subsequence| idx + 1 < subsequence.item_ids.len())
+            })
+            .collect();
+        let input_ids: Vec<_> = rows
+            .iter()
+            .map(|row| row.map_or(0, |subsequence| subsequence.item_ids[idx].index()))
+            .collect();
+        let output_ids: Vec<_> = rows
+            .iter()
+            .map(|row| row.map_or(0, |subsequence| subsequence.item_ids[idx + 1].index()))
+            .collect();
+
+        input.set_value(&input_ids[..]);
+
+        if parameters.loss() == &Loss::WARP {
+            hidden.forward();
+        }
+
+        let negative_ids: Vec<_> = izip!(&rows, &output_ids)
+            .enumerate()
+            .map(|(row_idx, (row, &output_idx))| match row {
+                Some(subsequence) if parameters.loss() == &Loss::WARP => {
+                    let hidden_state = hidden.value();
+
+                    sample_warp_negative(
+                        parameters,
+                        hidden_state.row(row_idx).as_slice().unwrap(),
+                        subsequence.user_id,
+                        output_idx,
+                        sampler,
+                        thread_rng,
+                    )
+                }
+                Some(subsequence) => sampler
+                    .sample_negative(
+                        subsequence.user_id,
+                        ItemId::from_index(output_idx),
+                        thread_rng,
+                    )
+                    .index(),
+                None => 0,
+            })
+            .collect();
+
+        output.set_value(&output_ids[..]);
+        negative.set_value(&negative_ids[..]);
+    }
+
+    let examples = batch
+        .iter()
+        .map(|subsequence| subsequence.item_ids.len() - 1)
+        .sum();
+
+    (max_len.saturating_sub(2), examples)
+}
+
+/// Mark the graph behind `roots`, each of which has been run forward in
+/// order, as not evaluated, so that the next forward pass picks up new
+/// input values.
+///
+/// wyrm only clears a node once every forward pass through it has been
+/// matched by a backward pass, and asserts as much in debug builds, so
+/// zero gradients are propagated back from the roots, latest first.
+fn reset_graph(roots: &mut [Variable<BoxedNode>]) {
+    for root in roots.iter_mut().rev() {
+        root.backward(0.0);
+    }
 }
 
+#[cfg(feature = "training")]
 fn sample_warp_negative<U: SequenceModel, T: SequenceModelParameters<Output = U>>(
     parameters: &T,
     hidden_state: &[f32],
+    user_id: UserId,
     positive_idx: usize,
-    negative_item_range: &Uniform<usize>,
+    sampler: &dyn MinibatchSampler,
     thread_rng: &mut XorShiftRng,
 ) -> usize {
     let pos_prediction = parameters.predict_single(hidden_state, positive_idx);
@@ -56,7 +475,9 @@ fn sample_warp_negative<U: SequenceModel, T: SequenceModelParameters<Output = U>
     let mut negative_idx = 0;
 
     for _ in 0..5 {
-        negative_idx = negative_item_range.sample(thread_rng);
+        negative_idx = sampler
+            .sample_negative(user_id, ItemId::from_index(positive_idx), thread_rng)
+            .index();
         let neg_prediction = parameters.predict_single(hidden_state, negative_idx);
 
         if 1.0 - pos_prediction + neg_prediction > 0.0 {
@@ -67,18 +488,206 @@ fn sample_warp_negative<U: SequenceModel, T: SequenceModelParameters<Output = U>
     negative_idx
 }
 
+/// Largest number of distinct learning rates during warmup. Longer
+/// warmups hold each rate for several steps, so that the number of
+/// optimizers built for the schedule does not grow with its length.
+#[cfg(feature = "training")]
+const MAX_WARMUP_RATES: usize = 100;
+
+/// Index of the learning rate to use at (zero-indexed) `step` of a warmup
+/// over `warmup_steps` steps: the index of the warmup rate, or the number
+/// of warmup rates once warmup is over.
+#[cfg(feature = "training")]
+fn warmup_rate_idx(warmup_steps: usize, step: usize) -> usize {
+    let num_rates = warmup_steps.min(MAX_WARMUP_RATES);
+
+    if step >= warmup_steps {
+        num_rates
+    } else {
+        step * num_rates / warmup_steps
+    }
+}
+
+/// Learning rate of index `rate_idx` (see [warmup_rate_idx]) when the
+/// learning rate is ramped up over `warmup_steps` steps.
+#[cfg(feature = "training")]
+fn indexed_learning_rate(learning_rate: f32, warmup_steps: usize, rate_idx: usize) -> f32 {
+    let num_rates = warmup_steps.min(MAX_WARMUP_RATES);
+
+    if rate_idx >= num_rates {
+        learning_rate
+    } else {
+        learning_rate * (rate_idx + 1) as f32 / num_rates as f32
+    }
+}
+
+/// Learning rate to use at (zero-indexed) `step` when the learning rate
+/// is linearly ramped up over the first `warmup_steps` steps, in at most
+/// [MAX_WARMUP_RATES] equal increments.
+#[cfg(feature = "training")]
+pub fn warmup_learning_rate(learning_rate: f32, warmup_steps: usize, step: usize) -> f32 {
+    indexed_learning_rate(
+        learning_rate,
+        warmup_steps,
+        warmup_rate_idx(warmup_steps, step),
+    )
+}
+
+/// Return the error for hyperparameter `name` having the invalid `value`.
+#[cfg(feature = "training")]
+pub fn invalid_hyperparameter<V: Display>(name: &'static str, value: V) -> FittingError {
+    FittingError::InvalidHyperparameter {
+        name,
+        value: value.to_string(),
+    }
+}
+
+/// Check that `interactions` can be fitted by a sequence model of
+/// `num_items` items: it must have the same number of items, only
+/// item ids in range, and at least one user with a sequence long
+/// enough to train on.
+#[cfg(feature = "training")]
+pub fn validate_interactions(
+    interactions: &CompressedInteractions,
+    num_items: usize,
+) -> Result<(), FittingError> {
+    if interactions.num_items() != num_items {
+        return Err(FittingError::ItemCountMismatch {
+            model_items: num_items,
+            data_items: interactions.num_items(),
+        });
+    }
+
+    let mut num_interactions = 0;
+    let mut has_sequence = false;
+
+    for user in interactions.iter_users() {
+        if let Some(&item_id) = user
+            .item_ids
+            .iter()
+            .find(|item_id| item_id.index() >= num_items)
+        {
+            return Err(FittingError::ItemIdOutOfRange { item_id, num_items });
+        }
+
+        num_interactions += user.len();
+        has_sequence |= user.len() > 2;
+    }
+
+    if num_interactions == 0 {
+        return Err(FittingError::NoInteractions);
+    }
+
+    if !has_sequence {
+        return Err(FittingError::DegenerateDataset {
+            reason: "no user has more than two interactions".to_owned(),
+        });
+    }
+
+    Ok(())
+}
+
+/// A running sum of losses: in `f64` if `f64_precision`, and otherwise
+/// rounded to `f32` after every addition.
+#[cfg(feature = "training")]
+#[derive(Clone, Copy, Debug)]
+struct LossSum {
+    sum: f64,
+    f64_precision: bool,
+}
+
+#[cfg(feature = "training")]
+impl LossSum {
+    fn new(f64_precision: bool) -> Self {
+        LossSum {
+            sum: 0.0,
+            f64_precision,
+        }
+    }
+
+    fn add(&mut self, loss: f32) {
+        self.sum = if self.f64_precision {
+            self.sum + f64::from(loss)
+        } else {
+            f64::from(self.sum as f32 + loss)
+        };
+    }
+
+    /// The sum divided by one plus `count`, in the precision of the sum.
+    fn mean(&self, count: usize) -> f64 {
+        if self.f64_precision {
+            self.sum / (1.0 + count as f64)
+        } else {
+            f64::from(self.sum as f32 / (1.0 + count as f32))
+        }
+    }
+}
+
+#[cfg(feature = "training")]
 pub fn fit_sequence_model<U: SequenceModel, T: SequenceModelParameters<Output = U> + Sync>(
     interactions: &CompressedInteractions,
     parameters: &mut T,
 ) -> Result<f32, FittingError> {
-    let negative_item_range = Uniform::new(0, interactions.num_items());
+    fit_sequence_model_with_hook(interactions, parameters, &|_, _| {})
+}
+
+/// Fit as [fit_sequence_model], calling `on_step` with the (zero-indexed)
+/// step and the learning rate of every optimizer step.
+#[cfg(feature = "training")]
+pub fn fit_sequence_model_with_hook<
+    U: SequenceModel,
+    T: SequenceModelParameters<Output = U> + Sync,
+>(
+    interactions: &CompressedInteractions,
+    parameters: &mut T,
+    on_step: &(dyn Fn(usize, f32) + Sync),
+) -> Result<f32, FittingError> {
+    let start = Instant::now();
+
+    if interactions.num_items() != parameters.num_items() {
+        return Err(FittingError::ItemCountMismatch {
+            model_items: parameters.num_items(),
+            data_items: interactions.num_items(),
+        });
+    }
+
+    if let Some(num_users) = *parameters.num_users() {
+        if interactions.num_users() != num_users {
+            return Err(FittingError::UserCountMismatch {
+                model_users: num_users,
+                data_users: interactions.num_users(),
+            });
+        }
+    }
+
+    let uniform_sampler = UniformSampler::new(interactions.num_items());
 
     let mut subsequences: Vec<_> = interactions
         .iter_users()
         .flat_map(|user| {
+            let user = match parameters.max_history() {
+                Some(max_history) => user.most_recent(max_history),
+                None => user,
+            };
+            let user_id = user.user_id;
+            let contexts = user.contexts;
+            let weights = user.weights;
+            let mut offset = 0;
+
             user.chunks(parameters.max_sequence_length())
-                .map(|(item_ids, _)| item_ids)
-                .filter(|item_ids| item_ids.len() > 2)
+                .map(move |(item_ids, timestamps)| {
+                    let start = offset;
+                    offset += item_ids.len();
+
+                    Subsequence {
+                        user_id,
+                        item_ids,
+                        timestamps,
+                        contexts: contexts.map(|x| &x[start..offset]),
+                        weights: weights.map(|x| &x[start..offset]),
+                    }
+                })
+                .filter(|subsequence| subsequence.item_ids.len() > 2)
         })
         .collect();
     parameters.rng().shuffle(&mut subsequences);
@@ -87,147 +696,793 @@ pub fn fit_sequence_model<U: SequenceModel, T: SequenceModelParameters<Output =
         return Err(FittingError::NoInteractions);
     }
 
-    let optimizer = parameters.optimizer();
-    let num_chunks = subsequences.len() / parameters.num_threads();
-    let sync_optim = optimizer.synchronized(parameters.num_threads());
+    let num_threads = parameters.num_threads();
+    let synchronous = num_threads > 1 && parameters.parallelism() == &Parallelism::Synchronous;
 
+    let num_chunks = subsequences.len() / num_threads;
     let mut partitions: Vec<_> = subsequences
         .chunks_mut(num_chunks)
-        .zip(sync_optim.into_iter())
-        .map(|(chunk, optim)| (chunk, XorShiftRng::from_seed(parameters.rng().gen()), optim))
+        .take(num_threads)
+        .map(|chunk| (chunk, XorShiftRng::from_seed(parameters.rng().gen())))
         .collect();
 
-    let loss = partitions
-        .par_iter_mut()
-        .map(|(partition, ref mut thread_rng, sync_optim)| {
-            let mut model = parameters.build();
+    // One optimizer per learning rate of the warmup schedule, the last
+    // using the configured rate, each built when first needed. Optimizer
+    // state is stored alongside the parameters, so switching optimizers
+    // continues where the previous step left off. Synchronized threads
+    // each take their own handle of a rate's synchronized set.
+    let (target_learning_rate, warmup_steps) =
+        (parameters.learning_rate(), parameters.warmup_steps());
+    let learning_rate = |step| warmup_learning_rate(target_learning_rate, warmup_steps, step);
+    let num_rates = warmup_steps.min(MAX_WARMUP_RATES);
+    let optimizers: Vec<OnceLock<Optimizers>> = (0..=num_rates).map(|_| OnceLock::new()).collect();
+    let optimizer = |rate_idx: usize| {
+        optimizers[rate_idx].get_or_init(|| {
+            parameters.optimizer(indexed_learning_rate(
+                target_learning_rate,
+                warmup_steps,
+                rate_idx,
+            ))
+        })
+    };
+    let sync_sets: Vec<OnceLock<Vec<Mutex<Option<_>>>>> =
+        (0..=num_rates).map(|_| OnceLock::new()).collect();
+
+    let cancelled = AtomicBool::new(false);
+    let steps = AtomicUsize::new(0);
+    let batch_size = parameters.sequence_batch_size().max(1);
+
+    let losses = partitions.par_iter_mut().enumerate().map(
+        |(partition_idx, (partition, ref mut thread_rng))| {
+            let mut model = if batch_size > 1 {
+                parameters.build_batch(batch_size)
+            } else {
+                parameters.build()
+            };
+            let sampler = parameters.negative_sampler().unwrap_or(&uniform_sampler);
 
-            let mut loss_value = 0.0;
+            let mut loss_value = LossSum::new(parameters.accumulate_f64());
             let mut examples = 0;
+            let mut partition_steps = 0;
+            let mut step_learning_rate = learning_rate(0);
+            let mut sync_optim = None;
+            let mut sync_rate_idx = None;
+
+            for epoch in 0..parameters.num_epochs() {
+                if parameters.is_cancelled() {
+                    cancelled.store(true, Ordering::SeqCst);
+                    break;
+                }
+
+                let epoch_start = Instant::now();
+                let mut epoch_loss = LossSum::new(parameters.accumulate_f64());
+                let examples_before = examples;
 
-            for _ in 0..parameters.num_epochs() {
                 thread_rng.shuffle(partition);
+                parameters.start_epoch(&model, epoch);
 
-                for &item_ids in partition.iter() {
-                    {
-                        let (inputs, outputs, negatives, hidden_states) = model.state();
-
-                        for (&input_idx, &output_idx, input, output, negative, hidden) in izip!(
-                            item_ids,
-                            item_ids.iter().skip(1),
-                            inputs,
-                            outputs,
-                            negatives,
-                            hidden_states
-                        ) {
-                            input.set_value(input_idx);
-
-                            let negative_idx = if parameters.loss() == &Loss::WARP {
-                                hidden.forward();
-                                let hidden_state = hidden.value();
-
-                                sample_warp_negative(
-                                    parameters,
-                                    hidden_state.as_slice().unwrap(),
-                                    output_idx,
-                                    &negative_item_range,
-                                    thread_rng,
-                                )
-                            } else {
-                                negative_item_range.sample(thread_rng)
-                            };
-
-                            output.set_value(output_idx);
-                            negative.set_value(negative_idx);
-                        }
-                    }
+                let sequence_length = parameters
+                    .sequence_length(epoch)
+                    .min(parameters.max_sequence_length());
 
-                    // Get the loss at the end of the sequence.
-                    let loss_idx = item_ids.len().saturating_sub(2);
+                let subsequences: Vec<_> = partition
+                    .iter()
+                    .flat_map(|subsequence| subsequence.chunks(sequence_length))
+                    .filter(|subsequence| subsequence.item_ids.len() > 2)
+                    .collect();
 
-                    // We need to clear the graph if the loss is WARP
+                for batch in subsequences.chunks(batch_size) {
+                    let (loss_idx, batch_examples) = if batch_size > 1 {
+                        set_batch(parameters, &model, batch, batch_size, sampler, thread_rng)
+                    } else {
+                        set_sequence(parameters, &model, &batch[0], sampler, thread_rng)
+                    };
+
+                    // We need to reset the graph if the loss is WARP
                     // in order for backpropagation to trigger correctly.
-                    // This is because by calling forward we've added the
-                    // resulting nodes to the graph.
+                    // This is because sampling the negatives has run the
+                    // hidden states forward.
                     if parameters.loss() == &Loss::WARP {
-                        model.hidden_states()[loss_idx].clear();
+                        reset_graph(&mut model.hidden_states()[..=loss_idx]);
                     }
 
                     let loss = &mut model.losses()[loss_idx];
-                    loss_value += loss.value().scalar_sum();
-                    examples += loss_idx + 1;
-
                     loss.forward();
+
+                    loss_value.add(loss.value().scalar_sum());
+                    epoch_loss.add(loss.value().scalar_sum());
+                    examples += batch_examples;
+
                     loss.backward(1.0);
 
-                    if parameters.num_threads() > 1
-                        && parameters.parallelism() == &Parallelism::Synchronous
-                    {
-                        sync_optim.step(loss.parameters());
+                    // Synchronized threads update the parameters together,
+                    // once per step of each thread; Hogwild threads update
+                    // them independently, so their steps are counted across
+                    // all threads.
+                    let step = if synchronous {
+                        partition_steps
                     } else {
-                        optimizer.step(loss.parameters());
+                        steps.fetch_add(1, Ordering::SeqCst)
+                    };
+                    step_learning_rate = learning_rate(step);
+
+                    log_debug!(
+                        "Step {} of partition {}: loss {:.4} over {} examples, learning rate {}",
+                        step,
+                        partition_idx,
+                        loss.value().scalar_sum(),
+                        batch_examples,
+                        step_learning_rate
+                    );
+
+                    let trainable: Vec<_> = loss
+                        .parameters()
+                        .iter()
+                        .filter(|parameter| !parameters.is_frozen(parameter))
+                        .cloned()
+                        .collect();
+
+                    let rate_idx = warmup_rate_idx(warmup_steps, step);
+                    if synchronous {
+                        if sync_rate_idx != Some(rate_idx) {
+                            let sync_set = sync_sets[rate_idx].get_or_init(|| {
+                                optimizer(rate_idx)
+                                    .synchronized(num_threads)
+                                    .into_iter()
+                                    .map(|optim| Mutex::new(Some(optim)))
+                                    .collect()
+                            });
+                            sync_optim = sync_set[partition_idx].lock().unwrap().take();
+                            sync_rate_idx = Some(rate_idx);
+                        }
+                        sync_optim.as_ref().unwrap().step(&trainable);
+                    } else {
+                        optimizer(rate_idx).step(&trainable);
                     }
+                    on_step(step, step_learning_rate);
+
+                    partition_steps += 1;
+                }
+
+                let epoch_examples = examples - examples_before;
+                let epoch_loss = epoch_loss.mean(epoch_examples);
+                let elapsed = epoch_start.elapsed();
+
+                if !epoch_loss.is_finite() {
+                    log_warn!(
+                        "Epoch {} of partition {}: the loss is not finite.",
+                        epoch,
+                        partition_idx
+                    );
                 }
+                log_info!(
+                    "Epoch {} of partition {}: loss {:.4}, learning rate {}, \
+                     {:.0} examples/s ({} in {:.2?})",
+                    epoch,
+                    partition_idx,
+                    epoch_loss,
+                    step_learning_rate,
+                    epoch_examples as f64 / elapsed.as_secs_f64().max(1e-9),
+                    epoch_examples,
+                    elapsed
+                );
             }
 
-            loss_value / (1.0 + examples as f32)
-        })
-        .sum();
+            loss_value.mean(examples)
+        },
+    );
+    let accumulate_f64 = parameters.accumulate_f64();
+    let loss = config::install(|| {
+        if accumulate_f64 {
+            losses.sum::<f64>() as f32
+        } else {
+            losses.map(|loss| loss as f32).sum()
+        }
+    });
+
+    if cancelled.load(Ordering::SeqCst) {
+        return Err(FittingError::Cancelled);
+    }
+
+    *parameters.num_users() = Some(interactions.num_users());
+
+    let tags = parameters
+        .metadata()
+        .take()
+        .map(|metadata| metadata.tags)
+        .unwrap_or_default();
+    let fit = FitStats {
+        num_epochs: parameters.num_epochs(),
+        loss,
+        duration: start.elapsed(),
+    };
+    let mut metadata = ModelMetadata::new(
+        parameters.hyperparameters(),
+        DatasetStats::from_interactions(interactions),
+        fit,
+    );
+    metadata.tags = tags;
+    *parameters.metadata() = Some(metadata);
 
     Ok(loss)
 }
 
+/// Compute the global L2 norm of the gradient of the summed training
+/// losses of `sequences`, without updating any parameters.
+///
+/// Negatives are drawn from the model's negative sampler, or uniformly
+/// if it has none, also for the WARP loss.
+#[cfg(feature = "training")]
+pub fn sequence_gradient_norm<U: SequenceModel, T: SequenceModelParameters<Output = U>>(
+    parameters: &T,
+    sequences: &[CompressedInteractionsUser],
+    rng: &mut XorShiftRng,
+) -> f32 {
+    let uniform_sampler = UniformSampler::new(parameters.num_items());
+    let sampler = parameters.negative_sampler().unwrap_or(&uniform_sampler);
+
+    // Summed gradients, keyed by the parameter they belong to.
+    let mut gradients: HashMap<*const f32, Arr> = HashMap::new();
+
+    for sequence in sequences.iter().filter(|sequence| sequence.len() > 2) {
+        let item_ids = sequence.item_ids;
+        // A fresh graph, so that gradients are dropped along with it.
+        let mut model = parameters.build();
+
+        if let Some(contexts) = sequence.contexts {
+            for (&context, input) in izip!(contexts, model.contexts()) {
+                input.set_value(context);
+            }
+        }
+
+        set_time_deltas(&model, Some(sequence.timestamps));
+        set_weights(&model, sequence.weights);
+        set_user(&model, sequence.user_id);
+        resample_dropout_masks(&model, parameters.dropout(), rng);
+
+        {
+            let (inputs, outputs, negatives, _) = model.state();
+
+            for (&input_id, &output_id, input, output, negative) in izip!(
+                item_ids,
+                item_ids.iter().skip(1),
+                inputs,
+                outputs,
+                negatives
+            ) {
+                let negative_id = sampler.sample_negative(sequence.user_id, output_id, rng);
+
+                input.set_value(input_id.index());
+                output.set_value(output_id.index());
+                negative.set_value(negative_id.index());
+            }
+        }
+
+        let loss = &mut model.losses()[item_ids.len() - 2];
+        loss.forward();
+        loss.backward(1.0);
+
+        for parameter in loss.parameters() {
+            if parameters.is_frozen(&parameter) {
+                continue;
+            }
+
+            let gradient = parameter.gradient();
+            match gradients.entry(parameter.value().as_ptr()) {
+                Entry::Occupied(mut entry) => *entry.get_mut() += &gradient,
+                Entry::Vacant(entry) => {
+                    entry.insert(gradient);
+                }
+            }
+        }
+    }
+
+    gradients
+        .values()
+        .map(|gradient| gradient.iter().map(|x| x * x).sum::<f32>())
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// Score the first `num_items` items with a single matrix-vector product.
+pub fn score_all_items(
+    item_embedding: &Arr,
+    item_biases: &Arr,
+    user: &[f32],
+    num_items: usize,
+) -> Vec<f32> {
+    let user = Arr::from_shape_vec((user.len(), 1), user.to_vec())
+        .expect("User vector must be contiguous.");
+    let scores = item_embedding.dot(&user);
+
+    scores
+        .iter()
+        .zip(item_biases.iter())
+        .take(num_items)
+        .map(|(score, bias)| score + bias)
+        .collect()
+}
+
+/// Check that the model can be used for inference.
+fn check_fitted<T: SequenceModelParameters>(parameters: &T) -> Result<(), PredictionError> {
+    if parameters.is_fitted() {
+        Ok(())
+    } else {
+        Err(PredictionError::ModelNotFitted)
+    }
+}
+
+/// Check that all `item_ids` are smaller than `num_items`.
+pub fn check_item_ids(item_ids: &[ItemId], num_items: usize) -> Result<(), PredictionError> {
+    match item_ids.iter().find(|item_id| item_id.index() >= num_items) {
+        Some(&item_id) => Err(PredictionError::ItemIdOutOfRange { item_id, num_items }),
+        None => Ok(()),
+    }
+}
+
+/// Check that the model can score all `item_ids`.
+fn check_scored_item_ids<T: SequenceModelParameters>(
+    parameters: &T,
+    item_ids: &[ItemId],
+) -> Result<(), PredictionError> {
+    if parameters.scores_unknown_items() {
+        Ok(())
+    } else {
+        check_item_ids(item_ids, parameters.num_items())
+    }
+}
+
+fn check_contexts(
+    contexts: &[usize],
+    num_items: usize,
+    cardinality: Option<usize>,
+) -> Result<(), PredictionError> {
+    if contexts.len() != num_items {
+        return Err(PredictionError::ContextLengthMismatch {
+            num_items,
+            num_contexts: contexts.len(),
+        });
+    }
+
+    match cardinality.and_then(|cardinality| {
+        contexts
+            .iter()
+            .find(|&&context| context >= cardinality)
+            .map(|&context| (context, cardinality))
+    }) {
+        Some((context, cardinality)) => Err(PredictionError::ContextOutOfRange {
+            context,
+            cardinality,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Score only `candidates` by gathering their embedding rows, returning
+/// the `k` best. No graph nodes are constructed.
+pub fn predict_candidates<T: SequenceModelParameters>(
+    parameters: &T,
+    user: &ImplicitUser,
+    candidates: &[ItemId],
+    k: usize,
+) -> Result<Vec<(ItemId, f32)>, PredictionError> {
+    check_fitted(parameters)?;
+    check_scored_item_ids(parameters, candidates)?;
+
+    let scored = candidates
+        .iter()
+        .map(|&item_id| {
+            let prediction = parameters.predict_single(&user.user_embedding, item_id.index());
+
+            if prediction.is_finite() {
+                Ok((item_id, prediction))
+            } else {
+                Err(PredictionError::InvalidPredictionValue)
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(top_k(scored, k))
+}
+
+/// Compute the user representation from past interactions and, optionally,
+/// their context features and timestamps.
+pub fn sequence_representation<U: SequenceModel, T: SequenceModelParameters<Output = U>>(
+    parameters: &T,
+    item_ids: &[ItemId],
+    contexts: Option<&[usize]>,
+    timestamps: Option<&[Timestamp]>,
+) -> Result<ImplicitUser, PredictionError> {
+    let mut model = parameters.build();
+
+    graph_representation(parameters, &mut model, item_ids, contexts, timestamps)
+}
+
+/// Compute the representations of several users, reusing a single graph.
+/// `contexts`, if given, holds the contexts of each history.
+pub fn sequence_representations<U: SequenceModel, T: SequenceModelParameters<Output = U>>(
+    parameters: &T,
+    histories: &[&[ItemId]],
+    contexts: Option<&[&[usize]]>,
+) -> Result<Vec<ImplicitUser>, PredictionError> {
+    if let Some(contexts) = contexts {
+        if contexts.len() != histories.len() {
+            return Err(PredictionError::ContextLengthMismatch {
+                num_items: histories.len(),
+                num_contexts: contexts.len(),
+            });
+        }
+    }
+
+    let mut model = parameters.build();
+
+    histories
+        .iter()
+        .enumerate()
+        .map(|(idx, item_ids)| {
+            let contexts = contexts.map(|contexts| contexts[idx]);
+            graph_representation(parameters, &mut model, item_ids, contexts, None)
+        })
+        .collect()
+}
+
+/// Extend `item_ids` by up to `steps` items, calling `next_item` with the
+/// representation of the sequence so far and the sequence itself to pick
+/// each one. The rollout stops early if `next_item` returns `None`.
+///
+/// The graph is built once. While the sequence fits in it, each new item
+/// only evaluates its own step, the earlier steps keeping their values
+/// from the previous forward pass; once it is full, the oldest item drops
+/// out and the graph is rebuilt.
+pub fn sequence_rollout<U, T, F>(
+    parameters: &T,
+    item_ids: &[ItemId],
+    steps: usize,
+    mut next_item: F,
+) -> Result<Vec<ItemId>, PredictionError>
+where
+    U: SequenceModel,
+    T: SequenceModelParameters<Output = U>,
+    F: FnMut(&ImplicitUser, &[ItemId]) -> Result<Option<ItemId>, PredictionError>,
+{
+    check_fitted(parameters)?;
+    if item_ids.is_empty() {
+        return Err(PredictionError::EmptyHistory);
+    }
+    check_item_ids(item_ids, parameters.num_items())?;
+
+    let max_length = representation_length(parameters);
+    let mut sequence = item_ids.to_owned();
+    let mut model = rollout_graph(parameters, &sequence, max_length);
+    let mut length = sequence.len().min(max_length);
+
+    for _ in 0..steps {
+        let user = {
+            let (_, _, _, hidden_states) = model.state();
+            let hidden_state = &hidden_states[length - 1];
+            hidden_state.forward();
+
+            ImplicitUser {
+                user_embedding: hidden_state.value().as_slice().unwrap().to_owned(),
+                num_interactions: length,
+                user_bias: None,
+            }
+        };
+
+        let item_id = match next_item(&user, &sequence)? {
+            Some(item_id) => item_id,
+            None => break,
+        };
+        check_item_ids(&[item_id], parameters.num_items())?;
+        sequence.push(item_id);
+
+        if length < max_length {
+            let (inputs, _, _, _) = model.state();
+            inputs[length].set_value(item_id.index());
+            length += 1;
+        } else {
+            model = rollout_graph(parameters, &sequence, max_length);
+        }
+    }
+
+    Ok(sequence.split_off(item_ids.len()))
+}
+
+/// Build a graph whose inputs hold the last `max_length` items of
+/// `item_ids`, with no contexts or timestamps.
+fn rollout_graph<U: SequenceModel, T: SequenceModelParameters<Output = U>>(
+    parameters: &T,
+    item_ids: &[ItemId],
+    max_length: usize,
+) -> U {
+    let model = parameters.build();
+    let item_ids = &item_ids[item_ids.len().saturating_sub(max_length)..];
+
+    set_time_deltas(&model, None);
+
+    let (inputs, _, _, _) = model.state();
+    for (&input_id, input) in izip!(item_ids, inputs) {
+        input.set_value(input_id.index());
+    }
+
+    model
+}
+
+/// The number of most recent items a user representation is computed from.
+fn representation_length<T: SequenceModelParameters>(parameters: &T) -> usize {
+    parameters
+        .max_history()
+        .map_or(parameters.max_sequence_length(), |max_history| {
+            max_history.min(parameters.max_sequence_length())
+        })
+}
+
+/// Compute the perplexity of the model on `interactions`: the exponent
+/// of the negative mean log-probability of each observed next item, under
+/// the softmax of the scores of all items given the preceding items.
+#[cfg(feature = "training")]
+pub fn sequence_perplexity<U: SequenceModel, T: SequenceModelParameters<Output = U> + Sync>(
+    parameters: &T,
+    interactions: &CompressedInteractions,
+) -> Result<f32, PredictionError> {
+    let num_items = parameters.num_items();
+
+    let users: Vec<_> = interactions
+        .iter_users()
+        .filter(|user| user.len() >= 2)
+        .collect();
+
+    let log_probabilities = config::install(|| {
+        users
+            .par_iter()
+            .map(|user| {
+                let histories: Vec<_> = (1..user.len()).map(|idx| &user.item_ids[..idx]).collect();
+                let contexts: Option<Vec<_>> = user
+                    .contexts
+                    .map(|contexts| (1..user.len()).map(|idx| &contexts[..idx]).collect());
+                let representations =
+                    sequence_representations(parameters, &histories, contexts.as_deref())?;
+
+                let mut log_probability = 0.0;
+
+                for (representation, &next_item) in izip!(&representations, &user.item_ids[1..]) {
+                    check_item_ids(&[next_item], num_items)?;
+                    let scores = parameters.predict_all(&representation.user_embedding, num_items);
+
+                    if !scores.iter().all(|score| score.is_finite()) {
+                        return Err(PredictionError::InvalidPredictionValue);
+                    }
+
+                    let max_score = scores.iter().cloned().fold(std::f32::MIN, f32::max);
+                    let log_normalizer = f64::from(max_score)
+                        + scores
+                            .iter()
+                            .map(|&score| f64::from(score - max_score).exp())
+                            .sum::<f64>()
+                            .ln();
+
+                    log_probability += f64::from(scores[next_item.index()]) - log_normalizer;
+                }
+
+                Ok((log_probability, histories.len()))
+            })
+            .collect::<Result<Vec<_>, PredictionError>>()
+    })?;
+
+    let (total, count) = log_probabilities
+        .into_iter()
+        .fold((0.0, 0), |(total, count), (log_probability, num_steps)| {
+            (total + log_probability, count + num_steps)
+        });
+
+    Ok((-total / count as f64).exp() as f32)
+}
+
+/// Compute a user representation with an already built `model` graph,
+/// leaving the graph ready for the next user.
+fn graph_representation<U: SequenceModel, T: SequenceModelParameters<Output = U>>(
+    parameters: &T,
+    model: &mut U,
+    item_ids: &[ItemId],
+    contexts: Option<&[usize]>,
+    timestamps: Option<&[Timestamp]>,
+) -> Result<ImplicitUser, PredictionError> {
+    check_fitted(parameters)?;
+    if item_ids.is_empty() {
+        return Err(PredictionError::EmptyHistory);
+    }
+    check_item_ids(item_ids, parameters.num_items())?;
+    if let Some(contexts) = contexts {
+        check_contexts(contexts, item_ids.len(), parameters.context_cardinality())?;
+    }
+
+    let max_length = representation_length(parameters);
+    let start = item_ids.len().saturating_sub(max_length);
+    let item_ids = &item_ids[start..];
+
+    // Steps without a context use context 0, rather than the context
+    // the previous user of the graph left behind.
+    let contexts = contexts.map(|contexts| &contexts[start..]);
+    for (idx, input) in model.contexts().iter().enumerate() {
+        input.set_value(
+            contexts
+                .and_then(|contexts| contexts.get(idx))
+                .cloned()
+                .unwrap_or(0),
+        );
+    }
+
+    set_time_deltas(
+        model,
+        timestamps.map(|timestamps| &timestamps[start.min(timestamps.len())..]),
+    );
+
+    let (inputs, _, _, hidden_states) = model.state();
+
+    for (&input_id, input) in izip!(item_ids, inputs) {
+        input.set_value(input_id.index());
+    }
+
+    // Get the loss at the end of the sequence.
+    let loss_idx = item_ids.len().saturating_sub(1);
+
+    // Select the hidden state after ingesting all the inputs.
+    let hidden_state = &hidden_states[loss_idx];
+
+    // Run the network forward up to that point.
+    hidden_state.forward();
+
+    // Get the value.
+    let user_embedding = hidden_state.value().as_slice().unwrap().to_owned();
+
+    // Mark the graph as not evaluated, so that the next forward pass
+    // picks up new input values.
+    reset_graph(&mut model.hidden_states()[loss_idx..=loss_idx]);
+
+    Ok(ImplicitUser {
+        user_embedding,
+        num_interactions: item_ids.len(),
+        user_bias: None,
+    })
+}
+
 impl<U: SequenceModel, T: SequenceModelParameters<Output = U> + Sync> OnlineRankingModel for T {
     type UserRepresentation = ImplicitUser;
     fn user_representation(
         &self,
         item_ids: &[ItemId],
     ) -> Result<Self::UserRepresentation, PredictionError> {
-        let model = self.build();
+        sequence_representation(self, item_ids, None, None)
+    }
 
-        let item_ids = &item_ids[item_ids.len().saturating_sub(self.max_sequence_length())..];
+    fn user_representations(
+        &self,
+        histories: &[&[ItemId]],
+    ) -> Result<Vec<Self::UserRepresentation>, PredictionError> {
+        sequence_representations(self, histories, None)
+    }
 
-        let (inputs, _, _, hidden_states) = model.state();
+    fn predict(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+    ) -> Result<Vec<f32>, PredictionError> {
+        let mut predictions = vec![0.0; item_ids.len()];
+        self.predict_into(user, item_ids, &mut predictions)?;
 
-        for (&input_idx, input) in izip!(item_ids, inputs) {
-            input.set_value(input_idx);
-        }
+        Ok(predictions)
+    }
 
-        // Get the loss at the end of the sequence.
-        let loss_idx = item_ids.len().saturating_sub(1);
+    fn predict_into(
+        &self,
+        user: &Self::UserRepresentation,
+        item_ids: &[ItemId],
+        out: &mut [f32],
+    ) -> Result<(), PredictionError> {
+        check_fitted(self)?;
+        check_scored_item_ids(self, item_ids)?;
+        check_output_len(item_ids, out)?;
 
-        // Select the hidden state after ingesting all the inputs.
-        let hidden_state = &hidden_states[loss_idx];
+        let user_slice = &user.user_embedding;
 
-        // Run the network forward up to that point.
-        hidden_state.forward();
+        for (&item_id, prediction) in izip!(item_ids, out) {
+            *prediction = self.predict_single(user_slice, item_id.index());
 
-        // Get the value.
-        let representation = hidden_state.value();
+            if !prediction.is_finite() {
+                return Err(PredictionError::InvalidPredictionValue);
+            }
+        }
 
-        Ok(ImplicitUser {
-            user_embedding: representation.as_slice().unwrap().to_owned(),
-        })
+        Ok(())
     }
 
-    fn predict(
+    fn predict_all_items(
         &self,
         user: &Self::UserRepresentation,
-        item_ids: &[ItemId],
+        num_items: usize,
     ) -> Result<Vec<f32>, PredictionError> {
-        let user_slice = &user.user_embedding;
+        check_fitted(self)?;
+        if num_items > self.num_items() && !self.scores_unknown_items() {
+            return Err(PredictionError::ItemIdOutOfRange {
+                item_id: ItemId::from_index(num_items - 1),
+                num_items: self.num_items(),
+            });
+        }
 
-        item_ids
-            .iter()
-            .map(|&item_idx| {
-                let prediction = self.predict_single(user_slice, item_idx);
+        let predictions = self.predict_all(&user.user_embedding, num_items);
 
-                if prediction.is_finite() {
-                    Ok(prediction)
-                } else {
-                    Err(PredictionError::InvalidPredictionValue)
-                }
-            })
-            .collect()
+        if predictions.iter().all(|prediction| prediction.is_finite()) {
+            Ok(predictions)
+        } else {
+            Err(PredictionError::InvalidPredictionValue)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use super::*;
+    use crate::ids::items;
+
+    #[test]
+    fn loss_sum_precision() {
+        let loss = 1e-4;
+        let mut single = LossSum::new(false);
+        let mut double = LossSum::new(true);
+
+        for _ in 0..10_000_000 {
+            single.add(loss);
+            double.add(loss);
+        }
+
+        let reference = 1e7 * f64::from(loss);
+        assert!((double.sum - reference).abs() < 1e-6 * reference);
+        assert!((single.sum - reference).abs() > 1e-3 * reference);
+        assert_eq!(single.sum, f64::from(single.sum as f32));
+    }
+
+    #[test]
+    fn subsequence_chunks() {
+        let item_ids = items(&[0, 1, 2, 3, 4]);
+        let timestamps: Vec<_> = (10..15).map(Timestamp).collect();
+        let contexts = [5, 6, 7, 8, 9];
+
+        let subsequence = Subsequence {
+            user_id: UserId(0),
+            item_ids: &item_ids,
+            timestamps: &timestamps,
+            contexts: Some(&contexts),
+            weights: None,
+        };
+
+        let chunks: Vec<_> = subsequence.chunks(2).collect();
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].item_ids, &[ItemId(2), ItemId(3)]);
+        assert_eq!(chunks[1].timestamps, &[Timestamp(12), Timestamp(13)]);
+        assert_eq!(chunks[2].contexts, Some(&[9][..]));
+    }
+
+    #[test]
+    fn warmup_ramp() {
+        let learning_rates: Vec<_> = (0..6)
+            .map(|step| warmup_learning_rate(1.0, 4, step))
+            .collect();
+
+        assert_eq!(learning_rates, vec![0.25, 0.5, 0.75, 1.0, 1.0, 1.0]);
+        assert_eq!(warmup_learning_rate(0.1, 0, 0), 0.1);
+
+        // Long warmups hold each of a bounded number of rates for several
+        // steps.
+        let warmup_steps = 10 * MAX_WARMUP_RATES;
+        let learning_rates: Vec<_> = (0..=warmup_steps)
+            .map(|step| warmup_learning_rate(1.0, warmup_steps, step))
+            .collect();
+        assert_eq!(learning_rates[0], learning_rates[9]);
+        assert!(learning_rates[10] > learning_rates[9]);
+        assert_eq!(learning_rates[warmup_steps], 1.0);
+        let mut distinct = learning_rates.clone();
+        distinct.dedup();
+        assert_eq!(distinct.len(), MAX_WARMUP_RATES);
     }
 }
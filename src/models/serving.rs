@@ -0,0 +1,415 @@
+//! Precomputed exact top-k retrieval for serving.
+//!
+//! The item scoring vectors of a model do not change between model loads,
+//! so [ScoringCache] copies them once into a layout suited to scanning the
+//! whole catalog: rows are padded to a multiple of [LANES] values and
+//! stored in aligned blocks, so that the inner products compile to SIMD
+//! instructions.
+//!
+//! Rows are also sorted by decreasing norm. By the Cauchy-Schwarz
+//! inequality, no item can score more than the product of its norm with
+//! the norm of the user vector, so the scan stops as soon as that bound
+//! falls below the lowest score kept; the results are still exact.
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt;
+
+use ndarray::Array2;
+
+use super::ItemEmbeddings;
+use crate::ids::ItemId;
+
+/// Number of values in each aligned block of a row.
+pub const LANES: usize = 8;
+
+/// Relative slack added to the score bounds, so that rounding errors in
+/// the computed scores never prune an item that belongs in the top k.
+const BOUND_SLACK: f32 = 1e-4;
+
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, align(32))]
+struct Block([f32; LANES]);
+
+/// Copy `values` into zero-padded blocks.
+fn to_blocks<'a, I: IntoIterator<Item = &'a f32>>(values: I, num_blocks: usize) -> Vec<Block> {
+    let mut blocks = vec![Block::default(); num_blocks];
+
+    for (idx, &value) in values.into_iter().enumerate() {
+        blocks[idx / LANES].0[idx % LANES] = value;
+    }
+
+    blocks
+}
+
+fn dot(x: &[Block], y: &[Block]) -> f32 {
+    let mut sums = [0.0; LANES];
+
+    for (x, y) in x.iter().zip(y) {
+        for ((sum, x), y) in sums.iter_mut().zip(&x.0).zip(&y.0) {
+            *sum += x * y;
+        }
+    }
+
+    sums.iter().sum()
+}
+
+/// A set of items to leave out of [ScoringCache::top_k], made by
+/// [ScoringCache::exclusion_bitmap].
+#[derive(Clone, Debug)]
+pub struct ExclusionBitmap {
+    words: Vec<u64>,
+}
+
+impl ExclusionBitmap {
+    /// Exclude `item_id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `item_id` is not smaller than the number of items of the
+    /// cache that made the bitmap.
+    pub fn insert(&mut self, item_id: ItemId) {
+        let idx = item_id.index();
+        self.words[idx / 64] |= 1u64 << (idx % 64);
+    }
+
+    /// Return whether `item_id` is excluded.
+    pub fn contains(&self, item_id: ItemId) -> bool {
+        self.words
+            .get(item_id.index() / 64)
+            .is_some_and(|word| word & (1u64 << (item_id.index() % 64)) != 0)
+    }
+
+    /// Remove all items, so that the bitmap can be reused.
+    pub fn clear(&mut self) {
+        self.words.iter_mut().for_each(|word| *word = 0);
+    }
+}
+
+/// An item kept while scanning, ordered so that the worst item is at the
+/// top of a [BinaryHeap].
+#[derive(Clone, Copy, Debug)]
+struct Scored {
+    score: f32,
+    item_id: ItemId,
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Lower scores, then higher item ids, are worse.
+        other
+            .score
+            .partial_cmp(&self.score)
+            .unwrap_or(Ordering::Equal)
+            .then(self.item_id.cmp(&other.item_id))
+    }
+}
+
+/// A copy of the item scoring vectors of a model, laid out for fast exact
+/// top-k retrieval. See the [module documentation](self).
+#[derive(Clone)]
+pub struct ScoringCache {
+    num_items: usize,
+    dim: usize,
+    blocks_per_row: usize,
+    /// Scoring vectors in order of decreasing norm, `blocks_per_row`
+    /// blocks each.
+    rows: Vec<Block>,
+    /// Item id of each row.
+    item_ids: Vec<ItemId>,
+    /// Norm of each row.
+    norms: Vec<f32>,
+}
+
+impl ScoringCache {
+    /// Cache the scoring vectors of `model`. The cache is not updated if
+    /// the model changes.
+    pub fn new<T: ItemEmbeddings>(model: &T) -> Self {
+        ScoringCache::from_embeddings(&model.item_embeddings())
+    }
+
+    /// Cache the item vectors given by the rows of `embeddings`.
+    pub fn from_embeddings(embeddings: &Array2<f32>) -> Self {
+        let (num_items, dim) = embeddings.dim();
+        let blocks_per_row = ((dim + LANES - 1) / LANES).max(1);
+
+        let norms: Vec<f32> = embeddings
+            .outer_iter()
+            .map(|row| row.dot(&row).sqrt())
+            .collect();
+        let mut item_ids: Vec<ItemId> = (0..num_items).map(ItemId::from_index).collect();
+        item_ids.sort_by(|&x, &y| {
+            norms[y.index()]
+                .partial_cmp(&norms[x.index()])
+                .unwrap_or(Ordering::Equal)
+                .then(x.cmp(&y))
+        });
+
+        let rows = item_ids
+            .iter()
+            .flat_map(|&item_id| to_blocks(embeddings.row(item_id.index()), blocks_per_row))
+            .collect();
+        let norms = item_ids
+            .iter()
+            .map(|&item_id| norms[item_id.index()])
+            .collect();
+
+        ScoringCache {
+            num_items,
+            dim,
+            blocks_per_row,
+            rows,
+            item_ids,
+            norms,
+        }
+    }
+
+    /// Number of cached items.
+    pub fn num_items(&self) -> usize {
+        self.num_items
+    }
+
+    /// Return an empty exclusion bitmap covering all cached items.
+    pub fn exclusion_bitmap(&self) -> ExclusionBitmap {
+        ExclusionBitmap {
+            words: vec![0; (self.num_items + 63) / 64],
+        }
+    }
+
+    /// Return the `k` items with the largest inner product with
+    /// `user_vector`, other than those in `exclude`, as
+    /// `(item_id, score)` pairs, best first. Ties are broken in favour
+    /// of lower item ids.
+    ///
+    /// For the sequence models, `user_vector` is given by
+    /// [AsVector::as_vector](super::AsVector::as_vector).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `user_vector` does not have the dimension of the item vectors.
+    pub fn top_k(
+        &self,
+        user_vector: &[f32],
+        k: usize,
+        exclude: Option<&ExclusionBitmap>,
+    ) -> Vec<(ItemId, f32)> {
+        assert_eq!(
+            user_vector.len(),
+            self.dim,
+            "User vector has the wrong dimension."
+        );
+
+        let user = to_blocks(user_vector, self.blocks_per_row);
+        let user_norm = user_vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+
+        if k > 0 {
+            let rows = self.rows.chunks(self.blocks_per_row);
+
+            for (row, &item_id, &norm) in izip!(rows, &self.item_ids, &self.norms) {
+                if heap.len() == k {
+                    let worst: &Scored = heap.peek().unwrap();
+                    let bound = user_norm * norm;
+
+                    if bound + BOUND_SLACK * bound.abs().max(1.0) < worst.score {
+                        break;
+                    }
+                }
+
+                if exclude.is_some_and(|exclude| exclude.contains(item_id)) {
+                    continue;
+                }
+
+                let scored = Scored {
+                    score: dot(row, &user),
+                    item_id,
+                };
+
+                if heap.len() < k {
+                    heap.push(scored);
+                } else if scored < *heap.peek().unwrap() {
+                    heap.pop();
+                    heap.push(scored);
+                }
+            }
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|scored| (scored.item_id, scored.score))
+            .collect()
+    }
+}
+
+impl fmt::Debug for ScoringCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScoringCache")
+            .field("num_items", &self.num_items)
+            .field("dim", &self.dim)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::distributions::{Distribution, Normal};
+    use rand::{SeedableRng, XorShiftRng};
+
+    use super::*;
+    use crate::models::top_k;
+
+    fn random_embeddings(num_items: usize, dim: usize, rng: &mut XorShiftRng) -> Array2<f32> {
+        let normal = Normal::new(0.0, 1.0);
+        Array2::from_shape_fn((num_items, dim), |_| normal.sample(rng) as f32)
+    }
+
+    /// Score every item and sort, as `predict` followed by a sort would.
+    fn slow_top_k(
+        embeddings: &Array2<f32>,
+        query: &[f32],
+        k: usize,
+        exclude: &[ItemId],
+    ) -> Vec<(ItemId, f32)> {
+        let scored = embeddings
+            .outer_iter()
+            .enumerate()
+            .map(|(idx, row)| (ItemId::from_index(idx), row))
+            .filter(|(item_id, _)| !exclude.contains(item_id))
+            .map(|(item_id, row)| (item_id, row.iter().zip(query).map(|(x, y)| x * y).sum()))
+            .collect();
+
+        top_k(scored, k)
+    }
+
+    fn assert_same(fast: &[(ItemId, f32)], slow: &[(ItemId, f32)]) {
+        assert_eq!(fast.len(), slow.len());
+
+        for (&(fast_id, fast_score), &(slow_id, slow_score)) in fast.iter().zip(slow) {
+            assert_eq!(fast_id, slow_id);
+            assert!((fast_score - slow_score).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn matches_slow_path() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+
+        // Dimensions below, at and above a multiple of the block size.
+        for &dim in &[5, 8, 33] {
+            let embeddings = random_embeddings(2000, dim, &mut rng);
+            let cache = ScoringCache::from_embeddings(&embeddings);
+
+            for query in random_embeddings(20, dim, &mut rng).outer_iter() {
+                let query = query.to_vec();
+
+                for &k in &[1, 10, 100] {
+                    assert_same(
+                        &cache.top_k(&query, k, None),
+                        &slow_top_k(&embeddings, &query, k, &[]),
+                    );
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "training")]
+    #[test]
+    fn matches_model_predictions() {
+        use crate::data::{Interaction, Interactions};
+        use crate::ids::{items, Timestamp, UserId};
+        use crate::models::{ewma, AsVector};
+        use crate::OnlineRankingModel;
+
+        let num_items = 500;
+        let mut data = Interactions::new(1, num_items);
+        for item_id in 0..3 {
+            data.push(Interaction::new(
+                UserId(0),
+                ItemId(item_id),
+                Timestamp(item_id as i64),
+            ));
+        }
+
+        let mut model = ewma::Hyperparameters::new(num_items, 10)
+            .num_epochs(0)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data.to_compressed()).unwrap();
+        let cache = ScoringCache::new(&model);
+
+        let user = model.user_representation(&items(&[1, 2, 3])).unwrap();
+        let scored = model
+            .predict_all_items(&user, num_items)
+            .unwrap()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, score)| (ItemId::from_index(idx), score))
+            .collect();
+
+        assert_same(
+            &cache.top_k(&user.as_vector(), 10, None),
+            &top_k(scored, 10),
+        );
+    }
+
+    #[test]
+    fn excluded_items() {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let embeddings = random_embeddings(300, 16, &mut rng);
+        let cache = ScoringCache::from_embeddings(&embeddings);
+        let query = embeddings.row(7).to_vec();
+
+        let top: Vec<_> = cache
+            .top_k(&query, 5, None)
+            .into_iter()
+            .map(|(item_id, _)| item_id)
+            .collect();
+        let excluded = &top[..2];
+
+        let mut bitmap = cache.exclusion_bitmap();
+        for &item_id in excluded {
+            bitmap.insert(item_id);
+        }
+        assert!(bitmap.contains(excluded[0]));
+        assert!(!bitmap.contains(top[2]));
+
+        assert_same(
+            &cache.top_k(&query, 5, Some(&bitmap)),
+            &slow_top_k(&embeddings, &query, 5, excluded),
+        );
+
+        bitmap.clear();
+        assert!(!bitmap.contains(excluded[0]));
+    }
+
+    #[test]
+    fn small_catalogs() {
+        let embeddings =
+            Array2::from_shape_vec((3, 2), vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0]).unwrap();
+        let cache = ScoringCache::from_embeddings(&embeddings);
+
+        assert_eq!(
+            cache.top_k(&[1.0, 0.0], 10, None),
+            vec![(ItemId(0), 1.0), (ItemId(2), 1.0), (ItemId(1), 0.0)]
+        );
+        assert!(cache.top_k(&[1.0, 0.0], 0, None).is_empty());
+
+        let empty = ScoringCache::from_embeddings(&Array2::zeros((0, 2)));
+        assert!(empty.top_k(&[1.0, 0.0], 10, None).is_empty());
+    }
+}
@@ -0,0 +1,514 @@
+//! Split, fit and evaluate a model in one call.
+//!
+//! [run] takes the interactions and a [PipelineConfig] choosing how to
+//! split them, which model to fit and which metrics to compute, and
+//! returns the fitted model together with its evaluation:
+//!
+//! ```rust,no_run
+//! use recommenders::models::ewma;
+//! use recommenders::pipeline::{self, Metric, ModelConfig, PipelineConfig, Split};
+//!
+//! # async_std::task::block_on(async {
+//! let data = recommenders::datasets::download_movielens_100k().await.unwrap();
+//!
+//! let model = ewma::Hyperparameters::new(data.num_items(), 32).num_epochs(10);
+//! let config = PipelineConfig::new(ModelConfig::Ewma(model))
+//!     .split(Split::UserBased { test_fraction: 0.2 })
+//!     .metrics(vec![Metric::Mrr, Metric::Ndcg { k: 10 }])
+//!     .seed(42);
+//!
+//! let result = pipeline::run(data, config).unwrap();
+//! println!("{} (fitted in {:?})", result.report, result.timings.fit);
+//! # });
+//! ```
+//!
+//! The configuration serializes with serde, so that experiments can be
+//! described in JSON files.
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data::{train_test_split, user_based_split, CompressedInteractions, Interactions};
+use crate::evaluation::{
+    coverage_at_k, gini_coefficient, item_recommendation_frequency_at_k, mrr_score, ndcg_score,
+};
+use crate::ids::Timestamp;
+use crate::models::baselines::Popularity;
+use crate::models::boxed::{BoxedModel, IntoBoxedModel};
+use crate::models::{attention, cnn, ewma, fism, hierarchical, lstm, mf, pooling};
+use crate::rng::SeedSequence;
+use crate::{Error, FittingError, PredictionError};
+
+/// How to split the interactions between training and test sets.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Split {
+    /// Put a random `test_fraction` of the interactions in the test set:
+    /// see [crate::data::train_test_split].
+    Random {
+        /// Fraction of interactions in the test set.
+        test_fraction: f32,
+    },
+    /// Put a random `test_fraction` of the users, with all their
+    /// interactions, in the test set: see [crate::data::user_based_split].
+    UserBased {
+        /// Fraction of users in the test set.
+        test_fraction: f32,
+    },
+    /// Put the latest `test_fraction` of the interactions in the test set,
+    /// so that every test interaction happened after every training one,
+    /// except for interactions at the cutoff timestamp.
+    Temporal {
+        /// Fraction of interactions in the test set.
+        test_fraction: f32,
+    },
+}
+
+impl Split {
+    fn test_fraction(&self) -> f32 {
+        match *self {
+            Split::Random { test_fraction }
+            | Split::UserBased { test_fraction }
+            | Split::Temporal { test_fraction } => test_fraction,
+        }
+    }
+
+    fn apply(
+        &self,
+        mut interactions: Interactions,
+        seeds: &SeedSequence,
+    ) -> (Interactions, Interactions) {
+        match *self {
+            Split::Random { test_fraction } => {
+                train_test_split(&mut interactions, &mut seeds.derive("split"), test_fraction)
+            }
+            Split::UserBased { test_fraction } => {
+                user_based_split(&interactions, &mut seeds.derive("split"), test_fraction)
+            }
+            Split::Temporal { test_fraction } => {
+                let mut timestamps: Vec<Timestamp> =
+                    interactions.data().iter().map(|x| x.timestamp()).collect();
+                timestamps.sort_unstable();
+
+                let num_test = (test_fraction * timestamps.len() as f32) as usize;
+                match timestamps.get(timestamps.len() - num_test) {
+                    Some(&cutoff) => interactions.split_by(|x| x.timestamp() < cutoff),
+                    None => interactions.split_by(|_| true),
+                }
+            }
+        }
+    }
+}
+
+impl Default for Split {
+    fn default() -> Self {
+        Split::UserBased { test_fraction: 0.2 }
+    }
+}
+
+/// The model to fit, with its hyperparameters.
+///
+/// The random number generators in the hyperparameters are replaced by
+/// ones derived from the seed of the [PipelineConfig].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ModelConfig {
+    /// The [crate::models::baselines::Popularity] baseline.
+    Popularity,
+    /// An [crate::models::ewma::ImplicitEWMAModel].
+    Ewma(ewma::Hyperparameters),
+    /// An [crate::models::lstm::ImplicitLSTMModel].
+    Lstm(lstm::Hyperparameters),
+    /// An [crate::models::attention::ImplicitAttentionModel].
+    Attention(attention::Hyperparameters),
+    /// An [crate::models::cnn::ImplicitCNNModel].
+    Cnn(cnn::Hyperparameters),
+    /// An [crate::models::hierarchical::ImplicitHierarchicalModel].
+    Hierarchical(hierarchical::Hyperparameters),
+    /// An [crate::models::pooling::ImplicitPoolingModel].
+    Pooling(pooling::Hyperparameters),
+    /// A [crate::models::fism::Fism] model.
+    Fism(fism::Hyperparameters),
+    /// A [crate::models::mf::BprMf] model.
+    Mf(mf::Hyperparameters),
+}
+
+impl ModelConfig {
+    /// Build the model and fit it on `train`, returning the model and
+    /// its final loss, if it has one.
+    fn fit(
+        self,
+        train: &CompressedInteractions,
+        seeds: &SeedSequence,
+    ) -> Result<(BoxedModel, Option<f32>), FittingError> {
+        let num_items = train.num_items();
+
+        macro_rules! fit {
+            ($hyper:expr) => {{
+                let mut model = $hyper.seed_sequence(seeds).build();
+                let loss = model.fit(train)?;
+                Ok((model.boxed(num_items), Some(loss)))
+            }};
+        }
+
+        match self {
+            ModelConfig::Popularity => {
+                let mut model = Popularity::new(num_items);
+                model.fit(train)?;
+                Ok((model.boxed(num_items), None))
+            }
+            ModelConfig::Ewma(hyper) => fit!(hyper),
+            ModelConfig::Lstm(hyper) => fit!(hyper),
+            ModelConfig::Attention(hyper) => fit!(hyper),
+            ModelConfig::Cnn(hyper) => fit!(hyper),
+            ModelConfig::Hierarchical(hyper) => fit!(hyper),
+            ModelConfig::Pooling(hyper) => fit!(hyper),
+            ModelConfig::Fism(hyper) => fit!(hyper),
+            ModelConfig::Mf(hyper) => fit!(hyper),
+        }
+    }
+}
+
+/// A metric computed on the test set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Metric {
+    /// Mean reciprocal rank: see [crate::evaluation::mrr_score].
+    Mrr,
+    /// NDCG at `k`: see [crate::evaluation::ndcg_score].
+    Ndcg {
+        /// Number of recommendations.
+        k: usize,
+    },
+    /// Catalog coverage at `k`: see [crate::evaluation::coverage_at_k].
+    Coverage {
+        /// Number of recommendations.
+        k: usize,
+    },
+    /// Gini coefficient of how often each item is recommended in the
+    /// top `k`: see [crate::evaluation::gini_coefficient].
+    Gini {
+        /// Number of recommendations.
+        k: usize,
+    },
+}
+
+impl Metric {
+    fn compute(
+        &self,
+        model: &BoxedModel,
+        test: &CompressedInteractions,
+    ) -> Result<f32, PredictionError> {
+        match *self {
+            Metric::Mrr => mrr_score(model, test),
+            Metric::Ndcg { k } => ndcg_score(model, test, k),
+            Metric::Coverage { k } => coverage_at_k(model, test, k),
+            Metric::Gini { k } => Ok(gini_coefficient(&item_recommendation_frequency_at_k(
+                model, test, k,
+            )?)),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Metric::Mrr => write!(f, "MRR"),
+            Metric::Ndcg { k } => write!(f, "NDCG@{}", k),
+            Metric::Coverage { k } => write!(f, "Cov@{}", k),
+            Metric::Gini { k } => write!(f, "Gini@{}", k),
+        }
+    }
+}
+
+/// What [run] does.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    split: Split,
+    model: ModelConfig,
+    metrics: Vec<Metric>,
+    seed: u64,
+}
+
+impl PipelineConfig {
+    /// Fit `model` on a user-based split with 20% of the users in the
+    /// test set, report its MRR, and seed everything with 0.
+    pub fn new(model: ModelConfig) -> Self {
+        PipelineConfig {
+            split: Split::default(),
+            model,
+            metrics: vec![Metric::Mrr],
+            seed: 0,
+        }
+    }
+
+    /// Set how to split the interactions.
+    pub fn split(mut self, split: Split) -> Self {
+        self.split = split;
+        self
+    }
+
+    /// Set the metrics to compute on the test set, in the order they are
+    /// reported.
+    pub fn metrics(mut self, metrics: Vec<Metric>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Set the seed of the [SeedSequence] used to split the data and to
+    /// fit the model.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// Values of the metrics of a [PipelineConfig], in order.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationReport {
+    /// Each metric with its value on the test set.
+    pub values: Vec<(Metric, f32)>,
+}
+
+impl EvaluationReport {
+    /// The value of `metric`, if it was computed.
+    pub fn get(&self, metric: Metric) -> Option<f32> {
+        self.values
+            .iter()
+            .find(|(computed, _)| *computed == metric)
+            .map(|&(_, value)| value)
+    }
+}
+
+impl fmt::Display for EvaluationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (idx, (metric, value)) in self.values.iter().enumerate() {
+            if idx > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {:.4}", metric, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Time taken by each step of [run].
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Timings {
+    /// Splitting and compressing the data.
+    pub split: Duration,
+    /// Building and fitting the model.
+    pub fit: Duration,
+    /// Computing the metrics.
+    pub evaluate: Duration,
+}
+
+/// The outcome of [run].
+#[derive(Debug)]
+pub struct PipelineResult {
+    /// The fitted model.
+    pub model: BoxedModel,
+    /// The final training loss, for models that have one.
+    pub loss: Option<f32>,
+    /// Number of training interactions.
+    pub num_train: usize,
+    /// Number of test interactions.
+    pub num_test: usize,
+    /// The metrics on the test set.
+    pub report: EvaluationReport,
+    /// Time taken by each step.
+    pub timings: Timings,
+}
+
+/// Split `interactions`, fit a model on the training set and evaluate it
+/// on the test set, as described by `config`.
+///
+/// Fails if the test fraction is not between 0 and 1, if either set is
+/// empty, or if fitting or evaluating the model fails.
+pub fn run(interactions: Interactions, config: PipelineConfig) -> Result<PipelineResult, Error> {
+    let test_fraction = config.split.test_fraction();
+    if !(test_fraction > 0.0 && test_fraction < 1.0) {
+        return Err(FittingError::InvalidHyperparameter {
+            name: "test_fraction",
+            value: test_fraction.to_string(),
+        }
+        .into());
+    }
+
+    let seeds = SeedSequence::new(config.seed);
+
+    let start = Instant::now();
+    let (train, test) = config.split.apply(interactions, &seeds);
+    if train.is_empty() {
+        return Err(FittingError::NoInteractions.into());
+    }
+    if test.is_empty() {
+        return Err(FittingError::DegenerateDataset {
+            reason: "the split left no test interactions".to_owned(),
+        }
+        .into());
+    }
+    let (train_mat, test_mat) = (train.to_compressed(), test.to_compressed());
+    let split_time = start.elapsed();
+
+    log_info!(
+        "Fitting on {} interactions, testing on {}",
+        train.len(),
+        test.len()
+    );
+    let start = Instant::now();
+    let (model, loss) = config.model.fit(&train_mat, &seeds)?;
+    let fit_time = start.elapsed();
+
+    let start = Instant::now();
+    let values = config
+        .metrics
+        .iter()
+        .map(|metric| Ok((*metric, metric.compute(&model, &test_mat)?)))
+        .collect::<Result<Vec<_>, PredictionError>>()?;
+    let evaluate_time = start.elapsed();
+
+    Ok(PipelineResult {
+        model,
+        loss,
+        num_train: train.len(),
+        num_test: test.len(),
+        report: EvaluationReport { values },
+        timings: Timings {
+            split: split_time,
+            fit: fit_time,
+            evaluate: evaluate_time,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::data::Interaction;
+    use crate::ids::{ItemId, UserId};
+    use crate::OnlineRankingModel;
+
+    const NUM_USERS: usize = 100;
+    const NUM_ITEMS: usize = 20;
+
+    /// Users walking through the items in order from a random start,
+    /// occasionally skipping one.
+    fn synthetic_data(seed: u64) -> Interactions {
+        let mut rng = SeedSequence::new(seed).derive("data");
+        let mut interactions = Interactions::new(NUM_USERS, NUM_ITEMS);
+
+        for user_id in 0..NUM_USERS as u64 {
+            let mut item_id = rng.gen_range(0, NUM_ITEMS as u64);
+            for timestamp in 0..10 {
+                interactions.push(Interaction::new(
+                    UserId(user_id),
+                    ItemId(item_id),
+                    Timestamp(timestamp),
+                ));
+                item_id = (item_id + rng.gen_range(1, 3)) % NUM_ITEMS as u64;
+            }
+        }
+
+        interactions
+    }
+
+    fn ewma_config() -> PipelineConfig {
+        let model = ewma::Hyperparameters::new(NUM_ITEMS, 10)
+            .learning_rate(0.05)
+            .num_epochs(20)
+            .num_threads(1);
+
+        PipelineConfig::new(ModelConfig::Ewma(model))
+            .metrics(vec![
+                Metric::Mrr,
+                Metric::Ndcg { k: 5 },
+                Metric::Coverage { k: 5 },
+                Metric::Gini { k: 5 },
+            ])
+            .seed(42)
+    }
+
+    #[test]
+    fn end_to_end() {
+        let result = run(synthetic_data(42), ewma_config()).unwrap();
+
+        assert_eq!(result.num_train + result.num_test, NUM_USERS * 10);
+        assert!(result.loss.unwrap().is_finite());
+        assert_eq!(result.report.values.len(), 4);
+        for (metric, value) in &result.report.values {
+            assert!(*value >= 0.0 && *value <= 1.0, "{} is {}", metric, value);
+        }
+
+        let baseline = run(
+            synthetic_data(42),
+            PipelineConfig::new(ModelConfig::Popularity).seed(42),
+        )
+        .unwrap();
+        assert!(baseline.loss.is_none());
+        assert!(result.report.get(Metric::Mrr) > baseline.report.get(Metric::Mrr));
+
+        let user = result
+            .model
+            .user_representation(&[ItemId(1), ItemId(2)])
+            .unwrap();
+        let recommendations = result.model.predict_top_k(&user, 3, &[]).unwrap();
+        assert_eq!(recommendations.len(), 3);
+
+        let report = result.report.to_string();
+        assert!(report.starts_with("MRR: "));
+        assert!(report.contains(", NDCG@5: "));
+    }
+
+    #[test]
+    fn reproducible_from_json() {
+        let json = serde_json::to_string(&ewma_config()).unwrap();
+        let config: PipelineConfig = serde_json::from_str(&json).unwrap();
+
+        let first = run(synthetic_data(42), ewma_config()).unwrap();
+        let second = run(synthetic_data(42), config).unwrap();
+
+        assert_eq!(first.report, second.report);
+        assert_eq!(first.loss, second.loss);
+    }
+
+    #[test]
+    fn splits() {
+        let seeds = SeedSequence::new(42);
+
+        let (train, test) =
+            Split::Temporal { test_fraction: 0.3 }.apply(synthetic_data(42), &seeds);
+        assert_eq!(test.len(), 300);
+        let last_train = train.data().iter().map(|x| x.timestamp()).max().unwrap();
+        assert!(test.data().iter().all(|x| x.timestamp() > last_train));
+
+        let (train, test) =
+            Split::UserBased { test_fraction: 0.3 }.apply(synthetic_data(42), &seeds);
+        assert!(test
+            .data()
+            .iter()
+            .all(|x| train.data().iter().all(|y| x.user_id() != y.user_id())));
+
+        let (train, test) = Split::Random { test_fraction: 0.3 }.apply(synthetic_data(42), &seeds);
+        assert_eq!((train.len(), test.len()), (700, 300));
+    }
+
+    #[test]
+    fn invalid_configs() {
+        let config = ewma_config().split(Split::Random { test_fraction: 1.5 });
+        match run(synthetic_data(42), config) {
+            Err(Error::Fitting(FittingError::InvalidHyperparameter { name, .. })) => {
+                assert_eq!(name, "test_fraction")
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let model = ewma::Hyperparameters::new(NUM_ITEMS - 1, 10);
+        let config = PipelineConfig::new(ModelConfig::Ewma(model));
+        match run(synthetic_data(42), config) {
+            Err(Error::Fitting(FittingError::ItemCountMismatch { .. })) => {}
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
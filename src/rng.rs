@@ -0,0 +1,106 @@
+//! Deterministic seeding of whole experiments.
+//!
+//! An experiment uses randomness in many places: splitting the data,
+//! initializing and fitting each model, sampling negatives and sampling
+//! users. A [SeedSequence] is created from a single `u64` seed, and
+//! derives an independent random number generator for each of them by
+//! name, so that one seed pins the whole experiment:
+//!
+//! - the splitters and samplers in [crate::data] take
+//!   `&mut seeds.derive("split")` and the like;
+//! - the model hyperparameters take the sequence itself through their
+//!   `seed_sequence` method, deriving the generator named after the model,
+//!   which is also used to sample negatives while fitting.
+//!
+//! Fitted parameters are only reproducible when fitting is deterministic,
+//! that is with a single thread.
+//!
+//! Generators derived from different names are independent, and adding a
+//! step to an experiment does not change the generators of the others.
+use std::hash::Hasher;
+
+use rand::{SeedableRng, XorShiftRng};
+use serde::{Deserialize, Serialize};
+use siphasher::sip::SipHasher;
+
+/// Source of named, reproducible random number generators, derived from a
+/// single seed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SeedSequence {
+    seed: u64,
+}
+
+impl SeedSequence {
+    /// Create a sequence from `seed`.
+    pub fn new(seed: u64) -> Self {
+        SeedSequence { seed }
+    }
+
+    /// The seed the sequence was created from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn hash(&self, name: &str, stream: u64) -> u64 {
+        let mut hasher = SipHasher::new_with_keys(self.seed, stream);
+        hasher.write(name.as_bytes());
+        hasher.finish()
+    }
+
+    /// The seed of the generator named `name`.
+    pub fn derive_seed(&self, name: &str) -> [u8; 16] {
+        let mut seed = [0; 16];
+        seed[..8].copy_from_slice(&self.hash(name, 0).to_le_bytes());
+        seed[8..].copy_from_slice(&self.hash(name, 1).to_le_bytes());
+        seed
+    }
+
+    /// A new generator named `name`. Generators with the same name are
+    /// identical.
+    pub fn derive(&self, name: &str) -> XorShiftRng {
+        XorShiftRng::from_seed(self.derive_seed(name))
+    }
+
+    /// A new sequence named `name`, for example to seed each fold of a
+    /// cross-validation or each trial of a hyperparameter search.
+    pub fn child(&self, name: &str) -> SeedSequence {
+        SeedSequence::new(self.hash(name, 2))
+    }
+}
+
+impl From<u64> for SeedSequence {
+    fn from(seed: u64) -> Self {
+        SeedSequence::new(seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    #[test]
+    fn derived_generators() {
+        let seeds = SeedSequence::new(42);
+        let draw = |mut rng: XorShiftRng| (0..4).map(|_| rng.gen::<u64>()).collect::<Vec<_>>();
+
+        assert_eq!(draw(seeds.derive("split")), draw(seeds.derive("split")));
+        assert_eq!(
+            draw(seeds.derive("split")),
+            draw(SeedSequence::new(42).derive("split"))
+        );
+        assert_ne!(draw(seeds.derive("split")), draw(seeds.derive("lstm")));
+        assert_ne!(
+            draw(seeds.derive("split")),
+            draw(SeedSequence::new(43).derive("split"))
+        );
+
+        assert_eq!(seeds.child("fold-0"), seeds.child("fold-0"));
+        assert_ne!(seeds.child("fold-0"), seeds.child("fold-1"));
+        assert_ne!(
+            seeds.child("fold-0").derive_seed("split"),
+            seeds.derive_seed("split")
+        );
+    }
+}
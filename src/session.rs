@@ -0,0 +1,157 @@
+//! Stateful multi-turn recommendation.
+//!
+//! A [RecommendationSession] repeatedly recommends items, recording
+//! which recommendations the user accepts or rejects. Accepted items
+//! extend the history used to compute the user representation; neither
+//! accepted nor rejected items are recommended again.
+use std::collections::HashSet;
+
+use crate::ids::ItemId;
+use crate::models::top_k;
+use crate::{OnlineRankingModel, PredictionError};
+
+/// A recommendation session over a borrowed model.
+///
+/// The model is never modified: only the session state changes.
+#[derive(Debug)]
+pub struct RecommendationSession<'a, M: OnlineRankingModel> {
+    model: &'a M,
+    num_items: usize,
+    seen_items: HashSet<ItemId>,
+    history: Vec<ItemId>,
+}
+
+impl<'a, M: OnlineRankingModel> RecommendationSession<'a, M> {
+    /// Start a session recommending from items `0..num_items`, given the
+    /// user's past interactions. Items in the history are not recommended.
+    ///
+    /// The sequence models cannot represent a user without interactions,
+    /// so with an empty history [RecommendationSession::recommend_next]
+    /// returns [PredictionError::EmptyHistory] until an item is accepted.
+    pub fn new(model: &'a M, num_items: usize, history: Vec<ItemId>) -> Self {
+        RecommendationSession {
+            model,
+            num_items,
+            seen_items: history.iter().cloned().collect(),
+            history,
+        }
+    }
+
+    /// Return the `k` highest-scoring `(item_id, score)` pairs, best first,
+    /// excluding items already seen in this session.
+    pub fn recommend_next(&mut self, k: usize) -> Result<Vec<(ItemId, f32)>, PredictionError> {
+        let user = self.model.user_representation(&self.history)?;
+        let predictions = self.model.predict_all_items(&user, self.num_items)?;
+
+        let candidates = predictions
+            .into_iter()
+            .enumerate()
+            .map(|(idx, score)| (ItemId::from_index(idx), score))
+            .filter(|(item_id, _)| !self.seen_items.contains(item_id))
+            .collect();
+
+        Ok(top_k(candidates, k))
+    }
+
+    /// Record that the user accepted `item_id`: it is appended to the
+    /// history and will not be recommended again.
+    pub fn mark_accepted(&mut self, item_id: ItemId) {
+        self.history.push(item_id);
+        self.mark_seen(item_id);
+    }
+
+    /// Record that the user rejected `item_id`: it will not be
+    /// recommended again, but does not affect the history.
+    pub fn mark_rejected(&mut self, item_id: ItemId) {
+        self.mark_seen(item_id);
+    }
+
+    fn mark_seen(&mut self, item_id: ItemId) {
+        self.seen_items.insert(item_id);
+    }
+
+    /// The items accepted in this session, including the initial history.
+    pub fn history(&self) -> &[ItemId] {
+        &self.history
+    }
+
+    /// The items that will not be recommended again.
+    pub fn seen_items(&self) -> &HashSet<ItemId> {
+        &self.seen_items
+    }
+}
+
+#[cfg(all(test, feature = "training"))]
+mod tests {
+    use super::*;
+    use crate::data::{Interaction, Interactions};
+    use crate::ids::{items, Timestamp, UserId};
+    use crate::models::ewma::{Hyperparameters, ImplicitEWMAModel};
+
+    fn fitted_model() -> ImplicitEWMAModel {
+        let data = Interactions::from(
+            (0..20)
+                .map(|idx| Interaction::new(UserId(0), ItemId(idx), Timestamp(idx as i64)))
+                .collect::<Vec<_>>(),
+        )
+        .to_compressed();
+        let mut model = Hyperparameters::new(20, 10)
+            .num_epochs(0)
+            .num_threads(1)
+            .from_seed([42; 16])
+            .build();
+        model.fit(&data).unwrap();
+
+        model
+    }
+
+    #[test]
+    fn session() {
+        let model = fitted_model();
+        let mut session = RecommendationSession::new(&model, 20, items(&[1, 2]));
+
+        let first = session.recommend_next(3).unwrap();
+        assert_eq!(first.len(), 3);
+        assert!(first
+            .iter()
+            .all(|&(item_id, _)| item_id != ItemId(1) && item_id != ItemId(2)));
+
+        let (accepted, _) = first[0];
+        let (rejected, _) = first[1];
+        session.mark_accepted(accepted);
+        session.mark_rejected(rejected);
+
+        assert_eq!(session.history(), &[ItemId(1), ItemId(2), accepted]);
+        assert_eq!(
+            session.seen_items(),
+            &[ItemId(1), ItemId(2), accepted, rejected]
+                .iter()
+                .cloned()
+                .collect::<HashSet<_>>()
+        );
+
+        let second = session.recommend_next(20).unwrap();
+        assert_eq!(second.len(), 16);
+        assert!(second
+            .iter()
+            .all(|&(item_id, _)| !session.seen_items().contains(&item_id)));
+    }
+
+    #[test]
+    fn empty_history() {
+        let model = fitted_model();
+        let mut session = RecommendationSession::new(&model, 20, Vec::new());
+
+        match session.recommend_next(3) {
+            Err(PredictionError::EmptyHistory) => {}
+            other => panic!("Expected EmptyHistory, got {:?}", other),
+        }
+
+        session.mark_accepted(ItemId(5));
+        let recommendations = session.recommend_next(3).unwrap();
+        assert_eq!(recommendations.len(), 3);
+        assert!(recommendations
+            .iter()
+            .all(|&(item_id, _)| item_id != ItemId(5)));
+    }
+}
@@ -0,0 +1,100 @@
+//! Check that a single seed reproduces a whole experiment: the data
+//! split, the fitted models, the sampled negatives and the evaluation.
+#![cfg(feature = "training")]
+use rand::Rng;
+
+use recommenders::data::{sample_hard_negatives, user_based_split, Interaction, Interactions};
+use recommenders::evaluation::mrr_score;
+use recommenders::ids::{ItemId, Timestamp, UserId};
+use recommenders::models::{ewma, lstm};
+use recommenders::rng::SeedSequence;
+use recommenders::OnlineRankingModel;
+
+const NUM_USERS: usize = 100;
+const NUM_ITEMS: usize = 30;
+
+#[derive(Debug, PartialEq)]
+struct Experiment {
+    train: Vec<Interaction>,
+    test: Vec<Interaction>,
+    predictions: Vec<Vec<f32>>,
+    negatives: Vec<ItemId>,
+    mrrs: Vec<f32>,
+}
+
+/// Random interactions, drawn from the `"data"` generator of `seeds`.
+fn interactions(seeds: &SeedSequence) -> Interactions {
+    let mut rng = seeds.derive("data");
+    let mut interactions = Interactions::new(NUM_USERS, NUM_ITEMS);
+
+    for user_id in 0..NUM_USERS as u64 {
+        for timestamp in 0..10 {
+            interactions.push(Interaction::new(
+                UserId(user_id),
+                ItemId(rng.gen_range(0, NUM_ITEMS as u64)),
+                Timestamp(timestamp),
+            ));
+        }
+    }
+
+    interactions
+}
+
+fn run(seed: u64) -> Experiment {
+    let seeds = SeedSequence::new(seed);
+
+    let data = interactions(&seeds);
+    let (train, test) = user_based_split(&data, &mut seeds.derive("split"), 0.2);
+    let (train_mat, test_mat) = (train.to_compressed(), test.to_compressed());
+
+    // A single thread makes fitting deterministic.
+    let mut ewma = ewma::Hyperparameters::new(NUM_ITEMS, 10)
+        .num_epochs(2)
+        .num_threads(1)
+        .seed_sequence(&seeds)
+        .build();
+    ewma.fit(&train_mat).unwrap();
+    let mut lstm = lstm::Hyperparameters::new(NUM_ITEMS, 10)
+        .num_epochs(2)
+        .num_threads(1)
+        .seed_sequence(&seeds)
+        .build();
+    lstm.fit(&train_mat).unwrap();
+
+    let history = [ItemId(1), ItemId(2), ItemId(3)];
+    let predictions = vec![
+        ewma.predict_all_items(&ewma.user_representation(&history).unwrap(), NUM_ITEMS)
+            .unwrap(),
+        lstm.predict_all_items(&lstm.user_representation(&history).unwrap(), NUM_ITEMS)
+            .unwrap(),
+    ];
+
+    let negatives = sample_hard_negatives(&train_mat, &ewma, 5, &mut seeds.derive("negatives"))
+        .unwrap()
+        .negative_item_ids()
+        .to_vec();
+
+    Experiment {
+        train: train.data().to_vec(),
+        test: test.data().to_vec(),
+        predictions,
+        negatives,
+        mrrs: vec![
+            mrr_score(&ewma, &test_mat).unwrap(),
+            mrr_score(&lstm, &test_mat).unwrap(),
+        ],
+    }
+}
+
+#[test]
+fn same_seed_same_experiment() {
+    assert_eq!(run(42), run(42));
+}
+
+#[test]
+fn different_seeds_differ() {
+    let (first, second) = (run(42), run(43));
+
+    assert_ne!(first.train, second.train);
+    assert_ne!(first.predictions, second.predictions);
+}
@@ -0,0 +1,74 @@
+//! Check the shared thread pool configuration. The test changes the
+//! crate-wide pool, so it lives in its own test binary.
+#![cfg(feature = "training")]
+use recommenders::config::{self, ThreadConfig};
+use recommenders::data::{CompressedInteractions, Interaction, Interactions};
+use recommenders::evaluation::mrr_score;
+use recommenders::ids::{ItemId, Timestamp, UserId};
+use recommenders::models::ewma;
+use recommenders::OnlineRankingModel;
+
+fn interactions() -> CompressedInteractions {
+    let interactions: Vec<_> = (0..50)
+        .flat_map(|user_id| {
+            (0..10).map(move |t| {
+                Interaction::new(
+                    UserId(user_id),
+                    ItemId((user_id + t) % 30),
+                    Timestamp(t as i64),
+                )
+            })
+        })
+        .collect();
+
+    Interactions::from(interactions).to_compressed()
+}
+
+fn fit(data: &CompressedInteractions) -> Vec<f32> {
+    let mut model = ewma::Hyperparameters::new(30, 6)
+        .num_epochs(2)
+        .from_seed([42; 16])
+        .build();
+    model.fit(data).unwrap();
+
+    let user = model
+        .user_representation(&[ItemId(1), ItemId(2), ItemId(3)])
+        .unwrap();
+    model.predict_all_items(&user, 30).unwrap()
+}
+
+#[test]
+fn single_thread_matches_serial() {
+    let data = interactions();
+
+    config::set_num_threads(1).unwrap();
+    assert_eq!(config::num_threads(), 1);
+
+    // The model defaults to one partition, so fitting is serial, and
+    // repeated runs agree exactly.
+    let predictions = fit(&data);
+    assert_eq!(predictions, fit(&data));
+
+    let mut model = ewma::Hyperparameters::new(30, 6)
+        .num_epochs(2)
+        .from_seed([42; 16])
+        .build();
+    model.fit(&data).unwrap();
+    let mrr = mrr_score(&model, &data).unwrap();
+
+    // Running on more threads, through a per-call pool, does not change
+    // the results of a model with the same number of partitions.
+    let pool = ThreadConfig::new().num_threads(4).build().unwrap();
+    assert_eq!(pool.install(|| mrr_score(&model, &data)).unwrap(), mrr);
+
+    let mut pooled = ewma::Hyperparameters::new(30, 6)
+        .num_epochs(2)
+        .num_threads(1)
+        .from_seed([42; 16])
+        .build();
+    pool.install(|| pooled.fit(&data)).unwrap();
+    let user = pooled
+        .user_representation(&[ItemId(1), ItemId(2), ItemId(3)])
+        .unwrap();
+    assert_eq!(pooled.predict_all_items(&user, 30).unwrap(), predictions);
+}
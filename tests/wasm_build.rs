@@ -0,0 +1,47 @@
+//! Check that the prediction-only build of the crate, without the
+//! `datasets` and `training` features, compiles for WASM.
+//!
+//! Skipped, with a message, if the `wasm32-unknown-unknown` target is not
+//! installed; install it with `rustup target add wasm32-unknown-unknown`.
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+const TARGET: &str = "wasm32-unknown-unknown";
+
+fn target_installed() -> bool {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+
+    Command::new(rustc)
+        .args(&["--print", "target-libdir", "--target", TARGET])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map_or(false, |output| {
+            Path::new(String::from_utf8_lossy(&output.stdout).trim()).exists()
+        })
+}
+
+#[test]
+fn prediction_only_build_for_wasm() {
+    if !target_installed() {
+        println!("Skipping: the {} target is not installed.", TARGET);
+        return;
+    }
+
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    // A separate target directory avoids waiting on the lock held by the
+    // enclosing `cargo test`.
+    let target_dir = Path::new(manifest_dir).join("target").join("wasm-check");
+
+    let status = Command::new(env!("CARGO"))
+        .current_dir(manifest_dir)
+        .args(&["check", "--lib", "--no-default-features"])
+        .args(&["--target", TARGET])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status()
+        .expect("Failed to run cargo.");
+
+    assert!(status.success(), "The prediction-only build failed.");
+}